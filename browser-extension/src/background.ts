@@ -76,7 +76,7 @@ async function handleTrustScoreRequest(idDomain: string, agentId: string) {
     const result = await chrome.storage.sync.get(['apiEndpoint']);
     const apiEndpoint = result.apiEndpoint || 'http://localhost:8080';
     
-    const url = `${apiEndpoint}/trust/${idDomain}/${agentId}`;
+    const url = `${apiEndpoint}/v1/trust/${idDomain}/${agentId}`;
     const response = await fetch(url);
     if (!response.ok) {
       throw new Error(`HTTP ${response.status}: ${response.statusText}`);
@@ -107,7 +107,7 @@ async function handleBatchTrustScoreRequest(agentIds: string[]) {
           ? [agentId.substring(0, colonIndex), agentId.substring(colonIndex + 1)]
           : ['ethereum', agentId]; // Default to ethereum if no domain specified
         
-        const response = await fetch(`${apiEndpoint}/trust/${idDomain}/${agentIdPart}`);
+        const response = await fetch(`${apiEndpoint}/v1/trust/${idDomain}/${agentIdPart}`);
         if (response.ok) {
           scores[agentId] = await response.json();
         }
@@ -129,7 +129,7 @@ async function handleRecordExperience(experience: any) {
     const result = await chrome.storage.sync.get(['apiEndpoint']);
     const apiEndpoint = result.apiEndpoint || 'http://localhost:8080';
     
-    const response = await fetch(`${apiEndpoint}/experiences`, {
+    const response = await fetch(`${apiEndpoint}/v1/experiences`, {
       method: 'POST',
       headers: {
         'Content-Type': 'application/json',
@@ -225,7 +225,7 @@ async function handleGetExperiences(idDomain: string, agentId: string) {
     const result = await chrome.storage.sync.get(['apiEndpoint']);
     const apiEndpoint = result.apiEndpoint || 'http://localhost:8080';
     
-    const response = await fetch(`${apiEndpoint}/experiences/${idDomain}/${agentId}`);
+    const response = await fetch(`${apiEndpoint}/v1/experiences/${idDomain}/${agentId}`);
     if (!response.ok) {
       throw new Error(`HTTP ${response.status}: ${response.statusText}`);
     }
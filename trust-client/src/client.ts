@@ -6,19 +6,39 @@ import {
   TrustQuery,
   TrustResponse,
   AddExperienceRequest,
+  AddPendingExperienceRequest,
+  AddTemplateRequest,
+  ExperienceTemplate,
+  PendingExperience,
+  SettleExperienceRequest,
   AddPeerRequest,
+  CreateInviteRequest,
+  IdentityRotation,
+  IssuedInvite,
+  RedeemInviteRequest,
   UpdateQualityRequest,
+  QualityRevision,
+  PeerRequest,
+  AcceptPeerRequest,
+  ProposePeeringRequest,
   TrustQueryParams,
   TrustDataExport,
+  ExportDiff,
+  MappedImportSummary,
   ImportRequest,
+  ExperienceSearchParams,
+  ExperienceSearchResult,
 } from './types';
 
+/** Node API version this client speaks; unversioned paths still work but are deprecated */
+export const API_VERSION = 'v1';
+
 export class TrustClient {
   private client: AxiosInstance;
 
   constructor(baseURL: string = 'http://localhost:8080') {
     this.client = axios.create({
-      baseURL,
+      baseURL: `${baseURL.replace(/\/+$/, '')}/${API_VERSION}`,
       timeout: 10000, // Reduced timeout for faster local testing 
       headers: {
         'Content-Type': 'application/json',
@@ -45,10 +65,46 @@ export class TrustClient {
     return response.data;
   }
 
+  async searchExperiences(params: ExperienceSearchParams): Promise<ExperienceSearchResult> {
+    const response = await this.client.get<TrustExperience[]>('/experiences', { params });
+    const total = Number(response.headers['x-total-count'] ?? response.data.length);
+    return { experiences: response.data, total };
+  }
+
   async removeExperience(experienceId: string): Promise<void> {
     await this.client.delete(`/experiences/${experienceId}`);
   }
 
+  async addPendingExperience(request: AddPendingExperienceRequest): Promise<PendingExperience> {
+    const response = await this.client.post<PendingExperience>('/experiences/pending', request);
+    return response.data;
+  }
+
+  async getPendingExperiences(): Promise<PendingExperience[]> {
+    const response = await this.client.get<PendingExperience[]>('/experiences/pending');
+    return response.data;
+  }
+
+  /** Record the return of a pending experience, which then counts toward the agent's score */
+  async settleExperience(experienceId: string, request: SettleExperienceRequest): Promise<TrustExperience> {
+    const response = await this.client.post<TrustExperience>(`/experience/${experienceId}/settle`, request);
+    return response.data;
+  }
+
+  async addTemplate(request: AddTemplateRequest): Promise<ExperienceTemplate> {
+    const response = await this.client.post<ExperienceTemplate>('/templates', request);
+    return response.data;
+  }
+
+  async getTemplates(): Promise<ExperienceTemplate[]> {
+    const response = await this.client.get<ExperienceTemplate[]>('/templates');
+    return response.data;
+  }
+
+  async removeTemplate(templateId: string): Promise<void> {
+    await this.client.delete(`/templates/${templateId}`);
+  }
+
   async queryTrust(idDomain: string, agentId: string, params?: TrustQueryParams): Promise<TrustScore> {
     const response = await this.client.get<TrustScore>(`/trust/${idDomain}/${agentId}`, { params });
     return response.data;
@@ -69,14 +125,64 @@ export class TrustClient {
     return response.data;
   }
 
+  /** A one-shot token a friend redeems to add you, after which their node asks yours to add them back */
+  async createInvite(request: CreateInviteRequest = {}): Promise<IssuedInvite> {
+    const response = await this.client.post<IssuedInvite>('/peers/invites', request);
+    return response.data;
+  }
+
+  async redeemInvite(request: RedeemInviteRequest): Promise<Peer> {
+    const response = await this.client.post<Peer>('/peers/redeem', request);
+    return response.data;
+  }
+
+  /** Switch the node to a new key; connected peers are told and keep it listed with the same quality */
+  async rotateIdentity(): Promise<IdentityRotation> {
+    const response = await this.client.post<IdentityRotation>('/identity/rotate');
+    return response.data;
+  }
+
   async updatePeerQuality(peerId: string, request: UpdateQualityRequest): Promise<void> {
     await this.client.post(`/peers/${peerId}/quality`, request);
   }
 
+  async getQualityHistory(peerId: string): Promise<QualityRevision[]> {
+    const response = await this.client.get<QualityRevision[]>(`/peers/${peerId}/quality/history`);
+    return response.data;
+  }
+
   async removePeer(peerId: string): Promise<void> {
     await this.client.delete(`/peers/${peerId}`);
   }
 
+  /** Ask a node to add each other as peers; it's listed once its user accepts */
+  async proposePeering(request: ProposePeeringRequest): Promise<void> {
+    await this.client.post('/peers/propose', request);
+  }
+
+  async getPeerRequests(): Promise<PeerRequest[]> {
+    const response = await this.client.get<PeerRequest[]>('/peers/requests');
+    return response.data;
+  }
+
+  async acceptPeerRequest(peerId: string, request: AcceptPeerRequest = {}): Promise<Peer> {
+    const response = await this.client.post<Peer>(`/peers/requests/${peerId}/accept`, request);
+    return response.data;
+  }
+
+  async ignorePeerRequest(peerId: string): Promise<void> {
+    await this.client.post(`/peers/requests/${peerId}/ignore`);
+  }
+
+  async blockPeer(peerId: string): Promise<void> {
+    await this.client.post(`/peers/requests/${peerId}/block`);
+  }
+
+  /** Forget a request; the peer shows up as pending again when it next queries */
+  async removePeerRequest(peerId: string): Promise<void> {
+    await this.client.delete(`/peers/requests/${peerId}`);
+  }
+
   async getConnectedPeers(): Promise<string[]> {
     const response = await this.client.get<string[]>('/peers/connected');
     return response.data;
@@ -86,8 +192,21 @@ export class TrustClient {
     await this.client.post('/peers/discover');
   }
 
-  async exportTrustData(): Promise<TrustDataExport> {
-    const response = await this.client.get<TrustDataExport>('/export');
+  async exportTrustData(pointInTime?: string, anonymize: boolean = false): Promise<TrustDataExport> {
+    const params = { point_in_time: pointInTime, anonymize: anonymize || undefined };
+    const response = await this.client.get<TrustDataExport>('/export', { params });
+    return response.data;
+  }
+
+  // `mapping` follows ImportMapping in the node's src/mapping.rs; `input` is the JSON or CSV dump as text
+  async importMapped(mapping: object, input: string, dryRun: boolean = false): Promise<MappedImportSummary> {
+    const response = await this.client.post<MappedImportSummary>('/import/mapped', { mapping, input, dry_run: dryRun });
+    return response.data;
+  }
+
+  // Without `after`, `before` is compared with the node's current data
+  async diffExports(before: TrustDataExport, after?: TrustDataExport): Promise<ExportDiff> {
+    const response = await this.client.post<ExportDiff>('/diff', { before, after });
     return response.data;
   }
 
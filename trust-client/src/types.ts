@@ -15,11 +15,26 @@ export interface TrustScore {
   data_points: number;
 }
 
+export interface PeerAddress {
+  address: string;
+  /** When a connection through this address last succeeded */
+  last_success_at: string | null;
+}
+
 export interface Peer {
+  /** Bare libp2p PeerId */
   peer_id: string;
+  /** Multiaddrs the peer is dialed at, without the /p2p/<id> suffix, tried by most recent success */
+  addresses: PeerAddress[];
+  /** Unique short name usable in place of peer_id in API paths */
+  handle: string | null;
   name: string;
   recommender_quality: number;
   added_at: string;
+  /** When the peer last answered a query or its quality was last set */
+  last_interaction_at?: string;
+  /** Quality recommendations are weighted with after decay; only listed by GET /peers */
+  effective_quality?: number;
 }
 
 export interface TrustQuery {
@@ -27,32 +42,197 @@ export interface TrustQuery {
   max_depth: number;
   point_in_time?: string;
   forget_rate?: number;
+  /** Recalculate and ask peers afresh instead of using cached scores */
+  refresh?: boolean;
+  /** Answer from stored scores only, without asking peers */
+  cache_only?: boolean;
+  /** Nodes neither answer nor forward the query after this time */
+  expires_at?: string;
+}
+
+export interface ScoreDepth {
+  /** The max_depth the answering node queried with */
+  max_depth: number;
+  /** Hops to the farthest evidence behind the score */
+  farthest_hops: number;
 }
 
 export interface TrustResponse {
-  scores: Array<{id_domain: string; agent_id: string; score: TrustScore}>;
+  scores: Array<{id_domain: string; agent_id: string; score: TrustScore; hops?: number; depth?: ScoreDepth}>;
   timestamp: string;
 }
 
+/** How a later return is brought to present value; domains and the node have their own default */
+export type Discounting =
+  | { model: 'annual'; rate: number }
+  | { model: 'continuous'; rate: number }
+  | { model: 'zero' }
+  /** Annual compounding at a rate interpolated by how many years the money was out */
+  | { model: 'curve'; points: Array<{ years: number; rate: number }> };
+
 export interface AddExperienceRequest {
   id_domain: string;
   agent_id: string;
   investment: number;
   return_value: number;
   timeframe_days: number;
+  discounting?: Discounting;
+  /** Rate for the domain's or node's model */
   discount_rate?: number;
   notes?: string;
   data?: any;
 }
 
+/** An investment whose return isn't known yet; it counts toward no score until settled */
+export interface PendingExperience {
+  id: string;
+  id_domain: string;
+  agent_id: string;
+  invested_volume: number;
+  timestamp: string;
+  notes?: string;
+  data?: any;
+  /** Return it settles at when confirmed without one; set for experiences from a template */
+  expected_return?: number;
+  template_id?: string;
+}
+
+export interface AddPendingExperienceRequest {
+  id_domain: string;
+  agent_id: string;
+  investment: number;
+  notes?: string;
+  data?: any;
+}
+
+export interface SettleExperienceRequest {
+  /** Left out to confirm an experience from a template at its expected return */
+  return_value?: number;
+  discounting?: Discounting;
+  discount_rate?: number;
+}
+
+/** A recurring relationship that adds a pending experience every interval_days */
+export interface ExperienceTemplate {
+  id: string;
+  id_domain: string;
+  agent_id: string;
+  investment: number;
+  expected_return: number;
+  interval_days: number;
+  next_due_at: string;
+  notes?: string;
+  created_at: string;
+}
+
+export interface AddTemplateRequest {
+  id_domain: string;
+  agent_id: string;
+  investment: number;
+  expected_return: number;
+  interval_days: number;
+  /** Defaults to now */
+  first_due_at?: string;
+  notes?: string;
+}
+
 export interface AddPeerRequest {
+  /** Bare PeerId; a full multiaddr ending in /p2p/<id> is split by the node */
   peer_id: string;
+  addresses?: string[];
+  /** Lowercase letters, digits, '-' and '_', at most 32 characters */
+  handle?: string;
   name: string;
   recommender_quality?: number;
 }
 
+export interface CreateInviteRequest {
+  /** How you introduce yourself to the friend */
+  name?: string;
+  /** Addresses to hand out instead of the ones the node listens on */
+  addresses?: string[];
+  /** One day by default, a week at most */
+  ttl_secs?: number;
+}
+
+export interface IssuedInvite {
+  token: string;
+  expires_at: string;
+  addresses: string[];
+}
+
+export interface RedeemInviteRequest {
+  token: string;
+  /** Name to list the inviter under; defaults to the one in the invite */
+  name?: string;
+  handle?: string;
+  recommender_quality?: number;
+  /** How you introduce yourself to the inviter */
+  introduce_as?: string;
+}
+
+export interface IdentityRotation {
+  old_peer_id: string;
+  new_peer_id: string;
+  /** Connected peers that were sent the signed transition */
+  notified: string[];
+}
+
 export interface UpdateQualityRequest {
   quality: number;
+  /** Kept in the peer's quality history */
+  reason?: string;
+}
+
+/** A peer outside the peer list that queried this node */
+export interface PeerRequest {
+  peer_id: string;
+  /** pending waits in the inbox, ignored is still answered, blocked is answered empty */
+  status: 'pending' | 'ignored' | 'blocked';
+  first_seen_at: string;
+  last_seen_at: string;
+  queries: number;
+  /** Set when the peer asked to add each other as peers */
+  proposal?: PeerProposal;
+}
+
+/** What a node proposing peering tells about itself */
+export interface PeerProposal {
+  name: string | null;
+  addresses: string[];
+  contact: { email: string | null; fediverse: string | null };
+  /** Circles the proposer lists you under, used for its entry when you accept */
+  tags: string[];
+  note: string | null;
+}
+
+export interface ProposePeeringRequest {
+  peer_id: string;
+  /** Where to reach the peer, unless it can be found through the DHT */
+  addresses?: string[];
+  /** Name to list the peer under once it accepts; defaults to the peer id */
+  name?: string;
+  recommender_quality?: number;
+  tags?: string[];
+  /** How you introduce yourself */
+  introduce_as?: string;
+  note?: string;
+}
+
+export interface AcceptPeerRequest {
+  /** Defaults to the peer id */
+  name?: string;
+  quality?: number;
+}
+
+export interface QualityRevision {
+  revision: number;
+  peer_id: string;
+  /** Absent for the quality the peer was added with */
+  previous_quality: number | null;
+  quality: number;
+  changed_at: string;
+  reason: string;
 }
 
 export interface TrustDataExport {
@@ -60,6 +240,34 @@ export interface TrustDataExport {
   exported_at: string;
   experiences: TrustExperience[];
   peers: Peer[];
+  // Set on snapshots, with the merged scores as of that time
+  point_in_time?: string;
+  scores?: Array<{id_domain: string; agent_id: string; score: TrustScore; hops?: number; depth?: ScoreDepth}>;
+}
+
+export interface RecordChange<T> {
+  fields: string[];
+  before: T;
+  after: T;
+}
+
+export interface ExportDiff {
+  added_experiences: TrustExperience[];
+  removed_experiences: TrustExperience[];
+  changed_experiences: RecordChange<TrustExperience>[];
+  added_peers: Peer[];
+  removed_peers: Peer[];
+  changed_peers: RecordChange<Peer>[];
+}
+
+export interface MappedImportSummary {
+  dry_run: boolean;
+  strategy: string;
+  new_experiences: number;
+  conflicting_experiences: number;
+  new_peers: number;
+  existing_peers: number;
+  rejected: Array<{record: number; message: string}>;
 }
 
 export interface ImportRequest {
@@ -70,6 +278,25 @@ export interface ImportRequest {
 export interface TrustQueryParams {
   max_depth?: number;
   forget_rate?: number;
+  refresh?: boolean;
+  cache_only?: boolean;
+}
+
+export interface ExperienceSearchParams {
+  domain?: string;
+  agent?: string;
+  tag?: string;
+  from?: string;
+  to?: string;
+  q?: string;
+  sort?: 'newest' | 'oldest' | 'volume' | 'roi' | 'relevance';
+  limit?: number;
+  offset?: number;
+}
+
+export interface ExperienceSearchResult {
+  experiences: TrustExperience[];
+  total: number;
 }
 
 export interface IDAdapter {
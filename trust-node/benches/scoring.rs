@@ -0,0 +1,91 @@
+use chrono::{Duration, Utc};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use futures::TryStreamExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use trust_node::{
+    query_engine::QueryEngine,
+    storage::{SqliteStorage, Storage},
+    types::{DomainDefaults, TrustExperience},
+};
+use uuid::Uuid;
+
+const AGENTS: usize = 1_000;
+
+/// Deterministic spread of experiences over AGENTS agents and the last year
+fn experiences(count: usize) -> Vec<TrustExperience> {
+    let now = Utc::now();
+    (0..count)
+        .map(|i| TrustExperience {
+            id: Uuid::new_v4(),
+            id_domain: "bench".to_string(),
+            agent_id: format!("agent-{}", i % AGENTS),
+            pv_roi: 0.5 + (i % 17) as f64 / 10.0,
+            invested_volume: 10.0 + (i % 97) as f64 * 10.0,
+            timestamp: now - Duration::hours((i % (365 * 24)) as i64),
+            notes: None,
+            data: None,
+        })
+        .collect()
+}
+
+async fn populated_storage(count: usize) -> Arc<SqliteStorage> {
+    let storage = SqliteStorage::new(&PathBuf::from(":memory:")).await.unwrap();
+    storage.add_experiences(experiences(count)).await.unwrap();
+    Arc::new(storage)
+}
+
+fn bench_storage(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("storage");
+    for batch in [100, 1_000] {
+        group.throughput(Throughput::Elements(batch as u64));
+        group.bench_with_input(BenchmarkId::new("add_experiences", batch), &batch, |b, &batch| {
+            let storage = runtime.block_on(populated_storage(0));
+            b.to_async(&runtime).iter_batched(
+                || experiences(batch),
+                |batch| {
+                    let storage = storage.clone();
+                    async move { storage.add_experiences(batch).await.unwrap() }
+                },
+                BatchSize::SmallInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+fn bench_query_engine(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("query_engine");
+    for count in [10_000, 100_000] {
+        let storage = runtime.block_on(populated_storage(count));
+        let now = Utc::now();
+        // Every agent is scored with forgetting, as the single agent is
+        let defaults = DomainDefaults { id_domain: "bench".to_string(), forget_rate: Some(0.1), ..DomainDefaults::default() };
+        runtime.block_on(storage.set_domain_defaults(&defaults)).unwrap();
+
+        group.bench_with_input(BenchmarkId::new("single_agent_uncached", count), &storage, |b, storage| {
+            // A fresh engine per run keeps the score cache out of the measurement
+            b.to_async(&runtime).iter(|| {
+                let engine = QueryEngine::new(storage.clone());
+                async move { engine.calculate_trust_score("bench", "agent-7", now, 0.1).await.unwrap() }
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("all_agents", count), &storage, |b, storage| {
+            b.to_async(&runtime).iter(|| {
+                let engine = QueryEngine::new(storage.clone());
+                async move {
+                    let scores: Vec<_> = engine.stream_all_trust_scores(now).try_collect().await.unwrap();
+                    assert_eq!(scores.len(), AGENTS);
+                }
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_storage, bench_query_engine);
+criterion_main!(benches);
\ No newline at end of file
@@ -0,0 +1,307 @@
+use crate::clock::SharedClock;
+use crate::events::{EventSender, NodeEvent};
+use crate::storage::Storage;
+use crate::types::{CachedTrustScore, TrustExperience};
+use anyhow::Result;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use futures::TryStreamExt;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+/// Two peers are suspicious once they agree exactly on at least this many agents, and never differ
+const IDENTICAL_MIN_COMMON_AGENTS: usize = 3;
+/// A recommender is flagged when its scores miss my later outcomes by this much ROI on average...
+const DIVERGENCE_MIN_MEAN_ERROR: f64 = 0.5;
+/// ...across at least this many agents
+const DIVERGENCE_MIN_AGENTS: usize = 3;
+/// An agent first seen within this many days...
+const BURST_WINDOW_DAYS: i64 = 7;
+/// ...with at least this many reports at or above BURST_HIGH_ROI looks like a pump
+const BURST_MIN_REPORTS: usize = 5;
+const BURST_HIGH_ROI: f64 = 1.5;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AnomalyKind {
+    /// Peers that always report exactly the same scores, as sock puppets would
+    IdenticalScores { peers: Vec<String>, common_agents: usize },
+    /// A peer whose recommendations systematically differ from what I experienced afterwards
+    DivergentRecommender { peer: String, agents: usize, mean_abs_error: f64 },
+    /// A new agent that quickly collected many high-ROI reports
+    HighRoiBurst { id_domain: String, agent_id: String, first_seen: DateTime<Utc>, high_roi_reports: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Anomaly {
+    pub description: String,
+    #[serde(flatten)]
+    pub kind: AnomalyKind,
+}
+
+impl Anomaly {
+    /// Identifies the same finding across runs, so only new findings are announced
+    fn key(&self) -> String {
+        match &self.kind {
+            AnomalyKind::IdenticalScores { peers, .. } => format!("identical:{}", peers.join(",")),
+            AnomalyKind::DivergentRecommender { peer, .. } => format!("divergent:{}", peer),
+            AnomalyKind::HighRoiBurst { id_domain, agent_id, .. } => format!("burst:{}:{}", id_domain, agent_id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AnomalyReport {
+    pub enabled: bool,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub anomalies: Vec<Anomaly>,
+}
+
+/// Periodically scans cached recommendations and experiences for suspicious patterns
+pub struct AnomalyDetector<S: Storage> {
+    storage: Arc<S>,
+    interval: Duration,
+    report: Arc<RwLock<AnomalyReport>>,
+    events: EventSender,
+    clock: SharedClock,
+}
+
+impl<S: Storage + 'static> AnomalyDetector<S> {
+    pub fn new(
+        storage: Arc<S>,
+        interval: Duration,
+        report: Arc<RwLock<AnomalyReport>>,
+        events: EventSender,
+        clock: SharedClock,
+    ) -> Self {
+        Self { storage, interval, report, events, clock }
+    }
+
+    pub async fn run(self) {
+        self.report.write().unwrap().enabled = true;
+        let mut ticker = interval(self.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            let result = scan(self.storage.as_ref(), self.clock.now()).await;
+            let mut report = self.report.write().unwrap();
+            match result {
+                Ok(anomalies) => {
+                    let known: HashSet<String> = report.anomalies.iter().map(Anomaly::key).collect();
+                    for anomaly in anomalies.iter().filter(|a| !known.contains(&a.key())) {
+                        info!("Anomaly detected: {}", anomaly.description);
+                        // Nobody listening is fine
+                        let _ = self.events.send(NodeEvent::Anomaly(anomaly.clone()));
+                    }
+                    report.anomalies = anomalies;
+                    report.last_error = None;
+                }
+                Err(e) => {
+                    warn!("Anomaly scan failed: {}", e);
+                    report.last_error = Some(e.to_string());
+                }
+            }
+            report.last_run_at = Some(self.clock.now());
+        }
+    }
+}
+
+/// Run all heuristics once against the current data
+pub async fn scan<S: Storage + ?Sized>(storage: &S, now: DateTime<Utc>) -> Result<Vec<Anomaly>> {
+    let cached = storage.get_all_cached_scores().await?;
+    let recommended: HashSet<(String, String)> = cached
+        .iter()
+        .map(|c| (c.id_domain.clone(), c.agent_id.clone()))
+        .collect();
+
+    // One pass over the experiences: keep those of recommended agents, count the rest
+    let mut outcomes: HashMap<(String, String), Vec<TrustExperience>> = HashMap::new();
+    let mut activity: HashMap<(String, String), AgentActivity> = HashMap::new();
+    let burst_since = now - ChronoDuration::days(BURST_WINDOW_DAYS);
+    let mut experiences = storage.stream_experiences();
+    while let Some(exp) = experiences.try_next().await? {
+        let key = (exp.id_domain.clone(), exp.agent_id.clone());
+        let entry = activity.entry(key.clone()).or_insert(AgentActivity {
+            first_seen: exp.timestamp,
+            high_roi_reports: 0,
+        });
+        entry.first_seen = entry.first_seen.min(exp.timestamp);
+        if exp.timestamp >= burst_since && exp.pv_roi >= BURST_HIGH_ROI {
+            entry.high_roi_reports += 1;
+        }
+        if recommended.contains(&key) {
+            outcomes.entry(key).or_default().push(exp);
+        }
+    }
+
+    let mut anomalies = detect_identical_peers(&cached);
+    anomalies.extend(detect_divergent_peers(&cached, &outcomes));
+    anomalies.extend(detect_bursts(&activity, burst_since));
+    Ok(anomalies)
+}
+
+struct AgentActivity {
+    first_seen: DateTime<Utc>,
+    high_roi_reports: usize,
+}
+
+fn detect_identical_peers(cached: &[CachedTrustScore]) -> Vec<Anomaly> {
+    let mut by_agent: HashMap<(&str, &str), Vec<&CachedTrustScore>> = HashMap::new();
+    for score in cached {
+        by_agent.entry((score.id_domain.as_str(), score.agent_id.as_str())).or_default().push(score);
+    }
+
+    // (peer, peer) -> (agents both scored, agents they scored identically)
+    let mut pairs: BTreeMap<(&str, &str), (usize, usize)> = BTreeMap::new();
+    for scores in by_agent.values() {
+        for (i, a) in scores.iter().enumerate() {
+            for b in &scores[i + 1..] {
+                let key = if a.from_peer < b.from_peer {
+                    (a.from_peer.as_str(), b.from_peer.as_str())
+                } else {
+                    (b.from_peer.as_str(), a.from_peer.as_str())
+                };
+                let counts = pairs.entry(key).or_default();
+                counts.0 += 1;
+                if a.score.expected_pv_roi == b.score.expected_pv_roi && a.score.total_volume == b.score.total_volume {
+                    counts.1 += 1;
+                }
+            }
+        }
+    }
+
+    pairs
+        .into_iter()
+        .filter(|(_, (common, identical))| *common >= IDENTICAL_MIN_COMMON_AGENTS && identical == common)
+        .map(|((a, b), (common, _))| Anomaly {
+            description: format!("Peers {} and {} reported identical scores for all {} agents they share", a, b, common),
+            kind: AnomalyKind::IdenticalScores {
+                peers: vec![a.to_string(), b.to_string()],
+                common_agents: common,
+            },
+        })
+        .collect()
+}
+
+fn detect_divergent_peers(
+    cached: &[CachedTrustScore],
+    outcomes: &HashMap<(String, String), Vec<TrustExperience>>,
+) -> Vec<Anomaly> {
+    // peer -> errors against my experiences made after the recommendation
+    let mut errors: BTreeMap<&str, Vec<f64>> = BTreeMap::new();
+    for score in cached {
+        let Some(experiences) = outcomes.get(&(score.id_domain.clone(), score.agent_id.clone())) else {
+            continue;
+        };
+        let later: Vec<(f64, f64)> = experiences
+            .iter()
+            .filter(|e| e.timestamp > score.cached_at)
+            .map(|e| (e.pv_roi, e.invested_volume))
+            .collect();
+        let weight: f64 = later.iter().map(|(_, volume)| volume.max(0.0)).sum();
+        if weight <= 0.0 {
+            continue;
+        }
+        let observed = later.iter().map(|(roi, volume)| roi * volume.max(0.0)).sum::<f64>() / weight;
+        errors
+            .entry(score.from_peer.as_str())
+            .or_default()
+            .push((score.score.expected_pv_roi - observed).abs());
+    }
+
+    errors
+        .into_iter()
+        .filter_map(|(peer, errors)| {
+            let mean_abs_error = errors.iter().sum::<f64>() / errors.len() as f64;
+            (errors.len() >= DIVERGENCE_MIN_AGENTS && mean_abs_error >= DIVERGENCE_MIN_MEAN_ERROR).then(|| Anomaly {
+                description: format!(
+                    "Recommendations of {} missed my later outcomes by {:.2} ROI on average across {} agents",
+                    peer, mean_abs_error, errors.len()
+                ),
+                kind: AnomalyKind::DivergentRecommender {
+                    peer: peer.to_string(),
+                    agents: errors.len(),
+                    mean_abs_error,
+                },
+            })
+        })
+        .collect()
+}
+
+fn detect_bursts(activity: &HashMap<(String, String), AgentActivity>, since: DateTime<Utc>) -> Vec<Anomaly> {
+    let mut anomalies: Vec<Anomaly> = activity
+        .iter()
+        .filter(|(_, a)| a.first_seen >= since && a.high_roi_reports >= BURST_MIN_REPORTS)
+        .map(|((id_domain, agent_id), a)| Anomaly {
+            description: format!(
+                "{}:{} was first seen {} and already has {} reports with ROI of at least {}",
+                id_domain, agent_id, a.first_seen.format("%Y-%m-%d"), a.high_roi_reports, BURST_HIGH_ROI
+            ),
+            kind: AnomalyKind::HighRoiBurst {
+                id_domain: id_domain.clone(),
+                agent_id: agent_id.clone(),
+                first_seen: a.first_seen,
+                high_roi_reports: a.high_roi_reports,
+            },
+        })
+        .collect();
+    anomalies.sort_by(|a, b| a.description.cmp(&b.description));
+    anomalies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TrustScore;
+
+    fn cached(peer: &str, agent: &str, roi: f64) -> CachedTrustScore {
+        CachedTrustScore {
+            id_domain: "test".to_string(),
+            agent_id: agent.to_string(),
+            score: TrustScore::new(roi, 100.0, 1),
+            from_peer: peer.to_string(),
+            cached_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_identical_peers_are_flagged() {
+        let mut scores = Vec::new();
+        for (agent, roi) in [("a", 1.2), ("b", 0.8), ("c", 1.5)] {
+            scores.push(cached("puppet1", agent, roi));
+            scores.push(cached("puppet2", agent, roi));
+            scores.push(cached("honest", agent, roi + 0.1));
+        }
+
+        let anomalies = detect_identical_peers(&scores);
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(
+            anomalies[0].kind,
+            AnomalyKind::IdenticalScores {
+                peers: vec!["puppet1".to_string(), "puppet2".to_string()],
+                common_agents: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_bursts_only_for_new_agents() {
+        let now = Utc::now();
+        let since = now - ChronoDuration::days(BURST_WINDOW_DAYS);
+        let mut activity = HashMap::new();
+        activity.insert(("test".to_string(), "new".to_string()), AgentActivity { first_seen: now, high_roi_reports: 6 });
+        activity.insert(
+            ("test".to_string(), "established".to_string()),
+            AgentActivity { first_seen: now - ChronoDuration::days(365), high_roi_reports: 6 },
+        );
+
+        let anomalies = detect_bursts(&activity, since);
+        assert_eq!(anomalies.len(), 1);
+        assert!(matches!(&anomalies[0].kind, AnomalyKind::HighRoiBurst { agent_id, .. } if agent_id == "new"));
+    }
+}
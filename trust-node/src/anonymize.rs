@@ -0,0 +1,120 @@
+use crate::node::ExportStream;
+use crate::types::{bucket_volume, Peer, PeerContact, TrustExperience};
+use futures::StreamExt;
+use std::collections::HashMap;
+
+/// Strip an export of what identifies the user, their friends and single transactions, keeping what scores
+/// are made of: experiences lose notes and adapter data and get bucketed volumes, peers are renamed to
+/// `peer-1`, `peer-2`, ... in the order of their ids and lose addresses, contact details, notes and tags
+pub fn anonymize(export: ExportStream) -> ExportStream {
+    let mut peers = export.peers;
+    peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+    let pseudonyms: HashMap<String, String> = peers
+        .iter()
+        .enumerate()
+        .map(|(index, peer)| (peer.peer_id.clone(), format!("peer-{}", index + 1)))
+        .collect();
+
+    let peers = peers.into_iter().map(|peer| anonymize_peer(peer, &pseudonyms)).collect();
+    let scores = export
+        .scores
+        .into_iter()
+        .map(|mut score| {
+            // Caps name peers and the tags of circles
+            score.capped.clear();
+            score
+        })
+        .collect();
+
+    ExportStream {
+        peers,
+        experiences: export.experiences.map(|experience| experience.map(anonymize_experience)).boxed(),
+        point_in_time: export.point_in_time,
+        scores,
+    }
+}
+
+fn anonymize_experience(experience: TrustExperience) -> TrustExperience {
+    TrustExperience {
+        invested_volume: bucket_volume(experience.invested_volume),
+        notes: None,
+        data: None,
+        ..experience
+    }
+}
+
+fn anonymize_peer(peer: Peer, pseudonyms: &HashMap<String, String>) -> Peer {
+    let pseudonym = pseudonyms.get(&peer.peer_id).cloned().unwrap_or_default();
+    Peer {
+        peer_id: pseudonym.clone(),
+        addresses: Vec::new(),
+        handle: None,
+        name: pseudonym,
+        notes: None,
+        tags: Vec::new(),
+        contact: PeerContact::default(),
+        last_interaction_at: None,
+        ..peer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use futures::stream;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_volumes_are_bucketed_on_a_log_scale() {
+        assert_eq!(bucket_volume(1.3), 1.0);
+        assert_eq!(bucket_volume(1.5), 2.0);
+        assert_eq!(bucket_volume(37.0), 50.0);
+        assert_eq!(bucket_volume(8.0), 10.0);
+        assert_eq!(bucket_volume(1234.0), 1000.0);
+        assert_eq!(bucket_volume(0.0), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_anonymized_exports_keep_rois_and_drop_names() {
+        let experience = TrustExperience {
+            id: Uuid::new_v4(),
+            id_domain: "test".to_string(),
+            agent_id: "vendor".to_string(),
+            pv_roi: 1.2,
+            invested_volume: 180.0,
+            timestamp: Utc::now(),
+            notes: Some("bought a gift for my sister".to_string()),
+            data: Some(serde_json::json!({"tx": "0xabc"})),
+        };
+        let peer = Peer {
+            peer_id: "12D3KooWBob".to_string(),
+            addresses: Vec::new(),
+            handle: Some("bob".to_string()),
+            name: "Bob Smith".to_string(),
+            recommender_quality: 0.7,
+            added_at: Utc::now(),
+            notes: Some("neighbour".to_string()),
+            tags: vec!["family".to_string()],
+            contact: PeerContact { email: Some("bob@example.com".to_string()), fediverse: None },
+            last_interaction_at: None,
+        };
+        let export = ExportStream {
+            peers: vec![peer],
+            experiences: stream::iter(vec![Ok(experience.clone())]).boxed(),
+            point_in_time: None,
+            scores: Vec::new(),
+        };
+
+        let anonymized = anonymize(export);
+        assert_eq!(anonymized.peers[0].peer_id, "peer-1");
+        assert_eq!(anonymized.peers[0].name, "peer-1");
+        assert_eq!(anonymized.peers[0].recommender_quality, 0.7);
+        assert!(anonymized.peers[0].handle.is_none() && anonymized.peers[0].contact.email.is_none());
+
+        let experiences: Vec<_> = anonymized.experiences.collect().await;
+        let stripped = experiences[0].as_ref().unwrap();
+        assert_eq!((stripped.id, stripped.pv_roi, stripped.invested_volume), (experience.id, 1.2, 200.0));
+        assert!(stripped.notes.is_none() && stripped.data.is_none());
+    }
+}
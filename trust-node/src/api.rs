@@ -1,27 +1,404 @@
-use crate::node::NodeCommand;
-use crate::types::{Peer, TrustDataExport, TrustExperience, TrustQuery, TrustResponse, TrustScore};
+use crate::anomaly::AnomalyReport;
+use crate::anonymize;
+use crate::eigentrust::GlobalTrust;
+use crate::clock::{system_clock, SharedClock};
+use crate::archive::{ArchiveDecompressor, ArchiveError, ArchiveManifest, ArchiveVerifier, ArchiveWriter, ARCHIVE_CONTENT_TYPE};
+use crate::backup::{BackupStatus, RestoreSummary};
+use crate::diff::ExportDiff;
+use crate::events::{NodeEvent, PeerEvent};
+use crate::graph::GraphFormat;
+use crate::import::{ImportBatch, ImportRecord, ImportStreamError, NdjsonImport};
+use crate::mapping::{ImportMapping, RecordError};
+use crate::node::{ExportStream, NodeCommand};
+use crate::storage::StorageError;
+use crate::identity::IdentityRotation;
+use crate::invite::{InvalidInvite, IssuedInvite, MAX_INVITE_TTL};
+use crate::quarantine::QuarantinedPeer;
+use crate::reputation::{InvalidProof, PeerOpinion};
+use crate::validation::{validate, FieldError, Fields, InvalidInput, Validate};
+use crate::types::{AgentClaim, AgentIdForm, Aggregator, CacheStats, DecayFunction, Discounting, DomainDefaults, ExperienceFilter, ExperienceTemplate, Freshness, ExperienceRevision, ExperienceUpdate, IdempotentResponse, ImportStrategy, ImportSummary, IndexPoint, InflationIndex, InvalidConfig, InvalidQuery, InvalidWatch, MaintenanceReport, Peer, PeerAddress, PeerContact, PeerReciprocity, PeerRequest, PeerRequestStatus, PeerStatus, PeerUpdate, PendingExperience, QualityRevision, QueryStats, QueryTiming, RuntimeConfig, RuntimeStats, RuntimeConfigUpdate, ScoreDepth, SharingPrecision, SimulationResult, StorageStats, TrustDataExport, TrustExperience, TrustQuery, TrustScore, TrustSimulation, WatchRequest, WatchedAgent, is_valid_correlation_id, EXPORT_FORMAT_VERSION, MAX_PEER_ADDRESSES, MAX_TEMPLATE_INTERVAL_DAYS};
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::Json,
-    routing::{delete, get, post},
-    Router,
+    async_trait,
+    extract::{DefaultBodyLimit, FromRequest, FromRequestParts, Multipart, OriginalUri, Path, Query, Request, State},
+    body::{Body, Bytes},
+    http::{header, request::Parts, HeaderMap, HeaderName, HeaderValue, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{sse::{Event, KeepAlive, Sse}, Html, IntoResponse, Json, Response},
+    routing::{delete, get, patch, post, put},
+    Router, ServiceExt,
 };
-use chrono::Utc;
-use serde::Deserialize;
+use chrono::{DateTime, Utc};
+use futures::{future, stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
-use tokio::sync::{mpsc, oneshot};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tower::Layer;
+use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::CorsLayer;
-use tracing::info;
+use tracing::{info, warn};
 use uuid::Uuid;
 
+/// Header that selects which hosted user a request is for
+pub const USER_HEADER: &str = "x-repeer-user";
+/// Alternative to the header: `/v1/users/<name>/trust/...` is routed like `/v1/trust/...` for `<name>`
+const USER_PATH_PREFIX: &str = "/users/";
+/// Content type of streamed imports and exports
+const NDJSON: &str = "application/x-ndjson";
+/// Compressed bytes read from a spooled archive at a time
+const ARCHIVE_READ_CHUNK: usize = 64 * 1024;
+/// Multipart part holding the NDJSON file or archive of an upload
+const IMPORT_FILE_FIELD: &str = "file";
+/// Largest import read whole into memory, a JSON document rather than a stream; bigger data goes as NDJSON or an archive
+const MAX_IMPORT_DOCUMENT_BYTES: usize = 32 * 1024 * 1024;
+/// Versions served under `/<version>/`, oldest first
+pub const API_VERSIONS: [&str; 1] = ["v1"];
+/// Version that serves paths without a version prefix, as they were before versioning
+const LEGACY_API_VERSION: &str = "v1";
+/// Answered the same in every version, for probes and clients that don't know the version yet
+const UNVERSIONED_PATHS: [&str; 3] = ["/health", "/versions", "/ui"];
+/// The admin UI, a single page talking to the versioned API like any other client
+const ADMIN_UI: &str = include_str!("ui/index.html");
+/// Number of search matches across all pages
+const TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+/// Id of a trust query across all the nodes it reaches; taken from the request if valid, and always answered
+const CORRELATION_ID: HeaderName = HeaderName::from_static("x-correlation-id");
+/// Set on responses to unversioned paths, which will keep working only until LEGACY_API_VERSION is retired
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+/// Client-chosen key that makes a retried POST answer with the first attempt's response instead of writing again
+const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+/// Set on responses replayed for an Idempotency-Key rather than produced by this request
+const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+/// Longest Idempotency-Key accepted, enough for any UUID or hash a client would use
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// The user profiles served by this process, each backed by its own node
+#[derive(Clone)]
+pub struct Tenants {
+    nodes: Arc<HashMap<String, mpsc::Sender<NodeCommand>>>,
+    default_user: String,
+    /// Idempotency keys of requests still being answered, by user, endpoint and key
+    in_flight: Arc<Mutex<HashSet<String>>>,
+    /// Timestamps new records and query times; shared with the nodes in tests
+    clock: SharedClock,
+}
+
+impl Tenants {
+    pub fn new(default_user: String, nodes: HashMap<String, mpsc::Sender<NodeCommand>>) -> Self {
+        Self {
+            nodes: Arc::new(nodes),
+            default_user,
+            in_flight: Arc::default(),
+            clock: system_clock(),
+        }
+    }
+
+    pub fn with_clock(self, clock: SharedClock) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// Mark a key as being answered until the returned guard drops; None if another request holds it
+    fn claim_idempotency_key(&self, user: &str, endpoint: &str, key: &str) -> Option<InFlightKey> {
+        let claim = format!("{} {} {}", user, endpoint, key);
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        in_flight.insert(claim.clone()).then(|| InFlightKey { in_flight: self.in_flight.clone(), claim })
+    }
+}
+
+/// An Idempotency-Key claimed by a request in progress, released when it's answered
+struct InFlightKey {
+    in_flight: Arc<Mutex<HashSet<String>>>,
+    claim: String,
+}
+
+impl Drop for InFlightKey {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&self.claim);
+    }
+}
+
+/// User picked by `select_user` for the current request
+#[derive(Clone)]
+struct SelectedUser(String);
+
+/// Command channel of the node serving the request's user
 #[derive(Clone)]
 pub struct ApiState {
     pub command_tx: mpsc::Sender<NodeCommand>,
+    pub user: String,
+    pub clock: SharedClock,
+}
+
+#[async_trait]
+impl FromRequestParts<Tenants> for ApiState {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, tenants: &Tenants) -> Result<Self, Self::Rejection> {
+        let user = parts
+            .extensions
+            .get::<SelectedUser>()
+            .map(|selected| selected.0.as_str())
+            .unwrap_or(&tenants.default_user);
+
+        tenants
+            .nodes
+            .get(user)
+            .map(|command_tx| ApiState {
+                command_tx: command_tx.clone(),
+                user: user.to_string(),
+                clock: tenants.clock.clone(),
+            })
+            .ok_or(StatusCode::NOT_FOUND)
+    }
+}
+
+/// Where a request path is routed, once the version and user prefix are taken apart
+#[derive(Debug, PartialEq)]
+struct RoutedPath {
+    /// Path as the router sees it, always starting with the version unless it's version-independent
+    path: String,
+    user: Option<String>,
+    /// Unversioned path served by LEGACY_API_VERSION, answered with deprecation headers
+    legacy: bool,
+}
+
+/// Split `/v1/users/<name>/trust/...`, `/users/<name>/trust/...` or `/trust/...` into version, user and route
+fn route_path(path: &str) -> RoutedPath {
+    if UNVERSIONED_PATHS.contains(&path) {
+        return RoutedPath { path: path.to_string(), user: None, legacy: false };
+    }
+
+    let first_segment = path.trim_start_matches('/').split('/').next().unwrap_or("");
+    let is_version = first_segment.len() > 1
+        && first_segment.starts_with('v')
+        && first_segment[1..].bytes().all(|b| b.is_ascii_digit());
+    let (version, rest) = if is_version {
+        (first_segment, &path[first_segment.len() + 1..])
+    } else {
+        (LEGACY_API_VERSION, path)
+    };
+
+    let (user, rest) = match rest.strip_prefix(USER_PATH_PREFIX) {
+        Some(prefixed) => match prefixed.split_once('/') {
+            Some((user, route)) => (Some(user.to_string()), format!("/{}", route)),
+            None => (Some(prefixed.to_string()), "/".to_string()),
+        },
+        None => (None, rest.to_string()),
+    };
+
+    RoutedPath { path: format!("/{}{}", version, rest), user, legacy: !is_version }
+}
+
+/// Resolve the API version and pick the user from the path prefix or the header before routing,
+/// rewriting the path to the versioned route
+async fn select_user(mut request: Request, next: Next) -> Response {
+    let original_path = request.uri().path().to_string();
+    let routed = route_path(&original_path);
+
+    if routed.path != original_path {
+        let path_and_query = match request.uri().query() {
+            Some(query) => format!("{}?{}", routed.path, query),
+            None => routed.path,
+        };
+        match path_and_query.parse::<Uri>() {
+            Ok(uri) => *request.uri_mut() = uri,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        }
+    }
+
+    if let Some(user) = routed.user {
+        request.extensions_mut().insert(SelectedUser(user));
+    } else if let Some(user) = request.headers().get(USER_HEADER).and_then(|value| value.to_str().ok()) {
+        let user = SelectedUser(user.to_string());
+        request.extensions_mut().insert(user);
+    }
+
+    let mut response = next.run(request).await;
+    if routed.legacy {
+        let successor = format!("</{}{}>; rel=\"successor-version\"", LEGACY_API_VERSION, original_path);
+        let headers = response.headers_mut();
+        headers.insert(DEPRECATION, HeaderValue::from_static("true"));
+        if let Ok(link) = HeaderValue::from_str(&successor) {
+            headers.insert(header::LINK, link);
+        }
+    }
+    response
+}
+
+/// Running SHA-256 over what a request was answered on: its query string, then its body bytes
+struct Fingerprint {
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl Fingerprint {
+    fn new(query: Option<&str>) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(query.unwrap_or("").as_bytes());
+        hasher.update(b"\n");
+        Self { hasher, bytes: 0 }
+    }
+
+    fn add(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+        self.bytes += chunk.len() as u64;
+    }
+
+    fn sha256(&self) -> String {
+        format!("{:x}", self.hasher.clone().finalize())
+    }
+}
+
+/// Keys of 1 to MAX_IDEMPOTENCY_KEY_LEN visible ASCII characters, as UUIDs and hashes are
+fn is_valid_idempotency_key(key: &[u8]) -> bool {
+    (1..=MAX_IDEMPOTENCY_KEY_LEN).contains(&key.len()) && key.iter().all(u8::is_ascii_graphic)
+}
+
+fn idempotency_key_error(message: &str) -> Response {
+    InvalidInput { errors: vec![FieldError { field: "Idempotency-Key".to_string(), message: message.to_string() }] }.into_response()
+}
+
+fn replayed_response(snapshot: IdempotentResponse) -> Response {
+    let mut response = Response::new(Body::from(snapshot.body));
+    *response.status_mut() = StatusCode::from_u16(snapshot.status).unwrap_or(StatusCode::OK);
+    let headers = response.headers_mut();
+    if let Some(content_type) = snapshot.content_type.and_then(|value| HeaderValue::from_str(&value).ok()) {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    headers.insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+    response
+}
+
+/// Answer a POST retried with the same Idempotency-Key with the response the first attempt got, so clients can
+/// retry after a dropped connection without writing twice; requests without the header pass through
+async fn idempotent(State(tenants): State<Tenants>, request: Request, next: Next) -> Response {
+    let Some(key) = request.headers().get(&IDEMPOTENCY_KEY) else {
+        return next.run(request).await;
+    };
+    if !is_valid_idempotency_key(key.as_bytes()) {
+        return idempotency_key_error("must be 1 to 255 visible ASCII characters");
+    }
+    let key = String::from_utf8_lossy(key.as_bytes()).into_owned();
+
+    let (mut parts, body) = request.into_parts();
+    let Ok(state) = ApiState::from_request_parts(&mut parts, &tenants).await else {
+        return next.run(Request::from_parts(parts, body)).await;
+    };
+    let path = parts.extensions.get::<OriginalUri>().map_or(parts.uri.path(), |original| original.0.path());
+    let endpoint = format!("{} {}", parts.method, path);
+    let mut fingerprint = Fingerprint::new(parts.uri.query());
+
+    let Some(_claim) = tenants.claim_idempotency_key(&state.user, &endpoint, &key) else {
+        return (StatusCode::CONFLICT, "a request with this Idempotency-Key is still in progress").into_response();
+    };
+    let stored = send_command(&state, |response| NodeCommand::GetIdempotentResponse {
+        endpoint: endpoint.clone(),
+        key: key.clone(),
+        response,
+    })
+    .await;
+    let stored = match stored {
+        Ok(Ok(stored)) => stored,
+        Ok(Err(e)) => {
+            warn!("Failed to look up idempotency key: {}", e);
+            return error_status(&e).into_response();
+        }
+        Err(status) => return status.into_response(),
+    };
+
+    if let Some(snapshot) = stored {
+        // Only the bytes the first attempt was answered on count, so one cut short by an error still matches
+        let mut chunks = body.into_data_stream();
+        while fingerprint.bytes < snapshot.request_bytes {
+            match chunks.next().await {
+                Some(Ok(chunk)) => {
+                    let wanted = (snapshot.request_bytes - fingerprint.bytes).min(chunk.len() as u64) as usize;
+                    fingerprint.add(&chunk[..wanted]);
+                }
+                Some(Err(_)) => return StatusCode::BAD_REQUEST.into_response(),
+                None => break,
+            }
+        }
+        if fingerprint.bytes != snapshot.request_bytes || fingerprint.sha256() != snapshot.request_hash {
+            return idempotency_key_error("was already used for a different request");
+        }
+        return replayed_response(snapshot);
+    }
+
+    let fingerprint = Arc::new(Mutex::new(fingerprint));
+    let hashed = fingerprint.clone();
+    let body = Body::from_stream(body.into_data_stream().inspect_ok(move |chunk| {
+        hashed.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).add(chunk);
+    }));
+    let response = next.run(Request::from_parts(parts, body)).await;
+    // Failures on this side may well go away, so a retry gets another go rather than the same error
+    if response.status().is_server_error() {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let (request_hash, request_bytes) = {
+        let fingerprint = fingerprint.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        (fingerprint.sha256(), fingerprint.bytes)
+    };
+    let snapshot = IdempotentResponse {
+        endpoint,
+        key,
+        request_hash,
+        request_bytes,
+        status: parts.status.as_u16(),
+        content_type: parts.headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).map(str::to_string),
+        body: body.to_vec(),
+    };
+    match send_command(&state, |response| NodeCommand::RecordIdempotentResponse { snapshot, response }).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!("Failed to record idempotent response: {}", e),
+        Err(status) => warn!("Failed to record idempotent response: {}", status),
+    }
+    Response::from_parts(parts, Body::from(body))
 }
 
 /// Helper function to execute a node command and handle the standard error cases
 async fn execute_command<T, F>(state: &ApiState, command_builder: F) -> Result<T, StatusCode>
+where
+    F: FnOnce(oneshot::Sender<Result<T, anyhow::Error>>) -> NodeCommand,
+{
+    send_command(state, command_builder).await?.map_err(|e| {
+        warn!("Command failed: {}", e);
+        error_status(&e)
+    })
+}
+
+/// Like execute_command, but a rejected query, config or watch becomes a 422 listing what's wrong with it
+async fn execute_query<T, F>(state: &ApiState, command_builder: F) -> Result<T, Response>
+where
+    F: FnOnce(oneshot::Sender<Result<T, anyhow::Error>>) -> NodeCommand,
+{
+    let result = send_command(state, command_builder).await.map_err(IntoResponse::into_response)?;
+    result.map_err(|e| {
+        let violations = if let Some(invalid) = e.downcast_ref::<InvalidQuery>() {
+            invalid.violations.clone()
+        } else if let Some(invalid) = e.downcast_ref::<InvalidConfig>() {
+            invalid.violations.clone()
+        } else if let Some(invalid) = e.downcast_ref::<InvalidWatch>() {
+            invalid.violations.clone()
+        } else {
+            warn!("Command failed: {}", e);
+            return error_status(&e).into_response();
+        };
+        (StatusCode::UNPROCESSABLE_ENTITY, Json(QueryViolations { violations })).into_response()
+    })
+}
+
+async fn send_command<T, F>(state: &ApiState, command_builder: F) -> Result<Result<T, anyhow::Error>, StatusCode>
 where
     F: FnOnce(oneshot::Sender<Result<T, anyhow::Error>>) -> NodeCommand,
 {
@@ -32,46 +409,183 @@ where
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    rx.await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    rx.await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[derive(Serialize)]
+struct QueryViolations {
+    violations: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct InputViolations {
+    violations: Vec<String>,
+    errors: Vec<FieldError>,
 }
 
-pub async fn run_api_server(port: u16, command_tx: mpsc::Sender<NodeCommand>) -> anyhow::Result<()> {
-    let state = ApiState { command_tx };
+/// A 422 like a rejected query's, with each violation also broken out into its field and message
+impl IntoResponse for InvalidInput {
+    fn into_response(self) -> Response {
+        let violations = self.errors.iter().map(|e| format!("{}: {}", e.field, e.message)).collect();
+        (StatusCode::UNPROCESSABLE_ENTITY, Json(InputViolations { violations, errors: self.errors })).into_response()
+    }
+}
+
+/// Map a command error to an HTTP status, using the storage error kind where there is one
+fn error_status(err: &anyhow::Error) -> StatusCode {
+    match err.downcast_ref::<StorageError>() {
+        Some(StorageError::Duplicate(_)) => StatusCode::CONFLICT,
+        Some(StorageError::NotFound(_)) => StatusCode::NOT_FOUND,
+        Some(StorageError::Io(_)) => StatusCode::SERVICE_UNAVAILABLE,
+        Some(StorageError::Corruption(_)) | Some(StorageError::Database(_)) | None => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Bind the API port on localhost, so callers know it's taken before serving starts
+pub async fn bind_api(port: u16) -> anyhow::Result<tokio::net::TcpListener> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("API server listening on {}", addr);
+    Ok(listener)
+}
 
+pub async fn serve_api(listener: tokio::net::TcpListener, tenants: Tenants) -> anyhow::Result<()> {
     let app = Router::new()
         .route("/health", get(health))
-        .route("/experiences", post(add_experience))
+        .route("/versions", get(list_versions))
+        .route("/ui", get(admin_ui))
+        .nest("/v1", v1_routes(&tenants))
+        .with_state(tenants)
+        // Only for clients sending Accept-Encoding; the default predicate leaves event streams and tiny bodies alone
+        .layer(CompressionLayer::new().compress_when(
+            DefaultPredicate::new().and(NotForContentType::const_new(ARCHIVE_CONTENT_TYPE)),
+        ))
+        .layer(CorsLayer::permissive());
+    // Applied outside the router so the path is rewritten before routes are matched
+    let app = middleware::from_fn(select_user).layer(app);
+
+    axum::serve(listener, ServiceExt::<Request>::into_make_service(app)).await?;
+
+    Ok(())
+}
+
+/// The API as of v1; breaking changes go into a new version next to it, leaving these paths as they are
+fn v1_routes(tenants: &Tenants) -> Router<Tenants> {
+    let idempotent = || middleware::from_fn_with_state(tenants.clone(), idempotent);
+    Router::new()
+        .route("/health", get(health))
+        .route("/users", get(list_users))
+        .route("/experiences", get(find_experiences))
+        .route("/experiences", post(add_experience).layer(idempotent()))
+        .route("/experiences/batch", post(add_experiences_batch))
+        .route("/experiences/pending", get(get_pending_experiences))
+        .route("/experiences/pending", post(add_pending_experience))
         .route("/experiences/clear", delete(clear_experiences))
         .route("/experiences/:id_domain/:agent_id", get(get_experiences))
         .route("/experience/:experience_id", delete(delete_experience))
+        .route("/experience/:experience_id", patch(update_experience))
+        .route("/experience/:experience_id/history", get(get_experience_history))
+        .route("/experience/:experience_id/revert/:revision", post(revert_experience))
+        .route("/experience/:experience_id/settle", post(settle_experience))
+        .route("/templates", get(get_experience_templates))
+        .route("/templates", post(add_experience_template))
+        .route("/templates/:template_id", delete(remove_experience_template))
         .route("/trust/:id_domain/:agent_id", get(query_trust))
         .route("/trust/batch", post(query_trust_batch))
+        .route("/trust/simulate", post(simulate_trust))
         .route("/peers", get(get_peers))
-        .route("/peers", post(add_peer))
+        .route("/peers", post(add_peer).layer(idempotent()))
+        .route("/peers/batch", post(add_peers))
+        .route("/peers/invites", post(create_invite))
+        .route("/peers/redeem", post(redeem_invite))
+        .route("/peers/propose", post(propose_peering))
         .route("/peers/clear", delete(clear_peers))
+        .route("/peers/requests", get(get_peer_requests))
+        .route("/peers/requests/:peer_id", delete(remove_peer_request))
+        .route("/peers/requests/:peer_id/accept", post(accept_peer_request))
+        .route("/peers/requests/:peer_id/ignore", post(ignore_peer_request))
+        .route("/peers/requests/:peer_id/block", post(block_peer_request))
         .route("/peers/:peer_id", delete(delete_peer))
+        .route("/peers/:peer_id", patch(update_peer))
         .route("/peers/:peer_id/quality", post(update_peer_quality))
+        .route("/peers/:peer_id/quality/history", get(get_quality_history))
         .route("/peers/connected", get(get_connected_peers))
+        .route("/peers/quarantine", get(get_quarantined_peers))
+        .route("/peers/quarantine/:peer_id", delete(release_quarantined_peer))
         .route("/peers/discover", post(trigger_peer_discovery))
         .route("/peers/self", get(get_self_peer_id))
+        .route("/peers/events", get(get_peer_events))
+        .route("/identity/rotate", post(rotate_identity))
         .route("/export", get(export_trust_data))
-        .route("/import", post(import_trust_data))
-        .with_state(state)
-        .layer(CorsLayer::permissive());
+        // Uploads are streamed, so the default 2 MB cap on bodies would only get in the way
+        .route("/import", post(import_trust_data).layer(DefaultBodyLimit::disable()).layer(idempotent()))
+        .route("/import/mapped", post(import_mapped).layer(DefaultBodyLimit::max(MAX_IMPORT_DOCUMENT_BYTES)))
+        .route("/diff", post(diff_exports))
+        .route("/domains", get(get_domain_defaults))
+        .route("/domains/:id_domain", put(set_domain_defaults))
+        .route("/domains/:id_domain", delete(remove_domain_defaults))
+        .route("/inflation", get(get_inflation_indexes))
+        .route("/inflation/:currency", put(set_inflation_index))
+        .route("/inflation/:currency", delete(remove_inflation_index))
+        .route("/precision", get(get_sharing_precision))
+        .route("/precision/:subject", put(set_sharing_precision))
+        .route("/precision/:subject", delete(remove_sharing_precision))
+        .route("/claims", get(get_agent_claims))
+        .route("/claims", post(claim_agent))
+        .route("/claims/:id_domain/:agent_id", delete(remove_agent_claim))
+        .route("/claims/:id_domain/:agent_id/verify", post(verify_agent_claim))
+        .route("/reputation", get(get_own_reputation))
+        .route("/watchlist", get(get_watchlist))
+        .route("/watchlist/:id_domain/:agent_id", put(watch_agent))
+        .route("/watchlist/:id_domain/:agent_id", delete(unwatch_agent))
+        .route("/stats/storage", get(get_storage_stats))
+        .route("/stats/cache", get(get_cache_stats))
+        .route("/stats/cache/clear", post(clear_cache))
+        .route("/stats/queries", get(get_query_stats))
+        .route("/stats/runtime", get(get_runtime_stats))
+        .route("/stats/reciprocity", get(get_reciprocity))
+        .route("/config", get(get_runtime_config))
+        .route("/config", patch(update_runtime_config))
+        .route("/backups", get(get_backup_status))
+        .route("/anomalies", get(get_anomalies))
+        .route("/global-trust", get(get_global_trust))
+        .route("/graph", get(get_trust_graph))
+        .route("/events", get(subscribe_events))
+        .route("/admin/restore", post(restore_backup))
+        .route("/admin/maintenance", post(maintain_database))
+}
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
-    info!("API server listening on {}", addr);
+async fn health() -> &'static str {
+    "OK"
+}
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+/// Versions this node serves, for clients to pick the newest they understand
+#[derive(Serialize)]
+struct ApiVersions {
+    versions: Vec<&'static str>,
+    latest: &'static str,
+    /// Versions still served but due to be removed
+    deprecated: Vec<&'static str>,
+}
 
-    Ok(())
+async fn list_versions() -> Json<ApiVersions> {
+    Json(ApiVersions {
+        versions: API_VERSIONS.to_vec(),
+        latest: API_VERSIONS[API_VERSIONS.len() - 1],
+        deprecated: Vec::new(),
+    })
 }
 
-async fn health() -> &'static str {
-    "OK"
+async fn admin_ui() -> Html<&'static str> {
+    Html(ADMIN_UI)
+}
+
+async fn list_users(State(tenants): State<Tenants>) -> Json<Vec<String>> {
+    let mut users: Vec<String> = tenants.nodes.keys().cloned().collect();
+    users.sort();
+    Json(users)
 }
 
 #[derive(Deserialize)]
@@ -81,40 +595,276 @@ pub struct AddExperienceRequest {
     pub investment: f64,
     pub return_value: f64,
     pub timeframe_days: f64,
+    /// Replaces the domain's or the node's model for this experience
+    pub discounting: Option<Discounting>,
+    /// Rate for the model that applies otherwise
     pub discount_rate: Option<f64>,
     pub notes: Option<String>,
     pub data: Option<serde_json::Value>,
 }
 
+impl Validate for AddExperienceRequest {
+    fn validate(&self, fields: &mut Fields) {
+        fields.non_empty("id_domain", &self.id_domain);
+        fields.agent_id("agent_id", &self.id_domain, &self.agent_id);
+        // The return is divided by the investment
+        fields.positive("investment", self.investment);
+        fields.finite("return_value", self.return_value);
+        fields.non_negative("timeframe_days", self.timeframe_days);
+        fields.discounting(self.discounting.as_ref(), self.discount_rate);
+    }
+}
+
+impl AddExperienceRequest {
+    fn into_experience(self, discounting: &Discounting, now: DateTime<Utc>) -> TrustExperience {
+        let pv_roi = discounting.pv_roi(self.investment, self.return_value, self.timeframe_days);
+
+        TrustExperience {
+            id: Uuid::new_v4(),
+            id_domain: self.id_domain,
+            agent_id: self.agent_id,
+            pv_roi,
+            invested_volume: self.investment,
+            timestamp: now,
+            notes: self.notes,
+            data: self.data,
+        }
+    }
+}
+
+/// The model each request is valued with: its own, else its domain's or the node's, at the request's rate if it names one
+async fn resolve_discounting(state: &ApiState, requests: &[AddExperienceRequest]) -> Result<Vec<Discounting>, StatusCode> {
+    let id_domains: Vec<String> = requests
+        .iter()
+        .filter(|req| req.discounting.is_none())
+        .map(|req| req.id_domain.clone())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let registered = if id_domains.is_empty() {
+        HashMap::new()
+    } else {
+        execute_command(state, |response| NodeCommand::GetDiscounting { id_domains, response }).await?
+    };
+
+    requests
+        .iter()
+        .map(|req| {
+            let mut discounting = req.discounting.clone()
+                .or_else(|| registered.get(&req.id_domain).cloned())
+                .unwrap_or_default();
+            if let Some(rate) = req.discount_rate {
+                discounting = discounting.with_rate(rate);
+            }
+            discounting.check().map_err(|_| StatusCode::BAD_REQUEST)?;
+            Ok(discounting)
+        })
+        .collect()
+}
+
 async fn add_experience(
-    State(state): State<ApiState>,
+    state: ApiState,
     Json(req): Json<AddExperienceRequest>,
-) -> Result<Json<TrustExperience>, StatusCode> {
-    let discount_rate = req.discount_rate.unwrap_or(0.05);
-    let years = req.timeframe_days / 365.0;
-    let pv_roi = (req.return_value / (1.0 + discount_rate).powf(years)) / req.investment;
+) -> Result<Json<TrustExperience>, Response> {
+    validate(&req).map_err(IntoResponse::into_response)?;
+    let discounting = resolve_discounting(&state, std::slice::from_ref(&req)).await.map_err(IntoResponse::into_response)?;
+    let experience = req.into_experience(&discounting[0], state.clock.now());
+
+    execute_command(&state, |response| NodeCommand::AddExperience {
+        experience: experience.clone(),
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(experience))
+}
+
+async fn add_experiences_batch(
+    state: ApiState,
+    Json(reqs): Json<Vec<AddExperienceRequest>>,
+) -> Result<Json<Vec<TrustExperience>>, Response> {
+    validate(reqs.as_slice()).map_err(IntoResponse::into_response)?;
+    let discounting = resolve_discounting(&state, &reqs).await.map_err(IntoResponse::into_response)?;
+    let now = state.clock.now();
+    let experiences: Vec<TrustExperience> = reqs
+        .into_iter()
+        .zip(&discounting)
+        .map(|(req, discounting)| req.into_experience(discounting, now))
+        .collect();
+
+    execute_command(&state, |response| NodeCommand::AddExperiences {
+        experiences: experiences.clone(),
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(experiences))
+}
+
+/// An investment made now whose return isn't known yet
+#[derive(Deserialize)]
+pub struct AddPendingExperienceRequest {
+    pub id_domain: String,
+    pub agent_id: String,
+    pub investment: f64,
+    pub notes: Option<String>,
+    pub data: Option<serde_json::Value>,
+}
 
-    let experience = TrustExperience {
+impl Validate for AddPendingExperienceRequest {
+    fn validate(&self, fields: &mut Fields) {
+        fields.non_empty("id_domain", &self.id_domain);
+        fields.agent_id("agent_id", &self.id_domain, &self.agent_id);
+        // Settling divides the return by the investment
+        fields.positive("investment", self.investment);
+    }
+}
+
+async fn add_pending_experience(
+    state: ApiState,
+    Json(req): Json<AddPendingExperienceRequest>,
+) -> Result<Json<PendingExperience>, Response> {
+    validate(&req).map_err(IntoResponse::into_response)?;
+    let pending = PendingExperience {
         id: Uuid::new_v4(),
         id_domain: req.id_domain,
         agent_id: req.agent_id,
-        pv_roi,
         invested_volume: req.investment,
-        timestamp: Utc::now(),
+        timestamp: state.clock.now(),
         notes: req.notes,
         data: req.data,
+        expected_return: None,
+        template_id: None,
     };
 
-    execute_command(&state, |response| NodeCommand::AddExperience {
-        experience: experience.clone(),
+    execute_command(&state, |response| NodeCommand::AddPendingExperience {
+        pending: pending.clone(),
         response,
-    }).await?;
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(pending))
+}
+
+async fn get_pending_experiences(state: ApiState) -> Result<Json<Vec<PendingExperience>>, StatusCode> {
+    let pending = execute_command(&state, |response| NodeCommand::GetPendingExperiences { response }).await?;
+    Ok(Json(pending))
+}
+
+#[derive(Deserialize)]
+pub struct SettleExperienceRequest {
+    /// Left out to confirm an experience from a template at its expected return
+    pub return_value: Option<f64>,
+    pub discounting: Option<Discounting>,
+    pub discount_rate: Option<f64>,
+}
+
+impl Validate for SettleExperienceRequest {
+    fn validate(&self, fields: &mut Fields) {
+        if let Some(return_value) = self.return_value {
+            fields.finite("return_value", return_value);
+        }
+        fields.discounting(self.discounting.as_ref(), self.discount_rate);
+    }
+}
+
+/// Record the return of a pending experience; it is discounted over the time since the investment
+async fn settle_experience(
+    state: ApiState,
+    Path(experience_id): Path<String>,
+    Json(req): Json<SettleExperienceRequest>,
+) -> Result<Json<TrustExperience>, Response> {
+    validate(&req).map_err(IntoResponse::into_response)?;
+    let experience = execute_command(&state, |response| NodeCommand::SettleExperience {
+        experience_id,
+        return_value: req.return_value,
+        discounting: req.discounting,
+        discount_rate: req.discount_rate,
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
 
     Ok(Json(experience))
 }
 
+#[derive(Deserialize)]
+pub struct AddTemplateRequest {
+    pub id_domain: String,
+    pub agent_id: String,
+    pub investment: f64,
+    pub expected_return: f64,
+    pub interval_days: u32,
+    /// When the first occurrence is due, now if left out
+    pub first_due_at: Option<chrono::DateTime<Utc>>,
+    pub notes: Option<String>,
+}
+
+impl Validate for AddTemplateRequest {
+    fn validate(&self, fields: &mut Fields) {
+        fields.non_empty("id_domain", &self.id_domain);
+        fields.agent_id("agent_id", &self.id_domain, &self.agent_id);
+        fields.positive("investment", self.investment);
+        fields.finite("expected_return", self.expected_return);
+        fields.check(
+            (1..=MAX_TEMPLATE_INTERVAL_DAYS).contains(&self.interval_days),
+            "interval_days",
+            format!("must be between 1 and {}", MAX_TEMPLATE_INTERVAL_DAYS),
+        );
+    }
+}
+
+async fn add_experience_template(
+    state: ApiState,
+    Json(req): Json<AddTemplateRequest>,
+) -> Result<Json<ExperienceTemplate>, Response> {
+    validate(&req).map_err(IntoResponse::into_response)?;
+    let now = state.clock.now();
+    let template = ExperienceTemplate {
+        id: Uuid::new_v4(),
+        id_domain: req.id_domain,
+        agent_id: req.agent_id,
+        investment: req.investment,
+        expected_return: req.expected_return,
+        interval_days: req.interval_days,
+        next_due_at: req.first_due_at.unwrap_or(now),
+        notes: req.notes,
+        created_at: now,
+    };
+
+    execute_command(&state, |response| NodeCommand::AddExperienceTemplate {
+        template: template.clone(),
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(template))
+}
+
+async fn get_experience_templates(state: ApiState) -> Result<Json<Vec<ExperienceTemplate>>, StatusCode> {
+    let templates = execute_command(&state, |response| NodeCommand::GetExperienceTemplates { response }).await?;
+    Ok(Json(templates))
+}
+
+async fn remove_experience_template(
+    state: ApiState,
+    Path(template_id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    execute_command(&state, |response| NodeCommand::RemoveExperienceTemplate { template_id, response }).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Search experiences by domain, agent, tag, time range and full text, a page at a time; the number
+/// of matches across all pages is in the X-Total-Count header
+async fn find_experiences(
+    state: ApiState,
+    Query(filter): Query<ExperienceFilter>,
+) -> Result<Response, StatusCode> {
+    let page = execute_command(&state, |response| NodeCommand::FindExperiences {
+        filter,
+        response,
+    }).await?;
+
+    Ok(([(TOTAL_COUNT, page.total.to_string())], Json(page.experiences)).into_response())
+}
+
+/// Every experience with one agent; GET /experiences?domain=&agent= does the same with paging and sorting
 async fn get_experiences(
-    State(state): State<ApiState>,
+    state: ApiState,
     Path((id_domain, agent_id)): Path<(String, String)>,
 ) -> Result<Json<Vec<TrustExperience>>, StatusCode> {
     let experiences = execute_command(&state, |response| NodeCommand::GetExperiences { 
@@ -130,49 +880,191 @@ async fn get_experiences(
 pub struct TrustQueryParams {
     pub max_depth: Option<u8>,
     pub forget_rate: Option<f64>,
+    pub decay: Option<DecayFunction>,
+    pub aggregator: Option<Aggregator>,
+    pub budget_ms: Option<u64>,
+    /// Skip every cache and ask peers afresh
+    #[serde(default)]
+    pub refresh: bool,
+    /// Don't ask peers, answer from what's stored
+    #[serde(default)]
+    pub cache_only: bool,
+    /// Include where the time went, on this node and every peer asked
+    #[serde(default)]
+    pub timing: bool,
+}
+
+#[derive(Serialize)]
+pub struct TrustScoreResponse {
+    #[serde(flatten)]
+    pub score: TrustScore,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub insufficient_data: bool,
+    pub hops: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<ScoreDepth>,
+    pub freshness: Freshness,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timing: Option<QueryTiming>,
+}
+
+/// The caller's correlation id from the X-Correlation-Id header, or a new one
+fn correlation_id(headers: &HeaderMap) -> String {
+    headers
+        .get(CORRELATION_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_correlation_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
 }
 
 async fn query_trust(
-    State(state): State<ApiState>,
+    state: ApiState,
+    headers: HeaderMap,
     Path((id_domain, agent_id)): Path<(String, String)>,
     Query(params): Query<TrustQueryParams>,
-) -> Result<Json<TrustScore>, StatusCode> {
+) -> Result<Response, Response> {
+    let correlation_id = correlation_id(&headers);
+    let now = state.clock.now();
     let query = TrustQuery {
         agents: vec![crate::types::AgentIdentifier::new(id_domain.clone(), agent_id.clone())],
-        max_depth: params.max_depth.unwrap_or(3),
-        point_in_time: Some(Utc::now()),
-        forget_rate: Some(params.forget_rate.unwrap_or(0.0)),
+        max_depth: params.max_depth,
+        point_in_time: Some(now),
+        forget_rate: params.forget_rate,
+        decay: params.decay,
+        aggregator: params.aggregator,
+        budget_ms: params.budget_ms,
+        continuation: None,
+        traceparent: None,
+        correlation_id: Some(correlation_id.clone()),
+        timing: params.timing,
+        refresh: params.refresh,
+        cache_only: params.cache_only,
+        // The budget holds all the way down the chain, not just for the first hop
+        expires_at: params.budget_ms.map(|ms| now + chrono::Duration::milliseconds(ms as i64)),
     };
 
-    let response = execute_command(&state, |response| NodeCommand::QueryTrust { 
+    let response = execute_query(&state, |response| NodeCommand::QueryTrust { 
         query, 
         response 
     }).await?;
     
     tracing::debug!("API: Received response with {} scores for single trust query", response.scores.len());
-    let trust_score = response
+    let timing = response.timing;
+    let mut trust_score = response
         .scores
         .into_iter()
         .find(|agent_score| agent_score.id_domain == id_domain && agent_score.agent_id == agent_id)
-        .map(|agent_score| agent_score.score)
-        .unwrap_or_else(|| TrustScore::default()); // Return default score (PV-ROI=1, volume=0) instead of 404
+        .map(|agent_score| TrustScoreResponse {
+            score: agent_score.score,
+            insufficient_data: agent_score.insufficient_data,
+            hops: agent_score.hops,
+            depth: agent_score.depth,
+            freshness: agent_score.freshness,
+            timing: None,
+        })
+        .unwrap_or_else(|| TrustScoreResponse {
+            score: TrustScore::default(), // Return default score (PV-ROI=1, volume=0) instead of 404
+            insufficient_data: false,
+            hops: 0,
+            depth: None,
+            freshness: Freshness::default(),
+            timing: None,
+        });
+    trust_score.timing = timing;
     
-    Ok(Json(trust_score))
+    Ok(([(CORRELATION_ID, correlation_id)], Json(trust_score)).into_response())
 }
 
 async fn query_trust_batch(
-    State(state): State<ApiState>,
-    Json(query): Json<TrustQuery>,
-) -> Result<Json<TrustResponse>, StatusCode> {
-    let response = execute_command(&state, |response| NodeCommand::QueryTrust { 
+    state: ApiState,
+    headers: HeaderMap,
+    Json(mut query): Json<TrustQuery>,
+) -> Result<Response, Response> {
+    // The header wins over an id in the body, as for the single query
+    let correlation_id = match query.correlation_id.take().filter(|id| is_valid_correlation_id(id)) {
+        Some(id) if !headers.contains_key(CORRELATION_ID) => id,
+        _ => correlation_id(&headers),
+    };
+    query.correlation_id = Some(correlation_id.clone());
+    let response = execute_query(&state, |response| NodeCommand::QueryTrust { 
         query, 
         response 
     }).await?;
 
-    Ok(Json(response))
+    Ok(([(CORRELATION_ID, correlation_id)], Json(response)).into_response())
+}
+
+/// An experience to try out, described like in POST /experiences
+#[derive(Deserialize)]
+pub struct HypotheticalExperience {
+    pub investment: f64,
+    pub return_value: f64,
+    pub timeframe_days: f64,
+    pub discounting: Option<Discounting>,
+    pub discount_rate: Option<f64>,
+}
+
+#[derive(Deserialize)]
+pub struct SimulateTrustRequest {
+    pub id_domain: String,
+    pub agent_id: String,
+    #[serde(default)]
+    pub experiences: Vec<HypotheticalExperience>,
+    /// Recommender qualities to assume, by peer_id
+    #[serde(default)]
+    pub peer_qualities: HashMap<String, f64>,
+    pub forget_rate: Option<f64>,
+    pub decay: Option<DecayFunction>,
+    pub aggregator: Option<Aggregator>,
+}
+
+/// Score an agent with hypothetical experiences and peer qualities, without persisting anything
+async fn simulate_trust(
+    state: ApiState,
+    Json(req): Json<SimulateTrustRequest>,
+) -> Result<Json<SimulationResult>, StatusCode> {
+    let hypothetical: Vec<AddExperienceRequest> = req.experiences
+        .into_iter()
+        .map(|exp| AddExperienceRequest {
+            id_domain: req.id_domain.clone(),
+            agent_id: req.agent_id.clone(),
+            investment: exp.investment,
+            return_value: exp.return_value,
+            timeframe_days: exp.timeframe_days,
+            discounting: exp.discounting,
+            discount_rate: exp.discount_rate,
+            notes: None,
+            data: None,
+        })
+        .collect();
+    let discounting = resolve_discounting(&state, &hypothetical).await?;
+    let now = state.clock.now();
+    let experiences = hypothetical
+        .into_iter()
+        .zip(&discounting)
+        .map(|(exp, discounting)| exp.into_experience(discounting, now))
+        .collect();
+    let simulation = TrustSimulation {
+        id_domain: req.id_domain,
+        agent_id: req.agent_id,
+        experiences,
+        peer_qualities: req.peer_qualities,
+        point_in_time: Some(now),
+        forget_rate: req.forget_rate,
+        decay: req.decay,
+        aggregator: req.aggregator,
+    };
+
+    let result = execute_command(&state, |response| NodeCommand::SimulateTrust {
+        simulation,
+        response,
+    }).await?;
+
+    Ok(Json(result))
 }
 
-async fn get_peers(State(state): State<ApiState>) -> Result<Json<Vec<Peer>>, StatusCode> {
+async fn get_peers(state: ApiState) -> Result<Json<Vec<PeerStatus>>, StatusCode> {
     let peers = execute_command(&state, |response| NodeCommand::GetPeers { 
         response 
     }).await?;
@@ -182,56 +1074,240 @@ async fn get_peers(State(state): State<ApiState>) -> Result<Json<Vec<Peer>>, Sta
 
 #[derive(Deserialize)]
 pub struct AddPeerRequest {
+    /// The bare PeerId; a full multiaddr ending in /p2p/<id> is still accepted and split
     pub peer_id: String,
+    #[serde(default)]
+    pub addresses: Vec<PeerAddress>,
+    /// Unique short name to use in paths instead of the PeerId
+    pub handle: Option<String>,
     pub name: String,
     pub recommender_quality: Option<f64>,
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub contact: PeerContact,
 }
 
-async fn add_peer(
-    State(state): State<ApiState>,
-    Json(req): Json<AddPeerRequest>,
-) -> Result<Json<Peer>, StatusCode> {
-    let peer = Peer {
-        peer_id: req.peer_id,
-        name: req.name,
-        recommender_quality: req.recommender_quality.unwrap_or(0.5),
-        added_at: Utc::now(),
-    };
-
-    match execute_command(&state, |response| NodeCommand::AddPeer {
-        peer: peer.clone(),
-        response,
-    }).await {
-        Ok(_) => Ok(Json(peer)),
-        Err(_) => {
-            // Return a more specific error for duplicates
-            // This will be improved when we add proper error types
-            Err(StatusCode::CONFLICT)
+impl Validate for AddPeerRequest {
+    fn validate(&self, fields: &mut Fields) {
+        fields.non_empty("peer_id", &self.peer_id);
+        if let Some(quality) = self.recommender_quality {
+            fields.quality("recommender_quality", quality);
         }
+        fields.handle("handle", self.handle.as_deref());
     }
 }
 
+impl AddPeerRequest {
+    fn into_peer(self, now: DateTime<Utc>) -> Peer {
+        Peer {
+            peer_id: self.peer_id,
+            addresses: self.addresses,
+            handle: self.handle.filter(|h| !h.is_empty()),
+            name: self.name,
+            recommender_quality: self.recommender_quality.unwrap_or(0.5),
+            added_at: now,
+            notes: self.notes,
+            tags: self.tags,
+            contact: self.contact,
+            last_interaction_at: None,
+        }
+        .normalized()
+    }
+}
+
+async fn add_peer(
+    state: ApiState,
+    Json(req): Json<AddPeerRequest>,
+) -> Result<Json<Peer>, Response> {
+    validate(&req).map_err(IntoResponse::into_response)?;
+    let peer = req.into_peer(state.clock.now());
+
+    execute_command(&state, |response| NodeCommand::AddPeer {
+        peer: peer.clone(),
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(peer))
+}
+
+/// Add many peers at once; known peers and repeats within the list are handled per `strategy`,
+/// with one result per listed peer
+async fn add_peers(
+    state: ApiState,
+    Query(params): Query<ImportParams>,
+    Json(reqs): Json<Vec<AddPeerRequest>>,
+) -> Result<Json<ImportSummary>, Response> {
+    validate(reqs.as_slice()).map_err(IntoResponse::into_response)?;
+    let now = state.clock.now();
+    let peers = reqs.into_iter().map(|req| req.into_peer(now)).collect();
+    let summary = execute_command(&state, |response| NodeCommand::AddPeers {
+        peers,
+        strategy: params.strategy.unwrap_or_default(),
+        dry_run: params.dry_run.unwrap_or(false),
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(summary))
+}
+
+#[derive(Deserialize)]
+pub struct CreateInviteRequest {
+    /// How we introduce ourselves to the friend
+    pub name: Option<String>,
+    /// Addresses to hand out instead of the ones the node listens on
+    pub addresses: Option<Vec<String>>,
+    /// How long the invite can be redeemed, one day by default and a week at most
+    pub ttl_secs: Option<u64>,
+}
+
+impl Validate for CreateInviteRequest {
+    fn validate(&self, fields: &mut Fields) {
+        if let Some(name) = &self.name {
+            fields.non_empty("name", name);
+        }
+        if let Some(addresses) = &self.addresses {
+            fields.check(addresses.len() <= MAX_PEER_ADDRESSES, "addresses", format!("must be at most {}", MAX_PEER_ADDRESSES));
+            for (index, address) in addresses.iter().enumerate() {
+                fields.check(address.parse::<libp2p::Multiaddr>().is_ok(), &format!("addresses[{}]", index), "must be a multiaddr");
+            }
+        }
+        if let Some(ttl_secs) = self.ttl_secs {
+            fields.check(ttl_secs > 0, "ttl_secs", "must be at least 1");
+        }
+    }
+}
+
+/// Issue a one-shot signed invite token carrying our PeerId and addresses
+async fn create_invite(
+    state: ApiState,
+    Json(req): Json<CreateInviteRequest>,
+) -> Result<Json<IssuedInvite>, Response> {
+    validate(&req).map_err(IntoResponse::into_response)?;
+    // The node caps it anyway, this just keeps huge values from overflowing
+    let ttl = req.ttl_secs.map(|secs| chrono::Duration::seconds(secs.min(MAX_INVITE_TTL.num_seconds() as u64) as i64));
+    let invite = execute_command(&state, |response| NodeCommand::CreateInvite {
+        name: req.name,
+        addresses: req.addresses,
+        ttl,
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(invite))
+}
+
+#[derive(Deserialize)]
+pub struct RedeemInviteRequest {
+    pub token: String,
+    /// Name to list the inviter under, instead of the one in the invite
+    pub name: Option<String>,
+    pub handle: Option<String>,
+    pub recommender_quality: Option<f64>,
+    /// How we introduce ourselves to the inviter
+    pub introduce_as: Option<String>,
+}
+
+impl Validate for RedeemInviteRequest {
+    fn validate(&self, fields: &mut Fields) {
+        fields.non_empty("token", &self.token);
+        if let Some(quality) = self.recommender_quality {
+            fields.quality("recommender_quality", quality);
+        }
+        fields.handle("handle", self.handle.as_deref());
+    }
+}
+
+/// Add the friend behind an invite token; their node is asked to add us back
+async fn redeem_invite(
+    state: ApiState,
+    Json(req): Json<RedeemInviteRequest>,
+) -> Result<Json<Peer>, Response> {
+    validate(&req).map_err(IntoResponse::into_response)?;
+    let handle = req.handle.filter(|h| !h.is_empty());
+    let result = send_command(&state, |response| NodeCommand::RedeemInvite {
+        token: req.token,
+        name: req.name,
+        handle,
+        recommender_quality: req.recommender_quality.unwrap_or(0.5),
+        introduce_as: req.introduce_as,
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    result.map(Json).map_err(|e| {
+        warn!("Redeeming invite failed: {}", e);
+        if e.downcast_ref::<InvalidInvite>().is_some() {
+            StatusCode::BAD_REQUEST.into_response()
+        } else {
+            error_status(&e).into_response()
+        }
+    })
+}
+
+/// Move the node to a new key; connected peers are told and keep us listed as before
+async fn rotate_identity(state: ApiState) -> Result<Json<IdentityRotation>, StatusCode> {
+    let rotation = execute_command(&state, |response| NodeCommand::RotateIdentity { response }).await?;
+    Ok(Json(rotation))
+}
+
+async fn update_peer(
+    state: ApiState,
+    Path(peer_id): Path<String>,
+    Json(update): Json<PeerUpdate>,
+) -> Result<Json<Peer>, Response> {
+    validate(&update).map_err(IntoResponse::into_response)?;
+    let peer = execute_command(&state, |response| NodeCommand::UpdatePeer {
+        peer_id,
+        update,
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(peer))
+}
+
 #[derive(Deserialize)]
 pub struct UpdateQualityRequest {
     pub quality: f64,
+    /// Why the quality changed, kept in the peer's quality history
+    pub reason: Option<String>,
+}
+
+impl Validate for UpdateQualityRequest {
+    fn validate(&self, fields: &mut Fields) {
+        fields.quality("quality", self.quality);
+    }
 }
 
 async fn update_peer_quality(
-    State(state): State<ApiState>,
+    state: ApiState,
     Path(peer_id): Path<String>,
     Json(req): Json<UpdateQualityRequest>,
-) -> Result<StatusCode, StatusCode> {
+) -> Result<StatusCode, Response> {
+    validate(&req).map_err(IntoResponse::into_response)?;
     execute_command(&state, |response| NodeCommand::UpdatePeerQuality {
         peer_id,
         quality: req.quality,
+        reason: req.reason,
         response,
-    }).await?;
+    }).await.map_err(IntoResponse::into_response)?;
 
     Ok(StatusCode::OK)
 }
 
+async fn get_quality_history(
+    state: ApiState,
+    Path(peer_id): Path<String>,
+) -> Result<Json<Vec<QualityRevision>>, StatusCode> {
+    let history = execute_command(&state, |response| NodeCommand::GetQualityHistory {
+        peer_id,
+        response,
+    }).await?;
+
+    Ok(Json(history))
+}
+
 async fn delete_peer(
-    State(state): State<ApiState>,
+    state: ApiState,
     Path(peer_id): Path<String>,
 ) -> Result<StatusCode, StatusCode> {
     execute_command(&state, |response| NodeCommand::RemovePeer {
@@ -242,7 +1318,129 @@ async fn delete_peer(
     Ok(StatusCode::NO_CONTENT)
 }
 
-async fn get_connected_peers(State(state): State<ApiState>) -> Result<Json<Vec<String>>, StatusCode> {
+#[derive(Deserialize)]
+pub struct ProposePeeringRequest {
+    pub peer_id: String,
+    /// Where to reach the peer, unless it can be found through the DHT
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    /// Defaults to the peer id
+    pub name: Option<String>,
+    pub recommender_quality: Option<f64>,
+    /// Circles to list the peer under, which it's offered for listing us too
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// How we introduce ourselves
+    pub introduce_as: Option<String>,
+    /// Shown to the peer's user next to the request
+    pub note: Option<String>,
+}
+
+impl Validate for ProposePeeringRequest {
+    fn validate(&self, fields: &mut Fields) {
+        fields.check(self.peer_id.parse::<libp2p::PeerId>().is_ok(), "peer_id", "must be a libp2p peer id");
+        if let Some(quality) = self.recommender_quality {
+            fields.quality("recommender_quality", quality);
+        }
+    }
+}
+
+/// Ask a node to add each other as peers; it's added to our peer list once its user accepts
+async fn propose_peering(
+    state: ApiState,
+    Json(req): Json<ProposePeeringRequest>,
+) -> Result<StatusCode, Response> {
+    validate(&req).map_err(IntoResponse::into_response)?;
+    execute_command(&state, |response| NodeCommand::ProposePeering {
+        peer_id: req.peer_id,
+        addresses: req.addresses,
+        name: req.name,
+        recommender_quality: req.recommender_quality.unwrap_or(0.5),
+        tags: req.tags,
+        introduce_as: req.introduce_as,
+        note: req.note,
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn get_peer_requests(state: ApiState) -> Result<Json<Vec<PeerRequest>>, StatusCode> {
+    let requests = execute_command(&state, |response| NodeCommand::GetPeerRequests { response }).await?;
+    Ok(Json(requests))
+}
+
+#[derive(Deserialize, Default)]
+pub struct AcceptPeerRequest {
+    /// Defaults to the peer id
+    pub name: Option<String>,
+    /// Defaults to the quality invited peers start with
+    pub quality: Option<f64>,
+    /// Circles to file the peer under; the ones a proposal suggests aren't taken over
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Validate for AcceptPeerRequest {
+    fn validate(&self, fields: &mut Fields) {
+        if let Some(quality) = self.quality {
+            fields.quality("quality", quality);
+        }
+    }
+}
+
+async fn accept_peer_request(
+    state: ApiState,
+    Path(peer_id): Path<String>,
+    body: Option<Json<AcceptPeerRequest>>,
+) -> Result<Json<Peer>, Response> {
+    // The body is optional, accepting with the defaults needs none
+    let Json(req) = body.unwrap_or_default();
+    validate(&req).map_err(IntoResponse::into_response)?;
+    let peer = execute_command(&state, |response| NodeCommand::AcceptPeerRequest {
+        peer_id,
+        name: req.name,
+        quality: req.quality,
+        tags: req.tags,
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(peer))
+}
+
+async fn ignore_peer_request(state: ApiState, Path(peer_id): Path<String>) -> Result<StatusCode, StatusCode> {
+    set_peer_request_status(&state, peer_id, PeerRequestStatus::Ignored).await
+}
+
+/// Blocking works for any peer id, so a peer can be blocked before it ever queries
+async fn block_peer_request(state: ApiState, Path(peer_id): Path<String>) -> Result<StatusCode, StatusCode> {
+    set_peer_request_status(&state, peer_id, PeerRequestStatus::Blocked).await
+}
+
+async fn set_peer_request_status(
+    state: &ApiState,
+    peer_id: String,
+    status: PeerRequestStatus,
+) -> Result<StatusCode, StatusCode> {
+    execute_command(state, |response| NodeCommand::SetPeerRequestStatus {
+        peer_id,
+        status,
+        response,
+    }).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn remove_peer_request(state: ApiState, Path(peer_id): Path<String>) -> Result<StatusCode, StatusCode> {
+    execute_command(&state, |response| NodeCommand::RemovePeerRequest {
+        peer_id,
+        response,
+    }).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_connected_peers(state: ApiState) -> Result<Json<Vec<String>>, StatusCode> {
     let connected_peers = execute_command(&state, |response| NodeCommand::GetConnectedPeers { 
         response 
     }).await?;
@@ -250,7 +1448,17 @@ async fn get_connected_peers(State(state): State<ApiState>) -> Result<Json<Vec<S
     Ok(Json(connected_peers))
 }
 
-async fn get_self_peer_id(State(state): State<ApiState>) -> Result<Json<String>, StatusCode> {
+async fn get_quarantined_peers(state: ApiState) -> Result<Json<Vec<QuarantinedPeer>>, StatusCode> {
+    let peers = execute_command(&state, |response| NodeCommand::GetQuarantinedPeers { response }).await?;
+    Ok(Json(peers))
+}
+
+async fn release_quarantined_peer(state: ApiState, Path(peer_id): Path<String>) -> Result<StatusCode, StatusCode> {
+    execute_command(&state, |response| NodeCommand::ReleaseQuarantinedPeer { peer_id, response }).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_self_peer_id(state: ApiState) -> Result<Json<String>, StatusCode> {
     let self_peer_id = execute_command(&state, |response| NodeCommand::GetSelfPeerId { 
         response 
     }).await?;
@@ -258,7 +1466,7 @@ async fn get_self_peer_id(State(state): State<ApiState>) -> Result<Json<String>,
     Ok(Json(self_peer_id))
 }
 
-async fn trigger_peer_discovery(State(state): State<ApiState>) -> Result<StatusCode, StatusCode> {
+async fn trigger_peer_discovery(state: ApiState) -> Result<StatusCode, StatusCode> {
     execute_command(&state, |response| NodeCommand::TriggerPeerDiscovery { 
         response 
     }).await?;
@@ -267,7 +1475,7 @@ async fn trigger_peer_discovery(State(state): State<ApiState>) -> Result<StatusC
 }
 
 async fn delete_experience(
-    State(state): State<ApiState>,
+    state: ApiState,
     Path(experience_id): Path<String>,
 ) -> Result<StatusCode, StatusCode> {
     execute_command(&state, |response| NodeCommand::RemoveExperience {
@@ -278,39 +1486,893 @@ async fn delete_experience(
     Ok(StatusCode::NO_CONTENT)
 }
 
+async fn update_experience(
+    state: ApiState,
+    Path(experience_id): Path<String>,
+    Json(update): Json<ExperienceUpdate>,
+) -> Result<Json<TrustExperience>, Response> {
+    validate(&update).map_err(IntoResponse::into_response)?;
+    let experience = execute_command(&state, |response| NodeCommand::UpdateExperience {
+        experience_id,
+        update,
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(experience))
+}
+
+async fn get_experience_history(
+    state: ApiState,
+    Path(experience_id): Path<String>,
+) -> Result<Json<Vec<ExperienceRevision>>, StatusCode> {
+    let history = execute_command(&state, |response| NodeCommand::GetExperienceHistory {
+        experience_id,
+        response,
+    }).await?;
+
+    Ok(Json(history))
+}
+
+async fn revert_experience(
+    state: ApiState,
+    Path((experience_id, revision)): Path<(String, i64)>,
+) -> Result<Json<TrustExperience>, StatusCode> {
+    let experience = execute_command(&state, |response| NodeCommand::RevertExperience {
+        experience_id,
+        revision,
+        response,
+    }).await?;
+
+    Ok(Json(experience))
+}
+
 #[derive(Deserialize)]
 pub struct ImportRequest {
     pub data: TrustDataExport,
+    /// Legacy flag, equivalent to `strategy: "overwrite"`
     pub overwrite: Option<bool>,
+    pub strategy: Option<ImportStrategy>,
+    pub dry_run: Option<bool>,
 }
 
-async fn export_trust_data(State(state): State<ApiState>) -> Result<Json<TrustDataExport>, StatusCode> {
-    let export_data = execute_command(&state, |response| NodeCommand::ExportTrustData { 
-        response 
+impl Validate for ImportRequest {
+    fn validate(&self, fields: &mut Fields) {
+        fields.nested("data.", &self.data);
+    }
+}
+
+/// Options of a streamed import, in the query string since the body is the data itself
+#[derive(Deserialize)]
+pub struct ImportParams {
+    pub strategy: Option<ImportStrategy>,
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// One TrustDataExport document
+    #[default]
+    Json,
+    /// One ImportRecord per line, peers first, as accepted by a streamed import
+    Ndjson,
+    /// The NDJSON lines compressed with zstd and closed by a manifest with counts and checksums
+    Archive,
+}
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    #[serde(default)]
+    pub format: ExportFormat,
+    /// Export a snapshot as of this time, with merged scores; only as JSON
+    pub point_in_time: Option<DateTime<Utc>>,
+    /// Leave out notes and names and bucket volumes, for sharing
+    #[serde(default)]
+    pub anonymize: bool,
+}
+
+async fn export_trust_data(state: ApiState, Query(params): Query<ExportParams>) -> Result<Response, StatusCode> {
+    if params.point_in_time.is_some() && params.format != ExportFormat::Json {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let export = execute_command(&state, |response| NodeCommand::ExportTrustData {
+        point_in_time: params.point_in_time,
+        response,
     }).await?;
+    let export = if params.anonymize { anonymize::anonymize(export) } else { export };
+    let now = state.clock.now();
 
-    Ok(Json(export_data))
+    match params.format {
+        ExportFormat::Json => {
+            Ok(([(header::CONTENT_TYPE, "application/json")], Body::from_stream(export_json_stream(export, now)?)).into_response())
+        }
+        ExportFormat::Ndjson => Ok(([(header::CONTENT_TYPE, NDJSON)], Body::from_stream(export_ndjson_stream(export))).into_response()),
+        ExportFormat::Archive => {
+            let disposition = format!("attachment; filename=\"repeer-export-{}.ndjson.zst\"", now.format("%Y%m%d-%H%M%S"));
+            Ok((
+                [(header::CONTENT_TYPE, ARCHIVE_CONTENT_TYPE.to_string()), (header::CONTENT_DISPOSITION, disposition)],
+                Body::from_stream(export_archive_stream(export, now)?),
+            )
+                .into_response())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DiffRequest {
+    pub before: TrustDataExport,
+    /// Compared with the node's current data when left out
+    pub after: Option<TrustDataExport>,
+}
+
+impl Validate for DiffRequest {
+    fn validate(&self, fields: &mut Fields) {
+        fields.nested("before.", &self.before);
+        if let Some(after) = &self.after {
+            fields.nested("after.", after);
+        }
+    }
+}
+
+async fn diff_exports(state: ApiState, Json(req): Json<DiffRequest>) -> Result<Json<ExportDiff>, Response> {
+    validate(&req).map_err(IntoResponse::into_response)?;
+    let after = match req.after {
+        Some(after) => after,
+        None => {
+            let export = execute_command(&state, |response| NodeCommand::ExportTrustData {
+                point_in_time: None,
+                response,
+            }).await.map_err(IntoResponse::into_response)?;
+            let experiences: Vec<TrustExperience> =
+                export.experiences.try_collect().await.map_err(|e| error_status(&e.into()).into_response())?;
+            TrustDataExport::new(experiences, export.peers)
+        }
+    };
+
+    Ok(Json(crate::diff::diff(&req.before, &after)))
+}
+
+/// Compress an export into an archive as its records come out of storage
+fn export_archive_stream(
+    export: ExportStream,
+    now: DateTime<Utc>,
+) -> Result<impl futures::Stream<Item = Result<Vec<u8>, StorageError>>, StatusCode> {
+    let writer = ArchiveWriter::new().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let records = stream::iter(export.peers.into_iter().map(|peer| Ok(ImportRecord::Peer(peer))))
+        .chain(export.experiences.map(|experience| experience.map(ImportRecord::Experience)))
+        .map(Some)
+        // A last None tells the writer to close the archive
+        .chain(stream::once(async { None }));
+
+    let io_error = |e: std::io::Error| StorageError::Io(e.to_string());
+    let chunks = records.scan(Some(writer), move |writer, record| {
+        let chunk = match (record, writer.as_mut()) {
+            (Some(record), Some(open)) => record.and_then(|record| open.write(&record).map_err(io_error)),
+            (None, Some(_)) => match writer.take() {
+                Some(open) => open.finish(now).map_err(io_error),
+                None => Ok(Vec::new()),
+            },
+            (_, None) => return future::ready(None),
+        };
+        future::ready(Some(chunk))
+    });
+    Ok(chunks.try_filter(|chunk| future::ready(!chunk.is_empty())))
+}
+
+/// Serialize an export as NDJSON, one ImportRecord per line
+fn export_ndjson_stream(export: ExportStream) -> impl futures::Stream<Item = Result<String, StorageError>> {
+    let line = |record: ImportRecord| {
+        serde_json::to_string(&record)
+            .map(|json| json + "\n")
+            .map_err(|e| StorageError::Corruption(e.to_string()))
+    };
+    let peers = stream::iter(export.peers.into_iter().map(move |peer| line(ImportRecord::Peer(peer))));
+    let experiences = export.experiences.map(move |experience| line(ImportRecord::Experience(experience?)));
+    peers.chain(experiences)
+}
+
+/// Serialize an export as the same JSON document as TrustDataExport, one experience at a time
+fn export_json_stream(
+    export: ExportStream,
+    now: DateTime<Utc>,
+) -> Result<impl futures::Stream<Item = Result<String, StorageError>>, StatusCode> {
+    let mut head = format!(
+        r#"{{"version":{},"exported_at":{},"peers":{},"#,
+        serde_json::to_string(EXPORT_FORMAT_VERSION).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        serde_json::to_string(&now).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        serde_json::to_string(&export.peers).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+    );
+    if let Some(point_in_time) = export.point_in_time {
+        head += &format!(
+            r#""point_in_time":{},"scores":{},"#,
+            serde_json::to_string(&point_in_time).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+            serde_json::to_string(&export.scores).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
+        );
+    }
+    head += r#""experiences":["#;
+
+    let mut first = true;
+    let experiences = export.experiences.map(move |experience| {
+        let json = serde_json::to_string(&experience?)
+            .map_err(|e| StorageError::Corruption(e.to_string()))?;
+        let separator = if first { "" } else { "," };
+        first = false;
+        Ok::<_, StorageError>(format!("{}{}", separator, json))
+    });
+
+    Ok(stream::once(async move { Ok::<_, StorageError>(head) })
+        .chain(experiences)
+        .chain(stream::once(async { Ok::<_, StorageError>("]}".to_string()) })))
 }
 
+/// Import a JSON export document, or stream in NDJSON records from the body or from the `file` part of
+/// a multipart upload; streamed records are written in batches as they arrive
 async fn import_trust_data(
-    State(state): State<ApiState>,
-    Json(req): Json<ImportRequest>,
-) -> Result<StatusCode, StatusCode> {
-    execute_command(&state, |response| NodeCommand::ImportTrustData {
-        data: req.data,
-        overwrite: req.overwrite.unwrap_or(false),
+    state: ApiState,
+    Query(params): Query<ImportParams>,
+    request: Request,
+) -> Result<Json<ImportSummary>, Response> {
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let strategy = params.strategy.unwrap_or_default();
+    let dry_run = params.dry_run.unwrap_or(false);
+
+    if content_type.starts_with("multipart/form-data") {
+        let mut multipart = Multipart::from_request(request, &()).await.map_err(IntoResponse::into_response)?;
+        while let Some(field) = multipart.next_field().await.map_err(IntoResponse::into_response)? {
+            if field.name() != Some(IMPORT_FILE_FIELD) {
+                continue;
+            }
+            let is_archive = field.content_type() == Some(ARCHIVE_CONTENT_TYPE)
+                || field.file_name().is_some_and(|name| name.ends_with(".zst"));
+            return if is_archive {
+                import_archive(&state, field, strategy, dry_run).await.map(Json)
+            } else {
+                import_ndjson(&state, field, strategy, dry_run).await.map(Json)
+            };
+        }
+        Err((StatusCode::BAD_REQUEST, format!("no `{}` part in the upload", IMPORT_FILE_FIELD)).into_response())
+    } else if content_type.starts_with(ARCHIVE_CONTENT_TYPE) {
+        import_archive(&state, request.into_body().into_data_stream(), strategy, dry_run).await.map(Json)
+    } else if content_type.starts_with(NDJSON) {
+        import_ndjson(&state, request.into_body().into_data_stream(), strategy, dry_run).await.map(Json)
+    } else {
+        // The route lifts the default limit for the streamed forms, this one is buffered
+        let (parts, body) = request.into_parts();
+        let body = axum::body::to_bytes(body, MAX_IMPORT_DOCUMENT_BYTES)
+            .await
+            .map_err(|e| (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()).into_response())?;
+        let request = Request::from_parts(parts, Body::from(body));
+        let Json(req) = Json::<ImportRequest>::from_request(request, &()).await.map_err(IntoResponse::into_response)?;
+        validate(&req).map_err(IntoResponse::into_response)?;
+        import_document(&state, req).await.map(Json).map_err(IntoResponse::into_response)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MappedImportRequest {
+    pub mapping: ImportMapping,
+    /// The JSON or CSV dump, as text
+    pub input: String,
+    pub strategy: Option<ImportStrategy>,
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct MappedImportSummary {
+    #[serde(flatten)]
+    pub summary: ImportSummary,
+    /// Records the mapping couldn't turn into experiences
+    pub rejected: Vec<RecordError>,
+}
+
+/// Import a dump of another reputation system through a mapping
+async fn import_mapped(state: ApiState, Json(req): Json<MappedImportRequest>) -> Result<Json<MappedImportSummary>, Response> {
+    let mapped = req
+        .mapping
+        .apply(req.input.as_bytes(), state.clock.now())
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+
+    let summary = execute_command(&state, |response| NodeCommand::ImportTrustData {
+        data: TrustDataExport::new(mapped.experiences, Vec::new()),
+        strategy: req.strategy.unwrap_or_default(),
+        dry_run: req.dry_run.unwrap_or(false),
         response,
-    }).await?;
+    })
+    .await
+    .map_err(IntoResponse::into_response)?;
 
-    Ok(StatusCode::OK)
+    Ok(Json(MappedImportSummary { summary, rejected: mapped.errors }))
 }
 
-async fn clear_peers(State(state): State<ApiState>) -> Result<StatusCode, StatusCode> {
+/// Parse NDJSON records as the chunks arrive and hand them to the node a batch at a time; batches
+/// before a malformed line stay imported
+async fn import_ndjson<B, E>(state: &ApiState, body: B, strategy: ImportStrategy, dry_run: bool) -> Result<ImportSummary, Response>
+where
+    B: futures::Stream<Item = Result<Bytes, E>>,
+    E: std::fmt::Display,
+{
+    let mut body = std::pin::pin!(body);
+    let mut import = NdjsonImport::default();
+    let mut summary = ImportSummary { dry_run, strategy, ..Default::default() };
+    let rejected = |e: ImportStreamError| match e {
+        ImportStreamError::Invalid(invalid) => invalid.into_response(),
+        e => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+        for batch in import.push(&chunk).map_err(rejected)? {
+            import_batch(state, batch, strategy, dry_run, &mut summary).await?;
+        }
+    }
+    let last = import.finish().map_err(rejected)?;
+    if !last.is_empty() {
+        import_batch(state, last, strategy, dry_run, &mut summary).await?;
+    }
+
+    info!("Streamed import: {} new / {} conflicting experiences, {} new / {} existing peers{}",
+          summary.new_experiences, summary.conflicting_experiences,
+          summary.new_peers, summary.existing_peers,
+          if dry_run { " (dry run)" } else { "" });
+    Ok(summary)
+}
+
+/// Spool an archive to a temporary file while checking it against its manifest, then import it from
+/// there, so a damaged archive is rejected before anything is written
+async fn import_archive<B, E>(state: &ApiState, body: B, strategy: ImportStrategy, dry_run: bool) -> Result<ImportSummary, Response>
+where
+    B: futures::Stream<Item = Result<Bytes, E>>,
+    E: std::fmt::Display,
+{
+    let spool = std::env::temp_dir().join(format!("repeer-import-{}.zst", Uuid::new_v4()));
+    let result = async {
+        let manifest = spool_archive(body, &spool).await?;
+        info!("Archive of {} peers and {} experiences from {} verified, importing",
+              manifest.peers, manifest.experiences, manifest.exported_at);
+        let file = tokio::fs::File::open(&spool).await.map_err(|e| archive_rejection(e.into()))?;
+        import_ndjson(state, decompressed_chunks(file), strategy, dry_run).await
+    }
+    .await;
+    if let Err(e) = tokio::fs::remove_file(&spool).await {
+        warn!("Failed to remove {}: {}", spool.display(), e);
+    }
+    result
+}
+
+async fn spool_archive<B, E>(body: B, spool: &std::path::Path) -> Result<ArchiveManifest, Response>
+where
+    B: futures::Stream<Item = Result<Bytes, E>>,
+    E: std::fmt::Display,
+{
+    let mut body = std::pin::pin!(body);
+    let mut file = tokio::fs::File::create(spool).await.map_err(|e| archive_rejection(e.into()))?;
+    let mut verifier = ArchiveVerifier::new().map_err(|e| archive_rejection(e.into()))?;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+        verifier.push(&chunk).map_err(archive_rejection)?;
+        file.write_all(&chunk).await.map_err(|e| archive_rejection(e.into()))?;
+    }
+    file.flush().await.map_err(|e| archive_rejection(e.into()))?;
+    verifier.finish().map_err(archive_rejection)
+}
+
+/// The decompressed content of an archive file, read a chunk at a time
+fn decompressed_chunks(file: tokio::fs::File) -> impl futures::Stream<Item = Result<Bytes, std::io::Error>> {
+    stream::try_unfold((file, ArchiveDecompressor::new().ok()), |(mut file, decompressor)| async move {
+        let Some(mut decompressor) = decompressor else {
+            return Ok(None);
+        };
+        let mut buffer = vec![0; ARCHIVE_READ_CHUNK];
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(Some((Bytes::from(decompressor.finish()?), (file, None))));
+        }
+        let chunk = decompressor.push(&buffer[..read])?;
+        Ok(Some((Bytes::from(chunk), (file, Some(decompressor)))))
+    })
+}
+
+fn archive_rejection(err: ArchiveError) -> Response {
+    match err {
+        ArchiveError::Corrupt(_) => (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()).into_response(),
+        ArchiveError::Io(_) => {
+            warn!("Archive import failed: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn import_batch(
+    state: &ApiState,
+    batch: ImportBatch,
+    strategy: ImportStrategy,
+    dry_run: bool,
+    summary: &mut ImportSummary,
+) -> Result<(), Response> {
+    let imported = execute_command(state, |response| NodeCommand::ImportBatch { batch, strategy, dry_run, response })
+        .await
+        .map_err(IntoResponse::into_response)?;
+    summary.absorb(&imported);
+    Ok(())
+}
+
+async fn import_document(state: &ApiState, req: ImportRequest) -> Result<ImportSummary, StatusCode> {
+    let strategy = req.strategy.unwrap_or(if req.overwrite.unwrap_or(false) {
+        ImportStrategy::Overwrite
+    } else {
+        ImportStrategy::SkipDuplicates
+    });
+
+    execute_command(state, |response| NodeCommand::ImportTrustData {
+        data: req.data,
+        strategy,
+        dry_run: req.dry_run.unwrap_or(false),
+        response,
+    }).await
+}
+
+async fn clear_peers(state: ApiState) -> Result<StatusCode, StatusCode> {
     execute_command(&state, |response| NodeCommand::ClearPeers { response }).await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
-async fn clear_experiences(State(state): State<ApiState>) -> Result<StatusCode, StatusCode> {
+async fn clear_experiences(state: ApiState) -> Result<StatusCode, StatusCode> {
     execute_command(&state, |response| NodeCommand::ClearExperiences { response }).await?;
     Ok(StatusCode::NO_CONTENT)
-}
\ No newline at end of file
+}
+
+async fn get_domain_defaults(state: ApiState) -> Result<Json<Vec<DomainDefaults>>, StatusCode> {
+    let domains = execute_command(&state, |response| NodeCommand::GetDomainDefaults { response }).await?;
+    Ok(Json(domains))
+}
+
+#[derive(Deserialize)]
+pub struct DomainDefaultsRequest {
+    pub forget_rate: Option<f64>,
+    pub decay: Option<DecayFunction>,
+    pub max_depth: Option<u8>,
+    pub aggregator: Option<Aggregator>,
+    pub agent_id_form: Option<AgentIdForm>,
+    pub discounting: Option<Discounting>,
+    pub currency: Option<String>,
+}
+
+async fn set_domain_defaults(
+    state: ApiState,
+    Path(id_domain): Path<String>,
+    Json(req): Json<DomainDefaultsRequest>,
+) -> Result<Json<DomainDefaults>, Response> {
+    let defaults = DomainDefaults {
+        id_domain,
+        forget_rate: req.forget_rate,
+        decay: req.decay,
+        max_depth: req.max_depth,
+        aggregator: req.aggregator,
+        agent_id_form: req.agent_id_form,
+        discounting: req.discounting,
+        currency: req.currency,
+    };
+    validate(&defaults).map_err(IntoResponse::into_response)?;
+
+    execute_command(&state, |response| NodeCommand::SetDomainDefaults {
+        defaults: defaults.clone(),
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(defaults))
+}
+
+async fn remove_domain_defaults(
+    state: ApiState,
+    Path(id_domain): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    execute_command(&state, |response| NodeCommand::RemoveDomainDefaults {
+        id_domain,
+        response,
+    }).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_inflation_indexes(state: ApiState) -> Result<Json<Vec<InflationIndex>>, StatusCode> {
+    let indexes = execute_command(&state, |response| NodeCommand::GetInflationIndexes { response }).await?;
+    Ok(Json(indexes))
+}
+
+#[derive(Deserialize)]
+pub struct InflationIndexRequest {
+    pub points: Vec<IndexPoint>,
+}
+
+async fn set_inflation_index(
+    state: ApiState,
+    Path(currency): Path<String>,
+    Json(req): Json<InflationIndexRequest>,
+) -> Result<Json<InflationIndex>, Response> {
+    let index = InflationIndex { currency, points: req.points };
+    validate(&index).map_err(IntoResponse::into_response)?;
+
+    execute_command(&state, |response| NodeCommand::SetInflationIndex {
+        index: index.clone(),
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(index))
+}
+
+async fn remove_inflation_index(
+    state: ApiState,
+    Path(currency): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    execute_command(&state, |response| NodeCommand::RemoveInflationIndex {
+        currency,
+        response,
+    }).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_sharing_precision(state: ApiState) -> Result<Json<Vec<SharingPrecision>>, StatusCode> {
+    let precision = execute_command(&state, |response| NodeCommand::GetSharingPrecision { response }).await?;
+    Ok(Json(precision))
+}
+
+#[derive(Deserialize)]
+pub struct SharingPrecisionRequest {
+    #[serde(default)]
+    pub roi_step: Option<f64>,
+    #[serde(default)]
+    pub bucket_volumes: bool,
+}
+
+async fn set_sharing_precision(
+    state: ApiState,
+    Path(subject): Path<String>,
+    Json(req): Json<SharingPrecisionRequest>,
+) -> Result<Json<SharingPrecision>, Response> {
+    let precision = SharingPrecision { subject, roi_step: req.roi_step, bucket_volumes: req.bucket_volumes };
+    validate(&precision).map_err(IntoResponse::into_response)?;
+
+    execute_command(&state, |response| NodeCommand::SetSharingPrecision {
+        precision: precision.clone(),
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(precision))
+}
+
+async fn remove_sharing_precision(
+    state: ApiState,
+    Path(subject): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    execute_command(&state, |response| NodeCommand::RemoveSharingPrecision {
+        subject,
+        response,
+    }).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_agent_claims(state: ApiState) -> Result<Json<Vec<AgentClaim>>, StatusCode> {
+    let claims = execute_command(&state, |response| NodeCommand::GetAgentClaims { response }).await?;
+    Ok(Json(claims))
+}
+
+#[derive(Deserialize)]
+pub struct ClaimAgentRequest {
+    pub id_domain: String,
+    pub agent_id: String,
+}
+
+impl Validate for ClaimAgentRequest {
+    fn validate(&self, fields: &mut Fields) {
+        fields.non_empty("id_domain", &self.id_domain);
+        fields.agent_id("agent_id", &self.id_domain, &self.agent_id);
+    }
+}
+
+/// Claim an agent id as our own; the answer holds the challenge to publish at a proof URL
+async fn claim_agent(
+    state: ApiState,
+    Json(req): Json<ClaimAgentRequest>,
+) -> Result<(StatusCode, Json<AgentClaim>), Response> {
+    validate(&req).map_err(IntoResponse::into_response)?;
+    let claim = execute_command(&state, |response| NodeCommand::ClaimAgent {
+        id_domain: req.id_domain,
+        agent_id: req.agent_id,
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok((StatusCode::CREATED, Json(claim)))
+}
+
+#[derive(Deserialize)]
+pub struct VerifyClaimRequest {
+    /// A page only the agent's owner can edit, showing the claim's challenge
+    pub proof_url: String,
+}
+
+impl Validate for VerifyClaimRequest {
+    fn validate(&self, fields: &mut Fields) {
+        fields.http_url("proof_url", &self.proof_url);
+    }
+}
+
+async fn verify_agent_claim(
+    state: ApiState,
+    Path((id_domain, agent_id)): Path<(String, String)>,
+    Json(req): Json<VerifyClaimRequest>,
+) -> Result<Json<AgentClaim>, Response> {
+    validate(&req).map_err(IntoResponse::into_response)?;
+    let result = send_command(&state, |response| NodeCommand::VerifyAgentClaim {
+        id_domain,
+        agent_id,
+        proof_url: req.proof_url,
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    result.map(Json).map_err(|e| {
+        warn!("Verifying claim failed: {}", e);
+        if e.downcast_ref::<InvalidProof>().is_some() {
+            StatusCode::UNPROCESSABLE_ENTITY.into_response()
+        } else {
+            error_status(&e).into_response()
+        }
+    })
+}
+
+async fn remove_agent_claim(
+    state: ApiState,
+    Path((id_domain, agent_id)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    execute_command(&state, |response| NodeCommand::RemoveAgentClaim {
+        id_domain,
+        agent_id,
+        response,
+    }).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// What each connected peer thinks of our verified agents, from its own experiences
+async fn get_own_reputation(state: ApiState) -> Result<Json<Vec<PeerOpinion>>, StatusCode> {
+    let opinions = execute_command(&state, |response| NodeCommand::QueryOwnReputation { response }).await?;
+    Ok(Json(opinions))
+}
+
+async fn get_watchlist(state: ApiState) -> Result<Json<Vec<WatchedAgent>>, StatusCode> {
+    let watchlist = execute_command(&state, |response| NodeCommand::GetWatchlist { response }).await?;
+    Ok(Json(watchlist))
+}
+
+async fn watch_agent(
+    state: ApiState,
+    Path((id_domain, agent_id)): Path<(String, String)>,
+    Json(request): Json<WatchRequest>,
+) -> Result<Json<WatchedAgent>, Response> {
+    validate(&request).map_err(IntoResponse::into_response)?;
+    let watched = execute_query(&state, |response| NodeCommand::WatchAgent {
+        id_domain,
+        agent_id,
+        request,
+        response,
+    }).await?;
+
+    Ok(Json(watched))
+}
+
+async fn unwatch_agent(
+    state: ApiState,
+    Path((id_domain, agent_id)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    execute_command(&state, |response| NodeCommand::UnwatchAgent {
+        id_domain,
+        agent_id,
+        response,
+    }).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_storage_stats(state: ApiState) -> Result<Json<StorageStats>, StatusCode> {
+    let stats = execute_command(&state, |response| NodeCommand::GetStorageStats { response }).await?;
+    Ok(Json(stats))
+}
+
+async fn get_cache_stats(state: ApiState) -> Result<Json<CacheStats>, StatusCode> {
+    let stats = execute_command(&state, |response| NodeCommand::GetCacheStats { response }).await?;
+    Ok(Json(stats))
+}
+
+async fn get_query_stats(state: ApiState) -> Result<Json<QueryStats>, StatusCode> {
+    let stats = execute_command(&state, |response| NodeCommand::GetQueryStats { response }).await?;
+    Ok(Json(stats))
+}
+
+async fn get_runtime_stats(state: ApiState) -> Result<Json<RuntimeStats>, StatusCode> {
+    let stats = execute_command(&state, |response| NodeCommand::GetRuntimeStats { response }).await?;
+    Ok(Json(stats))
+}
+
+async fn get_reciprocity(state: ApiState) -> Result<Json<Vec<PeerReciprocity>>, StatusCode> {
+    let reciprocity = execute_command(&state, |response| NodeCommand::GetReciprocity { response }).await?;
+    Ok(Json(reciprocity))
+}
+
+async fn get_runtime_config(state: ApiState) -> Result<Json<RuntimeConfig>, StatusCode> {
+    let config = execute_command(&state, |response| NodeCommand::GetRuntimeConfig { response }).await?;
+    Ok(Json(config))
+}
+
+async fn update_runtime_config(
+    state: ApiState,
+    Json(update): Json<RuntimeConfigUpdate>,
+) -> Result<Json<RuntimeConfig>, Response> {
+    validate(&update).map_err(IntoResponse::into_response)?;
+    let config = execute_query(&state, |response| NodeCommand::UpdateRuntimeConfig { update, response }).await?;
+    Ok(Json(config))
+}
+
+async fn clear_cache(state: ApiState) -> Result<StatusCode, StatusCode> {
+    execute_command(&state, |response| NodeCommand::ClearCache { response }).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_backup_status(state: ApiState) -> Result<Json<BackupStatus>, StatusCode> {
+    let status = execute_command(&state, |response| NodeCommand::GetBackupStatus { response }).await?;
+    Ok(Json(status))
+}
+
+async fn get_anomalies(state: ApiState) -> Result<Json<AnomalyReport>, StatusCode> {
+    let report = execute_command(&state, |response| NodeCommand::GetAnomalies { response }).await?;
+    Ok(Json(report))
+}
+
+/// Rater weights of the last global aggregation, to see whose opinions the `global` scores lean on
+async fn get_global_trust(state: ApiState) -> Result<Json<GlobalTrust>, StatusCode> {
+    let report = execute_command(&state, |response| NodeCommand::GetGlobalTrust { response }).await?;
+    Ok(Json(report))
+}
+
+#[derive(Deserialize)]
+pub struct GraphParams {
+    /// json (the default), dot or graphml, as for `trust-node graph`
+    pub format: Option<String>,
+}
+
+async fn get_trust_graph(state: ApiState, Query(params): Query<GraphParams>) -> Result<Response, Response> {
+    let format = match params.format.as_deref() {
+        Some(format) => format.parse::<GraphFormat>().map_err(|e| (StatusCode::BAD_REQUEST, e).into_response())?,
+        None => GraphFormat::Json,
+    };
+    let user = state.user.clone();
+    let graph = execute_command(&state, |response| NodeCommand::GetTrustGraph { user, response })
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    let content_type = match format {
+        GraphFormat::Json => "application/json",
+        GraphFormat::Dot => "text/vnd.graphviz",
+        GraphFormat::Graphml => "application/graphml+xml",
+    };
+    let body = graph.render(format).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+    Ok(([(header::CONTENT_TYPE, content_type)], body).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct PeerEventParams {
+    pub peer_id: Option<String>,
+}
+
+/// Recent dials, connections and failures, for when two nodes won't connect
+async fn get_peer_events(
+    state: ApiState,
+    Query(params): Query<PeerEventParams>,
+) -> Result<Json<Vec<PeerEvent>>, StatusCode> {
+    let events = execute_command(&state, |response| NodeCommand::GetPeerEvents {
+        peer_id: params.peer_id,
+        response,
+    }).await?;
+
+    Ok(Json(events))
+}
+
+/// Server-sent events for anomalies and other node notifications
+async fn subscribe_events(state: ApiState) -> Result<impl IntoResponse, StatusCode> {
+    let receiver = execute_command(&state, |response| NodeCommand::SubscribeEvents { response }).await?;
+
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, receiver)),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Event subscriber lagged behind, {} events dropped", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+    .map(|event: NodeEvent| Event::default().event(event.name()).json_data(&event));
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[derive(Deserialize)]
+pub struct RestoreRequest {
+    pub path: PathBuf,
+}
+
+impl Validate for RestoreRequest {
+    fn validate(&self, fields: &mut Fields) {
+        fields.check(!self.path.as_os_str().is_empty(), "path", "must not be empty");
+    }
+}
+
+async fn restore_backup(
+    state: ApiState,
+    Json(req): Json<RestoreRequest>,
+) -> Result<Json<RestoreSummary>, Response> {
+    validate(&req).map_err(IntoResponse::into_response)?;
+    let summary = execute_command(&state, |response| NodeCommand::RestoreBackup {
+        path: req.path,
+        response,
+    }).await.map_err(IntoResponse::into_response)?;
+
+    Ok(Json(summary))
+}
+
+async fn maintain_database(state: ApiState) -> Result<Json<MaintenanceReport>, StatusCode> {
+    let report = execute_command(&state, |response| NodeCommand::MaintainDatabase { response }).await?;
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routed(path: &str, user: Option<&str>, legacy: bool) -> RoutedPath {
+        RoutedPath { path: path.to_string(), user: user.map(str::to_string), legacy }
+    }
+
+    #[test]
+    fn test_paths_are_routed_to_their_version() {
+        assert_eq!(route_path("/v1/trust/ethereum/0xabc"), routed("/v1/trust/ethereum/0xabc", None, false));
+        assert_eq!(route_path("/v1/users/alice/peers"), routed("/v1/peers", Some("alice"), false));
+        assert_eq!(route_path("/v2/peers"), routed("/v2/peers", None, false));
+        assert_eq!(route_path("/health"), routed("/health", None, false));
+        assert_eq!(route_path("/ui"), routed("/ui", None, false));
+    }
+
+    #[test]
+    fn test_unversioned_paths_are_legacy_v1() {
+        assert_eq!(route_path("/trust/batch"), routed("/v1/trust/batch", None, true));
+        assert_eq!(route_path("/users/bob/experiences"), routed("/v1/experiences", Some("bob"), true));
+        assert_eq!(route_path("/users"), routed("/v1/users", None, true));
+        assert_eq!(route_path("/version-info"), routed("/v1/version-info", None, true));
+    }
+
+    #[test]
+    fn test_idempotency_fingerprint_covers_query_and_body() {
+        let fingerprint = |query: Option<&str>, chunks: &[&[u8]]| {
+            let mut fingerprint = Fingerprint::new(query);
+            chunks.iter().for_each(|chunk| fingerprint.add(chunk));
+            (fingerprint.sha256(), fingerprint.bytes)
+        };
+        // However the body is split into chunks, the same bytes give the same fingerprint
+        let whole = fingerprint(Some("strategy=merge"), &[b"{\"peer_id\":\"a\"}"]);
+        assert_eq!(whole, fingerprint(Some("strategy=merge"), &[b"{\"peer_", b"id\":\"a\"}"]));
+        assert_eq!(whole.1, 15);
+        assert_ne!(whole, fingerprint(Some("strategy=overwrite"), &[b"{\"peer_id\":\"a\"}"]));
+        assert_ne!(whole.0, fingerprint(Some("strategy=merge"), &[b"{\"peer_id\":\"b\"}"]).0);
+
+        assert!(is_valid_idempotency_key(b"8c5f0e4a-2b6d-4f1e-9a3c-7d2e1b0f6a59"));
+        assert!(!is_valid_idempotency_key(b""));
+        assert!(!is_valid_idempotency_key(b"two words"));
+        assert!(!is_valid_idempotency_key(&[b'k'; MAX_IDEMPOTENCY_KEY_LEN + 1]));
+    }
+}
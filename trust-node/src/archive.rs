@@ -0,0 +1,275 @@
+use crate::import::{ImportRecord, ImportStreamError, LineSplitter};
+use crate::types::EXPORT_FORMAT_VERSION;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+
+/// Content type of export archives
+pub const ARCHIVE_CONTENT_TYPE: &str = "application/zstd";
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Closing line of an archive, describing the records before it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub version: String,
+    pub exported_at: DateTime<Utc>,
+    pub peers: usize,
+    pub experiences: usize,
+    /// SHA-256 of the peer lines, newlines included, in archive order
+    pub peers_sha256: String,
+    pub experiences_sha256: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("archive I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("corrupt archive: {0}")]
+    Corrupt(String),
+}
+
+impl From<ImportStreamError> for ArchiveError {
+    fn from(err: ImportStreamError) -> Self {
+        ArchiveError::Corrupt(err.to_string())
+    }
+}
+
+/// Record count and running checksum of one kind of record
+#[derive(Default)]
+struct Section {
+    count: usize,
+    hasher: Sha256,
+}
+
+impl Section {
+    fn add(&mut self, line: &[u8]) {
+        self.count += 1;
+        self.hasher.update(line);
+        self.hasher.update(b"\n");
+    }
+
+    fn sha256(self) -> String {
+        format!("{:x}", self.hasher.finalize())
+    }
+}
+
+/// Writes an export as a zstd-compressed NDJSON archive, one ImportRecord per line and the manifest last
+pub struct ArchiveWriter {
+    encoder: zstd::stream::write::Encoder<'static, Vec<u8>>,
+    peers: Section,
+    experiences: Section,
+}
+
+impl ArchiveWriter {
+    pub fn new() -> io::Result<Self> {
+        let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), COMPRESSION_LEVEL)?;
+        encoder.include_checksum(true)?;
+        Ok(Self { encoder, peers: Section::default(), experiences: Section::default() })
+    }
+
+    /// Add a record, returning the compressed bytes that are ready so far
+    pub fn write(&mut self, record: &ImportRecord) -> io::Result<Vec<u8>> {
+        let line = serde_json::to_vec(record)?;
+        match record {
+            ImportRecord::Peer(_) => self.peers.add(&line),
+            ImportRecord::Experience(_) => self.experiences.add(&line),
+            ImportRecord::Manifest(_) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "the manifest is written by finish"))
+            }
+        }
+        self.encoder.write_all(&line)?;
+        self.encoder.write_all(b"\n")?;
+        Ok(std::mem::take(self.encoder.get_mut()))
+    }
+
+    /// Append the manifest and return the rest of the archive
+    pub fn finish(mut self, exported_at: DateTime<Utc>) -> io::Result<Vec<u8>> {
+        let manifest = ArchiveManifest {
+            version: EXPORT_FORMAT_VERSION.to_string(),
+            exported_at,
+            peers: self.peers.count,
+            experiences: self.experiences.count,
+            peers_sha256: self.peers.sha256(),
+            experiences_sha256: self.experiences.sha256(),
+        };
+        self.encoder.write_all(&serde_json::to_vec(&ImportRecord::Manifest(manifest))?)?;
+        self.encoder.write_all(b"\n")?;
+        self.encoder.finish()
+    }
+}
+
+/// Decompresses an archive chunk by chunk
+pub struct ArchiveDecompressor {
+    decoder: zstd::stream::write::Decoder<'static, Vec<u8>>,
+}
+
+impl ArchiveDecompressor {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self { decoder: zstd::stream::write::Decoder::new(Vec::new())? })
+    }
+
+    /// Feed compressed bytes, returning what they decompress to
+    pub fn push(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        self.decoder.write_all(chunk)?;
+        Ok(std::mem::take(self.decoder.get_mut()))
+    }
+
+    pub fn finish(mut self) -> io::Result<Vec<u8>> {
+        self.decoder.flush()?;
+        Ok(std::mem::take(self.decoder.get_mut()))
+    }
+}
+
+/// Only the tag of a line, enough to tell which checksum it belongs to
+#[derive(Deserialize)]
+struct LineKind {
+    kind: String,
+}
+
+/// Checks an archive against its manifest as its chunks arrive, without keeping the records
+pub struct ArchiveVerifier {
+    decompressor: ArchiveDecompressor,
+    lines: LineSplitter,
+    peers: Section,
+    experiences: Section,
+    manifest: Option<ArchiveManifest>,
+}
+
+impl ArchiveVerifier {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            decompressor: ArchiveDecompressor::new()?,
+            lines: LineSplitter::default(),
+            peers: Section::default(),
+            experiences: Section::default(),
+            manifest: None,
+        })
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Result<(), ArchiveError> {
+        let decompressed = self.decompressor.push(chunk)?;
+        let (peers, experiences, manifest) = (&mut self.peers, &mut self.experiences, &mut self.manifest);
+        self.lines.push(&decompressed, |line, bytes| Self::check_line(line, bytes, peers, experiences, manifest))
+    }
+
+    /// The manifest, if every record is there and matches it
+    pub fn finish(mut self) -> Result<ArchiveManifest, ArchiveError> {
+        let decompressed = self.decompressor.finish()?;
+        let (peers, experiences, manifest) = (&mut self.peers, &mut self.experiences, &mut self.manifest);
+        self.lines.push(&decompressed, |line, bytes| Self::check_line(line, bytes, peers, experiences, manifest))?;
+        self.lines.finish(|line, bytes| Self::check_line(line, bytes, peers, experiences, manifest))?;
+
+        let manifest = self.manifest.ok_or_else(|| ArchiveError::Corrupt("the manifest is missing".to_string()))?;
+        if manifest.version != EXPORT_FORMAT_VERSION {
+            return Err(ArchiveError::Corrupt(format!("unsupported archive version {}", manifest.version)));
+        }
+        let mut problems = Vec::new();
+        if self.peers.count != manifest.peers {
+            problems.push(format!("{} peers instead of {}", self.peers.count, manifest.peers));
+        }
+        if self.experiences.count != manifest.experiences {
+            problems.push(format!("{} experiences instead of {}", self.experiences.count, manifest.experiences));
+        }
+        if self.peers.sha256() != manifest.peers_sha256 {
+            problems.push("peer checksum mismatch".to_string());
+        }
+        if self.experiences.sha256() != manifest.experiences_sha256 {
+            problems.push("experience checksum mismatch".to_string());
+        }
+        if problems.is_empty() {
+            Ok(manifest)
+        } else {
+            Err(ArchiveError::Corrupt(problems.join(", ")))
+        }
+    }
+
+    fn check_line(
+        line: usize,
+        bytes: &[u8],
+        peers: &mut Section,
+        experiences: &mut Section,
+        manifest: &mut Option<ArchiveManifest>,
+    ) -> Result<(), ArchiveError> {
+        if manifest.is_some() {
+            return Err(ArchiveError::Corrupt(format!("line {} follows the manifest", line)));
+        }
+        let kind: LineKind = serde_json::from_slice(bytes)
+            .map_err(|e| ArchiveError::Corrupt(format!("line {}: {}", line, e)))?;
+        match kind.kind.as_str() {
+            "peer" => peers.add(bytes),
+            "experience" => experiences.add(bytes),
+            "manifest" => match serde_json::from_slice(bytes) {
+                Ok(ImportRecord::Manifest(parsed)) => *manifest = Some(parsed),
+                _ => return Err(ArchiveError::Corrupt(format!("line {}: unreadable manifest", line))),
+            },
+            other => return Err(ArchiveError::Corrupt(format!("line {}: unknown record kind {}", line, other))),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Peer, PeerAddress, PeerContact, TrustExperience};
+    use uuid::Uuid;
+
+    fn archive(experiences: usize) -> Vec<u8> {
+        let mut writer = ArchiveWriter::new().unwrap();
+        let mut bytes = writer
+            .write(&ImportRecord::Peer(Peer {
+                peer_id: "12D3KooWbob".to_string(),
+                addresses: vec![PeerAddress::new("/ip4/127.0.0.1/tcp/9001")],
+                handle: None,
+                name: "bob".to_string(),
+                recommender_quality: 0.8,
+                added_at: Utc::now(),
+                notes: None,
+                tags: Vec::new(),
+                contact: PeerContact::default(),
+                last_interaction_at: None,
+            }))
+            .unwrap();
+        for i in 0..experiences {
+            let experience = TrustExperience {
+                id: Uuid::new_v4(),
+                id_domain: "test".to_string(),
+                agent_id: format!("agent-{}", i),
+                pv_roi: 1.1,
+                invested_volume: 100.0,
+                timestamp: Utc::now(),
+                notes: None,
+                data: None,
+            };
+            bytes.extend(writer.write(&ImportRecord::Experience(experience)).unwrap());
+        }
+        bytes.extend(writer.finish(Utc::now()).unwrap());
+        bytes
+    }
+
+    fn verify(bytes: &[u8]) -> Result<ArchiveManifest, ArchiveError> {
+        let mut verifier = ArchiveVerifier::new()?;
+        for chunk in bytes.chunks(100) {
+            verifier.push(chunk)?;
+        }
+        verifier.finish()
+    }
+
+    #[test]
+    fn test_archive_round_trip_matches_manifest() {
+        let manifest = verify(&archive(500)).unwrap();
+        assert_eq!((manifest.peers, manifest.experiences), (1, 500));
+    }
+
+    #[test]
+    fn test_damaged_archive_is_rejected() {
+        let mut bytes = archive(500);
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        assert!(verify(&bytes).is_err());
+
+        let truncated = archive(500);
+        assert!(verify(&truncated[..truncated.len() - 20]).is_err());
+    }
+}
\ No newline at end of file
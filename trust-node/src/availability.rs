@@ -0,0 +1,130 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use libp2p::StreamProtocol;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+/// Protocol peers swap their availability filters over, each sending its own and getting the other's back
+pub const AVAILABILITY_PROTOCOL: StreamProtocol = StreamProtocol::new("/repeer/availability/1.0.0");
+/// 8 KiB per filter; with 4 hashes about 2% false positives at 10,000 agents
+pub const FILTER_BITS: u32 = 1 << 16;
+pub const FILTER_HASHES: u32 = 4;
+
+/// Bloom filter of the agents a node can answer about, from its own experiences or through its peers.
+/// A miss means the node surely has nothing; a hit may be a false positive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentFilter {
+    bit_count: u32,
+    hashes: u32,
+    #[serde(serialize_with = "to_base64", deserialize_with = "from_base64")]
+    bits: Vec<u8>,
+}
+
+impl Default for AgentFilter {
+    fn default() -> Self {
+        Self::new(FILTER_BITS, FILTER_HASHES)
+    }
+}
+
+impl AgentFilter {
+    pub fn new(bit_count: u32, hashes: u32) -> Self {
+        let bit_count = bit_count.max(8);
+        Self { bit_count, hashes: hashes.max(1), bits: vec![0; bit_count.div_ceil(8) as usize] }
+    }
+
+    pub fn insert(&mut self, id_domain: &str, agent_id: &str) {
+        for index in self.indexes(id_domain, agent_id) {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    pub fn might_contain(&self, id_domain: &str, agent_id: &str) -> bool {
+        self.indexes(id_domain, agent_id).all(|index| self.bits[index / 8] & (1 << (index % 8)) != 0)
+    }
+
+    /// Add everything another filter holds; false, and nothing added, if it was built with other parameters
+    pub fn union(&mut self, other: &AgentFilter) -> bool {
+        if !self.is_compatible(other) {
+            return false;
+        }
+        self.bits.iter_mut().zip(&other.bits).for_each(|(bit, other)| *bit |= other);
+        true
+    }
+
+    /// Whether the filter is well-formed; one from a peer may not be
+    pub fn is_valid(&self) -> bool {
+        self.bit_count > 0 && self.hashes > 0 && self.bits.len() == self.bit_count.div_ceil(8) as usize
+    }
+
+    pub fn is_compatible(&self, other: &AgentFilter) -> bool {
+        self.bit_count == other.bit_count && self.hashes == other.hashes && self.bits.len() == other.bits.len()
+    }
+
+    /// Share of bits set; the false positive rate is about this to the power of the hash count
+    pub fn fill_ratio(&self) -> f64 {
+        let set: u32 = self.bits.iter().map(|byte| byte.count_ones()).sum();
+        set as f64 / self.bit_count as f64
+    }
+
+    /// Double hashing over two halves of a SHA-256, so every node derives the same bits
+    fn indexes(&self, id_domain: &str, agent_id: &str) -> impl Iterator<Item = usize> {
+        let digest = Sha256::new()
+            .chain_update(id_domain.as_bytes())
+            .chain_update([0])
+            .chain_update(agent_id.as_bytes())
+            .finalize();
+        let h1 = u64::from_le_bytes(digest[0..8].try_into().unwrap_or_default());
+        let h2 = u64::from_le_bytes(digest[8..16].try_into().unwrap_or_default()) | 1;
+        let bit_count = self.bit_count as u64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bit_count) as usize)
+    }
+}
+
+fn to_base64<S: Serializer>(bits: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&STANDARD.encode(bits))
+}
+
+fn from_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+}
+
+/// Sent both ways; None while the node hasn't built its filter yet, which the other side treats as "ask anyway"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AvailabilityExchange {
+    pub filter: Option<AgentFilter>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_has_no_false_negatives() {
+        let mut filter = AgentFilter::default();
+        for i in 0..1000 {
+            filter.insert("ethereum", &format!("0x{:x}", i));
+        }
+        assert!((0..1000).all(|i| filter.might_contain("ethereum", &format!("0x{:x}", i))));
+
+        let false_positives = (1000..11000).filter(|i| filter.might_contain("ethereum", &format!("0x{:x}", i))).count();
+        assert!(false_positives < 10, "{} false positives", false_positives);
+        // The domain is part of the key
+        assert!(!filter.might_contain("shop", "0x1"));
+    }
+
+    #[test]
+    fn test_filters_union_and_survive_the_wire() {
+        let mut alice = AgentFilter::default();
+        alice.insert("ethereum", "0xabc");
+        let mut bob = AgentFilter::default();
+        bob.insert("shop", "bobs");
+
+        assert!(alice.union(&bob));
+        assert!(alice.might_contain("shop", "bobs") && alice.might_contain("ethereum", "0xabc"));
+        assert!(!alice.union(&AgentFilter::new(1024, 4)));
+
+        let decoded: AgentFilter = serde_json::from_str(&serde_json::to_string(&alice).unwrap()).unwrap();
+        assert_eq!(decoded, alice);
+        assert!(decoded.is_valid());
+    }
+}
@@ -0,0 +1,211 @@
+use crate::storage::{Storage, StorageError};
+use crate::types::TrustDataExport;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+const BACKUP_PREFIX: &str = "backup-";
+const BACKUP_SUFFIX: &str = ".db";
+
+#[derive(Debug, Clone)]
+pub struct BackupConfig {
+    pub directory: PathBuf,
+    pub interval: Duration,
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BackupStatus {
+    pub enabled: bool,
+    pub directory: Option<PathBuf>,
+    pub interval_seconds: Option<u64>,
+    pub keep: Option<usize>,
+    pub last_backup_at: Option<DateTime<Utc>>,
+    pub last_backup_path: Option<PathBuf>,
+    pub last_error: Option<String>,
+    pub backups: Vec<PathBuf>,
+}
+
+impl BackupStatus {
+    pub fn new(config: Option<&BackupConfig>) -> Self {
+        match config {
+            Some(config) => Self {
+                enabled: true,
+                directory: Some(config.directory.clone()),
+                interval_seconds: Some(config.interval.as_secs()),
+                keep: Some(config.keep),
+                backups: list_backups(&config.directory).unwrap_or_default(),
+                ..Default::default()
+            },
+            None => Self::default(),
+        }
+    }
+}
+
+/// Periodically snapshots the database into the backup directory, keeping the newest `keep` files
+pub struct BackupScheduler<S: Storage> {
+    storage: Arc<S>,
+    config: BackupConfig,
+    status: Arc<RwLock<BackupStatus>>,
+}
+
+impl<S: Storage + 'static> BackupScheduler<S> {
+    pub fn new(storage: Arc<S>, config: BackupConfig, status: Arc<RwLock<BackupStatus>>) -> Self {
+        Self { storage, config, status }
+    }
+
+    pub async fn run(self) {
+        let mut ticker = interval(self.config.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately, skip it so we don't back up on every restart
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            let result = run_backup(self.storage.as_ref(), &self.config).await;
+            let mut status = self.status.write().unwrap();
+            match result {
+                Ok(path) => {
+                    info!("Wrote backup to {}", path.display());
+                    status.last_backup_at = Some(Utc::now());
+                    status.last_backup_path = Some(path);
+                    status.last_error = None;
+                }
+                Err(e) => {
+                    warn!("Scheduled backup failed: {}", e);
+                    status.last_error = Some(e.to_string());
+                }
+            }
+            status.backups = list_backups(&self.config.directory).unwrap_or_default();
+        }
+    }
+}
+
+/// Write a consistent snapshot of the database and rotate old snapshots away
+pub async fn run_backup<S: Storage + ?Sized>(storage: &S, config: &BackupConfig) -> Result<PathBuf> {
+    std::fs::create_dir_all(&config.directory)?;
+
+    let file_name = format!("{}{}{}", BACKUP_PREFIX, Utc::now().format("%Y%m%dT%H%M%S%.3fZ"), BACKUP_SUFFIX);
+    let path = config.directory.join(file_name);
+    // Written under another name first, so a crash midway can't leave a half snapshot that counts as a backup
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    crate::journal::remove_database(&partial)?;
+    storage.backup_to(&partial).await?;
+    std::fs::rename(&partial, &path)?;
+
+    rotate_backups(&config.directory, config.keep)?;
+    Ok(path)
+}
+
+/// List backup files in `directory`, oldest first
+pub fn list_backups(directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut backups: Vec<PathBuf> = std::fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_SUFFIX))
+                .unwrap_or(false)
+        })
+        .collect();
+    // Timestamps in the file names sort lexicographically
+    backups.sort();
+    Ok(backups)
+}
+
+fn rotate_backups(directory: &Path, keep: usize) -> Result<()> {
+    let backups = list_backups(directory)?;
+    let excess = backups.len().saturating_sub(keep);
+    for old in &backups[..excess] {
+        info!("Removing old backup {}", old.display());
+        std::fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreFormat {
+    /// SQLite database file as written by the backup scheduler
+    Snapshot,
+    /// JSON file as returned by GET /export
+    Export,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreSummary {
+    pub source: PathBuf,
+    pub format: RestoreFormat,
+}
+
+/// Replace the database contents with a backup snapshot or export file
+///
+/// The file is validated before anything is touched, and the swap happens in a single
+/// transaction, so a bad file leaves the current data in place.
+pub async fn restore_from_file<S: Storage + ?Sized>(storage: &S, path: &Path) -> Result<RestoreSummary> {
+    if !path.is_file() {
+        return Err(StorageError::NotFound(format!("Backup file {} does not exist", path.display())).into());
+    }
+
+    let format = detect_format(path)?;
+    info!("Restoring {:?} from {}", format, path.display());
+    match format {
+        RestoreFormat::Snapshot => storage.restore_snapshot(path).await?,
+        RestoreFormat::Export => {
+            let data: TrustDataExport = serde_json::from_slice(&std::fs::read(path)?)
+                .map_err(|e| anyhow::anyhow!("Invalid export file {}: {}", path.display(), e))?;
+            storage.restore_export(data).await?;
+        }
+    }
+
+    Ok(RestoreSummary {
+        source: path.to_path_buf(),
+        format,
+    })
+}
+
+fn detect_format(path: &Path) -> Result<RestoreFormat> {
+    const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+    let mut header = Vec::with_capacity(SQLITE_HEADER.len());
+    std::fs::File::open(path)?
+        .take(SQLITE_HEADER.len() as u64)
+        .read_to_end(&mut header)?;
+
+    if header == SQLITE_HEADER {
+        Ok(RestoreFormat::Snapshot)
+    } else {
+        Ok(RestoreFormat::Export)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_rotate_keeps_newest() -> Result<()> {
+        let dir = tempdir()?;
+        for name in ["backup-20240101T000000.000Z.db", "backup-20240102T000000.000Z.db", "backup-20240103T000000.000Z.db", "unrelated.txt"] {
+            std::fs::write(dir.path().join(name), b"")?;
+        }
+
+        rotate_backups(dir.path(), 2)?;
+
+        let remaining = list_backups(dir.path())?;
+        assert_eq!(remaining.len(), 2);
+        assert!(remaining[0].ends_with("backup-20240102T000000.000Z.db"));
+        assert!(dir.path().join("unrelated.txt").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use crate::storage::Storage;
+use crate::types::TrustDataExport;
+use anyhow::{anyhow, Result};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chacha20poly1305::aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use libp2p::identity::Keypair;
+use serde::{Deserialize, Serialize};
+
+/// Marks a file written by `trust-node identity export`
+const BUNDLE_FORMAT: &str = "repeer-identity";
+const BUNDLE_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+
+/// A bundle that can't be opened with the given passphrase, or isn't a bundle at all
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("invalid identity bundle: {0}")]
+pub struct InvalidBundle(pub String);
+
+/// Everything a user's peers know them by and everything they know, to move to another machine in one file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityBundle {
+    /// Protobuf-encoded keypair, base64
+    key: String,
+    pub data: TrustDataExport,
+}
+
+/// The file on disk: the bundle encrypted with XChaCha20-Poly1305 under an Argon2id key from the passphrase
+#[derive(Debug, Serialize, Deserialize)]
+struct SealedBundle {
+    format: String,
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// What an export wrote or an import brought in
+#[derive(Debug, Clone, Serialize)]
+pub struct BundleSummary {
+    pub peer_id: String,
+    pub peers: usize,
+    pub experiences: usize,
+}
+
+impl IdentityBundle {
+    pub async fn collect<S: Storage + ?Sized>(storage: &S, key: &Keypair) -> Result<Self> {
+        let data = TrustDataExport::new(storage.get_all_experiences().await?, storage.get_peers().await?);
+        Ok(Self { key: STANDARD.encode(key.to_protobuf_encoding()?), data })
+    }
+
+    pub fn key(&self) -> Result<Keypair, InvalidBundle> {
+        STANDARD
+            .decode(&self.key)
+            .ok()
+            .and_then(|bytes| Keypair::from_protobuf_encoding(&bytes).ok())
+            .ok_or_else(|| InvalidBundle("unreadable key".to_string()))
+    }
+
+    pub fn summary(&self) -> Result<BundleSummary, InvalidBundle> {
+        Ok(BundleSummary {
+            peer_id: self.key()?.public().to_peer_id().to_string(),
+            peers: self.data.peers.len(),
+            experiences: self.data.experiences.len(),
+        })
+    }
+
+    pub fn seal(&self, passphrase: &str) -> Result<Vec<u8>> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let cipher = XChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+        let ciphertext = cipher
+            .encrypt(&nonce, serde_json::to_vec(self)?.as_slice())
+            .map_err(|_| anyhow!("encrypting identity bundle failed"))?;
+
+        let sealed = SealedBundle {
+            format: BUNDLE_FORMAT.to_string(),
+            version: BUNDLE_VERSION,
+            salt: STANDARD.encode(salt),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        };
+        Ok(serde_json::to_vec_pretty(&sealed)?)
+    }
+
+    pub fn open(bytes: &[u8], passphrase: &str) -> Result<Self, InvalidBundle> {
+        let malformed = || InvalidBundle("not an identity bundle".to_string());
+        let sealed: SealedBundle = serde_json::from_slice(bytes).map_err(|_| malformed())?;
+        if sealed.format != BUNDLE_FORMAT {
+            return Err(malformed());
+        }
+        if sealed.version != BUNDLE_VERSION {
+            return Err(InvalidBundle(format!("unsupported version {}", sealed.version)));
+        }
+        let decode = |field: &str| STANDARD.decode(field).map_err(|_| malformed());
+        let (salt, nonce, ciphertext) = (decode(&sealed.salt)?, decode(&sealed.nonce)?, decode(&sealed.ciphertext)?);
+        if nonce.len() != 24 {
+            return Err(malformed());
+        }
+
+        let key = derive_key(passphrase, &salt).map_err(|e| InvalidBundle(e.to_string()))?;
+        let plaintext = XChaCha20Poly1305::new(&key)
+            .decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| InvalidBundle("wrong passphrase or damaged file".to_string()))?;
+        serde_json::from_slice(&plaintext).map_err(|_| malformed())
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow!("deriving bundle key: {}", e))?;
+    Ok(Key::from(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Peer, PeerContact};
+    use chrono::Utc;
+
+    fn bundle(key: &Keypair) -> IdentityBundle {
+        let peer = Peer {
+            peer_id: "12D3KooWbob".to_string(),
+            addresses: Vec::new(),
+            handle: Some("bob".to_string()),
+            name: "Bob".to_string(),
+            recommender_quality: 0.8,
+            added_at: Utc::now(),
+            notes: None,
+            tags: Vec::new(),
+            contact: PeerContact::default(),
+            last_interaction_at: None,
+        };
+        IdentityBundle {
+            key: STANDARD.encode(key.to_protobuf_encoding().unwrap()),
+            data: TrustDataExport::new(Vec::new(), vec![peer]),
+        }
+    }
+
+    #[test]
+    fn test_bundles_open_with_their_passphrase_only() {
+        let key = Keypair::generate_ed25519();
+        let sealed = bundle(&key).seal("correct horse").unwrap();
+        assert!(!String::from_utf8_lossy(&sealed).contains("12D3KooWbob"));
+
+        let opened = IdentityBundle::open(&sealed, "correct horse").unwrap();
+        assert_eq!(opened.key().unwrap().public(), key.public());
+        assert_eq!(opened.data.peers[0].handle.as_deref(), Some("bob"));
+        let summary = opened.summary().unwrap();
+        assert_eq!(summary.peer_id, key.public().to_peer_id().to_string());
+        assert_eq!(summary.peers, 1);
+
+        assert!(IdentityBundle::open(&sealed, "battery staple").is_err());
+        assert!(IdentityBundle::open(b"{}", "correct horse").is_err());
+    }
+}
@@ -0,0 +1,552 @@
+use crate::rng::SplitMix;
+use crate::storage::{Storage, StorageError, StorageResult};
+use crate::types::{
+    AgentClaim, AgentIdentifier, AgentQueries, CachedTrustScore, DomainDefaults, ExperienceFilter, ExperienceRevision, ExperienceTemplate,
+    IdempotentResponse, InflationIndex, MaintenanceReport, Peer, PeerProposal, PeeringAnswerOut, PeerRequest, PeerRequestStatus, PendingExperience, ProposedPeer, QualityRevision, QueryResultEntry, SharingPrecision,
+    StorageStats, TrustDataExport, TrustExperience, TrustScore, WatchedAgent,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+/// Share of each kind of fault to inject; all zero means chaos is off
+#[derive(Debug, Clone, Default)]
+pub struct ChaosConfig {
+    /// Outgoing peer responses whose stream is reset instead of answered
+    pub drop_responses: f64,
+    /// Outgoing peer responses held back by `response_delay` first
+    pub delay_responses: f64,
+    pub response_delay: Duration,
+    /// Outgoing peer responses replaced by bytes that don't decode
+    pub corrupt_frames: f64,
+    /// Storage calls that fail with an I/O error
+    pub fail_storage: f64,
+    pub seed: u64,
+}
+
+/// What to do to one outgoing response
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResponseFault {
+    Drop,
+    Delay(Duration),
+    Corrupt,
+}
+
+/// Rolls the dice for the faults of a ChaosConfig, shared by the codec and ChaosStorage
+#[derive(Debug)]
+pub struct Chaos {
+    config: ChaosConfig,
+    /// Starts as `config.fail_storage`; can be raised once a node is up, since it can't start without storage
+    fail_storage: Mutex<f64>,
+    rng: Mutex<SplitMix>,
+}
+
+impl Chaos {
+    pub fn new(config: ChaosConfig) -> Self {
+        let rng = Mutex::new(SplitMix(config.seed));
+        let fail_storage = Mutex::new(config.fail_storage);
+        Self { config, fail_storage, rng }
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(ChaosConfig::default())
+    }
+
+    fn roll(&self, probability: f64) -> bool {
+        probability > 0.0 && self.rng.lock().unwrap().unit() < probability
+    }
+
+    pub fn response_fault(&self) -> Option<ResponseFault> {
+        if self.roll(self.config.drop_responses) {
+            Some(ResponseFault::Drop)
+        } else if self.roll(self.config.corrupt_frames) {
+            Some(ResponseFault::Corrupt)
+        } else if self.roll(self.config.delay_responses) {
+            Some(ResponseFault::Delay(self.config.response_delay))
+        } else {
+            None
+        }
+    }
+
+    /// Fail `share` of the storage calls from now on
+    pub fn set_fail_storage(&self, share: f64) {
+        *self.fail_storage.lock().unwrap() = share;
+    }
+
+    fn storage_fault(&self, call: &str) -> StorageResult<()> {
+        let fail_storage = *self.fail_storage.lock().unwrap();
+        if self.roll(fail_storage) {
+            debug!("Chaos: failing storage call {}", call);
+            return Err(StorageError::Io(format!("chaos: injected failure in {}", call)));
+        }
+        Ok(())
+    }
+}
+
+/// Storage wrapper failing a share of calls before they reach the real storage
+pub struct ChaosStorage<S: Storage> {
+    inner: S,
+    chaos: Arc<Chaos>,
+}
+
+impl<S: Storage> ChaosStorage<S> {
+    pub fn new(inner: S, chaos: Arc<Chaos>) -> Self {
+        Self { inner, chaos }
+    }
+}
+
+#[async_trait]
+impl<S: Storage> Storage for ChaosStorage<S> {
+    async fn add_experience(&self, experience: TrustExperience) -> StorageResult<()> {
+        self.chaos.storage_fault("add_experience")?;
+        self.inner.add_experience(experience).await
+    }
+
+    async fn add_experiences(&self, experiences: Vec<TrustExperience>) -> StorageResult<()> {
+        self.chaos.storage_fault("add_experiences")?;
+        self.inner.add_experiences(experiences).await
+    }
+
+    async fn get_experience(&self, experience_id: &str) -> StorageResult<Option<TrustExperience>> {
+        self.chaos.storage_fault("get_experience")?;
+        self.inner.get_experience(experience_id).await
+    }
+
+    async fn get_experiences(&self, id_domain: &str, agent_id: &str) -> StorageResult<Vec<TrustExperience>> {
+        self.chaos.storage_fault("get_experiences")?;
+        self.inner.get_experiences(id_domain, agent_id).await
+    }
+
+    async fn get_all_experiences(&self) -> StorageResult<Vec<TrustExperience>> {
+        self.chaos.storage_fault("get_all_experiences")?;
+        self.inner.get_all_experiences().await
+    }
+
+    fn stream_experiences(&self) -> BoxStream<'static, StorageResult<TrustExperience>> {
+        match self.chaos.storage_fault("stream_experiences") {
+            Ok(()) => self.inner.stream_experiences(),
+            Err(e) => stream::once(async move { Err(e) }).boxed(),
+        }
+    }
+
+    async fn find_experiences(&self, filter: &ExperienceFilter) -> StorageResult<Vec<TrustExperience>> {
+        self.chaos.storage_fault("find_experiences")?;
+        self.inner.find_experiences(filter).await
+    }
+
+    async fn count_experiences(&self, filter: &ExperienceFilter) -> StorageResult<u64> {
+        self.chaos.storage_fault("count_experiences")?;
+        self.inner.count_experiences(filter).await
+    }
+
+    async fn update_experience(&self, experience: TrustExperience, changed_by: Option<&str>) -> StorageResult<()> {
+        self.chaos.storage_fault("update_experience")?;
+        self.inner.update_experience(experience, changed_by).await
+    }
+
+    async fn remove_experience(&self, experience_id: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("remove_experience")?;
+        self.inner.remove_experience(experience_id).await
+    }
+
+    async fn add_pending_experience(&self, pending: PendingExperience) -> StorageResult<()> {
+        self.chaos.storage_fault("add_pending_experience")?;
+        self.inner.add_pending_experience(pending).await
+    }
+
+    async fn get_pending_experiences(&self) -> StorageResult<Vec<PendingExperience>> {
+        self.chaos.storage_fault("get_pending_experiences")?;
+        self.inner.get_pending_experiences().await
+    }
+
+    async fn settle_experience(&self, experience: TrustExperience) -> StorageResult<()> {
+        self.chaos.storage_fault("settle_experience")?;
+        self.inner.settle_experience(experience).await
+    }
+
+    async fn remove_pending_experience(&self, experience_id: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("remove_pending_experience")?;
+        self.inner.remove_pending_experience(experience_id).await
+    }
+
+    async fn add_experience_template(&self, template: &ExperienceTemplate) -> StorageResult<()> {
+        self.chaos.storage_fault("add_experience_template")?;
+        self.inner.add_experience_template(template).await
+    }
+
+    async fn get_experience_templates(&self) -> StorageResult<Vec<ExperienceTemplate>> {
+        self.chaos.storage_fault("get_experience_templates")?;
+        self.inner.get_experience_templates().await
+    }
+
+    async fn remove_experience_template(&self, template_id: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("remove_experience_template")?;
+        self.inner.remove_experience_template(template_id).await
+    }
+
+    async fn record_template_occurrences(
+        &self,
+        template_id: &str,
+        occurrences: Vec<PendingExperience>,
+        next_due_at: DateTime<Utc>,
+    ) -> StorageResult<()> {
+        self.chaos.storage_fault("record_template_occurrences")?;
+        self.inner.record_template_occurrences(template_id, occurrences, next_due_at).await
+    }
+
+    async fn get_experience_history(&self, experience_id: &str) -> StorageResult<Vec<ExperienceRevision>> {
+        self.chaos.storage_fault("get_experience_history")?;
+        self.inner.get_experience_history(experience_id).await
+    }
+
+    async fn get_experience_deletions(&self) -> StorageResult<Vec<ExperienceRevision>> {
+        self.chaos.storage_fault("get_experience_deletions")?;
+        self.inner.get_experience_deletions().await
+    }
+
+    async fn get_experienced_agents(&self) -> StorageResult<Vec<AgentIdentifier>> {
+        self.chaos.storage_fault("get_experienced_agents")?;
+        self.inner.get_experienced_agents().await
+    }
+
+    async fn get_experiences_page(&self, after: Option<(&str, &str)>, limit: u32) -> StorageResult<Vec<TrustExperience>> {
+        self.chaos.storage_fault("get_experiences_page")?;
+        self.inner.get_experiences_page(after, limit).await
+    }
+
+    async fn get_agent_aggregate(&self, id_domain: &str, agent_id: &str) -> StorageResult<Option<TrustScore>> {
+        self.chaos.storage_fault("get_agent_aggregate")?;
+        self.inner.get_agent_aggregate(id_domain, agent_id).await
+    }
+
+    async fn add_peer(&self, peer: Peer) -> StorageResult<()> {
+        self.chaos.storage_fault("add_peer")?;
+        self.inner.add_peer(peer).await
+    }
+
+    async fn add_peers(&self, peers: Vec<Peer>) -> StorageResult<()> {
+        self.chaos.storage_fault("add_peers")?;
+        self.inner.add_peers(peers).await
+    }
+
+    async fn get_peers(&self) -> StorageResult<Vec<Peer>> {
+        self.chaos.storage_fault("get_peers")?;
+        self.inner.get_peers().await
+    }
+
+    async fn update_peer(&self, peer: &Peer) -> StorageResult<()> {
+        self.chaos.storage_fault("update_peer")?;
+        self.inner.update_peer(peer).await
+    }
+
+    async fn update_peer_quality(&self, peer_id: &str, quality: f64, reason: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("update_peer_quality")?;
+        self.inner.update_peer_quality(peer_id, quality, reason).await
+    }
+
+    async fn get_quality_history(&self, peer_id: &str) -> StorageResult<Vec<QualityRevision>> {
+        self.chaos.storage_fault("get_quality_history")?;
+        self.inner.get_quality_history(peer_id).await
+    }
+
+    async fn remove_peer(&self, peer_id: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("remove_peer")?;
+        self.inner.remove_peer(peer_id).await
+    }
+
+    async fn rename_peer(&self, old_peer_id: &str, new_peer_id: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("rename_peer")?;
+        self.inner.rename_peer(old_peer_id, new_peer_id).await
+    }
+
+    async fn clear_peers(&self) -> StorageResult<()> {
+        self.chaos.storage_fault("clear_peers")?;
+        self.inner.clear_peers().await
+    }
+
+    async fn clear_experiences(&self) -> StorageResult<()> {
+        self.chaos.storage_fault("clear_experiences")?;
+        self.inner.clear_experiences().await
+    }
+
+    async fn cache_trust_score(&self, cached: CachedTrustScore) -> StorageResult<()> {
+        self.chaos.storage_fault("cache_trust_score")?;
+        self.inner.cache_trust_score(cached).await
+    }
+
+    async fn get_cached_scores(&self, id_domain: &str, agent_id: &str) -> StorageResult<Vec<CachedTrustScore>> {
+        self.chaos.storage_fault("get_cached_scores")?;
+        self.inner.get_cached_scores(id_domain, agent_id).await
+    }
+
+    async fn get_all_cached_scores(&self) -> StorageResult<Vec<CachedTrustScore>> {
+        self.chaos.storage_fault("get_all_cached_scores")?;
+        self.inner.get_all_cached_scores().await
+    }
+
+    async fn compact_cached_scores(&self, older_than: DateTime<Utc>) -> StorageResult<u64> {
+        self.chaos.storage_fault("compact_cached_scores")?;
+        self.inner.compact_cached_scores(older_than).await
+    }
+
+    async fn get_query_result(&self, cache_key: &str) -> StorageResult<Option<QueryResultEntry>> {
+        self.chaos.storage_fault("get_query_result")?;
+        self.inner.get_query_result(cache_key).await
+    }
+
+    async fn put_query_result(&self, entry: &QueryResultEntry) -> StorageResult<()> {
+        self.chaos.storage_fault("put_query_result")?;
+        self.inner.put_query_result(entry).await
+    }
+
+    async fn invalidate_query_results(&self, id_domain: &str, agent_id: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("invalidate_query_results")?;
+        self.inner.invalidate_query_results(id_domain, agent_id).await
+    }
+
+    async fn purge_query_results(&self, older_than: Option<DateTime<Utc>>) -> StorageResult<u64> {
+        self.chaos.storage_fault("purge_query_results")?;
+        self.inner.purge_query_results(older_than).await
+    }
+
+    async fn record_agent_queries(&self, queries: &[AgentQueries]) -> StorageResult<()> {
+        self.chaos.storage_fault("record_agent_queries")?;
+        self.inner.record_agent_queries(queries).await
+    }
+
+    async fn get_hot_agents(&self, limit: usize, now: DateTime<Utc>) -> StorageResult<Vec<AgentIdentifier>> {
+        self.chaos.storage_fault("get_hot_agents")?;
+        self.inner.get_hot_agents(limit, now).await
+    }
+
+    async fn get_idempotent_response(&self, endpoint: &str, key: &str, now: DateTime<Utc>) -> StorageResult<Option<IdempotentResponse>> {
+        self.chaos.storage_fault("get_idempotent_response")?;
+        self.inner.get_idempotent_response(endpoint, key, now).await
+    }
+
+    async fn put_idempotent_response(&self, response: &IdempotentResponse, expires_at: DateTime<Utc>) -> StorageResult<()> {
+        self.chaos.storage_fault("put_idempotent_response")?;
+        self.inner.put_idempotent_response(response, expires_at).await
+    }
+
+    async fn purge_idempotent_responses(&self, now: DateTime<Utc>) -> StorageResult<u64> {
+        self.chaos.storage_fault("purge_idempotent_responses")?;
+        self.inner.purge_idempotent_responses(now).await
+    }
+
+    async fn get_domain_defaults(&self) -> StorageResult<Vec<DomainDefaults>> {
+        self.chaos.storage_fault("get_domain_defaults")?;
+        self.inner.get_domain_defaults().await
+    }
+
+    async fn set_domain_defaults(&self, defaults: &DomainDefaults) -> StorageResult<()> {
+        self.chaos.storage_fault("set_domain_defaults")?;
+        self.inner.set_domain_defaults(defaults).await
+    }
+
+    async fn remove_domain_defaults(&self, id_domain: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("remove_domain_defaults")?;
+        self.inner.remove_domain_defaults(id_domain).await
+    }
+
+    async fn get_inflation_indexes(&self) -> StorageResult<Vec<InflationIndex>> {
+        self.chaos.storage_fault("get_inflation_indexes")?;
+        self.inner.get_inflation_indexes().await
+    }
+
+    async fn set_inflation_index(&self, index: &InflationIndex) -> StorageResult<()> {
+        self.chaos.storage_fault("set_inflation_index")?;
+        self.inner.set_inflation_index(index).await
+    }
+
+    async fn remove_inflation_index(&self, currency: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("remove_inflation_index")?;
+        self.inner.remove_inflation_index(currency).await
+    }
+
+    async fn get_sharing_precision(&self) -> StorageResult<Vec<SharingPrecision>> {
+        self.chaos.storage_fault("get_sharing_precision")?;
+        self.inner.get_sharing_precision().await
+    }
+
+    async fn set_sharing_precision(&self, precision: &SharingPrecision) -> StorageResult<()> {
+        self.chaos.storage_fault("set_sharing_precision")?;
+        self.inner.set_sharing_precision(precision).await
+    }
+
+    async fn remove_sharing_precision(&self, subject: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("remove_sharing_precision")?;
+        self.inner.remove_sharing_precision(subject).await
+    }
+
+    async fn get_agent_claims(&self) -> StorageResult<Vec<AgentClaim>> {
+        self.chaos.storage_fault("get_agent_claims")?;
+        self.inner.get_agent_claims().await
+    }
+
+    async fn add_agent_claim(&self, claim: &AgentClaim) -> StorageResult<()> {
+        self.chaos.storage_fault("add_agent_claim")?;
+        self.inner.add_agent_claim(claim).await
+    }
+
+    async fn verify_agent_claim(&self, id_domain: &str, agent_id: &str, proof_url: &str, at: DateTime<Utc>) -> StorageResult<()> {
+        self.chaos.storage_fault("verify_agent_claim")?;
+        self.inner.verify_agent_claim(id_domain, agent_id, proof_url, at).await
+    }
+
+    async fn remove_agent_claim(&self, id_domain: &str, agent_id: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("remove_agent_claim")?;
+        self.inner.remove_agent_claim(id_domain, agent_id).await
+    }
+
+    async fn get_watchlist(&self) -> StorageResult<Vec<WatchedAgent>> {
+        self.chaos.storage_fault("get_watchlist")?;
+        self.inner.get_watchlist().await
+    }
+
+    async fn watch_agent(&self, watched: &WatchedAgent) -> StorageResult<()> {
+        self.chaos.storage_fault("watch_agent")?;
+        self.inner.watch_agent(watched).await
+    }
+
+    async fn unwatch_agent(&self, id_domain: &str, agent_id: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("unwatch_agent")?;
+        self.inner.unwatch_agent(id_domain, agent_id).await
+    }
+
+    async fn set_watched_score(&self, id_domain: &str, agent_id: &str, score: &TrustScore) -> StorageResult<()> {
+        self.chaos.storage_fault("set_watched_score")?;
+        self.inner.set_watched_score(id_domain, agent_id, score).await
+    }
+
+    async fn get_peer_requests(&self) -> StorageResult<Vec<PeerRequest>> {
+        self.chaos.storage_fault("get_peer_requests")?;
+        self.inner.get_peer_requests().await
+    }
+
+    async fn record_peer_request(&self, peer_id: &str, at: DateTime<Utc>) -> StorageResult<PeerRequest> {
+        self.chaos.storage_fault("record_peer_request")?;
+        self.inner.record_peer_request(peer_id, at).await
+    }
+
+    async fn record_peer_proposal(&self, peer_id: &str, proposal: &PeerProposal, at: DateTime<Utc>) -> StorageResult<PeerRequest> {
+        self.chaos.storage_fault("record_peer_proposal")?;
+        self.inner.record_peer_proposal(peer_id, proposal, at).await
+    }
+
+    async fn set_peer_request_status(&self, peer_id: &str, status: PeerRequestStatus, at: DateTime<Utc>) -> StorageResult<()> {
+        self.chaos.storage_fault("set_peer_request_status")?;
+        self.inner.set_peer_request_status(peer_id, status, at).await
+    }
+
+    async fn remove_peer_request(&self, peer_id: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("remove_peer_request")?;
+        self.inner.remove_peer_request(peer_id).await
+    }
+
+    async fn get_proposed_peers(&self) -> StorageResult<Vec<ProposedPeer>> {
+        self.chaos.storage_fault("get_proposed_peers")?;
+        self.inner.get_proposed_peers().await
+    }
+
+    async fn put_proposed_peer(&self, proposed: &ProposedPeer) -> StorageResult<()> {
+        self.chaos.storage_fault("put_proposed_peer")?;
+        self.inner.put_proposed_peer(proposed).await
+    }
+
+    async fn remove_proposed_peer(&self, peer_id: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("remove_proposed_peer")?;
+        self.inner.remove_proposed_peer(peer_id).await
+    }
+
+    async fn get_peering_answers(&self) -> StorageResult<Vec<PeeringAnswerOut>> {
+        self.chaos.storage_fault("get_peering_answers")?;
+        self.inner.get_peering_answers().await
+    }
+
+    async fn put_peering_answer(&self, answer: &PeeringAnswerOut) -> StorageResult<()> {
+        self.chaos.storage_fault("put_peering_answer")?;
+        self.inner.put_peering_answer(answer).await
+    }
+
+    async fn remove_peering_answer(&self, peer_id: &str) -> StorageResult<()> {
+        self.chaos.storage_fault("remove_peering_answer")?;
+        self.inner.remove_peering_answer(peer_id).await
+    }
+
+    async fn storage_stats(&self) -> StorageResult<StorageStats> {
+        self.chaos.storage_fault("storage_stats")?;
+        self.inner.storage_stats().await
+    }
+
+    async fn maintain(&self) -> StorageResult<MaintenanceReport> {
+        self.chaos.storage_fault("maintain")?;
+        self.inner.maintain().await
+    }
+
+    async fn backup_to(&self, path: &Path) -> StorageResult<()> {
+        self.chaos.storage_fault("backup_to")?;
+        self.inner.backup_to(path).await
+    }
+
+    async fn restore_snapshot(&self, path: &Path) -> StorageResult<()> {
+        self.chaos.storage_fault("restore_snapshot")?;
+        self.inner.restore_snapshot(path).await
+    }
+
+    async fn restore_export(&self, data: TrustDataExport) -> StorageResult<()> {
+        self.chaos.storage_fault("restore_export")?;
+        self.inner.restore_export(data).await
+    }
+
+    async fn import_records(
+        &self,
+        replaced_experiences: &[String],
+        experiences: Vec<TrustExperience>,
+        replaced_peers: &[String],
+        peers: Vec<Peer>,
+    ) -> StorageResult<()> {
+        self.chaos.storage_fault("import_records")?;
+        self.inner.import_records(replaced_experiences, experiences, replaced_peers, peers).await
+    }
+
+    async fn merge_records(
+        &self,
+        added: Vec<TrustExperience>,
+        updated: Vec<TrustExperience>,
+        removed: &[String],
+        peers: Vec<Peer>,
+        changed_by: &str,
+    ) -> StorageResult<()> {
+        self.chaos.storage_fault("merge_records")?;
+        self.inner.merge_records(added, updated, removed, peers, changed_by).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_chaos_never_interferes() {
+        let chaos = Chaos::disabled();
+        for _ in 0..1000 {
+            assert_eq!(chaos.response_fault(), None);
+            assert!(chaos.storage_fault("test").is_ok());
+        }
+    }
+
+    #[test]
+    fn test_faults_follow_their_rates() {
+        let chaos = Chaos::new(ChaosConfig { drop_responses: 0.25, seed: 7, ..ChaosConfig::default() });
+        let drops = (0..10_000).filter(|_| chaos.response_fault() == Some(ResponseFault::Drop)).count();
+        assert!((2_000..3_000).contains(&drops), "{} drops", drops);
+
+        let always = Chaos::new(ChaosConfig { fail_storage: 1.0, ..ChaosConfig::default() });
+        assert!(matches!(always.storage_fault("test"), Err(StorageError::Io(_))));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,197 @@
+use crate::backup::BackupConfig;
+#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::clock::{system_clock, SharedClock};
+use crate::notify::NotifyConfig;
+use crate::quarantine::QuarantinePolicy;
+use crate::reputation::ReputationPolicy;
+use crate::types::{AnswerPrivacy, Discounting, InfluenceCaps, MinEvidence, Peer, QualityDecay, QueryLimits, ScoreBounds, ScoreClamping};
+#[cfg(feature = "chaos")]
+use std::sync::Arc;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Optional node features, assembled from the command line in main.rs
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    pub backup: Option<BackupConfig>,
+    /// How often to scan for collusion and other anomalies; None disables the scans
+    pub anomaly_interval: Option<Duration>,
+    /// How often to recompute the EigenTrust-style global aggregate; None leaves it out of responses
+    pub global_trust_interval: Option<Duration>,
+    /// Where friend requests, falling watched scores and long outages are pushed to; None sends nothing
+    pub notify: Option<NotifyConfig>,
+    /// Scores below this are answered as insufficient data, locally and to peers
+    pub min_evidence: MinEvidence,
+    /// Scores peers only get once enough experiences back them
+    pub answer_privacy: AnswerPrivacy,
+    /// Who gets this node's own scores of the agents they claim
+    pub reputation_policy: ReputationPolicy,
+    /// Most of an agent's merged volume a single peer or circle may supply
+    pub influence_caps: InfluenceCaps,
+    /// Scores from peers outside these are rejected rather than cached and merged
+    pub score_bounds: ScoreBounds,
+    /// Range merged ROIs are clamped into and cap on each source's weighted volume; the default clamps nothing
+    pub score_clamping: ScoreClamping,
+    /// Weight of a peer's answer relative to first-hand data; applied once per hop, so it compounds along the path
+    pub hop_damping: f64,
+    /// Fading of silent peers' recommender quality at query time; None weights peers as set
+    pub quality_decay: Option<QualityDecay>,
+    /// How often to prefetch scores of watched and most-queried agents while idle; None disables the prefetch
+    pub refresh_interval: Option<Duration>,
+    /// Best peers asked during a prefetch; 0 asks every peer good enough
+    pub prefetch_peers: usize,
+    /// Most-queried agents whose scores are computed at startup, before the node answers commands; 0 starts cold
+    pub warm_up_agents: usize,
+    /// How often to swap Bloom filters of the agents we can answer about with peers; None asks every peer on every query
+    pub availability_interval: Option<Duration>,
+    /// How often recurring experience templates are checked for due occurrences; None never generates them
+    pub recurring_interval: Option<Duration>,
+    /// Age past which peers' cached recommendations are folded into one entry per agent; None keeps them all
+    pub score_compaction_age: Option<Duration>,
+    /// How returns are brought to present value unless a domain or the request says otherwise
+    pub discounting: Discounting,
+    /// Peer queries whose local scores are gathered concurrently
+    pub inbound_workers: usize,
+    /// Largest query accepted from the API or from peers
+    pub query_limits: QueryLimits,
+    /// Queries one peer may have waiting for a worker before further ones are answered empty
+    pub max_queued_per_peer: usize,
+    /// Time between Kademlia peer discovery rounds
+    pub discovery_interval: Duration,
+    /// Most peers asked per query, highest recommender quality first; 0 asks every connected peer
+    pub max_fanout: usize,
+    /// How long calculated scores are served from the cache
+    pub cache_ttl: Duration,
+    /// How long a peer that answered without a score for an agent isn't asked about it again; zero always asks
+    pub no_data_ttl: Duration,
+    /// How long responses to writes sent with an Idempotency-Key are replayed to retries; None ignores the header
+    pub idempotency_ttl: Option<Duration>,
+    /// How long a peer is waited for until its latency has been measured; after that it gets a multiple of its
+    /// typical response time
+    pub request_timeout: Duration,
+    /// Longest a peer with high measured latency may be waited for, e.g. over WAN or mobile links; None holds
+    /// every peer to request_timeout
+    pub max_request_timeout: Option<Duration>,
+    /// Dials to known peers in flight at once; the rest wait for a later round
+    pub max_concurrent_dials: usize,
+    /// Which known peers get the dial slots first, e.g. after a restart
+    pub dial_priority: DialPriority,
+    /// When peers that break the protocol are kept out; None never quarantines anyone
+    pub quarantine: Option<QuarantinePolicy>,
+    /// How long a connection without traffic stays open
+    pub idle_connection_timeout: Duration,
+    /// Peers whose recommender quality is at least this keep their connections open regardless of the idle
+    /// timeout, saving a dial before queries to them; None keeps no connection open
+    pub keep_alive_quality: Option<f64>,
+    pub transport: P2pTransport,
+    /// Where the node's key is kept across restarts; None gives it a new identity every start, as simulations want
+    pub identity_file: Option<PathBuf>,
+    /// Time used for scoring, caching and aging; tests substitute a ManualClock
+    pub clock: SharedClock,
+    /// Faults injected into peer responses; storage faults need the storage wrapped in ChaosStorage
+    #[cfg(feature = "chaos")]
+    pub chaos: Arc<Chaos>,
+}
+
+/// How the node reaches its peers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum P2pTransport {
+    #[default]
+    Tcp,
+    /// In-process channels, for simulating a network inside one test; the port names the node
+    Memory,
+}
+
+impl P2pTransport {
+    pub fn listen_address(&self, port: u16) -> String {
+        match self {
+            P2pTransport::Tcp => format!("/ip4/0.0.0.0/tcp/{}", port),
+            P2pTransport::Memory => format!("/memory/{}", port),
+        }
+    }
+}
+
+/// Order in which unconnected known peers are dialed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DialPriority {
+    /// Best recommenders first, the ones we heard from most recently breaking ties
+    #[default]
+    Quality,
+    /// The peers we heard from most recently first, better recommenders breaking ties
+    Recency,
+}
+
+impl DialPriority {
+    /// A priority by the name used on the command line
+    pub fn named(name: &str) -> Result<Self, String> {
+        match name {
+            "quality" => Ok(DialPriority::Quality),
+            "recency" => Ok(DialPriority::Recency),
+            other => Err(format!("unknown dial priority {}, expected quality or recency", other)),
+        }
+    }
+
+    /// Sort peers, each with its current recommender quality, the one to dial first first
+    pub fn sort(&self, peers: &mut [(&Peer, f64)]) {
+        peers.sort_by(|(a, a_quality), (b, b_quality)| {
+            let quality = b_quality.total_cmp(a_quality);
+            let recency = b.last_interaction().cmp(&a.last_interaction());
+            match self {
+                DialPriority::Quality => quality.then(recency),
+                DialPriority::Recency => recency.then(quality),
+            }
+        });
+    }
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            backup: None,
+            anomaly_interval: None,
+            global_trust_interval: None,
+            notify: None,
+            min_evidence: MinEvidence::default(),
+            answer_privacy: AnswerPrivacy::default(),
+            reputation_policy: ReputationPolicy::default(),
+            influence_caps: InfluenceCaps::default(),
+            score_bounds: ScoreBounds::default(),
+            score_clamping: ScoreClamping::default(),
+            hop_damping: 1.0,
+            quality_decay: None,
+            refresh_interval: None,
+            prefetch_peers: 5,
+            warm_up_agents: 0,
+            availability_interval: None,
+            recurring_interval: None,
+            score_compaction_age: None,
+            discounting: Discounting::default(),
+            inbound_workers: default_inbound_workers(),
+            query_limits: QueryLimits::default(),
+            max_queued_per_peer: 32,
+            discovery_interval: Duration::from_secs(30),
+            max_fanout: 0,
+            cache_ttl: Duration::from_secs(300),
+            no_data_ttl: Duration::from_secs(60),
+            idempotency_ttl: Some(Duration::from_secs(24 * 60 * 60)),
+            request_timeout: Duration::from_secs(5),
+            max_request_timeout: None,
+            max_concurrent_dials: 5,
+            dial_priority: DialPriority::default(),
+            quarantine: Some(QuarantinePolicy::default()),
+            idle_connection_timeout: Duration::from_secs(60),
+            keep_alive_quality: None,
+            transport: P2pTransport::default(),
+            identity_file: None,
+            clock: system_clock(),
+            #[cfg(feature = "chaos")]
+            chaos: Arc::new(Chaos::disabled()),
+        }
+    }
+}
+
+/// One worker per CPU, as gathering scores is mostly SQLite work
+pub fn default_inbound_workers() -> usize {
+    std::thread::available_parallelism().map_or(4, |n| n.get())
+}
\ No newline at end of file
@@ -0,0 +1,89 @@
+use crate::node::NodeCommand;
+use crate::telemetry::LogFile;
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{interval, sleep, timeout, MissedTickBehavior};
+use tracing::{info, warn};
+
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Send a state to systemd; without NOTIFY_SOCKET, i.e. when not run as a notify service, nothing happens
+fn notify(state: &[sd_notify::NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+/// How often systemd wants a keepalive: half its WatchdogSec, or None without a watchdog
+pub fn watchdog_interval() -> Option<Duration> {
+    let mut usec = 0;
+    sd_notify::watchdog_enabled(false, &mut usec).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Ask a node something cheap, to see that its loop still turns
+async fn ping(commands: &mpsc::Sender<NodeCommand>, patience: Duration) -> Result<Vec<String>> {
+    let (tx, rx) = oneshot::channel();
+    timeout(patience, async {
+        commands.send(NodeCommand::GetListenAddresses { response: tx }).await.map_err(|_| anyhow!("node stopped"))?;
+        rx.await.map_err(|_| anyhow!("node dropped the command"))?
+    })
+    .await
+    .map_err(|_| anyhow!("node loop didn't answer within {:?}", patience))?
+}
+
+/// Report READY once every node's swarm is listening and its startup warm-up is done, as a node only
+/// answers commands after that; the API must be bound before this is called
+pub async fn notify_when_ready(nodes: Vec<mpsc::Sender<NodeCommand>>) {
+    for commands in &nodes {
+        loop {
+            // A node that stopped takes the whole process down, so there's no giving up here
+            match ping(commands, READY_POLL_INTERVAL).await {
+                Ok(addresses) if !addresses.is_empty() => break,
+                _ => sleep(READY_POLL_INTERVAL).await,
+            }
+        }
+    }
+    info!("All {} nodes are listening, ready", nodes.len());
+    notify(&[sd_notify::NotifyState::Ready, sd_notify::NotifyState::Status("Serving")]);
+}
+
+/// Send watchdog keepalives while every node loop keeps answering, so systemd restarts a hung node
+pub async fn run_watchdog(period: Duration, nodes: Vec<mpsc::Sender<NodeCommand>>) {
+    let mut ticker = interval(period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let mut healthy = true;
+        for commands in &nodes {
+            if let Err(e) = ping(commands, period).await {
+                warn!("Withholding watchdog keepalive: {}", e);
+                healthy = false;
+                break;
+            }
+        }
+        if healthy {
+            notify(&[sd_notify::NotifyState::Watchdog]);
+        }
+    }
+}
+
+pub fn notify_stopping() {
+    notify(&[sd_notify::NotifyState::Stopping]);
+}
+
+/// Reopen the log file on every SIGHUP, so logrotate can move it away without restarting the node
+pub async fn reopen_log_on_sighup(log_file: Option<Arc<LogFile>>) -> Result<()> {
+    let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    while hangups.recv().await.is_some() {
+        match &log_file {
+            Some(log_file) => match log_file.reopen() {
+                Ok(()) => info!("Reopened log file {}", log_file.path().display()),
+                Err(e) => warn!("Failed to reopen log file {}: {}", log_file.path().display(), e),
+            },
+            None => info!("Received SIGHUP, logging to stdout so there is nothing to reopen"),
+        }
+    }
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,162 @@
+use crate::types::{Peer, TrustDataExport, TrustExperience};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// What changed between two exports, experiences matched by id and peers by peer_id
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExportDiff {
+    pub added_experiences: Vec<TrustExperience>,
+    pub removed_experiences: Vec<TrustExperience>,
+    pub changed_experiences: Vec<Change<TrustExperience>>,
+    pub added_peers: Vec<Peer>,
+    pub removed_peers: Vec<Peer>,
+    pub changed_peers: Vec<Change<Peer>>,
+}
+
+/// A record present in both exports with different contents
+#[derive(Debug, Clone, Serialize)]
+pub struct Change<T> {
+    /// Top-level fields whose values differ
+    pub fields: Vec<String>,
+    pub before: T,
+    pub after: T,
+}
+
+impl ExportDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_experiences.is_empty()
+            && self.removed_experiences.is_empty()
+            && self.changed_experiences.is_empty()
+            && self.added_peers.is_empty()
+            && self.removed_peers.is_empty()
+            && self.changed_peers.is_empty()
+    }
+}
+
+/// Compare `before` with `after`; records come out ordered by their key
+pub fn diff(before: &TrustDataExport, after: &TrustDataExport) -> ExportDiff {
+    let (added_experiences, removed_experiences, changed_experiences) = diff_records(
+        &before.experiences,
+        &after.experiences,
+        |experience| experience.id.to_string(),
+    );
+    let (added_peers, removed_peers, changed_peers) =
+        diff_records(&before.peers, &after.peers, |peer| peer.peer_id.clone());
+
+    ExportDiff {
+        added_experiences,
+        removed_experiences,
+        changed_experiences,
+        added_peers,
+        removed_peers,
+        changed_peers,
+    }
+}
+
+/// Read two export files, as written by GET /export, and compare them
+pub fn diff_files(before: &Path, after: &Path) -> Result<ExportDiff> {
+    Ok(diff(&read_export(before)?, &read_export(after)?))
+}
+
+fn read_export(path: &Path) -> Result<TrustDataExport> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&bytes).with_context(|| format!("Invalid export file {}", path.display()))
+}
+
+type RecordDiff<T> = (Vec<T>, Vec<T>, Vec<Change<T>>);
+
+fn diff_records<T: Clone + Serialize>(before: &[T], after: &[T], key: impl Fn(&T) -> String) -> RecordDiff<T> {
+    let before: BTreeMap<String, &T> = before.iter().map(|record| (key(record), record)).collect();
+    let after: BTreeMap<String, &T> = after.iter().map(|record| (key(record), record)).collect();
+
+    let added = after
+        .iter()
+        .filter(|(key, _)| !before.contains_key(*key))
+        .map(|(_, record)| (*record).clone())
+        .collect();
+    let removed = before
+        .iter()
+        .filter(|(key, _)| !after.contains_key(*key))
+        .map(|(_, record)| (*record).clone())
+        .collect();
+    let changed = before
+        .iter()
+        .filter_map(|(key, old)| {
+            let new = after.get(key)?;
+            let fields = changed_fields(*old, *new);
+            (!fields.is_empty()).then(|| Change {
+                fields,
+                before: (*old).clone(),
+                after: (*new).clone(),
+            })
+        })
+        .collect();
+
+    (added, removed, changed)
+}
+
+fn changed_fields<T: Serialize>(before: &T, after: &T) -> Vec<String> {
+    let object = |record: &T| match serde_json::to_value(record) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => serde_json::Map::new(),
+    };
+    let (before, after) = (object(before), object(after));
+
+    let mut fields: Vec<String> = before
+        .keys()
+        .chain(after.keys())
+        .filter(|field| before.get(*field) != after.get(*field))
+        .cloned()
+        .collect();
+    fields.sort();
+    fields.dedup();
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn experience(agent_id: &str, pv_roi: f64) -> TrustExperience {
+        TrustExperience {
+            id: Uuid::new_v4(),
+            id_domain: "test".to_string(),
+            agent_id: agent_id.to_string(),
+            pv_roi,
+            invested_volume: 100.0,
+            timestamp: Utc::now(),
+            notes: None,
+            data: None,
+        }
+    }
+
+    #[test]
+    fn test_diff_reports_added_removed_and_changed_records() {
+        let kept = experience("kept", 1.0);
+        let dropped = experience("dropped", 1.0);
+        let revised = experience("revised", 1.0);
+        let before = TrustDataExport::new(vec![kept.clone(), dropped.clone(), revised.clone()], Vec::new());
+
+        let added = experience("added", 1.2);
+        let revised_after = TrustExperience { pv_roi: 0.8, notes: Some("late delivery".to_string()), ..revised.clone() };
+        let after = TrustDataExport::new(vec![kept, revised_after, added.clone()], Vec::new());
+
+        let diff = diff(&before, &after);
+        assert_eq!(diff.added_experiences.iter().map(|e| e.id).collect::<Vec<_>>(), vec![added.id]);
+        assert_eq!(diff.removed_experiences.iter().map(|e| e.id).collect::<Vec<_>>(), vec![dropped.id]);
+        assert_eq!(diff.changed_experiences.len(), 1);
+        assert_eq!(diff.changed_experiences[0].before.id, revised.id);
+        assert_eq!(diff.changed_experiences[0].fields, vec!["notes", "pv_roi"]);
+        assert!(diff.added_peers.is_empty() && diff.removed_peers.is_empty() && diff.changed_peers.is_empty());
+    }
+
+    #[test]
+    fn test_identical_exports_have_an_empty_diff() {
+        let export = TrustDataExport::new(vec![experience("vendor", 1.1)], Vec::new());
+        assert!(diff(&export, &export.clone()).is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+use crate::clock::SharedClock;
+use crate::query_engine::QueryEngine;
+use crate::storage::Storage;
+use crate::types::TrustScore;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+/// Rater id of this node's own experiences
+pub const SELF_RATER: &str = "self";
+/// Share of each round that returns to the pre-trusted raters, as EigenTrust's `a`
+const PRETRUST_WEIGHT: f64 = 0.15;
+const MAX_ITERATIONS: usize = 100;
+/// Stop once no rater's trust moves by more than this in a round
+const CONVERGENCE: f64 = 1e-6;
+/// Agents read from storage per page when collecting own scores
+const OWN_SCORE_PAGE_SIZE: u32 = 1000;
+
+/// Outcome of the latest global aggregation, kept for query responses and `GET /v1/global-trust`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GlobalTrust {
+    pub enabled: bool,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub iterations: usize,
+    pub converged: bool,
+    /// Global trust of each rater, summing to 1; `self` stands for this node's own experiences
+    pub rater_trust: BTreeMap<String, f64>,
+    pub agents: usize,
+    #[serde(skip)]
+    scores: HashMap<(String, String), TrustScore>,
+}
+
+impl GlobalTrust {
+    pub fn score(&self, id_domain: &str, agent_id: &str) -> Option<TrustScore> {
+        self.scores.get(&(id_domain.to_string(), agent_id.to_string())).cloned()
+    }
+}
+
+/// Periodically recomputes the global aggregate over all locally known scores
+pub struct GlobalAggregator<S: Storage> {
+    storage: Arc<S>,
+    query_engine: Arc<QueryEngine<S>>,
+    interval: Duration,
+    report: Arc<RwLock<GlobalTrust>>,
+    clock: SharedClock,
+}
+
+impl<S: Storage + 'static> GlobalAggregator<S> {
+    pub fn new(
+        storage: Arc<S>,
+        query_engine: Arc<QueryEngine<S>>,
+        interval: Duration,
+        report: Arc<RwLock<GlobalTrust>>,
+        clock: SharedClock,
+    ) -> Self {
+        Self { storage, query_engine, interval, report, clock }
+    }
+
+    pub async fn run(self) {
+        self.report.write().unwrap().enabled = true;
+        let mut ticker = interval(self.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            let now = self.clock.now();
+            let result = compute(self.storage.as_ref(), &self.query_engine, now).await;
+            let mut report = self.report.write().unwrap();
+            match result {
+                Ok(global) => {
+                    info!(
+                        "Global trust over {} raters and {} agents after {} iterations",
+                        global.rater_trust.len(),
+                        global.agents,
+                        global.iterations
+                    );
+                    *report = GlobalTrust { enabled: true, last_run_at: Some(now), ..global };
+                }
+                Err(e) => {
+                    warn!("Global trust aggregation failed: {}", e);
+                    report.last_error = Some(e.to_string());
+                    report.last_run_at = Some(now);
+                }
+            }
+        }
+    }
+}
+
+/// What one rater reported about one agent
+struct Opinion {
+    rater: usize,
+    score: TrustScore,
+}
+
+/// EigenTrust over the raters this node knows: itself and every peer with cached recommendations.
+/// Raters trust each other by how much volume they agree on (both above or both below an ROI of 1.0);
+/// the power iteration starts from, and keeps returning to, the pre-trust given by recommender qualities.
+/// Our own opinions are scored by `query_engine` as trust queries score them.
+pub async fn compute<S: Storage>(storage: &S, query_engine: &QueryEngine<S>, now: DateTime<Utc>) -> Result<GlobalTrust> {
+    let mut raters: Vec<String> = vec![SELF_RATER.to_string()];
+    let mut rater_index: HashMap<String, usize> = HashMap::from([(SELF_RATER.to_string(), 0)]);
+    let mut opinions: BTreeMap<(String, String), Vec<Opinion>> = BTreeMap::new();
+
+    let mut after: Option<(String, String)> = None;
+    loop {
+        let page = query_engine
+            .calculate_trust_scores_page(now, after.as_ref().map(|(d, a)| (d.as_str(), a.as_str())), OWN_SCORE_PAGE_SIZE)
+            .await?;
+        let Some(last) = page.last() else { break };
+        after = Some((last.id_domain.clone(), last.agent_id.clone()));
+        for agent in page.into_iter().filter(|agent| agent.score.total_volume > 0.0) {
+            opinions.entry((agent.id_domain, agent.agent_id)).or_default().push(Opinion { rater: 0, score: agent.score });
+        }
+    }
+
+    for cached in storage.get_all_cached_scores().await? {
+        if cached.score.total_volume <= 0.0 {
+            continue;
+        }
+        let rater = *rater_index.entry(cached.from_peer.clone()).or_insert_with(|| {
+            raters.push(cached.from_peer.clone());
+            raters.len() - 1
+        });
+        opinions.entry((cached.id_domain, cached.agent_id)).or_default().push(Opinion { rater, score: cached.score });
+    }
+
+    // Pre-trust: full for ourselves, the recommender quality for peers we know
+    let mut pretrust = vec![0.0; raters.len()];
+    pretrust[0] = 1.0;
+    for peer in storage.get_peers().await? {
+        if let Some(&index) = rater_index.get(&peer.peer_id) {
+            pretrust[index] = peer.recommender_quality.max(0.0);
+        }
+    }
+    normalize(&mut pretrust);
+
+    let trust_matrix = local_trust(raters.len(), &opinions);
+    let (trust, iterations, converged) = power_iteration(&trust_matrix, &pretrust);
+
+    // Weigh raters relative to the most trusted one, so volumes stay comparable to personal scores
+    let max_trust = trust.iter().copied().fold(0.0, f64::max);
+    let scores = opinions
+        .into_iter()
+        .filter_map(|(agent, opinions)| {
+            let weighted: Vec<(TrustScore, f64)> = opinions
+                .into_iter()
+                .filter(|opinion| trust[opinion.rater] > 0.0)
+                .map(|opinion| (opinion.score, trust[opinion.rater] / max_trust))
+                .collect();
+            (!weighted.is_empty()).then(|| (agent, TrustScore::merge_multiple(weighted)))
+        })
+        .collect::<HashMap<_, _>>();
+
+    Ok(GlobalTrust {
+        enabled: true,
+        last_run_at: Some(now),
+        last_error: None,
+        iterations,
+        converged,
+        rater_trust: raters.into_iter().zip(trust).collect(),
+        agents: scores.len(),
+        scores,
+    })
+}
+
+/// Row-normalized trust of each rater in each other rater, from agreement on shared agents
+fn local_trust(raters: usize, opinions: &BTreeMap<(String, String), Vec<Opinion>>) -> Vec<Vec<f64>> {
+    let mut agreement = vec![vec![0.0; raters]; raters];
+    for opinions in opinions.values() {
+        for (i, a) in opinions.iter().enumerate() {
+            for b in &opinions[i + 1..] {
+                if a.rater == b.rater {
+                    continue;
+                }
+                let volume = a.score.total_volume.min(b.score.total_volume);
+                let same_side = (a.score.expected_pv_roi - 1.0) * (b.score.expected_pv_roi - 1.0) >= 0.0;
+                let delta = if same_side { volume } else { -volume };
+                agreement[a.rater][b.rater] += delta;
+                agreement[b.rater][a.rater] += delta;
+            }
+        }
+    }
+    for row in &mut agreement {
+        for value in row.iter_mut() {
+            *value = value.max(0.0);
+        }
+        normalize(row);
+    }
+    agreement
+}
+
+/// t ← (1 − a)·Cᵀt + a·p; raters who agree with nobody pass their trust on as pre-trust
+fn power_iteration(trust_matrix: &[Vec<f64>], pretrust: &[f64]) -> (Vec<f64>, usize, bool) {
+    let mut trust = pretrust.to_vec();
+    for iteration in 1..=MAX_ITERATIONS {
+        let mut next: Vec<f64> = pretrust.iter().map(|p| PRETRUST_WEIGHT * p).collect();
+        for (i, row) in trust_matrix.iter().enumerate() {
+            let share = (1.0 - PRETRUST_WEIGHT) * trust[i];
+            if row.iter().all(|c| *c == 0.0) {
+                for (j, p) in pretrust.iter().enumerate() {
+                    next[j] += share * p;
+                }
+            } else {
+                for (j, c) in row.iter().enumerate() {
+                    next[j] += share * c;
+                }
+            }
+        }
+        let change = trust.iter().zip(&next).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max);
+        trust = next;
+        if change < CONVERGENCE {
+            return (trust, iteration, true);
+        }
+    }
+    (trust, MAX_ITERATIONS, false)
+}
+
+fn normalize(values: &mut [f64]) {
+    let sum: f64 = values.iter().sum();
+    if sum > 0.0 {
+        for value in values.iter_mut() {
+            *value /= sum;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opinion(rater: usize, roi: f64, volume: f64) -> Opinion {
+        Opinion { rater, score: TrustScore::new(roi, volume, 1) }
+    }
+
+    #[test]
+    fn test_raters_agreeing_with_pretrusted_gain_trust() {
+        // Rater 1 agrees with us on both agents, rater 2 contradicts us, rater 3 only agrees with rater 2
+        let mut opinions = BTreeMap::new();
+        opinions.insert(
+            ("d".to_string(), "a".to_string()),
+            vec![opinion(0, 1.3, 100.0), opinion(1, 1.2, 100.0), opinion(2, 0.7, 100.0), opinion(3, 0.6, 100.0)],
+        );
+        opinions.insert(
+            ("d".to_string(), "b".to_string()),
+            vec![opinion(0, 0.8, 50.0), opinion(1, 0.9, 50.0), opinion(2, 1.4, 50.0), opinion(3, 1.5, 50.0)],
+        );
+
+        let matrix = local_trust(4, &opinions);
+        let (trust, _, converged) = power_iteration(&matrix, &[1.0, 0.0, 0.0, 0.0]);
+
+        assert!(converged);
+        assert!((trust.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        // Stationary point of t0 = 0.15 + 0.85·t1, t1 = 0.85·t0
+        assert!((trust[1] - 0.4595).abs() < 1e-3);
+        assert!(trust[2] < 1e-9);
+        assert!(trust[3] < 1e-9);
+    }
+
+    #[test]
+    fn test_pretrust_carries_raters_without_agreements() {
+        let matrix = vec![vec![0.0; 2]; 2];
+        let (trust, iterations, converged) = power_iteration(&matrix, &[0.5, 0.5]);
+
+        assert!(converged);
+        assert_eq!(iterations, 1);
+        assert!(trust.iter().all(|t| (t - 0.5).abs() < 1e-12));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,104 @@
+use crate::anomaly::Anomaly;
+use crate::types::{PeerRequest, ScoreChange};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use tokio::sync::broadcast;
+
+/// Events buffered per subscriber before slow subscribers start missing some
+const EVENT_BUFFER: usize = 256;
+/// Peer events kept for GET /peers/events once they've been broadcast
+pub const PEER_EVENT_HISTORY: usize = 500;
+
+/// Notifications pushed to GET /events subscribers
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NodeEvent {
+    Anomaly(Anomaly),
+    /// A watched agent's merged score crossed a threshold or moved by its min_delta
+    ScoreChanged(ScoreChange),
+    Peer(PeerEvent),
+    /// A peer outside the peer list queried us for the first time and waits in the peer requests
+    PeerRequest(PeerRequest),
+}
+
+impl NodeEvent {
+    /// Name used for the SSE `event:` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            NodeEvent::Anomaly(_) => "anomaly",
+            NodeEvent::ScoreChanged(_) => "score_changed",
+            NodeEvent::Peer(_) => "peer",
+            NodeEvent::PeerRequest(_) => "peer_request",
+        }
+    }
+}
+
+/// Steps on the way to talking to a peer, in the order they normally happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerEventKind {
+    /// Kademlia learned a new peer and its address
+    Discovered,
+    Dialed,
+    Connected,
+    /// The peer told us its agent version and protocols
+    Identified,
+    /// An invite was redeemed and the peer added us, or we added the peer that redeemed ours
+    Befriended,
+    /// The peer moved to a new key and keeps its place in our list; `reason` names the id it had before
+    Rotated,
+    Disconnected,
+    /// A dial or connection failed; `reason` says why
+    Failed,
+    /// The peer broke the protocol too often and is kept out for a while; `reason` names the last violation
+    Quarantined,
+    /// The peer's quarantine ended or was lifted
+    Released,
+}
+
+/// Something that happened with a peer connection, so failed connections can be explained without debug logs
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerEvent {
+    pub kind: PeerEventKind,
+    /// Unknown for dials to a bare address
+    pub peer_id: Option<String>,
+    pub address: Option<String>,
+    pub reason: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+/// The most recent peer events, oldest first
+#[derive(Debug)]
+pub struct PeerEventLog {
+    events: VecDeque<PeerEvent>,
+    capacity: usize,
+}
+
+impl PeerEventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self { events: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn push(&mut self, event: PeerEvent) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Kept events, only those about `peer_id` if given
+    pub fn recent(&self, peer_id: Option<&str>) -> Vec<PeerEvent> {
+        self.events
+            .iter()
+            .filter(|event| peer_id.is_none() || event.peer_id.as_deref() == peer_id)
+            .cloned()
+            .collect()
+    }
+}
+
+pub type EventSender = broadcast::Sender<NodeEvent>;
+
+pub fn event_channel() -> EventSender {
+    broadcast::channel(EVENT_BUFFER).0
+}
\ No newline at end of file
@@ -0,0 +1,315 @@
+use crate::query_engine::QueryEngine;
+use crate::storage::Storage;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Agents read from storage per page while building the graph
+const GRAPH_PAGE_SIZE: u32 = 500;
+/// Id of the node standing for the user whose database the graph is built from
+const ME: &str = "me";
+
+/// How `trust-node graph` writes the trust network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GraphFormat {
+    #[default]
+    Json,
+    /// Graphviz, e.g. `trust-node graph --format dot | dot -Tsvg`
+    Dot,
+    /// For Gephi, yEd, NetworkX and Cytoscape
+    Graphml,
+}
+
+impl std::str::FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(GraphFormat::Json),
+            "dot" => Ok(GraphFormat::Dot),
+            "graphml" => Ok(GraphFormat::Graphml),
+            other => Err(format!("unknown graph format {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GraphNodeKind {
+    Me,
+    Peer,
+    Agent,
+}
+
+impl GraphNodeKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            GraphNodeKind::Me => "me",
+            GraphNodeKind::Peer => "peer",
+            GraphNodeKind::Agent => "agent",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GraphEdgeKind {
+    /// Me to a peer, weighted by recommender quality
+    Trusts,
+    /// Me to an agent, from own experiences
+    Experienced,
+    /// A peer to an agent, from a cached recommendation
+    Recommends,
+}
+
+impl GraphEdgeKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            GraphEdgeKind::Trusts => "trusts",
+            GraphEdgeKind::Experienced => "experienced",
+            GraphEdgeKind::Recommends => "recommends",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphNode {
+    pub id: String,
+    pub kind: GraphNodeKind,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    pub kind: GraphEdgeKind,
+    /// Recommender quality for trusts edges, expected ROI otherwise
+    pub weight: f64,
+    /// Invested volume behind an ROI; None for trusts edges
+    pub volume: Option<f64>,
+}
+
+/// Who trusts whom and who thinks what of which agent, as seen from one user's database
+#[derive(Debug, Clone, Serialize)]
+pub struct TrustGraph {
+    pub generated_at: DateTime<Utc>,
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+fn peer_node_id(peer_id: &str) -> String {
+    format!("peer:{}", peer_id)
+}
+
+fn agent_node_id(id_domain: &str, agent_id: &str) -> String {
+    format!("agent:{}/{}", id_domain, agent_id)
+}
+
+/// Build the graph of `user`'s peers, own experiences and cached recommendations;
+/// own experiences are scored by `query_engine` as trust queries score them
+pub async fn build<S: Storage>(storage: &S, query_engine: &QueryEngine<S>, user: &str, now: DateTime<Utc>) -> Result<TrustGraph> {
+    // Ordered so the same database always renders the same file
+    let mut nodes = BTreeMap::new();
+    let mut edges = Vec::new();
+    nodes.insert(ME.to_string(), GraphNode { id: ME.to_string(), kind: GraphNodeKind::Me, label: user.to_string() });
+
+    for peer in storage.get_peers().await? {
+        let id = peer_node_id(&peer.peer_id);
+        edges.push(GraphEdge {
+            from: ME.to_string(),
+            to: id.clone(),
+            kind: GraphEdgeKind::Trusts,
+            weight: peer.recommender_quality,
+            volume: None,
+        });
+        nodes.insert(id.clone(), GraphNode { id, kind: GraphNodeKind::Peer, label: peer.name });
+    }
+
+    let mut after: Option<(String, String)> = None;
+    loop {
+        let page = query_engine
+            .calculate_trust_scores_page(now, after.as_ref().map(|(d, a)| (d.as_str(), a.as_str())), GRAPH_PAGE_SIZE)
+            .await?;
+        let Some(last) = page.last() else { break };
+        after = Some((last.id_domain.clone(), last.agent_id.clone()));
+        for agent in page {
+            let id = agent_node_id(&agent.id_domain, &agent.agent_id);
+            edges.push(GraphEdge {
+                from: ME.to_string(),
+                to: id.clone(),
+                kind: GraphEdgeKind::Experienced,
+                weight: agent.score.expected_pv_roi,
+                volume: Some(agent.score.total_volume),
+            });
+            let label = format!("{}/{}", agent.id_domain, agent.agent_id);
+            nodes.entry(id.clone()).or_insert(GraphNode { id, kind: GraphNodeKind::Agent, label });
+        }
+    }
+
+    for cached in storage.get_all_cached_scores().await? {
+        let from = peer_node_id(&cached.from_peer);
+        let to = agent_node_id(&cached.id_domain, &cached.agent_id);
+        // Recommendations from peers no longer in the list still show who said what
+        nodes.entry(from.clone()).or_insert_with(|| GraphNode {
+            id: from.clone(),
+            kind: GraphNodeKind::Peer,
+            label: cached.from_peer.clone(),
+        });
+        nodes.entry(to.clone()).or_insert_with(|| GraphNode {
+            id: to.clone(),
+            kind: GraphNodeKind::Agent,
+            label: format!("{}/{}", cached.id_domain, cached.agent_id),
+        });
+        edges.push(GraphEdge {
+            from,
+            to,
+            kind: GraphEdgeKind::Recommends,
+            weight: cached.score.expected_pv_roi,
+            volume: Some(cached.score.total_volume),
+        });
+    }
+
+    Ok(TrustGraph { generated_at: now, nodes: nodes.into_values().collect(), edges })
+}
+
+impl TrustGraph {
+    pub fn render(&self, format: GraphFormat) -> Result<String> {
+        Ok(match format {
+            GraphFormat::Json => serde_json::to_string_pretty(self)?,
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Graphml => self.to_graphml(),
+        })
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph trust {\n");
+        for node in &self.nodes {
+            let shape = match node.kind {
+                GraphNodeKind::Me => "doublecircle",
+                GraphNodeKind::Peer => "ellipse",
+                GraphNodeKind::Agent => "box",
+            };
+            let _ = writeln!(
+                dot,
+                "  \"{}\" [label=\"{}\", kind=\"{}\", shape={}];",
+                dot_escape(&node.id),
+                dot_escape(&node.label),
+                node.kind.as_str(),
+                shape
+            );
+        }
+        for edge in &self.edges {
+            let volume = edge.volume.map(|volume| format!(", volume={}", volume)).unwrap_or_default();
+            let _ = writeln!(
+                dot,
+                "  \"{}\" -> \"{}\" [label=\"{:.2}\", kind=\"{}\", weight={}{}];",
+                dot_escape(&edge.from),
+                dot_escape(&edge.to),
+                edge.weight,
+                edge.kind.as_str(),
+                edge.weight,
+                volume
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_graphml(&self) -> String {
+        let mut xml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n",
+            "  <key id=\"node_kind\" for=\"node\" attr.name=\"kind\" attr.type=\"string\"/>\n",
+            "  <key id=\"edge_kind\" for=\"edge\" attr.name=\"kind\" attr.type=\"string\"/>\n",
+            "  <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"double\"/>\n",
+            "  <key id=\"volume\" for=\"edge\" attr.name=\"volume\" attr.type=\"double\"/>\n",
+            "  <graph id=\"trust\" edgedefault=\"directed\">\n",
+        ));
+        for node in &self.nodes {
+            let _ = writeln!(
+                xml,
+                "    <node id=\"{}\"><data key=\"label\">{}</data><data key=\"node_kind\">{}</data></node>",
+                xml_escape(&node.id),
+                xml_escape(&node.label),
+                node.kind.as_str()
+            );
+        }
+        for edge in &self.edges {
+            let volume = edge
+                .volume
+                .map(|volume| format!("<data key=\"volume\">{}</data>", volume))
+                .unwrap_or_default();
+            let _ = writeln!(
+                xml,
+                "    <edge source=\"{}\" target=\"{}\"><data key=\"edge_kind\">{}</data><data key=\"weight\">{}</data>{}</edge>",
+                xml_escape(&edge.from),
+                xml_escape(&edge.to),
+                edge.kind.as_str(),
+                edge.weight,
+                volume
+            );
+        }
+        xml.push_str("  </graph>\n</graphml>\n");
+        xml
+    }
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> TrustGraph {
+        TrustGraph {
+            generated_at: Utc::now(),
+            nodes: vec![
+                GraphNode { id: ME.to_string(), kind: GraphNodeKind::Me, label: "alice".to_string() },
+                GraphNode { id: "peer:12D3".to_string(), kind: GraphNodeKind::Peer, label: "bob \"the\" <builder>".to_string() },
+                GraphNode { id: "agent:domain/a&b.example".to_string(), kind: GraphNodeKind::Agent, label: "domain/a&b.example".to_string() },
+            ],
+            edges: vec![
+                GraphEdge { from: ME.to_string(), to: "peer:12D3".to_string(), kind: GraphEdgeKind::Trusts, weight: 0.8, volume: None },
+                GraphEdge {
+                    from: "peer:12D3".to_string(),
+                    to: "agent:domain/a&b.example".to_string(),
+                    kind: GraphEdgeKind::Recommends,
+                    weight: 1.2,
+                    volume: Some(300.0),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_dot_output_escapes_labels() {
+        let dot = graph().to_dot();
+        assert!(dot.starts_with("digraph trust {"));
+        assert!(dot.contains(r#""peer:12D3" [label="bob \"the\" <builder>", kind="peer", shape=ellipse];"#));
+        assert!(dot.contains(r#""peer:12D3" -> "agent:domain/a&b.example" [label="1.20", kind="recommends", weight=1.2, volume=300];"#));
+    }
+
+    #[test]
+    fn test_graphml_output_escapes_markup() {
+        let xml = graph().to_graphml();
+        assert_eq!(xml.matches("<node ").count(), 3);
+        assert_eq!(xml.matches("<edge ").count(), 2);
+        assert!(xml.contains("bob &quot;the&quot; &lt;builder&gt;"));
+        assert!(xml.contains(r#"target="agent:domain/a&amp;b.example""#));
+        assert!(!xml.contains("a&b"));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::StreamProtocol;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Protocol a node uses to tell its peers it moved to a new key
+pub const ROTATION_PROTOCOL: StreamProtocol = StreamProtocol::new("/repeer/rotate/1.0.0");
+
+/// The node's key as stored in `path`, generating and saving one on first start
+pub fn load_or_create(path: &Path) -> Result<Keypair> {
+    if path.exists() {
+        let bytes = std::fs::read(path).with_context(|| format!("reading identity key {}", path.display()))?;
+        return Keypair::from_protobuf_encoding(&bytes).with_context(|| format!("decoding identity key {}", path.display()));
+    }
+    let key = Keypair::generate_ed25519();
+    save(path, &key)?;
+    Ok(key)
+}
+
+/// Replace the key in `path`
+pub fn save(path: &Path, key: &Keypair) -> Result<()> {
+    write_private(path, &key.to_protobuf_encoding()?)
+}
+
+/// Write a file only the current user can read, next to its destination first so a crash can't leave half of it behind
+pub fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&partial).with_context(|| format!("writing {}", partial.display()))?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// A transition statement that doesn't prove what it claims
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("invalid key transition: {0}")]
+pub struct InvalidTransition(pub String);
+
+/// A node's announcement that it moved from one key to another
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyTransition {
+    pub old_peer_id: String,
+    pub new_peer_id: String,
+    pub rotated_at: DateTime<Utc>,
+}
+
+impl KeyTransition {
+    /// `<payload>.<old key>.<old signature>.<new key>.<new signature>`, each base64url.
+    /// The old key vouches for the move, the new one shows the move isn't onto somebody else's id.
+    pub fn sign(&self, old_key: &Keypair, new_key: &Keypair) -> Result<String> {
+        let payload = serde_json::to_vec(self)?;
+        let old_signature = old_key.sign(&payload)?;
+        let new_signature = new_key.sign(&payload)?;
+        Ok([
+            payload,
+            old_key.public().encode_protobuf(),
+            old_signature,
+            new_key.public().encode_protobuf(),
+            new_signature,
+        ]
+        .iter()
+        .map(|part| URL_SAFE_NO_PAD.encode(part))
+        .collect::<Vec<_>>()
+        .join("."))
+    }
+
+    /// Decode a statement, checking that both keys signed it and that they are the ones it names
+    pub fn verify(statement: &str) -> Result<KeyTransition, InvalidTransition> {
+        let malformed = || InvalidTransition("malformed statement".to_string());
+        let parts = statement
+            .trim()
+            .split('.')
+            .map(|part| URL_SAFE_NO_PAD.decode(part))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| malformed())?;
+        let [payload, old_key, old_signature, new_key, new_signature] = parts.as_slice() else {
+            return Err(malformed());
+        };
+
+        let old_key = PublicKey::try_decode_protobuf(old_key).map_err(|_| malformed())?;
+        let new_key = PublicKey::try_decode_protobuf(new_key).map_err(|_| malformed())?;
+        if !old_key.verify(payload, old_signature) || !new_key.verify(payload, new_signature) {
+            return Err(InvalidTransition("bad signature".to_string()));
+        }
+        let transition: KeyTransition = serde_json::from_slice(payload).map_err(|_| malformed())?;
+        if old_key.to_peer_id().to_string() != transition.old_peer_id
+            || new_key.to_peer_id().to_string() != transition.new_peer_id
+        {
+            return Err(InvalidTransition("signed by keys other than the ones it names".to_string()));
+        }
+        Ok(transition)
+    }
+}
+
+/// Sent to each connected peer from the new identity right after a rotation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotationNotice {
+    pub statement: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotationAck {
+    /// Whether the peer had us listed and now has us under the new id
+    pub updated: bool,
+    pub reason: Option<String>,
+}
+
+/// Outcome of `POST /v1/identity/rotate`
+#[derive(Debug, Clone, Serialize)]
+pub struct IdentityRotation {
+    pub old_peer_id: String,
+    pub new_peer_id: String,
+    /// Peers that were connected and were sent the transition statement
+    pub notified: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transition(old_key: &Keypair, new_key: &Keypair) -> KeyTransition {
+        KeyTransition {
+            old_peer_id: old_key.public().to_peer_id().to_string(),
+            new_peer_id: new_key.public().to_peer_id().to_string(),
+            rotated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_transitions_need_both_keys() {
+        let old_key = Keypair::generate_ed25519();
+        let new_key = Keypair::generate_ed25519();
+        let statement = transition(&old_key, &new_key).sign(&old_key, &new_key).unwrap();
+        assert_eq!(KeyTransition::verify(&statement).unwrap().new_peer_id, new_key.public().to_peer_id().to_string());
+
+        // Mallory can't move our entry in someone's peer list over to her id
+        let mallory = Keypair::generate_ed25519();
+        let hijack = transition(&old_key, &mallory).sign(&mallory, &mallory).unwrap();
+        assert!(KeyTransition::verify(&hijack).is_err());
+
+        // Nor claim our old identity moved to a key she doesn't hold
+        let stranded = transition(&mallory, &new_key).sign(&mallory, &mallory).unwrap();
+        assert!(KeyTransition::verify(&stranded).is_err());
+        assert!(KeyTransition::verify("a.b.c").is_err());
+    }
+
+    #[test]
+    fn test_keys_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("alice.key");
+        let created = load_or_create(&path).unwrap();
+        let loaded = load_or_create(&path).unwrap();
+        assert_eq!(created.public(), loaded.public());
+
+        let rotated = Keypair::generate_ed25519();
+        save(&path, &rotated).unwrap();
+        assert_eq!(load_or_create(&path).unwrap().public(), rotated.public());
+    }
+}
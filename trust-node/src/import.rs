@@ -0,0 +1,226 @@
+use crate::archive::ArchiveManifest;
+use crate::types::{Peer, TrustExperience};
+use crate::validation::{Fields, InvalidInput};
+use serde::{Deserialize, Serialize};
+
+/// Records handed to the node per command during a streamed import
+pub const IMPORT_BATCH: usize = 1000;
+/// Longest NDJSON line accepted, so a body without newlines can't grow the buffer without bound
+pub const MAX_LINE_BYTES: usize = 1 << 20;
+
+/// One line of an NDJSON import or export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ImportRecord {
+    Experience(TrustExperience),
+    Peer(Peer),
+    /// Last line of an export archive
+    Manifest(ArchiveManifest),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportStreamError {
+    #[error("line {line}: {source}")]
+    Parse { line: usize, source: serde_json::Error },
+    #[error("line {0} is longer than {MAX_LINE_BYTES} bytes")]
+    LineTooLong(usize),
+    /// A record that parsed but fails validation, with its fields named `lines[<line>].<field>`
+    #[error(transparent)]
+    Invalid(#[from] InvalidInput),
+}
+
+#[derive(Debug, Default)]
+pub struct ImportBatch {
+    pub experiences: Vec<TrustExperience>,
+    pub peers: Vec<Peer>,
+}
+
+impl ImportBatch {
+    pub fn len(&self) -> usize {
+        self.experiences.len() + self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push(&mut self, record: ImportRecord) {
+        match record {
+            ImportRecord::Experience(experience) => self.experiences.push(experience),
+            ImportRecord::Peer(peer) => self.peers.push(peer),
+            // Already checked before an archive's records are imported
+            ImportRecord::Manifest(_) => {}
+        }
+    }
+}
+
+/// Splits a byte stream into lines as chunks arrive
+#[derive(Debug, Default)]
+pub struct LineSplitter {
+    /// Start of a line whose end hasn't arrived yet
+    partial: Vec<u8>,
+    line: usize,
+}
+
+impl LineSplitter {
+    /// Call `each` with the number and content of every line `chunk` completes
+    pub fn push<E>(&mut self, chunk: &[u8], mut each: impl FnMut(usize, &[u8]) -> Result<(), E>) -> Result<(), E>
+    where
+        E: From<ImportStreamError>,
+    {
+        let mut rest = chunk;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            self.line += 1;
+            if self.partial.is_empty() {
+                each(self.line, &rest[..end])?;
+            } else {
+                self.partial.extend_from_slice(&rest[..end]);
+                let buffered = std::mem::take(&mut self.partial);
+                each(self.line, &buffered)?;
+            }
+            rest = &rest[end + 1..];
+        }
+        if self.partial.len() + rest.len() > MAX_LINE_BYTES {
+            return Err(ImportStreamError::LineTooLong(self.line + 1).into());
+        }
+        self.partial.extend_from_slice(rest);
+        Ok(())
+    }
+
+    /// End of the stream: a last line without a newline is passed to `each` too
+    pub fn finish<E>(self, mut each: impl FnMut(usize, &[u8]) -> Result<(), E>) -> Result<(), E> {
+        if self.partial.is_empty() {
+            return Ok(());
+        }
+        each(self.line + 1, &self.partial)
+    }
+}
+
+/// Parses an NDJSON body chunk by chunk, handing out batches as they fill up
+#[derive(Debug, Default)]
+pub struct NdjsonImport {
+    lines: LineSplitter,
+    batch: ImportBatch,
+}
+
+impl NdjsonImport {
+    /// Feed the next chunk of the body; returns the batches it completed
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<ImportBatch>, ImportStreamError> {
+        let mut full = Vec::new();
+        let batch = &mut self.batch;
+        self.lines.push(chunk, |line, bytes| {
+            if let Some(record) = parse_line(line, bytes)? {
+                batch.push(record);
+                if batch.len() >= IMPORT_BATCH {
+                    full.push(std::mem::take(batch));
+                }
+            }
+            Ok::<_, ImportStreamError>(())
+        })?;
+        Ok(full)
+    }
+
+    /// End of the body: parse a last line without a newline and return what's left
+    pub fn finish(self) -> Result<ImportBatch, ImportStreamError> {
+        let mut batch = self.batch;
+        self.lines.finish(|line, bytes| {
+            if let Some(record) = parse_line(line, bytes)? {
+                batch.push(record);
+            }
+            Ok::<_, ImportStreamError>(())
+        })?;
+        Ok(batch)
+    }
+}
+
+fn parse_line(line: usize, bytes: &[u8]) -> Result<Option<ImportRecord>, ImportStreamError> {
+    if bytes.iter().all(u8::is_ascii_whitespace) {
+        return Ok(None);
+    }
+    let record: ImportRecord = serde_json::from_slice(bytes).map_err(|source| ImportStreamError::Parse { line, source })?;
+    let mut fields = Fields::default();
+    fields.nested(&format!("lines[{}].", line), &record);
+    fields.finish()?;
+    Ok(Some(record))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{PeerAddress, PeerContact};
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn experience_line(agent_id: &str) -> String {
+        let experience = TrustExperience {
+            id: Uuid::new_v4(),
+            id_domain: "test".to_string(),
+            agent_id: agent_id.to_string(),
+            pv_roi: 1.1,
+            invested_volume: 100.0,
+            timestamp: Utc::now(),
+            notes: None,
+            data: None,
+        };
+        serde_json::to_string(&ImportRecord::Experience(experience)).unwrap() + "\n"
+    }
+
+    #[test]
+    fn test_lines_split_across_chunks_are_parsed() {
+        let peer = Peer {
+            peer_id: "12D3KooWbob".to_string(),
+            addresses: vec![PeerAddress::new("/ip4/127.0.0.1/tcp/9001")],
+            handle: None,
+            name: "bob".to_string(),
+            recommender_quality: 0.8,
+            added_at: Utc::now(),
+            notes: None,
+            tags: Vec::new(),
+            contact: PeerContact::default(),
+            last_interaction_at: None,
+        };
+        let body = format!(
+            "{}\n{}{}",
+            serde_json::to_string(&ImportRecord::Peer(peer)).unwrap(),
+            experience_line("a"),
+            experience_line("b").trim_end(),
+        );
+
+        let mut import = NdjsonImport::default();
+        for chunk in body.as_bytes().chunks(7) {
+            assert!(import.push(chunk).unwrap().is_empty());
+        }
+        let batch = import.finish().unwrap();
+        assert_eq!(batch.peers.len(), 1);
+        assert_eq!(batch.experiences.iter().map(|e| e.agent_id.as_str()).collect::<Vec<_>>(), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_full_batches_are_handed_out_early() {
+        let body: String = (0..IMPORT_BATCH + 1).map(|i| experience_line(&i.to_string())).collect();
+        let mut import = NdjsonImport::default();
+        let full = import.push(body.as_bytes()).unwrap();
+        assert_eq!(full.len(), 1);
+        assert_eq!(full[0].len(), IMPORT_BATCH);
+        assert_eq!(import.finish().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_malformed_line_is_reported_by_number() {
+        let mut import = NdjsonImport::default();
+        let body = format!("{}\n{{\"kind\":\"experience\"}}\n", experience_line("a"));
+        match import.push(body.as_bytes()) {
+            Err(ImportStreamError::Parse { line, .. }) => assert_eq!(line, 3),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_invalid_record_is_reported_by_line() {
+        let mut import = NdjsonImport::default();
+        match import.push(experience_line(" ").as_bytes()) {
+            Err(ImportStreamError::Invalid(invalid)) => assert_eq!(invalid.errors[0].field, "lines[1].agent_id"),
+            other => panic!("expected invalid input, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// Queue of inbound work served round-robin by sender, so a burst from one peer can't starve the others
+pub struct FairQueue<K, T> {
+    queues: HashMap<K, VecDeque<T>>,
+    /// Senders with queued work, in the order they get their next turn
+    order: VecDeque<K>,
+    per_sender_limit: usize,
+}
+
+impl<K: Eq + Hash + Clone, T> FairQueue<K, T> {
+    pub fn new(per_sender_limit: usize) -> Self {
+        Self {
+            queues: HashMap::new(),
+            order: VecDeque::new(),
+            per_sender_limit,
+        }
+    }
+
+    /// Queue an item, handing it back if its sender already has the maximum waiting
+    pub fn push(&mut self, sender: K, item: T) -> Result<(), T> {
+        let queue = self.queues.entry(sender.clone()).or_default();
+        if queue.len() >= self.per_sender_limit {
+            return Err(item);
+        }
+        if queue.is_empty() {
+            self.order.push_back(sender);
+        }
+        queue.push_back(item);
+        Ok(())
+    }
+
+    /// Next item from the sender whose turn it is
+    pub fn pop(&mut self) -> Option<(K, T)> {
+        let sender = self.order.pop_front()?;
+        let queue = self.queues.get_mut(&sender)?;
+        let item = queue.pop_front()?;
+        if queue.is_empty() {
+            self.queues.remove(&sender);
+        } else {
+            self.order.push_back(sender.clone());
+        }
+        Some((sender, item))
+    }
+
+    /// Applies to later pushes; senders already over the new limit keep what they have queued
+    pub fn set_per_sender_limit(&mut self, per_sender_limit: usize) {
+        self.per_sender_limit = per_sender_limit;
+    }
+
+    pub fn per_sender_limit(&self) -> usize {
+        self.per_sender_limit
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_senders_take_turns() {
+        let mut queue = FairQueue::new(10);
+        for i in 0..3 {
+            queue.push("busy", i).unwrap();
+        }
+        queue.push("quiet", 100).unwrap();
+
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(order, vec![("busy", 0), ("quiet", 100), ("busy", 1), ("busy", 2)]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_per_sender_limit() {
+        let mut queue = FairQueue::new(2);
+        queue.push("peer", 1).unwrap();
+        queue.push("peer", 2).unwrap();
+        assert_eq!(queue.push("peer", 3), Err(3));
+        assert!(queue.push("other", 4).is_ok());
+        assert_eq!(queue.len(), 3);
+    }
+}
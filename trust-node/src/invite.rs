@@ -0,0 +1,168 @@
+use crate::types::PeerProposal;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::StreamProtocol;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Protocol the redeeming node uses to ask the inviter to add it back
+pub const INVITE_PROTOCOL: StreamProtocol = StreamProtocol::new("/repeer/invite/1.0.0");
+/// Protocol for asking a node we know the peer id of to add each other as peers, and for answering
+pub const PEERING_PROTOCOL: StreamProtocol = StreamProtocol::new("/repeer/peering/1.0.0");
+/// How long an invite can be redeemed when the request doesn't say
+pub const DEFAULT_INVITE_TTL: chrono::Duration = chrono::Duration::days(1);
+pub const MAX_INVITE_TTL: chrono::Duration = chrono::Duration::days(7);
+
+/// A token that can't be redeemed
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("invalid invite: {0}")]
+pub struct InvalidInvite(pub String);
+
+/// What an invite token carries: who issued it and where to reach them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Invite {
+    /// Checked by the inviter when the friend request arrives, so each invite adds one friend
+    pub id: Uuid,
+    pub peer_id: String,
+    pub addresses: Vec<String>,
+    /// How the inviter introduces themselves; the redeemer may pick another name
+    pub name: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Invite {
+    /// `<payload>.<public key>.<signature>`, each base64url; the signature covers the payload bytes
+    pub fn sign(&self, key: &Keypair) -> anyhow::Result<String> {
+        let payload = serde_json::to_vec(self)?;
+        let signature = key.sign(&payload)?;
+        Ok(format!(
+            "{}.{}.{}",
+            URL_SAFE_NO_PAD.encode(&payload),
+            URL_SAFE_NO_PAD.encode(key.public().encode_protobuf()),
+            URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+
+    /// Decode a token, checking that the key it names signed it, that the key is the inviter's and that it hasn't expired
+    pub fn verify(token: &str, now: DateTime<Utc>) -> Result<Invite, InvalidInvite> {
+        let malformed = || InvalidInvite("malformed token".to_string());
+        let mut parts = token.trim().split('.').map(|part| URL_SAFE_NO_PAD.decode(part));
+        let (Some(Ok(payload)), Some(Ok(public_key)), Some(Ok(signature)), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(malformed());
+        };
+
+        let public_key = PublicKey::try_decode_protobuf(&public_key).map_err(|_| malformed())?;
+        if !public_key.verify(&payload, &signature) {
+            return Err(InvalidInvite("bad signature".to_string()));
+        }
+        let invite: Invite = serde_json::from_slice(&payload).map_err(|_| malformed())?;
+        if public_key.to_peer_id().to_string() != invite.peer_id {
+            return Err(InvalidInvite("signed by someone other than the inviter".to_string()));
+        }
+        if invite.expires_at <= now {
+            return Err(InvalidInvite("expired".to_string()));
+        }
+        Ok(invite)
+    }
+}
+
+/// An invite as handed to the user, to pass on to a friend
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedInvite {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub addresses: Vec<String>,
+}
+
+/// Sent to the inviter once its invite is redeemed, so both sides end up with each other as peers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FriendRequest {
+    pub invite_id: Uuid,
+    /// How the redeemer introduces themselves
+    pub name: Option<String>,
+    pub addresses: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FriendResponse {
+    pub accepted: bool,
+    pub reason: Option<String>,
+}
+
+/// Sent either way over the peering protocol; the proposal waits in the recipient's peer requests until
+/// its user accepts or declines, which goes back as an answer on a new request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PeeringMessage {
+    Propose(PeerProposal),
+    Answer(PeeringAnswer),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeeringAnswer {
+    pub accepted: bool,
+    /// Where the answering node listens, so the proposer can dial it back
+    pub addresses: Vec<String>,
+    pub reason: Option<String>,
+}
+
+/// Only confirms receipt; the actual answer to a proposal can take days
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeeringAck {
+    pub received: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// Fixed, so expiry checks don't depend on when the tests run
+    fn issued_at() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 6, 1, 12, 0, 0).unwrap()
+    }
+
+    fn invite(key: &Keypair, expires_at: DateTime<Utc>) -> Invite {
+        Invite {
+            id: Uuid::new_v4(),
+            peer_id: key.public().to_peer_id().to_string(),
+            addresses: vec!["/ip4/10.0.0.1/tcp/9001".to_string()],
+            name: Some("alice".to_string()),
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn test_signed_invites_verify_until_they_expire() {
+        let key = Keypair::generate_ed25519();
+        let now = issued_at();
+        let issued = invite(&key, now + DEFAULT_INVITE_TTL);
+        let token = issued.sign(&key).unwrap();
+
+        assert_eq!(Invite::verify(&token, now).unwrap(), issued);
+        assert!(Invite::verify(&token, now + MAX_INVITE_TTL).is_err());
+        assert!(Invite::verify("not.a.token", now).is_err());
+    }
+
+    #[test]
+    fn test_tampered_or_foreign_invites_are_rejected() {
+        let key = Keypair::generate_ed25519();
+        let now = issued_at();
+        let token = invite(&key, now + DEFAULT_INVITE_TTL).sign(&key).unwrap();
+
+        // Pointing the invite at other addresses breaks the signature
+        let mut tampered = invite(&key, now + DEFAULT_INVITE_TTL);
+        tampered.addresses = vec!["/ip4/6.6.6.6/tcp/9001".to_string()];
+        let forged_payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&tampered).unwrap());
+        let rest = token.split_once('.').unwrap().1;
+        assert!(Invite::verify(&format!("{}.{}", forged_payload, rest), now).is_err());
+
+        // Someone else can't issue invites in our name
+        let mallory = Keypair::generate_ed25519();
+        let forged = invite(&key, now + DEFAULT_INVITE_TTL).sign(&mallory).unwrap();
+        assert!(Invite::verify(&forged, now).is_err());
+    }
+}
@@ -0,0 +1,814 @@
+use crate::clock::SharedClock;
+use crate::storage::{SqliteStorage, Storage, StorageError, StorageResult, QUALITY_SET};
+use crate::types::{
+    AgentClaim, AgentIdentifier, AgentQueries, CachedTrustScore, DomainDefaults, ExperienceFilter, ExperienceRevision, ExperienceTemplate,
+    IdempotentResponse, InflationIndex, MaintenanceReport, Peer, PeerProposal, PeeringAnswerOut, PeerRequest, PeerRequestStatus, PendingExperience, ProposedPeer, QualityRevision, QueryResultEntry, SharingPrecision,
+    StorageStats, TrustDataExport, TrustExperience, TrustScore, WatchedAgent,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Where a user's journal lives next to their database
+pub fn journal_path(data_dir: &Path, user: &str) -> PathBuf {
+    data_dir.join(format!("{}.journal", user))
+}
+
+/// A state change, as applied to storage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JournalEvent {
+    ExperienceAdded { experience: TrustExperience },
+    ExperienceUpdated { experience: TrustExperience, changed_by: Option<String> },
+    ExperienceRemoved { experience_id: String },
+    ExperiencesCleared,
+    PendingExperienceAdded { pending: PendingExperience },
+    ExperienceSettled { experience: TrustExperience },
+    PendingExperienceRemoved { experience_id: String },
+    TemplateAdded { template: ExperienceTemplate },
+    TemplateRemoved { template_id: String },
+    TemplateOccurred { template_id: String, occurrences: Vec<PendingExperience>, next_due_at: DateTime<Utc> },
+    PeerAdded { peer: Peer },
+    PeerUpdated { peer: Peer },
+    PeerQualityUpdated {
+        peer_id: String,
+        quality: f64,
+        /// Missing in journals written before qualities had a history
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    PeerRemoved { peer_id: String },
+    PeerRenamed { old_peer_id: String, new_peer_id: String },
+    PeersCleared,
+    ScoreCached { cached: CachedTrustScore },
+    /// Replayed against the same peers, so it folds the same recommendations again
+    ScoresCompacted { older_than: DateTime<Utc> },
+    DomainDefaultsSet { defaults: DomainDefaults },
+    DomainDefaultsRemoved { id_domain: String },
+    InflationIndexSet { index: InflationIndex },
+    InflationIndexRemoved { currency: String },
+    SharingPrecisionSet { precision: SharingPrecision },
+    SharingPrecisionRemoved { subject: String },
+    AgentClaimed { claim: AgentClaim },
+    AgentClaimVerified { id_domain: String, agent_id: String, proof_url: String, verified_at: DateTime<Utc> },
+    AgentClaimRemoved { id_domain: String, agent_id: String },
+    AgentWatched { watched: WatchedAgent },
+    AgentUnwatched { id_domain: String, agent_id: String },
+    PeerRequestRecorded { peer_id: String, seen_at: DateTime<Utc> },
+    PeerProposalRecorded { peer_id: String, proposal: PeerProposal, seen_at: DateTime<Utc> },
+    PeerRequestStatusSet { peer_id: String, status: PeerRequestStatus, set_at: DateTime<Utc> },
+    PeerRequestRemoved { peer_id: String },
+    ProposedPeerSet { proposed: ProposedPeer },
+    ProposedPeerRemoved { peer_id: String },
+    PeeringAnswerSet { answer: PeeringAnswerOut },
+    PeeringAnswerRemoved { peer_id: String },
+    /// Experiences and peers replaced by an export file
+    ExportRestored { data: TrustDataExport },
+    /// Experiences and peers an import replaced or added, applied together
+    RecordsImported {
+        replaced_experiences: Vec<String>,
+        experiences: Vec<TrustExperience>,
+        replaced_peers: Vec<String>,
+        peers: Vec<Peer>,
+    },
+    /// Experiences and peers a merge with another database added, updated or removed, applied together
+    RecordsMerged {
+        added: Vec<TrustExperience>,
+        updated: Vec<TrustExperience>,
+        removed: Vec<String>,
+        peers: Vec<Peer>,
+        changed_by: String,
+    },
+    /// Everything a snapshot restore brought in, since the snapshot itself may be gone by replay time
+    SnapshotRestored {
+        experiences: Vec<TrustExperience>,
+        peers: Vec<Peer>,
+        cached_scores: Vec<CachedTrustScore>,
+        domain_defaults: Vec<DomainDefaults>,
+    },
+}
+
+/// One line of the journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: JournalEvent,
+}
+
+/// Apply one journaled event to storage, the same call that produced it
+pub async fn apply<S: Storage + ?Sized>(storage: &S, event: JournalEvent) -> StorageResult<()> {
+    match event {
+        JournalEvent::ExperienceAdded { experience } => storage.add_experience(experience).await,
+        JournalEvent::ExperienceUpdated { experience, changed_by } => {
+            storage.update_experience(experience, changed_by.as_deref()).await
+        }
+        JournalEvent::ExperienceRemoved { experience_id } => storage.remove_experience(&experience_id).await,
+        JournalEvent::ExperiencesCleared => storage.clear_experiences().await,
+        JournalEvent::PendingExperienceAdded { pending } => storage.add_pending_experience(pending).await,
+        JournalEvent::ExperienceSettled { experience } => storage.settle_experience(experience).await,
+        JournalEvent::PendingExperienceRemoved { experience_id } => {
+            storage.remove_pending_experience(&experience_id).await
+        }
+        JournalEvent::TemplateAdded { template } => storage.add_experience_template(&template).await,
+        JournalEvent::TemplateRemoved { template_id } => storage.remove_experience_template(&template_id).await,
+        JournalEvent::TemplateOccurred { template_id, occurrences, next_due_at } => {
+            storage.record_template_occurrences(&template_id, occurrences, next_due_at).await
+        }
+        JournalEvent::PeerAdded { peer } => storage.add_peer(peer).await,
+        JournalEvent::PeerUpdated { peer } => storage.update_peer(&peer).await,
+        JournalEvent::PeerQualityUpdated { peer_id, quality, reason } => {
+            storage.update_peer_quality(&peer_id, quality, reason.as_deref().unwrap_or(QUALITY_SET)).await
+        }
+        JournalEvent::PeerRemoved { peer_id } => storage.remove_peer(&peer_id).await,
+        JournalEvent::PeerRenamed { old_peer_id, new_peer_id } => storage.rename_peer(&old_peer_id, &new_peer_id).await,
+        JournalEvent::PeersCleared => storage.clear_peers().await,
+        JournalEvent::ScoreCached { cached } => storage.cache_trust_score(cached).await,
+        JournalEvent::ScoresCompacted { older_than } => storage.compact_cached_scores(older_than).await.map(|_| ()),
+        JournalEvent::DomainDefaultsSet { defaults } => storage.set_domain_defaults(&defaults).await,
+        JournalEvent::DomainDefaultsRemoved { id_domain } => storage.remove_domain_defaults(&id_domain).await,
+        JournalEvent::InflationIndexSet { index } => storage.set_inflation_index(&index).await,
+        JournalEvent::InflationIndexRemoved { currency } => storage.remove_inflation_index(&currency).await,
+        JournalEvent::SharingPrecisionSet { precision } => storage.set_sharing_precision(&precision).await,
+        JournalEvent::SharingPrecisionRemoved { subject } => storage.remove_sharing_precision(&subject).await,
+        JournalEvent::AgentClaimed { claim } => storage.add_agent_claim(&claim).await,
+        JournalEvent::AgentClaimVerified { id_domain, agent_id, proof_url, verified_at } => {
+            storage.verify_agent_claim(&id_domain, &agent_id, &proof_url, verified_at).await
+        }
+        JournalEvent::AgentClaimRemoved { id_domain, agent_id } => storage.remove_agent_claim(&id_domain, &agent_id).await,
+        JournalEvent::AgentWatched { watched } => storage.watch_agent(&watched).await,
+        JournalEvent::AgentUnwatched { id_domain, agent_id } => storage.unwatch_agent(&id_domain, &agent_id).await,
+        JournalEvent::PeerRequestRecorded { peer_id, seen_at } => storage.record_peer_request(&peer_id, seen_at).await.map(|_| ()),
+        JournalEvent::PeerProposalRecorded { peer_id, proposal, seen_at } => {
+            storage.record_peer_proposal(&peer_id, &proposal, seen_at).await.map(|_| ())
+        }
+        JournalEvent::PeerRequestStatusSet { peer_id, status, set_at } => storage.set_peer_request_status(&peer_id, status, set_at).await,
+        JournalEvent::PeerRequestRemoved { peer_id } => storage.remove_peer_request(&peer_id).await,
+        JournalEvent::ProposedPeerSet { proposed } => storage.put_proposed_peer(&proposed).await,
+        JournalEvent::ProposedPeerRemoved { peer_id } => storage.remove_proposed_peer(&peer_id).await,
+        JournalEvent::PeeringAnswerSet { answer } => storage.put_peering_answer(&answer).await,
+        JournalEvent::PeeringAnswerRemoved { peer_id } => storage.remove_peering_answer(&peer_id).await,
+        JournalEvent::ExportRestored { data } => storage.restore_export(data).await,
+        JournalEvent::RecordsImported { replaced_experiences, experiences, replaced_peers, peers } => {
+            storage.import_records(&replaced_experiences, experiences, &replaced_peers, peers).await
+        }
+        JournalEvent::RecordsMerged { added, updated, removed, peers, changed_by } => {
+            storage.merge_records(added, updated, &removed, peers, &changed_by).await
+        }
+        JournalEvent::SnapshotRestored { experiences, peers, cached_scores, domain_defaults } => {
+            storage.restore_export(TrustDataExport::new(experiences, peers)).await?;
+            for defaults in &domain_defaults {
+                storage.set_domain_defaults(defaults).await?;
+            }
+            for cached in cached_scores {
+                storage.cache_trust_score(cached).await?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReplaySummary {
+    pub journal: PathBuf,
+    pub applied: usize,
+    /// Events after `until` that were left out
+    pub skipped: usize,
+    pub last_seq: Option<u64>,
+    pub last_at: Option<DateTime<Utc>>,
+    /// Bytes of the journal covered by the applied events
+    #[serde(skip)]
+    pub applied_bytes: u64,
+}
+
+/// Apply the journal's events in order, stopping after the last one at or before `until`
+pub async fn replay<S: Storage + ?Sized>(
+    storage: &S,
+    journal: &Path,
+    until: Option<DateTime<Utc>>,
+) -> Result<ReplaySummary> {
+    let mut summary = ReplaySummary {
+        journal: journal.to_path_buf(),
+        ..ReplaySummary::default()
+    };
+    let mut lines = BufReader::new(std::fs::File::open(journal)?).lines().peekable();
+    let mut offset = 0u64;
+    while let Some(line) = lines.next() {
+        let line = line?;
+        offset += line.len() as u64 + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: JournalEntry = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            // A crash mid-append leaves a torn last line; everything before it is intact
+            Err(e) if lines.peek().is_none() => {
+                warn!("Ignoring incomplete last journal line: {}", e);
+                break;
+            }
+            Err(e) => return Err(anyhow!("Invalid journal entry after seq {:?}: {}", summary.last_seq, e)),
+        };
+        // Entries are appended in order, so everything from here on is later too
+        if until.is_some_and(|until| entry.at > until) {
+            summary.skipped = 1 + lines.by_ref().map_while(|line| line.ok()).filter(|line| !line.trim().is_empty()).count();
+            break;
+        }
+        summary.last_seq = Some(entry.seq);
+        summary.last_at = Some(entry.at);
+        apply(storage, entry.event).await?;
+        summary.applied += 1;
+        summary.applied_bytes = offset;
+    }
+    Ok(summary)
+}
+
+/// Rebuild `storage` from the journal through a scratch database, so a failed replay leaves it untouched;
+/// with `until`, the journal is cut back to the replayed events and the full one kept as *.before-replay
+pub async fn rebuild(
+    storage: &SqliteStorage,
+    journal: &Path,
+    scratch: &Path,
+    until: Option<DateTime<Utc>>,
+) -> Result<ReplaySummary> {
+    remove_database(scratch)?;
+    let summary = {
+        let rebuilt = SqliteStorage::new(scratch).await?;
+        replay(&rebuilt, journal, until).await?
+    };
+    storage.restore_snapshot(scratch).await?;
+    remove_database(scratch)?;
+
+    if summary.skipped > 0 {
+        let mut original = journal.as_os_str().to_owned();
+        original.push(".before-replay");
+        std::fs::copy(journal, &original)?;
+        // The cut journal replaces the full one in a single rename, so a crash leaves one or the other
+        let mut partial = journal.as_os_str().to_owned();
+        partial.push(".partial");
+        std::fs::copy(journal, &partial)?;
+        let file = std::fs::OpenOptions::new().write(true).open(&partial)?;
+        file.set_len(summary.applied_bytes)?;
+        file.sync_all()?;
+        std::fs::rename(&partial, journal)?;
+        info!("Cut journal back to seq {:?}, the full journal is in {}", summary.last_seq, Path::new(&original).display());
+    }
+    Ok(summary)
+}
+
+pub(crate) fn remove_database(path: &Path) -> std::io::Result<()> {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        match std::fs::remove_file(&file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+struct Journal {
+    file: tokio::fs::File,
+    next_seq: u64,
+}
+
+impl Journal {
+    async fn open(path: &Path) -> Result<Self> {
+        // Continue numbering after the last complete entry
+        let next_seq = match std::fs::File::open(path) {
+            Ok(file) => BufReader::new(file)
+                .lines()
+                .map_while(|line| line.ok())
+                .filter_map(|line| serde_json::from_str::<JournalEntry>(&line).ok())
+                .last()
+                .map_or(1, |entry| entry.seq + 1),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 1,
+            Err(e) => return Err(e.into()),
+        };
+        let file = tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+        Ok(Self { file, next_seq })
+    }
+
+    async fn append(&mut self, events: Vec<JournalEvent>, at: DateTime<Utc>) -> StorageResult<()> {
+        let mut lines = Vec::new();
+        for event in events {
+            let entry = JournalEntry { seq: self.next_seq, at, event };
+            serde_json::to_writer(&mut lines, &entry).map_err(|e| StorageError::Io(e.to_string()))?;
+            lines.push(b'\n');
+            self.next_seq += 1;
+        }
+        self.file.write_all(&lines).await?;
+        self.file.flush().await?;
+        Ok(())
+    }
+}
+
+/// Storage wrapper appending every state change to a journal after the inner storage accepted it;
+/// without a journal it only passes calls through
+pub struct JournaledStorage<S: Storage> {
+    inner: S,
+    journal: Option<Mutex<Journal>>,
+    clock: SharedClock,
+}
+
+impl<S: Storage> JournaledStorage<S> {
+    pub async fn open(inner: S, path: Option<&Path>, clock: SharedClock) -> Result<Self> {
+        let journal = match path {
+            Some(path) => Some(Mutex::new(Journal::open(path).await?)),
+            None => None,
+        };
+        Ok(Self { inner, journal, clock })
+    }
+
+    /// Run a change and journal it; the lock keeps journal order equal to the order changes were applied
+    async fn journaled<T>(
+        &self,
+        change: impl Future<Output = StorageResult<T>>,
+        events: impl FnOnce() -> Vec<JournalEvent>,
+    ) -> StorageResult<T> {
+        let Some(journal) = &self.journal else {
+            return change.await;
+        };
+        let mut journal = journal.lock().await;
+        let result = change.await?;
+        journal.append(events(), self.clock.now()).await?;
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl<S: Storage> Storage for JournaledStorage<S> {
+    async fn add_experience(&self, experience: TrustExperience) -> StorageResult<()> {
+        let event = JournalEvent::ExperienceAdded { experience: experience.clone() };
+        self.journaled(self.inner.add_experience(experience), || vec![event]).await
+    }
+
+    async fn add_experiences(&self, experiences: Vec<TrustExperience>) -> StorageResult<()> {
+        let events = experiences.iter().cloned().map(|experience| JournalEvent::ExperienceAdded { experience }).collect();
+        self.journaled(self.inner.add_experiences(experiences), || events).await
+    }
+
+    async fn get_experience(&self, experience_id: &str) -> StorageResult<Option<TrustExperience>> {
+        self.inner.get_experience(experience_id).await
+    }
+
+    async fn get_experiences(&self, id_domain: &str, agent_id: &str) -> StorageResult<Vec<TrustExperience>> {
+        self.inner.get_experiences(id_domain, agent_id).await
+    }
+
+    async fn get_all_experiences(&self) -> StorageResult<Vec<TrustExperience>> {
+        self.inner.get_all_experiences().await
+    }
+
+    fn stream_experiences(&self) -> BoxStream<'static, StorageResult<TrustExperience>> {
+        self.inner.stream_experiences()
+    }
+
+    async fn find_experiences(&self, filter: &ExperienceFilter) -> StorageResult<Vec<TrustExperience>> {
+        self.inner.find_experiences(filter).await
+    }
+
+    async fn count_experiences(&self, filter: &ExperienceFilter) -> StorageResult<u64> {
+        self.inner.count_experiences(filter).await
+    }
+
+    async fn update_experience(&self, experience: TrustExperience, changed_by: Option<&str>) -> StorageResult<()> {
+        let event = JournalEvent::ExperienceUpdated {
+            experience: experience.clone(),
+            changed_by: changed_by.map(str::to_string),
+        };
+        self.journaled(self.inner.update_experience(experience, changed_by), || vec![event]).await
+    }
+
+    async fn remove_experience(&self, experience_id: &str) -> StorageResult<()> {
+        self.journaled(self.inner.remove_experience(experience_id), || {
+            vec![JournalEvent::ExperienceRemoved { experience_id: experience_id.to_string() }]
+        })
+        .await
+    }
+
+    async fn add_pending_experience(&self, pending: PendingExperience) -> StorageResult<()> {
+        let event = JournalEvent::PendingExperienceAdded { pending: pending.clone() };
+        self.journaled(self.inner.add_pending_experience(pending), || vec![event]).await
+    }
+
+    async fn get_pending_experiences(&self) -> StorageResult<Vec<PendingExperience>> {
+        self.inner.get_pending_experiences().await
+    }
+
+    async fn settle_experience(&self, experience: TrustExperience) -> StorageResult<()> {
+        let event = JournalEvent::ExperienceSettled { experience: experience.clone() };
+        self.journaled(self.inner.settle_experience(experience), || vec![event]).await
+    }
+
+    async fn remove_pending_experience(&self, experience_id: &str) -> StorageResult<()> {
+        self.journaled(self.inner.remove_pending_experience(experience_id), || {
+            vec![JournalEvent::PendingExperienceRemoved { experience_id: experience_id.to_string() }]
+        })
+        .await
+    }
+
+    async fn add_experience_template(&self, template: &ExperienceTemplate) -> StorageResult<()> {
+        self.journaled(self.inner.add_experience_template(template), || {
+            vec![JournalEvent::TemplateAdded { template: template.clone() }]
+        })
+        .await
+    }
+
+    async fn get_experience_templates(&self) -> StorageResult<Vec<ExperienceTemplate>> {
+        self.inner.get_experience_templates().await
+    }
+
+    async fn remove_experience_template(&self, template_id: &str) -> StorageResult<()> {
+        self.journaled(self.inner.remove_experience_template(template_id), || {
+            vec![JournalEvent::TemplateRemoved { template_id: template_id.to_string() }]
+        })
+        .await
+    }
+
+    async fn record_template_occurrences(
+        &self,
+        template_id: &str,
+        occurrences: Vec<PendingExperience>,
+        next_due_at: DateTime<Utc>,
+    ) -> StorageResult<()> {
+        let event = JournalEvent::TemplateOccurred {
+            template_id: template_id.to_string(),
+            occurrences: occurrences.clone(),
+            next_due_at,
+        };
+        self.journaled(self.inner.record_template_occurrences(template_id, occurrences, next_due_at), || vec![event]).await
+    }
+
+    async fn get_experience_history(&self, experience_id: &str) -> StorageResult<Vec<ExperienceRevision>> {
+        self.inner.get_experience_history(experience_id).await
+    }
+
+    async fn get_experience_deletions(&self) -> StorageResult<Vec<ExperienceRevision>> {
+        self.inner.get_experience_deletions().await
+    }
+
+    async fn get_experienced_agents(&self) -> StorageResult<Vec<AgentIdentifier>> {
+        self.inner.get_experienced_agents().await
+    }
+
+    async fn get_experiences_page(&self, after: Option<(&str, &str)>, limit: u32) -> StorageResult<Vec<TrustExperience>> {
+        self.inner.get_experiences_page(after, limit).await
+    }
+
+    async fn get_agent_aggregate(&self, id_domain: &str, agent_id: &str) -> StorageResult<Option<TrustScore>> {
+        self.inner.get_agent_aggregate(id_domain, agent_id).await
+    }
+
+    async fn add_peer(&self, peer: Peer) -> StorageResult<()> {
+        let event = JournalEvent::PeerAdded { peer: peer.clone() };
+        self.journaled(self.inner.add_peer(peer), || vec![event]).await
+    }
+
+    async fn add_peers(&self, peers: Vec<Peer>) -> StorageResult<()> {
+        let events = peers.iter().cloned().map(|peer| JournalEvent::PeerAdded { peer }).collect();
+        self.journaled(self.inner.add_peers(peers), || events).await
+    }
+
+    async fn get_peers(&self) -> StorageResult<Vec<Peer>> {
+        self.inner.get_peers().await
+    }
+
+    async fn update_peer(&self, peer: &Peer) -> StorageResult<()> {
+        self.journaled(self.inner.update_peer(peer), || vec![JournalEvent::PeerUpdated { peer: peer.clone() }]).await
+    }
+
+    async fn update_peer_quality(&self, peer_id: &str, quality: f64, reason: &str) -> StorageResult<()> {
+        self.journaled(self.inner.update_peer_quality(peer_id, quality, reason), || {
+            vec![JournalEvent::PeerQualityUpdated {
+                peer_id: peer_id.to_string(),
+                quality,
+                reason: Some(reason.to_string()),
+            }]
+        })
+        .await
+    }
+
+    async fn get_quality_history(&self, peer_id: &str) -> StorageResult<Vec<QualityRevision>> {
+        self.inner.get_quality_history(peer_id).await
+    }
+
+    async fn remove_peer(&self, peer_id: &str) -> StorageResult<()> {
+        self.journaled(self.inner.remove_peer(peer_id), || {
+            vec![JournalEvent::PeerRemoved { peer_id: peer_id.to_string() }]
+        })
+        .await
+    }
+
+    async fn rename_peer(&self, old_peer_id: &str, new_peer_id: &str) -> StorageResult<()> {
+        self.journaled(self.inner.rename_peer(old_peer_id, new_peer_id), || {
+            vec![JournalEvent::PeerRenamed { old_peer_id: old_peer_id.to_string(), new_peer_id: new_peer_id.to_string() }]
+        })
+        .await
+    }
+
+    async fn clear_peers(&self) -> StorageResult<()> {
+        self.journaled(self.inner.clear_peers(), || vec![JournalEvent::PeersCleared]).await
+    }
+
+    async fn clear_experiences(&self) -> StorageResult<()> {
+        self.journaled(self.inner.clear_experiences(), || vec![JournalEvent::ExperiencesCleared]).await
+    }
+
+    async fn cache_trust_score(&self, cached: CachedTrustScore) -> StorageResult<()> {
+        let event = JournalEvent::ScoreCached { cached: cached.clone() };
+        self.journaled(self.inner.cache_trust_score(cached), || vec![event]).await
+    }
+
+    async fn get_cached_scores(&self, id_domain: &str, agent_id: &str) -> StorageResult<Vec<CachedTrustScore>> {
+        self.inner.get_cached_scores(id_domain, agent_id).await
+    }
+
+    async fn get_all_cached_scores(&self) -> StorageResult<Vec<CachedTrustScore>> {
+        self.inner.get_all_cached_scores().await
+    }
+
+    async fn compact_cached_scores(&self, older_than: DateTime<Utc>) -> StorageResult<u64> {
+        let event = JournalEvent::ScoresCompacted { older_than };
+        self.journaled(self.inner.compact_cached_scores(older_than), || vec![event]).await
+    }
+
+    // Query results are derived from the journaled state and rebuilt on demand, so they aren't journaled
+
+    async fn get_query_result(&self, cache_key: &str) -> StorageResult<Option<QueryResultEntry>> {
+        self.inner.get_query_result(cache_key).await
+    }
+
+    async fn put_query_result(&self, entry: &QueryResultEntry) -> StorageResult<()> {
+        self.inner.put_query_result(entry).await
+    }
+
+    async fn invalidate_query_results(&self, id_domain: &str, agent_id: &str) -> StorageResult<()> {
+        self.inner.invalidate_query_results(id_domain, agent_id).await
+    }
+
+    async fn purge_query_results(&self, older_than: Option<DateTime<Utc>>) -> StorageResult<u64> {
+        self.inner.purge_query_results(older_than).await
+    }
+
+    async fn record_agent_queries(&self, queries: &[AgentQueries]) -> StorageResult<()> {
+        self.inner.record_agent_queries(queries).await
+    }
+
+    async fn get_hot_agents(&self, limit: usize, now: DateTime<Utc>) -> StorageResult<Vec<AgentIdentifier>> {
+        self.inner.get_hot_agents(limit, now).await
+    }
+
+    async fn get_idempotent_response(&self, endpoint: &str, key: &str, now: DateTime<Utc>) -> StorageResult<Option<IdempotentResponse>> {
+        self.inner.get_idempotent_response(endpoint, key, now).await
+    }
+
+    async fn put_idempotent_response(&self, response: &IdempotentResponse, expires_at: DateTime<Utc>) -> StorageResult<()> {
+        self.inner.put_idempotent_response(response, expires_at).await
+    }
+
+    async fn purge_idempotent_responses(&self, now: DateTime<Utc>) -> StorageResult<u64> {
+        self.inner.purge_idempotent_responses(now).await
+    }
+
+    async fn get_domain_defaults(&self) -> StorageResult<Vec<DomainDefaults>> {
+        self.inner.get_domain_defaults().await
+    }
+
+    async fn set_domain_defaults(&self, defaults: &DomainDefaults) -> StorageResult<()> {
+        self.journaled(self.inner.set_domain_defaults(defaults), || {
+            vec![JournalEvent::DomainDefaultsSet { defaults: defaults.clone() }]
+        })
+        .await
+    }
+
+    async fn remove_domain_defaults(&self, id_domain: &str) -> StorageResult<()> {
+        self.journaled(self.inner.remove_domain_defaults(id_domain), || {
+            vec![JournalEvent::DomainDefaultsRemoved { id_domain: id_domain.to_string() }]
+        })
+        .await
+    }
+
+    async fn get_inflation_indexes(&self) -> StorageResult<Vec<InflationIndex>> {
+        self.inner.get_inflation_indexes().await
+    }
+
+    async fn set_inflation_index(&self, index: &InflationIndex) -> StorageResult<()> {
+        self.journaled(self.inner.set_inflation_index(index), || {
+            vec![JournalEvent::InflationIndexSet { index: index.clone() }]
+        })
+        .await
+    }
+
+    async fn remove_inflation_index(&self, currency: &str) -> StorageResult<()> {
+        self.journaled(self.inner.remove_inflation_index(currency), || {
+            vec![JournalEvent::InflationIndexRemoved { currency: currency.to_string() }]
+        })
+        .await
+    }
+
+    async fn get_sharing_precision(&self) -> StorageResult<Vec<SharingPrecision>> {
+        self.inner.get_sharing_precision().await
+    }
+
+    async fn set_sharing_precision(&self, precision: &SharingPrecision) -> StorageResult<()> {
+        self.journaled(self.inner.set_sharing_precision(precision), || {
+            vec![JournalEvent::SharingPrecisionSet { precision: precision.clone() }]
+        })
+        .await
+    }
+
+    async fn remove_sharing_precision(&self, subject: &str) -> StorageResult<()> {
+        self.journaled(self.inner.remove_sharing_precision(subject), || {
+            vec![JournalEvent::SharingPrecisionRemoved { subject: subject.to_string() }]
+        })
+        .await
+    }
+
+    async fn get_agent_claims(&self) -> StorageResult<Vec<AgentClaim>> {
+        self.inner.get_agent_claims().await
+    }
+
+    async fn add_agent_claim(&self, claim: &AgentClaim) -> StorageResult<()> {
+        self.journaled(self.inner.add_agent_claim(claim), || vec![JournalEvent::AgentClaimed { claim: claim.clone() }])
+            .await
+    }
+
+    async fn verify_agent_claim(&self, id_domain: &str, agent_id: &str, proof_url: &str, at: DateTime<Utc>) -> StorageResult<()> {
+        self.journaled(self.inner.verify_agent_claim(id_domain, agent_id, proof_url, at), || {
+            vec![JournalEvent::AgentClaimVerified {
+                id_domain: id_domain.to_string(),
+                agent_id: agent_id.to_string(),
+                proof_url: proof_url.to_string(),
+                verified_at: at,
+            }]
+        })
+        .await
+    }
+
+    async fn remove_agent_claim(&self, id_domain: &str, agent_id: &str) -> StorageResult<()> {
+        self.journaled(self.inner.remove_agent_claim(id_domain, agent_id), || {
+            vec![JournalEvent::AgentClaimRemoved { id_domain: id_domain.to_string(), agent_id: agent_id.to_string() }]
+        })
+        .await
+    }
+
+    async fn get_watchlist(&self) -> StorageResult<Vec<WatchedAgent>> {
+        self.inner.get_watchlist().await
+    }
+
+    async fn watch_agent(&self, watched: &WatchedAgent) -> StorageResult<()> {
+        self.journaled(self.inner.watch_agent(watched), || {
+            vec![JournalEvent::AgentWatched { watched: watched.clone() }]
+        })
+        .await
+    }
+
+    async fn unwatch_agent(&self, id_domain: &str, agent_id: &str) -> StorageResult<()> {
+        self.journaled(self.inner.unwatch_agent(id_domain, agent_id), || {
+            vec![JournalEvent::AgentUnwatched { id_domain: id_domain.to_string(), agent_id: agent_id.to_string() }]
+        })
+        .await
+    }
+
+    async fn set_watched_score(&self, id_domain: &str, agent_id: &str, score: &TrustScore) -> StorageResult<()> {
+        self.inner.set_watched_score(id_domain, agent_id, score).await
+    }
+
+    async fn get_peer_requests(&self) -> StorageResult<Vec<PeerRequest>> {
+        self.inner.get_peer_requests().await
+    }
+
+    async fn record_peer_request(&self, peer_id: &str, at: DateTime<Utc>) -> StorageResult<PeerRequest> {
+        self.journaled(self.inner.record_peer_request(peer_id, at), || {
+            vec![JournalEvent::PeerRequestRecorded { peer_id: peer_id.to_string(), seen_at: at }]
+        }).await
+    }
+
+    async fn record_peer_proposal(&self, peer_id: &str, proposal: &PeerProposal, at: DateTime<Utc>) -> StorageResult<PeerRequest> {
+        self.journaled(self.inner.record_peer_proposal(peer_id, proposal, at), || {
+            vec![JournalEvent::PeerProposalRecorded { peer_id: peer_id.to_string(), proposal: proposal.clone(), seen_at: at }]
+        }).await
+    }
+
+    async fn set_peer_request_status(&self, peer_id: &str, status: PeerRequestStatus, at: DateTime<Utc>) -> StorageResult<()> {
+        self.journaled(self.inner.set_peer_request_status(peer_id, status, at), || {
+            vec![JournalEvent::PeerRequestStatusSet { peer_id: peer_id.to_string(), status, set_at: at }]
+        })
+        .await
+    }
+
+    async fn remove_peer_request(&self, peer_id: &str) -> StorageResult<()> {
+        self.journaled(self.inner.remove_peer_request(peer_id), || {
+            vec![JournalEvent::PeerRequestRemoved { peer_id: peer_id.to_string() }]
+        })
+        .await
+    }
+
+    async fn get_proposed_peers(&self) -> StorageResult<Vec<ProposedPeer>> {
+        self.inner.get_proposed_peers().await
+    }
+
+    async fn put_proposed_peer(&self, proposed: &ProposedPeer) -> StorageResult<()> {
+        self.journaled(self.inner.put_proposed_peer(proposed), || vec![JournalEvent::ProposedPeerSet { proposed: proposed.clone() }])
+            .await
+    }
+
+    async fn remove_proposed_peer(&self, peer_id: &str) -> StorageResult<()> {
+        self.journaled(self.inner.remove_proposed_peer(peer_id), || {
+            vec![JournalEvent::ProposedPeerRemoved { peer_id: peer_id.to_string() }]
+        })
+        .await
+    }
+
+    async fn get_peering_answers(&self) -> StorageResult<Vec<PeeringAnswerOut>> {
+        self.inner.get_peering_answers().await
+    }
+
+    async fn put_peering_answer(&self, answer: &PeeringAnswerOut) -> StorageResult<()> {
+        self.journaled(self.inner.put_peering_answer(answer), || vec![JournalEvent::PeeringAnswerSet { answer: answer.clone() }])
+            .await
+    }
+
+    async fn remove_peering_answer(&self, peer_id: &str) -> StorageResult<()> {
+        self.journaled(self.inner.remove_peering_answer(peer_id), || {
+            vec![JournalEvent::PeeringAnswerRemoved { peer_id: peer_id.to_string() }]
+        })
+        .await
+    }
+
+    async fn storage_stats(&self) -> StorageResult<StorageStats> {
+        self.inner.storage_stats().await
+    }
+
+    async fn maintain(&self) -> StorageResult<MaintenanceReport> {
+        self.inner.maintain().await
+    }
+
+    async fn backup_to(&self, path: &Path) -> StorageResult<()> {
+        self.inner.backup_to(path).await
+    }
+
+    async fn restore_snapshot(&self, path: &Path) -> StorageResult<()> {
+        let Some(journal) = &self.journal else {
+            return self.inner.restore_snapshot(path).await;
+        };
+        let mut journal = journal.lock().await;
+        self.inner.restore_snapshot(path).await?;
+        let event = JournalEvent::SnapshotRestored {
+            experiences: self.inner.get_all_experiences().await?,
+            peers: self.inner.get_peers().await?,
+            cached_scores: self.inner.get_all_cached_scores().await?,
+            domain_defaults: self.inner.get_domain_defaults().await?,
+        };
+        journal.append(vec![event], self.clock.now()).await
+    }
+
+    async fn restore_export(&self, data: TrustDataExport) -> StorageResult<()> {
+        let event = JournalEvent::ExportRestored { data: data.clone() };
+        self.journaled(self.inner.restore_export(data), || vec![event]).await
+    }
+
+    async fn import_records(
+        &self,
+        replaced_experiences: &[String],
+        experiences: Vec<TrustExperience>,
+        replaced_peers: &[String],
+        peers: Vec<Peer>,
+    ) -> StorageResult<()> {
+        let event = JournalEvent::RecordsImported {
+            replaced_experiences: replaced_experiences.to_vec(),
+            experiences: experiences.clone(),
+            replaced_peers: replaced_peers.to_vec(),
+            peers: peers.clone(),
+        };
+        self.journaled(self.inner.import_records(replaced_experiences, experiences, replaced_peers, peers), || vec![event]).await
+    }
+
+    async fn merge_records(
+        &self,
+        added: Vec<TrustExperience>,
+        updated: Vec<TrustExperience>,
+        removed: &[String],
+        peers: Vec<Peer>,
+        changed_by: &str,
+    ) -> StorageResult<()> {
+        let event = JournalEvent::RecordsMerged {
+            added: added.clone(),
+            updated: updated.clone(),
+            removed: removed.to_vec(),
+            peers: peers.clone(),
+            changed_by: changed_by.to_string(),
+        };
+        self.journaled(self.inner.merge_records(added, updated, removed, peers, changed_by), || vec![event]).await
+    }
+}
\ No newline at end of file
@@ -3,4 +3,41 @@ pub mod protocols;
 pub mod storage;
 pub mod query_engine;
 pub mod types;
-pub mod api;
\ No newline at end of file
+pub mod api;
+pub mod backup;
+pub mod config;
+pub mod anomaly;
+pub mod eigentrust;
+pub mod events;
+pub mod inbound;
+pub mod clock;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(feature = "http-client")]
+pub mod loadtest;
+mod rng;
+pub mod seed;
+pub mod journal;
+pub mod import;
+pub mod mapping;
+pub mod archive;
+pub mod anonymize;
+pub mod watchlist;
+pub mod notify;
+pub mod graph;
+pub mod diff;
+pub mod merge;
+pub mod bundle;
+pub mod identity;
+pub mod invite;
+pub mod reputation;
+pub mod availability;
+pub mod keep_alive;
+pub mod quarantine;
+pub mod validation;
+pub mod runtime_metrics;
+#[cfg(unix)]
+pub mod daemon;
+pub mod telemetry;
+#[cfg(feature = "simulation")]
+pub mod simulation;
\ No newline at end of file
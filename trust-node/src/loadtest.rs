@@ -0,0 +1,212 @@
+use crate::rng::SplitMix;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Experiences sent per POST /v1/experiences/batch while populating
+const POPULATE_BATCH: usize = 500;
+/// Every this many reads is a POST /v1/trust/batch instead of a single agent lookup
+const BATCH_READ_EVERY: usize = 10;
+const BATCH_READ_AGENTS: usize = 20;
+const DOMAIN: &str = "loadtest";
+
+/// What to throw at a running node
+#[derive(Debug, Clone)]
+pub struct LoadTestOptions {
+    /// Base URL of the node's API, e.g. http://127.0.0.1:8080
+    pub target: String,
+    /// Synthetic experiences added before the workload starts
+    pub experiences: usize,
+    /// Distinct agents the experiences and queries are spread over
+    pub agents: usize,
+    pub requests: usize,
+    pub concurrency: usize,
+    /// Share of requests that add an experience rather than query trust
+    pub write_ratio: f64,
+    pub max_depth: u8,
+    /// Same seed, same workload
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub errors: usize,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencySummary {
+    fn from_samples(mut samples: Vec<Duration>, errors: usize) -> Self {
+        samples.sort();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        Self {
+            count: samples.len(),
+            errors,
+            p50_ms: percentile(&samples, 0.50).map_or(0.0, ms),
+            p90_ms: percentile(&samples, 0.90).map_or(0.0, ms),
+            p99_ms: percentile(&samples, 0.99).map_or(0.0, ms),
+            max_ms: samples.last().copied().map_or(0.0, ms),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadTestReport {
+    pub populated: usize,
+    pub populate_seconds: f64,
+    pub workload_seconds: f64,
+    pub requests_per_second: f64,
+    pub reads: LatencySummary,
+    pub writes: LatencySummary,
+}
+
+/// Nearest-rank percentile of already sorted samples
+fn percentile(sorted: &[Duration], q: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+fn synthetic_experience(rng: &mut SplitMix, agents: usize) -> serde_json::Value {
+    let investment = 10.0 + rng.unit() * 990.0;
+    json!({
+        "id_domain": DOMAIN,
+        "agent_id": format!("agent-{}", rng.below(agents)),
+        "investment": investment,
+        "return_value": investment * (0.5 + rng.unit()),
+        "timeframe_days": 1.0 + rng.unit() * 364.0,
+    })
+}
+
+pub async fn run(options: LoadTestOptions) -> Result<LoadTestReport> {
+    let client = reqwest::Client::new();
+    let base = options.target.trim_end_matches('/').to_string();
+    let mut rng = SplitMix(options.seed);
+
+    let started = Instant::now();
+    let mut populated = 0;
+    while populated < options.experiences {
+        let count = POPULATE_BATCH.min(options.experiences - populated);
+        let batch: Vec<_> = (0..count).map(|_| synthetic_experience(&mut rng, options.agents)).collect();
+        client
+            .post(format!("{}/v1/experiences/batch", base))
+            .json(&batch)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context("populating the node")?;
+        populated += count;
+    }
+    let populate_seconds = started.elapsed().as_secs_f64();
+    info!("Populated {} experiences in {:.1}s", populated, populate_seconds);
+
+    let next_request = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+    let workers: Vec<_> = (0..options.concurrency.max(1))
+        .map(|worker| {
+            let client = client.clone();
+            let base = base.clone();
+            let options = options.clone();
+            let next_request = next_request.clone();
+            let mut rng = SplitMix(options.seed ^ (worker as u64 + 1).wrapping_mul(0x2545_f491_4f6c_dd1d));
+            tokio::spawn(async move {
+                let mut samples = WorkerSamples::default();
+                loop {
+                    let index = next_request.fetch_add(1, Ordering::Relaxed);
+                    if index >= options.requests {
+                        return samples;
+                    }
+                    let write = rng.unit() < options.write_ratio;
+                    let request = if write {
+                        client.post(format!("{}/v1/experiences", base)).json(&synthetic_experience(&mut rng, options.agents))
+                    } else if index.is_multiple_of(BATCH_READ_EVERY) {
+                        let agents: Vec<_> = (0..BATCH_READ_AGENTS)
+                            .map(|_| json!({ "id_domain": DOMAIN, "agent_id": format!("agent-{}", rng.below(options.agents)) }))
+                            .collect();
+                        client
+                            .post(format!("{}/v1/trust/batch", base))
+                            .json(&json!({ "agents": agents, "max_depth": options.max_depth }))
+                    } else {
+                        client.get(format!(
+                            "{}/v1/trust/{}/agent-{}?max_depth={}",
+                            base, DOMAIN, rng.below(options.agents), options.max_depth
+                        ))
+                    };
+
+                    let sent = Instant::now();
+                    let ok = matches!(request.send().await, Ok(response) if response.status().is_success());
+                    samples.record(write, ok, sent.elapsed());
+                }
+            })
+        })
+        .collect();
+
+    let mut all = WorkerSamples::default();
+    for worker in workers {
+        all.extend(worker.await?);
+    }
+    let workload_seconds = started.elapsed().as_secs_f64();
+
+    Ok(LoadTestReport {
+        populated,
+        populate_seconds,
+        workload_seconds,
+        requests_per_second: options.requests as f64 / workload_seconds.max(f64::EPSILON),
+        reads: LatencySummary::from_samples(all.reads, all.read_errors),
+        writes: LatencySummary::from_samples(all.writes, all.write_errors),
+    })
+}
+
+/// Latencies of successful requests; failures are only counted
+#[derive(Default)]
+struct WorkerSamples {
+    reads: Vec<Duration>,
+    writes: Vec<Duration>,
+    read_errors: usize,
+    write_errors: usize,
+}
+
+impl WorkerSamples {
+    fn record(&mut self, write: bool, ok: bool, latency: Duration) {
+        match (write, ok) {
+            (true, true) => self.writes.push(latency),
+            (true, false) => self.write_errors += 1,
+            (false, true) => self.reads.push(latency),
+            (false, false) => self.read_errors += 1,
+        }
+    }
+
+    fn extend(&mut self, other: WorkerSamples) {
+        self.reads.extend(other.reads);
+        self.writes.extend(other.writes);
+        self.read_errors += other.read_errors;
+        self.write_errors += other.write_errors;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_use_nearest_rank() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 0.50), Some(Duration::from_millis(50)));
+        assert_eq!(percentile(&samples, 0.99), Some(Duration::from_millis(99)));
+        assert_eq!(percentile(&samples, 1.0), Some(Duration::from_millis(100)));
+        assert_eq!(percentile(&[], 0.5), None);
+
+        let summary = LatencySummary::from_samples(vec![Duration::from_millis(3)], 2);
+        assert_eq!((summary.count, summary.errors), (1, 2));
+        assert_eq!(summary.p50_ms, 3.0);
+    }
+}
\ No newline at end of file
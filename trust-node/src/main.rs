@@ -1,14 +1,20 @@
-mod node;
-mod protocols;
-mod storage;
-mod query_engine;
-mod types;
-mod api;
-
-use clap::Parser;
+#[cfg(feature = "chaos")]
+use trust_node::chaos;
+#[cfg(unix)]
+use trust_node::daemon;
+#[cfg(feature = "http-client")]
+use trust_node::loadtest;
+use trust_node::{
+    api, backup, bundle, clock, config, diff, graph, identity, journal, mapping, merge, node, notify, quarantine, query_engine, reputation,
+    seed, storage, telemetry, types,
+};
+use clap::{Parser, Subcommand};
+use storage::Storage;
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::Duration;
 use tracing::info;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -19,42 +25,810 @@ struct Args {
     #[arg(short, long, default_value_t = 0)]
     p2p_port: u16,
 
-    #[arg(short, long)]
-    user: String,
+    /// User profile to host (repeatable); each gets its own database and identity key, the first is the default
+    #[arg(short, long, required = true)]
+    user: Vec<String>,
 
     #[arg(short, long, default_value = "./trust_data")]
     data_dir: PathBuf,
 
     #[arg(long)]
     bootstrap_peers: Vec<String>,
+
+    /// Directory for scheduled database backups (backups are disabled if not set)
+    #[arg(long)]
+    backup_dir: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 3600)]
+    backup_interval_secs: u64,
+
+    /// Number of backups to keep before the oldest are deleted
+    #[arg(long, default_value_t = 7)]
+    backup_keep: usize,
+
+    /// Scores from fewer experiences are reported as insufficient data
+    #[arg(long, default_value_t = 0)]
+    min_data_points: usize,
+
+    /// Scores backed by less volume are reported as insufficient data
+    #[arg(long, default_value_t = 0.0)]
+    min_total_volume: f64,
+
+    /// Peers only get scores backed by at least this many experiences
+    #[arg(long, default_value_t = 0)]
+    answer_min_data_points: usize,
+
+    /// Give peers scores below --answer-min-data-points with bucketed volumes instead of none at all
+    #[arg(long)]
+    answer_bucket_volumes: bool,
+
+    /// Who may ask what this node thinks of the agents they claim as their own: nobody, peers or anyone
+    #[arg(long, default_value = "peers")]
+    reputation_answers: reputation::ReputationPolicy,
+
+    /// Largest share of an agent's merged volume one peer may supply, e.g. 0.25; unset leaves peers uncapped
+    #[arg(long, value_parser = parse_share)]
+    max_peer_share: Option<f64>,
+
+    /// Largest share of an agent's merged volume the peers sharing a tag may supply together
+    #[arg(long, value_parser = parse_share)]
+    max_circle_share: Option<f64>,
+
+    /// Lowest ROI a peer's score may claim; lower ones are rejected rather than cached
+    #[arg(long, default_value_t = -1e6, allow_negative_numbers = true)]
+    min_peer_roi: f64,
+
+    /// Highest ROI a peer's score may claim
+    #[arg(long, default_value_t = 1e6)]
+    max_peer_roi: f64,
+
+    /// Largest volume a peer's score may claim
+    #[arg(long, default_value_t = 1e15)]
+    max_peer_volume: f64,
+
+    /// Most experiences a peer's score may claim to rest on
+    #[arg(long, default_value_t = 1_000_000_000)]
+    max_peer_data_points: usize,
+
+    /// Lowest ROI a merged score may have; lower ones are clamped up to it
+    #[arg(long, allow_negative_numbers = true)]
+    clamp_min_roi: Option<f64>,
+
+    /// Highest ROI a merged score may have; higher ones are clamped down to it
+    #[arg(long, allow_negative_numbers = true)]
+    clamp_max_roi: Option<f64>,
+
+    /// Most weighted volume a single source may add to a merged score, own experiences included
+    #[arg(long)]
+    max_source_volume: Option<f64>,
+
+    /// Weight of scores relayed by peers, applied once per hop (1.0 treats them like own experiences)
+    #[arg(long, default_value_t = 1.0)]
+    hop_damping: f64,
+
+    /// Days without hearing from a peer after which its recommender quality is halfway back to
+    /// --quality-prior; 0 keeps qualities as set
+    #[arg(long, default_value_t = 0.0)]
+    quality_half_life_days: f64,
+
+    /// Neutral recommender quality that silent peers decay toward
+    #[arg(long, default_value_t = 0.5)]
+    quality_prior: f64,
+
+    /// Seconds between idle-time prefetches of watched and most-queried agents; 0 disables them
+    #[arg(long, default_value_t = 300)]
+    refresh_interval_secs: u64,
+
+    /// Best peers asked during a prefetch; 0 asks every peer of good enough quality
+    #[arg(long, default_value_t = 5)]
+    prefetch_peers: usize,
+
+    /// Most recently and frequently queried agents scored at startup, before reporting ready; 0 starts cold
+    #[arg(long, default_value_t = 0)]
+    warm_up_agents: usize,
+
+    /// Seconds between swaps of agent availability filters with peers; 0 (the default) asks every peer on every query
+    #[arg(long, default_value_t = 0)]
+    availability_interval_secs: u64,
+
+    /// Seconds between checks for due recurring experiences; 0 stops generating them
+    #[arg(long, default_value_t = 3600)]
+    recurring_interval_secs: u64,
+
+    /// Days after which peers' cached recommendations are folded into one historical entry per agent; 0 keeps them all
+    #[arg(long, default_value_t = 0, value_parser = parse_compaction_age_days)]
+    score_compaction_age_days: u64,
+
+    /// How returns are discounted to present value: annual, continuous or zero; domains can register their own
+    #[arg(long, default_value = "annual", value_parser = parse_discounting_model)]
+    discounting: String,
+
+    /// Rate of the annual or continuous discounting
+    #[arg(long, default_value_t = types::DEFAULT_DISCOUNT_RATE)]
+    discount_rate: f64,
+
+    /// Peer queries processed concurrently; defaults to the number of CPUs
+    #[arg(long)]
+    inbound_workers: Option<usize>,
+
+    /// Most agents a single trust query may ask about
+    #[arg(long, default_value_t = 1000)]
+    max_query_agents: usize,
+
+    /// Deepest a trust query may be forwarded through the network
+    #[arg(long, default_value_t = 6)]
+    max_query_depth: u8,
+
+    /// Queries one peer may have waiting for a worker before further ones are answered empty
+    #[arg(long, default_value_t = 32)]
+    max_queued_per_peer: usize,
+
+    /// Seconds between Kademlia peer discovery rounds
+    #[arg(long, default_value_t = 30)]
+    discovery_interval_secs: u64,
+
+    /// Most peers asked per query, highest recommender quality first; 0 asks every connected peer
+    #[arg(long, default_value_t = 0)]
+    max_fanout: usize,
+
+    /// Seconds a calculated score is served from the cache
+    #[arg(long, default_value_t = 300)]
+    cache_ttl_secs: u64,
+
+    /// Seconds a peer that answered without a score for an agent isn't asked about it again; 0 always asks
+    #[arg(long, default_value_t = 60)]
+    no_data_ttl_secs: u64,
+
+    /// Hours the response to a POST sent with an Idempotency-Key is replayed to retries with the same key; 0 ignores the header
+    #[arg(long, default_value_t = 24)]
+    idempotency_ttl_hours: u64,
+
+    /// Seconds a peer is waited for until its latency has been measured
+    #[arg(long, default_value_t = 5)]
+    request_timeout_secs: u64,
+
+    /// Seconds a peer with high measured latency may be waited for at most; 0 holds every peer to --request-timeout-secs
+    #[arg(long, default_value_t = 0)]
+    max_request_timeout_secs: u64,
+
+    /// Dials to known peers in flight at once
+    #[arg(long, default_value_t = 5)]
+    max_concurrent_dials: usize,
+
+    /// Which known peers are dialed first: quality (best recommenders) or recency (heard from last)
+    #[arg(long, default_value = "quality", value_parser = config::DialPriority::named)]
+    dial_priority: config::DialPriority,
+
+    /// Protocol violations, like undecodable or oversized messages and absurd scores, that quarantine a peer; 0 never does
+    #[arg(long, default_value_t = 3)]
+    quarantine_after: usize,
+
+    /// Minutes within which the violations have to happen
+    #[arg(long, default_value_t = 10)]
+    quarantine_window_mins: u64,
+
+    /// Minutes a quarantined peer is neither dialed nor answered
+    #[arg(long, default_value_t = 60)]
+    quarantine_mins: u64,
+
+    /// Seconds a connection without traffic stays open
+    #[arg(long, default_value_t = 60)]
+    idle_connection_timeout_secs: u64,
+
+    /// Keep connections to peers with at least this recommender quality open, however idle
+    #[arg(long)]
+    keep_alive_quality: Option<f64>,
+
+    /// ntfy topic to push notifications to, e.g. https://ntfy.sh/<topic>; may be given several times
+    #[arg(long)]
+    notify_ntfy: Vec<String>,
+
+    /// URL each notification is POSTed to as JSON; may be given several times
+    #[arg(long)]
+    notify_webhook: Vec<String>,
+
+    /// Minutes without a connected peer before a notification says the node is unreachable; 0 never sends one
+    #[arg(long, default_value_t = 360)]
+    notify_unreachable_mins: u64,
+
+    /// Seconds between anomaly scans; 0 disables them
+    #[arg(long, default_value_t = 3600)]
+    anomaly_interval_secs: u64,
+
+    /// Seconds between recomputing the EigenTrust-style global aggregate reported next to each score; 0 disables it
+    #[arg(long, default_value_t = 0)]
+    global_trust_interval_secs: u64,
+
+    #[arg(long, default_value_t = 5)]
+    db_max_connections: u32,
+
+    #[arg(long, default_value_t = 5000)]
+    db_busy_timeout_ms: u64,
+
+    /// Extra SQLite pragma as key=value, e.g. --db-pragma synchronous=FULL (repeatable)
+    #[arg(long, value_parser = parse_pragma)]
+    db_pragma: Vec<(String, String)>,
+
+    /// Log output: text, or json with one object per line for log collectors
+    #[arg(long, default_value = "text")]
+    log_format: telemetry::LogFormat,
+
+    /// Write logs to this file instead of stdout; it's reopened on SIGHUP, e.g. after logrotate
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// Append every change to <data-dir>/<user>.journal, so the database can be rebuilt with `replay`
+    #[arg(long)]
+    journal: bool,
+
+    /// Share of peer responses to drop (chaos builds only)
+    #[cfg(feature = "chaos")]
+    #[arg(long, default_value_t = 0.0)]
+    chaos_drop_responses: f64,
+
+    /// Share of peer responses to delay by --chaos-delay-ms
+    #[cfg(feature = "chaos")]
+    #[arg(long, default_value_t = 0.0)]
+    chaos_delay_responses: f64,
+
+    #[cfg(feature = "chaos")]
+    #[arg(long, default_value_t = 1000)]
+    chaos_delay_ms: u64,
+
+    /// Share of peer responses to replace with undecodable bytes
+    #[cfg(feature = "chaos")]
+    #[arg(long, default_value_t = 0.0)]
+    chaos_corrupt_frames: f64,
+
+    /// Share of storage calls to fail
+    #[cfg(feature = "chaos")]
+    #[arg(long, default_value_t = 0.0)]
+    chaos_fail_storage: f64,
+
+    #[cfg(feature = "chaos")]
+    #[arg(long, default_value_t = 1)]
+    chaos_seed: u64,
+
+    /// Export spans to this OTLP gRPC collector, e.g. http://localhost:4317 for Jaeger or Tempo
+    #[cfg(feature = "otlp")]
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+
+    /// Share of traces started on this node to export; traces started by peers follow their decision
+    #[cfg(feature = "otlp")]
+    #[arg(long, default_value_t = 1.0)]
+    otlp_sample_ratio: f64,
+
+    /// Serve tokio-console on this address, e.g. 127.0.0.1:6669
+    #[cfg(feature = "console")]
+    #[arg(long)]
+    console_addr: Option<std::net::SocketAddr>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Replace the user's database with a backup snapshot or export file, then exit
+    Restore {
+        file: PathBuf,
+    },
+    /// Rebuild the user's database from their journal, optionally only up to a point in time, then exit
+    Replay {
+        /// Journal to replay instead of <data-dir>/<user>.journal
+        file: Option<PathBuf>,
+        /// Leave out events after this RFC 3339 time; the journal is cut back to match
+        #[arg(long)]
+        until: Option<chrono::DateTime<chrono::Utc>>,
+    },
+    /// Database administration, then exit
+    Db {
+        #[command(subcommand)]
+        action: DbCommand,
+    },
+    /// Move the user's trust identity to another machine, then exit
+    Identity {
+        #[command(subcommand)]
+        action: IdentityCommand,
+    },
+    /// Populate a running node with synthetic experiences, fire a mixed workload at its API
+    /// and print latency percentiles, then exit
+    #[cfg(feature = "http-client")]
+    Loadtest {
+        #[arg(long, default_value = "http://127.0.0.1:8080")]
+        target: String,
+        #[arg(long, default_value_t = 10_000)]
+        experiences: usize,
+        #[arg(long, default_value_t = 1_000)]
+        agents: usize,
+        #[arg(long, default_value_t = 5_000)]
+        requests: usize,
+        #[arg(long, default_value_t = 16)]
+        concurrency: usize,
+        /// Share of requests that add an experience rather than query trust
+        #[arg(long, default_value_t = 0.2)]
+        write_ratio: f64,
+        #[arg(long, default_value_t = 0)]
+        max_depth: u8,
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+    },
+    /// Print the user's trust network (peers, own experiences and cached recommendations), then exit
+    Graph {
+        /// json, dot for Graphviz, or graphml for Gephi and similar tools
+        #[arg(long, default_value = "json")]
+        format: graph::GraphFormat,
+    },
+    /// Merge the database of the same user on another device into this one, honoring deletions
+    /// made on either side, then exit
+    Merge {
+        /// The other device's database; it is read from a copy and left as it is
+        #[arg(long)]
+        other: PathBuf,
+    },
+    /// Convert a JSON or CSV dump of another reputation system into an export document on stdout,
+    /// as described by a mapping file, then exit; records that don't fit are reported on stderr
+    Convert {
+        #[arg(long)]
+        mapping: PathBuf,
+        input: PathBuf,
+    },
+    /// Compare two export files and print the experiences and peers added, removed or changed, then exit
+    Diff {
+        before: PathBuf,
+        after: PathBuf,
+    },
+    /// Fill the user's database with randomized agents, experiences, peers and cached
+    /// recommendations to explore a populated node, then exit
+    Seed {
+        #[arg(long, default_value_t = 50)]
+        agents: usize,
+        #[arg(long, default_value_t = 2_000)]
+        experiences: usize,
+        #[arg(long, default_value_t = 5)]
+        peers: usize,
+        /// Experiences are spread over this many years
+        #[arg(long, default_value_t = 3)]
+        years: u32,
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+        /// Date the newest data up to this RFC 3339 time instead of now, to get the same data on every run
+        #[arg(long)]
+        end: Option<chrono::DateTime<chrono::Utc>>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum DbCommand {
+    /// Run integrity_check, VACUUM and ANALYZE and print a report
+    Maintain,
+}
+
+#[derive(Subcommand, Debug)]
+enum IdentityCommand {
+    /// Write the user's key, peers and experiences to a passphrase-encrypted bundle
+    Export {
+        file: PathBuf,
+        #[arg(long, env = "TRUST_NODE_PASSPHRASE", hide_env_values = true)]
+        passphrase: String,
+    },
+    /// Replace the user's key, peers and experiences with the ones in a bundle
+    Import {
+        file: PathBuf,
+        #[arg(long, env = "TRUST_NODE_PASSPHRASE", hide_env_values = true)]
+        passphrase: String,
+    },
+}
+
+fn parse_discounting_model(s: &str) -> Result<String, String> {
+    types::Discounting::named(s, types::DEFAULT_DISCOUNT_RATE).map(|_| s.to_string())
+}
+
+fn parse_share(s: &str) -> Result<f64, String> {
+    let share: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if types::InfluenceCaps::valid_share(share) {
+        Ok(share)
+    } else {
+        Err(format!("expected a share above 0 and at most 1, got {}", s))
+    }
+}
+
+fn parse_compaction_age_days(s: &str) -> Result<u64, String> {
+    let days: u64 = s.parse().map_err(|e| format!("{}", e))?;
+    if days <= MAX_SCORE_COMPACTION_AGE_DAYS {
+        Ok(days)
+    } else {
+        Err(format!("expected at most {} days, got {}", MAX_SCORE_COMPACTION_AGE_DAYS, s))
+    }
+}
+
+fn parse_pragma(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .ok_or_else(|| format!("expected key=value, got {}", s))
+}
+
+/// The notification channels given on the command line, or None when there are none
+fn notify_channels(args: &Args) -> Option<Vec<notify::NotifyChannel>> {
+    let channels: Vec<_> = args
+        .notify_ntfy
+        .iter()
+        .cloned()
+        .map(notify::NotifyChannel::Ntfy)
+        .chain(args.notify_webhook.iter().cloned().map(notify::NotifyChannel::Webhook))
+        .collect();
+    (!channels.is_empty()).then_some(channels)
+}
+
+/// Longest `--score-compaction-age-days`, about a century
+const MAX_SCORE_COMPACTION_AGE_DAYS: u64 = 36_500;
+
+/// How long a node gets to write out what it buffers when the process is asked to stop
+const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolves on Ctrl-C or SIGTERM; never if neither can be listened for
+async fn shutdown_signal() {
+    let interrupt = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+async fn flush_nodes(nodes: &[tokio::sync::mpsc::Sender<node::NodeCommand>]) {
+    for commands in nodes {
+        let (response, flushed) = tokio::sync::oneshot::channel();
+        if commands.send(node::NodeCommand::FlushForShutdown { response }).await.is_ok() {
+            let _ = tokio::time::timeout(SHUTDOWN_FLUSH_TIMEOUT, flushed).await;
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    // Spans are flushed when the guard drops at the end of main
+    #[cfg(feature = "otlp")]
+    let (otlp_layer, _otlp_guard): (_, Option<telemetry::OtlpGuard>) = match &args.otlp_endpoint {
+        Some(endpoint) => {
+            let (layer, guard) = telemetry::otlp_layer(&telemetry::OtlpConfig {
+                endpoint: endpoint.clone(),
+                sample_ratio: args.otlp_sample_ratio,
+                service_name: format!("trust-node {}", args.user.join(",")),
+            })?;
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+    #[cfg(not(feature = "otlp"))]
+    let otlp_layer = None::<tracing_subscriber::layer::Identity>;
+    #[cfg(feature = "console")]
+    let console_layer = args.console_addr.map(telemetry::console_layer);
+    #[cfg(not(feature = "console"))]
+    let console_layer = None::<tracing_subscriber::layer::Identity>;
+    let log_file = args.log_file.as_deref().map(telemetry::LogFile::open).transpose()?;
+
     tracing_subscriber::registry()
+        .with(console_layer)
         .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "trust_node=debug,tower_http=debug".into()),
+            telemetry::fmt_layer(args.log_format, log_file.clone())
+                .and_then(otlp_layer)
+                .with_filter(
+                    tracing_subscriber::EnvFilter::try_from_default_env()
+                        .unwrap_or_else(|_| "trust_node=debug,tower_http=debug".into()),
+                ),
         )
-        .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let args = Args::parse();
+    // The load test talks to a node over HTTP and opens no databases of its own
+    #[cfg(feature = "http-client")]
+    if let Some(Command::Loadtest { target, experiences, agents, requests, concurrency, write_ratio, max_depth, seed }) = &args.command {
+        let report = loadtest::run(loadtest::LoadTestOptions {
+            target: target.clone(),
+            experiences: *experiences,
+            agents: *agents,
+            requests: *requests,
+            concurrency: *concurrency,
+            write_ratio: *write_ratio,
+            max_depth: *max_depth,
+            seed: *seed,
+        }).await?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
     
-    info!("Starting trust node for user: {}", args.user);
-    info!("API port: {}, P2P port: {}", args.api_port, args.p2p_port);
+    if let Some(Command::Convert { mapping, input }) = &args.command {
+        let mapping: mapping::ImportMapping = serde_json::from_slice(&std::fs::read(mapping)?)?;
+        let mapped = mapping.apply(&std::fs::read(input)?, chrono::Utc::now())?;
+        for error in &mapped.errors {
+            eprintln!("record {}: {}", error.record, error.message);
+        }
+        let export = types::TrustDataExport::new(mapped.experiences, Vec::new());
+        println!("{}", serde_json::to_string_pretty(&export)?);
+        return Ok(());
+    }
 
-    let storage = storage::SqliteStorage::new(&args.data_dir.join(format!("{}.db", args.user))).await?;
+    // Comparing exports only reads the two files
+    if let Some(Command::Diff { before, after }) = &args.command {
+        let diff = diff::diff_files(before, after)?;
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
     
-    let (node, api_handle) = node::TrustNode::new(
-        args.p2p_port,
-        args.api_port,
-        storage,
-        args.bootstrap_peers,
-    ).await?;
+    info!("Starting trust node for users: {}", args.user.join(", "));
+    info!("API port: {}, P2P port: {}", args.api_port, args.p2p_port);
 
+    let storage_options = storage::StorageOptions {
+        max_connections: args.db_max_connections,
+        busy_timeout: Duration::from_millis(args.db_busy_timeout_ms),
+        pragmas: args.db_pragma.clone(),
+        clock: clock::system_clock(),
+    };
+    #[cfg(feature = "chaos")]
+    let chaos = std::sync::Arc::new(chaos::Chaos::new(chaos::ChaosConfig {
+        drop_responses: args.chaos_drop_responses,
+        delay_responses: args.chaos_delay_responses,
+        response_delay: Duration::from_millis(args.chaos_delay_ms),
+        corrupt_frames: args.chaos_corrupt_frames,
+        fail_storage: args.chaos_fail_storage,
+        seed: args.chaos_seed,
+    }));
+    let multi_user = args.user.len() > 1;
+    if multi_user && matches!(args.command, Some(Command::Identity { .. })) {
+        anyhow::bail!("identity export and import work on one --user at a time");
+    }
+    if multi_user && matches!(args.command, Some(Command::Merge { .. })) {
+        anyhow::bail!("merge works on one --user at a time");
+    }
+    if multi_user && matches!(args.command, Some(Command::Restore { .. })) {
+        anyhow::bail!("restore works on one --user at a time");
+    }
+    if multi_user && matches!(args.command, Some(Command::Db { action: DbCommand::Maintain })) {
+        anyhow::bail!("db maintain works on one --user at a time");
+    }
+    if args.min_peer_roi.is_nan() || args.max_peer_roi.is_nan() || args.min_peer_roi > args.max_peer_roi {
+        anyhow::bail!("--min-peer-roi {} must not be above --max-peer-roi {}", args.min_peer_roi, args.max_peer_roi);
+    }
+
+    let quality_decay = if args.quality_half_life_days > 0.0 {
+        let half_life = Duration::try_from_secs_f64(args.quality_half_life_days * 86400.0)
+            .map_err(|e| anyhow::anyhow!("--quality-half-life-days {}: {}", args.quality_half_life_days, e))?;
+        Some(types::QualityDecay { half_life, prior: args.quality_prior })
+    } else {
+        None
+    };
+
+    let mut nodes = Vec::new();
+    let mut command_channels = HashMap::new();
+    for (index, user) in args.user.iter().enumerate() {
+        let storage = storage::SqliteStorage::new_with_options(
+            &args.data_dir.join(format!("{}.db", user)),
+            storage_options.clone(),
+        ).await?;
+        let journal_path = journal::journal_path(&args.data_dir, user);
+
+        if let Some(Command::Replay { file, until }) = &args.command {
+            let file = file.clone().unwrap_or(journal_path);
+            let scratch = args.data_dir.join(format!("{}.replay.db", user));
+            let summary = journal::rebuild(&storage, &file, &scratch, *until).await?;
+            info!("Replayed {} journal events for {}", summary.applied, user);
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+            continue;
+        }
+        let storage = journal::JournaledStorage::open(
+            storage,
+            args.journal.then_some(journal_path.as_path()),
+            clock::system_clock(),
+        ).await?;
+
+        match &args.command {
+            Some(Command::Restore { file }) => {
+                let summary = backup::restore_from_file(&storage, file).await?;
+                info!("Restored {:?} from {} for {}", summary.format, summary.source.display(), user);
+                continue;
+            }
+            Some(Command::Identity { action: IdentityCommand::Export { file, passphrase } }) => {
+                let key = identity::load_or_create(&args.data_dir.join(format!("{}.key", user)))?;
+                let bundle = bundle::IdentityBundle::collect(&storage, &key).await?;
+                identity::write_private(file, &bundle.seal(passphrase)?)?;
+                println!("{}", serde_json::to_string_pretty(&bundle.summary()?)?);
+                continue;
+            }
+            Some(Command::Identity { action: IdentityCommand::Import { file, passphrase } }) => {
+                let bundle = bundle::IdentityBundle::open(&std::fs::read(file)?, passphrase)?;
+                let (summary, key) = (bundle.summary()?, bundle.key()?);
+                // Data first: if the restore fails, the node keeps its own key along with its own data
+                storage.restore_export(bundle.data).await?;
+                identity::save(&args.data_dir.join(format!("{}.key", user)), &key)?;
+                info!("Imported identity {} for {}", summary.peer_id, user);
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+                continue;
+            }
+            Some(Command::Merge { other }) => {
+                let scratch = args.data_dir.join(format!("{}.merge.db", user));
+                let summary = merge::merge_database(&storage, other, &scratch).await?;
+                info!("Merged {} into the database of {}", other.display(), user);
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+                continue;
+            }
+            Some(Command::Db { action: DbCommand::Maintain }) => {
+                let report = storage.maintain().await?;
+                println!("{}", serde_json::to_string_pretty(&report)?);
+                continue;
+            }
+            Some(Command::Graph { format }) => {
+                // Scored as the running node would, with the inflation of each domain's currency
+                let defaults = storage.get_domain_defaults().await?;
+                let inflation = query_engine::inflation_by_domain(&defaults, storage.get_inflation_indexes().await?);
+                let storage = std::sync::Arc::new(storage);
+                let engine = query_engine::QueryEngine::new(storage.clone());
+                engine.set_inflation(inflation).await;
+                let graph = graph::build(storage.as_ref(), &engine, user, chrono::Utc::now()).await?;
+                print!("{}", graph.render(*format)?);
+                continue;
+            }
+            Some(Command::Seed { agents, experiences, peers, years, seed, end }) => {
+                let options = seed::SeedOptions {
+                    agents: *agents,
+                    experiences: *experiences,
+                    peers: *peers,
+                    years: *years,
+                    seed: *seed,
+                };
+                let summary = seed::populate(&storage, &options, end.unwrap_or_else(chrono::Utc::now)).await?;
+                info!("Seeded demo data for {}", user);
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+                continue;
+            }
+            #[cfg(feature = "http-client")]
+            Some(Command::Loadtest { .. }) => {}
+            Some(Command::Convert { .. }) | Some(Command::Diff { .. }) | Some(Command::Replay { .. }) | None => {}
+        }
+
+        let config = config::NodeConfig {
+            backup: args.backup_dir.as_ref().map(|directory| backup::BackupConfig {
+                // Backup file names don't include the user, so give each profile its own directory
+                directory: if multi_user { directory.join(user) } else { directory.clone() },
+                interval: Duration::from_secs(args.backup_interval_secs),
+                keep: args.backup_keep,
+            }),
+            anomaly_interval: (args.anomaly_interval_secs > 0)
+                .then(|| Duration::from_secs(args.anomaly_interval_secs)),
+            global_trust_interval: (args.global_trust_interval_secs > 0)
+                .then(|| Duration::from_secs(args.global_trust_interval_secs)),
+            notify: notify_channels(&args).map(|channels| notify::NotifyConfig {
+                channels,
+                unreachable_after: (args.notify_unreachable_mins > 0)
+                    .then(|| Duration::from_secs(args.notify_unreachable_mins * 60)),
+                user: multi_user.then(|| user.clone()),
+            }),
+            min_evidence: types::MinEvidence {
+                data_points: args.min_data_points,
+                total_volume: args.min_total_volume,
+            },
+            answer_privacy: types::AnswerPrivacy {
+                min_data_points: args.answer_min_data_points,
+                bucket_below_min: args.answer_bucket_volumes,
+            },
+            reputation_policy: args.reputation_answers,
+            influence_caps: types::InfluenceCaps {
+                max_peer_share: args.max_peer_share,
+                max_circle_share: args.max_circle_share,
+            },
+            score_bounds: types::ScoreBounds {
+                min_roi: args.min_peer_roi,
+                max_roi: args.max_peer_roi,
+                max_volume: args.max_peer_volume,
+                max_data_points: args.max_peer_data_points,
+            },
+            score_clamping: types::ScoreClamping {
+                min_roi: args.clamp_min_roi,
+                max_roi: args.clamp_max_roi,
+                max_source_volume: args.max_source_volume,
+            },
+            hop_damping: args.hop_damping,
+            quality_decay,
+            refresh_interval: (args.refresh_interval_secs > 0)
+                .then(|| Duration::from_secs(args.refresh_interval_secs)),
+            prefetch_peers: args.prefetch_peers,
+            warm_up_agents: args.warm_up_agents,
+            availability_interval: (args.availability_interval_secs > 0)
+                .then(|| Duration::from_secs(args.availability_interval_secs)),
+            recurring_interval: (args.recurring_interval_secs > 0)
+                .then(|| Duration::from_secs(args.recurring_interval_secs)),
+            score_compaction_age: (args.score_compaction_age_days > 0)
+                .then(|| Duration::from_secs(args.score_compaction_age_days * 24 * 60 * 60)),
+            discounting: types::Discounting::named(&args.discounting, args.discount_rate)
+                .and_then(|discounting| discounting.check().map(|()| discounting))
+                .map_err(anyhow::Error::msg)?,
+            inbound_workers: args.inbound_workers.unwrap_or_else(config::default_inbound_workers),
+            query_limits: types::QueryLimits {
+                max_agents: args.max_query_agents,
+                max_depth: args.max_query_depth,
+            },
+            max_queued_per_peer: args.max_queued_per_peer,
+            discovery_interval: Duration::from_secs(args.discovery_interval_secs.clamp(1, types::MAX_DISCOVERY_INTERVAL_SECS)),
+            max_fanout: args.max_fanout,
+            cache_ttl: Duration::from_secs(args.cache_ttl_secs),
+            no_data_ttl: Duration::from_secs(args.no_data_ttl_secs),
+            idempotency_ttl: (args.idempotency_ttl_hours > 0)
+                .then(|| Duration::from_secs(args.idempotency_ttl_hours * 60 * 60)),
+            request_timeout: Duration::from_secs(args.request_timeout_secs.max(1)),
+            max_request_timeout: (args.max_request_timeout_secs > 0)
+                .then(|| Duration::from_secs(args.max_request_timeout_secs)),
+            max_concurrent_dials: args.max_concurrent_dials.max(1),
+            dial_priority: args.dial_priority,
+            quarantine: (args.quarantine_after > 0).then(|| quarantine::QuarantinePolicy {
+                max_violations: args.quarantine_after,
+                window: Duration::from_secs(args.quarantine_window_mins * 60),
+                cooling_off: Duration::from_secs(args.quarantine_mins * 60),
+            }),
+            idle_connection_timeout: Duration::from_secs(args.idle_connection_timeout_secs),
+            keep_alive_quality: args.keep_alive_quality,
+            transport: config::P2pTransport::Tcp,
+            identity_file: Some(args.data_dir.join(format!("{}.key", user))),
+            clock: clock::system_clock(),
+            #[cfg(feature = "chaos")]
+            chaos: chaos.clone(),
+        };
+        #[cfg(feature = "chaos")]
+        let storage = chaos::ChaosStorage::new(storage, chaos.clone());
+
+        // Every profile has its own swarm, so consecutive ports are used when a fixed port is given
+        let p2p_port = if args.p2p_port == 0 { 0 } else { args.p2p_port + index as u16 };
+        let (node, command_tx) = node::TrustNode::new(
+            p2p_port,
+            storage,
+            args.bootstrap_peers.clone(),
+            config,
+        ).await?;
+
+        nodes.push(node.run());
+        command_channels.insert(user.clone(), command_tx);
+    }
+
+    if args.command.is_some() {
+        return Ok(());
+    }
+
+    let node_commands: Vec<_> = command_channels.values().cloned().collect();
+    let flush_commands = node_commands.clone();
+    let tenants = api::Tenants::new(args.user[0].clone(), command_channels);
+    let api_listener = api::bind_api(args.api_port).await?;
+    let api_handle = tokio::spawn(api::serve_api(api_listener, tenants));
+
+    #[cfg(unix)]
+    {
+        tokio::spawn(daemon::notify_when_ready(node_commands.clone()));
+        if let Some(period) = daemon::watchdog_interval() {
+            info!("systemd watchdog enabled, sending keepalives every {:?}", period);
+            tokio::spawn(daemon::run_watchdog(period, node_commands));
+        }
+        tokio::spawn(daemon::reopen_log_on_sighup(log_file));
+    }
+    #[cfg(not(unix))]
+    let _ = (node_commands, log_file);
+
+    let mut nodes = std::pin::pin!(futures::future::try_join_all(nodes));
     tokio::select! {
-        res = node.run() => {
+        res = &mut nodes => {
             if let Err(e) = res {
                 eprintln!("Node error: {}", e);
             }
@@ -64,7 +838,17 @@ async fn main() -> anyhow::Result<()> {
                 eprintln!("API server error: {}", e);
             }
         }
+        _ = shutdown_signal() => {
+            info!("Shutting down");
+            // The nodes have to keep running to answer
+            tokio::select! {
+                _ = flush_nodes(&flush_commands) => {}
+                _ = &mut nodes => {}
+            }
+        }
     }
+    #[cfg(unix)]
+    daemon::notify_stopping();
 
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,401 @@
+use crate::types::TrustExperience;
+use crate::validation::validate;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// How to turn the records of a foreign JSON or CSV dump into experiences, e.g.
+///
+/// ```json
+/// {
+///   "format": "csv",
+///   "id": {"path": "order_id"},
+///   "id_domain": {"value": "ebay"},
+///   "agent_id": {"path": "seller", "transforms": [{"op": "lowercase"}]},
+///   "invested_volume": {"path": "price_cents", "transforms": [{"op": "scale", "factor": 0.01}]},
+///   "pv_roi": {"path": "stars", "transforms": [{"op": "rescale", "from": [1, 5], "to": [0.5, 1.5]}]},
+///   "timestamp": {"path": "date", "transforms": [{"op": "parse_time", "format": "%d.%m.%Y"}]}
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportMapping {
+    #[serde(default)]
+    pub format: SourceFormat,
+    /// Dot-separated path to the array of records in a JSON dump; the document itself when unset
+    #[serde(default)]
+    pub records: Option<String>,
+    /// CSV field separator, `,` by default
+    #[serde(default)]
+    pub delimiter: Option<char>,
+    /// A stable id in the source; experiences then get the same id on every import, so running it
+    /// again only finds duplicates
+    #[serde(default)]
+    pub id: Option<FieldMapping>,
+    pub id_domain: FieldMapping,
+    pub agent_id: FieldMapping,
+    pub invested_volume: FieldMapping,
+    /// The ROI itself; alternatively give `return_value`, which is divided by the investment
+    #[serde(default)]
+    pub pv_roi: Option<FieldMapping>,
+    #[serde(default)]
+    pub return_value: Option<FieldMapping>,
+    /// Unix seconds or an RFC 3339 string; the time of the import when unset
+    #[serde(default)]
+    pub timestamp: Option<FieldMapping>,
+    #[serde(default)]
+    pub notes: Option<FieldMapping>,
+    /// Keep the whole source record in the experience's `data`
+    #[serde(default)]
+    pub keep_record: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceFormat {
+    #[default]
+    Json,
+    /// With a header row naming the fields
+    Csv,
+}
+
+/// Where a field comes from: a path into the record or a constant, then transforms applied in order
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FieldMapping {
+    /// Dot-separated, with numbers indexing into arrays, e.g. `buyer.ratings.0.score`
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub value: Option<Value>,
+    #[serde(default)]
+    pub transforms: Vec<Transform>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Transform {
+    Scale { factor: f64 },
+    Offset { amount: f64 },
+    /// Map a number linearly from one range onto another, e.g. star ratings onto ROIs
+    Rescale { from: [f64; 2], to: [f64; 2] },
+    Lowercase,
+    Trim,
+    Prefix { prefix: String },
+    /// Replace values found in the table; others pass through unchanged
+    Lookup { values: HashMap<String, Value> },
+    /// Read a time with a chrono format string, as a date or a date and time in UTC
+    ParseTime { format: String },
+    /// Use this when the field is missing, null or an empty string
+    Default { value: Value },
+}
+
+/// The experiences a dump was mapped to, and the records that couldn't be
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MappedRecords {
+    pub experiences: Vec<TrustExperience>,
+    pub errors: Vec<RecordError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordError {
+    /// Index of the record in the dump, counting from 0 and without the CSV header
+    pub record: usize,
+    pub message: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MappingError {
+    #[error("invalid mapping: {0}")]
+    Invalid(String),
+    #[error("invalid JSON input: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid CSV input: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+impl ImportMapping {
+    pub fn check(&self) -> Result<(), MappingError> {
+        if self.pv_roi.is_some() == self.return_value.is_some() {
+            return Err(MappingError::Invalid("map exactly one of pv_roi and return_value".to_string()));
+        }
+        if self.format == SourceFormat::Json && self.delimiter.is_some() {
+            return Err(MappingError::Invalid("delimiter only applies to CSV".to_string()));
+        }
+        // The CSV reader splits on a single byte
+        if self.delimiter.is_some_and(|delimiter| !delimiter.is_ascii()) {
+            return Err(MappingError::Invalid("delimiter must be an ASCII character".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Map every record of `input`; records that don't fit are reported rather than failing the whole dump
+    pub fn apply(&self, input: &[u8], now: DateTime<Utc>) -> Result<MappedRecords, MappingError> {
+        self.check()?;
+        let mut mapped = MappedRecords::default();
+        for (index, record) in self.read_records(input)?.iter().enumerate() {
+            match self.experience(record, now) {
+                Ok(experience) => mapped.experiences.push(experience),
+                Err(message) => mapped.errors.push(RecordError { record: index, message }),
+            }
+        }
+        Ok(mapped)
+    }
+
+    fn read_records(&self, input: &[u8]) -> Result<Vec<Value>, MappingError> {
+        match self.format {
+            SourceFormat::Json => {
+                let document: Value = serde_json::from_slice(input)?;
+                let records = match &self.records {
+                    Some(path) => lookup(&document, path)
+                        .ok_or_else(|| MappingError::Invalid(format!("no records at {}", path)))?,
+                    None => &document,
+                };
+                records
+                    .as_array()
+                    .cloned()
+                    .ok_or_else(|| MappingError::Invalid("the records are not an array".to_string()))
+            }
+            SourceFormat::Csv => {
+                let mut reader = csv::ReaderBuilder::new()
+                    .delimiter(self.delimiter.unwrap_or(',') as u8)
+                    .from_reader(input);
+                let headers = reader.headers()?.clone();
+                reader
+                    .records()
+                    .map(|row| {
+                        let row = row?;
+                        let fields = headers
+                            .iter()
+                            .zip(row.iter())
+                            .map(|(header, field)| (header.to_string(), Value::String(field.to_string())))
+                            .collect();
+                        Ok(Value::Object(fields))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    fn experience(&self, record: &Value, now: DateTime<Utc>) -> Result<TrustExperience, String> {
+        let field = |name: &str, mapping: &FieldMapping| {
+            mapping.resolve(record).map_err(|e| format!("{}: {}", name, e))
+        };
+        let invested_volume = as_number(&field("invested_volume", &self.invested_volume)?)
+            .ok_or("invested_volume is not a number")?;
+        if invested_volume <= 0.0 {
+            return Err("invested_volume must be positive".to_string());
+        }
+        let pv_roi = match (&self.pv_roi, &self.return_value) {
+            (Some(pv_roi), _) => as_number(&field("pv_roi", pv_roi)?).ok_or("pv_roi is not a number")?,
+            (None, Some(return_value)) => {
+                as_number(&field("return_value", return_value)?).ok_or("return_value is not a number")? / invested_volume
+            }
+            (None, None) => return Err("neither pv_roi nor return_value is mapped".to_string()),
+        };
+        if !pv_roi.is_finite() {
+            return Err("pv_roi is not finite".to_string());
+        }
+
+        let id = match &self.id {
+            Some(id) => stable_id(&as_text(&field("id", id)?).ok_or("id is empty")?),
+            None => Uuid::new_v4(),
+        };
+        let timestamp = match &self.timestamp {
+            Some(timestamp) => as_time(&field("timestamp", timestamp)?).ok_or("timestamp is not a time")?,
+            None => now,
+        };
+        let notes = match &self.notes {
+            Some(notes) => as_text(&field("notes", notes)?),
+            None => None,
+        };
+
+        let experience = TrustExperience {
+            id,
+            id_domain: as_text(&field("id_domain", &self.id_domain)?).ok_or("id_domain is empty")?,
+            agent_id: as_text(&field("agent_id", &self.agent_id)?).ok_or("agent_id is empty")?,
+            pv_roi,
+            invested_volume,
+            timestamp,
+            notes,
+            data: self.keep_record.then(|| record.clone()),
+        };
+        // The same checks as experiences posted directly, e.g. against a negative volume
+        validate(&experience).map_err(|e| e.to_string())?;
+        Ok(experience)
+    }
+}
+
+impl FieldMapping {
+    fn resolve(&self, record: &Value) -> Result<Value, String> {
+        let mut value = match (&self.path, &self.value) {
+            (Some(path), _) => lookup(record, path).cloned().unwrap_or(Value::Null),
+            (None, Some(value)) => value.clone(),
+            (None, None) => return Err("needs a path or a value".to_string()),
+        };
+        for transform in &self.transforms {
+            value = transform.apply(value)?;
+        }
+        Ok(value)
+    }
+}
+
+impl Transform {
+    fn apply(&self, value: Value) -> Result<Value, String> {
+        let number = |value: &Value| as_number(value).ok_or_else(|| format!("{} is not a number", value));
+        let text = |value: &Value| as_text(value).unwrap_or_default();
+        Ok(match self {
+            Transform::Scale { factor } => Value::from(number(&value)? * factor),
+            Transform::Offset { amount } => Value::from(number(&value)? + amount),
+            Transform::Rescale { from, to } => {
+                if from[0] == from[1] {
+                    return Err("rescale needs a range with two different ends".to_string());
+                }
+                let t = (number(&value)? - from[0]) / (from[1] - from[0]);
+                Value::from(to[0] + t * (to[1] - to[0]))
+            }
+            Transform::Lowercase => Value::String(text(&value).to_lowercase()),
+            Transform::Trim => Value::String(text(&value).trim().to_string()),
+            Transform::Prefix { prefix } => Value::String(format!("{}{}", prefix, text(&value))),
+            Transform::Lookup { values } => values.get(&text(&value)).cloned().unwrap_or(value),
+            Transform::ParseTime { format } => {
+                let raw = text(&value);
+                let time = NaiveDateTime::parse_from_str(&raw, format)
+                    .or_else(|_| NaiveDate::parse_from_str(&raw, format).map(|date| date.and_time(NaiveTime::MIN)))
+                    .map_err(|e| format!("{:?} doesn't match {}: {}", raw, format, e))?;
+                Value::String(time.and_utc().to_rfc3339())
+            }
+            Transform::Default { value: default } => match &value {
+                Value::Null => default.clone(),
+                Value::String(s) if s.is_empty() => default.clone(),
+                _ => value,
+            },
+        })
+    }
+}
+
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').filter(|segment| !segment.is_empty()).try_fold(value, |value, segment| match value {
+        Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+        _ => value.get(segment),
+    })
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+    // "NaN" and "inf" parse as numbers, but no amount or timeframe is one
+    .filter(|n: &f64| n.is_finite())
+}
+
+fn as_text(value: &Value) -> Option<String> {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        _ => return None,
+    };
+    (!text.is_empty()).then_some(text)
+}
+
+fn as_time(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::Number(n) => DateTime::from_timestamp(n.as_i64()?, 0),
+        Value::String(s) => match s.trim().parse::<i64>() {
+            Ok(seconds) => DateTime::from_timestamp(seconds, 0),
+            Err(_) => DateTime::parse_from_rfc3339(s.trim()).ok().map(|t| t.with_timezone(&Utc)),
+        },
+        _ => None,
+    }
+}
+
+/// The same source id always gives the same experience id
+fn stable_id(source_id: &str) -> Uuid {
+    let digest = Sha256::digest(source_id.as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    uuid::Builder::from_random_bytes(bytes).into_uuid()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(json: Value) -> ImportMapping {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_csv_rows_become_experiences() {
+        let mapping = mapping(serde_json::json!({
+            "format": "csv",
+            "id": {"path": "order"},
+            "id_domain": {"value": "ebay"},
+            "agent_id": {"path": "seller", "transforms": [{"op": "trim"}, {"op": "lowercase"}]},
+            "invested_volume": {"path": "cents", "transforms": [{"op": "scale", "factor": 0.01}]},
+            "pv_roi": {"path": "stars", "transforms": [{"op": "rescale", "from": [1, 5], "to": [0.5, 1.5]}]},
+            "timestamp": {"path": "date", "transforms": [{"op": "parse_time", "format": "%d.%m.%Y"}]}
+        }));
+        let input = b"order,seller,cents,stars,date\n1, Alice ,2500,5,01.02.2015\n2,bob,1000,three,02.02.2015\n";
+
+        let mapped = mapping.apply(input, Utc::now()).unwrap();
+        assert_eq!(mapped.experiences.len(), 1);
+        let experience = &mapped.experiences[0];
+        assert_eq!((experience.id_domain.as_str(), experience.agent_id.as_str()), ("ebay", "alice"));
+        assert_eq!(experience.invested_volume, 25.0);
+        assert_eq!(experience.pv_roi, 1.5);
+        assert_eq!(experience.timestamp.to_rfc3339(), "2015-02-01T00:00:00+00:00");
+        assert_eq!(experience.id, mapping.apply(input, Utc::now()).unwrap().experiences[0].id);
+
+        assert_eq!(mapped.errors.len(), 1);
+        assert_eq!(mapped.errors[0].record, 1);
+    }
+
+    #[test]
+    fn test_nested_json_records_with_lookup_domains() {
+        let mapping = mapping(serde_json::json!({
+            "records": "export.reviews",
+            "id_domain": {"path": "kind", "transforms": [{"op": "lookup", "values": {"shop": "domain"}}]},
+            "agent_id": {"path": "target.name"},
+            "invested_volume": {"path": "amount"},
+            "return_value": {"path": "got"},
+            "notes": {"path": "comment", "transforms": [{"op": "default", "value": "imported"}]},
+            "keep_record": true
+        }));
+        let input = br#"{"export": {"reviews": [{"kind": "shop", "target": {"name": "example.com"}, "amount": 200, "got": 240}]}}"#;
+
+        let mapped = mapping.apply(input, Utc::now()).unwrap();
+        assert!(mapped.errors.is_empty());
+        let experience = &mapped.experiences[0];
+        assert_eq!(experience.id_domain, "domain");
+        assert!((experience.pv_roi - 1.2).abs() < 1e-9);
+        assert_eq!(experience.notes.as_deref(), Some("imported"));
+        assert!(experience.data.is_some());
+    }
+
+    #[test]
+    fn test_mapping_needs_one_source_for_the_roi() {
+        let both = mapping(serde_json::json!({
+            "id_domain": {"value": "x"},
+            "agent_id": {"path": "a"},
+            "invested_volume": {"path": "v"},
+            "pv_roi": {"path": "r"},
+            "return_value": {"path": "r"}
+        }));
+        assert!(both.check().is_err());
+
+        let mut wide = mapping(serde_json::json!({
+            "format": "csv",
+            "id_domain": {"value": "x"},
+            "agent_id": {"path": "a"},
+            "invested_volume": {"path": "v"},
+            "pv_roi": {"path": "r"}
+        }));
+        wide.delimiter = Some('；');
+        assert!(wide.check().is_err());
+    }
+}
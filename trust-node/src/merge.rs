@@ -0,0 +1,148 @@
+use crate::journal::remove_database;
+use crate::storage::{SqliteStorage, Storage};
+use crate::types::{ExperienceChange, TrustExperience};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tracing::info;
+
+/// Recorded as the author of experience edits a merge makes
+pub const MERGE_AUTHOR: &str = "merge";
+
+/// What merging another database changed here
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MergeSummary {
+    pub experiences_added: usize,
+    pub experiences_updated: usize,
+    pub experiences_removed: usize,
+    /// Experiences the other side still has but that were deleted here afterwards
+    pub deletions_kept: usize,
+    /// Experiences both sides changed, where the local version was the newer one
+    pub conflicts_kept_local: usize,
+    pub peers_added: usize,
+}
+
+/// Merge the experiences and peers of `other`, a database of the same user from another device, into `storage`
+///
+/// Experiences are matched by id. When both sides have one with different contents, the side that edited it
+/// last wins. A deletion on either side wins over versions last edited before it, and loses to later edits.
+/// Peers only known to `other` are added; peers both know keep their local settings. The changes are written
+/// in one transaction, so a failed merge leaves `storage` as it was.
+pub async fn merge_from<S, O>(storage: &S, other: &O) -> Result<MergeSummary>
+where
+    S: Storage + ?Sized,
+    O: Storage + ?Sized,
+{
+    let mut summary = MergeSummary::default();
+    let local: HashMap<_, _> = storage.get_all_experiences().await?.into_iter().map(|e| (e.id, e)).collect();
+    let theirs: HashMap<_, _> = other.get_all_experiences().await?.into_iter().map(|e| (e.id, e)).collect();
+    let local_deletions = deletion_times(storage).await?;
+    let their_deletions = deletion_times(other).await?;
+
+    let (mut added, mut updated) = (Vec::new(), Vec::new());
+    for (id, their_experience) in &theirs {
+        let id_str = id.to_string();
+        match local.get(id) {
+            None => {
+                let deleted_here = match local_deletions.get(id) {
+                    Some(deleted_at) => last_edit(other, &id_str).await? <= Some(*deleted_at),
+                    None => false,
+                };
+                if deleted_here {
+                    summary.deletions_kept += 1;
+                } else {
+                    added.push(their_experience.clone());
+                }
+            }
+            Some(local_experience) => {
+                if same(local_experience, their_experience)? {
+                    continue;
+                }
+                if last_edit(other, &id_str).await? > last_edit(storage, &id_str).await? {
+                    updated.push(their_experience.clone());
+                } else {
+                    summary.conflicts_kept_local += 1;
+                }
+            }
+        }
+    }
+
+    let mut removed = Vec::new();
+    for (id, deleted_at) in &their_deletions {
+        if local.contains_key(id) && !theirs.contains_key(id) {
+            let id_str = id.to_string();
+            if last_edit(storage, &id_str).await? <= Some(*deleted_at) {
+                removed.push(id_str);
+            }
+        }
+    }
+
+    let known: HashSet<String> = storage.get_peers().await?.into_iter().map(|peer| peer.peer_id).collect();
+    let new_peers: Vec<_> = other
+        .get_peers()
+        .await?
+        .into_iter()
+        .filter(|peer| !known.contains(&peer.peer_id))
+        .collect();
+    summary.experiences_added = added.len();
+    summary.experiences_updated = updated.len();
+    summary.experiences_removed = removed.len();
+    summary.peers_added = new_peers.len();
+    // Nothing is written until everything is decided, and then all of it or none
+    storage.merge_records(added, updated, &removed, new_peers, MERGE_AUTHOR).await?;
+
+    info!(
+        "Merged {} new, {} updated and {} removed experiences and {} peers",
+        summary.experiences_added, summary.experiences_updated, summary.experiences_removed, summary.peers_added
+    );
+    Ok(summary)
+}
+
+/// Merge the database file at `other` through a copy at `scratch`, since opening a database brings its schema
+/// up to date and the other device's file should stay as it is
+pub async fn merge_database<S: Storage + ?Sized>(storage: &S, other: &Path, scratch: &Path) -> Result<MergeSummary> {
+    remove_database(scratch)?;
+    // Commits the other node hasn't checkpointed yet are still in its write-ahead log
+    for suffix in ["", "-wal"] {
+        let (mut from, mut to) = (other.as_os_str().to_owned(), scratch.as_os_str().to_owned());
+        from.push(suffix);
+        to.push(suffix);
+        if suffix.is_empty() || Path::new(&from).is_file() {
+            std::fs::copy(&from, &to)?;
+        }
+    }
+    let result = async {
+        let other = SqliteStorage::new(scratch).await?;
+        merge_from(storage, &other).await
+    }
+    .await;
+    remove_database(scratch)?;
+    result
+}
+
+/// When each experience was last deleted
+async fn deletion_times<S: Storage + ?Sized>(storage: &S) -> Result<HashMap<uuid::Uuid, DateTime<Utc>>> {
+    // Oldest first, so later deletions of a re-added experience overwrite earlier ones
+    Ok(storage
+        .get_experience_deletions()
+        .await?
+        .into_iter()
+        .map(|revision| (revision.experience_id, revision.changed_at))
+        .collect())
+}
+
+/// When the experience was last edited; None if it's as first recorded
+async fn last_edit<S: Storage + ?Sized>(storage: &S, experience_id: &str) -> Result<Option<DateTime<Utc>>> {
+    Ok(storage
+        .get_experience_history(experience_id)
+        .await?
+        .into_iter()
+        .find(|revision| revision.change == ExperienceChange::Update)
+        .map(|revision| revision.changed_at))
+}
+
+fn same(a: &TrustExperience, b: &TrustExperience) -> Result<bool> {
+    Ok(serde_json::to_value(a)? == serde_json::to_value(b)?)
+}
@@ -1,28 +1,159 @@
-use crate::api::run_api_server;
-use crate::protocols::{TrustCodec, TrustProtocol, merge_responses, TrustResponseInternal};
-use crate::query_engine::QueryEngine;
-use crate::storage::Storage;
-use crate::types::{Peer, TrustDataExport, TrustExperience, TrustQuery, TrustResponse, TrustScore};
+use crate::anomaly::{AnomalyDetector, AnomalyReport};
+use crate::availability::{AgentFilter, AvailabilityExchange, AVAILABILITY_PROTOCOL};
+use crate::eigentrust::{GlobalAggregator, GlobalTrust};
+use crate::backup::{restore_from_file, BackupScheduler, BackupStatus, RestoreSummary};
+use crate::events::{event_channel, EventSender, NodeEvent, PeerEvent, PeerEventKind, PeerEventLog, PEER_EVENT_HISTORY};
+use crate::identity::{IdentityRotation, KeyTransition, RotationAck, RotationNotice, ROTATION_PROTOCOL};
+use crate::import::ImportBatch;
+use crate::inbound::FairQueue;
+use crate::keep_alive;
+use crate::quarantine::{Quarantine, QuarantinedPeer, Violation};
+use crate::notify::Notifier;
+use crate::runtime_metrics::{self, RuntimeProbe};
+use crate::reputation::{self, ClaimedAgent, PeerOpinion, ReputationAnswer, ReputationPolicy, ReputationQuery, REPUTATION_PROTOCOL};
+use crate::invite::{FriendRequest, FriendResponse, InvalidInvite, Invite, IssuedInvite, PeeringAck, PeeringAnswer, PeeringMessage, DEFAULT_INVITE_TTL, INVITE_PROTOCOL, MAX_INVITE_TTL, PEERING_PROTOCOL};
+use crate::clock::SharedClock;
+use crate::config::{DialPriority, NodeConfig, P2pTransport};
+use crate::graph::{self, TrustGraph};
+use crate::protocols::{TrustCodec, TrustProtocol, agents_after_continuation, wire_size, TrustResponseInternal};
+use crate::query_engine::{inflation_by_domain, QueryEngine};
+use crate::storage::{Storage, StorageError, StorageResult, QUALITY_SET};
+use crate::telemetry;
+use crate::watchlist::Watchlist;
+use crate::types::{AgentClaim, AgentIdForm, AgentIdentifier, AgentQueries, AgentScore, Aggregator, CacheStats, CachedTrustScore, COMPACTED_PEER, is_valid_correlation_id, CappedInfluence, ScoreBounds, ScoreClamping, Discounting, DomainDefaults, ExperienceFilter, ExperiencePage, Freshness, ExperienceRevision, ExperienceTemplate, ExperienceUpdate, IdempotentResponse, ImportOutcome, ImportRecordKind, ImportRecordResult, ImportStrategy, ImportSummary, InflationIndex, InfluenceCaps, MaintenanceReport, MinEvidence, AnswerPrivacy, Peer, PeerProposal, PeerReciprocity, PeeringAnswerOut, PeerRequest, PeerRequestStatus, PeerStatus, PeerTraffic, PeerUpdate, PendingExperience, ProposedPeer, QualityDecay, QualityRevision, PeerTiming, QueryLimits, QueryStats, QueryTiming, RuntimeConfig, RuntimeStats, RuntimeConfigUpdate, ScoreDepth, ScoringOptions, SharingPrecision, SimulationResult, StorageStats, TrustDataExport, TrustExperience, TrustQuery, TrustResponse, TrustScore, TrustSimulation, WatchRequest, WatchedAgent, split_peer_multiaddr, DEFAULT_MAX_DEPTH};
 use anyhow::Result;
 use chrono::Utc;
+use futures::stream::BoxStream;
 use futures::StreamExt;
 use libp2p::{
-    identity, kad, noise, request_response::{self, Event as ReqResEvent, Message, ResponseChannel},
-    swarm::{NetworkBehaviour, SwarmEvent}, tcp, yamux, Multiaddr, PeerId, Swarm, SwarmBuilder
+    core::{transport::MemoryTransport, upgrade, Transport as _},
+    allow_block_list, identity, kad, noise,
+    request_response::{self, Event as ReqResEvent, InboundFailure, Message, OutboundFailure, ResponseChannel},
+    swarm::{dial_opts::DialOpts, ConnectionId, DialError, NetworkBehaviour, SwarmEvent}, tcp, yamux, Multiaddr, PeerId, Swarm, SwarmBuilder
 };
-use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::num::NonZeroU8;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
-use tokio::sync::{mpsc, oneshot};
-use tokio::task::JoinHandle;
-use tokio::time::{interval, Duration as TokioDuration};
-use tracing::{debug, info, warn};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::{interval, interval_at, sleep_until, Duration as TokioDuration, Instant, MissedTickBehavior};
+use tracing::field::Empty;
+use uuid::Uuid;
+use tracing::{debug, info, info_span, warn, Instrument, Span};
 
 #[derive(NetworkBehaviour)]
 pub struct TrustBehaviour {
     request_response: request_response::Behaviour<TrustCodec>,
     kademlia: kad::Behaviour<kad::store::MemoryStore>,
     identify: libp2p::identify::Behaviour,
+    invites: request_response::json::Behaviour<FriendRequest, FriendResponse>,
+    rotations: request_response::json::Behaviour<RotationNotice, RotationAck>,
+    peering: request_response::json::Behaviour<PeeringMessage, PeeringAck>,
+    reputation: request_response::json::Behaviour<ReputationQuery, ReputationAnswer>,
+    availability: request_response::json::Behaviour<AvailabilityExchange, AvailabilityExchange>,
+    keep_alive: keep_alive::Behaviour,
+    /// Quarantined peers, who can neither connect nor be dialed
+    blocked: allow_block_list::Behaviour<allow_block_list::BlockedPeers>,
+}
+
+/// `request_timeout` bounds every outgoing request; trust queries are usually cut off sooner, see `peer_cutoff`
+fn trust_behaviour(key: &identity::Keypair, codec: TrustCodec, request_timeout: Duration) -> TrustBehaviour {
+    let local_peer_id = PeerId::from(key.public());
+    let kademlia = kad::Behaviour::new(
+        local_peer_id,
+        kad::store::MemoryStore::new(local_peer_id),
+    );
+
+    let request_response = request_response::Behaviour::with_codec(
+        codec,
+        [(TrustProtocol, request_response::ProtocolSupport::Full)],
+        request_response::Config::default()
+            .with_request_timeout(request_timeout),
+    );
+
+    let identify = libp2p::identify::Behaviour::new(
+        libp2p::identify::Config::new("/repeer/1.0.0".to_string(), key.public())
+    );
+
+    let invites = request_response::json::Behaviour::new(
+        [(INVITE_PROTOCOL, request_response::ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(request_timeout),
+    );
+
+    let rotations = request_response::json::Behaviour::new(
+        [(ROTATION_PROTOCOL, request_response::ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(request_timeout),
+    );
+
+    let peering = request_response::json::Behaviour::new(
+        [(PEERING_PROTOCOL, request_response::ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(request_timeout),
+    );
+
+    let reputation = request_response::json::Behaviour::new(
+        [(REPUTATION_PROTOCOL, request_response::ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(request_timeout),
+    );
+
+    let availability = request_response::json::Behaviour::new(
+        [(AVAILABILITY_PROTOCOL, request_response::ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(request_timeout),
+    );
+
+    TrustBehaviour {
+        request_response,
+        kademlia,
+        identify,
+        invites,
+        rotations,
+        peering,
+        reputation,
+        availability,
+        keep_alive: keep_alive::Behaviour::default(),
+        blocked: allow_block_list::Behaviour::default(),
+    }
+}
+
+/// What the swarm is built with besides key, transport and codec, kept for rebuilding it under a new key
+#[derive(Debug, Clone, Copy)]
+struct SwarmTimeouts {
+    idle: Duration,
+    /// The longest any peer is waited for
+    request: Duration,
+}
+
+fn build_swarm(
+    key: identity::Keypair,
+    transport: P2pTransport,
+    codec: TrustCodec,
+    timeouts: SwarmTimeouts,
+) -> Result<Swarm<TrustBehaviour>> {
+    Ok(match transport {
+        P2pTransport::Tcp => SwarmBuilder::with_existing_identity(key)
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default(),
+                noise::Config::new,
+                yamux::Config::default,
+            )?
+            .with_behaviour(|key| trust_behaviour(key, codec.clone(), timeouts.request))?
+            .with_swarm_config(|c| c.with_idle_connection_timeout(timeouts.idle))
+            .build(),
+        P2pTransport::Memory => SwarmBuilder::with_existing_identity(key)
+            .with_tokio()
+            .with_other_transport(|key| {
+                Ok::<_, Box<dyn std::error::Error + Send + Sync>>(
+                    MemoryTransport::default()
+                        .upgrade(upgrade::Version::V1)
+                        .authenticate(noise::Config::new(key)?)
+                        .multiplex(yamux::Config::default()),
+                )
+            })?
+            .with_behaviour(|key| trust_behaviour(key, codec.clone(), timeouts.request))?
+            .with_swarm_config(|c| c.with_idle_connection_timeout(timeouts.idle))
+            .build(),
+    })
 }
 
 pub enum NodeCommand {
@@ -30,31 +161,193 @@ pub enum NodeCommand {
         experience: TrustExperience,
         response: oneshot::Sender<Result<()>>,
     },
+    AddExperiences {
+        experiences: Vec<TrustExperience>,
+        response: oneshot::Sender<Result<()>>,
+    },
     GetExperiences {
         id_domain: String,
         agent_id: String,
         response: oneshot::Sender<Result<Vec<TrustExperience>>>,
     },
+    /// A page of experiences matching the filter, with the number of matches across all pages
+    FindExperiences {
+        filter: ExperienceFilter,
+        response: oneshot::Sender<Result<ExperiencePage>>,
+    },
+    UpdateExperience {
+        experience_id: String,
+        update: ExperienceUpdate,
+        response: oneshot::Sender<Result<TrustExperience>>,
+    },
+    GetExperienceHistory {
+        experience_id: String,
+        response: oneshot::Sender<Result<Vec<ExperienceRevision>>>,
+    },
+    RevertExperience {
+        experience_id: String,
+        revision: i64,
+        response: oneshot::Sender<Result<TrustExperience>>,
+    },
     RemoveExperience {
         experience_id: String,
         response: oneshot::Sender<Result<()>>,
     },
+    /// Record an investment now and its outcome later with SettleExperience
+    AddPendingExperience {
+        pending: PendingExperience,
+        response: oneshot::Sender<Result<()>>,
+    },
+    GetPendingExperiences {
+        response: oneshot::Sender<Result<Vec<PendingExperience>>>,
+    },
+    /// Turn a pending experience into a scored one, with pv_roi from the return realized by now;
+    /// without a return, one from a template settles at the expected return
+    SettleExperience {
+        experience_id: String,
+        return_value: Option<f64>,
+        discounting: Option<Discounting>,
+        discount_rate: Option<f64>,
+        response: oneshot::Sender<Result<TrustExperience>>,
+    },
+    /// How experiences in each domain are discounted, from the domain registry or the node's default
+    GetDiscounting {
+        id_domains: Vec<String>,
+        response: oneshot::Sender<Result<HashMap<String, Discounting>>>,
+    },
+    /// Start a recurring experience; occurrences already due are added right away
+    AddExperienceTemplate {
+        template: ExperienceTemplate,
+        response: oneshot::Sender<Result<()>>,
+    },
+    GetExperienceTemplates {
+        response: oneshot::Sender<Result<Vec<ExperienceTemplate>>>,
+    },
+    /// Stop a recurring experience; what it already added stays pending
+    RemoveExperienceTemplate {
+        template_id: String,
+        response: oneshot::Sender<Result<()>>,
+    },
     AddPeer {
         peer: Peer,
         response: oneshot::Sender<Result<()>>,
     },
+    /// Issue a signed token a friend's node can redeem to add us, and us them
+    CreateInvite {
+        /// How we introduce ourselves to whoever redeems it
+        name: Option<String>,
+        /// Addresses to hand out instead of the ones we listen on, e.g. a public one behind NAT
+        addresses: Option<Vec<String>>,
+        ttl: Option<chrono::Duration>,
+        response: oneshot::Sender<Result<IssuedInvite>>,
+    },
+    /// Add the inviter from a token and ask them to add us back
+    RedeemInvite {
+        token: String,
+        /// Defaults to the name the inviter suggested
+        name: Option<String>,
+        handle: Option<String>,
+        recommender_quality: f64,
+        /// How we introduce ourselves to the inviter
+        introduce_as: Option<String>,
+        response: oneshot::Sender<Result<Peer>>,
+    },
+    /// Ask a node to add each other as peers; it's added here once its user accepts
+    ProposePeering {
+        peer_id: String,
+        addresses: Vec<String>,
+        /// Defaults to the name the other side introduces itself with
+        name: Option<String>,
+        recommender_quality: f64,
+        tags: Vec<String>,
+        /// How we introduce ourselves
+        introduce_as: Option<String>,
+        note: Option<String>,
+        response: oneshot::Sender<Result<()>>,
+    },
+    /// Agent ids claimed as our own
+    GetAgentClaims {
+        response: oneshot::Sender<Result<Vec<AgentClaim>>>,
+    },
+    /// Claim an agent id, getting the challenge to publish as proof
+    ClaimAgent {
+        id_domain: String,
+        agent_id: String,
+        response: oneshot::Sender<Result<AgentClaim>>,
+    },
+    /// Fetch the proof URL and mark the claim verified if it shows the challenge
+    VerifyAgentClaim {
+        id_domain: String,
+        agent_id: String,
+        proof_url: String,
+        response: oneshot::Sender<Result<AgentClaim>>,
+    },
+    RemoveAgentClaim {
+        id_domain: String,
+        agent_id: String,
+        response: oneshot::Sender<Result<()>>,
+    },
+    /// Ask connected peers for their own scores of our verified agents, one opinion per peer
+    QueryOwnReputation {
+        response: oneshot::Sender<Result<Vec<PeerOpinion>>>,
+    },
+    /// Move to a new key and tell connected peers, who keep us listed with the quality we had
+    RotateIdentity {
+        response: oneshot::Sender<Result<IdentityRotation>>,
+    },
+    AddPeers {
+        peers: Vec<Peer>,
+        strategy: ImportStrategy,
+        dry_run: bool,
+        response: oneshot::Sender<Result<ImportSummary>>,
+    },
+    /// Peers with the quality their recommendations are weighted with right now
     GetPeers {
-        response: oneshot::Sender<Result<Vec<Peer>>>,
+        response: oneshot::Sender<Result<Vec<PeerStatus>>>,
+    },
+    UpdatePeer {
+        peer_id: String,
+        update: PeerUpdate,
+        response: oneshot::Sender<Result<Peer>>,
     },
     UpdatePeerQuality {
         peer_id: String,
         quality: f64,
+        /// Kept in the quality history; defaults to "set"
+        reason: Option<String>,
         response: oneshot::Sender<Result<()>>,
     },
+    GetQualityHistory {
+        peer_id: String,
+        response: oneshot::Sender<Result<Vec<QualityRevision>>>,
+    },
     RemovePeer {
         peer_id: String,
         response: oneshot::Sender<Result<()>>,
     },
+    /// Peers outside the peer list that queried us, most recently seen first
+    GetPeerRequests {
+        response: oneshot::Sender<Result<Vec<PeerRequest>>>,
+    },
+    /// Add a peer from the requests to the peer list
+    AcceptPeerRequest {
+        peer_id: String,
+        name: Option<String>,
+        quality: Option<f64>,
+        tags: Vec<String>,
+        response: oneshot::Sender<Result<Peer>>,
+    },
+    /// Ignore or block a peer, or put it back into the inbox
+    SetPeerRequestStatus {
+        peer_id: String,
+        status: PeerRequestStatus,
+        response: oneshot::Sender<Result<()>>,
+    },
+    /// Forget a request; the peer shows up as pending again when it next queries
+    RemovePeerRequest {
+        peer_id: String,
+        response: oneshot::Sender<Result<()>>,
+    },
     QueryTrust {
         query: TrustQuery,
         response: oneshot::Sender<Result<TrustResponse>>,
@@ -62,88 +355,497 @@ pub enum NodeCommand {
     GetConnectedPeers {
         response: oneshot::Sender<Result<Vec<String>>>,
     },
+    GetQuarantinedPeers {
+        response: oneshot::Sender<Result<Vec<QuarantinedPeer>>>,
+    },
+    /// Let a quarantined peer back in before its cooling-off period is over
+    ReleaseQuarantinedPeer {
+        peer_id: String,
+        response: oneshot::Sender<Result<()>>,
+    },
     TriggerPeerDiscovery {
         response: oneshot::Sender<Result<()>>,
     },
     ExportTrustData {
-        response: oneshot::Sender<Result<TrustDataExport>>,
+        /// Export a snapshot of what was known at this time, with the merged scores it gave
+        point_in_time: Option<chrono::DateTime<Utc>>,
+        response: oneshot::Sender<Result<ExportStream>>,
     },
     ImportTrustData {
         data: TrustDataExport,
-        overwrite: bool,
+        strategy: ImportStrategy,
+        dry_run: bool,
+        response: oneshot::Sender<Result<ImportSummary>>,
+    },
+    /// One batch of a streamed import, answered with the outcome of just this batch
+    ImportBatch {
+        batch: ImportBatch,
+        strategy: ImportStrategy,
+        dry_run: bool,
+        response: oneshot::Sender<Result<ImportSummary>>,
+    },
+    /// The response kept for a write retried with the same Idempotency-Key; None if there's none or keys are off
+    GetIdempotentResponse {
+        endpoint: String,
+        key: String,
+        response: oneshot::Sender<Result<Option<IdempotentResponse>>>,
+    },
+    /// Keep the response to a write sent with an Idempotency-Key for the idempotency TTL
+    RecordIdempotentResponse {
+        snapshot: IdempotentResponse,
         response: oneshot::Sender<Result<()>>,
     },
     GetSelfPeerId {
         response: oneshot::Sender<Result<String>>,
     },
+    /// Addresses the swarm is listening on; empty until the transport is bound
+    GetListenAddresses {
+        response: oneshot::Sender<Result<Vec<String>>>,
+    },
+    /// Write out what's only kept in memory between flushes, before the process exits
+    FlushForShutdown {
+        response: oneshot::Sender<Result<()>>,
+    },
+    GetRuntimeConfig {
+        response: oneshot::Sender<Result<RuntimeConfig>>,
+    },
+    /// Apply tunables live, answering with the full config in effect afterwards
+    UpdateRuntimeConfig {
+        update: RuntimeConfigUpdate,
+        response: oneshot::Sender<Result<RuntimeConfig>>,
+    },
     ClearPeers {
         response: oneshot::Sender<Result<()>>,
     },
     ClearExperiences {
         response: oneshot::Sender<Result<()>>,
     },
+    GetDomainDefaults {
+        response: oneshot::Sender<Result<Vec<DomainDefaults>>>,
+    },
+    SetDomainDefaults {
+        defaults: DomainDefaults,
+        response: oneshot::Sender<Result<()>>,
+    },
+    RemoveDomainDefaults {
+        id_domain: String,
+        response: oneshot::Sender<Result<()>>,
+    },
+    GetInflationIndexes {
+        response: oneshot::Sender<Result<Vec<InflationIndex>>>,
+    },
+    SetInflationIndex {
+        index: InflationIndex,
+        response: oneshot::Sender<Result<()>>,
+    },
+    RemoveInflationIndex {
+        currency: String,
+        response: oneshot::Sender<Result<()>>,
+    },
+    GetSharingPrecision {
+        response: oneshot::Sender<Result<Vec<SharingPrecision>>>,
+    },
+    SetSharingPrecision {
+        precision: SharingPrecision,
+        response: oneshot::Sender<Result<()>>,
+    },
+    /// `subject` is a peer id or `circle:<tag>`
+    RemoveSharingPrecision {
+        subject: String,
+        response: oneshot::Sender<Result<()>>,
+    },
+    GetWatchlist {
+        response: oneshot::Sender<Result<Vec<WatchedAgent>>>,
+    },
+    /// Start or change watching an agent, with its current merged score as the baseline
+    WatchAgent {
+        id_domain: String,
+        agent_id: String,
+        request: WatchRequest,
+        response: oneshot::Sender<Result<WatchedAgent>>,
+    },
+    UnwatchAgent {
+        id_domain: String,
+        agent_id: String,
+        response: oneshot::Sender<Result<()>>,
+    },
+    GetStorageStats {
+        response: oneshot::Sender<Result<StorageStats>>,
+    },
+    GetCacheStats {
+        response: oneshot::Sender<Result<CacheStats>>,
+    },
+    GetQueryStats {
+        response: oneshot::Sender<Result<QueryStats>>,
+    },
+    GetRuntimeStats {
+        response: oneshot::Sender<Result<RuntimeStats>>,
+    },
+    /// Queries and answers exchanged with each peer, biggest takers first
+    GetReciprocity {
+        response: oneshot::Sender<Result<Vec<PeerReciprocity>>>,
+    },
+    ClearCache {
+        response: oneshot::Sender<Result<()>>,
+    },
+    MaintainDatabase {
+        response: oneshot::Sender<Result<MaintenanceReport>>,
+    },
+    GetBackupStatus {
+        response: oneshot::Sender<Result<BackupStatus>>,
+    },
+    SimulateTrust {
+        simulation: TrustSimulation,
+        response: oneshot::Sender<Result<SimulationResult>>,
+    },
+    GetAnomalies {
+        response: oneshot::Sender<Result<AnomalyReport>>,
+    },
+    GetGlobalTrust {
+        response: oneshot::Sender<Result<GlobalTrust>>,
+    },
+    SubscribeEvents {
+        response: oneshot::Sender<Result<broadcast::Receiver<NodeEvent>>>,
+    },
+    /// The trust network as seen from this node, with `user` labelling the node itself
+    GetTrustGraph {
+        user: String,
+        response: oneshot::Sender<Result<TrustGraph>>,
+    },
+    /// Recent peer lifecycle events, oldest first, only those about `peer_id` if given
+    GetPeerEvents {
+        peer_id: Option<String>,
+        response: oneshot::Sender<Result<Vec<PeerEvent>>>,
+    },
+    RestoreBackup {
+        path: PathBuf,
+        response: oneshot::Sender<Result<RestoreSummary>>,
+    },
+}
+
+/// Which watched agents a command can move the merged scores of
+enum Rescore {
+    /// These (id_domain, agent_id) pairs, as the command names them
+    Agents(Vec<(String, String)>),
+    /// The agent of this experience, before and after the command
+    Experience(String),
+    /// Every agent of this domain
+    Domain(String),
+    All,
+}
+
+/// Which of the peering messages kept for a peer a request carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PeeringKind {
+    Proposal,
+    Answer,
+}
+
+impl NodeCommand {
+    /// Which watched agents have to be looked at again after the command; None if it can't move merged scores
+    fn rescore(&self) -> Option<Rescore> {
+        let agent = |experience: &TrustExperience| (experience.id_domain.clone(), experience.agent_id.clone());
+        match self {
+            NodeCommand::AddExperience { experience, .. } => Some(Rescore::Agents(vec![agent(experience)])),
+            NodeCommand::AddExperiences { experiences, .. } => Some(Rescore::Agents(experiences.iter().map(agent).collect())),
+            NodeCommand::UpdateExperience { experience_id, .. }
+            | NodeCommand::RevertExperience { experience_id, .. }
+            | NodeCommand::RemoveExperience { experience_id, .. }
+            | NodeCommand::SettleExperience { experience_id, .. } => Some(Rescore::Experience(experience_id.clone())),
+            NodeCommand::ImportTrustData { dry_run: true, .. } | NodeCommand::ImportBatch { dry_run: true, .. } => None,
+            // Imports that only add experiences touch just their agents; replacing ones may move any agent
+            NodeCommand::ImportTrustData { data: TrustDataExport { experiences, peers, .. }, strategy, .. }
+            | NodeCommand::ImportBatch { batch: ImportBatch { experiences, peers }, strategy, .. }
+                if peers.is_empty() && !matches!(strategy, ImportStrategy::NewerWins | ImportStrategy::Overwrite) =>
+            {
+                Some(Rescore::Agents(experiences.iter().map(agent).collect()))
+            }
+            NodeCommand::SetDomainDefaults { defaults, .. } => Some(Rescore::Domain(defaults.id_domain.clone())),
+            NodeCommand::RemoveDomainDefaults { id_domain, .. } => Some(Rescore::Domain(id_domain.clone())),
+            // Peers weigh into every merged score, and an index can apply to experiences of any agent
+            NodeCommand::AddPeer { .. }
+            | NodeCommand::AddPeers { .. }
+            | NodeCommand::RedeemInvite { .. }
+            | NodeCommand::UpdatePeer { .. }
+            | NodeCommand::UpdatePeerQuality { .. }
+            | NodeCommand::RemovePeer { .. }
+            | NodeCommand::AcceptPeerRequest { .. }
+            | NodeCommand::ImportTrustData { .. }
+            | NodeCommand::ImportBatch { .. }
+            | NodeCommand::ClearPeers { .. }
+            | NodeCommand::ClearExperiences { .. }
+            | NodeCommand::SetInflationIndex { .. }
+            | NodeCommand::RemoveInflationIndex { .. }
+            | NodeCommand::RestoreBackup { .. } => Some(Rescore::All),
+            _ => None,
+        }
+    }
+}
+
+/// Export whose experiences are read lazily, so /export can stream large databases
+pub struct ExportStream {
+    pub peers: Vec<Peer>,
+    pub experiences: BoxStream<'static, StorageResult<TrustExperience>>,
+    pub point_in_time: Option<chrono::DateTime<Utc>>,
+    pub scores: Vec<AgentScore>,
 }
 
 pub struct TrustNode<S: Storage> {
     swarm: Swarm<TrustBehaviour>,
     storage: Arc<S>,
-    query_engine: QueryEngine<S>,
+    query_engine: Arc<QueryEngine<S>>,
     command_rx: mpsc::Receiver<NodeCommand>,
+    /// Results of work spawned off the swarm loop
+    loop_tx: mpsc::Sender<LoopEvent>,
+    loop_rx: mpsc::Receiver<LoopEvent>,
+    /// Peer queries waiting for a worker, and how many workers are gathering scores right now
+    inbound_queue: FairQueue<PeerId, (TrustQuery, ResponseChannel<TrustResponse>)>,
+    inbound_active: usize,
+    inbound_workers: usize,
     peers: HashMap<String, Peer>,
     pending_requests: HashMap<request_response::OutboundRequestId, Arc<Mutex<PendingRequest>>>,
+    backup_status: Arc<RwLock<BackupStatus>>,
+    anomalies: Arc<RwLock<AnomalyReport>>,
+    global_trust: Arc<RwLock<GlobalTrust>>,
+    events: EventSender,
+    peer_events: PeerEventLog,
+    watchlist: Watchlist,
+    min_evidence: MinEvidence,
+    answer_privacy: AnswerPrivacy,
+    reputation_policy: ReputationPolicy,
+    /// Reputation requests in flight, by the round they belong to
+    reputation_asks: HashMap<request_response::OutboundRequestId, Uuid>,
+    reputation_rounds: HashMap<Uuid, ReputationRound>,
+    /// How precise the scores answered to particular peers and circles are
+    sharing_precision: Vec<SharingPrecision>,
+    influence_caps: InfluenceCaps,
+    /// Registered agent id forms by domain, kept in memory as every query and experience passes through them
+    agent_id_forms: HashMap<String, AgentIdForm>,
+    hop_damping: f64,
+    quality_decay: Option<QualityDecay>,
+    query_limits: QueryLimits,
+    discovery_interval: Duration,
+    /// Most peers asked per query; 0 asks all connected peers
+    max_fanout: usize,
+    /// Moving average of how long each peer took to answer a query
+    peer_latency: HashMap<PeerId, Duration>,
+    /// Signs invites; the same key the swarm authenticates with
+    identity: identity::Keypair,
+    /// Where a rotated key is saved; None for nodes whose identity lasts one run
+    identity_file: Option<PathBuf>,
+    /// What the swarm is rebuilt from when the key changes
+    transport: P2pTransport,
+    codec: TrustCodec,
+    swarm_timeouts: SwarmTimeouts,
+    /// How long a peer whose latency isn't known yet is waited for
+    request_timeout: Duration,
+    listen_address: Multiaddr,
+    /// Listen addresses connected peers told us about, so we can find them again under a new key
+    announced_addresses: HashMap<PeerId, Vec<Multiaddr>>,
+    /// Invites issued and not yet redeemed, with when they expire
+    open_invites: HashMap<Uuid, chrono::DateTime<Utc>>,
+    /// Peers we proposed peering to, as they're added once they accept; stored, so they survive restarts
+    proposed_peers: HashMap<PeerId, ProposedPeer>,
+    /// Our answers to peering proposals, stored and sent on every connection until the proposer confirms one
+    peering_answers: HashMap<PeerId, PeeringAnswerOut>,
+    /// Peering messages on their way, so a new connection doesn't send them a second time
+    peering_in_flight: HashMap<request_response::OutboundRequestId, (PeerId, PeeringKind)>,
+    query_stats: QueryStats,
+    /// Trust protocol traffic per peer id, kept in memory like query_stats
+    traffic: HashMap<String, PeerTraffic>,
+    /// How often each agent was asked about, halved after every refresh so it follows recent interest
+    query_counts: HashMap<(String, String), u64>,
+    /// Queries per agent, and when the last came in, not yet written to storage for the startup warm-up
+    agent_queries: HashMap<(String, String), (u64, chrono::DateTime<Utc>)>,
+    last_activity: Instant,
+    refresh_interval: Option<Duration>,
+    /// Best peers asked during a prefetch; 0 asks all good enough
+    prefetch_peers: usize,
+    warm_up_agents: usize,
+    availability_interval: Option<Duration>,
+    /// What we last told peers we can answer about
+    own_filter: Option<AgentFilter>,
+    /// What peers told us they can answer about, and when
+    peer_filters: HashMap<PeerId, (AgentFilter, Instant)>,
+    /// Peers that answered without a score for an agent, by (peer, id_domain, agent_id), and until when that's believed
+    no_data: HashMap<(PeerId, String, String), Instant>,
+    no_data_ttl: Duration,
+    idempotency_ttl: Option<Duration>,
+    recurring_interval: Option<Duration>,
+    score_compaction_age: Option<Duration>,
+    /// Peers at least this good as recommenders keep their connections open; None lets every connection idle out
+    keep_alive_quality: Option<f64>,
+    max_concurrent_dials: usize,
+    dial_priority: DialPriority,
+    /// Dials to known peers that haven't connected or failed yet
+    dials_in_flight: HashMap<ConnectionId, PeerId>,
+    /// Known peers whose last dial failed, and when, so they don't hold up the others' slots
+    failed_dials: HashMap<PeerId, Instant>,
+    /// Unknown peers recently recorded in the peer requests, when and with what status, so their
+    /// queries don't each write to the database
+    screened_peers: HashMap<String, (Instant, PeerRequestStatus)>,
+    quarantine: Quarantine,
+    /// What scores from peers may claim to be cached and merged
+    score_bounds: ScoreBounds,
+    score_clamping: ScoreClamping,
+    /// When each kept peer was last redialed after losing its connection
+    kept_redials: HashMap<PeerId, Instant>,
+    runtime_probe: RuntimeProbe,
+    discounting: Discounting,
+    clock: SharedClock,
+}
+
+/// Time between attempts to connect to known peers; short for faster test connections
+const PEER_CONNECTION_INTERVAL: Duration = Duration::from_secs(5);
+/// A known peer that couldn't be dialed waits this long before it's tried again
+const FAILED_DIAL_BACKOFF: Duration = Duration::from_secs(30);
+/// How often an unknown peer that keeps querying is recorded in the peer requests
+const SCREEN_RECORD_INTERVAL: Duration = Duration::from_secs(60);
+/// Share of the remaining budget handed on to peers; the rest covers transport and merging
+const FORWARD_BUDGET_SHARE: f64 = 0.75;
+/// Peers aren't asked at all when less than this would be left for them
+const MIN_FORWARD_BUDGET_MS: u64 = 20;
+
+/// Most queries waiting on peers at once; the oldest is answered early to make room
+const MAX_PENDING_QUERIES: usize = 1024;
+/// Queries still pending after this are answered with what they have, in case a peer event got lost
+const MAX_PENDING_AGE: Duration = Duration::from_secs(30);
+
+/// How often the agents' query counts are written out; what a crash loses of them only affects the warm-up
+const AGENT_QUERIES_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Exchanges a peer's availability filter may miss before it's ignored
+const FILTER_STALE_AFTER_ROUNDS: u32 = 3;
+/// A peer is given this multiple of its typical response time before the answer goes out without it
+const PEER_TIMEOUT_FACTOR: f64 = 3.0;
+const MIN_PEER_TIMEOUT: Duration = Duration::from_millis(250);
+/// Weight of the newest sample in a peer's moving average latency
+const LATENCY_SMOOTHING: f64 = 0.3;
+
+/// Recommender quality an invited friend starts with once they redeem our invite
+const DEFAULT_INVITED_QUALITY: f64 = 0.5;
+
+/// How many of the most-queried agents a background refresh covers, on top of the watched ones
+const REFRESH_TOP_AGENTS: usize = 20;
+/// Only peers at least this good are re-asked during a refresh
+const REFRESH_MIN_PEER_QUALITY: f64 = 0.6;
+/// The node counts as idle once nothing came in for this long
+const REFRESH_IDLE_AFTER: Duration = Duration::from_secs(2);
+
+/// How often old cached recommendations are compacted, when compaction is on
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A peer's last interaction is only written back once it's this much out of date, not on every answer
+const INTERACTION_RESOLUTION: chrono::Duration = chrono::Duration::hours(1);
+
+/// A contribution to an agent's score
+#[derive(Clone)]
+struct ScoreSource {
+    score: TrustScore,
+    weight: f64,
+    /// Hops to the evidence behind the score
+    hops: u8,
+    /// Hops to the farthest evidence, when the score merges several sources further down
+    farthest_hops: u8,
+    origin: ScoreOrigin,
+    /// Bare id of the peer the score came from; None for own experiences
+    peer: Option<String>,
+}
+
+#[derive(Clone)]
+enum ScoreOrigin {
+    Local,
+    Cached { cached_at: chrono::DateTime<Utc> },
+    /// A peer's answer to this query, with the peer's own account of its freshness
+    Live(Freshness),
+}
+
+/// Work finished on spawned tasks that the swarm loop has to continue
+enum LoopEvent {
+    LocalScoresReady {
+        query: TrustQuery,
+        deadline: Option<Instant>,
+        /// When the node took the query on, and how long the local scores took
+        received: Instant,
+        local_elapsed: TokioDuration,
+        local: Result<LocalScores>,
+        response: oneshot::Sender<Result<TrustResponse>>,
+        /// Whether the query came from a peer and holds an inbound worker
+        inbound: bool,
+        span: Span,
+    },
+    InboundAnswer {
+        peer: PeerId,
+        channel: ResponseChannel<TrustResponse>,
+        result: Result<TrustResponse>,
+    },
+    /// Our own scores of a peer's claimed agents, ready to go back to it
+    ReputationAnswer {
+        peer: PeerId,
+        channel: ResponseChannel<ReputationAnswer>,
+        result: Result<Vec<AgentScore>>,
+    },
+}
+
+/// A QueryOwnReputation waiting for the peers it asked
+struct ReputationRound {
+    waiting: usize,
+    opinions: Vec<PeerOpinion>,
+    response: oneshot::Sender<Result<Vec<PeerOpinion>>>,
 }
 
 struct PendingRequest {
     responses: Vec<TrustResponseInternal>,
-    waiting_for: HashSet<PeerId>,
+    /// Peers that haven't answered yet, with the time after which we stop waiting for each
+    waiting_for: HashMap<PeerId, Instant>,
+    sent_at: Instant,
     response_channel: oneshot::Sender<Result<TrustResponse>>,
-    local_scores: HashMap<(String, String), Vec<(String, TrustScore, f64)>>, // Store original local+cached scores
+    local_scores: HashMap<(String, String), Vec<ScoreSource>>, // Store original local+cached scores
+    aggregators: HashMap<(String, String), Aggregator>,
+    /// When the query's budget runs out and the answer goes out with whatever arrived by then
+    deadline: Option<Instant>,
+    /// What peers were asked, repeated with a continuation token when an answer comes in pages
+    peer_query: Option<TrustQuery>,
+    /// Depth this node queried with; peers were asked one less
+    max_depth: u8,
+    /// Answer with a QueryTiming
+    timing: bool,
+    received: Instant,
+    local_elapsed: TokioDuration,
+    /// Every peer asked, and how long those that answered took
+    asked: Vec<PeerId>,
+    latencies: HashMap<PeerId, TokioDuration>,
+    /// Open until the last peer answers or the request is given up on
+    _span: Span,
 }
 
 impl<S: Storage + 'static> TrustNode<S> {
+    /// Create a node with its own identity key and storage; the returned sender is what the API uses to reach it
     pub async fn new(
         p2p_port: u16,
-        api_port: u16,
         storage: S,
         bootstrap_peers: Vec<String>,
-    ) -> Result<(Self, JoinHandle<Result<()>>)> {
-        let local_key = identity::Keypair::generate_ed25519();
+        config: NodeConfig,
+    ) -> Result<(Self, mpsc::Sender<NodeCommand>)> {
+        let local_key = match &config.identity_file {
+            Some(path) => crate::identity::load_or_create(path)?,
+            None => identity::Keypair::generate_ed25519(),
+        };
         let local_peer_id = PeerId::from(local_key.public());
-        info!("Local peer id: {}", local_peer_id);
+        info!(peer_id = %local_peer_id, "Local peer id");
 
-        let mut swarm = SwarmBuilder::with_existing_identity(local_key)
-            .with_tokio()
-            .with_tcp(
-                tcp::Config::default(),
-                noise::Config::new,
-                yamux::Config::default,
-            )?
-            .with_behaviour(|key| {
-                let kademlia = kad::Behaviour::new(
-                    local_peer_id,
-                    kad::store::MemoryStore::new(local_peer_id),
-                );
-                
-                let request_response = request_response::Behaviour::new(
-                    [(TrustProtocol, request_response::ProtocolSupport::Full)],
-                    request_response::Config::default()
-                        .with_request_timeout(Duration::from_secs(5)), // Reduced for local testing
-                );
-
-                let identify = libp2p::identify::Behaviour::new(
-                    libp2p::identify::Config::new("/repeer/1.0.0".to_string(), key.public())
-                );
-
-                Ok(TrustBehaviour {
-                    request_response,
-                    kademlia,
-                    identify,
-                })
-            })?
-            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
-            .build();
+        #[cfg(feature = "chaos")]
+        let codec = TrustCodec::with_chaos(config.chaos.clone());
+        #[cfg(not(feature = "chaos"))]
+        let codec = TrustCodec::default();
 
-        swarm.listen_on(format!("/ip4/0.0.0.0/tcp/{}", p2p_port).parse()?)?;
+        let swarm_timeouts = SwarmTimeouts {
+            idle: config.idle_connection_timeout,
+            request: config.max_request_timeout.unwrap_or(config.request_timeout).max(config.request_timeout),
+        };
+        let mut swarm = build_swarm(local_key.clone(), config.transport, codec.clone(), swarm_timeouts)?;
+        let listen_address: Multiaddr = config.transport.listen_address(p2p_port).parse()?;
+        swarm.listen_on(listen_address.clone())?;
 
         // Add bootstrap peers and start Kademlia bootstrap
         for addr_str in bootstrap_peers {
@@ -163,48 +865,197 @@ impl<S: Storage + 'static> TrustNode<S> {
         }
 
         let storage = Arc::new(storage);
-        let query_engine = QueryEngine::new(storage.clone());
+        let query_engine = Arc::new(
+            QueryEngine::new_with_cache_ttl(storage.clone(), config.cache_ttl.as_secs() as i64).with_clock(config.clock.clone()),
+        );
         
         let (command_tx, command_rx) = mpsc::channel(100);
+        let (loop_tx, loop_rx) = mpsc::channel(100);
         
         // Load peers from storage
         let peers = storage.get_peers().await?
             .into_iter()
             .map(|p| (p.peer_id.clone(), p))
             .collect();
+        let proposed_peers = storage.get_proposed_peers().await?
+            .into_iter()
+            .filter_map(|proposed| Some((proposed.peer.peer_id.parse().ok()?, proposed)))
+            .collect();
+        let peering_answers = storage.get_peering_answers().await?
+            .into_iter()
+            .filter_map(|answer| Some((answer.peer_id.parse().ok()?, answer)))
+            .collect();
+
+        let backup_status = Arc::new(RwLock::new(BackupStatus::new(config.backup.as_ref())));
+        if let Some(backup_config) = config.backup {
+            info!("Scheduling backups to {} every {:?}", backup_config.directory.display(), backup_config.interval);
+            let scheduler = BackupScheduler::new(storage.clone(), backup_config, backup_status.clone());
+            tokio::spawn(scheduler.run());
+        }
+
+        let domain_defaults = storage.get_domain_defaults().await?;
+        let agent_id_forms = registered_id_forms(&domain_defaults);
+        query_engine
+            .set_inflation(inflation_by_domain(&domain_defaults, storage.get_inflation_indexes().await?))
+            .await;
+        let sharing_precision = storage.get_sharing_precision().await?;
+        let events = event_channel();
+        let watchlist = Watchlist::new(storage.get_watchlist().await?);
+        if let Some(notify) = config.notify.clone() {
+            tokio::spawn(Notifier::new(notify, events.clone()).run());
+        }
+        let anomalies = Arc::new(RwLock::new(AnomalyReport::default()));
+        if let Some(anomaly_interval) = config.anomaly_interval {
+            let detector = AnomalyDetector::new(storage.clone(), anomaly_interval, anomalies.clone(), events.clone(), config.clock.clone());
+            tokio::spawn(detector.run());
+        }
+        let global_trust = Arc::new(RwLock::new(GlobalTrust::default()));
+        if let Some(global_interval) = config.global_trust_interval {
+            let aggregator = GlobalAggregator::new(storage.clone(), query_engine.clone(), global_interval, global_trust.clone(), config.clock.clone());
+            tokio::spawn(aggregator.run());
+        }
 
         let node = Self {
             swarm,
             storage,
             query_engine,
             command_rx,
+            loop_tx,
+            loop_rx,
+            inbound_queue: FairQueue::new(config.max_queued_per_peer.max(1)),
+            inbound_active: 0,
+            inbound_workers: config.inbound_workers.max(1),
             peers,
             pending_requests: HashMap::new(),
+            backup_status,
+            anomalies,
+            global_trust,
+            events,
+            peer_events: PeerEventLog::new(PEER_EVENT_HISTORY),
+            watchlist,
+            min_evidence: config.min_evidence,
+            answer_privacy: config.answer_privacy,
+            reputation_policy: config.reputation_policy,
+            reputation_asks: HashMap::new(),
+            reputation_rounds: HashMap::new(),
+            sharing_precision,
+            influence_caps: config.influence_caps,
+            agent_id_forms,
+            hop_damping: config.hop_damping,
+            quality_decay: config.quality_decay,
+            query_limits: config.query_limits,
+            discovery_interval: config.discovery_interval,
+            max_fanout: config.max_fanout,
+            peer_latency: HashMap::new(),
+            identity: local_key,
+            identity_file: config.identity_file,
+            transport: config.transport,
+            codec,
+            swarm_timeouts,
+            request_timeout: config.request_timeout,
+            listen_address,
+            announced_addresses: HashMap::new(),
+            open_invites: HashMap::new(),
+            proposed_peers,
+            peering_answers,
+            peering_in_flight: HashMap::new(),
+            query_stats: QueryStats::default(),
+            traffic: HashMap::new(),
+            query_counts: HashMap::new(),
+            agent_queries: HashMap::new(),
+            last_activity: Instant::now(),
+            refresh_interval: config.refresh_interval,
+            prefetch_peers: config.prefetch_peers,
+            warm_up_agents: config.warm_up_agents,
+            availability_interval: config.availability_interval,
+            own_filter: None,
+            peer_filters: HashMap::new(),
+            no_data: HashMap::new(),
+            no_data_ttl: config.no_data_ttl,
+            idempotency_ttl: config.idempotency_ttl,
+            recurring_interval: config.recurring_interval,
+            score_compaction_age: config.score_compaction_age,
+            keep_alive_quality: config.keep_alive_quality,
+            max_concurrent_dials: config.max_concurrent_dials,
+            dial_priority: config.dial_priority,
+            dials_in_flight: HashMap::new(),
+            failed_dials: HashMap::new(),
+            screened_peers: HashMap::new(),
+            quarantine: Quarantine::new(config.quarantine),
+            score_bounds: config.score_bounds,
+            score_clamping: config.score_clamping,
+            kept_redials: HashMap::new(),
+            runtime_probe: RuntimeProbe::default(),
+            discounting: config.discounting,
+            clock: config.clock,
         };
 
-        let api_handle = tokio::spawn(run_api_server(api_port, command_tx));
-
-        Ok((node, api_handle))
+        Ok((node, command_tx))
     }
 
     pub async fn run(mut self) -> Result<()> {
-        let mut discovery_interval = interval(TokioDuration::from_secs(30)); // 30 seconds for faster test discovery
-        let mut peer_connection_interval = interval(TokioDuration::from_secs(5)); // 5 seconds for faster test connections
-        
+        let mut discovery_interval = interval(self.discovery_interval);
+        let mut peer_connection_interval = interval(PEER_CONNECTION_INTERVAL);
+        let mut refresh_interval = self.refresh_interval.map(interval);
+        let mut recurring_interval = self.recurring_interval.map(interval);
+        let mut availability_interval = self.availability_interval.map(interval);
+        let mut compaction_interval = self.score_compaction_age.map(|_| interval(COMPACTION_INTERVAL));
+        let mut agent_queries_interval = interval_at(Instant::now() + AGENT_QUERIES_FLUSH_INTERVAL, AGENT_QUERIES_FLUSH_INTERVAL);
+        let mut probe_interval = interval(runtime_metrics::PROBE_INTERVAL);
+        // A stalled loop should show as one late tick, not a burst of catch-up ticks
+        probe_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        if self.warm_up_agents > 0 {
+            self.warm_up().await;
+        }
+
         loop {
+            // The period can be changed through the runtime config
+            if discovery_interval.period() != self.discovery_interval {
+                discovery_interval = interval_at(Instant::now() + self.discovery_interval, self.discovery_interval);
+            }
+            let next_deadline = self.next_pending_deadline();
             tokio::select! {
                 Some(event) = self.swarm.next() => {
                     self.handle_swarm_event(event).await?;
                 }
                 Some(command) = self.command_rx.recv() => {
+                    self.last_activity = Instant::now();
                     self.handle_command(command).await?;
                 }
+                Some(event) = self.loop_rx.recv() => {
+                    self.handle_loop_event(event)?;
+                }
                 _ = discovery_interval.tick() => {
                     self.discover_peers().await?;
                 }
                 _ = peer_connection_interval.tick() => {
                     self.connect_to_known_peers().await?;
                 }
+                _ = async { refresh_interval.as_mut().unwrap().tick().await }, if refresh_interval.is_some() => {
+                    // A prefetch is only an optimization, so a failed one must not stop the node
+                    if let Err(e) = self.refresh_popular_scores().await {
+                        warn!("Failed to refresh popular scores: {}", e);
+                    }
+                }
+                _ = async { recurring_interval.as_mut().unwrap().tick().await }, if recurring_interval.is_some() => {
+                    self.generate_recurring().await;
+                }
+                _ = async { availability_interval.as_mut().unwrap().tick().await }, if availability_interval.is_some() => {
+                    self.exchange_availability().await;
+                }
+                _ = async { compaction_interval.as_mut().unwrap().tick().await }, if compaction_interval.is_some() => {
+                    self.compact_cached_scores().await;
+                }
+                _ = agent_queries_interval.tick() => {
+                    self.flush_agent_queries().await;
+                }
+                deadline = probe_interval.tick() => {
+                    self.runtime_probe.tick(deadline, Instant::now());
+                }
+                _ = sleep_until(next_deadline.unwrap_or_else(Instant::now)), if next_deadline.is_some() => {
+                    self.expire_pending_requests();
+                }
             }
         }
     }
@@ -214,11 +1065,47 @@ impl<S: Storage + 'static> TrustNode<S> {
             SwarmEvent::NewListenAddr { address, .. } => {
                 info!("Listening on {}", address);
             }
-            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
-                info!("Connected to peer: {}", peer_id);
+            SwarmEvent::Dialing { peer_id, .. } => {
+                debug!(peer_id = ?peer_id, "Dialing peer");
+                self.record_peer_event(PeerEventKind::Dialed, peer_id, None, None);
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, .. } => {
+                self.dials_in_flight.remove(&connection_id);
+                self.failed_dials.remove(&peer_id);
+                info!(peer_id = %peer_id, "Connected to peer");
+                let address = endpoint.get_remote_address().to_string();
+                self.record_peer_event(PeerEventKind::Connected, Some(peer_id), Some(address.clone()), None);
+                if endpoint.is_dialer() {
+                    self.note_address_success(&peer_id, &address).await;
+                }
+                if self.peers.contains_key(&peer_id.to_string()) {
+                    self.update_kept_connections();
+                }
+                // Proposals and answers the peer hasn't confirmed yet
+                self.send_peering(peer_id, PeeringKind::Proposal);
+                self.send_peering(peer_id, PeeringKind::Answer);
+                // A peer we just reached shouldn't wait a whole interval to learn what we have
+                if self.availability_interval.is_some() && self.peers.contains_key(&peer_id.to_string()) {
+                    self.send_availability(&peer_id);
+                }
+            }
+            SwarmEvent::ConnectionClosed { peer_id, cause, num_established, .. } => {
+                info!(peer_id = %peer_id, "Connection to peer closed: {:?}", cause);
+                if num_established == 0 {
+                    self.announced_addresses.remove(&peer_id);
+                }
+                let reason = cause.map(|cause| cause.to_string());
+                self.record_peer_event(PeerEventKind::Disconnected, Some(peer_id), None, reason);
+                if num_established == 0 {
+                    self.redial_kept_peer(peer_id);
+                }
             }
-            SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
-                info!("Connection to peer {} closed: {:?}", peer_id, cause);
+            SwarmEvent::OutgoingConnectionError { peer_id, connection_id, error } => {
+                if let Some(peer) = self.dials_in_flight.remove(&connection_id) {
+                    self.failed_dials.insert(peer, Instant::now());
+                }
+                warn!(peer_id = ?peer_id, "Failed to connect to peer: {}", error);
+                self.record_peer_event(PeerEventKind::Failed, peer_id, None, Some(error.to_string()));
             }
             SwarmEvent::IncomingConnection { local_addr, send_back_addr, .. } => {
                 debug!("Incoming connection from {} to {}", send_back_addr, local_addr);
@@ -231,7 +1118,7 @@ impl<S: Storage + 'static> TrustNode<S> {
                     kad::Event::OutboundQueryProgressed { result, .. } => {
                         match result {
                             kad::QueryResult::Bootstrap(Ok(kad::BootstrapOk { peer, .. })) => {
-                                info!("Successfully bootstrapped with peer: {}", peer);
+                                info!(peer_id = %peer, "Successfully bootstrapped with peer");
                             }
                             kad::QueryResult::Bootstrap(Err(e)) => {
                                 warn!("Bootstrap failed: {:?}", e);
@@ -239,7 +1126,7 @@ impl<S: Storage + 'static> TrustNode<S> {
                             kad::QueryResult::GetClosestPeers(Ok(kad::GetClosestPeersOk { peers, .. })) => {
                                 info!("Found {} closest peers", peers.len());
                                 for peer in peers {
-                                    debug!("Discovered peer: {:?}", peer);
+                                    debug!(peer_id = ?peer, "Discovered peer");
                                 }
                             }
                             _ => {
@@ -247,20 +1134,41 @@ impl<S: Storage + 'static> TrustNode<S> {
                             }
                         }
                     }
-                    kad::Event::RoutingUpdated { peer, .. } => {
-                        info!("Routing table updated with peer: {}", peer);
+                    kad::Event::RoutingUpdated { peer, is_new_peer, addresses, .. } => {
+                        info!(peer_id = %peer, "Routing table updated with peer");
+                        if is_new_peer {
+                            let address = addresses.first().to_string();
+                            self.record_peer_event(PeerEventKind::Discovered, Some(peer), Some(address), None);
+                        }
                     }
                     _ => {
                         debug!("Kademlia event: {:?}", event);
                     }
                 }
             }
-            SwarmEvent::Behaviour(TrustBehaviourEvent::Identify(event)) => {
-                if let libp2p::identify::Event::Received { peer_id, info, .. } = event {
-                    debug!("Identified peer {} with protocols: {:?}", peer_id, info.protocols);
-                    for addr in info.listen_addrs {
-                        self.swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
-                    }
+            SwarmEvent::Behaviour(TrustBehaviourEvent::Invites(event)) => {
+                self.handle_invite_event(event).await;
+            }
+            SwarmEvent::Behaviour(TrustBehaviourEvent::Rotations(event)) => {
+                self.handle_rotation_event(event).await;
+            }
+            SwarmEvent::Behaviour(TrustBehaviourEvent::Peering(event)) => {
+                self.handle_peering_event(event).await;
+            }
+            SwarmEvent::Behaviour(TrustBehaviourEvent::Reputation(event)) => {
+                self.handle_reputation_event(event).await;
+            }
+            SwarmEvent::Behaviour(TrustBehaviourEvent::Availability(event)) => {
+                self.handle_availability_event(event);
+            }
+            SwarmEvent::Behaviour(TrustBehaviourEvent::Identify(libp2p::identify::Event::Received { peer_id, info, .. })) => {
+                debug!(peer_id = %peer_id, "Identified peer with protocols: {:?}", info.protocols);
+                let reason = format!("{} speaking {} protocols", info.agent_version, info.protocols.len());
+                self.record_peer_event(PeerEventKind::Identified, Some(peer_id), None, Some(reason));
+                self.learn_announced_addresses(&peer_id, &info.listen_addrs).await;
+                self.announced_addresses.insert(peer_id, info.listen_addrs.clone());
+                for addr in info.listen_addrs {
+                    self.swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
                 }
             }
             _ => {}
@@ -268,150 +1176,442 @@ impl<S: Storage + 'static> TrustNode<S> {
         Ok(())
     }
 
+    /// Keep a peer lifecycle event for GET /peers/events and publish it to event subscribers
+    fn record_peer_event(&mut self, kind: PeerEventKind, peer_id: Option<PeerId>, address: Option<String>, reason: Option<String>) {
+        let event = PeerEvent {
+            kind,
+            peer_id: peer_id.map(|peer_id| peer_id.to_string()),
+            address,
+            reason,
+            at: self.clock.now(),
+        };
+        self.peer_events.push(event.clone());
+        // Nobody listening is fine
+        let _ = self.events.send(NodeEvent::Peer(event));
+    }
+
     async fn handle_request_response_event(&mut self, event: ReqResEvent<TrustQuery, TrustResponse>) -> Result<()> {
         match event {
             ReqResEvent::Message { peer, message } => match message {
                 Message::Request { request, channel, .. } => {
-                    debug!("Received trust query from {}: {:?}", peer, request);
-                    self.handle_trust_query(request, channel).await?;
+                    debug!(peer_id = %peer, correlation_id = ?request.correlation_id, "Received trust query: {:?}", request);
+                    let traffic = self.traffic.entry(peer.to_string()).or_default();
+                    traffic.queries_received += 1;
+                    traffic.bytes_received += wire_size(&request);
+                    if self.screen_peer(&peer).await {
+                        debug!(peer_id = %peer, "Blocked peer queried us, answering empty");
+                        let _ = self.swarm.behaviour_mut().request_response.send_response(channel, TrustResponse::new(vec![]));
+                        return Ok(());
+                    }
+                    self.handle_trust_query(peer, request, channel);
                 }
                 Message::Response { request_id, response } => {
-                    debug!("Received trust response for request {:?}", request_id);
+                    debug!(peer_id = %peer, request_id = %request_id, "Received trust response");
+                    let traffic = self.traffic.entry(peer.to_string()).or_default();
+                    traffic.answers_received += 1;
+                    traffic.scores_received += response.scores.len() as u64;
+                    traffic.bytes_received += wire_size(&response);
                     self.handle_trust_response(request_id, peer, response).await?;
                 }
             },
             ReqResEvent::OutboundFailure { peer, request_id, error } => {
-                warn!("Outbound request to {} failed: {:?}", peer, error);
+                warn!(peer_id = %peer, request_id = %request_id, "Outbound request failed: {:?}", error);
+                if let Some(violation) = match &error {
+                    OutboundFailure::Io(e) => Violation::of_io_error(e),
+                    _ => None,
+                } {
+                    self.record_violation(peer, violation);
+                }
                 self.handle_request_failure(request_id, peer).await?;
             }
             ReqResEvent::InboundFailure { peer, error, .. } => {
-                warn!("Inbound request from {} failed: {:?}", peer, error);
+                warn!(peer_id = %peer, "Inbound request failed: {:?}", error);
+                if let Some(violation) = match &error {
+                    InboundFailure::Io(e) => Violation::of_io_error(e),
+                    _ => None,
+                } {
+                    self.record_violation(peer, violation);
+                }
             }
             _ => {}
         }
         Ok(())
     }
 
-    async fn handle_trust_query(&mut self, query: TrustQuery, channel: ResponseChannel<TrustResponse>) -> Result<()> {
-        // Create a oneshot channel for the response
-        let (tx, rx) = oneshot::channel();
-        
-        // Process the query using the same logic as HTTP queries
-        // This ensures depth-based forwarding works for libp2p queries too
-        self.process_trust_query(query, tx).await?;
-        
-        // Wait for the response
-        match rx.await {
-            Ok(Ok(response)) => {
-                debug!("Sending trust response via libp2p: {} scores", response.scores.len());
-                // Send the response back through libp2p
-                self.swarm
-                    .behaviour_mut()
-                    .request_response
-                    .send_response(channel, response)
-                    .map_err(|_| anyhow::anyhow!("Failed to send response"))?;
-                debug!("Trust response sent successfully via libp2p");
-            }
-            Ok(Err(e)) => {
-                warn!("Trust query processing failed: {}", e);
-                // Send empty response on error
-                let empty_response = TrustResponse {
-                    scores: vec![],
-                    timestamp: Utc::now(),
-                };
-                self.swarm
-                    .behaviour_mut()
-                    .request_response
-                    .send_response(channel, empty_response)
-                    .map_err(|_| anyhow::anyhow!("Failed to send response"))?;
-            }
-            Err(_) => {
-                warn!("Trust query response channel closed");
-            }
+    /// Count a protocol violation against a peer, quarantining it once it has too many
+    fn record_violation(&mut self, peer: PeerId, violation: Violation) {
+        debug!(peer_id = %peer, "Protocol violation: {:?}", violation);
+        if !self.quarantine.record(peer, violation, self.clock.now()) {
+            return;
         }
+        warn!(peer_id = %peer, "Quarantining peer after repeated protocol violations");
+        // Closes its connections and refuses new ones either way, so it's neither asked nor answered
+        self.swarm.behaviour_mut().blocked.block_peer(peer);
+        let reason = format!("{:?}", violation);
+        self.record_peer_event(PeerEventKind::Quarantined, Some(peer), None, Some(reason));
+    }
 
+    fn release_quarantined_peer(&mut self, reference: &str) -> StorageResult<()> {
+        let peer_id = self.resolve_peer(reference).unwrap_or_else(|_| reference.to_string());
+        let peer = peer_id
+            .parse::<PeerId>()
+            .ok()
+            .filter(|peer| self.quarantine.release(peer))
+            .ok_or_else(|| StorageError::NotFound(format!("{} is not quarantined", reference)))?;
+        self.swarm.behaviour_mut().blocked.unblock_peer(peer);
+        self.record_peer_event(PeerEventKind::Released, Some(peer), None, None);
         Ok(())
     }
 
-    async fn handle_trust_response(&mut self, request_id: request_response::OutboundRequestId, peer: PeerId, response: TrustResponse) -> Result<()> {
-        debug!("LIBP2P: Received response from peer {} with {} scores for request {:?}", 
-               peer, response.scores.len(), request_id);
-        
-        // Cache the received trust scores from this peer
-        for agent_score in &response.scores {
-            let cached = crate::types::CachedTrustScore {
-                id_domain: agent_score.id_domain.clone(),
-                agent_id: agent_score.agent_id.clone(),
-                score: agent_score.score.clone(),
-                from_peer: peer.to_string(),
-                cached_at: Utc::now(),
-            };
-            if let Err(e) = self.storage.cache_trust_score(cached).await {
-                debug!("Failed to cache trust score from {}: {}", peer, e);
-            }
+    /// Keep a response for retries, dropping the ones whose time is up on the way
+    async fn record_idempotent_response(&mut self, snapshot: IdempotentResponse) -> Result<()> {
+        let Some(ttl) = self.idempotency_ttl else {
+            return Ok(());
+        };
+        let now = self.clock.now();
+        let purged = self.storage.purge_idempotent_responses(now).await?;
+        if purged > 0 {
+            debug!("Dropped {} expired idempotent responses", purged);
         }
+        let expires_at = now.checked_add_signed(chrono::Duration::from_std(ttl)?).unwrap_or(chrono::DateTime::<Utc>::MAX_UTC);
+        self.storage.put_idempotent_response(&snapshot, expires_at).await?;
+        Ok(())
+    }
 
-        if let Some(pending_arc) = self.pending_requests.get(&request_id).cloned() {
-            debug!("LIBP2P: Found pending request for {:?}", request_id);
-            let (should_remove, response_channel, final_response) = {
-                let mut pending = pending_arc.lock().unwrap();
-                pending.responses.push(TrustResponseInternal {
-                    response,
-                    peer_id: peer.to_string(),
-                });
-                pending.waiting_for.remove(&peer);
-                debug!("LIBP2P: Added response from {}, still waiting for {} peers", peer, pending.waiting_for.len());
+    /// Let peers whose cooling-off period is over back in
+    fn release_expired_quarantines(&mut self) {
+        for peer in self.quarantine.expire(self.clock.now()) {
+            info!(peer_id = %peer, "Releasing peer from quarantine");
+            self.swarm.behaviour_mut().blocked.unblock_peer(peer);
+            self.record_peer_event(PeerEventKind::Released, Some(peer), None, None);
+        }
+    }
 
-                if pending.waiting_for.is_empty() {
-                    // All responses received, combine with local scores
-                    let peer_response = merge_responses(pending.responses.clone());
-                    debug!("LIBP2P: Peer responses contain {} scores", peer_response.scores.len());
-                    for score in &peer_response.scores {
-                        debug!("LIBP2P: Peer response score: {}:{} = ROI:{} vol:{} pts:{}", 
-                               score.id_domain, score.agent_id, 
-                               score.score.expected_pv_roi, score.score.total_volume, score.score.data_points);
-                    }
-                    
-                    // Merge local scores with peer responses
-                    let mut final_all_scores = pending.local_scores.clone();
-                    debug!("LIBP2P: Local scores contain {} agents", final_all_scores.len());
-                    
-                    // Add peer responses to the all_scores map
-                    for agent_score in peer_response.scores {
-                        let key = (agent_score.id_domain.clone(), agent_score.agent_id.clone());
-                        debug!("LIBP2P: Adding peer score for {}:{} with ROI {} and volume {}", 
-                               agent_score.id_domain, agent_score.agent_id, 
-                               agent_score.score.expected_pv_roi, agent_score.score.total_volume);
-                        final_all_scores
-                            .entry(key)
-                            .or_default()
-                            .push(("peers".to_string(), agent_score.score, 1.0)); // Peer responses get weight 1.0
+    fn send_query(&mut self, peer: &PeerId, query: TrustQuery) -> request_response::OutboundRequestId {
+        let traffic = self.traffic.entry(peer.to_string()).or_default();
+        traffic.queries_sent += 1;
+        traffic.bytes_sent += wire_size(&query);
+        self.swarm.behaviour_mut().request_response.send_request(peer, query)
+    }
+
+    /// Put a querying peer that isn't in the peer list into the requests; true if the user blocked it
+    async fn screen_peer(&mut self, peer: &PeerId) -> bool {
+        let peer_id = peer.to_string();
+        if self.peers.contains_key(&peer_id) {
+            return false;
+        }
+        let now = Instant::now();
+        self.screened_peers.retain(|_, (recorded_at, _)| now < *recorded_at + SCREEN_RECORD_INTERVAL);
+        if let Some((_, status)) = self.screened_peers.get(&peer_id) {
+            return *status == PeerRequestStatus::Blocked;
+        }
+        match self.storage.record_peer_request(&peer_id, self.clock.now()).await {
+            Ok(request) => {
+                if request.queries == 1 && request.status == PeerRequestStatus::Pending {
+                    info!(peer_id = %peer, "Unknown peer queried us, added to the peer requests");
+                    // Nobody listening is fine
+                    let _ = self.events.send(NodeEvent::PeerRequest(request.clone()));
+                }
+                self.screened_peers.insert(peer_id, (now, request.status));
+                request.status == PeerRequestStatus::Blocked
+            }
+            Err(e) => {
+                warn!(peer_id = %peer, "Failed to record peer request: {}", e);
+                false
+            }
+        }
+    }
+
+    async fn accept_peer_request(&mut self, peer_id: String, name: Option<String>, quality: Option<f64>, tags: Vec<String>) -> Result<Peer> {
+        let request = self.find_peer_request(&peer_id).await?;
+        let proposed = request.proposal.is_some();
+        let proposal = request.proposal.unwrap_or_default();
+        let mut peer = Peer {
+            peer_id: peer_id.clone(),
+            addresses: Vec::new(),
+            handle: None,
+            name: name.or(proposal.name).unwrap_or_else(|| peer_id.clone()),
+            recommender_quality: quality.unwrap_or(DEFAULT_INVITED_QUALITY),
+            added_at: self.clock.now(),
+            notes: proposal.note,
+            tags,
+            contact: proposal.contact,
+            last_interaction_at: None,
+        };
+        for address in &proposal.addresses {
+            peer.learn_address(address);
+        }
+        let peer = peer.normalized();
+        self.storage.add_peer(peer.clone()).await?;
+        self.storage.remove_peer_request(&peer_id).await?;
+        self.screened_peers.remove(&peer_id);
+        self.dial_new_peer(&peer);
+        self.peers.insert(peer_id, peer.clone());
+        info!(peer_id = %peer.peer_id, "Accepted peer request");
+        if proposed {
+            self.answer_proposal(&peer.peer_id, true, None).await;
+            self.record_peer_event(PeerEventKind::Befriended, peer.peer_id.parse().ok(), None, None);
+        }
+        Ok(peer)
+    }
+
+    /// Ignoring or blocking a peer that proposed peering declines the proposal
+    async fn set_peer_request_status(&mut self, peer_id: String, status: PeerRequestStatus) -> Result<()> {
+        self.storage.set_peer_request_status(&peer_id, status, self.clock.now()).await?;
+        self.screened_peers.remove(&peer_id);
+        if status != PeerRequestStatus::Pending {
+            let proposed = self.find_peer_request(&peer_id).await.is_ok_and(|request| request.proposal.is_some());
+            if proposed {
+                self.answer_proposal(&peer_id, false, Some("declined".to_string())).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn find_peer_request(&mut self, peer_id: &str) -> Result<PeerRequest> {
+        self.storage
+            .get_peer_requests()
+            .await?
+            .into_iter()
+            .find(|request| request.peer_id == peer_id)
+            .ok_or_else(|| StorageError::NotFound(format!("No peer request from {}", peer_id)).into())
+    }
+
+    fn handle_trust_query(&mut self, peer: PeerId, query: TrustQuery, channel: ResponseChannel<TrustResponse>) {
+        self.last_activity = Instant::now();
+        if let Err(e) = self.query_limits.check(&query) {
+            warn!(peer_id = %peer, "Rejecting query: {}", e);
+            let _ = self.swarm.behaviour_mut().request_response.send_response(channel, TrustResponse::new(vec![]));
+            return;
+        }
+        if self.expired(&query) {
+            debug!(peer_id = %peer, "Query expired before it arrived, answering empty");
+            self.refuse_expired(channel);
+            return;
+        }
+        if let Err((_, channel)) = self.inbound_queue.push(peer, (query, channel)) {
+            warn!(peer_id = %peer, "Peer has too many queries waiting, answering empty");
+            let empty_response = TrustResponse::new(vec![]);
+            let _ = self.swarm.behaviour_mut().request_response.send_response(channel, empty_response);
+            return;
+        }
+        self.start_inbound_queries();
+    }
+
+    /// Hand queued inbound queries to free workers, taking turns between peers
+    fn start_inbound_queries(&mut self) {
+        while self.inbound_active < self.inbound_workers {
+            let Some((peer, (query, channel))) = self.inbound_queue.pop() else {
+                break;
+            };
+            if self.expired(&query) {
+                debug!(peer_id = %peer, "Query expired while queued, answering empty");
+                self.refuse_expired(channel);
+                continue;
+            }
+            debug!(peer_id = %peer, "Processing query, {} more queued", self.inbound_queue.len());
+            self.inbound_active += 1;
+            self.process_inbound_query(peer, query, channel);
+        }
+    }
+
+    /// The precision answers to `peer` are rounded to; peers we don't know have no circles
+    fn precision_for(&self, peer: &PeerId) -> Option<SharingPrecision> {
+        let peer_id = peer.to_string();
+        let tags = self.peers.get(&peer_id).map(|peer| peer.tags.as_slice()).unwrap_or_default();
+        SharingPrecision::for_peer(&self.sharing_precision, &peer_id, tags)
+    }
+
+    fn expired(&self, query: &TrustQuery) -> bool {
+        query.expires_at.is_some_and(|expires_at| expires_at <= self.clock.now())
+    }
+
+    /// The asker has given up on an expired query, so it gets nothing to merge and nothing is forwarded
+    fn refuse_expired(&mut self, channel: ResponseChannel<TrustResponse>) {
+        self.query_stats.expired += 1;
+        let _ = self.swarm.behaviour_mut().request_response.send_response(channel, TrustResponse::new(vec![]));
+    }
+
+    fn process_inbound_query(&mut self, peer: PeerId, query: TrustQuery, channel: ResponseChannel<TrustResponse>) {
+        // Create a oneshot channel for the response
+        let (tx, rx) = oneshot::channel();
+        
+        // Process the query using the same logic as HTTP queries
+        // This ensures depth-based forwarding works for libp2p queries too
+        self.process_trust_query(query, tx, true);
+        
+        // Wait for the response on a task, the loop has to keep running to collect peer answers
+        let loop_tx = self.loop_tx.clone();
+        tokio::spawn(async move {
+            match rx.await {
+                Ok(result) => {
+                    let _ = loop_tx.send(LoopEvent::InboundAnswer { peer, channel, result }).await;
+                }
+                Err(_) => {
+                    warn!("Trust query response channel closed");
+                }
+            }
+        });
+    }
+
+    fn handle_loop_event(&mut self, event: LoopEvent) -> Result<()> {
+        match event {
+            LoopEvent::LocalScoresReady { query, deadline, received, local_elapsed, local, response, inbound, span } => {
+                if inbound {
+                    // The worker's share is done, waiting for peers doesn't need one
+                    self.inbound_active -= 1;
+                    self.start_inbound_queries();
+                }
+                match local {
+                    Ok(local) => self.dispatch_query(query, deadline, (received, local_elapsed), local, response, &span),
+                    Err(e) => {
+                        let _ = response.send(Err(e));
                     }
-                    
-                    // Generate final scores using the same logic as immediate response
-                    let final_scores: Vec<crate::types::AgentScore> = final_all_scores
-                        .into_iter()
-                        .map(|((id_domain, agent_id), scores)| {
-                            let combined = TrustScore::merge_multiple(
-                                scores.into_iter().map(|(_, score, quality)| (score, quality)).collect()
-                            );
-                            crate::types::AgentScore::new(id_domain, agent_id, combined)
-                        })
-                        .collect();
-                    
-                    let final_response = TrustResponse {
-                        scores: final_scores,
-                        timestamp: chrono::Utc::now(),
-                    };
-                    
+                }
+            }
+            LoopEvent::InboundAnswer { peer, channel, result: Ok(mut response) } => {
+                // Don't pass on numbers we wouldn't trust ourselves
+                response.scores.retain(|score| !score.insufficient_data);
+                if let Some(timing) = &mut response.timing {
+                    timing.anonymize();
+                }
+                self.query_stats.withheld_from_peers += self.answer_privacy.apply(&mut response.scores) as u64;
+                if let Some(precision) = self.precision_for(&peer) {
+                    response.scores.iter_mut().for_each(|score| precision.apply(score));
+                }
+                debug!("Sending trust response via libp2p: {} scores", response.scores.len());
+                let traffic = self.traffic.entry(peer.to_string()).or_default();
+                traffic.answers_sent += 1;
+                traffic.scores_sent += response.scores.len() as u64;
+                traffic.bytes_sent += wire_size(&response);
+                // Answers can take longer than the requester waits, so it may have timed out or disconnected by now
+                if self.swarm.behaviour_mut().request_response.send_response(channel, response).is_err() {
+                    debug!(peer_id = %peer, "Requester gone before the answer was ready");
+                } else {
+                    debug!("Trust response sent successfully via libp2p");
+                }
+            }
+            LoopEvent::InboundAnswer { peer, channel, result: Err(e) } => {
+                warn!("Trust query processing failed: {}", e);
+                // Send empty response on error
+                let empty_response = TrustResponse::new(vec![]);
+                if self.swarm.behaviour_mut().request_response.send_response(channel, empty_response).is_err() {
+                    debug!(peer_id = %peer, "Requester gone before the answer was ready");
+                }
+            }
+            LoopEvent::ReputationAnswer { peer, channel, result } => {
+                let mut scores = result.unwrap_or_else(|e| {
+                    warn!(peer_id = %peer, "Failed to score claimed agents: {}", e);
+                    Vec::new()
+                });
+                // The owner gets what any peer would
+                self.query_stats.withheld_from_peers += self.answer_privacy.apply(&mut scores) as u64;
+                if let Some(precision) = self.precision_for(&peer) {
+                    scores.iter_mut().for_each(|score| precision.apply(score));
+                }
+                let answer = ReputationAnswer { scores, refused: false };
+                let _ = self.swarm.behaviour_mut().reputation.send_response(channel, answer);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_trust_response(&mut self, request_id: request_response::OutboundRequestId, peer: PeerId, mut response: TrustResponse) -> Result<()> {
+        debug!(peer_id = %peer, request_id = %request_id, "LIBP2P: Received response with {} scores", response.scores.len());
+        // Peers may spell ids differently, e.g. an older version that doesn't canonicalize
+        for agent_score in &mut response.scores {
+            agent_score.agent_id = self.canonical_agent_id(&agent_score.id_domain, &agent_score.agent_id);
+        }
+
+        let received = response.scores.len();
+        let bounds = self.score_bounds;
+        let mut absurd = false;
+        response.scores.retain(|agent_score| match bounds.check(&agent_score.score) {
+            Ok(()) => true,
+            Err(reason) => {
+                warn!(peer_id = %peer, "Rejecting score for {}:{}: {}", agent_score.id_domain, agent_score.agent_id, reason);
+                absurd |= ScoreBounds::is_absurd(&agent_score.score);
+                false
+            }
+        });
+        self.query_stats.rejected_scores += (received - response.scores.len()) as u64;
+        // Bounds are ours to choose, so a peer only breaks the protocol with a score that can't be right anywhere
+        if absurd {
+            self.record_violation(peer, Violation::AbsurdScore);
+        }
+
+        // Cache the received trust scores from this peer
+        for agent_score in &response.scores {
+            let cached = crate::types::CachedTrustScore {
+                id_domain: agent_score.id_domain.clone(),
+                agent_id: agent_score.agent_id.clone(),
+                score: agent_score.score.clone(),
+                from_peer: peer.to_string(),
+                cached_at: self.clock.now(),
+            };
+            if let Err(e) = self.storage.cache_trust_score(cached).await {
+                debug!(peer_id = %peer, "Failed to cache trust score: {}", e);
+            }
+        }
+        self.note_interaction(&peer).await;
+        let agents = response.scores.iter().map(|score| (score.id_domain.as_str(), score.agent_id.as_str()));
+        let watched = self.watchlist.select(agents);
+        self.evaluate_watched(watched).await;
+
+        if let Some(error) = &response.error {
+            warn!(peer_id = %peer, request_id = %request_id, "Peer could not answer request: {:?}", error);
+            return self.handle_request_failure(request_id, peer).await;
+        }
+
+        if let Some(pending_arc) = self.pending_requests.get(&request_id).cloned() {
+            let correlation_id = pending_arc.lock().unwrap().peer_query.as_ref().and_then(|query| query.correlation_id.clone());
+            debug!(peer_id = %peer, request_id = %request_id, correlation_id = ?correlation_id, "LIBP2P: Found pending request");
+            // A truncated answer keeps the peer in waiting_for until its last page arrives
+            let next_page = response.continuation.clone().and_then(|token| {
+                let pending = pending_arc.lock().unwrap();
+                pending.peer_query.as_ref().map(|query| TrustQuery {
+                    continuation: Some(token),
+                    ..query.clone()
+                })
+            });
+            if let Some(next_page) = next_page {
+                debug!(peer_id = %peer, request_id = %request_id, "LIBP2P: Response is truncated, asking for the next page");
+                let next_request_id = self.send_query(&peer, next_page);
+                self.pending_requests.remove(&request_id);
+                self.pending_requests.insert(next_request_id, pending_arc.clone());
+                pending_arc.lock().unwrap().responses.push(TrustResponseInternal {
+                    response,
+                    peer_id: peer.to_string(),
+                });
+                return Ok(());
+            }
+
+            let (should_remove, response_channel, final_response, sent_at, empty) = {
+                let mut pending = pending_arc.lock().unwrap();
+                pending.responses.push(TrustResponseInternal {
+                    response,
+                    peer_id: peer.to_string(),
+                });
+                pending.waiting_for.remove(&peer);
+                let latency = pending.sent_at.elapsed();
+                pending.latencies.insert(peer, latency);
+                let empty = unanswered_agents(&pending, &peer);
+                debug!(peer_id = %peer, "LIBP2P: Added response, still waiting for {} peers", pending.waiting_for.len());
+
+                if pending.waiting_for.is_empty() {
+                    // All responses received, combine with local scores
+                    let final_response = self.merge_pending(&pending);
                     debug!("LIBP2P: All responses received, merged with local scores into {} final scores", final_response.scores.len());
                     (true, Some(std::mem::replace(&mut pending.response_channel, 
                         oneshot::channel().0)), // Dummy replacement
-                    Some(final_response))
+                    Some(final_response), pending.sent_at, empty)
                 } else {
-                    (false, None, None)
+                    (false, None, None, pending.sent_at, empty)
                 }
             };
+            self.record_peer_latency(peer, sent_at.elapsed());
+            self.record_no_data(peer, empty);
 
             if should_remove {
                 // Remove all request IDs that point to this pending request
@@ -426,6 +1626,173 @@ impl<S: Storage + 'static> TrustNode<S> {
         Ok(())
     }
 
+    /// Combine the local and cached scores of a pending query with the peer answers received so far
+    fn merge_pending(&self, pending: &PendingRequest) -> TrustResponse {
+        let merge_started = Instant::now();
+        let mut final_all_scores = pending.local_scores.clone();
+        debug!("LIBP2P: Local scores contain {} agents", final_all_scores.len());
+
+        // Add each peer's scores separately so robust aggregators see every recommendation;
+        // for the weighted mean this equals merging the peer responses first
+        for peer_response in &pending.responses {
+            for agent_score in &peer_response.response.scores {
+                let key = (agent_score.id_domain.clone(), agent_score.agent_id.clone());
+                debug!("LIBP2P: Adding score from {} for {}:{} with ROI {} and volume {}", 
+                       peer_response.peer_id, agent_score.id_domain, agent_score.agent_id, 
+                       agent_score.score.expected_pv_roi, agent_score.score.total_volume);
+                let peer = peer_response.peer_id.to_string();
+                let sources = final_all_scores.entry(key).or_default();
+                // What the peer told us before is superseded by its answer now, rather than counted next to it
+                sources.retain(|source| {
+                    !matches!(source.origin, ScoreOrigin::Cached { .. }) || source.peer.as_deref() != Some(peer.as_str())
+                });
+                sources.push(ScoreSource {
+                    score: agent_score.score.clone(),
+                    weight: self.hop_damping,
+                    hops: agent_score.hops.saturating_add(1),
+                    farthest_hops: agent_score.depth.map_or(agent_score.hops, |depth| depth.farthest_hops).saturating_add(1),
+                    origin: ScoreOrigin::Live(agent_score.freshness.clone()),
+                    peer: Some(peer),
+                });
+            }
+        }
+
+        // Generate final scores using the same logic as immediate response
+        let final_scores: Vec<AgentScore> = final_all_scores
+            .into_iter()
+            .map(|((id_domain, agent_id), scores)| {
+                let aggregator = pending.aggregators.get(&(id_domain.clone(), agent_id.clone())).copied().unwrap_or_default();
+                self.score_from_sources(id_domain, agent_id, scores, aggregator, Some(pending.max_depth))
+            })
+            .collect();
+
+        let mut response = TrustResponse::new(final_scores);
+        if pending.timing {
+            let peers = pending.asked
+                .iter()
+                .map(|peer| PeerTiming {
+                    peer_id: Some(peer.to_string()),
+                    latency_ms: pending.latencies.get(peer).copied().map(millis),
+                    // The last page carries the peer's breakdown of the whole answer
+                    timing: pending.responses
+                        .iter()
+                        .rev()
+                        .find(|answer| answer.peer_id == peer.to_string())
+                        .and_then(|answer| answer.response.timing.clone())
+                        .map(|timing| Box::new(timing.bounded(pending.max_depth))),
+                })
+                .collect();
+            response.timing = Some(QueryTiming {
+                local_ms: millis(pending.local_elapsed),
+                peers,
+                merge_ms: millis(merge_started.elapsed()),
+                total_ms: millis(pending.received.elapsed()),
+            });
+        }
+        response
+    }
+
+    fn next_pending_deadline(&self) -> Option<Instant> {
+        self.pending_requests
+            .values()
+            .filter_map(|pending| {
+                let pending = pending.lock().unwrap();
+                pending.waiting_for
+                    .values()
+                    .copied()
+                    .chain(pending.deadline)
+                    .chain(Some(pending.sent_at + self.max_pending_age()))
+                    .min()
+            })
+            .min()
+    }
+
+    /// Each pending query once, however many peer requests point to it
+    fn distinct_pending(&self) -> Vec<Arc<Mutex<PendingRequest>>> {
+        distinct_shared(self.pending_requests.values())
+    }
+
+    /// Stop waiting for peers past their cutoff, and answer queries that have nobody left to wait for
+    /// or whose budget ran out with the peer responses received so far
+    fn expire_pending_requests(&mut self) {
+        let now = Instant::now();
+        for pending_arc in self.distinct_pending() {
+            let (too_old, done) = {
+                let mut pending = pending_arc.lock().unwrap();
+                let late: Vec<PeerId> = pending.waiting_for
+                    .iter()
+                    .filter(|(_, cutoff)| **cutoff <= now)
+                    .map(|(peer, _)| *peer)
+                    .collect();
+                for peer in late {
+                    debug!(peer_id = %peer, "Peer missed its cutoff, answering without it");
+                    pending.waiting_for.remove(&peer);
+                    self.query_stats.peer_cutoffs_missed += 1;
+                    // Count the timeout as a slow response so the next cutoff for this peer is more generous
+                    self.record_peer_latency(peer, now - pending.sent_at);
+                }
+
+                let budget_exhausted = pending.deadline.is_some_and(|deadline| deadline <= now);
+                if budget_exhausted {
+                    debug!("Query budget exhausted with {} peers still outstanding", pending.waiting_for.len());
+                    self.query_stats.budget_exhausted += 1;
+                }
+                (
+                    now.duration_since(pending.sent_at) >= self.max_pending_age(),
+                    budget_exhausted || pending.waiting_for.is_empty(),
+                )
+            };
+
+            if too_old {
+                // Only reachable if a response or failure event got lost
+                warn!("Evicting a query that waited more than {:?} for peers", MAX_PENDING_AGE);
+                self.query_stats.evicted_for_age += 1;
+                self.evict_pending(&pending_arc);
+            } else if done {
+                self.pending_requests.retain(|_, v| !Arc::ptr_eq(v, &pending_arc));
+                let mut pending = pending_arc.lock().unwrap();
+                let final_response = self.merge_pending(&pending);
+                let channel = std::mem::replace(&mut pending.response_channel, oneshot::channel().0);
+                let _ = channel.send(Ok(final_response));
+            }
+        }
+    }
+
+    /// Drop a pending query before its peers are done, answering with the partial result,
+    /// or with a timeout error if there is nothing to show at all
+    fn evict_pending(&mut self, pending_arc: &Arc<Mutex<PendingRequest>>) {
+        self.pending_requests.retain(|_, v| !Arc::ptr_eq(v, pending_arc));
+        let mut pending = pending_arc.lock().unwrap();
+        let partial = self.merge_pending(&pending);
+        let result = if partial.scores.is_empty() {
+            Err(anyhow::anyhow!("Timed out waiting for {} peers", pending.waiting_for.len()))
+        } else {
+            Ok(partial)
+        };
+        let channel = std::mem::replace(&mut pending.response_channel, oneshot::channel().0);
+        let _ = channel.send(result);
+    }
+
+    /// How long to wait for a peer, from how fast it answered before; slow peers may get up to max_request_timeout
+    fn peer_cutoff(&self, peer: &PeerId) -> TokioDuration {
+        match self.peer_latency.get(peer) {
+            Some(latency) => latency.mul_f64(PEER_TIMEOUT_FACTOR).max(MIN_PEER_TIMEOUT).min(self.swarm_timeouts.request),
+            None => self.request_timeout,
+        }
+    }
+
+    /// Pending queries are dropped after MAX_PENDING_AGE, unless peers may be waited for longer
+    fn max_pending_age(&self) -> TokioDuration {
+        MAX_PENDING_AGE.max(self.swarm_timeouts.request)
+    }
+
+    fn record_peer_latency(&mut self, peer: PeerId, sample: TokioDuration) {
+        let latency = self.peer_latency
+            .get(&peer)
+            .map_or(sample, |previous| previous.mul_f64(1.0 - LATENCY_SMOOTHING) + sample.mul_f64(LATENCY_SMOOTHING));
+        self.peer_latency.insert(peer, latency);
+    }
+
     async fn handle_request_failure(&mut self, request_id: request_response::OutboundRequestId, peer: PeerId) -> Result<()> {
         if let Some(pending_arc) = self.pending_requests.get(&request_id).cloned() {
             let (should_remove, response_channel, result) = {
@@ -433,12 +1800,12 @@ impl<S: Storage + 'static> TrustNode<S> {
                 pending.waiting_for.remove(&peer);
 
                 if pending.waiting_for.is_empty() {
-                    // No more peers to wait for
-                    let result = if pending.responses.is_empty() {
+                    // No more peers to wait for; answer from what we have, like an expired query
+                    let partial = self.merge_pending(&pending);
+                    let result = if partial.scores.is_empty() && pending.responses.is_empty() {
                         Err(anyhow::anyhow!("All requests failed"))
                     } else {
-                        let final_response = merge_responses(pending.responses.clone());
-                        Ok(final_response)
+                        Ok(partial)
                     };
                     (true, Some(std::mem::replace(&mut pending.response_channel, 
                         oneshot::channel().0)), // Dummy replacement
@@ -461,68 +1828,244 @@ impl<S: Storage + 'static> TrustNode<S> {
     }
 
     async fn handle_command(&mut self, command: NodeCommand) -> Result<()> {
+        let rescore = command.rescore();
+        // An edit can move an experience to another agent, whose old one has to be looked at too
+        let agent_before = match &rescore {
+            Some(Rescore::Experience(experience_id)) => self.experience_agent(experience_id).await,
+            _ => None,
+        };
+        let registry_changed = matches!(
+            command,
+            NodeCommand::SetDomainDefaults { .. }
+                | NodeCommand::RemoveDomainDefaults { .. }
+                | NodeCommand::SetInflationIndex { .. }
+                | NodeCommand::RemoveInflationIndex { .. }
+                | NodeCommand::RestoreBackup { .. }
+        );
+        let precision_changed = matches!(
+            command,
+            NodeCommand::SetSharingPrecision { .. }
+                | NodeCommand::RemoveSharingPrecision { .. }
+                | NodeCommand::RestoreBackup { .. }
+        );
         match command {
-            NodeCommand::AddExperience { experience, response } => {
+            NodeCommand::AddExperience { mut experience, response } => {
+                self.canonicalize_experience(&mut experience);
+                let agent = (experience.id_domain.clone(), experience.agent_id.clone());
                 let result = self.storage.add_experience(experience).await;
-                let _ = response.send(result);
+                if result.is_ok() {
+                    self.query_engine.invalidate_agent(&agent.0, &agent.1).await;
+                }
+                let _ = response.send(result.map_err(Into::into));
+            }
+            NodeCommand::AddExperiences { mut experiences, response } => {
+                for experience in &mut experiences {
+                    self.canonicalize_experience(experience);
+                }
+                let agents: HashSet<(String, String)> = experiences
+                    .iter()
+                    .map(|e| (e.id_domain.clone(), e.agent_id.clone()))
+                    .collect();
+                let result = self.storage.add_experiences(experiences).await;
+                if result.is_ok() {
+                    for (id_domain, agent_id) in &agents {
+                        self.query_engine.invalidate_agent(id_domain, agent_id).await;
+                    }
+                }
+                let _ = response.send(result.map_err(Into::into));
             }
             NodeCommand::GetExperiences { id_domain, agent_id, response } => {
-                let result = self.storage.get_experiences(&id_domain, &agent_id).await;
+                let agent_id = self.canonical_agent_id(&id_domain, &agent_id);
+                let result = self.storage.get_experiences(&id_domain, &agent_id).await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::FindExperiences { filter, response } => {
+                let result = async {
+                    let experiences = self.storage.find_experiences(&filter).await?;
+                    let total = self.storage.count_experiences(&filter).await?;
+                    Ok::<_, anyhow::Error>(ExperiencePage { experiences, total })
+                }
+                .await;
+                let _ = response.send(result);
+            }
+            NodeCommand::UpdateExperience { experience_id, update, response } => {
+                let result = self.update_experience(&experience_id, update).await;
+                let _ = response.send(result);
+            }
+            NodeCommand::GetExperienceHistory { experience_id, response } => {
+                let result = self.storage.get_experience_history(&experience_id).await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::RevertExperience { experience_id, revision, response } => {
+                let result = self.revert_experience(&experience_id, revision).await;
                 let _ = response.send(result);
             }
             NodeCommand::RemoveExperience { experience_id, response } => {
-                let result = self.storage.remove_experience(&experience_id).await;
+                let result = self.remove_experience(&experience_id).await;
+                let _ = response.send(result);
+            }
+            NodeCommand::AddPendingExperience { mut pending, response } => {
+                pending.agent_id = self.canonical_agent_id(&pending.id_domain, &pending.agent_id);
+                let result = self.storage.add_pending_experience(pending).await;
+                let _ = response.send(result.map_err(Into::into));
+            }
+            NodeCommand::GetPendingExperiences { response } => {
+                let result = self.storage.get_pending_experiences().await;
+                let _ = response.send(result.map_err(Into::into));
+            }
+            NodeCommand::SettleExperience { experience_id, return_value, discounting, discount_rate, response } => {
+                let result = self.settle_experience(&experience_id, return_value, discounting, discount_rate).await;
+                let _ = response.send(result);
+            }
+            NodeCommand::GetDiscounting { id_domains, response } => {
+                let result = self.discounting_for(id_domains).await;
                 let _ = response.send(result);
             }
+            NodeCommand::AddExperienceTemplate { mut template, response } => {
+                template.agent_id = self.canonical_agent_id(&template.id_domain, &template.agent_id);
+                let result = self.storage.add_experience_template(&template).await;
+                if result.is_ok() {
+                    self.generate_recurring().await;
+                }
+                let _ = response.send(result.map_err(Into::into));
+            }
+            NodeCommand::GetExperienceTemplates { response } => {
+                let result = self.storage.get_experience_templates().await;
+                let _ = response.send(result.map_err(Into::into));
+            }
+            NodeCommand::RemoveExperienceTemplate { template_id, response } => {
+                let result = self.storage.remove_experience_template(&template_id).await;
+                let _ = response.send(result.map_err(Into::into));
+            }
             NodeCommand::AddPeer { peer, response } => {
-                // Try to parse peer_id as a multiaddr (e.g., /ip4/127.0.0.1/tcp/9015/p2p/12D3KooW...)
-                if let Ok(addr) = peer.peer_id.parse::<Multiaddr>() {
-                    // Extract peer ID from the multiaddr
-                    if let Some(libp2p::multiaddr::Protocol::P2p(peer_id_hash)) = addr.iter().last() {
-                        if let Ok(peer_id) = PeerId::from_multihash(peer_id_hash.into()) {
-                            debug!("Adding peer {} at address {}", peer_id, addr);
-                            
-                            // Add address to Kademlia DHT
-                            self.swarm.behaviour_mut().kademlia.add_address(&peer_id, addr.clone());
-                            
-                            // Attempt to dial the peer
-                            if let Err(e) = self.swarm.dial(addr) {
-                                warn!("Failed to dial peer {}: {}", peer_id, e);
-                            } else {
-                                info!("Dialing peer {} successfully initiated", peer_id);
-                            }
-                        } else {
-                            warn!("Failed to parse peer ID from multiaddr: {}", peer.peer_id);
-                        }
-                    } else {
-                        warn!("Multiaddr does not contain a peer ID: {}", peer.peer_id);
-                    }
-                } else {
-                    warn!("Failed to parse peer_id as multiaddr: {}", peer.peer_id);
+                let peer = peer.normalized();
+                self.dial_new_peer(&peer);
+                let result = self.storage.add_peer(peer.clone()).await;
+                if result.is_ok() {
+                    self.peers.insert(peer.peer_id.clone(), peer);
                 }
-                
-                self.peers.insert(peer.peer_id.clone(), peer.clone());
-                let result = self.storage.add_peer(peer).await;
+                let result = result.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::CreateInvite { name, addresses, ttl, response } => {
+                let _ = response.send(self.create_invite(name, addresses, ttl));
+            }
+            NodeCommand::RedeemInvite { token, name, handle, recommender_quality, introduce_as, response } => {
+                let result = self.redeem_invite(&token, name, handle, recommender_quality, introduce_as).await;
+                let _ = response.send(result);
+            }
+            NodeCommand::ProposePeering { peer_id, addresses, name, recommender_quality, tags, introduce_as, note, response } => {
+                let proposal = PeerProposal { name: introduce_as, addresses: self.own_addresses(), contact: Default::default(), tags: tags.clone(), note };
+                let result = self.propose_peering(&peer_id, addresses, name, recommender_quality, tags, proposal).await;
+                let _ = response.send(result);
+            }
+            NodeCommand::GetAgentClaims { response } => {
+                let result = self.storage.get_agent_claims().await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::ClaimAgent { id_domain, agent_id, response } => {
+                let claim = AgentClaim {
+                    agent_id: self.canonical_agent_id(&id_domain, &agent_id),
+                    id_domain,
+                    challenge: reputation::new_challenge(self.swarm.local_peer_id()),
+                    claimed_at: self.clock.now(),
+                    proof_url: None,
+                    verified_at: None,
+                };
+                let result = self.storage.add_agent_claim(&claim).await.map(|_| claim);
+                let _ = response.send(result.map_err(Into::into));
+            }
+            NodeCommand::VerifyAgentClaim { id_domain, agent_id, proof_url, response } => {
+                self.verify_agent_claim(id_domain, agent_id, proof_url, response).await;
+            }
+            NodeCommand::RemoveAgentClaim { id_domain, agent_id, response } => {
+                let agent_id = self.canonical_agent_id(&id_domain, &agent_id);
+                let result = self.storage.remove_agent_claim(&id_domain, &agent_id).await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::QueryOwnReputation { response } => {
+                self.query_own_reputation(response).await;
+            }
+            NodeCommand::RotateIdentity { response } => {
+                let _ = response.send(self.rotate_identity());
+            }
+            NodeCommand::AddPeers { peers, strategy, dry_run, response } => {
+                let result = self.add_peers(peers, strategy, dry_run).await;
                 let _ = response.send(result);
             }
             NodeCommand::GetPeers { response } => {
-                let result = self.storage.get_peers().await;
+                let now = self.clock.now();
+                let result = self.storage.get_peers().await.map(|peers| {
+                    peers
+                        .into_iter()
+                        .map(|peer| PeerStatus {
+                            effective_quality: effective_quality(&peer, self.quality_decay, now),
+                            peer,
+                        })
+                        .collect()
+                });
+                let _ = response.send(result.map_err(Into::into));
+            }
+            NodeCommand::UpdatePeer { peer_id, update, response } => {
+                let result = self.update_peer(&peer_id, update).await;
                 let _ = response.send(result);
             }
-            NodeCommand::UpdatePeerQuality { peer_id, quality, response } => {
-                if let Some(peer) = self.peers.get_mut(&peer_id) {
-                    peer.recommender_quality = quality;
-                }
-                let result = self.storage.update_peer_quality(&peer_id, quality).await;
+            NodeCommand::UpdatePeerQuality { peer_id, quality, reason, response } => {
+                let result = self.update_peer_quality(&peer_id, quality, reason.as_deref().unwrap_or(QUALITY_SET)).await;
+                let _ = response.send(result);
+            }
+            NodeCommand::GetQualityHistory { peer_id, response } => {
+                let peer_id = match self.resolve_peer(&peer_id) {
+                    Ok(resolved) => resolved,
+                    // History outlives the peer, so removed peers are still looked up by id
+                    Err(StorageError::NotFound(_)) => peer_id,
+                    Err(e) => {
+                        let _ = response.send(Err(e.into()));
+                        return Ok(());
+                    }
+                };
+                let result = self.storage.get_quality_history(&peer_id).await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::GetPeerRequests { response } => {
+                let result = self.storage.get_peer_requests().await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::AcceptPeerRequest { peer_id, name, quality, tags, response } => {
+                let _ = response.send(self.accept_peer_request(peer_id, name, quality, tags).await);
+            }
+            NodeCommand::SetPeerRequestStatus { peer_id, status, response } => {
+                let _ = response.send(self.set_peer_request_status(peer_id, status).await);
+            }
+            NodeCommand::RemovePeerRequest { peer_id, response } => {
+                self.screened_peers.remove(&peer_id);
+                let result = self.storage.remove_peer_request(&peer_id).await.map_err(Into::into);
                 let _ = response.send(result);
             }
             NodeCommand::RemovePeer { peer_id, response } => {
-                self.peers.remove(&peer_id);
-                let result = self.storage.remove_peer(&peer_id).await;
+                let result = match self.resolve_peer(&peer_id) {
+                    Ok(peer_id) => {
+                        self.peers.remove(&peer_id);
+                        self.storage.remove_peer(&peer_id).await.map_err(Into::into)
+                    }
+                    Err(e) => Err(e.into()),
+                };
                 let _ = response.send(result);
             }
             NodeCommand::QueryTrust { query, response } => {
-                self.process_trust_query(query, response).await?;
+                if let Err(e) = self.query_limits.check(&query) {
+                    let _ = response.send(Err(e.into()));
+                    return Ok(());
+                }
+                let now = self.clock.now();
+                for agent in &query.agents {
+                    let key = (agent.id_domain.clone(), agent.agent_id.clone());
+                    *self.query_counts.entry(key.clone()).or_default() += 1;
+                    let (queries, last_queried_at) = self.agent_queries.entry(key).or_insert((0, now));
+                    *queries += 1;
+                    *last_queried_at = now;
+                }
+                self.process_trust_query(query, response, false);
             }
             NodeCommand::GetConnectedPeers { response } => {
                 let connected: Vec<String> = self.swarm.connected_peers()
@@ -530,125 +2073,420 @@ impl<S: Storage + 'static> TrustNode<S> {
                     .collect();
                 let _ = response.send(Ok(connected));
             }
+            NodeCommand::GetQuarantinedPeers { response } => {
+                let _ = response.send(Ok(self.quarantine.list()));
+            }
+            NodeCommand::ReleaseQuarantinedPeer { peer_id, response } => {
+                let _ = response.send(self.release_quarantined_peer(&peer_id).map_err(Into::into));
+            }
             NodeCommand::TriggerPeerDiscovery { response } => {
                 let result = self.discover_peers().await;
                 let _ = response.send(result);
             }
-            NodeCommand::ExportTrustData { response } => {
-                let result = self.export_trust_data().await;
+            NodeCommand::ExportTrustData { point_in_time, response } => {
+                let result = match point_in_time {
+                    Some(point_in_time) => self.export_snapshot(point_in_time).await,
+                    None => self.export_trust_data().await,
+                };
+                let _ = response.send(result);
+            }
+            NodeCommand::ImportTrustData { data, strategy, dry_run, response } => {
+                let result = self.import_trust_data(data, strategy, dry_run).await;
                 let _ = response.send(result);
             }
-            NodeCommand::ImportTrustData { data, overwrite, response } => {
-                let result = self.import_trust_data(data, overwrite).await;
+            NodeCommand::ImportBatch { batch, strategy, dry_run, response } => {
+                debug!("Importing a batch of {} records", batch.len());
+                let result = self.import_records(batch.experiences, batch.peers, strategy, dry_run).await;
                 let _ = response.send(result);
             }
+            NodeCommand::GetIdempotentResponse { endpoint, key, response } => {
+                let result = match self.idempotency_ttl {
+                    Some(_) => self.storage.get_idempotent_response(&endpoint, &key, self.clock.now()).await.map_err(Into::into),
+                    None => Ok(None),
+                };
+                let _ = response.send(result);
+            }
+            NodeCommand::RecordIdempotentResponse { snapshot, response } => {
+                let _ = response.send(self.record_idempotent_response(snapshot).await);
+            }
             NodeCommand::GetSelfPeerId { response } => {
                 let peer_id = self.swarm.local_peer_id().to_string();
                 let _ = response.send(Ok(peer_id));
             }
+            NodeCommand::GetListenAddresses { response } => {
+                let addresses = self.swarm.listeners().map(ToString::to_string).collect();
+                let _ = response.send(Ok(addresses));
+            }
+            NodeCommand::FlushForShutdown { response } => {
+                self.flush_agent_queries().await;
+                let _ = response.send(Ok(()));
+            }
+            NodeCommand::GetRuntimeConfig { response } => {
+                let _ = response.send(Ok(self.runtime_config()));
+            }
+            NodeCommand::UpdateRuntimeConfig { update, response } => {
+                let result = update.apply_to(&self.runtime_config()).inspect(|config| {
+                    self.apply_runtime_config(config);
+                });
+                let _ = response.send(result.map_err(Into::into));
+            }
             NodeCommand::ClearPeers { response } => {
                 self.peers.clear();
-                let result = self.storage.clear_peers().await;
+                let result = self.storage.clear_peers().await.map_err(Into::into);
                 let _ = response.send(result);
             }
             NodeCommand::ClearExperiences { response } => {
-                let result = self.storage.clear_experiences().await;
+                let result = self.storage.clear_experiences().await.map_err(Into::into);
+                self.query_engine.clear_cache().await;
                 let _ = response.send(result);
             }
-        }
-        Ok(())
-    }
-
-    async fn process_trust_query(&mut self, query: TrustQuery, response: oneshot::Sender<Result<TrustResponse>>) -> Result<()> {
-        let point_in_time = query.point_in_time.unwrap_or_else(Utc::now);
-        let forget_rate = query.forget_rate.unwrap_or(0.0);
-        let max_depth = query.max_depth;
-
-        let mut all_scores: HashMap<(String, String), Vec<(String, TrustScore, f64)>> = HashMap::new();
-
-        // Get personal scores
-        for agent in &query.agents {
-            let personal_score = self.query_engine
-                .calculate_trust_score(&agent.id_domain, &agent.agent_id, point_in_time, forget_rate)
-                .await?;
-            
-            if personal_score.total_volume > 0.0 {
-                all_scores
-                    .entry((agent.id_domain.clone(), agent.agent_id.clone()))
-                    .or_default()
-                    .push(("self".to_string(), personal_score, 1.0));
+            NodeCommand::GetDomainDefaults { response } => {
+                let result = self.storage.get_domain_defaults().await.map_err(Into::into);
+                let _ = response.send(result);
             }
-        }
-
-        // Always check for cached scores from peers (even at depth 0)
-        for agent in &query.agents {
-            if let Ok(cached_scores) = self.storage.get_cached_scores(&agent.id_domain, &agent.agent_id).await {
-                debug!("Found {} cached scores for agent {}:{}", cached_scores.len(), agent.id_domain, agent.agent_id);
-                for cached in cached_scores {
-                    // Find the peer's recommender quality
-                    if let Some(peer) = self.peers.values().find(|p| p.peer_id == cached.from_peer) {
-                        // Apply age decay to cached scores
-                        let age_seconds = (Utc::now() - cached.cached_at).num_seconds() as f64;
-                        let age_factor = 1.0 / (1.0 + age_seconds / 86400.0); // Decay over days
-                        
-                        debug!("Using cached score from peer {} with age factor {}", cached.from_peer, age_factor);
-                        all_scores
-                            .entry((agent.id_domain.clone(), agent.agent_id.clone()))
-                            .or_default()
-                            .push((cached.from_peer, cached.score, peer.recommender_quality * age_factor));
-                    } else {
-                        debug!("Cached score from unknown peer: {}", cached.from_peer);
-                    }
+            NodeCommand::SetDomainDefaults { defaults, response } => {
+                let result = self.storage.set_domain_defaults(&defaults).await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::RemoveDomainDefaults { id_domain, response } => {
+                let result = self.storage.remove_domain_defaults(&id_domain).await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::GetInflationIndexes { response } => {
+                let result = self.storage.get_inflation_indexes().await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::SetInflationIndex { index, response } => {
+                let result = self.storage.set_inflation_index(&index).await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::RemoveInflationIndex { currency, response } => {
+                let result = self.storage.remove_inflation_index(&currency).await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::GetSharingPrecision { response } => {
+                let result = self.storage.get_sharing_precision().await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::SetSharingPrecision { precision, response } => {
+                let result = self.storage.set_sharing_precision(&precision).await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::RemoveSharingPrecision { subject, response } => {
+                let result = self.storage.remove_sharing_precision(&subject).await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::GetWatchlist { response } => {
+                let _ = response.send(Ok(self.watchlist.list()));
+            }
+            NodeCommand::WatchAgent { id_domain, agent_id, request, response } => {
+                let agent_id = self.canonical_agent_id(&id_domain, &agent_id);
+                let result = self.watch_agent(id_domain, agent_id, request).await;
+                let _ = response.send(result);
+            }
+            NodeCommand::UnwatchAgent { id_domain, agent_id, response } => {
+                let agent_id = self.canonical_agent_id(&id_domain, &agent_id);
+                let result = self.storage.unwatch_agent(&id_domain, &agent_id).await;
+                if result.is_ok() {
+                    self.watchlist.remove(&id_domain, &agent_id);
                 }
-            } else {
-                debug!("No cached scores found for agent {}:{}", agent.id_domain, agent.agent_id);
+                let _ = response.send(result.map_err(Into::into));
+            }
+            NodeCommand::GetStorageStats { response } => {
+                let result = self.storage.storage_stats().await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::GetCacheStats { response } => {
+                let _ = response.send(Ok(self.query_engine.cache_stats()));
+            }
+            NodeCommand::GetQueryStats { response } => {
+                let stats = QueryStats {
+                    pending_queries: self.distinct_pending().len(),
+                    ..self.query_stats.clone()
+                };
+                let _ = response.send(Ok(stats));
+            }
+            NodeCommand::GetRuntimeStats { response } => {
+                let _ = response.send(Ok(self.runtime_probe.stats()));
+            }
+            NodeCommand::GetReciprocity { response } => {
+                let _ = response.send(Ok(PeerReciprocity::list(&self.traffic, &self.peers)));
+            }
+            NodeCommand::ClearCache { response } => {
+                info!("Clearing the query cache");
+                self.query_engine.clear_cache().await;
+                let _ = response.send(Ok(()));
+            }
+            NodeCommand::MaintainDatabase { response } => {
+                info!("Running database maintenance");
+                let result = self.storage.maintain().await.map_err(Into::into);
+                let _ = response.send(result);
+            }
+            NodeCommand::GetBackupStatus { response } => {
+                let status = self.backup_status.read().unwrap().clone();
+                let _ = response.send(Ok(status));
+            }
+            NodeCommand::SimulateTrust { simulation, response } => {
+                let result = self.simulate_trust(simulation).await;
+                let _ = response.send(result);
+            }
+            NodeCommand::GetAnomalies { response } => {
+                let report = self.anomalies.read().unwrap().clone();
+                let _ = response.send(Ok(report));
+            }
+            NodeCommand::GetGlobalTrust { response } => {
+                let report = self.global_trust.read().unwrap().clone();
+                let _ = response.send(Ok(report));
+            }
+            NodeCommand::SubscribeEvents { response } => {
+                let _ = response.send(Ok(self.events.subscribe()));
+            }
+            NodeCommand::GetTrustGraph { user, response } => {
+                let result = graph::build(self.storage.as_ref(), &self.query_engine, &user, self.clock.now()).await;
+                let _ = response.send(result);
+            }
+            NodeCommand::GetPeerEvents { peer_id, response } => {
+                // Events also come from peers we don't know, which only have their id
+                let peer_id = peer_id.map(|peer_id| self.resolve_peer(&peer_id).unwrap_or(peer_id));
+                let _ = response.send(Ok(self.peer_events.recent(peer_id.as_deref())));
+            }
+            NodeCommand::RestoreBackup { path, response } => {
+                let result = self.restore_backup(&path).await;
+                let _ = response.send(result);
+            }
+        }
+        if registry_changed {
+            match self.storage.get_domain_defaults().await {
+                Ok(defaults) => {
+                    self.agent_id_forms = registered_id_forms(&defaults);
+                    match self.storage.get_inflation_indexes().await {
+                        Ok(indexes) => self.query_engine.set_inflation(inflation_by_domain(&defaults, indexes)).await,
+                        Err(e) => warn!("Failed to reload inflation indexes: {}", e),
+                    }
+                }
+                Err(e) => warn!("Failed to reload agent id forms: {}", e),
+            }
+        }
+        if precision_changed {
+            match self.storage.get_sharing_precision().await {
+                Ok(precision) => self.sharing_precision = precision,
+                Err(e) => warn!("Failed to reload sharing precision: {}", e),
+            }
+        }
+        if let Some(rescore) = rescore {
+            self.extend_own_filter(&rescore).await;
+            let watched = self.watched_to_rescore(rescore, agent_before).await;
+            self.evaluate_watched(watched).await;
+        }
+        Ok(())
+    }
+
+    /// The watched agents within `rescore`, each once
+    async fn watched_to_rescore(&mut self, rescore: Rescore, agent_before: Option<(String, String)>) -> Vec<WatchedAgent> {
+        let agents: BTreeSet<(String, String)> = match rescore {
+            Rescore::All => return self.watchlist.list(),
+            Rescore::Domain(id_domain) => {
+                return self.watchlist.list().into_iter().filter(|watched| watched.id_domain == id_domain).collect();
+            }
+            Rescore::Agents(agents) => agents
+                .into_iter()
+                .map(|(id_domain, agent_id)| {
+                    let agent_id = self.canonical_agent_id(&id_domain, &agent_id);
+                    (id_domain, agent_id)
+                })
+                .collect(),
+            Rescore::Experience(experience_id) => {
+                agent_before.into_iter().chain(self.experience_agent(&experience_id).await).collect()
+            }
+        };
+        self.watchlist.select(agents.iter().map(|(id_domain, agent_id)| (id_domain.as_str(), agent_id.as_str())))
+    }
+
+    /// Domain and agent of a stored experience, None if there is none by that id
+    async fn experience_agent(&mut self, experience_id: &str) -> Option<(String, String)> {
+        match self.storage.get_experience(experience_id).await {
+            Ok(experience) => experience.map(|experience| (experience.id_domain, experience.agent_id)),
+            Err(e) => {
+                warn!("Failed to look up experience {}: {}", experience_id, e);
+                None
             }
         }
+    }
+
+    /// Gather local and cached scores on a separate task, so SQLite work never stalls the swarm;
+    /// forwarding to peers continues in the loop once they're ready
+    fn process_trust_query(&mut self, mut query: TrustQuery, response: oneshot::Sender<Result<TrustResponse>>, inbound: bool) {
+        for agent in &mut query.agents {
+            agent.agent_id = self.canonical_agent_id(&agent.id_domain, &agent.agent_id);
+        }
+        if query.continuation.is_some() {
+            query.agents = agents_after_continuation(&query);
+        }
+        let received = Instant::now();
+        let mut deadline = query.budget_ms.map(|ms| received + TokioDuration::from_millis(ms));
+        // Whatever budget is left, nothing is worth doing past the expiry
+        if let Some(expires_at) = query.expires_at {
+            let expiry = Instant::now() + (expires_at - self.clock.now()).to_std().unwrap_or_default();
+            deadline = Some(deadline.map_or(expiry, |deadline| deadline.min(expiry)));
+        }
+        // Ids peers made up aren't trusted to be fit for logs
+        if !query.correlation_id.as_deref().is_some_and(is_valid_correlation_id) {
+            query.correlation_id = Some(Uuid::new_v4().to_string());
+        }
+        let span = info_span!(
+            "trust_query",
+            correlation_id = query.correlation_id.as_deref().unwrap_or_default(),
+            agents = query.agents.len(),
+            max_depth = ?query.max_depth,
+            inbound,
+        );
+        if inbound {
+            telemetry::set_remote_parent(&span, query.traceparent.as_deref());
+        }
+        let storage = self.storage.clone();
+        let query_engine = self.query_engine.clone();
+        let peers = self.weighted_peers();
+        let hop_damping = self.hop_damping;
+        let loop_tx = self.loop_tx.clone();
+        let now = self.clock.now();
+
+        let local_span = info_span!(parent: &span, "local_scores");
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let local = gather_local_scores(storage.as_ref(), &query_engine, &peers, hop_damping, &query, now)
+                .instrument(local_span)
+                .await;
+            let local_elapsed = started.elapsed();
+            let _ = loop_tx
+                .send(LoopEvent::LocalScoresReady { query, deadline, received, local_elapsed, local, response, inbound, span })
+                .await;
+        });
+    }
+
+    /// Second half of a query: ask connected peers, or answer right away from the local scores
+    fn dispatch_query(
+        &mut self,
+        query: TrustQuery,
+        deadline: Option<Instant>,
+        (received, local_elapsed): (Instant, TokioDuration),
+        local: LocalScores,
+        response: oneshot::Sender<Result<TrustResponse>>,
+        query_span: &Span,
+    ) {
+        let LocalScores { point_in_time, max_depth, all_scores, aggregators } = local;
+        // Domain defaults aren't checked with the query, so they're capped here
+        let max_depth = max_depth.min(self.query_limits.max_depth);
+
+        // Peers get a share of what's left of the budget, so deep queries run out before we do
+        let forward_budget_ms = deadline.map(|deadline| {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            (remaining.as_millis() as f64 * FORWARD_BUDGET_SHARE) as u64
+        });
+        let budget_allows_forwarding = forward_budget_ms.is_none_or(|ms| ms >= MIN_FORWARD_BUDGET_MS);
 
         // Query peers if depth > 0
-        if max_depth > 0 {
-            let mut waiting_for = HashSet::new();
+        if max_depth > 0 && budget_allows_forwarding && !query.cache_only {
+            let span = info_span!(parent: query_span, "peer_fanout", max_depth, peers = Empty);
+            let mut waiting_for = HashMap::new();
             let mut request_ids = Vec::new();
+            let mut asked = Vec::new();
+            let sent_at = Instant::now();
 
-            // Then try to get fresh scores from connected peers
+            // Connected peers, best recommenders first so a fan-out limit keeps the most useful ones
+            let mut targets = Vec::new();
+            let mut skipped = 0;
+            let mut skipped_no_data = 0;
             for peer in self.peers.values() {
-                // Try to extract peer ID from multiaddr
-                if let Ok(addr) = peer.peer_id.parse::<Multiaddr>() {
-                    if let Some(libp2p::multiaddr::Protocol::P2p(peer_id_hash)) = addr.iter().last() {
-                        if let Ok(peer_id) = PeerId::from_multihash(peer_id_hash.into()) {
-                            debug!("Checking peer {} ({}) - connected: {}", peer.name, peer_id, self.swarm.is_connected(&peer_id));
-                            // Only query if peer is connected
-                            if self.swarm.is_connected(&peer_id) {
-                                let peer_query = TrustQuery {
-                                    agents: query.agents.clone(),
-                                    max_depth: max_depth.saturating_sub(1),
-                                    point_in_time: Some(point_in_time),
-                                    forget_rate: Some(forget_rate),
-                                };
-
-                                debug!("LIBP2P: Sending request to peer {} for {} agents with depth {}", 
-                                       peer_id, peer_query.agents.len(), peer_query.max_depth);
-                                let request_id = self.swarm
-                                    .behaviour_mut()
-                                    .request_response
-                                    .send_request(&peer_id, peer_query);
-
-                                debug!("LIBP2P: Request sent with ID {:?}", request_id);
-                                waiting_for.insert(peer_id);
-                                request_ids.push(request_id);
-                            }
-                        }
+                if let Ok(peer_id) = peer.peer_id.parse::<PeerId>() {
+                    debug!(peer_id = %peer_id, "Checking peer {} - connected: {}", peer.name, self.swarm.is_connected(&peer_id));
+                    // Only query if peer is connected
+                    if !self.swarm.is_connected(&peer_id) {
+                        continue;
+                    }
+                    if !self.might_know_any(&peer_id, &query.agents) {
+                        skipped += 1;
+                        continue;
+                    }
+                    // A refresh wants fresh answers, so it asks again anyway
+                    if !query.refresh && query.agents.iter().all(|agent| self.known_empty(&peer_id, agent)) {
+                        skipped_no_data += 1;
+                        continue;
                     }
+                    targets.push((effective_quality(peer, self.quality_decay, self.clock.now()), peer_id));
                 }
             }
+            if skipped > 0 {
+                debug!("Skipping {} peers whose availability filter has none of the agents", skipped);
+                self.query_stats.skipped_by_filter += skipped;
+            }
+            if skipped_no_data > 0 {
+                debug!("Skipping {} peers that recently had no score for any of the agents", skipped_no_data);
+                self.query_stats.skipped_no_data += skipped_no_data;
+            }
+            targets.sort_by(|a, b| b.0.total_cmp(&a.0));
+            if self.max_fanout > 0 && targets.len() > self.max_fanout {
+                debug!("Asking {} of {} connected peers", self.max_fanout, targets.len());
+                targets.truncate(self.max_fanout);
+            }
+
+            // Unset decay parameters stay unset so peers apply their own domain defaults
+            let peer_query = TrustQuery {
+                agents: query.agents.clone(),
+                max_depth: Some(max_depth.saturating_sub(1)),
+                point_in_time: Some(point_in_time),
+                forget_rate: query.forget_rate,
+                decay: query.decay,
+                aggregator: query.aggregator,
+                budget_ms: forward_budget_ms,
+                continuation: None,
+                traceparent: telemetry::traceparent(&span),
+                correlation_id: query.correlation_id.clone(),
+                timing: query.timing,
+                refresh: query.refresh,
+                cache_only: false,
+                expires_at: query.expires_at,
+            };
+            for (_, peer_id) in targets {
+                debug!(peer_id = %peer_id, "LIBP2P: Sending request for {} agents with depth {}",
+                       peer_query.agents.len(), max_depth.saturating_sub(1));
+                let request_id = self.send_query(&peer_id, peer_query.clone());
+
+                debug!(peer_id = %peer_id, request_id = %request_id, "LIBP2P: Request sent");
+                // Never wait for a peer past the query's own budget
+                let cutoff = sent_at + self.peer_cutoff(&peer_id);
+                waiting_for.insert(peer_id, deadline.map_or(cutoff, |deadline| cutoff.min(deadline)));
+                request_ids.push(request_id);
+                asked.push(peer_id);
+            }
 
             if !waiting_for.is_empty() {
+                span.record("peers", waiting_for.len());
+                let pending_queries = self.distinct_pending();
+                if pending_queries.len() >= MAX_PENDING_QUERIES {
+                    if let Some(oldest) = pending_queries.iter().min_by_key(|p| p.lock().unwrap().sent_at) {
+                        warn!("{} queries are waiting on peers, evicting the oldest", pending_queries.len());
+                        self.query_stats.evicted_for_capacity += 1;
+                        self.evict_pending(oldest);
+                    }
+                }
+
                 // Store pending request with local scores to merge later
                 let pending = Arc::new(Mutex::new(PendingRequest {
                     responses: Vec::new(),
                     waiting_for,
+                    sent_at,
                     response_channel: response,
                     local_scores: all_scores.clone(), // Store the local+cached scores
+                    aggregators,
+                    deadline,
+                    peer_query: Some(peer_query),
+                    max_depth,
+                    timing: query.timing,
+                    received,
+                    local_elapsed,
+                    asked,
+                    latencies: HashMap::new(),
+                    _span: span,
                 }));
                 
                 // Map all request_ids to the same pending request
@@ -656,133 +2494,1973 @@ impl<S: Storage + 'static> TrustNode<S> {
                     self.pending_requests.insert(request_id, pending.clone());
                 }
                 
-                return Ok(());
+                return;
             }
         }
 
         // No peers to query or depth is 0, return personal scores
-        let final_scores: Vec<crate::types::AgentScore> = all_scores
+        let merge_started = Instant::now();
+        let final_scores: Vec<AgentScore> = all_scores
             .into_iter()
             .map(|((id_domain, agent_id), scores)| {
-                let combined = self.combine_scores_sync(scores);
-                crate::types::AgentScore::new(id_domain, agent_id, combined)
+                let aggregator = aggregators.get(&(id_domain.clone(), agent_id.clone())).copied().unwrap_or_default();
+                self.score_from_sources(id_domain, agent_id, scores, aggregator, Some(max_depth))
             })
             .collect();
 
-        let trust_response = TrustResponse {
-            scores: final_scores,
-            timestamp: Utc::now(),
-        };
+        let mut trust_response = TrustResponse::new(final_scores);
+        if query.timing {
+            trust_response.timing = Some(QueryTiming {
+                local_ms: millis(local_elapsed),
+                peers: Vec::new(),
+                merge_ms: millis(merge_started.elapsed()),
+                total_ms: millis(received.elapsed()),
+            });
+        }
 
         let _ = response.send(Ok(trust_response));
-        Ok(())
     }
 
-    fn combine_scores_sync(&self, scores: Vec<(String, TrustScore, f64)>) -> TrustScore {
-        // Convert to the format expected by TrustScore::merge_multiple
+    fn runtime_config(&self) -> RuntimeConfig {
+        RuntimeConfig {
+            discovery_interval_secs: self.discovery_interval.as_secs(),
+            max_fanout: self.max_fanout,
+            cache_ttl_secs: self.query_engine.cache_ttl_seconds().max(0) as u64,
+            inbound_workers: self.inbound_workers,
+            max_queued_per_peer: self.inbound_queue.per_sender_limit(),
+            max_query_agents: self.query_limits.max_agents,
+            max_query_depth: self.query_limits.max_depth,
+        }
+    }
+
+    fn apply_runtime_config(&mut self, config: &RuntimeConfig) {
+        info!("Applying runtime config {:?}", config);
+        // The run loop restarts its discovery timer when it sees the new period
+        self.discovery_interval = Duration::from_secs(config.discovery_interval_secs);
+        self.max_fanout = config.max_fanout;
+        self.query_engine.set_cache_ttl(config.cache_ttl_secs as i64);
+        self.inbound_queue.set_per_sender_limit(config.max_queued_per_peer);
+        self.query_limits = QueryLimits {
+            max_agents: config.max_query_agents,
+            max_depth: config.max_query_depth,
+        };
+        self.inbound_workers = config.inbound_workers;
+        // More workers can pick up queued queries right away
+        self.start_inbound_queries();
+    }
+
+    /// Score an agent from own experiences and cached recommendations, without asking peers or touching storage
+    async fn simulated_score(&mut self, simulation: &TrustSimulation, options: ScoringOptions, apply_changes: bool) -> Result<AgentScore> {
+        let now = self.clock.now();
+        let point_in_time = simulation.point_in_time.unwrap_or(now);
+        let no_overrides = HashMap::new();
+        let (extra, quality_overrides) = if apply_changes {
+            (simulation.experiences.as_slice(), &simulation.peer_qualities)
+        } else {
+            (&[][..], &no_overrides)
+        };
+
+        let mut sources = Vec::new();
+        let personal_score = self.query_engine
+            .simulate_trust_score(&simulation.id_domain, &simulation.agent_id, point_in_time, options, extra)
+            .await?;
+        if personal_score.total_volume > 0.0 {
+            sources.push(ScoreSource {
+                score: personal_score,
+                weight: 1.0,
+                hops: 0,
+                farthest_hops: 0,
+                origin: ScoreOrigin::Local,
+                peer: None,
+            });
+        }
+        let cached_scores = self.storage.get_cached_scores(&simulation.id_domain, &simulation.agent_id).await?;
+        sources.extend(cached_score_sources(&self.weighted_peers(), self.hop_damping, cached_scores, quality_overrides, now));
+
+        Ok(self.score_from_sources(simulation.id_domain.clone(), simulation.agent_id.clone(), sources, options.aggregator, None))
+    }
+
+    async fn simulate_trust(&mut self, simulation: TrustSimulation) -> Result<SimulationResult> {
+        let defaults = self.storage.get_domain_defaults().await?
+            .into_iter()
+            .find(|defaults| defaults.id_domain == simulation.id_domain);
+        let options = ScoringOptions::resolve(simulation.forget_rate, simulation.decay, simulation.aggregator, defaults.as_ref());
+
+        Ok(SimulationResult {
+            current: self.simulated_score(&simulation, options, false).await?,
+            simulated: self.simulated_score(&simulation, options, true).await?,
+        })
+    }
+
+    /// The agent's score from own experiences and cached recommendations, as a query without peers would answer
+    async fn merged_score(&mut self, id_domain: &str, agent_id: &str) -> Result<TrustScore> {
+        let simulation = TrustSimulation {
+            id_domain: id_domain.to_string(),
+            agent_id: agent_id.to_string(),
+            ..TrustSimulation::default()
+        };
+        let defaults = self.storage.get_domain_defaults().await?
+            .into_iter()
+            .find(|defaults| defaults.id_domain == id_domain);
+        let options = ScoringOptions::resolve(None, None, None, defaults.as_ref());
+        Ok(self.simulated_score(&simulation, options, false).await?.score)
+    }
+
+    async fn watch_agent(&mut self, id_domain: String, agent_id: String, request: WatchRequest) -> Result<WatchedAgent> {
+        request.validate()?;
+        let added_at = self.watchlist
+            .select([(id_domain.as_str(), agent_id.as_str())])
+            .first()
+            .map_or_else(|| self.clock.now(), |existing| existing.added_at);
+        let last_score = self.merged_score(&id_domain, &agent_id).await?;
+        let watched = WatchedAgent {
+            id_domain,
+            agent_id,
+            thresholds: request.thresholds,
+            min_delta: request.min_delta,
+            webhook: request.webhook,
+            last_score,
+            added_at,
+        };
+        self.storage.watch_agent(&watched).await?;
+        self.watchlist.insert(watched.clone());
+        Ok(watched)
+    }
+
+    /// Recompute the merged scores of `watched` and announce those that crossed a threshold or moved far enough
+    async fn evaluate_watched(&mut self, watched: Vec<WatchedAgent>) {
+        for agent in watched {
+            let current = match self.merged_score(&agent.id_domain, &agent.agent_id).await {
+                Ok(current) => current,
+                Err(e) => {
+                    warn!(agent = %agent.agent_id, "Failed to score watched agent: {}", e);
+                    continue;
+                }
+            };
+            let Some(change) = agent.change_to(&current, self.clock.now()) else {
+                continue;
+            };
+            if let Err(e) = self.storage.set_watched_score(&agent.id_domain, &agent.agent_id, &current).await {
+                warn!(agent = %agent.agent_id, "Failed to store watched score: {}", e);
+            }
+            self.watchlist.record(&agent.id_domain, &agent.agent_id, &current);
+            self.watchlist.announce(change, agent.webhook.as_deref(), &self.events);
+        }
+    }
+
+    /// Combine an agent's contributions into the score we answer with, including where its volume came from;
+    /// `max_depth` is the depth the query ran with, None for what-if scores that ask nobody
+    fn score_from_sources(
+        &self,
+        id_domain: String,
+        agent_id: String,
+        mut scores: Vec<ScoreSource>,
+        aggregator: Aggregator,
+        max_depth: Option<u8>,
+    ) -> AgentScore {
+        let capped = self.cap_influence(&mut scores);
+        let hops = scores.iter().map(|source| source.hops).min().unwrap_or(0);
+        let farthest_hops = scores.iter().map(|source| source.farthest_hops).max().unwrap_or(0);
+        let mut freshness = Freshness::default();
+        for source in &scores {
+            let volume = self.score_clamping.source_volume(source.score.total_volume, source.weight);
+            match &source.origin {
+                ScoreOrigin::Local => freshness.local_volume += volume,
+                ScoreOrigin::Cached { cached_at } => freshness.add_cached(volume, *cached_at),
+                ScoreOrigin::Live(reported) => freshness.add_live(volume, reported),
+            }
+        }
+
+        // Convert to the format expected by TrustScore::merge_multiple_with
         let score_weight_pairs: Vec<(TrustScore, f64)> = scores
             .into_iter()
-            .map(|(_, score, quality)| (score, quality))
+            .map(|source| (source.score, source.weight))
             .collect();
-        
-        TrustScore::merge_multiple(score_weight_pairs)
+        let combined = TrustScore::merge_multiple_with(score_weight_pairs, aggregator, &self.score_clamping);
+        let global = self.global_trust.read().unwrap().score(&id_domain, &agent_id);
+
+        let mut score = AgentScore::new(id_domain, agent_id, combined)
+            .with_hops(hops)
+            .with_depth(max_depth.map(|max_depth| ScoreDepth { max_depth, farthest_hops }))
+            .with_freshness(freshness)
+            .with_global(global)
+            .with_capped(capped);
+        score.apply_min_evidence(&self.min_evidence);
+        score
     }
 
-    async fn discover_peers(&mut self) -> Result<()> {
-        info!("Starting peer discovery");
-        
-        // Try to bootstrap again to discover new peers
-        if let Err(e) = self.swarm.behaviour_mut().kademlia.bootstrap() {
-            debug!("Bootstrap attempt failed: {:?}", e);
+    fn canonical_agent_id(&self, id_domain: &str, agent_id: &str) -> String {
+        self.agent_id_forms
+            .get(id_domain)
+            .copied()
+            .unwrap_or_else(|| AgentIdForm::for_domain(id_domain))
+            .canonicalize(agent_id)
+    }
+
+    fn canonicalize_experience(&self, experience: &mut TrustExperience) {
+        experience.agent_id = self.canonical_agent_id(&experience.id_domain, &experience.agent_id);
+    }
+
+    /// Make the peer's addresses known to Kademlia and dial it
+    fn dial_new_peer(&mut self, peer: &Peer) {
+        let Some((peer_id, addresses)) = dial_target(peer) else {
+            warn!("Failed to parse peer ID {}", peer.peer_id);
+            return;
+        };
+        for address in &addresses {
+            debug!(peer_id = %peer_id, "Adding peer at address {}", address);
+            self.swarm.behaviour_mut().kademlia.add_address(&peer_id, address.clone());
+        }
+
+        // Without addresses the swarm asks Kademlia where to find the peer
+        let address = addresses.first().map(|address| address.to_string());
+        if let Err(e) = self.swarm.dial(dial_opts(peer_id, addresses)) {
+            warn!(peer_id = %peer_id, "Failed to dial peer: {}", e);
+            self.record_peer_event(PeerEventKind::Failed, Some(peer_id), address, Some(e.to_string()));
+        } else {
+            info!(peer_id = %peer_id, "Dialing peer successfully initiated");
         }
-        
-        // Query for peers close to random keys to discover the network
-        let random_peer_id = PeerId::random();
-        self.swarm.behaviour_mut().kademlia.get_closest_peers(random_peer_id);
-        
-        // Also query for peers close to our own ID
-        let local_peer_id = *self.swarm.local_peer_id();
-        self.swarm.behaviour_mut().kademlia.get_closest_peers(local_peer_id);
-        
-        Ok(())
     }
 
-    async fn connect_to_known_peers(&mut self) -> Result<()> {
-        let connected_peers: HashSet<PeerId> = self.swarm.connected_peers().cloned().collect();
-        let mut connection_attempts = 0;
-        const MAX_CONNECTION_ATTEMPTS: usize = 5;
-        
-        for peer in self.peers.values() {
-            if connection_attempts >= MAX_CONNECTION_ATTEMPTS {
-                break;
+    /// Addresses friends can dial us at: the ones we listen on, minus unspecified ones like 0.0.0.0
+    fn own_addresses(&self) -> Vec<String> {
+        let mut addresses = Vec::new();
+        for address in self.swarm.external_addresses().chain(self.swarm.listeners()) {
+            let unspecified = address.iter().any(|protocol| match protocol {
+                libp2p::multiaddr::Protocol::Ip4(ip) => ip.is_unspecified(),
+                libp2p::multiaddr::Protocol::Ip6(ip) => ip.is_unspecified(),
+                _ => false,
+            });
+            let address = address.to_string();
+            if !unspecified && !addresses.contains(&address) {
+                addresses.push(address);
             }
-            
-            // Try to parse peer_id as either a PeerId or a multiaddr
-            if let Ok(peer_id) = peer.peer_id.parse::<PeerId>() {
-                if !connected_peers.contains(&peer_id) {
-                    debug!("Attempting to connect to known peer: {}", peer_id);
-                    if let Err(e) = self.swarm.dial(peer_id) {
-                        debug!("Failed to dial peer {}: {:?}", peer_id, e);
-                    } else {
-                        connection_attempts += 1;
-                    }
+        }
+        addresses
+    }
+
+    fn create_invite(&mut self, name: Option<String>, addresses: Option<Vec<String>>, ttl: Option<chrono::Duration>) -> Result<IssuedInvite> {
+        let now = self.clock.now();
+        self.open_invites.retain(|_, expires_at| *expires_at > now);
+
+        let ttl = ttl.unwrap_or(DEFAULT_INVITE_TTL).min(MAX_INVITE_TTL);
+        let invite = Invite {
+            id: Uuid::new_v4(),
+            peer_id: self.swarm.local_peer_id().to_string(),
+            addresses: addresses.unwrap_or_else(|| self.own_addresses()),
+            name,
+            expires_at: now + ttl,
+        };
+        let token = invite.sign(&self.identity)?;
+        self.open_invites.insert(invite.id, invite.expires_at);
+        info!("Issued invite {} valid until {}", invite.id, invite.expires_at);
+        Ok(IssuedInvite { token, expires_at: invite.expires_at, addresses: invite.addresses })
+    }
+
+    /// Add the peer behind an invite token, dial it and send it a friend request so it adds us too
+    async fn redeem_invite(
+        &mut self,
+        token: &str,
+        name: Option<String>,
+        handle: Option<String>,
+        recommender_quality: f64,
+        introduce_as: Option<String>,
+    ) -> Result<Peer> {
+        let invite = Invite::verify(token, self.clock.now())?;
+        let peer_id: PeerId = invite.peer_id.parse().map_err(|_| InvalidInvite("bad peer id".to_string()))?;
+        if peer_id == *self.swarm.local_peer_id() {
+            return Err(InvalidInvite("issued by this node".to_string()).into());
+        }
+
+        let peer = match self.peers.get(&invite.peer_id) {
+            // Already friends one way; still ask them to add us back
+            Some(known) => known.clone(),
+            None => {
+                let mut peer = Peer {
+                    peer_id: invite.peer_id.clone(),
+                    addresses: Vec::new(),
+                    handle,
+                    name: name.or(invite.name).unwrap_or_else(|| invite.peer_id.clone()),
+                    recommender_quality,
+                    added_at: self.clock.now(),
+                    notes: None,
+                    tags: Vec::new(),
+                    contact: Default::default(),
+                    last_interaction_at: None,
+                };
+                for address in &invite.addresses {
+                    peer.learn_address(address);
                 }
-            } else if let Ok(addr) = peer.peer_id.parse::<Multiaddr>() {
-                // Extract peer ID from multiaddr if possible
-                if let Some(peer_id) = addr.iter().find_map(|p| match p {
-                    libp2p::multiaddr::Protocol::P2p(id) => Some(id),
-                    _ => None,
-                }) {
-                    if !connected_peers.contains(&peer_id) {
-                        debug!("Attempting to connect to peer via multiaddr: {}", addr);
-                        if let Err(e) = self.swarm.dial(addr.clone()) {
-                            debug!("Failed to dial multiaddr {}: {:?}", addr, e);
-                        } else {
-                            connection_attempts += 1;
-                        }
-                    }
+                let peer = peer.normalized();
+                self.storage.add_peer(peer.clone()).await?;
+                self.peers.insert(peer.peer_id.clone(), peer.clone());
+                peer
+            }
+        };
+        // The friend request dials the inviter itself
+        if let Some((_, addresses)) = dial_target(&peer) {
+            self.remember_addresses(&peer_id, addresses);
+        }
+
+        let request = FriendRequest { invite_id: invite.id, name: introduce_as, addresses: self.own_addresses() };
+        self.swarm.behaviour_mut().invites.send_request(&peer_id, request);
+        info!(peer_id = %peer_id, "Redeemed invite, asked {} to add us back", peer.name);
+        Ok(peer)
+    }
+
+    async fn handle_invite_event(&mut self, event: ReqResEvent<FriendRequest, FriendResponse>) {
+        match event {
+            ReqResEvent::Message { peer, message: Message::Request { request, channel, .. } } => {
+                let response = self.accept_friend_request(peer, request).await;
+                let _ = self.swarm.behaviour_mut().invites.send_response(channel, response);
+            }
+            ReqResEvent::Message { peer, message: Message::Response { response, .. } } => {
+                if response.accepted {
+                    info!(peer_id = %peer, "Inviter added us back");
+                    self.record_peer_event(PeerEventKind::Befriended, Some(peer), None, None);
+                } else {
+                    let reason = response.reason.unwrap_or_default();
+                    warn!(peer_id = %peer, "Inviter declined our friend request: {}", reason);
+                    self.record_peer_event(PeerEventKind::Failed, Some(peer), None, Some(reason));
                 }
             }
+            ReqResEvent::OutboundFailure { peer, error, .. } => {
+                warn!(peer_id = %peer, "Friend request failed: {:?}", error);
+                self.record_peer_event(PeerEventKind::Failed, Some(peer), None, Some(error.to_string()));
+            }
+            _ => {}
         }
-        
-        if connection_attempts > 0 {
-            info!("Attempted {} peer connections", connection_attempts);
+    }
+
+    /// Add whoever redeemed one of our open invites; each invite is good for one friend
+    async fn accept_friend_request(&mut self, peer: PeerId, request: FriendRequest) -> FriendResponse {
+        let declined = |reason: &str| FriendResponse { accepted: false, reason: Some(reason.to_string()) };
+        let expires_at = match self.open_invites.remove(&request.invite_id) {
+            Some(expires_at) if expires_at > self.clock.now() => expires_at,
+            Some(_) => return declined("invite expired"),
+            None => return declined("unknown or already redeemed invite"),
+        };
+
+        let peer_id = peer.to_string();
+        if !self.peers.contains_key(&peer_id) {
+            let mut friend = Peer {
+                peer_id: peer_id.clone(),
+                addresses: Vec::new(),
+                handle: None,
+                name: request.name.unwrap_or_else(|| peer_id.clone()),
+                recommender_quality: DEFAULT_INVITED_QUALITY,
+                added_at: self.clock.now(),
+                notes: None,
+                tags: Vec::new(),
+                contact: Default::default(),
+                last_interaction_at: None,
+            };
+            for address in &request.addresses {
+                friend.learn_address(address);
+            }
+            let friend = friend.normalized();
+            if let Err(e) = self.storage.add_peer(friend.clone()).await {
+                warn!(peer_id = %peer, "Failed to add invited peer: {}", e);
+                // Let them try again, e.g. after a transient storage error
+                self.open_invites.insert(request.invite_id, expires_at);
+                return declined("could not store the friendship");
+            }
+            self.peers.insert(peer_id, friend);
         }
-        
+        info!(peer_id = %peer, "Accepted friend request for an invite");
+        self.record_peer_event(PeerEventKind::Befriended, Some(peer), None, None);
+        FriendResponse { accepted: true, reason: None }
+    }
+
+    /// Send a peering proposal and hold on to the peer entry until the answer comes back
+    async fn propose_peering(
+        &mut self,
+        peer_id: &str,
+        addresses: Vec<String>,
+        name: Option<String>,
+        recommender_quality: f64,
+        tags: Vec<String>,
+        proposal: PeerProposal,
+    ) -> Result<()> {
+        let peer: PeerId = peer_id.parse().map_err(|_| anyhow::anyhow!("Invalid peer ID {}", peer_id))?;
+        if peer == *self.swarm.local_peer_id() {
+            return Err(anyhow::anyhow!("Can't propose peering to this node"));
+        }
+        let mut proposed = Peer {
+            peer_id: peer_id.to_string(),
+            addresses: Vec::new(),
+            handle: None,
+            name: name.unwrap_or_default(),
+            recommender_quality,
+            added_at: self.clock.now(),
+            notes: None,
+            tags,
+            contact: Default::default(),
+            last_interaction_at: None,
+        };
+        for address in &addresses {
+            proposed.learn_address(address);
+        }
+        if let Some((_, addresses)) = dial_target(&proposed) {
+            self.remember_addresses(&peer, addresses);
+        }
+        let proposed = ProposedPeer { peer: proposed, proposal, proposed_at: self.clock.now(), delivered: false };
+        self.storage.put_proposed_peer(&proposed).await?;
+        self.proposed_peers.insert(peer, proposed);
+        self.send_peering(peer, PeeringKind::Proposal);
+        info!(peer_id = %peer, "Proposed peering");
         Ok(())
     }
 
-    async fn export_trust_data(&self) -> Result<TrustDataExport> {
-        let experiences = self.storage.get_all_experiences().await?;
-        let peers = self.storage.get_peers().await?;
-        
-        Ok(TrustDataExport::new(experiences, peers))
+    /// Keep the answer until the proposer confirms it, as it may not be reachable right now
+    async fn answer_proposal(&mut self, peer_id: &str, accepted: bool, reason: Option<String>) {
+        let Ok(peer) = peer_id.parse::<PeerId>() else { return };
+        let answer = PeeringAnswerOut { peer_id: peer_id.to_string(), accepted, reason, answered_at: self.clock.now() };
+        if let Err(e) = self.storage.put_peering_answer(&answer).await {
+            warn!(peer_id = %peer, "Failed to store peering answer: {}", e);
+        }
+        self.peering_answers.insert(peer, answer);
+        self.send_peering(peer, PeeringKind::Answer);
     }
 
-    async fn import_trust_data(&mut self, data: TrustDataExport, overwrite: bool) -> Result<()> {
-        if overwrite {
-            info!("Importing trust data with overwrite - clearing existing data");
-            // Note: In a production system, we might want to backup existing data first
+    /// Send the kept proposal or answer for `peer`, unless it's already on its way
+    fn send_peering(&mut self, peer: PeerId, kind: PeeringKind) {
+        if self.peering_in_flight.values().any(|sent| *sent == (peer, kind)) {
+            return;
         }
+        let message = match kind {
+            PeeringKind::Proposal => match self.proposed_peers.get(&peer) {
+                Some(proposed) if !proposed.delivered => PeeringMessage::Propose(proposed.proposal.clone()),
+                _ => return,
+            },
+            PeeringKind::Answer => match self.peering_answers.get(&peer) {
+                Some(answer) => PeeringMessage::Answer(PeeringAnswer {
+                    accepted: answer.accepted,
+                    addresses: self.own_addresses(),
+                    reason: answer.reason.clone(),
+                }),
+                None => return,
+            },
+        };
+        let request_id = self.swarm.behaviour_mut().peering.send_request(&peer, message);
+        self.peering_in_flight.insert(request_id, (peer, kind));
+    }
 
-        info!("Importing {} experiences and {} peers", data.experiences.len(), data.peers.len());
+    /// The other node confirmed a peering message: a proposal waits for its answer now, an answer is done
+    async fn peering_delivered(&mut self, peer: PeerId, kind: PeeringKind) {
+        let peer_id = peer.to_string();
+        let stored = match kind {
+            PeeringKind::Proposal => match self.proposed_peers.get_mut(&peer) {
+                Some(proposed) => {
+                    proposed.delivered = true;
+                    let proposed = proposed.clone();
+                    self.storage.put_proposed_peer(&proposed).await
+                }
+                None => return,
+            },
+            PeeringKind::Answer => {
+                self.peering_answers.remove(&peer);
+                self.storage.remove_peering_answer(&peer_id).await
+            }
+        };
+        if let Err(e) = stored {
+            warn!(peer_id = %peer, "Failed to store delivered peering message: {}", e);
+        }
+    }
 
-        // Import experiences
-        for experience in data.experiences {
-            if overwrite || self.storage.get_experiences(&experience.id_domain, &experience.agent_id).await?.is_empty() {
-                self.storage.add_experience(experience).await?;
+    async fn handle_peering_event(&mut self, event: ReqResEvent<PeeringMessage, PeeringAck>) {
+        match event {
+            ReqResEvent::Message { peer, message: Message::Request { request, channel, .. } } => {
+                let _ = self.swarm.behaviour_mut().peering.send_response(channel, PeeringAck { received: true });
+                match request {
+                    PeeringMessage::Propose(proposal) => self.receive_proposal(peer, proposal).await,
+                    PeeringMessage::Answer(answer) => self.receive_peering_answer(peer, answer).await,
+                }
+            }
+            ReqResEvent::Message { peer, message: Message::Response { request_id, response } } => {
+                if let Some((_, kind)) = self.peering_in_flight.remove(&request_id) {
+                    if response.received {
+                        self.peering_delivered(peer, kind).await;
+                    }
+                }
+            }
+            ReqResEvent::OutboundFailure { peer, request_id, error } => {
+                warn!(peer_id = %peer, "Peering message failed: {:?}", error);
+                self.record_peer_event(PeerEventKind::Failed, Some(peer), None, Some(error.to_string()));
+                // The message is kept and goes out again on the next connection. A dial that lost the race
+                // against another dial to the same peer leaves that connection up already, so retry on it.
+                if let Some((_, kind)) = self.peering_in_flight.remove(&request_id) {
+                    if matches!(error, OutboundFailure::DialFailure) && self.swarm.is_connected(&peer) {
+                        self.send_peering(peer, kind);
+                    }
+                }
             }
+            _ => {}
         }
+    }
 
-        // Import peers
-        for peer in data.peers {
-            if overwrite || !self.peers.contains_key(&peer.peer_id) {
-                self.peers.insert(peer.peer_id.clone(), peer.clone());
-                self.storage.add_peer(peer).await?;
+    /// Put a proposal into the peer requests; one from an existing peer is accepted right away
+    async fn receive_proposal(&mut self, peer: PeerId, proposal: PeerProposal) {
+        let peer_id = peer.to_string();
+        let addresses: Vec<Multiaddr> = proposal.addresses.iter().filter_map(|a| a.parse().ok()).collect();
+        self.remember_addresses(&peer, addresses);
+        if self.peers.contains_key(&peer_id) {
+            info!(peer_id = %peer, "Peer proposed peering, already listed");
+            self.answer_proposal(&peer_id, true, None).await;
+            return;
+        }
+        match self.storage.record_peer_proposal(&peer_id, &proposal, self.clock.now()).await {
+            // Blocked peers aren't told they are
+            Ok(request) if request.status == PeerRequestStatus::Blocked => {
+                debug!(peer_id = %peer, "Blocked peer proposed peering");
+            }
+            Ok(request) => {
+                info!(peer_id = %peer, "Peer proposed peering, added to the peer requests");
+                let _ = self.events.send(NodeEvent::PeerRequest(request));
             }
+            Err(e) => {
+                warn!(peer_id = %peer, "Failed to record peering proposal: {}", e);
+            }
+        }
+    }
+
+    async fn receive_peering_answer(&mut self, peer: PeerId, answer: PeeringAnswer) {
+        let Some(ProposedPeer { peer: mut proposed, .. }) = self.proposed_peers.remove(&peer) else {
+            debug!(peer_id = %peer, "Got a peering answer we didn't ask for");
+            return;
+        };
+        if let Err(e) = self.storage.remove_proposed_peer(&peer.to_string()).await {
+            warn!(peer_id = %peer, "Failed to forget answered proposal: {}", e);
+        }
+        if !answer.accepted {
+            let reason = answer.reason.unwrap_or_default();
+            info!(peer_id = %peer, "Peering proposal declined: {}", reason);
+            self.record_peer_event(PeerEventKind::Failed, Some(peer), None, Some(reason));
+            return;
         }
 
-        info!("Trust data import completed successfully");
-        Ok(())
+        let peer_id = peer.to_string();
+        if !self.peers.contains_key(&peer_id) {
+            if proposed.name.is_empty() {
+                proposed.name = peer_id.clone();
+            }
+            for address in &answer.addresses {
+                proposed.learn_address(address);
+            }
+            proposed.added_at = self.clock.now();
+            let proposed = proposed.normalized();
+            if let Err(e) = self.storage.add_peer(proposed.clone()).await {
+                warn!(peer_id = %peer, "Failed to add peer that accepted our proposal: {}", e);
+                return;
+            }
+            // They may have queried us before the proposal went out
+            let _ = self.storage.remove_peer_request(&peer_id).await;
+            self.screened_peers.remove(&peer_id);
+            self.peers.insert(peer_id, proposed);
+        }
+        info!(peer_id = %peer, "Peering proposal accepted");
+        self.record_peer_event(PeerEventKind::Befriended, Some(peer), None, None);
     }
-}
\ No newline at end of file
+
+    /// Switch to a fresh key: the old key signs the move, the swarm is rebuilt under the new one,
+    /// and everyone we were connected to is told from the new identity so they can rename us
+    fn rotate_identity(&mut self) -> Result<IdentityRotation> {
+        let old_peer_id = *self.swarm.local_peer_id();
+        let new_key = identity::Keypair::generate_ed25519();
+        let new_peer_id = new_key.public().to_peer_id();
+        let transition = KeyTransition {
+            old_peer_id: old_peer_id.to_string(),
+            new_peer_id: new_peer_id.to_string(),
+            rotated_at: self.clock.now(),
+        };
+        let statement = transition.sign(&self.identity, &new_key)?;
+
+        // The old connections go away with the old swarm, so note where to find each peer again
+        let connected: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+        let targets: Vec<(PeerId, Vec<Multiaddr>)> = connected
+            .into_iter()
+            .map(|peer| {
+                let mut addresses = self.peers.get(&peer.to_string()).and_then(dial_target).map_or_else(Vec::new, |(_, a)| a);
+                for address in self.announced_addresses.get(&peer).into_iter().flatten() {
+                    if !addresses.contains(address) {
+                        addresses.push(address.clone());
+                    }
+                }
+                (peer, addresses)
+            })
+            .collect();
+
+        // Nothing changes until the new swarm listens and the key is saved, so a failure leaves us as we were
+        let mut swarm = build_swarm(new_key.clone(), self.transport, self.codec.clone(), self.swarm_timeouts)?;
+        swarm.listen_on(self.relisten_address())?;
+        if let Some(path) = &self.identity_file {
+            crate::identity::save(path, &new_key)?;
+        }
+
+        // Request ids start over in the new swarm, so nothing may wait on the old ones
+        for pending in self.distinct_pending() {
+            self.evict_pending(&pending);
+        }
+        drop(std::mem::replace(&mut self.swarm, swarm));
+        self.identity = new_key;
+        self.announced_addresses.clear();
+        for peer in self.quarantine.peers() {
+            self.swarm.behaviour_mut().blocked.block_peer(*peer);
+        }
+        // They point friends at the old id, which nobody answers for anymore
+        self.open_invites.clear();
+        // Kept peering messages go out again from the new id on the next connection
+        self.peering_in_flight.clear();
+        info!(old_peer_id = %old_peer_id, peer_id = %new_peer_id, "Rotated identity key");
+
+        let mut notified = Vec::new();
+        for (peer, addresses) in targets {
+            self.remember_addresses(&peer, addresses);
+            self.swarm.behaviour_mut().rotations.send_request(&peer, RotationNotice { statement: statement.clone() });
+            notified.push(peer.to_string());
+        }
+        Ok(IdentityRotation { old_peer_id: old_peer_id.to_string(), new_peer_id: new_peer_id.to_string(), notified })
+    }
+
+    /// Where the swarm should look for `peer` once a request needs a connection to it. Dialing
+    /// ourselves instead would race the request's own dial, and the loser fails the request.
+    fn remember_addresses(&mut self, peer: &PeerId, addresses: Vec<Multiaddr>) {
+        for address in addresses {
+            self.swarm.behaviour_mut().kademlia.add_address(peer, address);
+        }
+    }
+
+    /// The address the swarm listened on, with a port the OS picked pinned so peers find us where they did before.
+    /// A memory port stays taken while the old swarm lives, so the new one gets a fresh port there.
+    fn relisten_address(&self) -> Multiaddr {
+        use libp2p::multiaddr::Protocol;
+        let bound_port = self.swarm.listeners().flat_map(|address| address.iter()).find_map(|protocol| match protocol {
+            Protocol::Tcp(port) => Some(port),
+            _ => None,
+        });
+        self.listen_address
+            .iter()
+            .map(|protocol| match (protocol, bound_port) {
+                (Protocol::Tcp(0), Some(port)) => Protocol::Tcp(port),
+                (Protocol::Memory(_), _) => Protocol::Memory(0),
+                (protocol, _) => protocol,
+            })
+            .collect()
+    }
+
+    /// Rebuild our filter from own experiences and what peers can reach, then send it to connected peers
+    async fn exchange_availability(&mut self) {
+        let agents = match self.storage.get_experienced_agents().await {
+            Ok(agents) => agents,
+            Err(e) => {
+                warn!("Failed to list agents for the availability filter: {}", e);
+                return;
+            }
+        };
+        let mut filter = AgentFilter::default();
+        for agent in &agents {
+            filter.insert(&agent.id_domain, &agent.agent_id);
+        }
+        // What peers can answer, we can answer by forwarding, so deeper queries aren't cut short a hop early
+        for peer in self.peer_filters.keys().copied().collect::<Vec<_>>() {
+            if let Some(peer_filter) = self.fresh_filter(&peer) {
+                filter.union(peer_filter);
+            }
+        }
+        debug!("Availability filter holds {} own agents and is {:.1}% full", agents.len(), filter.fill_ratio() * 100.0);
+        self.own_filter = Some(filter);
+
+        let connected: Vec<PeerId> = self
+            .peers
+            .keys()
+            .filter_map(|peer_id| peer_id.parse::<PeerId>().ok())
+            .filter(|peer| self.swarm.is_connected(peer))
+            .collect();
+        for peer in &connected {
+            self.send_availability(peer);
+        }
+    }
+
+    /// Add the agents a command gave experiences to, and send the filter on right away if that changed it,
+    /// so those who ask us don't skip us for a new agent until the next exchange
+    async fn extend_own_filter(&mut self, rescore: &Rescore) {
+        if self.own_filter.is_none() {
+            return;
+        }
+        let agents: Vec<(String, String)> = match rescore {
+            Rescore::All => return self.exchange_availability().await,
+            Rescore::Domain(_) => return,
+            Rescore::Agents(agents) => agents
+                .iter()
+                .map(|(id_domain, agent_id)| (id_domain.clone(), self.canonical_agent_id(id_domain, agent_id)))
+                .collect(),
+            Rescore::Experience(experience_id) => self.experience_agent(experience_id).await.into_iter().collect(),
+        };
+        let Some(filter) = self.own_filter.as_mut() else {
+            return;
+        };
+        let mut extended = false;
+        for (id_domain, agent_id) in &agents {
+            if !filter.might_contain(id_domain, agent_id) {
+                filter.insert(id_domain, agent_id);
+                extended = true;
+            }
+        }
+        // Peers that ask us need not be ours, so every connection gets it, not just those to our peers
+        if extended {
+            let connected: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+            for peer in &connected {
+                self.send_availability(peer);
+            }
+        }
+    }
+
+    fn send_availability(&mut self, peer: &PeerId) {
+        let exchange = AvailabilityExchange { filter: self.own_filter.clone() };
+        self.swarm.behaviour_mut().availability.send_request(peer, exchange);
+    }
+
+    fn handle_availability_event(&mut self, event: ReqResEvent<AvailabilityExchange, AvailabilityExchange>) {
+        match event {
+            ReqResEvent::Message { peer, message: Message::Request { request, channel, .. } } => {
+                self.store_filter(peer, request.filter);
+                let exchange = AvailabilityExchange { filter: self.own_filter.clone() };
+                let _ = self.swarm.behaviour_mut().availability.send_response(channel, exchange);
+            }
+            ReqResEvent::Message { peer, message: Message::Response { response, .. } } => {
+                self.store_filter(peer, response.filter);
+            }
+            ReqResEvent::OutboundFailure { peer, error, .. } => {
+                debug!(peer_id = %peer, "Availability exchange failed: {:?}", error);
+            }
+            _ => {}
+        }
+    }
+
+    /// Only filters of peers we ask are worth keeping
+    fn store_filter(&mut self, peer: PeerId, filter: Option<AgentFilter>) {
+        match filter {
+            Some(filter) if filter.is_valid() && self.peers.contains_key(&peer.to_string()) => {
+                // What the peer just gained we can reach by forwarding too, without waiting for our next rebuild
+                if let Some(own_filter) = self.own_filter.as_mut() {
+                    own_filter.union(&filter);
+                }
+                self.peer_filters.insert(peer, (filter, Instant::now()));
+            }
+            _ => {
+                self.peer_filters.remove(&peer);
+            }
+        }
+    }
+
+    /// A peer's filter, unless it's missed a few exchanges and may no longer hold what the peer has
+    fn fresh_filter(&self, peer: &PeerId) -> Option<&AgentFilter> {
+        let every = self.availability_interval?;
+        let (filter, received) = self.peer_filters.get(peer)?;
+        (received.elapsed() <= every * FILTER_STALE_AFTER_ROUNDS).then_some(filter)
+    }
+
+    /// False only if the peer's filter rules out every agent; without a fresh filter the peer is asked
+    fn might_know_any(&self, peer: &PeerId, agents: &[AgentIdentifier]) -> bool {
+        match self.fresh_filter(peer) {
+            Some(filter) => agents.iter().any(|agent| filter.might_contain(&agent.id_domain, &agent.agent_id)),
+            None => true,
+        }
+    }
+
+    /// Remember for a while that the peer had nothing on these agents, forgetting entries that ran out
+    fn record_no_data(&mut self, peer: PeerId, agents: Vec<AgentIdentifier>) {
+        if self.no_data_ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        self.no_data.retain(|_, until| *until > now);
+        for agent in agents {
+            self.no_data.insert((peer, agent.id_domain, agent.agent_id), now + self.no_data_ttl);
+        }
+    }
+
+    /// Whether the peer recently answered without a score for the agent
+    fn known_empty(&self, peer: &PeerId, agent: &AgentIdentifier) -> bool {
+        self.no_data
+            .get(&(*peer, agent.id_domain.clone(), agent.agent_id.clone()))
+            .is_some_and(|until| *until > Instant::now())
+    }
+
+    /// The proof is fetched off the loop; the claim is marked verified once it checks out
+    async fn verify_agent_claim(
+        &mut self,
+        id_domain: String,
+        agent_id: String,
+        proof_url: String,
+        response: oneshot::Sender<Result<AgentClaim>>,
+    ) {
+        let agent_id = self.canonical_agent_id(&id_domain, &agent_id);
+        let claim = match self.storage.get_agent_claims().await {
+            Ok(claims) => claims.into_iter().find(|c| c.id_domain == id_domain && c.agent_id == agent_id),
+            Err(e) => {
+                let _ = response.send(Err(e.into()));
+                return;
+            }
+        };
+        let Some(mut claim) = claim else {
+            let _ = response.send(Err(StorageError::NotFound(format!("No claim on {}/{}", id_domain, agent_id)).into()));
+            return;
+        };
+        let storage = self.storage.clone();
+        let now = self.clock.now();
+        tokio::spawn(async move {
+            let result = async {
+                reputation::check_proof(&proof_url, &claim.challenge).await?;
+                storage.verify_agent_claim(&claim.id_domain, &claim.agent_id, &proof_url, now).await?;
+                claim.proof_url = Some(proof_url);
+                claim.verified_at = Some(now);
+                info!("Verified claim on {}/{}", claim.id_domain, claim.agent_id);
+                Ok::<_, anyhow::Error>(claim)
+            }
+            .await;
+            let _ = response.send(result);
+        });
+    }
+
+    async fn query_own_reputation(&mut self, response: oneshot::Sender<Result<Vec<PeerOpinion>>>) {
+        let agents: Vec<ClaimedAgent> = match self.storage.get_agent_claims().await {
+            Ok(claims) => claims
+                .into_iter()
+                .filter_map(|claim| {
+                    Some(ClaimedAgent {
+                        proof_url: claim.proof_url.filter(|_| claim.verified_at.is_some())?,
+                        id_domain: claim.id_domain,
+                        agent_id: claim.agent_id,
+                        challenge: claim.challenge,
+                    })
+                })
+                .collect(),
+            Err(e) => {
+                let _ = response.send(Err(e.into()));
+                return;
+            }
+        };
+        if agents.is_empty() {
+            let _ = response.send(Err(StorageError::NotFound("No verified agent claims".to_string()).into()));
+            return;
+        }
+
+        let connected: Vec<PeerId> = self
+            .peers
+            .keys()
+            .filter_map(|peer_id| peer_id.parse::<PeerId>().ok())
+            .filter(|peer| self.swarm.is_connected(peer))
+            .collect();
+        if connected.is_empty() {
+            let _ = response.send(Ok(Vec::new()));
+            return;
+        }
+        let round = Uuid::new_v4();
+        for peer in &connected {
+            let query = ReputationQuery { claims: agents.clone() };
+            let request_id = self.swarm.behaviour_mut().reputation.send_request(peer, query);
+            self.reputation_asks.insert(request_id, round);
+        }
+        info!("Asked {} peers what they think of {} own agents", connected.len(), agents.len());
+        self.reputation_rounds.insert(round, ReputationRound { waiting: connected.len(), opinions: Vec::new(), response });
+    }
+
+    async fn handle_reputation_event(&mut self, event: ReqResEvent<ReputationQuery, ReputationAnswer>) {
+        match event {
+            ReqResEvent::Message { peer, message: Message::Request { request, channel, .. } } => {
+                let is_peer = self.peers.contains_key(&peer.to_string());
+                // Blocked peers are refused whatever the policy
+                let blocked = !is_peer && self.screen_peer(&peer).await;
+                if blocked || !self.reputation_policy.allows(is_peer) || request.claims.len() > self.query_limits.max_agents {
+                    debug!(peer_id = %peer, "Refusing to say what we think of its agents");
+                    let refused = ReputationAnswer { scores: Vec::new(), refused: true };
+                    let _ = self.swarm.behaviour_mut().reputation.send_response(channel, refused);
+                    return;
+                }
+                self.answer_reputation_query(peer, request, channel);
+            }
+            ReqResEvent::Message { peer, message: Message::Response { request_id, response } } => {
+                let error = response.refused.then(|| "refused".to_string());
+                self.record_opinion(request_id, peer, response.scores, error);
+            }
+            ReqResEvent::OutboundFailure { peer, request_id, error } => {
+                warn!(peer_id = %peer, "Reputation query failed: {:?}", error);
+                self.record_opinion(request_id, peer, Vec::new(), Some(error.to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    /// Score the claimed agents from our own experiences only; what peers told us isn't ours to pass on.
+    /// Claims are only answered once their proof shows a challenge made for the asking peer.
+    fn answer_reputation_query(&mut self, peer: PeerId, query: ReputationQuery, channel: ResponseChannel<ReputationAnswer>) {
+        let storage = self.storage.clone();
+        let query_engine = self.query_engine.clone();
+        let loop_tx = self.loop_tx.clone();
+        let now = self.clock.now();
+        tokio::spawn(async move {
+            let result = async {
+                let defaults: HashMap<String, DomainDefaults> = storage
+                    .get_domain_defaults()
+                    .await?
+                    .into_iter()
+                    .map(|defaults| (defaults.id_domain.clone(), defaults))
+                    .collect();
+                let mut scores = Vec::new();
+                for agent in query.claims {
+                    if !reputation::challenge_names(&agent.challenge, &peer) {
+                        debug!(peer_id = %peer, "Claim on {}/{} has a challenge made for another node", agent.id_domain, agent.agent_id);
+                        continue;
+                    }
+                    if let Err(e) = reputation::check_proof(&agent.proof_url, &agent.challenge).await {
+                        debug!(peer_id = %peer, "Not answering for {}/{}: {}", agent.id_domain, agent.agent_id, e);
+                        continue;
+                    }
+                    let options = ScoringOptions::resolve(None, None, None, defaults.get(&agent.id_domain));
+                    let score = query_engine.calculate_trust_score_with(&agent.id_domain, &agent.agent_id, now, options).await?;
+                    if score.data_points > 0 {
+                        scores.push(AgentScore::new(agent.id_domain, agent.agent_id, score));
+                    }
+                }
+                Ok::<_, anyhow::Error>(scores)
+            }
+            .await;
+            let _ = loop_tx.send(LoopEvent::ReputationAnswer { peer, channel, result }).await;
+        });
+    }
+
+    fn record_opinion(
+        &mut self,
+        request_id: request_response::OutboundRequestId,
+        peer: PeerId,
+        scores: Vec<AgentScore>,
+        error: Option<String>,
+    ) {
+        let Some(round_id) = self.reputation_asks.remove(&request_id) else { return };
+        let Some(round) = self.reputation_rounds.get_mut(&round_id) else { return };
+        let peer_id = peer.to_string();
+        let name = self.peers.get(&peer_id).map_or_else(|| peer_id.clone(), |p| p.name.clone());
+        round.opinions.push(PeerOpinion { peer_id, name, scores, error });
+        round.waiting -= 1;
+        if round.waiting == 0 {
+            if let Some(mut round) = self.reputation_rounds.remove(&round_id) {
+                round.opinions.sort_by(|a, b| a.name.cmp(&b.name));
+                let _ = round.response.send(Ok(round.opinions));
+            }
+        }
+    }
+
+    async fn handle_rotation_event(&mut self, event: ReqResEvent<RotationNotice, RotationAck>) {
+        match event {
+            ReqResEvent::Message { peer, message: Message::Request { request, channel, .. } } => {
+                let ack = self.accept_key_transition(peer, &request.statement).await;
+                let _ = self.swarm.behaviour_mut().rotations.send_response(channel, ack);
+            }
+            ReqResEvent::Message { peer, message: Message::Response { response, .. } } => {
+                if response.updated {
+                    info!(peer_id = %peer, "Peer now knows us by our new key");
+                } else {
+                    debug!(peer_id = %peer, "Peer kept no record of our old key: {}", response.reason.unwrap_or_default());
+                }
+            }
+            ReqResEvent::OutboundFailure { peer, error, .. } => {
+                warn!(peer_id = %peer, "Could not tell peer about our new key: {:?}", error);
+                self.record_peer_event(PeerEventKind::Failed, Some(peer), None, Some(error.to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    /// Move a peer that rotated its key over to the new id, keeping its quality, history and cached answers
+    async fn accept_key_transition(&mut self, peer: PeerId, statement: &str) -> RotationAck {
+        let declined = |reason: String| RotationAck { updated: false, reason: Some(reason) };
+        let transition = match KeyTransition::verify(statement) {
+            Ok(transition) => transition,
+            Err(e) => return declined(e.to_string()),
+        };
+        if transition.new_peer_id != peer.to_string() {
+            return declined("not sent by the new identity".to_string());
+        }
+        if !self.peers.contains_key(&transition.old_peer_id) {
+            return declined("not one of our peers".to_string());
+        }
+        if let Err(e) = self.storage.rename_peer(&transition.old_peer_id, &transition.new_peer_id).await {
+            warn!(peer_id = %peer, "Failed to move peer to its new key: {}", e);
+            return declined(e.to_string());
+        }
+
+        if let Some(mut renamed) = self.peers.remove(&transition.old_peer_id) {
+            renamed.peer_id = transition.new_peer_id.clone();
+            info!(peer_id = %peer, "{} rotated its key from {}", renamed.name, transition.old_peer_id);
+            self.peers.insert(transition.new_peer_id, renamed);
+        }
+        self.record_peer_event(PeerEventKind::Rotated, Some(peer), None, Some(format!("was {}", transition.old_peer_id)));
+        RotationAck { updated: true, reason: None }
+    }
+
+    /// Add a list of peers in one go, e.g. an exported friend list; known ones are handled per `strategy`
+    async fn add_peers(&mut self, peers: Vec<Peer>, strategy: ImportStrategy, dry_run: bool) -> Result<ImportSummary> {
+        let summary = self.import_records(Vec::new(), peers, strategy, dry_run).await?;
+        if !dry_run {
+            for record in summary.records.iter().filter(|record| record.outcome == ImportOutcome::Added) {
+                if let Some(peer) = self.peers.get(&record.id).cloned() {
+                    self.dial_new_peer(&peer);
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Scale down peers and circles that supply more of the volume than the influence caps allow
+    fn cap_influence(&self, scores: &mut [ScoreSource]) -> Vec<CappedInfluence> {
+        if !self.influence_caps.is_enabled() {
+            return Vec::new();
+        }
+        let circles: HashMap<String, Vec<String>> = self.peers
+            .values()
+            .filter(|peer| !peer.tags.is_empty())
+            .map(|peer| (peer.peer_id.clone(), peer.tags.clone()))
+            .collect();
+        let contributions: Vec<(Option<&str>, f64)> = scores
+            .iter()
+            .map(|source| (source.peer.as_deref(), self.score_clamping.source_volume(source.score.total_volume, source.weight)))
+            .collect();
+        let (factors, capped) = self.influence_caps.apply(&contributions, &circles);
+        for (source, factor) in scores.iter_mut().zip(factors) {
+            source.weight *= factor;
+        }
+        capped
+    }
+
+    /// Score the agents queried most before the restart and start dialing known peers, so the first queries
+    /// after startup don't all hit cold caches; failures only cost the warm-up, never the node
+    async fn warm_up(&mut self) {
+        let started = Instant::now();
+        if let Err(e) = self.connect_to_known_peers().await {
+            warn!("Warm-up couldn't dial known peers: {}", e);
+        }
+        let now = self.clock.now();
+        let agents = match self.storage.get_hot_agents(self.warm_up_agents, now).await {
+            Ok(agents) => agents,
+            Err(e) => {
+                warn!("Warm-up couldn't load queried agents: {}", e);
+                return;
+            }
+        };
+        let domain_defaults: HashMap<String, DomainDefaults> = match self.storage.get_domain_defaults().await {
+            Ok(defaults) => defaults.into_iter().map(|defaults| (defaults.id_domain.clone(), defaults)).collect(),
+            Err(e) => {
+                warn!("Warm-up couldn't load domain defaults: {}", e);
+                return;
+            }
+        };
+
+        let mut warmed = 0;
+        for agent in &agents {
+            let options = ScoringOptions::resolve(None, None, None, domain_defaults.get(&agent.id_domain));
+            match self.query_engine.calculate_trust_score_with(&agent.id_domain, &agent.agent_id, now, options).await {
+                Ok(_) => warmed += 1,
+                Err(e) => warn!(id_domain = %agent.id_domain, agent_id = %agent.agent_id, "Warm-up scoring failed: {}", e),
+            }
+        }
+        info!("Warmed up {} of {} agents in {:?}", warmed, agents.len(), started.elapsed());
+    }
+
+    /// Fold recommendations older than the compaction age into one entry per agent, keeping their signal
+    /// while the table, and the merging at query time, stay small
+    async fn compact_cached_scores(&mut self) {
+        let Some(age) = self.score_compaction_age else { return };
+        let Some(older_than) = chrono::Duration::from_std(age).ok().and_then(|age| self.clock.now().checked_sub_signed(age)) else {
+            return;
+        };
+        match self.storage.compact_cached_scores(older_than).await {
+            Ok(0) => {}
+            Ok(folded) => info!("Compacted {} cached scores older than {}", folded, older_than),
+            Err(e) => warn!("Failed to compact cached scores: {}", e),
+        }
+    }
+
+    /// Write out the query counts gathered since the last flush, all in one transaction
+    async fn flush_agent_queries(&mut self) {
+        if self.agent_queries.is_empty() {
+            return;
+        }
+        let queries: Vec<AgentQueries> = self
+            .agent_queries
+            .drain()
+            .map(|((id_domain, agent_id), (queries, last_queried_at))| AgentQueries { id_domain, agent_id, queries, last_queried_at })
+            .collect();
+        if let Err(e) = self.storage.record_agent_queries(&queries).await {
+            warn!("Failed to record {} queried agents: {}", queries.len(), e);
+        }
+    }
+
+    /// Recompute local scores for the watched and most-queried agents and ask the best peers about them,
+    /// so interactive queries at depth 0 find warm caches; skipped while the node is busy
+    async fn refresh_popular_scores(&mut self) -> Result<()> {
+        if !self.pending_requests.is_empty() || self.last_activity.elapsed() < REFRESH_IDLE_AFTER {
+            debug!("Skipping score refresh, node is busy");
+            return Ok(());
+        }
+
+        let mut popular: Vec<((String, String), u64)> = self.query_counts
+            .iter()
+            .map(|(agent, count)| (agent.clone(), *count))
+            .collect();
+        popular.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        popular.truncate(REFRESH_TOP_AGENTS);
+        self.query_counts.retain(|_, count| {
+            *count /= 2;
+            *count > 0
+        });
+
+        let mut agents: Vec<(String, String)> = self.watchlist
+            .list()
+            .into_iter()
+            .map(|watched| (watched.id_domain, watched.agent_id))
+            .collect();
+        for (agent, _) in popular {
+            if !agents.contains(&agent) {
+                agents.push(agent);
+            }
+        }
+        if agents.is_empty() {
+            return Ok(());
+        }
+
+        let domain_defaults: HashMap<String, DomainDefaults> = self.storage.get_domain_defaults().await?
+            .into_iter()
+            .map(|defaults| (defaults.id_domain.clone(), defaults))
+            .collect();
+        let now = self.clock.now();
+        for (id_domain, agent_id) in &agents {
+            let options = ScoringOptions::resolve(None, None, None, domain_defaults.get(id_domain));
+            self.query_engine.invalidate_agent(id_domain, agent_id).await;
+            self.query_engine.calculate_trust_score_with(id_domain, agent_id, now, options).await?;
+        }
+        let agents: Vec<AgentIdentifier> = agents
+            .into_iter()
+            .map(|(id_domain, agent_id)| AgentIdentifier::new(id_domain, agent_id))
+            .collect();
+
+        // Best recommenders first, as with the fan-out of interactive queries
+        let decay = self.quality_decay;
+        let mut peers: Vec<(f64, PeerId)> = self.peers
+            .values()
+            .map(|peer| (effective_quality(peer, decay, now), peer))
+            .filter(|(quality, _)| *quality >= REFRESH_MIN_PEER_QUALITY)
+            .filter_map(|(quality, peer)| peer.peer_id.parse::<PeerId>().ok().map(|peer_id| (quality, peer_id)))
+            .filter(|(_, peer_id)| self.swarm.is_connected(peer_id))
+            .collect();
+        peers.sort_by(|a, b| b.0.total_cmp(&a.0));
+        if self.prefetch_peers > 0 {
+            peers.truncate(self.prefetch_peers);
+        }
+
+        // One id for the whole prefetch, so its traffic can be told apart from interactive queries
+        let correlation_id = Uuid::new_v4().to_string();
+        // Answers land in the peer score cache through handle_trust_response; nobody waits for them
+        let mut asked = 0;
+        for (_, peer_id) in peers {
+            // Each peer only hears about the agents its availability filter doesn't rule out
+            // and that it didn't just answer without a score for
+            let filter = self.fresh_filter(&peer_id);
+            let wanted: Vec<AgentIdentifier> = agents
+                .iter()
+                .filter(|agent| filter.is_none_or(|filter| filter.might_contain(&agent.id_domain, &agent.agent_id)))
+                .filter(|agent| !self.known_empty(&peer_id, agent))
+                .cloned()
+                .collect();
+            if wanted.is_empty() {
+                continue;
+            }
+            for chunk in wanted.chunks(self.query_limits.max_agents.max(1)) {
+                let query = TrustQuery {
+                    agents: chunk.to_vec(),
+                    max_depth: Some(0),
+                    point_in_time: Some(now),
+                    forget_rate: None,
+                    decay: None,
+                    aggregator: None,
+                    budget_ms: None,
+                    continuation: None,
+                    traceparent: None,
+                    correlation_id: Some(correlation_id.clone()),
+                    timing: false,
+                    refresh: false,
+                    cache_only: false,
+                    expires_at: None,
+                };
+                self.send_query(&peer_id, query);
+            }
+            asked += 1;
+        }
+
+        info!(correlation_id = %correlation_id, "Refreshed scores for {} watched and popular agents, asked {} peers", agents.len(), asked);
+        Ok(())
+    }
+
+    async fn discover_peers(&mut self) -> Result<()> {
+        info!("Starting peer discovery");
+        
+        // Try to bootstrap again to discover new peers
+        if let Err(e) = self.swarm.behaviour_mut().kademlia.bootstrap() {
+            debug!("Bootstrap attempt failed: {:?}", e);
+        }
+        
+        // Query for peers close to random keys to discover the network
+        let random_peer_id = PeerId::random();
+        self.swarm.behaviour_mut().kademlia.get_closest_peers(random_peer_id);
+        
+        // Also query for peers close to our own ID
+        let local_peer_id = *self.swarm.local_peer_id();
+        self.swarm.behaviour_mut().kademlia.get_closest_peers(local_peer_id);
+        
+        Ok(())
+    }
+
+    /// Dial unconnected known peers in priority order, as many as there are free dial slots
+    async fn connect_to_known_peers(&mut self) -> Result<()> {
+        self.release_expired_quarantines();
+        self.update_kept_connections();
+        let slots = self.max_concurrent_dials.saturating_sub(self.dials_in_flight.len());
+        if slots == 0 {
+            debug!("All {} dial slots are taken", self.max_concurrent_dials);
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        self.failed_dials.retain(|_, failed_at| now < *failed_at + FAILED_DIAL_BACKOFF);
+        let connected_peers: HashSet<PeerId> = self.swarm.connected_peers().cloned().collect();
+        let dialing: HashSet<PeerId> = self.dials_in_flight.values().copied().collect();
+        let clock_now = self.clock.now();
+        let mut candidates: Vec<(&Peer, f64)> = self
+            .peers
+            .values()
+            .map(|peer| (peer, effective_quality(peer, self.quality_decay, clock_now)))
+            .collect();
+        self.dial_priority.sort(&mut candidates);
+        let targets: Vec<(PeerId, Vec<Multiaddr>)> = candidates
+            .into_iter()
+            .filter_map(|(peer, _)| dial_target(peer))
+            .filter(|(peer_id, _)| {
+                !connected_peers.contains(peer_id)
+                    && !dialing.contains(peer_id)
+                    && !self.failed_dials.contains_key(peer_id)
+                    && !self.quarantine.contains(peer_id)
+            })
+            .take(slots)
+            .collect();
+
+        let mut connection_attempts = 0;
+        for (peer_id, addresses) in targets {
+            debug!(peer_id = %peer_id, "Attempting to connect to known peer at {} addresses", addresses.len());
+            let address = addresses.first().map(|address| address.to_string());
+            if let Err(e) = self.dial_known_peer(peer_id, addresses) {
+                debug!(peer_id = %peer_id, "Failed to dial peer: {:?}", e);
+                self.failed_dials.insert(peer_id, now);
+                self.record_peer_event(PeerEventKind::Failed, Some(peer_id), address, Some(e.to_string()));
+            } else {
+                connection_attempts += 1;
+            }
+        }
+        if connection_attempts > 0 {
+            info!("Attempted {} peer connections", connection_attempts);
+        }
+        
+        Ok(())
+    }
+
+    /// Dial a known peer, holding a dial slot until the connection is established or fails
+    fn dial_known_peer(&mut self, peer_id: PeerId, addresses: Vec<Multiaddr>) -> Result<(), DialError> {
+        let opts = dial_opts(peer_id, addresses);
+        let connection_id = opts.connection_id();
+        self.swarm.dial(opts)?;
+        self.dials_in_flight.insert(connection_id, peer_id);
+        Ok(())
+    }
+
+    /// Keep connections to good recommenders open, as qualities change and peers come and go
+    fn update_kept_connections(&mut self) {
+        let Some(threshold) = self.keep_alive_quality else {
+            return;
+        };
+        let now = self.clock.now();
+        let kept = self
+            .peers
+            .values()
+            .filter(|peer| effective_quality(peer, self.quality_decay, now) >= threshold)
+            .filter_map(|peer| peer.peer_id.parse::<PeerId>().ok())
+            .collect();
+        self.swarm.behaviour_mut().keep_alive.set_kept(kept);
+    }
+
+    /// The other side closes connections it has no use for, so a kept peer is dialed again right away
+    /// rather than on the next connection round; at most once per round, in case it keeps refusing
+    fn redial_kept_peer(&mut self, peer_id: PeerId) {
+        if !self.swarm.behaviour().keep_alive.is_kept(&peer_id) || self.quarantine.contains(&peer_id) {
+            return;
+        }
+        let now = Instant::now();
+        if self.kept_redials.get(&peer_id).is_some_and(|at| now < *at + PEER_CONNECTION_INTERVAL) {
+            return;
+        }
+        let Some((peer_id, addresses)) = self.peers.get(&peer_id.to_string()).and_then(dial_target) else {
+            return;
+        };
+        self.kept_redials.insert(peer_id, now);
+        debug!(peer_id = %peer_id, "Redialing kept peer");
+        if let Err(e) = self.dial_known_peer(peer_id, addresses) {
+            debug!(peer_id = %peer_id, "Failed to redial kept peer: {:?}", e);
+        }
+    }
+
+    async fn update_experience(&mut self, experience_id: &str, update: ExperienceUpdate) -> Result<TrustExperience> {
+        let mut experience = self.storage.get_experience(experience_id).await?
+            .ok_or_else(|| StorageError::NotFound(format!("Unknown experience {}", experience_id)))?;
+        let previous_agent = (experience.id_domain.clone(), experience.agent_id.clone());
+        let changed_by = update.changed_by.clone();
+        update.apply_to(&mut experience);
+        self.canonicalize_experience(&mut experience);
+
+        self.storage.update_experience(experience.clone(), changed_by.as_deref()).await?;
+        // The edit may have moved the experience to another agent, so both lose their cached scores
+        self.query_engine.invalidate_agent(&previous_agent.0, &previous_agent.1).await;
+        self.query_engine.invalidate_agent(&experience.id_domain, &experience.agent_id).await;
+        Ok(experience)
+    }
+
+    async fn remove_experience(&mut self, experience_id: &str) -> Result<()> {
+        let Some(experience) = self.storage.get_experience(experience_id).await? else {
+            // Investments still waiting for their outcome are deleted the same way
+            return Ok(self.storage.remove_pending_experience(experience_id).await?);
+        };
+        self.storage.remove_experience(experience_id).await?;
+        self.query_engine.invalidate_agent(&experience.id_domain, &experience.agent_id).await;
+        Ok(())
+    }
+
+    async fn settle_experience(
+        &mut self,
+        experience_id: &str,
+        return_value: Option<f64>,
+        discounting: Option<Discounting>,
+        discount_rate: Option<f64>,
+    ) -> Result<TrustExperience> {
+        let pending = self.storage.get_pending_experiences().await?
+            .into_iter()
+            .find(|pending| pending.id.to_string() == experience_id)
+            .ok_or_else(|| StorageError::NotFound(format!("No pending experience {}", experience_id)))?;
+        let return_value = return_value
+            .or(pending.expected_return)
+            .ok_or_else(|| StorageError::NotFound(format!("Experience {} has no expected return to confirm", experience_id)))?;
+        let mut discounting = match discounting {
+            Some(discounting) => discounting,
+            None => self.discounting_for(vec![pending.id_domain.clone()]).await?.remove(&pending.id_domain).unwrap_or_default(),
+        };
+        if let Some(rate) = discount_rate {
+            discounting = discounting.with_rate(rate);
+        }
+        let experience = pending.settle(return_value, &discounting, self.clock.now());
+
+        self.storage.settle_experience(experience.clone()).await?;
+        self.query_engine.invalidate_agent(&experience.id_domain, &experience.agent_id).await;
+        Ok(experience)
+    }
+
+    async fn discounting_for(&mut self, id_domains: Vec<String>) -> Result<HashMap<String, Discounting>> {
+        let registered: HashMap<String, Discounting> = self.storage.get_domain_defaults().await?
+            .into_iter()
+            .filter_map(|defaults| Some((defaults.id_domain, defaults.discounting?)))
+            .collect();
+        Ok(id_domains
+            .into_iter()
+            .map(|id_domain| {
+                let discounting = registered.get(&id_domain).cloned().unwrap_or_else(|| self.discounting.clone());
+                (id_domain, discounting)
+            })
+            .collect())
+    }
+
+    /// Add the pending experiences recurring templates are due for, catching up on any missed while the node was down
+    async fn generate_recurring(&mut self) {
+        let templates = match self.storage.get_experience_templates().await {
+            Ok(templates) => templates,
+            Err(e) => {
+                warn!("Failed to load experience templates: {}", e);
+                return;
+            }
+        };
+        let now = self.clock.now();
+        for template in templates {
+            let (occurrences, next_due_at) = template.due(now);
+            if occurrences.is_empty() {
+                continue;
+            }
+            let count = occurrences.len();
+            match self.storage.record_template_occurrences(&template.id.to_string(), occurrences, next_due_at).await {
+                Ok(()) => info!(template = %template.id, "Added {} recurring experiences with {} to confirm", count, template.agent_id),
+                Err(e) => warn!(template = %template.id, "Failed to add recurring experiences: {}", e),
+            }
+        }
+    }
+
+    /// The PeerId of the known peer `reference` names: its PeerId, its handle, or a name no other peer has
+    fn resolve_peer(&self, reference: &str) -> StorageResult<String> {
+        if self.peers.contains_key(reference) {
+            return Ok(reference.to_string());
+        }
+        if let Some(peer) = self.peers.values().find(|peer| peer.handle.as_deref() == Some(reference)) {
+            return Ok(peer.peer_id.clone());
+        }
+        let named: Vec<&Peer> = self.peers.values().filter(|peer| peer.name.eq_ignore_ascii_case(reference)).collect();
+        match named.as_slice() {
+            [peer] => Ok(peer.peer_id.clone()),
+            [] => Err(StorageError::NotFound(format!("Unknown peer {}", reference))),
+            _ => Err(StorageError::Duplicate(format!(
+                "{} peers are named {}; use a handle or peer id",
+                named.len(),
+                reference
+            ))),
+        }
+    }
+
+    async fn update_peer(&mut self, peer_id: &str, update: PeerUpdate) -> Result<Peer> {
+        let peer_id = self.resolve_peer(peer_id)?;
+        let mut peer = self.storage.get_peers().await?
+            .into_iter()
+            .find(|p| p.peer_id == peer_id)
+            .ok_or_else(|| StorageError::NotFound(format!("Unknown peer {}", peer_id)))?;
+        if update.recommender_quality.is_some() {
+            peer.last_interaction_at = Some(self.clock.now());
+        }
+        update.apply_to(&mut peer);
+
+        self.storage.update_peer(&peer).await?;
+        self.peers.insert(peer.peer_id.clone(), peer.clone());
+        Ok(peer)
+    }
+
+    /// Setting a quality counts as checking on the peer, so its decay starts over
+    async fn update_peer_quality(&mut self, peer_id: &str, quality: f64, reason: &str) -> Result<()> {
+        let peer_id = self.resolve_peer(peer_id)?;
+        self.storage.update_peer_quality(&peer_id, quality, reason).await?;
+        if let Some(peer) = self.peers.get_mut(&peer_id) {
+            peer.recommender_quality = quality;
+            peer.last_interaction_at = Some(self.clock.now());
+            self.storage.update_peer(peer).await?;
+        }
+        Ok(())
+    }
+
+    /// Remember that `peer` answered us, so its recommender quality doesn't decay
+    async fn note_interaction(&mut self, peer: &PeerId) {
+        let now = self.clock.now();
+        let Some(known) = self.peers.get_mut(&peer.to_string()) else {
+            return;
+        };
+        if now - known.last_interaction() < INTERACTION_RESOLUTION {
+            return;
+        }
+        known.last_interaction_at = Some(now);
+        if let Err(e) = self.storage.update_peer(known).await {
+            debug!(peer_id = %peer, "Failed to store last interaction: {}", e);
+        }
+    }
+
+    /// Remember which address reached a known peer, so it's tried first next time
+    async fn note_address_success(&mut self, peer: &PeerId, address: &str) {
+        let now = self.clock.now();
+        let address = split_peer_multiaddr(address).map_or(address, |(_, address)| address);
+        let Some(known) = self.peers.get_mut(&peer.to_string()) else {
+            return;
+        };
+        known.record_address_success(address, now);
+        if let Err(e) = self.storage.update_peer(known).await {
+            debug!(peer_id = %peer, "Failed to store successful address: {}", e);
+        }
+    }
+
+    /// Add the addresses a known peer announces over identify to the ones we dial it at
+    async fn learn_announced_addresses(&mut self, peer: &PeerId, announced: &[Multiaddr]) {
+        let Some(known) = self.peers.get_mut(&peer.to_string()) else {
+            return;
+        };
+        let mut changed = false;
+        for address in announced {
+            let address = address.to_string();
+            changed |= known.learn_address(split_peer_multiaddr(&address).map_or(address.as_str(), |(_, address)| address));
+        }
+        if changed {
+            if let Err(e) = self.storage.update_peer(known).await {
+                debug!(peer_id = %peer, "Failed to store announced addresses: {}", e);
+            }
+        }
+    }
+
+    /// Known peers with their recommender quality decayed as of now, as queries weight them
+    fn weighted_peers(&self) -> HashMap<String, Peer> {
+        let now = self.clock.now();
+        self.peers
+            .iter()
+            .map(|(id, peer)| {
+                let weighted = Peer {
+                    recommender_quality: effective_quality(peer, self.quality_decay, now),
+                    ..peer.clone()
+                };
+                (id.clone(), weighted)
+            })
+            .collect()
+    }
+
+    /// Bring an experience back to the version stored in `revision`, re-creating it if it was deleted
+    async fn revert_experience(&mut self, experience_id: &str, revision: i64) -> Result<TrustExperience> {
+        let target = self.storage.get_experience_history(experience_id).await?
+            .into_iter()
+            .find(|r| r.revision == revision)
+            .ok_or_else(|| StorageError::NotFound(format!("Unknown revision {} of experience {}", revision, experience_id)))?;
+
+        let restored = target.previous;
+        if let Some(current) = self.storage.get_experience(experience_id).await? {
+            let changed_by = format!("revert to revision {}", revision);
+            self.storage.update_experience(restored.clone(), Some(&changed_by)).await?;
+            self.query_engine.invalidate_agent(&current.id_domain, &current.agent_id).await;
+        } else {
+            self.storage.add_experience(restored.clone()).await?;
+        }
+        self.query_engine.invalidate_agent(&restored.id_domain, &restored.agent_id).await;
+        Ok(restored)
+    }
+
+    /// Commands are handled one at a time, so no other write can interleave with the restore
+    async fn restore_backup(&mut self, path: &std::path::Path) -> Result<RestoreSummary> {
+        let summary = restore_from_file(self.storage.as_ref(), path).await?;
+
+        // Rebuild in-memory state from the restored database
+        self.peers = self.storage.get_peers().await?
+            .into_iter()
+            .map(|p| (p.peer_id.clone(), p))
+            .collect();
+        self.query_engine.clear_cache().await;
+
+        info!("Restored {} peers from {}", self.peers.len(), path.display());
+        Ok(summary)
+    }
+
+    async fn export_trust_data(&mut self) -> Result<ExportStream> {
+        let peers = self.storage.get_peers().await?;
+        let experiences = self.storage.stream_experiences();
+
+        Ok(ExportStream { peers, experiences, point_in_time: None, scores: Vec::new() })
+    }
+
+    /// Experiences and recommendations known at `point_in_time`, and the merged score of every agent they cover
+    /// as a query without peers would have answered then
+    async fn export_snapshot(&mut self, point_in_time: chrono::DateTime<Utc>) -> Result<ExportStream> {
+        let peers = self.storage.get_peers().await?;
+        let experiences: Vec<TrustExperience> = self.storage.get_all_experiences().await?
+            .into_iter()
+            .filter(|experience| experience.timestamp <= point_in_time)
+            .collect();
+        let mut by_agent: BTreeMap<(String, String), (Vec<TrustExperience>, Vec<CachedTrustScore>)> = BTreeMap::new();
+        for experience in &experiences {
+            let agent = (experience.id_domain.clone(), experience.agent_id.clone());
+            by_agent.entry(agent).or_default().0.push(experience.clone());
+        }
+        for cached in self.storage.get_all_cached_scores().await? {
+            if cached.cached_at <= point_in_time {
+                let agent = (cached.id_domain.clone(), cached.agent_id.clone());
+                by_agent.entry(agent).or_default().1.push(cached);
+            }
+        }
+
+        let defaults: HashMap<String, DomainDefaults> = self.storage.get_domain_defaults().await?
+            .into_iter()
+            .map(|defaults| (defaults.id_domain.clone(), defaults))
+            .collect();
+        let weighted_peers = self.weighted_peers();
+        let no_overrides = HashMap::new();
+        let mut scores = Vec::with_capacity(by_agent.len());
+        for ((id_domain, agent_id), (own, cached)) in by_agent {
+            let options = ScoringOptions::resolve(None, None, None, defaults.get(&id_domain));
+            let mut sources = Vec::new();
+            let personal_score = self.query_engine.score_experiences(&own, point_in_time, &options);
+            if personal_score.total_volume > 0.0 {
+                sources.push(ScoreSource {
+                    score: personal_score,
+                    weight: 1.0,
+                    hops: 0,
+                    farthest_hops: 0,
+                    origin: ScoreOrigin::Local,
+                    peer: None,
+                });
+            }
+            sources.extend(cached_score_sources(&weighted_peers, self.hop_damping, cached, &no_overrides, point_in_time));
+            scores.push(self.score_from_sources(id_domain, agent_id, sources, options.aggregator, None));
+        }
+
+        info!("Exporting a snapshot as of {} with {} experiences and {} scores", point_in_time, experiences.len(), scores.len());
+        Ok(ExportStream {
+            peers,
+            experiences: futures::stream::iter(experiences.into_iter().map(Ok)).boxed(),
+            point_in_time: Some(point_in_time),
+            scores,
+        })
+    }
+
+    async fn import_trust_data(&mut self, data: TrustDataExport, strategy: ImportStrategy, dry_run: bool) -> Result<ImportSummary> {
+        info!("{} {} experiences and {} peers with strategy {:?}",
+              if dry_run { "Previewing import of" } else { "Importing" },
+              data.experiences.len(), data.peers.len(), strategy);
+
+        let summary = self.import_records(data.experiences, data.peers, strategy, dry_run).await?;
+
+        if dry_run {
+            info!("Import preview: {} new / {} conflicting experiences, {} new / {} existing peers",
+                  summary.new_experiences, summary.conflicting_experiences,
+                  summary.new_peers, summary.existing_peers);
+        } else {
+            info!("Trust data import completed successfully");
+        }
+        Ok(summary)
+    }
+
+    /// Match records against local ones and, unless this is a dry run, write them; one call per batch of a streamed import
+    async fn import_records(
+        &mut self,
+        experiences: Vec<TrustExperience>,
+        peers: Vec<Peer>,
+        strategy: ImportStrategy,
+        dry_run: bool,
+    ) -> Result<ImportSummary> {
+        let mut summary = ImportSummary {
+            dry_run,
+            strategy,
+            ..Default::default()
+        };
+
+        let mut experiences_to_replace = Vec::new();
+        let mut experiences_to_write = Vec::new();
+        let mut peers_to_replace = Vec::new();
+        let mut peers_to_write = Vec::new();
+
+        // Import experiences, matched by experience id
+        for mut experience in experiences {
+            self.canonicalize_experience(&mut experience);
+            let id = experience.id.to_string();
+            let outcome = match self.storage.get_experience(&id).await? {
+                None => {
+                    summary.new_experiences += 1;
+                    ImportOutcome::Added
+                }
+                Some(local) => {
+                    summary.conflicting_experiences += 1;
+                    match strategy {
+                        ImportStrategy::Overwrite => ImportOutcome::Replaced,
+                        ImportStrategy::NewerWins if experience.timestamp > local.timestamp => ImportOutcome::Replaced,
+                        _ => ImportOutcome::Skipped,
+                    }
+                }
+            };
+
+            match outcome {
+                ImportOutcome::Added => experiences_to_write.push(experience),
+                ImportOutcome::Replaced => {
+                    experiences_to_replace.push(id.clone());
+                    experiences_to_write.push(experience);
+                }
+                ImportOutcome::Merged | ImportOutcome::Skipped => {}
+            }
+            summary.records.push(ImportRecordResult {
+                kind: ImportRecordKind::Experience,
+                id,
+                outcome,
+            });
+        }
+
+        // Import peers, matched by peer id; a peer listed twice is taken the first time
+        let mut seen = HashSet::new();
+        for peer in peers {
+            let peer = peer.normalized();
+            let id = peer.peer_id.clone();
+            if !seen.insert(id.clone()) {
+                summary.records.push(ImportRecordResult {
+                    kind: ImportRecordKind::Peer,
+                    id,
+                    outcome: ImportOutcome::Skipped,
+                });
+                continue;
+            }
+            let (outcome, resolved) = match self.peers.get(&peer.peer_id).cloned() {
+                None => {
+                    summary.new_peers += 1;
+                    (ImportOutcome::Added, peer)
+                }
+                Some(local) => {
+                    summary.existing_peers += 1;
+                    match strategy {
+                        ImportStrategy::Overwrite => (ImportOutcome::Replaced, peer),
+                        ImportStrategy::NewerWins if peer.added_at > local.added_at => (ImportOutcome::Replaced, peer),
+                        ImportStrategy::MergePeersKeepMyQuality => (
+                            ImportOutcome::Merged,
+                            Peer {
+                                name: peer.name,
+                                ..local
+                            },
+                        ),
+                        _ => (ImportOutcome::Skipped, local),
+                    }
+                }
+            };
+
+            match outcome {
+                ImportOutcome::Added => peers_to_write.push(resolved),
+                ImportOutcome::Replaced | ImportOutcome::Merged => {
+                    peers_to_replace.push(id.clone());
+                    peers_to_write.push(resolved);
+                }
+                ImportOutcome::Skipped => {}
+            }
+            summary.records.push(ImportRecordResult {
+                kind: ImportRecordKind::Peer,
+                id,
+                outcome,
+            });
+        }
+
+        if !dry_run {
+            let experiences_changed = !experiences_to_write.is_empty() || !experiences_to_replace.is_empty();
+            self.storage.import_records(
+                &experiences_to_replace,
+                experiences_to_write,
+                &peers_to_replace,
+                peers_to_write.clone(),
+            ).await?;
+            if experiences_changed {
+                // Bulk writes touch many agents at once, so start from an empty cache
+                self.query_engine.clear_cache().await;
+            }
+            for peer in peers_to_write {
+                self.peers.insert(peer.peer_id.clone(), peer);
+            }
+        }
+        Ok(summary)
+    }
+}
+
+/// Each shared value once, in the order first seen, however many times it is listed
+fn distinct_shared<'a, T: 'a>(listed: impl IntoIterator<Item = &'a Arc<T>>) -> Vec<Arc<T>> {
+    let mut seen = HashSet::new();
+    listed
+        .into_iter()
+        .filter(|shared| seen.insert(Arc::as_ptr(shared)))
+        .cloned()
+        .collect()
+}
+
+/// Local and cached contributions to the scores a query asks for
+struct LocalScores {
+    point_in_time: chrono::DateTime<Utc>,
+    max_depth: u8,
+    all_scores: HashMap<(String, String), Vec<ScoreSource>>,
+    aggregators: HashMap<(String, String), Aggregator>,
+}
+
+/// Read everything a query needs from storage; runs on its own task, away from the swarm loop
+async fn gather_local_scores<S: Storage>(
+    storage: &S,
+    query_engine: &QueryEngine<S>,
+    peers: &HashMap<String, Peer>,
+    hop_damping: f64,
+    query: &TrustQuery,
+    now: chrono::DateTime<Utc>,
+) -> Result<LocalScores> {
+    let point_in_time = query.point_in_time.unwrap_or(now);
+
+    // Parameters the query leaves unset come from the domain registry
+    let domain_defaults: HashMap<String, DomainDefaults> = storage.get_domain_defaults().await?
+        .into_iter()
+        .map(|defaults| (defaults.id_domain.clone(), defaults))
+        .collect();
+    let max_depth = query.max_depth.unwrap_or_else(|| {
+        query.agents
+            .iter()
+            .filter_map(|agent| domain_defaults.get(&agent.id_domain)?.max_depth)
+            .max()
+            .unwrap_or(DEFAULT_MAX_DEPTH)
+    });
+
+    let mut all_scores: HashMap<(String, String), Vec<ScoreSource>> = HashMap::new();
+    let mut aggregators: HashMap<(String, String), Aggregator> = HashMap::new();
+
+    // Get personal scores
+    for agent in &query.agents {
+        let options = ScoringOptions::resolve(
+            query.forget_rate,
+            query.decay,
+            query.aggregator,
+            domain_defaults.get(&agent.id_domain),
+        );
+        aggregators.insert((agent.id_domain.clone(), agent.agent_id.clone()), options.aggregator);
+        let personal_score = if query.refresh {
+            query_engine.recalculate_trust_score_with(&agent.id_domain, &agent.agent_id, point_in_time, options).await?
+        } else {
+            query_engine.calculate_trust_score_with(&agent.id_domain, &agent.agent_id, point_in_time, options).await?
+        };
+        
+        if personal_score.total_volume > 0.0 {
+            all_scores
+                .entry((agent.id_domain.clone(), agent.agent_id.clone()))
+                .or_default()
+                .push(ScoreSource {
+                    score: personal_score,
+                    weight: 1.0,
+                    hops: 0,
+                    farthest_hops: 0,
+                    origin: ScoreOrigin::Local,
+                    peer: None,
+                });
+        }
+    }
+
+    // Always check for cached scores from peers (even at depth 0), unless the query wants fresh answers
+    if !query.refresh {
+        for agent in &query.agents {
+            if let Ok(cached_scores) = storage.get_cached_scores(&agent.id_domain, &agent.agent_id).await {
+                debug!(agent = %agent, "Found {} cached scores", cached_scores.len());
+                let sources = cached_score_sources(peers, hop_damping, cached_scores, &HashMap::new(), now);
+                if !sources.is_empty() {
+                    all_scores
+                        .entry((agent.id_domain.clone(), agent.agent_id.clone()))
+                        .or_default()
+                        .extend(sources);
+                }
+            } else {
+                debug!(agent = %agent, "No cached scores found");
+            }
+        }
+    }
+
+    Ok(LocalScores { point_in_time, max_depth, all_scores, aggregators })
+}
+
+/// The agents `peer` was asked about in a pending query but gave no score for, across all its pages
+fn unanswered_agents(pending: &PendingRequest, peer: &PeerId) -> Vec<AgentIdentifier> {
+    let Some(query) = &pending.peer_query else {
+        return Vec::new();
+    };
+    let peer_id = peer.to_string();
+    let answered: HashSet<(&str, &str)> = pending
+        .responses
+        .iter()
+        .filter(|response| response.peer_id == peer_id)
+        .flat_map(|response| &response.response.scores)
+        .map(|score| (score.id_domain.as_str(), score.agent_id.as_str()))
+        .collect();
+    query
+        .agents
+        .iter()
+        .filter(|agent| !answered.contains(&(agent.id_domain.as_str(), agent.agent_id.as_str())))
+        .cloned()
+        .collect()
+}
+
+/// A known peer's PeerId with the addresses it can be dialed at, best first; None if the id doesn't parse
+fn dial_target(peer: &Peer) -> Option<(PeerId, Vec<Multiaddr>)> {
+    let peer_id = peer.peer_id.parse::<PeerId>().ok()?;
+    let addresses = peer
+        .dial_order()
+        .into_iter()
+        .filter_map(|address| address.address.parse::<Multiaddr>().ok())
+        .collect();
+    Some((peer_id, addresses))
+}
+
+/// Try the addresses one at a time, in order, so an address that worked recently is used before falling back
+fn dial_opts(peer_id: PeerId, addresses: Vec<Multiaddr>) -> DialOpts {
+    DialOpts::peer_id(peer_id)
+        .addresses(addresses)
+        .override_dial_concurrency_factor(NonZeroU8::MIN)
+        .build()
+}
+
+/// A peer's recommender quality at `now`, after decay if there is one
+fn effective_quality(peer: &Peer, decay: Option<QualityDecay>, now: chrono::DateTime<Utc>) -> f64 {
+    decay.map_or(peer.recommender_quality, |decay| decay.apply(peer, now))
+}
+
+/// Agent id forms set in the domain registry; unregistered domains use AgentIdForm::for_domain
+fn registered_id_forms(defaults: &[DomainDefaults]) -> HashMap<String, AgentIdForm> {
+    defaults
+        .iter()
+        .filter_map(|defaults| Some((defaults.id_domain.clone(), defaults.agent_id_form?)))
+        .collect()
+}
+
+fn millis(duration: TokioDuration) -> u64 {
+    duration.as_millis() as u64
+}
+
+/// Weigh cached peer recommendations by recommender quality (optionally overridden), age and hop damping
+fn cached_score_sources(
+    peers: &HashMap<String, Peer>,
+    hop_damping: f64,
+    cached_scores: Vec<CachedTrustScore>,
+    quality_overrides: &HashMap<String, f64>,
+    now: chrono::DateTime<Utc>,
+) -> Vec<ScoreSource> {
+    let mut sources = Vec::new();
+    for cached in cached_scores {
+        if cached.from_peer == COMPACTED_PEER {
+            // Qualities were folded into the volume when the entry was compacted
+            let age_seconds = (now - cached.cached_at).num_seconds() as f64;
+            sources.push(ScoreSource {
+                score: cached.score,
+                weight: hop_damping / (1.0 + age_seconds / 86400.0),
+                hops: 1,
+                farthest_hops: 1,
+                origin: ScoreOrigin::Cached { cached_at: cached.cached_at },
+                // Capped like a peer of its own, as it speaks for several
+                peer: Some(cached.from_peer),
+            });
+            continue;
+        }
+        // Find the peer's recommender quality
+        if let Some(peer) = peers.values().find(|p| p.peer_id == cached.from_peer) {
+            let quality = quality_overrides.get(&peer.peer_id).copied().unwrap_or(peer.recommender_quality);
+            // Apply age decay to cached scores
+            let age_seconds = (now - cached.cached_at).num_seconds() as f64;
+            let age_factor = 1.0 / (1.0 + age_seconds / 86400.0); // Decay over days
+            
+            debug!(peer_id = %cached.from_peer, "Using cached score with age factor {}", age_factor);
+            sources.push(ScoreSource {
+                score: cached.score,
+                weight: quality * age_factor * hop_damping,
+                hops: 1,
+                farthest_hops: 1,
+                origin: ScoreOrigin::Cached { cached_at: cached.cached_at },
+                peer: Some(cached.from_peer.clone()),
+            });
+        } else {
+            debug!(peer_id = %cached.from_peer, "Cached score from unknown peer");
+        }
+    }
+    sources
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distinct_shared_keeps_each_value_once_in_order() {
+        let (first, second) = (Arc::new(Mutex::new(1)), Arc::new(Mutex::new(1)));
+        let listed = [first.clone(), second.clone(), first.clone(), second.clone(), first.clone()];
+
+        let distinct = distinct_shared(&listed);
+        assert_eq!(distinct.len(), 2);
+        assert!(Arc::ptr_eq(&distinct[0], &first));
+        assert!(Arc::ptr_eq(&distinct[1], &second));
+    }
+}
@@ -0,0 +1,305 @@
+use crate::events::{EventSender, NodeEvent, PeerEvent, PeerEventKind};
+use crate::types::ScoreChange;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, info, warn};
+
+/// How long a channel gets to accept a notification before it's given up on
+#[cfg(feature = "http-client")]
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the notifier looks at how long the node has been without peers
+const REACHABILITY_CHECK: Duration = Duration::from_secs(60);
+
+/// Where notifications are pushed to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotifyChannel {
+    /// A topic URL like https://ntfy.sh/<topic>, which the ntfy app shows as phone notifications
+    Ntfy(String),
+    /// Any URL, which gets each notification POSTed as JSON
+    Webhook(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct NotifyConfig {
+    pub channels: Vec<NotifyChannel>,
+    /// How long the node may go without a connected peer before that's reported; None never reports it
+    pub unreachable_after: Option<Duration>,
+    /// Put in front of titles, to tell the users of one process apart
+    pub user: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// An invite was redeemed, by us or by someone we invited
+    Friend,
+    /// A peer we don't know started querying us
+    PeerRequest,
+    /// A watched agent's merged score went down past a threshold or by its min_delta
+    ScoreDropped,
+    /// No peer has been connected for longer than unreachable_after
+    Unreachable,
+}
+
+/// What reaches the phone: a short title and one line of text
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    pub title: String,
+    pub message: String,
+}
+
+impl Notification {
+    /// The notification an event is worth, if any; rising scores and routine connection events aren't
+    pub fn for_event(event: &NodeEvent) -> Option<Notification> {
+        match event {
+            NodeEvent::Peer(peer) if peer.kind == PeerEventKind::Befriended => Some(Notification {
+                kind: NotificationKind::Friend,
+                title: "New friend".to_string(),
+                message: format!("{} is now one of your peers", peer.peer_id.as_deref().unwrap_or("A peer")),
+            }),
+            NodeEvent::PeerRequest(request) => Some(Notification {
+                kind: NotificationKind::PeerRequest,
+                title: "Peer request".to_string(),
+                message: format!("{} is querying your node; accept, ignore or block it", request.peer_id),
+            }),
+            NodeEvent::ScoreChanged(change) if change.current.expected_pv_roi < change.previous.expected_pv_roi => {
+                Some(score_dropped(change))
+            }
+            _ => None,
+        }
+    }
+
+    fn unreachable(alone_for: Duration) -> Notification {
+        Notification {
+            kind: NotificationKind::Unreachable,
+            title: "Node unreachable".to_string(),
+            message: format!(
+                "No peer has been connected for {} minutes, so friends can't ask your node",
+                alone_for.as_secs() / 60
+            ),
+        }
+    }
+
+    /// ntfy priority: drops and outages are worth buzzing for, new friends aren't
+    #[cfg(feature = "http-client")]
+    fn priority(&self) -> &'static str {
+        match self.kind {
+            NotificationKind::Friend | NotificationKind::PeerRequest => "default",
+            NotificationKind::ScoreDropped | NotificationKind::Unreachable => "high",
+        }
+    }
+
+    #[cfg(feature = "http-client")]
+    fn tag(&self) -> &'static str {
+        match self.kind {
+            NotificationKind::Friend => "handshake",
+            NotificationKind::PeerRequest => "wave",
+            NotificationKind::ScoreDropped => "chart_with_downwards_trend",
+            NotificationKind::Unreachable => "warning",
+        }
+    }
+}
+
+fn score_dropped(change: &ScoreChange) -> Notification {
+    let mut message = format!(
+        "{}/{} fell from {:.2} to {:.2}",
+        change.id_domain, change.agent_id, change.previous.expected_pv_roi, change.current.expected_pv_roi
+    );
+    if let Some(threshold) = change.crossed.iter().copied().reduce(f64::min) {
+        message.push_str(&format!(", below {}", threshold));
+    }
+    Notification { kind: NotificationKind::ScoreDropped, title: "Trust score dropped".to_string(), message }
+}
+
+/// Connections per peer, to tell how long the node has had none
+#[derive(Debug)]
+pub struct Reachability {
+    connections: HashMap<String, usize>,
+    alone_since: Option<Instant>,
+    reported: bool,
+}
+
+impl Reachability {
+    /// Nothing is connected when the node starts
+    pub fn new(now: Instant) -> Self {
+        Self { connections: HashMap::new(), alone_since: Some(now), reported: false }
+    }
+
+    pub fn observe(&mut self, event: &PeerEvent, now: Instant) {
+        let Some(peer_id) = &event.peer_id else { return };
+        match event.kind {
+            PeerEventKind::Connected => {
+                *self.connections.entry(peer_id.clone()).or_default() += 1;
+                self.alone_since = None;
+                self.reported = false;
+            }
+            PeerEventKind::Disconnected => {
+                // A peer can hold several connections; it's gone when the last one closes
+                if let Some(count) = self.connections.get_mut(peer_id) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.connections.remove(peer_id);
+                    }
+                }
+                if self.connections.is_empty() && self.alone_since.is_none() {
+                    self.alone_since = Some(now);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// How long the node has been alone, the first time that's longer than `after`; once per outage
+    pub fn overdue(&mut self, now: Instant, after: Duration) -> Option<Duration> {
+        let alone_for = now.saturating_duration_since(self.alone_since?);
+        if self.reported || alone_for < after {
+            return None;
+        }
+        self.reported = true;
+        Some(alone_for)
+    }
+}
+
+/// Pushes notable node events to the configured channels
+pub struct Notifier {
+    config: NotifyConfig,
+    events: EventSender,
+    #[cfg(feature = "http-client")]
+    client: reqwest::Client,
+}
+
+impl Notifier {
+    pub fn new(config: NotifyConfig, events: EventSender) -> Self {
+        Self {
+            config,
+            events,
+            #[cfg(feature = "http-client")]
+            client: reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    pub async fn run(self) {
+        let mut receiver = self.events.subscribe();
+        let mut reachability = Reachability::new(Instant::now());
+        let mut ticker = interval(REACHABILITY_CHECK);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        info!("Sending notifications to {} channels", self.config.channels.len());
+
+        loop {
+            tokio::select! {
+                event = receiver.recv() => match event {
+                    Ok(event) => {
+                        if let NodeEvent::Peer(peer) = &event {
+                            reachability.observe(peer, Instant::now());
+                        }
+                        if let Some(notification) = Notification::for_event(&event) {
+                            self.deliver(notification);
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => warn!("Notifier fell behind and skipped {} events", missed),
+                    Err(RecvError::Closed) => break,
+                },
+                _ = ticker.tick(), if self.config.unreachable_after.is_some() => {
+                    let after = self.config.unreachable_after.unwrap_or_default();
+                    if let Some(alone_for) = reachability.overdue(Instant::now(), after) {
+                        self.deliver(Notification::unreachable(alone_for));
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "http-client")]
+    fn deliver(&self, mut notification: Notification) {
+        debug!("Notifying: {}", notification.message);
+        if let Some(user) = &self.config.user {
+            notification.title = format!("{}: {}", user, notification.title);
+        }
+        for channel in &self.config.channels {
+            let (url, request) = match channel {
+                NotifyChannel::Ntfy(url) => (
+                    url,
+                    self.client
+                        .post(url)
+                        .header("Title", notification.title.as_str())
+                        .header("Priority", notification.priority())
+                        .header("Tags", notification.tag())
+                        .body(notification.message.clone()),
+                ),
+                NotifyChannel::Webhook(url) => (url, self.client.post(url).json(&notification)),
+            };
+            let url = url.clone();
+            tokio::spawn(async move {
+                let result = request.send().await.and_then(reqwest::Response::error_for_status);
+                if let Err(e) = result {
+                    warn!("Failed to deliver notification to {}: {}", url, e);
+                }
+            });
+        }
+    }
+
+    #[cfg(not(feature = "http-client"))]
+    fn deliver(&self, notification: Notification) {
+        debug!("Notifying: {}", notification.message);
+        warn!("Built without http-client, dropping the notification for {} channels", self.config.channels.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TrustScore;
+    use chrono::Utc;
+
+    fn peer_event(kind: PeerEventKind, peer_id: &str) -> PeerEvent {
+        PeerEvent { kind, peer_id: Some(peer_id.to_string()), address: None, reason: None, at: Utc::now() }
+    }
+
+    fn change(previous: f64, current: f64) -> NodeEvent {
+        NodeEvent::ScoreChanged(ScoreChange {
+            id_domain: "ethereum".to_string(),
+            agent_id: "0xabc".to_string(),
+            previous: TrustScore::new(previous, 100.0, 3),
+            current: TrustScore::new(current, 100.0, 4),
+            crossed: vec![1.0],
+            changed_at: Utc::now(),
+        })
+    }
+
+    #[test]
+    fn test_only_drops_and_new_friends_are_notified() {
+        let dropped = Notification::for_event(&change(1.2, 0.7)).unwrap();
+        assert_eq!(dropped.kind, NotificationKind::ScoreDropped);
+        assert_eq!(dropped.message, "ethereum/0xabc fell from 1.20 to 0.70, below 1");
+        assert!(Notification::for_event(&change(0.7, 1.2)).is_none());
+
+        let friend = NodeEvent::Peer(peer_event(PeerEventKind::Befriended, "12D3KooWBob"));
+        assert_eq!(Notification::for_event(&friend).unwrap().kind, NotificationKind::Friend);
+        assert!(Notification::for_event(&NodeEvent::Peer(peer_event(PeerEventKind::Connected, "12D3KooWBob"))).is_none());
+    }
+
+    #[test]
+    fn test_unreachable_is_reported_once_per_outage() {
+        let start = Instant::now();
+        let hour = Duration::from_secs(3600);
+        let mut reachability = Reachability::new(start);
+        assert_eq!(reachability.overdue(start + hour / 2, hour), None);
+        assert_eq!(reachability.overdue(start + hour, hour), Some(hour));
+        assert_eq!(reachability.overdue(start + 2 * hour, hour), None);
+
+        // Two connections to one peer: alone again only once both are closed
+        reachability.observe(&peer_event(PeerEventKind::Connected, "a"), start + 2 * hour);
+        reachability.observe(&peer_event(PeerEventKind::Connected, "a"), start + 2 * hour);
+        reachability.observe(&peer_event(PeerEventKind::Disconnected, "a"), start + 3 * hour);
+        assert_eq!(reachability.overdue(start + 5 * hour, hour), None);
+        reachability.observe(&peer_event(PeerEventKind::Disconnected, "a"), start + 5 * hour);
+        assert_eq!(reachability.overdue(start + 6 * hour, hour), Some(hour));
+    }
+}
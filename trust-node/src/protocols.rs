@@ -1,9 +1,23 @@
-use crate::types::{TrustQuery, TrustResponse};
+#[cfg(feature = "chaos")]
+use crate::chaos::{Chaos, ResponseFault};
+use crate::types::{AgentIdentifier, TrustQuery, TrustResponse, WireError};
 use async_trait::async_trait;
 use futures::io::{AsyncRead, AsyncWrite};
 use libp2p::request_response::Codec;
 use serde::{Deserialize, Serialize};
 use std::io;
+#[cfg(feature = "chaos")]
+use std::sync::Arc;
+
+/// Largest query we accept from a peer
+pub const MAX_REQUEST_BYTES: usize = 1_000_000;
+/// Largest response we accept; bigger answers are split by the sender into pages
+pub const MAX_RESPONSE_BYTES: usize = 10_000_000;
+
+/// A length prefix over the limit, told apart from other undecodable input so the sender can be blamed for it
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Message too large")]
+pub struct MessageTooLarge;
 
 #[derive(Debug, Clone)]
 pub struct TrustProtocol;
@@ -15,7 +29,18 @@ impl AsRef<str> for TrustProtocol {
 }
 
 #[derive(Debug, Clone, Default)]
-pub struct TrustCodec;
+pub struct TrustCodec {
+    #[cfg(feature = "chaos")]
+    chaos: Option<Arc<Chaos>>,
+}
+
+#[cfg(feature = "chaos")]
+impl TrustCodec {
+    /// A codec that drops, delays or corrupts a share of outgoing responses
+    pub fn with_chaos(chaos: Arc<Chaos>) -> Self {
+        Self { chaos: Some(chaos) }
+    }
+}
 
 #[async_trait]
 impl Codec for TrustCodec {
@@ -23,45 +48,130 @@ impl Codec for TrustCodec {
     type Request = TrustQuery;
     type Response = TrustResponse;
 
+    #[tracing::instrument(level = "debug", skip_all, fields(bytes = tracing::field::Empty))]
     async fn read_request<T>(&mut self, _: &TrustProtocol, io: &mut T) -> io::Result<Self::Request>
     where
         T: AsyncRead + Unpin + Send,
     {
-        let vec = read_length_prefixed(io, 1_000_000).await?;
+        let vec = read_length_prefixed(io, MAX_REQUEST_BYTES).await?;
+        tracing::Span::current().record("bytes", vec.len());
         let request: Self::Request = serde_json::from_slice(&vec).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         tracing::debug!("LIBP2P: Decoded incoming request: {:?}", request);
         Ok(request)
     }
 
+    #[tracing::instrument(level = "debug", skip_all, fields(bytes = tracing::field::Empty))]
     async fn read_response<T>(&mut self, _: &TrustProtocol, io: &mut T) -> io::Result<Self::Response>
     where
         T: AsyncRead + Unpin + Send,
     {
-        let vec = read_length_prefixed(io, 10_000_000).await?;
+        let vec = read_length_prefixed(io, MAX_RESPONSE_BYTES).await?;
+        tracing::Span::current().record("bytes", vec.len());
         let response: Self::Response = serde_json::from_slice(&vec).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         tracing::debug!("LIBP2P: Decoded incoming response: {} scores", response.scores.len());
         Ok(response)
     }
 
+    #[tracing::instrument(level = "debug", skip_all, fields(bytes = tracing::field::Empty))]
     async fn write_request<T>(&mut self, _: &TrustProtocol, io: &mut T, req: Self::Request) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
         tracing::debug!("LIBP2P: Encoding outgoing request: {:?}", req);
         let data = serde_json::to_vec(&req).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        tracing::Span::current().record("bytes", data.len());
         write_length_prefixed(io, data).await
     }
 
+    #[tracing::instrument(level = "debug", skip_all, fields(bytes = tracing::field::Empty))]
     async fn write_response<T>(&mut self, _: &TrustProtocol, io: &mut T, res: Self::Response) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
         tracing::debug!("LIBP2P: Encoding outgoing response: {} scores", res.scores.len());
-        let data = serde_json::to_vec(&res).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        #[cfg(feature = "chaos")]
+        match self.chaos.as_ref().and_then(|chaos| chaos.response_fault()) {
+            Some(ResponseFault::Drop) => {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "chaos: response dropped"));
+            }
+            Some(ResponseFault::Corrupt) => return write_length_prefixed(io, b"\xffchaos".to_vec()).await,
+            Some(ResponseFault::Delay(delay)) => tokio::time::sleep(delay).await,
+            None => {}
+        }
+        let data = encode_response_page(res, MAX_RESPONSE_BYTES)?;
+        tracing::Span::current().record("bytes", data.len());
         write_length_prefixed(io, data).await
     }
 }
 
+/// Encode as many scores as fit in `limit` bytes, in agent order, with a continuation token for the rest;
+/// if not even one fits, encode a ResponseTooLarge error instead
+pub fn encode_response_page(mut res: TrustResponse, limit: usize) -> io::Result<Vec<u8>> {
+    let encode = |res: &TrustResponse| serde_json::to_vec(res).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+
+    let data = encode(&res)?;
+    if data.len() <= limit {
+        return Ok(data);
+    }
+
+    res.scores.sort_by(|a, b| (&a.id_domain, &a.agent_id).cmp(&(&b.id_domain, &b.agent_id)));
+    let all_scores = std::mem::take(&mut res.scores);
+    let page = |count: usize| {
+        let mut page = res.clone();
+        page.scores = all_scores[..count].to_vec();
+        page.continuation = all_scores[..count]
+            .last()
+            .map(|last| continuation_token(&last.id_domain, &last.agent_id));
+        page
+    };
+
+    // Largest prefix of the scores that still fits
+    let (mut fits, mut too_many) = (0, all_scores.len());
+    while too_many - fits > 1 {
+        let middle = (fits + too_many) / 2;
+        if encode(&page(middle))?.len() <= limit {
+            fits = middle;
+        } else {
+            too_many = middle;
+        }
+    }
+
+    tracing::warn!("Response with {} scores exceeds {} bytes, sending {} of them", all_scores.len(), limit, fits);
+    if fits == 0 {
+        let mut error = res.clone();
+        error.error = Some(WireError::ResponseTooLarge { limit_bytes: limit });
+        return encode(&error);
+    }
+    encode(&page(fits))
+}
+
+/// Opaque token naming the last agent of a page
+/// Bytes a message takes on the wire, length prefix included; answers over the size limit are counted whole
+pub fn wire_size<T: Serialize>(message: &T) -> u64 {
+    serde_json::to_vec(message).map_or(0, |data| data.len() as u64 + 4)
+}
+
+pub fn continuation_token(id_domain: &str, agent_id: &str) -> String {
+    serde_json::to_string(&(id_domain, agent_id)).unwrap_or_default()
+}
+
+/// The agents of a query that come after its continuation token, or all of them without one
+pub fn agents_after_continuation(query: &TrustQuery) -> Vec<AgentIdentifier> {
+    let after: Option<(String, String)> = query
+        .continuation
+        .as_deref()
+        .and_then(|token| serde_json::from_str(token).ok());
+    match after {
+        Some(after) => query
+            .agents
+            .iter()
+            .filter(|agent| (agent.id_domain.as_str(), agent.agent_id.as_str()) > (after.0.as_str(), after.1.as_str()))
+            .cloned()
+            .collect(),
+        None => query.agents.clone(),
+    }
+}
+
 async fn read_length_prefixed<T>(io: &mut T, max_len: usize) -> io::Result<Vec<u8>>
 where
     T: AsyncRead + Unpin + Send,
@@ -73,7 +183,7 @@ where
     let len = u32::from_be_bytes(len_bytes) as usize;
     
     if len > max_len {
-        return Err(io::Error::new(io::ErrorKind::InvalidData, "Message too large"));
+        return Err(io::Error::new(io::ErrorKind::InvalidData, MessageTooLarge));
     }
     
     let mut buf = vec![0u8; len];
@@ -106,42 +216,43 @@ pub struct TrustResponseInternal {
     pub peer_id: String,
 }
 
-pub fn merge_responses(responses: Vec<TrustResponseInternal>) -> TrustResponse {
-    use chrono::Utc;
+/// Merge peer answers, damping each by `hop_damping` since none of them is first-hand
+pub fn merge_responses(responses: Vec<TrustResponseInternal>, hop_damping: f64) -> TrustResponse {
     use std::collections::HashMap;
-    use crate::types::TrustScore;
+    use crate::types::{Freshness, TrustScore};
     
     tracing::debug!("merge_responses: Processing {} responses", responses.len());
     
-    let mut merged_scores: HashMap<(String, String), Vec<TrustScore>> = HashMap::new();
+    let mut merged_scores: HashMap<(String, String), (Vec<TrustScore>, u8, Freshness)> = HashMap::new();
     
     for resp in responses {
         for agent_score in resp.response.scores {
-            merged_scores
+            let (scores, hops, freshness) = merged_scores
                 .entry((agent_score.id_domain.clone(), agent_score.agent_id.clone()))
-                .or_default()
-                .push(agent_score.score);
+                .or_insert_with(|| (Vec::new(), u8::MAX, Freshness::default()));
+            freshness.add_live(agent_score.score.total_volume * hop_damping.abs(), &agent_score.freshness);
+            scores.push(agent_score.score);
+            *hops = (*hops).min(agent_score.hops.saturating_add(1));
         }
     }
     
     let final_scores: Vec<crate::types::AgentScore> = merged_scores
         .into_iter()
-        .map(|((id_domain, agent_id), scores)| {
+        .map(|((id_domain, agent_id), (scores, hops, freshness))| {
             // Use the new TrustScore merge functionality
-            // All peer responses get equal weight (1.0) since this is just combining responses
+            // All peer responses get equal weight since this is just combining responses
             let score_weight_pairs: Vec<(TrustScore, f64)> = scores
                 .into_iter()
-                .map(|score| (score, 1.0))
+                .map(|score| (score, hop_damping))
                 .collect();
             
             let merged_score = TrustScore::merge_multiple(score_weight_pairs);
             
             crate::types::AgentScore::new(id_domain, agent_id, merged_score)
+                .with_hops(hops)
+                .with_freshness(freshness)
         })
         .collect();
     
-    TrustResponse {
-        scores: final_scores,
-        timestamp: Utc::now(),
-    }
+    TrustResponse::new(final_scores)
 }
\ No newline at end of file
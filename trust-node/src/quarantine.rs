@@ -0,0 +1,184 @@
+use crate::protocols::MessageTooLarge;
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+
+/// Ways a peer can break the trust protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Violation {
+    /// A message that didn't decode
+    UndecodableFrame,
+    /// A message over the size limit
+    OversizedMessage,
+    /// A score no node could have computed, like a negative or infinite volume
+    AbsurdScore,
+}
+
+impl Violation {
+    /// The violation behind a failed read of a peer's message, if the peer is to blame for it
+    pub fn of_io_error(error: &io::Error) -> Option<Self> {
+        if error.kind() != io::ErrorKind::InvalidData {
+            return None;
+        }
+        match error.get_ref() {
+            Some(inner) if inner.is::<MessageTooLarge>() => Some(Violation::OversizedMessage),
+            _ => Some(Violation::UndecodableFrame),
+        }
+    }
+}
+
+/// How many violations put a peer into quarantine, and for how long
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuarantinePolicy {
+    /// Violations within `window` that quarantine a peer
+    pub max_violations: usize,
+    pub window: Duration,
+    /// How long a quarantined peer is neither dialed nor answered
+    pub cooling_off: Duration,
+}
+
+impl Default for QuarantinePolicy {
+    fn default() -> Self {
+        Self {
+            max_violations: 3,
+            window: Duration::from_secs(10 * 60),
+            cooling_off: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// A quarantined peer, as listed by GET /peers/quarantine
+#[derive(Debug, Clone, Serialize)]
+pub struct QuarantinedPeer {
+    pub peer_id: String,
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    /// The violations that got the peer quarantined, oldest first
+    pub violations: Vec<Violation>,
+}
+
+/// Counts protocol violations per peer and keeps repeat offenders out for a while; kept in memory, so a
+/// restart lets everyone back in
+#[derive(Debug, Default)]
+pub struct Quarantine {
+    /// None only lets peers in through `release`, never puts them into quarantine
+    policy: Option<QuarantinePolicy>,
+    /// Recent violations of peers that aren't quarantined
+    strikes: HashMap<PeerId, Vec<(DateTime<Utc>, Violation)>>,
+    quarantined: HashMap<PeerId, QuarantinedPeer>,
+}
+
+impl Quarantine {
+    pub fn new(policy: Option<QuarantinePolicy>) -> Self {
+        Self { policy, ..Self::default() }
+    }
+
+    /// Count a violation; true if it put the peer into quarantine
+    pub fn record(&mut self, peer: PeerId, violation: Violation, now: DateTime<Utc>) -> bool {
+        let Some(policy) = self.policy else {
+            return false;
+        };
+        if self.quarantined.contains_key(&peer) {
+            return false;
+        }
+        let window_start = now - chrono::Duration::from_std(policy.window).unwrap_or(chrono::Duration::MAX);
+        let strikes = self.strikes.entry(peer).or_default();
+        strikes.retain(|(at, _)| *at > window_start);
+        strikes.push((now, violation));
+        if strikes.len() < policy.max_violations {
+            return false;
+        }
+
+        let violations = self.strikes.remove(&peer).unwrap_or_default().into_iter().map(|(_, v)| v).collect();
+        let cooling_off = chrono::Duration::from_std(policy.cooling_off).unwrap_or(chrono::Duration::MAX);
+        self.quarantined.insert(peer, QuarantinedPeer {
+            peer_id: peer.to_string(),
+            since: now,
+            until: now.checked_add_signed(cooling_off).unwrap_or(DateTime::<Utc>::MAX_UTC),
+            violations,
+        });
+        true
+    }
+
+    pub fn contains(&self, peer: &PeerId) -> bool {
+        self.quarantined.contains_key(peer)
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.quarantined.keys()
+    }
+
+    /// Let a peer back in before its time is up, with a clean slate; false if it wasn't quarantined
+    pub fn release(&mut self, peer: &PeerId) -> bool {
+        self.strikes.remove(peer);
+        self.quarantined.remove(peer).is_some()
+    }
+
+    /// Release the peers whose cooling-off period is over
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<PeerId> {
+        let expired: Vec<PeerId> = self
+            .quarantined
+            .iter()
+            .filter(|(_, entry)| entry.until <= now)
+            .map(|(peer, _)| *peer)
+            .collect();
+        for peer in &expired {
+            self.quarantined.remove(peer);
+        }
+        expired
+    }
+
+    /// Quarantined peers, the most recently quarantined first
+    pub fn list(&self) -> Vec<QuarantinedPeer> {
+        let mut peers: Vec<QuarantinedPeer> = self.quarantined.values().cloned().collect();
+        peers.sort_by_key(|peer| std::cmp::Reverse(peer.since));
+        peers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeat_offenders_are_quarantined_until_they_cool_off() {
+        let policy = QuarantinePolicy {
+            max_violations: 2,
+            window: Duration::from_secs(60),
+            cooling_off: Duration::from_secs(3600),
+        };
+        let mut quarantine = Quarantine::new(Some(policy));
+        let peer = PeerId::random();
+        let start = Utc::now();
+
+        // Violations spread wider than the window don't add up
+        assert!(!quarantine.record(peer, Violation::UndecodableFrame, start));
+        assert!(!quarantine.record(peer, Violation::AbsurdScore, start + chrono::Duration::seconds(120)));
+        assert!(quarantine.record(peer, Violation::OversizedMessage, start + chrono::Duration::seconds(150)));
+        assert!(quarantine.contains(&peer));
+        assert_eq!(quarantine.list()[0].violations, [Violation::AbsurdScore, Violation::OversizedMessage]);
+
+        assert!(quarantine.expire(start + chrono::Duration::seconds(3000)).is_empty());
+        assert_eq!(quarantine.expire(start + chrono::Duration::seconds(3750)), [peer]);
+        assert!(!quarantine.contains(&peer));
+
+        // Disabled, nobody is quarantined however often they misbehave
+        let mut lenient = Quarantine::new(None);
+        for _ in 0..10 {
+            assert!(!lenient.record(peer, Violation::UndecodableFrame, start));
+        }
+    }
+
+    #[test]
+    fn test_only_read_errors_the_peer_caused_are_violations() {
+        let too_large = io::Error::new(io::ErrorKind::InvalidData, MessageTooLarge);
+        assert_eq!(Violation::of_io_error(&too_large), Some(Violation::OversizedMessage));
+        let garbled = io::Error::new(io::ErrorKind::InvalidData, "expected value");
+        assert_eq!(Violation::of_io_error(&garbled), Some(Violation::UndecodableFrame));
+        assert_eq!(Violation::of_io_error(&io::Error::from(io::ErrorKind::ConnectionReset)), None);
+    }
+}
@@ -1,9 +1,28 @@
+use crate::clock::{system_clock, SharedClock};
 use crate::storage::Storage;
-use crate::types::{TrustExperience, TrustScore};
+use crate::types::{AgentScore, Aggregator, CacheStats, DomainDefaults, InflationIndex, QueryResultEntry, ScoringOptions, TrustExperience, TrustScore};
 use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
-use tracing::debug;
+use tracing::{debug, warn};
+
+/// Agents per database round trip when streaming all scores
+const SCORE_PAGE_SIZE: u32 = 1000;
+
+/// The inflation index of each domain whose registered currency has one
+pub fn inflation_by_domain(defaults: &[DomainDefaults], indexes: Vec<InflationIndex>) -> HashMap<String, InflationIndex> {
+    let by_currency: HashMap<String, InflationIndex> =
+        indexes.into_iter().map(|index| (index.currency.clone(), index)).collect();
+    defaults
+        .iter()
+        .filter_map(|defaults| {
+            let index = by_currency.get(defaults.currency.as_deref()?)?;
+            Some((defaults.id_domain.clone(), index.clone()))
+        })
+        .collect()
+}
 
 #[derive(Clone)]
 struct CacheEntry {
@@ -14,7 +33,13 @@ struct CacheEntry {
 pub struct QueryEngine<S: Storage> {
     storage: Arc<S>,
     cache: Arc<RwLock<HashMap<String, CacheEntry>>>,
-    cache_ttl_seconds: i64,
+    /// Adjustable while the node runs, see set_cache_ttl
+    cache_ttl_seconds: AtomicI64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    clock: SharedClock,
+    /// Inflation index per id domain, see set_inflation
+    inflation: RwLock<HashMap<String, InflationIndex>>,
 }
 
 #[allow(dead_code)] // Public API methods for future extensibility
@@ -23,7 +48,11 @@ impl<S: Storage> QueryEngine<S> {
         Self { 
             storage,
             cache: Arc::new(RwLock::new(HashMap::new())),
-            cache_ttl_seconds: 300, // 5 minutes
+            cache_ttl_seconds: AtomicI64::new(300), // 5 minutes
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            clock: system_clock(),
+            inflation: RwLock::new(HashMap::new()),
         }
     }
     
@@ -31,34 +60,158 @@ impl<S: Storage> QueryEngine<S> {
         Self { 
             storage,
             cache: Arc::new(RwLock::new(HashMap::new())),
-            cache_ttl_seconds,
+            cache_ttl_seconds: AtomicI64::new(cache_ttl_seconds),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            clock: system_clock(),
+            inflation: RwLock::new(HashMap::new()),
         }
     }
     
-    fn get_cache_key(&self, agent_id: &str, point_in_time: DateTime<Utc>, forget_rate: f64) -> String {
-        format!("{}:{}:{:.3}", agent_id, point_in_time.timestamp(), forget_rate)
+    /// Use `clock` instead of the system time for cache expiry
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn cache_ttl_seconds(&self) -> i64 {
+        self.cache_ttl_seconds.load(Ordering::Relaxed)
+    }
+
+    /// Change how long scores are served from the cache; entries already cached are judged by the new TTL
+    pub fn set_cache_ttl(&self, cache_ttl_seconds: i64) {
+        self.cache_ttl_seconds.store(cache_ttl_seconds, Ordering::Relaxed);
+    }
+
+    /// Express volumes in these id domains in money of the scored point in time; cached scores are dropped
+    pub async fn set_inflation(&self, by_domain: HashMap<String, InflationIndex>) {
+        if let Ok(mut inflation) = self.inflation.write() {
+            if *inflation == by_domain {
+                return;
+            }
+            *inflation = by_domain;
+        }
+        self.clear_cache().await;
+    }
+
+    fn inflation_for(&self, id_domain: &str) -> Option<InflationIndex> {
+        self.inflation.read().ok()?.get(id_domain).cloned()
+    }
+
+    /// The running aggregates sum nominal volumes, so they only stand in for a full calculation without inflation
+    fn use_running_aggregate(&self, id_domain: &str, options: &ScoringOptions) -> bool {
+        options.forget_rate == 0.0 && options.aggregator == Aggregator::WeightedMean && self.inflation_for(id_domain).is_none()
+    }
+
+    fn get_cache_key(&self, agent_id: &str, point_in_time: DateTime<Utc>, options: &ScoringOptions) -> String {
+        format!(
+            "{}:{}:{:.3}:{}:{}",
+            agent_id,
+            point_in_time.timestamp(),
+            options.forget_rate,
+            options.decay.as_str(),
+            options.aggregator.as_str()
+        )
     }
     
     fn is_cache_valid(&self, entry: &CacheEntry, now: DateTime<Utc>) -> bool {
-        (now - entry.calculated_at).num_seconds() < self.cache_ttl_seconds
+        (now - entry.calculated_at).num_seconds() < self.cache_ttl_seconds()
     }
     
-    pub fn clear_cache(&self) {
+    pub async fn clear_cache(&self) {
         if let Ok(mut cache) = self.cache.write() {
             cache.clear();
         }
+        if let Err(e) = self.storage.purge_query_results(None).await {
+            warn!("Failed to clear persisted query results: {}", e);
+        }
     }
     
-    pub fn cleanup_expired_cache(&self) {
-        let now = Utc::now();
+    /// Drop the cached scores of one agent, for every point in time and forget rate
+    pub async fn invalidate_agent(&self, id_domain: &str, agent_id: &str) {
+        let prefix = format!("{}:{}:", id_domain, agent_id);
+        if let Ok(mut cache) = self.cache.write() {
+            cache.retain(|key, _| !key.starts_with(&prefix));
+        }
+        if let Err(e) = self.storage.invalidate_query_results(id_domain, agent_id).await {
+            warn!("Failed to invalidate persisted query results for {}:{}: {}", id_domain, agent_id, e);
+        }
+    }
+    
+    pub async fn cleanup_expired_cache(&self) {
+        let now = self.clock.now();
         if let Ok(mut cache) = self.cache.write() {
             cache.retain(|_, entry| self.is_cache_valid(entry, now));
         }
+        let cutoff = now - chrono::Duration::seconds(self.cache_ttl_seconds());
+        if let Err(e) = self.storage.purge_query_results(Some(cutoff)).await {
+            warn!("Failed to purge expired query results: {}", e);
+        }
+    }
+
+    /// Look up a score in memory, then in the persisted results, counting hits and misses
+    async fn lookup_cached(&self, cache_key: &str, now: DateTime<Utc>) -> Option<TrustScore> {
+        let result = self.lookup_cached_entry(cache_key, now).await;
+        let counter = if result.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+
+    /// Persisted results are loaded into memory on a hit
+    async fn lookup_cached_entry(&self, cache_key: &str, now: DateTime<Utc>) -> Option<TrustScore> {
+        if let Ok(cache) = self.cache.read() {
+            if let Some(entry) = cache.get(cache_key) {
+                if self.is_cache_valid(entry, now) {
+                    return Some(entry.score.clone());
+                }
+            }
+        }
+
+        let persisted = match self.storage.get_query_result(cache_key).await {
+            Ok(persisted) => persisted?,
+            Err(e) => {
+                warn!("Failed to read persisted query result: {}", e);
+                return None;
+            }
+        };
+        let entry = CacheEntry {
+            score: persisted.score,
+            calculated_at: persisted.calculated_at,
+        };
+        if !self.is_cache_valid(&entry, now) {
+            return None;
+        }
+
+        let score = entry.score.clone();
+        if let Ok(mut cache) = self.cache.write() {
+            cache.insert(cache_key.to_string(), entry);
+        }
+        Some(score)
+    }
+
+    async fn remember(&self, cache_key: String, id_domain: &str, agent_id: &str, score: &TrustScore, now: DateTime<Utc>) {
+        let entry = QueryResultEntry {
+            cache_key: cache_key.clone(),
+            id_domain: id_domain.to_string(),
+            agent_id: agent_id.to_string(),
+            score: score.clone(),
+            calculated_at: now,
+        };
+        if let Err(e) = self.storage.put_query_result(&entry).await {
+            warn!("Failed to persist query result for {}:{}: {}", id_domain, agent_id, e);
+        }
+
+        if let Ok(mut cache) = self.cache.write() {
+            cache.insert(cache_key, CacheEntry {
+                score: score.clone(),
+                calculated_at: now,
+            });
+        }
     }
     
     pub fn get_cache_stats(&self) -> (usize, usize) {
         if let Ok(cache) = self.cache.read() {
-            let now = Utc::now();
+            let now = self.clock.now();
             let total = cache.len();
             let valid = cache.values().filter(|entry| self.is_cache_valid(entry, now)).count();
             (total, valid)
@@ -67,6 +220,17 @@ impl<S: Storage> QueryEngine<S> {
         }
     }
 
+    pub fn cache_stats(&self) -> CacheStats {
+        let (entries, valid_entries) = self.get_cache_stats();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries,
+            valid_entries,
+            ttl_seconds: self.cache_ttl_seconds(),
+        }
+    }
+
     pub async fn calculate_trust_score(
         &self,
         id_domain: &str,
@@ -74,20 +238,66 @@ impl<S: Storage> QueryEngine<S> {
         point_in_time: DateTime<Utc>,
         forget_rate: f64,
     ) -> anyhow::Result<TrustScore> {
-        let now = Utc::now();
-        let cache_key = self.get_cache_key(&format!("{}:{}", id_domain, agent_id), point_in_time, forget_rate);
+        let options = ScoringOptions {
+            forget_rate,
+            ..Default::default()
+        };
+        self.calculate_trust_score_with(id_domain, agent_id, point_in_time, options).await
+    }
+
+    pub async fn calculate_trust_score_with(
+        &self,
+        id_domain: &str,
+        agent_id: &str,
+        point_in_time: DateTime<Utc>,
+        options: ScoringOptions,
+    ) -> anyhow::Result<TrustScore> {
+        // Without forgetting, age doesn't matter and the running aggregates give the mean directly
+        if self.use_running_aggregate(id_domain, &options) {
+            let score = self.storage.get_agent_aggregate(id_domain, agent_id).await?;
+            return Ok(score.unwrap_or_default());
+        }
+
+        let now = self.clock.now();
+        let cache_key = self.get_cache_key(&format!("{}:{}", id_domain, agent_id), point_in_time, &options);
         
         // Check cache first
-        if let Ok(cache) = self.cache.read() {
-            if let Some(entry) = cache.get(&cache_key) {
-                if self.is_cache_valid(entry, now) {
-                    debug!("Cache hit for agent {}:{}", id_domain, agent_id);
-                    return Ok(entry.score.clone());
-                }
-            }
+        if let Some(score) = self.lookup_cached(&cache_key, now).await {
+            debug!("Cache hit for agent {}:{}", id_domain, agent_id);
+            return Ok(score);
         }
         
         debug!("Cache miss for agent {}:{}, calculating...", id_domain, agent_id);
+        self.calculate_and_remember(cache_key, id_domain, agent_id, point_in_time, &options).await
+    }
+
+    /// Like calculate_trust_score_with, but ignores what's cached; the new score replaces it
+    pub async fn recalculate_trust_score_with(
+        &self,
+        id_domain: &str,
+        agent_id: &str,
+        point_in_time: DateTime<Utc>,
+        options: ScoringOptions,
+    ) -> anyhow::Result<TrustScore> {
+        // The running aggregates are never stale
+        if self.use_running_aggregate(id_domain, &options) {
+            let score = self.storage.get_agent_aggregate(id_domain, agent_id).await?;
+            return Ok(score.unwrap_or_default());
+        }
+
+        let cache_key = self.get_cache_key(&format!("{}:{}", id_domain, agent_id), point_in_time, &options);
+        self.calculate_and_remember(cache_key, id_domain, agent_id, point_in_time, &options).await
+    }
+
+    async fn calculate_and_remember(
+        &self,
+        cache_key: String,
+        id_domain: &str,
+        agent_id: &str,
+        point_in_time: DateTime<Utc>,
+        options: &ScoringOptions,
+    ) -> anyhow::Result<TrustScore> {
+        let now = self.clock.now();
         let experiences = self.storage.get_experiences(id_domain, agent_id).await?;
         debug!("Found {} experiences for agent {}:{}", experiences.len(), id_domain, agent_id);
         
@@ -95,12 +305,7 @@ impl<S: Storage> QueryEngine<S> {
             let default_score = TrustScore::default();
             
             // Cache the default score too
-            if let Ok(mut cache) = self.cache.write() {
-                cache.insert(cache_key, CacheEntry {
-                    score: default_score.clone(),
-                    calculated_at: now,
-                });
-            }
+            self.remember(cache_key, id_domain, agent_id, &default_score, now).await;
             
             return Ok(default_score);
         }
@@ -108,7 +313,7 @@ impl<S: Storage> QueryEngine<S> {
         let (weighted_roi, total_weight) = self.calculate_weighted_average(
             &experiences,
             point_in_time,
-            forget_rate,
+            options,
         );
 
         let score = TrustScore {
@@ -118,48 +323,120 @@ impl<S: Storage> QueryEngine<S> {
         };
         
         // Cache the result
-        if let Ok(mut cache) = self.cache.write() {
-            cache.insert(cache_key, CacheEntry {
-                score: score.clone(),
-                calculated_at: now,
-            });
-        }
+        self.remember(cache_key, id_domain, agent_id, &score, now).await;
 
         Ok(score)
     }
 
+    /// Score an agent as if `extra` experiences had been recorded, bypassing the cache
+    pub async fn simulate_trust_score(
+        &self,
+        id_domain: &str,
+        agent_id: &str,
+        point_in_time: DateTime<Utc>,
+        options: ScoringOptions,
+        extra: &[TrustExperience],
+    ) -> anyhow::Result<TrustScore> {
+        let mut experiences = self.storage.get_experiences(id_domain, agent_id).await?;
+        experiences.extend_from_slice(extra);
+        if experiences.is_empty() {
+            return Ok(TrustScore::default());
+        }
+
+        let (weighted_roi, total_weight) = self.calculate_weighted_average(&experiences, point_in_time, &options);
+        Ok(TrustScore::new(weighted_roi, total_weight, experiences.len()))
+    }
+
+    /// Score exactly these experiences, bypassing storage and the cache
+    pub fn score_experiences(
+        &self,
+        experiences: &[TrustExperience],
+        point_in_time: DateTime<Utc>,
+        options: &ScoringOptions,
+    ) -> TrustScore {
+        if experiences.is_empty() {
+            return TrustScore::default();
+        }
+        let (weighted_roi, total_weight) = self.calculate_weighted_average(experiences, point_in_time, options);
+        TrustScore::new(weighted_roi, total_weight, experiences.len())
+    }
+
+    /// One page of scores for all agents, each scored as a query leaving the scoring options to its domain's
+    /// defaults would, inflation included. Pass the last agent of a page as `after` to get the next one
+    pub async fn calculate_trust_scores_page(
+        &self,
+        point_in_time: DateTime<Utc>,
+        after: Option<(&str, &str)>,
+        limit: u32,
+    ) -> anyhow::Result<Vec<AgentScore>> {
+        let defaults: HashMap<String, DomainDefaults> = self.storage.get_domain_defaults().await?
+            .into_iter()
+            .map(|defaults| (defaults.id_domain.clone(), defaults))
+            .collect();
+        let experiences = self.storage.get_experiences_page(after, limit).await?;
+        let mut page: Vec<AgentScore> = Vec::new();
+        for group in experiences.chunk_by(|a, b| a.id_domain == b.id_domain && a.agent_id == b.agent_id) {
+            let agent = &group[0];
+            let options = ScoringOptions::resolve(None, None, None, defaults.get(&agent.id_domain));
+            let score = self.score_experiences(group, point_in_time, &options);
+            page.push(AgentScore::new(agent.id_domain.clone(), agent.agent_id.clone(), score));
+        }
+        Ok(page)
+    }
+
+    /// Scores for all agents, fetched page by page as the stream is consumed
+    pub fn stream_all_trust_scores(&self, point_in_time: DateTime<Utc>) -> BoxStream<'_, anyhow::Result<AgentScore>> {
+        let pages = stream::try_unfold(Some(None::<(String, String)>), move |cursor| async move {
+            let Some(after) = cursor else {
+                return Ok::<_, anyhow::Error>(None);
+            };
+            let page = self
+                .calculate_trust_scores_page(point_in_time, after.as_ref().map(|(d, a)| (d.as_str(), a.as_str())), SCORE_PAGE_SIZE)
+                .await?;
+            // A short page is the last one
+            let next = (page.len() == SCORE_PAGE_SIZE as usize)
+                .then(|| page.last().map(|s| (s.id_domain.clone(), s.agent_id.clone())));
+            Ok(Some((stream::iter(page.into_iter().map(Ok)), next)))
+        });
+        pages.try_flatten().boxed()
+    }
+
     pub async fn calculate_all_trust_scores(
         &self,
         point_in_time: DateTime<Utc>,
         forget_rate: f64,
     ) -> anyhow::Result<HashMap<String, TrustScore>> {
-        let all_experiences = self.storage.get_all_experiences().await?;
-        
-        let mut scores_by_agent: HashMap<String, Vec<TrustExperience>> = HashMap::new();
-        for exp in all_experiences {
-            scores_by_agent
+        // Fold experiences into per-agent running sums as they stream in, so memory
+        // grows with the number of agents rather than the number of experiences
+        let mut experiences = self.storage.stream_experiences();
+        let mut sums_by_agent: HashMap<String, (f64, f64, usize)> = HashMap::new();
+        let inflation = self.inflation.read().map(|inflation| inflation.clone()).unwrap_or_default();
+        while let Some(exp) = experiences.try_next().await? {
+            let (weighted_sum, total_weight, data_points) = sums_by_agent
                 .entry(exp.agent_id.clone())
-                .or_default()
-                .push(exp);
+                .or_insert((0.0, 0.0, 0));
+            let adjustment = inflation
+                .get(&exp.id_domain)
+                .map_or(1.0, |index| index.adjustment(exp.timestamp, point_in_time));
+            let aged_volume = exp.aged_volume(point_in_time, forget_rate) * adjustment;
+            if aged_volume > 0.0 {
+                *weighted_sum += exp.pv_roi * aged_volume;
+                *total_weight += aged_volume;
+            }
+            *data_points += 1;
         }
 
-        let mut results = HashMap::new();
-        for (agent_id, experiences) in scores_by_agent {
-            let (weighted_roi, total_weight) = self.calculate_weighted_average(
-                &experiences,
-                point_in_time,
-                forget_rate,
-            );
-
-            results.insert(
-                agent_id,
-                TrustScore {
-                    expected_pv_roi: weighted_roi,
-                    total_volume: total_weight,
-                    data_points: experiences.len(),
-                },
-            );
-        }
+        let results = sums_by_agent
+            .into_iter()
+            .map(|(agent_id, (weighted_sum, total_weight, data_points))| {
+                let (expected_pv_roi, total_volume) = if total_weight > 0.0 {
+                    (weighted_sum / total_weight, total_weight)
+                } else {
+                    (1.0, 0.0)
+                };
+                (agent_id, TrustScore::new(expected_pv_roi, total_volume, data_points))
+            })
+            .collect();
 
         Ok(results)
     }
@@ -168,26 +445,23 @@ impl<S: Storage> QueryEngine<S> {
         &self,
         experiences: &[TrustExperience],
         point_in_time: DateTime<Utc>,
-        forget_rate: f64,
+        options: &ScoringOptions,
     ) -> (f64, f64) {
-        let mut weighted_sum = 0.0;
-        let mut total_weight = 0.0;
-
-        for exp in experiences {
-            let aged_volume = exp.aged_volume(point_in_time, forget_rate);
-            debug!("Experience ROI: {}, invested_volume: {}, aged_volume: {}, forget_rate: {}", 
-                   exp.pv_roi, exp.invested_volume, aged_volume, forget_rate);
-            if aged_volume > 0.0 {
-                weighted_sum += exp.pv_roi * aged_volume;
-                total_weight += aged_volume;
-            }
-        }
+        let inflation = experiences.first().and_then(|exp| self.inflation_for(&exp.id_domain));
+        let aged: Vec<(f64, f64)> = experiences
+            .iter()
+            .map(|exp| {
+                let adjustment = inflation
+                    .as_ref()
+                    .map_or(1.0, |index| index.adjustment(exp.timestamp, point_in_time));
+                let aged_volume = exp.decayed_volume(point_in_time, options.forget_rate, options.decay) * adjustment;
+                debug!("Experience ROI: {}, invested_volume: {}, aged_volume: {}, forget_rate: {}", 
+                       exp.pv_roi, exp.invested_volume, aged_volume, options.forget_rate);
+                (exp.pv_roi, aged_volume)
+            })
+            .collect();
 
-        if total_weight > 0.0 {
-            (weighted_sum / total_weight, total_weight)
-        } else {
-            (1.0, 0.0)
-        }
+        options.aggregator.aggregate(&aged).unwrap_or((1.0, 0.0))
     }
 
     pub async fn combine_trust_information(
@@ -318,4 +592,44 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_inflation_weighs_old_volumes_in_todays_money() -> anyhow::Result<()> {
+        use crate::types::IndexPoint;
+
+        let dir = tempdir()?;
+        let storage = Arc::new(SqliteStorage::new(&dir.path().join("test.db")).await?);
+        let engine = QueryEngine::new(storage.clone());
+
+        let now = Utc::now();
+        let then = now - chrono::Duration::days(3650);
+        for (pv_roi, timestamp) in [(2.0, then), (1.0, now)] {
+            storage.add_experience(TrustExperience {
+                id: Uuid::new_v4(),
+                id_domain: "test".to_string(),
+                agent_id: "test_agent".to_string(),
+                pv_roi,
+                invested_volume: 100.0,
+                timestamp,
+                notes: None,
+                data: None,
+            }).await?;
+        }
+
+        let score = engine.calculate_trust_score("test", "test_agent", now, 0.0).await?;
+        assert!((score.expected_pv_roi - 1.5).abs() < 1e-9);
+
+        // Prices doubled over the decade, so the old purchase is worth 200 today
+        let index = InflationIndex {
+            currency: "EUR".to_string(),
+            points: vec![IndexPoint { at: then, level: 100.0 }, IndexPoint { at: now, level: 200.0 }],
+        };
+        engine.set_inflation(HashMap::from([("test".to_string(), index)])).await;
+
+        let score = engine.calculate_trust_score("test", "test_agent", now, 0.0).await?;
+        assert!((score.total_volume - 300.0).abs() < 1e-6);
+        assert!((score.expected_pv_roi - 5.0 / 3.0).abs() < 1e-6);
+
+        Ok(())
+    }
 }
\ No newline at end of file
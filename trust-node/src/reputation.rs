@@ -0,0 +1,159 @@
+use crate::types::AgentScore;
+use libp2p::{PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "http-client")]
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Protocol a node uses to ask its peers what they think of the agents it claims as its own
+pub const REPUTATION_PROTOCOL: StreamProtocol = StreamProtocol::new("/repeer/reputation/1.1.0");
+/// How long fetching a claim's proof may take
+#[cfg(feature = "http-client")]
+const PROOF_TIMEOUT: Duration = Duration::from_secs(10);
+/// Proof pages are read up to this size; the challenge is short and usually near the top
+const MAX_PROOF_BYTES: usize = 1_000_000;
+
+/// A proof that doesn't hold up
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("invalid claim proof: {0}")]
+pub struct InvalidProof(pub String);
+
+/// Whose questions about their own agents this node answers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReputationPolicy {
+    Nobody,
+    /// Only peers in the peer list
+    #[default]
+    Peers,
+    Anyone,
+}
+
+impl ReputationPolicy {
+    pub fn allows(&self, is_peer: bool) -> bool {
+        match self {
+            ReputationPolicy::Nobody => false,
+            ReputationPolicy::Peers => is_peer,
+            ReputationPolicy::Anyone => true,
+        }
+    }
+}
+
+impl std::str::FromStr for ReputationPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nobody" => Ok(ReputationPolicy::Nobody),
+            "peers" => Ok(ReputationPolicy::Peers),
+            "anyone" => Ok(ReputationPolicy::Anyone),
+            other => Err(format!("unknown reputation policy {}, expected nobody, peers or anyone", other)),
+        }
+    }
+}
+
+/// Asks a peer for its own scores of agents the asker claims; it answers from its own experiences only,
+/// and only about the claims whose proof it checked itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReputationQuery {
+    pub claims: Vec<ClaimedAgent>,
+}
+
+/// A verified claim as the asker sends it, so the peer can check the proof too
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClaimedAgent {
+    pub id_domain: String,
+    pub agent_id: String,
+    pub proof_url: String,
+    pub challenge: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReputationAnswer {
+    /// Scores of the agents the peer has experiences with
+    pub scores: Vec<AgentScore>,
+    /// The peer's policy doesn't let it answer us
+    pub refused: bool,
+}
+
+/// What one peer thinks of our own agents, as listed by GET /reputation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerOpinion {
+    pub peer_id: String,
+    pub name: String,
+    pub scores: Vec<AgentScore>,
+    /// Why there are no scores: the peer refused or couldn't be reached
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A fresh challenge to publish where only the agent's owner can. It names the claiming node, so nobody
+/// else can point at the same proof.
+pub fn new_challenge(owner: &PeerId) -> String {
+    format!("repeer-claim-{}-{}", owner, Uuid::new_v4().simple())
+}
+
+/// Whether `challenge` was made for a claim by `owner`
+pub fn challenge_names(challenge: &str, owner: &PeerId) -> bool {
+    challenge
+        .strip_prefix("repeer-claim-")
+        .and_then(|rest| rest.strip_prefix(owner.to_string().as_str()))
+        .is_some_and(|rest| rest.starts_with('-'))
+}
+
+/// Fetch the proof URL and check it shows the challenge
+pub async fn check_proof(url: &str, challenge: &str) -> Result<(), InvalidProof> {
+    let body = fetch_proof(url).await?;
+    if shows_challenge(&body[..body.len().min(MAX_PROOF_BYTES)], challenge) {
+        Ok(())
+    } else {
+        Err(InvalidProof(format!("{} doesn't show the challenge", url)))
+    }
+}
+
+#[cfg(feature = "http-client")]
+async fn fetch_proof(url: &str) -> Result<Vec<u8>, InvalidProof> {
+    let client = reqwest::Client::builder()
+        .timeout(PROOF_TIMEOUT)
+        .build()
+        .map_err(|e| InvalidProof(e.to_string()))?;
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| InvalidProof(format!("fetching {}: {}", url, e)))?;
+    let body = response.bytes().await.map_err(|e| InvalidProof(e.to_string()))?;
+    Ok(body.to_vec())
+}
+
+#[cfg(not(feature = "http-client"))]
+async fn fetch_proof(url: &str) -> Result<Vec<u8>, InvalidProof> {
+    Err(InvalidProof(format!("built without http-client, can't fetch {}", url)))
+}
+
+fn shows_challenge(body: &[u8], challenge: &str) -> bool {
+    !challenge.is_empty() && body.windows(challenge.len()).any(|window| window == challenge.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_and_challenges() {
+        assert!(!ReputationPolicy::Nobody.allows(true));
+        assert!(ReputationPolicy::Peers.allows(true) && !ReputationPolicy::Peers.allows(false));
+        assert!(ReputationPolicy::Anyone.allows(false));
+        assert_eq!("anyone".parse::<ReputationPolicy>(), Ok(ReputationPolicy::Anyone));
+
+        let owner = PeerId::random();
+        let challenge = new_challenge(&owner);
+        assert_ne!(challenge, new_challenge(&owner));
+        assert!(challenge_names(&challenge, &owner));
+        assert!(!challenge_names(&challenge, &PeerId::random()));
+        let page = format!("<p>My shop, verified: {}</p>", challenge);
+        assert!(shows_challenge(page.as_bytes(), &challenge));
+        assert!(!shows_challenge(b"<p>My shop</p>", &challenge));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::types::{LoopLag, RuntimeStats};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::time::Instant;
+
+/// How often the node loop samples itself and the runtime
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+/// A probe tick handled this late counts as a stall of the node loop
+const STALL_AFTER: Duration = Duration::from_millis(100);
+/// A worker busy for this share of a probe period counts as blocked
+const BLOCKED_SHARE: f64 = 0.9;
+
+/// Measures how late the node loop gets to its probe ticks and samples the runtime's workers on each,
+/// so a handler holding up the swarm and command loop shows in GET /stats/runtime
+#[derive(Debug, Default)]
+pub struct RuntimeProbe {
+    lag: LoopLag,
+    busy: Vec<Duration>,
+    sampled_at: Option<Instant>,
+    blocked_workers: usize,
+}
+
+impl RuntimeProbe {
+    /// Record a tick that was due at `deadline` and handled at `now`
+    pub fn tick(&mut self, deadline: Instant, now: Instant) {
+        let lag = now.saturating_duration_since(deadline);
+        self.lag.ticks += 1;
+        self.lag.last_ms = lag.as_millis() as u64;
+        self.lag.max_ms = self.lag.max_ms.max(self.lag.last_ms);
+        if lag >= STALL_AFTER {
+            self.lag.stalls += 1;
+        }
+
+        let metrics = Handle::current().metrics();
+        let busy: Vec<Duration> = (0..metrics.num_workers())
+            .map(|worker| metrics.worker_total_busy_duration(worker))
+            .collect();
+        if let Some(sampled_at) = self.sampled_at {
+            self.blocked_workers = blocked_workers(&self.busy, &busy, now - sampled_at);
+        }
+        self.busy = busy;
+        self.sampled_at = Some(now);
+    }
+
+    /// The runtime as it is now, with the loop lag measured so far
+    pub fn stats(&self) -> RuntimeStats {
+        let metrics = Handle::current().metrics();
+        let workers = metrics.num_workers();
+        #[cfg(tokio_unstable)]
+        let worker_mean_poll_us = Some(
+            (0..workers)
+                .map(|worker| metrics.worker_mean_poll_time(worker).as_micros() as u64)
+                .collect(),
+        );
+        #[cfg(not(tokio_unstable))]
+        let worker_mean_poll_us = None;
+        RuntimeStats {
+            workers,
+            alive_tasks: metrics.num_alive_tasks(),
+            global_queue_depth: metrics.global_queue_depth(),
+            blocked_workers: self.blocked_workers,
+            worker_busy_ms: (0..workers)
+                .map(|worker| metrics.worker_total_busy_duration(worker).as_millis() as u64)
+                .collect(),
+            worker_parks: (0..workers).map(|worker| metrics.worker_park_count(worker)).collect(),
+            worker_mean_poll_us,
+            event_loop: self.lag.clone(),
+        }
+    }
+}
+
+/// Workers that were busy for nearly all of `period`, going by their busy time before and after it
+fn blocked_workers(before: &[Duration], after: &[Duration], period: Duration) -> usize {
+    if period.is_zero() {
+        return 0;
+    }
+    before
+        .iter()
+        .zip(after)
+        .filter(|(before, after)| after.saturating_sub(**before).as_secs_f64() >= period.as_secs_f64() * BLOCKED_SHARE)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_workers_busy_for_the_whole_period_count_as_blocked() {
+        let before = [Duration::from_millis(100), Duration::from_millis(100)];
+        let after = [Duration::from_millis(1100), Duration::from_millis(300)];
+        assert_eq!(blocked_workers(&before, &after, Duration::from_secs(1)), 1);
+        assert_eq!(blocked_workers(&before, &after, Duration::ZERO), 0);
+    }
+
+    #[tokio::test]
+    async fn test_late_ticks_count_as_stalls() {
+        let mut probe = RuntimeProbe::default();
+        let deadline = Instant::now();
+        probe.tick(deadline, deadline + Duration::from_millis(5));
+        probe.tick(deadline, deadline + Duration::from_millis(250));
+
+        let stats = probe.stats();
+        assert_eq!(stats.event_loop.ticks, 2);
+        assert_eq!(stats.event_loop.stalls, 1);
+        assert_eq!((stats.event_loop.last_ms, stats.event_loop.max_ms), (250, 250));
+        assert_eq!(stats.workers, 1);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::rng::SplitMix;
+use crate::storage::Storage;
+use crate::types::{CachedTrustScore, Peer, PeerAddress, PeerContact, TrustExperience, TrustScore};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use libp2p::identity;
+use serde::Serialize;
+use uuid::Builder;
+
+/// Experiences inserted per transaction
+const SEED_BATCH: usize = 500;
+const PEER_NAMES: [&str; 8] = ["alice", "bob", "carol", "dave", "erin", "frank", "grace", "heidi"];
+/// Share of agents each peer has an opinion about
+const PEER_COVERAGE: f64 = 0.3;
+
+/// How much demo data to generate
+#[derive(Debug, Clone)]
+pub struct SeedOptions {
+    pub agents: usize,
+    pub experiences: usize,
+    pub peers: usize,
+    /// Experiences are spread over this many years before the end time passed to `populate`
+    pub years: u32,
+    /// Same seed and end time, same data
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SeedSummary {
+    pub agents: usize,
+    pub experiences: usize,
+    pub peers: usize,
+    pub cached_scores: usize,
+}
+
+/// A made-up agent and how it really behaves
+struct SyntheticAgent {
+    id_domain: &'static str,
+    agent_id: String,
+    mean_roi: f64,
+}
+
+impl SyntheticAgent {
+    fn generate(rng: &mut SplitMix, index: usize) -> Self {
+        let (id_domain, agent_id) = match rng.below(10) {
+            0..=4 => ("ethereum", format!("0x{:016x}{:016x}{:08x}", rng.next(), rng.next(), rng.next() as u32)),
+            5..=7 => ("aliexpress-product", format!("{}", 1_005_000_000_000u64 + rng.next() % 1_000_000_000)),
+            _ => ("domain", format!("shop-{}.example", index)),
+        };
+        // Most agents are fine, some are mediocre and a few are scams
+        let mean_roi = match rng.below(10) {
+            0..=6 => 1.0 + rng.unit() * 0.3,
+            7..=8 => 0.8 + rng.unit() * 0.2,
+            _ => rng.unit() * 0.3,
+        };
+        Self { id_domain, agent_id, mean_roi }
+    }
+}
+
+/// Fill `storage` with randomized agents, experiences, peers and their cached recommendations, all dated up to `now`
+pub async fn populate<S: Storage + ?Sized>(storage: &S, options: &SeedOptions, now: DateTime<Utc>) -> Result<SeedSummary> {
+    let mut rng = SplitMix(options.seed);
+    let agents: Vec<SyntheticAgent> = (0..options.agents.max(1)).map(|i| SyntheticAgent::generate(&mut rng, i)).collect();
+    let span_days = (options.years.max(1) * 365) as f64;
+
+    let mut batch = Vec::with_capacity(SEED_BATCH);
+    for _ in 0..options.experiences {
+        // A few popular agents get most of the experiences
+        let agent = &agents[(rng.unit().powi(2) * agents.len() as f64) as usize];
+        // Log-uniform volumes from 10 to 10,000, more recent experiences more likely
+        let invested_volume = 10f64.powf(1.0 + rng.unit() * 3.0);
+        let age_days = rng.unit().powf(1.5) * span_days;
+        batch.push(TrustExperience {
+            id: Builder::from_random_bytes(rng.bytes()).into_uuid(),
+            id_domain: agent.id_domain.to_string(),
+            agent_id: agent.agent_id.clone(),
+            pv_roi: rng.normal(agent.mean_roi, 0.15).max(0.0),
+            invested_volume: (invested_volume * 100.0).round() / 100.0,
+            timestamp: now - Duration::seconds((age_days * 86_400.0) as i64),
+            notes: None,
+            data: None,
+        });
+        if batch.len() == SEED_BATCH {
+            storage.add_experiences(std::mem::take(&mut batch)).await?;
+        }
+    }
+    if !batch.is_empty() {
+        storage.add_experiences(batch).await?;
+    }
+
+    let mut peers = Vec::with_capacity(options.peers);
+    let mut cached_scores = 0;
+    for index in 0..options.peers {
+        let peer_id = identity::Keypair::ed25519_from_bytes(rng.bytes::<32>())?.public().to_peer_id();
+        let name = match PEER_NAMES.get(index) {
+            Some(name) => name.to_string(),
+            None => format!("peer-{}", index),
+        };
+        peers.push(Peer {
+            peer_id: peer_id.to_string(),
+            addresses: vec![PeerAddress::new(format!("/ip4/127.0.0.1/tcp/{}", 9100 + index))],
+            handle: None,
+            name,
+            recommender_quality: ((0.3 + rng.unit() * 0.7) * 100.0).round() / 100.0,
+            added_at: now - Duration::days(rng.below(365) as i64),
+            notes: None,
+            tags: Vec::new(),
+            contact: PeerContact::default(),
+            last_interaction_at: None,
+        });
+
+        // Each peer knows some agents, with its own noisy view of them
+        for agent in &agents {
+            if rng.unit() >= PEER_COVERAGE {
+                continue;
+            }
+            let data_points = 1 + rng.below(20);
+            storage
+                .cache_trust_score(CachedTrustScore {
+                    id_domain: agent.id_domain.to_string(),
+                    agent_id: agent.agent_id.clone(),
+                    score: TrustScore::new(
+                        rng.normal(agent.mean_roi, 0.1).max(0.0),
+                        data_points as f64 * 10f64.powf(1.0 + rng.unit() * 2.0),
+                        data_points,
+                    ),
+                    from_peer: peer_id.to_string(),
+                    cached_at: now - Duration::hours(rng.below(24 * 30) as i64),
+                })
+                .await?;
+            cached_scores += 1;
+        }
+    }
+    storage.add_peers(peers).await?;
+
+    Ok(SeedSummary {
+        agents: agents.len(),
+        experiences: options.experiences,
+        peers: options.peers,
+        cached_scores,
+    })
+}
\ No newline at end of file
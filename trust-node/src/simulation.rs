@@ -0,0 +1,247 @@
+use crate::clock::{system_clock, SharedClock};
+use crate::config::{NodeConfig, P2pTransport};
+use crate::node::{NodeCommand, TrustNode};
+use crate::storage::{SqliteStorage, StorageOptions};
+use crate::types::{AgentIdentifier, AgentScore, Peer, PeerAddress, PeerContact, TrustExperience, TrustQuery, TrustResponse};
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Instant};
+use uuid::Uuid;
+
+/// Memory addresses are process-wide, so every simulated node gets its own
+static NEXT_MEMORY_PORT: AtomicU16 = AtomicU16::new(1);
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+pub struct SimNode {
+    pub name: String,
+    pub peer_id: String,
+    /// Memory multiaddr the node listens on, as stored in other nodes' peer lists next to its peer id
+    pub address: String,
+    pub commands: mpsc::Sender<NodeCommand>,
+    task: JoinHandle<Result<()>>,
+}
+
+/// Trust nodes running inside one process, connected over memory transport, so multi-node
+/// behaviour can be tested without launching processes or binding ports
+pub struct Network {
+    nodes: Vec<SimNode>,
+    /// (from, to): `from` asks `to` for recommendations
+    friendships: Vec<(usize, usize)>,
+    /// Shared by all nodes; pass a ManualClock in the config to control time
+    clock: SharedClock,
+}
+
+impl Network {
+    /// Start `size` nodes with in-memory databases; they know nobody until `befriend` is called
+    pub async fn spawn(size: usize, config: NodeConfig) -> Result<Self> {
+        Self::spawn_each(vec![config; size]).await
+    }
+
+    /// Start one node per config, e.g. to make only some of them faulty; the first config's clock is the network's
+    pub async fn spawn_each(configs: Vec<NodeConfig>) -> Result<Self> {
+        let clock = configs.first().map_or_else(system_clock, |config| config.clock.clone());
+        let mut nodes = Vec::with_capacity(configs.len());
+        for (index, config) in configs.into_iter().enumerate() {
+            let storage_options = StorageOptions {
+                clock: config.clock.clone(),
+                ..StorageOptions::default()
+            };
+            let storage = SqliteStorage::new_with_options(Path::new(":memory:"), storage_options).await?;
+            #[cfg(feature = "chaos")]
+            let storage = crate::chaos::ChaosStorage::new(storage, config.chaos.clone());
+            let port = NEXT_MEMORY_PORT.fetch_add(1, Ordering::Relaxed);
+            let config = NodeConfig {
+                transport: P2pTransport::Memory,
+                ..config
+            };
+            let (node, commands) = TrustNode::new(port, storage, vec![], config).await?;
+            let task = tokio::spawn(node.run());
+
+            let peer_id = command(&commands, |response| NodeCommand::GetSelfPeerId { response }).await?;
+            nodes.push(SimNode {
+                name: format!("node{}", index),
+                address: P2pTransport::Memory.listen_address(port),
+                peer_id,
+                commands,
+                task,
+            });
+        }
+        Ok(Self { nodes, friendships: Vec::new(), clock })
+    }
+
+    pub fn node(&self, index: usize) -> &SimNode {
+        &self.nodes[index]
+    }
+
+    /// Stop one node as if its process died, closing its connections; the others keep running
+    pub fn stop(&self, index: usize) {
+        self.nodes[index].task.abort();
+    }
+
+    /// Whether a node's run loop is still going, i.e. it hasn't been stopped or failed
+    pub fn is_running(&self, index: usize) -> bool {
+        !self.nodes[index].task.is_finished()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Make `from` ask `to` for recommendations, trusting them with `quality`
+    pub async fn befriend(&mut self, from: usize, to: usize, quality: f64) -> Result<()> {
+        let peer = Peer {
+            peer_id: self.nodes[to].peer_id.clone(),
+            addresses: vec![PeerAddress::new(self.nodes[to].address.clone())],
+            handle: None,
+            name: self.nodes[to].name.clone(),
+            recommender_quality: quality,
+            added_at: self.clock.now(),
+            notes: None,
+            tags: Vec::new(),
+            contact: PeerContact::default(),
+            last_interaction_at: None,
+        };
+        command(&self.nodes[from].commands, |response| NodeCommand::AddPeer { peer, response }).await?;
+        self.friendships.push((from, to));
+        Ok(())
+    }
+
+    /// Wire up a whole friendship graph of (from, to, quality) edges
+    pub async fn befriend_all(&mut self, edges: &[(usize, usize, f64)]) -> Result<()> {
+        for &(from, to, quality) in edges {
+            self.befriend(from, to, quality).await?;
+        }
+        Ok(())
+    }
+
+    /// Wait until every friendship has an open connection
+    pub async fn wait_connected(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut missing = None;
+            for &(from, to) in &self.friendships {
+                let connected =
+                    command(&self.nodes[from].commands, |response| NodeCommand::GetConnectedPeers { response }).await?;
+                if !connected.contains(&self.nodes[to].peer_id) {
+                    missing = Some((from, to));
+                    break;
+                }
+            }
+            match missing {
+                None => return Ok(()),
+                Some((from, to)) if Instant::now() >= deadline => {
+                    return Err(anyhow!("{} never connected to {}", self.nodes[from].name, self.nodes[to].name));
+                }
+                Some(_) => sleep(POLL_INTERVAL).await,
+            }
+        }
+    }
+
+    /// Record an experience on one node, computing PV-ROI directly rather than from cash flows
+    pub async fn add_experience(
+        &self,
+        node: usize,
+        id_domain: &str,
+        agent_id: &str,
+        pv_roi: f64,
+        invested_volume: f64,
+    ) -> Result<()> {
+        let experience = TrustExperience {
+            id: Uuid::new_v4(),
+            id_domain: id_domain.to_string(),
+            agent_id: agent_id.to_string(),
+            pv_roi,
+            invested_volume,
+            timestamp: self.clock.now(),
+            notes: None,
+            data: None,
+        };
+        command(&self.nodes[node].commands, |response| NodeCommand::AddExperience { experience, response }).await
+    }
+
+    pub async fn query(&self, node: usize, id_domain: &str, agent_id: &str, max_depth: u8) -> Result<AgentScore> {
+        self.query_with(node, self.trust_query(id_domain, agent_id, max_depth)).await
+    }
+
+    /// A query for one agent as `query` sends it, for tests that set further options
+    pub fn trust_query(&self, id_domain: &str, agent_id: &str, max_depth: u8) -> TrustQuery {
+        TrustQuery {
+            agents: vec![AgentIdentifier::new(id_domain, agent_id)],
+            max_depth: Some(max_depth),
+            point_in_time: Some(self.clock.now()),
+            forget_rate: None,
+            decay: None,
+            aggregator: None,
+            budget_ms: None,
+            continuation: None,
+            traceparent: None,
+            correlation_id: None,
+            timing: false,
+            refresh: false,
+            cache_only: false,
+            expires_at: None,
+        }
+    }
+
+    /// Send a query and return the whole response
+    pub async fn query_response(&self, node: usize, query: TrustQuery) -> Result<TrustResponse> {
+        command(&self.nodes[node].commands, |response| NodeCommand::QueryTrust { query, response }).await
+    }
+
+    /// Send a query for one agent and return that agent's score
+    pub async fn query_with(&self, node: usize, query: TrustQuery) -> Result<AgentScore> {
+        let agent = query.agents.first().cloned().ok_or_else(|| anyhow!("query names no agent"))?;
+        let response = self.query_response(node, query).await?;
+        response
+            .scores
+            .into_iter()
+            .find(|score| score.id_domain == agent.id_domain && score.agent_id == agent.agent_id)
+            .ok_or_else(|| anyhow!("{} returned no score for {}", self.nodes[node].name, agent))
+    }
+
+    /// Query until the score satisfies `converged`, returning the last score either way
+    pub async fn query_until(
+        &self,
+        node: usize,
+        id_domain: &str,
+        agent_id: &str,
+        max_depth: u8,
+        timeout: Duration,
+        converged: impl Fn(&AgentScore) -> bool,
+    ) -> Result<AgentScore> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let score = self.query(node, id_domain, agent_id, max_depth).await;
+            match score {
+                Ok(score) if converged(&score) || Instant::now() >= deadline => return Ok(score),
+                Err(e) if Instant::now() >= deadline => return Err(e),
+                _ => sleep(POLL_INTERVAL).await,
+            }
+        }
+    }
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        for node in &self.nodes {
+            node.task.abort();
+        }
+    }
+}
+
+async fn command<T>(
+    commands: &mpsc::Sender<NodeCommand>,
+    build: impl FnOnce(oneshot::Sender<Result<T>>) -> NodeCommand,
+) -> Result<T> {
+    let (tx, rx) = oneshot::channel();
+    commands.send(build(tx)).await.map_err(|_| anyhow!("node stopped"))?;
+    rx.await.map_err(|_| anyhow!("node dropped the command"))?
+}
\ No newline at end of file
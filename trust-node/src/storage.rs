@@ -1,42 +1,940 @@
-use crate::types::{CachedTrustScore, Peer, TrustExperience, TrustScore};
+use crate::clock::{system_clock, SharedClock};
+use crate::types::{AgentClaim, AgentIdForm, AgentIdentifier, AgentQueries, Aggregator, CachedTrustScore, COMPACTED_PEER, DecayFunction, DomainDefaults, ExperienceChange, ExperienceTemplate, ExperienceFilter, ExperienceRevision, ExperienceSort, IdempotentResponse, InflationIndex, MaintenanceReport, Peer, PeerAddress, PeerRequest, PeerRequestStatus, PendingExperience, PeerContact, PeerProposal, PeeringAnswerOut, ProposedPeer, QualityRevision, QueryResultEntry, SharingPrecision, StorageStats, TrustDataExport, TrustExperience, TrustScore, WatchedAgent, split_peer_multiaddr};
 use anyhow::Result;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use sqlx::{sqlite::SqlitePool, Pool, Sqlite};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+    Connection, Executor, Pool, QueryBuilder, Sqlite,
+};
 use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::instrument;
 use uuid::Uuid;
 
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("{0}")]
+    Duplicate(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("database corruption: {0}")]
+    Corruption(String),
+    #[error("storage I/O error: {0}")]
+    Io(String),
+    #[error(transparent)]
+    Database(sqlx::Error),
+}
+
+pub type StorageResult<T> = std::result::Result<T, StorageError>;
+
+impl From<sqlx::Error> for StorageError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => StorageError::NotFound(err.to_string()),
+            sqlx::Error::Io(e) => StorageError::Io(e.to_string()),
+            sqlx::Error::Database(ref db_err) => {
+                if db_err.is_unique_violation() {
+                    return StorageError::Duplicate(db_err.message().to_string());
+                }
+                // Extended result codes keep the primary code in the low byte
+                let primary_code = db_err.code()
+                    .and_then(|code| code.parse::<i32>().ok())
+                    .map(|code| code & 0xff);
+                match primary_code {
+                    // SQLITE_CORRUPT, SQLITE_NOTADB
+                    Some(11) | Some(26) => StorageError::Corruption(db_err.message().to_string()),
+                    // SQLITE_IOERR, SQLITE_FULL, SQLITE_CANTOPEN
+                    Some(10) | Some(13) | Some(14) => StorageError::Io(db_err.message().to_string()),
+                    _ => StorageError::Database(err),
+                }
+            }
+            _ => StorageError::Database(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(err: std::io::Error) -> Self {
+        StorageError::Io(err.to_string())
+    }
+}
+
 #[async_trait]
 pub trait Storage: Send + Sync {
-    async fn add_experience(&self, experience: TrustExperience) -> Result<()>;
-    async fn get_experiences(&self, id_domain: &str, agent_id: &str) -> Result<Vec<TrustExperience>>;
-    async fn get_all_experiences(&self) -> Result<Vec<TrustExperience>>;
-    async fn remove_experience(&self, experience_id: &str) -> Result<()>;
+    async fn add_experience(&self, experience: TrustExperience) -> StorageResult<()>;
+    /// Insert many experiences in a single transaction; nothing is written if any insert fails
+    async fn add_experiences(&self, experiences: Vec<TrustExperience>) -> StorageResult<()>;
+    async fn get_experience(&self, experience_id: &str) -> StorageResult<Option<TrustExperience>>;
+    async fn get_experiences(&self, id_domain: &str, agent_id: &str) -> StorageResult<Vec<TrustExperience>>;
+    async fn get_all_experiences(&self) -> StorageResult<Vec<TrustExperience>>;
+    /// Iterate over all experiences, newest first, without loading the whole table into memory
+    fn stream_experiences(&self) -> BoxStream<'static, StorageResult<TrustExperience>>;
+
+    async fn find_experiences(&self, filter: &ExperienceFilter) -> StorageResult<Vec<TrustExperience>>;
+    /// How many experiences match `filter`, ignoring its limit and offset
+    async fn count_experiences(&self, filter: &ExperienceFilter) -> StorageResult<u64>;
+    /// Replace an experience's fields, keeping the previous version in its history
+    async fn update_experience(&self, experience: TrustExperience, changed_by: Option<&str>) -> StorageResult<()>;
+    /// Delete an experience, keeping the deleted version in its history
+    async fn remove_experience(&self, experience_id: &str) -> StorageResult<()>;
+    /// Record an investment whose outcome isn't known yet
+    async fn add_pending_experience(&self, pending: PendingExperience) -> StorageResult<()>;
+    /// Investments waiting for their outcome, oldest first
+    async fn get_pending_experiences(&self) -> StorageResult<Vec<PendingExperience>>;
+    /// Replace the pending experience with the same id by its outcome, in one transaction
+    async fn settle_experience(&self, experience: TrustExperience) -> StorageResult<()>;
+    async fn remove_pending_experience(&self, experience_id: &str) -> StorageResult<()>;
+    async fn add_experience_template(&self, template: &ExperienceTemplate) -> StorageResult<()>;
+    async fn get_experience_templates(&self) -> StorageResult<Vec<ExperienceTemplate>>;
+    async fn remove_experience_template(&self, template_id: &str) -> StorageResult<()>;
+    /// Add the pending experiences a template generated and move it on to its next due time, in one transaction
+    async fn record_template_occurrences(
+        &self,
+        template_id: &str,
+        occurrences: Vec<PendingExperience>,
+        next_due_at: DateTime<Utc>,
+    ) -> StorageResult<()>;
+    /// Prior versions of an experience, newest first
+    async fn get_experience_history(&self, experience_id: &str) -> StorageResult<Vec<ExperienceRevision>>;
+    /// Every deletion on record, oldest first; the tombstones an offline merge honors
+    async fn get_experience_deletions(&self) -> StorageResult<Vec<ExperienceRevision>>;
+    /// Undecayed score of an agent from the running aggregates, without reading its experiences
+    async fn get_agent_aggregate(&self, id_domain: &str, agent_id: &str) -> StorageResult<Option<TrustScore>>;
+    /// Every agent with at least one own experience
+    async fn get_experienced_agents(&self) -> StorageResult<Vec<AgentIdentifier>>;
+    /// Experiences of up to `limit` agents following `after`, grouped by agent in (id_domain, agent_id)
+    /// order and newest first within each, so paging through all agents never holds more than a page in memory
+    async fn get_experiences_page(&self, after: Option<(&str, &str)>, limit: u32) -> StorageResult<Vec<TrustExperience>>;
     
-    async fn add_peer(&self, peer: Peer) -> Result<()>;
-    async fn get_peers(&self) -> Result<Vec<Peer>>;
-    async fn update_peer_quality(&self, peer_id: &str, quality: f64) -> Result<()>;
-    async fn remove_peer(&self, peer_id: &str) -> Result<()>;
-    async fn clear_peers(&self) -> Result<()>;
-    async fn clear_experiences(&self) -> Result<()>;
+    async fn add_peer(&self, peer: Peer) -> StorageResult<()>;
+    /// Insert many peers in a single transaction; nothing is written if any insert fails
+    async fn add_peers(&self, peers: Vec<Peer>) -> StorageResult<()>;
+    async fn get_peers(&self) -> StorageResult<Vec<Peer>>;
+    /// Overwrite the editable fields (name, handle, quality, notes, tags, contact, addresses) of an existing peer
+    async fn update_peer(&self, peer: &Peer) -> StorageResult<()>;
+    /// Set a peer's recommender quality, recording `reason` in its quality history
+    async fn update_peer_quality(&self, peer_id: &str, quality: f64, reason: &str) -> StorageResult<()>;
+    /// Every recorded quality of a peer, newest first; kept after the peer is removed
+    async fn get_quality_history(&self, peer_id: &str) -> StorageResult<Vec<QualityRevision>>;
+    async fn remove_peer(&self, peer_id: &str) -> StorageResult<()>;
+    /// Move a peer that rotated its key to its new id, along with its quality history and cached recommendations
+    async fn rename_peer(&self, old_peer_id: &str, new_peer_id: &str) -> StorageResult<()>;
+    async fn clear_peers(&self) -> StorageResult<()>;
+    async fn clear_experiences(&self) -> StorageResult<()>;
     
-    async fn cache_trust_score(&self, cached: CachedTrustScore) -> Result<()>;
-    async fn get_cached_scores(&self, id_domain: &str, agent_id: &str) -> Result<Vec<CachedTrustScore>>;
+    async fn cache_trust_score(&self, cached: CachedTrustScore) -> StorageResult<()>;
+    async fn get_cached_scores(&self, id_domain: &str, agent_id: &str) -> StorageResult<Vec<CachedTrustScore>>;
+    /// Every recommendation cached from peers, for analysis across agents
+    async fn get_all_cached_scores(&self) -> StorageResult<Vec<CachedTrustScore>>;
+    /// Move the scores known peers recommended before `older_than` out of the cache, into the history read back as
+    /// one COMPACTED_PEER entry per agent; returns how many recommendations were folded
+    async fn compact_cached_scores(&self, older_than: DateTime<Utc>) -> StorageResult<u64>;
+
+    /// Persisted QueryEngine result for a cache key, if one has been stored
+    async fn get_query_result(&self, cache_key: &str) -> StorageResult<Option<QueryResultEntry>>;
+    async fn put_query_result(&self, entry: &QueryResultEntry) -> StorageResult<()>;
+    /// Drop the persisted results of one agent
+    async fn invalidate_query_results(&self, id_domain: &str, agent_id: &str) -> StorageResult<()>;
+    /// Drop persisted results calculated before `older_than`, or all of them; returns how many were removed
+    async fn purge_query_results(&self, older_than: Option<DateTime<Utc>>) -> StorageResult<u64>;
+    /// Add the queries counted for each agent; traffic, so it isn't journaled
+    async fn record_agent_queries(&self, queries: &[AgentQueries]) -> StorageResult<()>;
+    /// Up to `limit` agents queried most, their counts weighed down by the days since their last query
+    async fn get_hot_agents(&self, limit: usize, now: DateTime<Utc>) -> StorageResult<Vec<AgentIdentifier>>;
+    /// The response kept for an Idempotency-Key on an endpoint, unless it expired by `now`
+    async fn get_idempotent_response(&self, endpoint: &str, key: &str, now: DateTime<Utc>) -> StorageResult<Option<IdempotentResponse>>;
+    /// Keep a response until `expires_at`, replacing one kept for the same key; traffic, so it isn't journaled
+    async fn put_idempotent_response(&self, response: &IdempotentResponse, expires_at: DateTime<Utc>) -> StorageResult<()>;
+    /// Drop the responses expired by `now`; returns how many were removed
+    async fn purge_idempotent_responses(&self, now: DateTime<Utc>) -> StorageResult<u64>;
+
+    /// All entries of the domain registry
+    async fn get_domain_defaults(&self) -> StorageResult<Vec<DomainDefaults>>;
+    /// Insert or replace the defaults registered for a domain
+    async fn set_domain_defaults(&self, defaults: &DomainDefaults) -> StorageResult<()>;
+    async fn remove_domain_defaults(&self, id_domain: &str) -> StorageResult<()>;
+
+    /// Registered inflation indexes, one per currency
+    async fn get_inflation_indexes(&self) -> StorageResult<Vec<InflationIndex>>;
+    /// Insert or replace the index of a currency
+    async fn set_inflation_index(&self, index: &InflationIndex) -> StorageResult<()>;
+    async fn remove_inflation_index(&self, currency: &str) -> StorageResult<()>;
+
+    /// How precise the scores shared with each peer or circle are, where it isn't full precision
+    async fn get_sharing_precision(&self) -> StorageResult<Vec<SharingPrecision>>;
+    /// Insert or replace the precision of a peer or circle
+    async fn set_sharing_precision(&self, precision: &SharingPrecision) -> StorageResult<()>;
+    async fn remove_sharing_precision(&self, subject: &str) -> StorageResult<()>;
+
+    /// Agent ids the user claimed as their own, verified or not
+    async fn get_agent_claims(&self) -> StorageResult<Vec<AgentClaim>>;
+    async fn add_agent_claim(&self, claim: &AgentClaim) -> StorageResult<()>;
+    /// Mark a claim verified through the proof found at `proof_url`
+    async fn verify_agent_claim(&self, id_domain: &str, agent_id: &str, proof_url: &str, at: DateTime<Utc>) -> StorageResult<()>;
+    async fn remove_agent_claim(&self, id_domain: &str, agent_id: &str) -> StorageResult<()>;
+
+    /// Agents whose merged score is watched, with the score last announced for each
+    async fn get_watchlist(&self) -> StorageResult<Vec<WatchedAgent>>;
+    /// Insert or replace a watchlist entry
+    async fn watch_agent(&self, watched: &WatchedAgent) -> StorageResult<()>;
+    async fn unwatch_agent(&self, id_domain: &str, agent_id: &str) -> StorageResult<()>;
+    /// Remember the score announced for a watched agent; derived state, so it isn't journaled
+    async fn set_watched_score(&self, id_domain: &str, agent_id: &str, score: &TrustScore) -> StorageResult<()>;
+
+    /// Peers outside the peer list that queried this node, most recently seen first
+    async fn get_peer_requests(&self) -> StorageResult<Vec<PeerRequest>>;
+    /// Count a query from a peer outside the peer list, adding it as pending the first time
+    async fn record_peer_request(&self, peer_id: &str, at: DateTime<Utc>) -> StorageResult<PeerRequest>;
+    /// Keep a peer's proposal to become mutual peers on its request, adding it as pending if new
+    async fn record_peer_proposal(&self, peer_id: &str, proposal: &PeerProposal, at: DateTime<Utc>) -> StorageResult<PeerRequest>;
+    /// Ignore or block a peer, whether or not it has queried yet
+    async fn set_peer_request_status(&self, peer_id: &str, status: PeerRequestStatus, at: DateTime<Utc>) -> StorageResult<()>;
+    async fn remove_peer_request(&self, peer_id: &str) -> StorageResult<()>;
+
+    /// Peering proposals we sent that weren't answered yet
+    async fn get_proposed_peers(&self) -> StorageResult<Vec<ProposedPeer>>;
+    /// Insert or replace the proposal to a peer
+    async fn put_proposed_peer(&self, proposed: &ProposedPeer) -> StorageResult<()>;
+    /// Forget the proposal to a peer, if there is one
+    async fn remove_proposed_peer(&self, peer_id: &str) -> StorageResult<()>;
+    /// Answers to peering proposals that their proposers haven't confirmed receiving yet
+    async fn get_peering_answers(&self) -> StorageResult<Vec<PeeringAnswerOut>>;
+    /// Insert or replace the answer to a peer's proposal
+    async fn put_peering_answer(&self, answer: &PeeringAnswerOut) -> StorageResult<()>;
+    /// Forget the answer to a peer's proposal, if there is one
+    async fn remove_peering_answer(&self, peer_id: &str) -> StorageResult<()>;
+
+    async fn storage_stats(&self) -> StorageResult<StorageStats>;
+    /// Check integrity, then VACUUM and ANALYZE if the database is healthy
+    async fn maintain(&self) -> StorageResult<MaintenanceReport>;
+
+    /// Write a consistent snapshot of the whole database to `path`
+    async fn backup_to(&self, path: &Path) -> StorageResult<()>;
+    /// Replace all data with the contents of a snapshot written by `backup_to`, in one transaction
+    async fn restore_snapshot(&self, path: &Path) -> StorageResult<()>;
+    /// Replace all experiences and peers with the contents of an export, in one transaction
+    async fn restore_export(&self, data: TrustDataExport) -> StorageResult<()>;
+    /// Remove the replaced experiences and peers and add the imported ones, in one transaction;
+    /// replaced experiences keep their previous version in the history
+    async fn import_records(
+        &self,
+        replaced_experiences: &[String],
+        experiences: Vec<TrustExperience>,
+        replaced_peers: &[String],
+        peers: Vec<Peer>,
+    ) -> StorageResult<()>;
+    /// Add, update and remove the experiences and add the peers a merge with another database decided on, in one
+    /// transaction; updated experiences keep their previous version in the history under `changed_by`
+    async fn merge_records(
+        &self,
+        added: Vec<TrustExperience>,
+        updated: Vec<TrustExperience>,
+        removed: &[String],
+        peers: Vec<Peer>,
+        changed_by: &str,
+    ) -> StorageResult<()>;
+}
+
+/// Keep the `experiences_fts` index of notes and adapter data in step with `experiences`
+const FTS_TRIGGERS: &[&str] = &[
+    r#"
+    CREATE TRIGGER IF NOT EXISTS experiences_fts_insert AFTER INSERT ON experiences
+    BEGIN
+        INSERT INTO experiences_fts (rowid, notes, data) VALUES (NEW.rowid, NEW.notes, NEW.data);
+    END
+    "#,
+    r#"
+    CREATE TRIGGER IF NOT EXISTS experiences_fts_delete AFTER DELETE ON experiences
+    BEGIN
+        INSERT INTO experiences_fts (experiences_fts, rowid, notes, data) VALUES ('delete', OLD.rowid, OLD.notes, OLD.data);
+    END
+    "#,
+    r#"
+    CREATE TRIGGER IF NOT EXISTS experiences_fts_update AFTER UPDATE OF notes, data ON experiences
+    BEGIN
+        INSERT INTO experiences_fts (experiences_fts, rowid, notes, data) VALUES ('delete', OLD.rowid, OLD.notes, OLD.data);
+        INSERT INTO experiences_fts (rowid, notes, data) VALUES (NEW.rowid, NEW.notes, NEW.data);
+    END
+    "#,
+];
+
+/// Keep `agent_aggregates` in step with every insert, update and delete on `experiences`
+const AGGREGATE_TRIGGERS: &[&str] = &[
+    r#"
+    CREATE TRIGGER IF NOT EXISTS experiences_aggregate_insert AFTER INSERT ON experiences
+    BEGIN
+        INSERT INTO agent_aggregates (id_domain, agent_id, weighted_roi_sum, total_volume, data_points)
+        VALUES (NEW.id_domain, NEW.agent_id,
+                CASE WHEN NEW.invested_volume > 0 THEN NEW.pv_roi * NEW.invested_volume ELSE 0 END,
+                MAX(NEW.invested_volume, 0), 1)
+        ON CONFLICT(id_domain, agent_id) DO UPDATE SET
+            weighted_roi_sum = weighted_roi_sum + excluded.weighted_roi_sum,
+            total_volume = total_volume + excluded.total_volume,
+            data_points = data_points + 1;
+    END
+    "#,
+    r#"
+    CREATE TRIGGER IF NOT EXISTS experiences_aggregate_delete AFTER DELETE ON experiences
+    BEGIN
+        UPDATE agent_aggregates SET
+            weighted_roi_sum = weighted_roi_sum - CASE WHEN OLD.invested_volume > 0 THEN OLD.pv_roi * OLD.invested_volume ELSE 0 END,
+            total_volume = total_volume - MAX(OLD.invested_volume, 0),
+            data_points = data_points - 1
+        WHERE id_domain = OLD.id_domain AND agent_id = OLD.agent_id;
+        DELETE FROM agent_aggregates
+        WHERE id_domain = OLD.id_domain AND agent_id = OLD.agent_id AND data_points <= 0;
+    END
+    "#,
+    r#"
+    CREATE TRIGGER IF NOT EXISTS experiences_aggregate_update
+    AFTER UPDATE OF id_domain, agent_id, pv_roi, invested_volume ON experiences
+    BEGIN
+        UPDATE agent_aggregates SET
+            weighted_roi_sum = weighted_roi_sum - CASE WHEN OLD.invested_volume > 0 THEN OLD.pv_roi * OLD.invested_volume ELSE 0 END,
+            total_volume = total_volume - MAX(OLD.invested_volume, 0),
+            data_points = data_points - 1
+        WHERE id_domain = OLD.id_domain AND agent_id = OLD.agent_id;
+        DELETE FROM agent_aggregates
+        WHERE id_domain = OLD.id_domain AND agent_id = OLD.agent_id AND data_points <= 0;
+        INSERT INTO agent_aggregates (id_domain, agent_id, weighted_roi_sum, total_volume, data_points)
+        VALUES (NEW.id_domain, NEW.agent_id,
+                CASE WHEN NEW.invested_volume > 0 THEN NEW.pv_roi * NEW.invested_volume ELSE 0 END,
+                MAX(NEW.invested_volume, 0), 1)
+        ON CONFLICT(id_domain, agent_id) DO UPDATE SET
+            weighted_roi_sum = weighted_roi_sum + excluded.weighted_roi_sum,
+            total_volume = total_volume + excluded.total_volume,
+            data_points = data_points + 1;
+    END
+    "#,
+];
+
+#[derive(sqlx::FromRow)]
+struct CachedScoreRow {
+    id_domain: String,
+    agent_id: String,
+    expected_pv_roi: f64,
+    total_volume: f64,
+    data_points: i64,
+    from_peer: String,
+    cached_at: String,
+}
+
+impl From<CachedScoreRow> for CachedTrustScore {
+    fn from(row: CachedScoreRow) -> Self {
+        CachedTrustScore {
+            id_domain: row.id_domain,
+            agent_id: row.agent_id,
+            score: TrustScore {
+                expected_pv_roi: row.expected_pv_roi,
+                total_volume: row.total_volume,
+                data_points: row.data_points as usize,
+            },
+            from_peer: row.from_peer,
+            cached_at: DateTime::parse_from_rfc3339(&row.cached_at).unwrap().with_timezone(&Utc),
+        }
+    }
+}
+
+/// Rows fetched per query by `stream_experiences`
+const STREAM_PAGE_SIZE: i64 = 500;
+
+/// Tables copied over when restoring a snapshot, and whether a snapshot must contain them
+const RESTORED_TABLES: &[(&str, bool)] = &[
+    ("experiences", true),
+    ("peers", true),
+    ("cached_scores", true),
+    ("compacted_scores", false),
+    ("experience_history", false),
+    ("peer_quality_history", false),
+    ("domains", false),
+    ("pending_experiences", false),
+    ("experience_templates", false),
+    ("inflation_indexes", false),
+    ("sharing_precision", false),
+    ("agent_claims", false),
+];
+
+#[derive(sqlx::FromRow)]
+struct AgentClaimRow {
+    id_domain: String,
+    agent_id: String,
+    challenge: String,
+    claimed_at: String,
+    proof_url: Option<String>,
+    verified_at: Option<String>,
+}
+
+impl TryFrom<AgentClaimRow> for AgentClaim {
+    type Error = StorageError;
+
+    fn try_from(row: AgentClaimRow) -> Result<Self, Self::Error> {
+        let parse_time = |value: &str| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|e| StorageError::Corruption(format!("agent claim time {}: {}", value, e)))
+        };
+        Ok(AgentClaim {
+            claimed_at: parse_time(&row.claimed_at)?,
+            verified_at: row.verified_at.as_deref().map(parse_time).transpose()?,
+            id_domain: row.id_domain,
+            agent_id: row.agent_id,
+            challenge: row.challenge,
+            proof_url: row.proof_url,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct PeerRequestRow {
+    peer_id: String,
+    status: String,
+    first_seen_at: String,
+    last_seen_at: String,
+    queries: i64,
+    proposal: Option<String>,
+}
+
+impl TryFrom<PeerRequestRow> for PeerRequest {
+    type Error = StorageError;
+
+    fn try_from(row: PeerRequestRow) -> Result<Self, Self::Error> {
+        let parse_time = |value: &str| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|e| StorageError::Corruption(format!("peer request time {}: {}", value, e)))
+        };
+        Ok(PeerRequest {
+            status: row.status.parse().map_err(StorageError::Corruption)?,
+            first_seen_at: parse_time(&row.first_seen_at)?,
+            last_seen_at: parse_time(&row.last_seen_at)?,
+            queries: row.queries.max(0) as u64,
+            proposal: row.proposal.and_then(|p| serde_json::from_str(&p).ok()),
+            peer_id: row.peer_id,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ExperienceRow {
+    id: String,
+    id_domain: String,
+    agent_id: String,
+    pv_roi: f64,
+    invested_volume: f64,
+    timestamp: String,
+    notes: Option<String>,
+    data: Option<String>,
+}
+
+impl From<ExperienceRow> for TrustExperience {
+    fn from(row: ExperienceRow) -> Self {
+        TrustExperience {
+            id: Uuid::parse_str(&row.id).unwrap(),
+            id_domain: row.id_domain,
+            agent_id: row.agent_id,
+            pv_roi: row.pv_roi,
+            invested_volume: row.invested_volume,
+            timestamp: DateTime::parse_from_rfc3339(&row.timestamp).unwrap().with_timezone(&Utc),
+            notes: row.notes,
+            data: row.data.and_then(|d| serde_json::from_str(&d).ok()),
+        }
+    }
+}
+
+async fn insert_experience<'e, E>(executor: E, experience: &TrustExperience) -> StorageResult<()>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let data_json = experience.data.as_ref()
+        .map(|d| serde_json::to_string(d).unwrap_or_else(|_| "{}".to_string()));
+
+    sqlx::query(
+        r#"
+        INSERT INTO experiences (id, id_domain, agent_id, pv_roi, invested_volume, timestamp, notes, data)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        "#
+    )
+    .bind(experience.id.to_string())
+    .bind(&experience.id_domain)
+    .bind(&experience.agent_id)
+    .bind(experience.pv_roi)
+    .bind(experience.invested_volume)
+    .bind(experience.timestamp.to_rfc3339())
+    .bind(&experience.notes)
+    .bind(&data_json)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_pending_experience<'e, E>(executor: E, pending: &PendingExperience) -> StorageResult<()>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let data_json = pending.data.as_ref()
+        .map(|d| serde_json::to_string(d).unwrap_or_else(|_| "{}".to_string()));
+
+    sqlx::query(
+        r#"
+        INSERT INTO pending_experiences
+            (id, id_domain, agent_id, invested_volume, timestamp, notes, data, expected_return, template_id)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+        "#
+    )
+    .bind(pending.id.to_string())
+    .bind(&pending.id_domain)
+    .bind(&pending.agent_id)
+    .bind(pending.invested_volume)
+    .bind(pending.timestamp.to_rfc3339())
+    .bind(&pending.notes)
+    .bind(&data_json)
+    .bind(pending.expected_return)
+    .bind(pending.template_id.map(|id| id.to_string()))
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Overwrite the stored experience with the same id
+async fn write_experience<'e, E>(executor: E, experience: &TrustExperience) -> StorageResult<()>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let data_json = experience.data.as_ref()
+        .map(|d| serde_json::to_string(d).unwrap_or_else(|_| "{}".to_string()));
+    sqlx::query(
+        r#"
+        UPDATE experiences
+        SET id_domain = ?2, agent_id = ?3, pv_roi = ?4, invested_volume = ?5, timestamp = ?6, notes = ?7, data = ?8
+        WHERE id = ?1
+        "#
+    )
+    .bind(experience.id.to_string())
+    .bind(&experience.id_domain)
+    .bind(&experience.agent_id)
+    .bind(experience.pv_roi)
+    .bind(experience.invested_volume)
+    .bind(experience.timestamp.to_rfc3339())
+    .bind(&experience.notes)
+    .bind(&data_json)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Delete an experience, keeping its last version in the history
+async fn delete_experience(tx: &mut sqlx::Transaction<'_, Sqlite>, experience_id: &str, now: DateTime<Utc>) -> StorageResult<()> {
+    let previous = fetch_experience(&mut **tx, experience_id).await?
+        .ok_or_else(|| StorageError::NotFound(format!("Unknown experience {}", experience_id)))?;
+    record_history(&mut **tx, &previous, ExperienceChange::Delete, None, now).await?;
+    sqlx::query("DELETE FROM experiences WHERE id = ?1").bind(experience_id).execute(&mut **tx).await?;
+    Ok(())
+}
+
+async fn fetch_experience<'e, E>(executor: E, experience_id: &str) -> StorageResult<Option<TrustExperience>>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let row = sqlx::query_as::<_, ExperienceRow>(
+        r#"
+        SELECT id, id_domain, agent_id, pv_roi, invested_volume, timestamp, notes, data
+        FROM experiences
+        WHERE id = ?1
+        "#
+    )
+    .bind(experience_id)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(row.map(TrustExperience::from))
+}
+
+#[derive(sqlx::FromRow)]
+struct RevisionRow {
+    revision: i64,
+    experience_id: String,
+    change: String,
+    changed_at: String,
+    changed_by: Option<String>,
+    previous: String,
+}
+
+impl TryFrom<RevisionRow> for ExperienceRevision {
+    type Error = StorageError;
+
+    fn try_from(row: RevisionRow) -> StorageResult<Self> {
+        let change = match row.change.as_str() {
+            "update" => ExperienceChange::Update,
+            "delete" => ExperienceChange::Delete,
+            other => return Err(StorageError::Corruption(format!("unknown history change {}", other))),
+        };
+        Ok(ExperienceRevision {
+            revision: row.revision,
+            experience_id: Uuid::parse_str(&row.experience_id)
+                .map_err(|e| StorageError::Corruption(e.to_string()))?,
+            change,
+            changed_at: DateTime::parse_from_rfc3339(&row.changed_at)
+                .map_err(|e| StorageError::Corruption(e.to_string()))?
+                .with_timezone(&Utc),
+            changed_by: row.changed_by,
+            previous: serde_json::from_str(&row.previous)
+                .map_err(|e| StorageError::Corruption(e.to_string()))?,
+        })
+    }
+}
+
+async fn record_history<'e, E>(
+    executor: E,
+    previous: &TrustExperience,
+    change: ExperienceChange,
+    changed_by: Option<&str>,
+    changed_at: DateTime<Utc>,
+) -> StorageResult<()>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let previous_json = serde_json::to_string(previous)
+        .map_err(|e| StorageError::Corruption(e.to_string()))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO experience_history (experience_id, change, changed_at, changed_by, previous)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        "#
+    )
+    .bind(previous.id.to_string())
+    .bind(change.as_str())
+    .bind(changed_at.to_rfc3339())
+    .bind(changed_by)
+    .bind(previous_json)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Reason recorded for the quality a peer is added with
+pub const QUALITY_ADDED: &str = "added";
+/// Reason recorded when a peer edit changes its quality
+pub const QUALITY_EDITED: &str = "edited";
+/// Reason recorded when a quality is set without one being given
+pub const QUALITY_SET: &str = "set";
+
+async fn record_quality_change<'e, E>(
+    executor: E,
+    peer_id: &str,
+    previous_quality: Option<f64>,
+    quality: f64,
+    reason: &str,
+    changed_at: DateTime<Utc>,
+) -> StorageResult<()>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    sqlx::query(
+        r#"
+        INSERT INTO peer_quality_history (peer_id, previous_quality, quality, changed_at, reason)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        "#
+    )
+    .bind(peer_id)
+    .bind(previous_quality)
+    .bind(quality)
+    .bind(changed_at.to_rfc3339())
+    .bind(reason)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Current quality of a peer, or NotFound
+async fn fetch_peer_quality<'e, E>(executor: E, peer_id: &str) -> StorageResult<f64>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let quality: Option<(f64,)> = sqlx::query_as("SELECT recommender_quality FROM peers WHERE peer_id = ?1")
+        .bind(peer_id)
+        .fetch_optional(executor)
+        .await?;
+    quality
+        .map(|(quality,)| quality)
+        .ok_or_else(|| StorageError::NotFound(format!("Unknown peer {}", peer_id)))
+}
+
+async fn insert_peer<'e, E>(executor: E, peer: &Peer) -> StorageResult<()>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    sqlx::query(
+        r#"
+        INSERT INTO peers (peer_id, name, recommender_quality, added_at, notes, tags, contact_email, contact_fediverse,
+            last_interaction_at, addresses, handle)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+        "#
+    )
+    .bind(&peer.peer_id)
+    .bind(&peer.name)
+    .bind(peer.recommender_quality)
+    .bind(peer.added_at.to_rfc3339())
+    .bind(&peer.notes)
+    .bind(serde_json::to_string(&peer.tags).unwrap_or_else(|_| "[]".to_string()))
+    .bind(&peer.contact.email)
+    .bind(&peer.contact.fediverse)
+    .bind(peer.last_interaction_at.map(|at| at.to_rfc3339()))
+    .bind(serde_json::to_string(&peer.addresses).unwrap_or_else(|_| "[]".to_string()))
+    .bind(&peer.handle)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Move peers stored with a multiaddr as peer_id, as older nodes did, to their bare PeerId plus an address.
+/// A peer known under both forms keeps the bare row, gaining the address.
+async fn split_legacy_peer_ids(conn: &mut SqliteConnection) -> StorageResult<()> {
+    let legacy: Vec<(String, String)> = sqlx::query_as("SELECT peer_id, addresses FROM peers WHERE peer_id LIKE '%/p2p/%'")
+        .fetch_all(&mut *conn)
+        .await?;
+
+    for (legacy_id, addresses) in legacy {
+        let Some((peer_id, address)) = split_peer_multiaddr(&legacy_id) else {
+            continue;
+        };
+        let mut addresses: Vec<PeerAddress> = serde_json::from_str(&addresses).unwrap_or_default();
+        if !address.is_empty() && !addresses.iter().any(|a| a.address == address) {
+            addresses.push(PeerAddress::new(address));
+        }
+
+        let existing: Option<String> = sqlx::query_scalar("SELECT addresses FROM peers WHERE peer_id = ?1")
+            .bind(peer_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        if let Some(existing) = existing {
+            let mut merged: Vec<PeerAddress> = serde_json::from_str(&existing).unwrap_or_default();
+            for address in addresses {
+                if !merged.iter().any(|a| a.address == address.address) {
+                    merged.push(address);
+                }
+            }
+            sqlx::query("UPDATE peers SET addresses = ?1 WHERE peer_id = ?2")
+                .bind(serde_json::to_string(&merged).unwrap_or_else(|_| "[]".to_string()))
+                .bind(peer_id)
+                .execute(&mut *conn)
+                .await?;
+            sqlx::query("DELETE FROM peers WHERE peer_id = ?1")
+                .bind(&legacy_id)
+                .execute(&mut *conn)
+                .await?;
+        } else {
+            sqlx::query("UPDATE peers SET peer_id = ?1, addresses = ?2 WHERE peer_id = ?3")
+                .bind(peer_id)
+                .bind(serde_json::to_string(&addresses).unwrap_or_else(|_| "[]".to_string()))
+                .bind(&legacy_id)
+                .execute(&mut *conn)
+                .await?;
+        }
+        sqlx::query("UPDATE peer_quality_history SET peer_id = ?1 WHERE peer_id = ?2")
+            .bind(peer_id)
+            .bind(&legacy_id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Add a column to a table created by an older version of the node
+async fn ensure_column(pool: &Pool<Sqlite>, table: &str, column: &str, definition: &str) -> StorageResult<()> {
+    let exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)"
+    )
+    .bind(table)
+    .bind(column)
+    .fetch_one(pool)
+    .await?;
+
+    if !exists {
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+            .execute(pool)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Re-index every experience, for databases from before the index and after VACUUM renumbered rows
+async fn rebuild_fts(pool: &Pool<Sqlite>) -> StorageResult<()> {
+    sqlx::query("INSERT INTO experiences_fts (experiences_fts) VALUES ('rebuild')")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Copy all restorable tables from the database attached as `snapshot` into `main`
+async fn copy_from_snapshot(conn: &mut SqliteConnection) -> StorageResult<()> {
+    let integrity: String = sqlx::query_scalar("PRAGMA snapshot.integrity_check")
+        .fetch_one(&mut *conn)
+        .await?;
+    if integrity != "ok" {
+        return Err(StorageError::Corruption(format!("snapshot failed integrity check: {}", integrity)));
+    }
+
+    let mut present_tables = Vec::new();
+    for &(table, required) in RESTORED_TABLES {
+        let exists: Option<String> = sqlx::query_scalar(
+            "SELECT name FROM snapshot.sqlite_master WHERE type = 'table' AND name = ?1"
+        )
+        .bind(table)
+        .fetch_optional(&mut *conn)
+        .await?;
+        match exists {
+            Some(_) => present_tables.push(table),
+            None if required => {
+                return Err(StorageError::Corruption(format!("snapshot is missing table {}", table)));
+            }
+            // Tables added after the snapshot was taken are simply left empty
+            None => {}
+        }
+    }
+
+    let mut tx = conn.begin().await?;
+    for table in present_tables {
+        // Only copy columns both schemas know about, so older snapshots stay restorable
+        let columns: Vec<String> = sqlx::query_scalar(
+            "SELECT name FROM pragma_table_info(?1, 'snapshot') WHERE name IN (SELECT name FROM pragma_table_info(?1, 'main'))"
+        )
+        .bind(table)
+        .fetch_all(&mut *tx)
+        .await?;
+        let columns = columns.join(", ");
+
+        sqlx::query(&format!("DELETE FROM main.{}", table))
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&format!("INSERT INTO main.{table} ({columns}) SELECT {columns} FROM snapshot.{table}"))
+            .execute(&mut *tx)
+            .await?;
+    }
+    split_legacy_peer_ids(&mut tx).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// FROM and WHERE clauses selecting the experiences matched by `filter`, as `e`
+fn push_experience_filter(query: &mut QueryBuilder<'_, Sqlite>, filter: &ExperienceFilter) {
+    let terms = filter.q.as_deref().map(fts_terms).filter(|terms| !terms.is_empty());
+    query.push(" FROM experiences e");
+    if terms.is_some() {
+        query.push(" JOIN experiences_fts ON experiences_fts.rowid = e.rowid");
+    }
+    query.push(" WHERE 1 = 1");
+
+    if let Some(terms) = terms {
+        query.push(" AND experiences_fts MATCH ").push_bind(terms);
+    }
+    if let Some(id_domain) = &filter.id_domain {
+        query.push(" AND e.id_domain = ").push_bind(id_domain.clone());
+    }
+    if let Some(agent_id) = &filter.agent_id {
+        query.push(" AND e.agent_id = ").push_bind(agent_id.clone());
+    }
+    if let Some(since) = filter.since {
+        query.push(" AND e.timestamp >= ").push_bind(since.to_rfc3339());
+    }
+    if let Some(until) = filter.until {
+        query.push(" AND e.timestamp <= ").push_bind(until.to_rfc3339());
+    }
+    if let Some(min_volume) = filter.min_volume {
+        query.push(" AND e.invested_volume >= ").push_bind(min_volume);
+    }
+    match filter.has_notes {
+        Some(true) => { query.push(" AND e.notes IS NOT NULL AND e.notes != ''"); }
+        Some(false) => { query.push(" AND (e.notes IS NULL OR e.notes = '')"); }
+        None => {}
+    }
+    match filter.has_data {
+        Some(true) => { query.push(" AND e.data IS NOT NULL"); }
+        Some(false) => { query.push(" AND e.data IS NULL"); }
+        None => {}
+    }
+    if let Some(tag) = &filter.tag {
+        query
+            .push(" AND EXISTS (SELECT 1 FROM json_each(e.data, '$.tags') WHERE json_each.value = ")
+            .push_bind(tag.clone())
+            .push(")");
+    }
+}
+
+/// Quote every word of a search so FTS5 syntax in it can't fail the query; each matches as a prefix
+fn fts_terms(q: &str) -> String {
+    q.split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Connection pool and pragma settings for SqliteStorage
+#[derive(Debug, Clone)]
+pub struct StorageOptions {
+    pub max_connections: u32,
+    pub busy_timeout: Duration,
+    /// Extra pragmas applied after the defaults (WAL journal, synchronous=NORMAL), overriding them on conflict
+    pub pragmas: Vec<(String, String)>,
+    /// Time stamped on revision history entries
+    pub clock: SharedClock,
+}
+
+impl Default for StorageOptions {
+    fn default() -> Self {
+        Self {
+            max_connections: 5,
+            busy_timeout: Duration::from_secs(5),
+            pragmas: Vec::new(),
+            clock: system_clock(),
+        }
+    }
 }
 
 pub struct SqliteStorage {
     pool: Pool<Sqlite>,
+    clock: SharedClock,
 }
 
 impl SqliteStorage {
     pub async fn new(path: &Path) -> Result<Self> {
+        Self::new_with_options(path, StorageOptions::default()).await
+    }
+
+    pub async fn new_with_options(path: &Path, options: StorageOptions) -> Result<Self> {
         // Ensure parent directory exists
         if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)?;
         }
         
         let db_url = format!("sqlite://{}?mode=rwc", path.display());
-        let pool = SqlitePool::connect(&db_url).await?;
+        // WAL lets swarm-triggered reads proceed while the API writes; busy_timeout
+        // makes the remaining writer/writer contention wait instead of failing
+        let mut connect_options = SqliteConnectOptions::from_str(&db_url)?
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(options.busy_timeout);
+        for (key, value) in options.pragmas {
+            connect_options = connect_options.pragma(key, value);
+        }
+
+        // Every connection to :memory: opens a database of its own, so an in-memory store gets exactly one, kept for good
+        let in_memory = path.as_os_str() == ":memory:";
+        let pool_options = if in_memory {
+            SqlitePoolOptions::new().max_connections(1).idle_timeout(None).max_lifetime(None)
+        } else {
+            SqlitePoolOptions::new().max_connections(options.max_connections)
+        };
+        let mut pool = pool_options.clone().connect_with(connect_options.clone()).await?;
         
         // Create tables
         sqlx::query(
@@ -69,6 +967,49 @@ impl SqliteStorage {
         .execute(&pool)
         .await?;
 
+        // Running per-agent sums, kept current by the triggers below so that
+        // undecayed scores don't need to read every experience
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS agent_aggregates (
+                id_domain TEXT NOT NULL,
+                agent_id TEXT NOT NULL,
+                weighted_roi_sum REAL NOT NULL, -- sum of pv_roi * invested_volume over positive volumes
+                total_volume REAL NOT NULL,
+                data_points INTEGER NOT NULL,
+                PRIMARY KEY (id_domain, agent_id)
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+
+        for trigger in AGGREGATE_TRIGGERS {
+            sqlx::query(trigger).execute(&pool).await?;
+        }
+
+        // Databases created before the aggregates existed need a one-off backfill
+        let needs_backfill: bool = sqlx::query_scalar(
+            "SELECT NOT EXISTS(SELECT 1 FROM agent_aggregates) AND EXISTS(SELECT 1 FROM experiences)"
+        )
+        .fetch_one(&pool)
+        .await?;
+        if needs_backfill {
+            sqlx::query(
+                r#"
+                INSERT INTO agent_aggregates (id_domain, agent_id, weighted_roi_sum, total_volume, data_points)
+                SELECT id_domain, agent_id,
+                       SUM(CASE WHEN invested_volume > 0 THEN pv_roi * invested_volume ELSE 0 END),
+                       SUM(MAX(invested_volume, 0)),
+                       COUNT(*)
+                FROM experiences
+                GROUP BY id_domain, agent_id
+                "#
+            )
+            .execute(&pool)
+            .await?;
+        }
+
         sqlx::query(
             r#"
             CREATE TABLE IF NOT EXISTS peers (
@@ -76,13 +1017,32 @@ impl SqliteStorage {
                 name TEXT NOT NULL,
                 recommender_quality REAL NOT NULL DEFAULT 0.5,
                 added_at TEXT NOT NULL,
-                updated_at TEXT DEFAULT CURRENT_TIMESTAMP
+                updated_at TEXT DEFAULT CURRENT_TIMESTAMP,
+                notes TEXT,
+                tags TEXT NOT NULL DEFAULT '[]', -- JSON array
+                contact_email TEXT,
+                contact_fediverse TEXT,
+                last_interaction_at TEXT,
+                addresses TEXT NOT NULL DEFAULT '[]', -- JSON array of {address, last_success_at}
+                handle TEXT
             )
             "#
         )
         .execute(&pool)
         .await?;
 
+        ensure_column(&pool, "peers", "notes", "TEXT").await?;
+        ensure_column(&pool, "peers", "tags", "TEXT NOT NULL DEFAULT '[]'").await?;
+        ensure_column(&pool, "peers", "contact_email", "TEXT").await?;
+        ensure_column(&pool, "peers", "contact_fediverse", "TEXT").await?;
+        ensure_column(&pool, "peers", "last_interaction_at", "TEXT").await?;
+        ensure_column(&pool, "peers", "addresses", "TEXT NOT NULL DEFAULT '[]'").await?;
+        ensure_column(&pool, "peers", "handle", "TEXT").await?;
+        // Handles name peers in API paths, so two peers can't share one
+        sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_peers_handle ON peers(handle)")
+            .execute(&pool)
+            .await?;
+
         sqlx::query(
             r#"
             CREATE TABLE IF NOT EXISTS cached_scores (
@@ -111,295 +1071,1971 @@ impl SqliteStorage {
         )
         .execute(&pool)
         .await?;
-        
-        Ok(Self { pool })
-    }
-}
 
-#[async_trait]
-impl Storage for SqliteStorage {
-    async fn add_experience(&self, experience: TrustExperience) -> Result<()> {
-        let data_json = experience.data.as_ref()
-            .map(|d| serde_json::to_string(d).unwrap_or_else(|_| "{}".to_string()));
-            
+        // Recommendations folded by compaction, still per peer, so a newer one from the same peer replaces
+        // instead of adding to it and one from a peer we no longer know drops out
         sqlx::query(
             r#"
-            INSERT INTO experiences (id, id_domain, agent_id, pv_roi, invested_volume, timestamp, notes, data)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            CREATE TABLE IF NOT EXISTS compacted_scores (
+                id_domain TEXT NOT NULL,
+                agent_id TEXT NOT NULL,
+                expected_pv_roi REAL NOT NULL,
+                total_volume REAL NOT NULL,
+                data_points INTEGER NOT NULL,
+                from_peer TEXT NOT NULL,
+                cached_at TEXT NOT NULL,
+                PRIMARY KEY (id_domain, agent_id, from_peer)
+            )
             "#
         )
-        .bind(experience.id.to_string())
-        .bind(&experience.id_domain)
-        .bind(&experience.agent_id)
-        .bind(experience.pv_roi)
-        .bind(experience.invested_volume)
-        .bind(experience.timestamp.to_rfc3339())
-        .bind(&experience.notes)
-        .bind(&data_json)
-        .execute(&self.pool)
+        .execute(&pool)
         .await?;
-        
-        Ok(())
-    }
 
-    async fn get_experiences(&self, id_domain: &str, agent_id: &str) -> Result<Vec<TrustExperience>> {
-        #[derive(sqlx::FromRow)]
-        struct ExperienceRow {
-            id: String,
-            id_domain: String,
-            agent_id: String,
-            pv_roi: f64,
-            invested_volume: f64,
-            timestamp: String,
-            notes: Option<String>,
-            data: Option<String>,
-        }
-        
-        let rows = sqlx::query_as::<_, ExperienceRow>(
+        // Full-text index over the experiences' own notes and data, filled by the triggers below
+        let fts_exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'experiences_fts')"
+        )
+        .fetch_one(&pool)
+        .await?;
+        sqlx::query(
             r#"
-            SELECT id, id_domain, agent_id, pv_roi, invested_volume, timestamp, notes, data
-            FROM experiences
-            WHERE id_domain = ?1 AND agent_id = ?2
-            ORDER BY timestamp DESC
+            CREATE VIRTUAL TABLE IF NOT EXISTS experiences_fts
+            USING fts5(notes, data, content = 'experiences', content_rowid = 'rowid')
             "#
         )
-        .bind(id_domain)
-        .bind(agent_id)
-        .fetch_all(&self.pool)
+        .execute(&pool)
         .await?;
-        
-        let experiences = rows
-            .into_iter()
-            .map(|row| TrustExperience {
-                id: Uuid::parse_str(&row.id).unwrap(),
-                id_domain: row.id_domain,
-                agent_id: row.agent_id,
-                pv_roi: row.pv_roi,
-                invested_volume: row.invested_volume,
-                timestamp: DateTime::parse_from_rfc3339(&row.timestamp).unwrap().with_timezone(&Utc),
-                notes: row.notes,
-                data: row.data.and_then(|d| serde_json::from_str(&d).ok()),
-            })
-            .collect();
-        
-        Ok(experiences)
-    }
-
-    async fn get_all_experiences(&self) -> Result<Vec<TrustExperience>> {
-        #[derive(sqlx::FromRow)]
-        struct ExperienceRow {
-            id: String,
-            id_domain: String,
-            agent_id: String,
-            pv_roi: f64,
-            invested_volume: f64,
-            timestamp: String,
-            notes: Option<String>,
-            data: Option<String>,
+        for trigger in FTS_TRIGGERS {
+            sqlx::query(trigger).execute(&pool).await?;
         }
-        
-        let rows = sqlx::query_as::<_, ExperienceRow>(
+        if !fts_exists {
+            rebuild_fts(&pool).await?;
+        }
+
+        sqlx::query(
             r#"
-            SELECT id, id_domain, agent_id, pv_roi, invested_volume, timestamp, notes, data
-            FROM experiences
-            ORDER BY timestamp DESC
+            CREATE TABLE IF NOT EXISTS experience_history (
+                revision INTEGER PRIMARY KEY AUTOINCREMENT,
+                experience_id TEXT NOT NULL,
+                change TEXT NOT NULL, -- 'update' or 'delete'
+                changed_at TEXT NOT NULL,
+                changed_by TEXT,
+                previous TEXT NOT NULL -- JSON of the experience before the change
+            )
             "#
         )
-        .fetch_all(&self.pool)
+        .execute(&pool)
         .await?;
-        
-        let experiences = rows
-            .into_iter()
-            .map(|row| TrustExperience {
-                id: Uuid::parse_str(&row.id).unwrap(),
-                id_domain: row.id_domain,
-                agent_id: row.agent_id,
-                pv_roi: row.pv_roi,
-                invested_volume: row.invested_volume,
-                timestamp: DateTime::parse_from_rfc3339(&row.timestamp).unwrap().with_timezone(&Utc),
-                notes: row.notes,
-                data: row.data.and_then(|d| serde_json::from_str(&d).ok()),
-            })
-            .collect();
-        
-        Ok(experiences)
-    }
 
-    async fn add_peer(&self, peer: Peer) -> Result<()> {
-        // Check if peer already exists
-        let existing = sqlx::query("SELECT peer_id FROM peers WHERE peer_id = ?1")
-            .bind(&peer.peer_id)
+        sqlx::query(
+            r#"CREATE INDEX IF NOT EXISTS idx_experience_history_experience_id ON experience_history(experience_id)"#
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS peer_quality_history (
+                revision INTEGER PRIMARY KEY AUTOINCREMENT,
+                peer_id TEXT NOT NULL,
+                previous_quality REAL, -- NULL when the peer was added
+                quality REAL NOT NULL,
+                changed_at TEXT NOT NULL,
+                reason TEXT NOT NULL
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            r#"CREATE INDEX IF NOT EXISTS idx_peer_quality_history_peer_id ON peer_quality_history(peer_id)"#
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS query_results (
+                cache_key TEXT PRIMARY KEY,
+                id_domain TEXT NOT NULL,
+                agent_id TEXT NOT NULL,
+                expected_pv_roi REAL NOT NULL,
+                total_volume REAL NOT NULL,
+                data_points INTEGER NOT NULL,
+                calculated_at TEXT NOT NULL
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            r#"CREATE INDEX IF NOT EXISTS idx_query_results_agent_id ON query_results(id_domain, agent_id)"#
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS domains (
+                id_domain TEXT PRIMARY KEY,
+                forget_rate REAL,
+                decay TEXT, -- 'linear' or 'exponential'
+                max_depth INTEGER,
+                updated_at TEXT DEFAULT CURRENT_TIMESTAMP,
+                aggregator TEXT -- 'weighted_mean', 'weighted_median' or 'trimmed_mean'
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+
+        ensure_column(&pool, "domains", "aggregator", "TEXT").await?;
+        ensure_column(&pool, "domains", "agent_id_form", "TEXT").await?;
+        ensure_column(&pool, "domains", "discounting", "TEXT").await?; // JSON Discounting
+        ensure_column(&pool, "domains", "currency", "TEXT").await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS inflation_indexes (
+                currency TEXT PRIMARY KEY,
+                points TEXT NOT NULL, -- JSON array of {at, level}
+                updated_at TEXT DEFAULT CURRENT_TIMESTAMP
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS sharing_precision (
+                subject TEXT PRIMARY KEY, -- peer id or circle:<tag>
+                roi_step REAL,
+                bucket_volumes INTEGER NOT NULL DEFAULT 0,
+                updated_at TEXT DEFAULT CURRENT_TIMESTAMP
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS agent_claims (
+                id_domain TEXT NOT NULL,
+                agent_id TEXT NOT NULL,
+                challenge TEXT NOT NULL,
+                claimed_at TEXT NOT NULL,
+                proof_url TEXT,
+                verified_at TEXT,
+                PRIMARY KEY (id_domain, agent_id)
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+
+        // Kept apart from experiences so no score, aggregate or search ever sees an outcome that isn't known
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS pending_experiences (
+                id TEXT PRIMARY KEY,
+                id_domain TEXT NOT NULL,
+                agent_id TEXT NOT NULL,
+                invested_volume REAL NOT NULL,
+                timestamp TEXT NOT NULL,
+                notes TEXT,
+                data TEXT, -- JSON data from adapters
+                expected_return REAL,
+                template_id TEXT
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS experience_templates (
+                id TEXT PRIMARY KEY,
+                id_domain TEXT NOT NULL,
+                agent_id TEXT NOT NULL,
+                investment REAL NOT NULL,
+                expected_return REAL NOT NULL,
+                interval_days INTEGER NOT NULL,
+                next_due_at TEXT NOT NULL,
+                notes TEXT,
+                created_at TEXT NOT NULL
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+
+        // Not among RESTORED_TABLES: restoring data keeps what the user is watching
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS watchlist (
+                id_domain TEXT NOT NULL,
+                agent_id TEXT NOT NULL,
+                thresholds TEXT NOT NULL DEFAULT '[]', -- JSON array of expected ROI levels
+                min_delta REAL,
+                webhook TEXT,
+                last_expected_pv_roi REAL NOT NULL,
+                last_total_volume REAL NOT NULL,
+                last_data_points INTEGER NOT NULL,
+                added_at TEXT NOT NULL,
+                PRIMARY KEY (id_domain, agent_id)
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+
+        // Not among RESTORED_TABLES either: who the user blocked shouldn't come back with old data
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS peer_requests (
+                peer_id TEXT PRIMARY KEY,
+                status TEXT NOT NULL DEFAULT 'pending', -- 'pending', 'ignored' or 'blocked'
+                first_seen_at TEXT NOT NULL,
+                last_seen_at TEXT NOT NULL,
+                queries INTEGER NOT NULL DEFAULT 0
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+        ensure_column(&pool, "peer_requests", "proposal", "TEXT").await?; // JSON PeerProposal
+
+        // Peering messages waiting for the other node, not restored for the same reason as peer_requests
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS proposed_peers (
+                peer_id TEXT PRIMARY KEY,
+                peer TEXT NOT NULL, -- JSON Peer
+                proposal TEXT NOT NULL, -- JSON PeerProposal
+                proposed_at TEXT NOT NULL,
+                delivered INTEGER NOT NULL DEFAULT 0
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS peering_answers (
+                peer_id TEXT PRIMARY KEY,
+                accepted INTEGER NOT NULL,
+                reason TEXT,
+                answered_at TEXT NOT NULL
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+
+        // What the startup warm-up precomputes; not restored, it describes this node's own traffic
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS agent_queries (
+                id_domain TEXT NOT NULL,
+                agent_id TEXT NOT NULL,
+                queries INTEGER NOT NULL,
+                last_queried_at TEXT NOT NULL,
+                PRIMARY KEY (id_domain, agent_id)
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+
+        // Responses replayed to retried write requests; not restored, they only matter to this node's clients
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS idempotent_responses (
+                endpoint TEXT NOT NULL,
+                idempotency_key TEXT NOT NULL,
+                request_hash TEXT NOT NULL,
+                request_bytes INTEGER NOT NULL,
+                status INTEGER NOT NULL,
+                content_type TEXT,
+                body BLOB NOT NULL,
+                expires_at TEXT NOT NULL,
+                PRIMARY KEY (endpoint, idempotency_key)
+            )
+            "#
+        )
+        .execute(&pool)
+        .await?;
+
+        // A peer moved halfway, its old row gone but its history not, would lose that history
+        let mut tx = pool.begin().await?;
+        split_legacy_peer_ids(&mut tx).await?;
+        tx.commit().await?;
+        
+        // Connections opened while the schema was being built can keep reading it as it was,
+        // failing writes to tables created after them, so serve from fresh ones
+        if !in_memory {
+            pool.close().await;
+            pool = pool_options.connect_with(connect_options).await?;
+        }
+
+        Ok(Self { pool, clock: options.clock })
+    }
+}
+
+impl SqliteStorage {
+    async fn database_size_bytes(&self) -> StorageResult<u64> {
+        let size: i64 = sqlx::query_scalar(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()"
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(size as u64)
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    #[instrument(level = "debug", skip_all)]
+    async fn add_experience(&self, experience: TrustExperience) -> StorageResult<()> {
+        insert_experience(&self.pool, &experience).await
+    }
+
+    #[instrument(level = "debug", skip_all, fields(count = experiences.len()))]
+    async fn add_experiences(&self, experiences: Vec<TrustExperience>) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        for experience in &experiences {
+            insert_experience(&mut *tx, experience).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_experience(&self, experience_id: &str) -> StorageResult<Option<TrustExperience>> {
+        fetch_experience(&self.pool, experience_id).await
+    }
+
+    #[instrument(level = "debug", skip_all, fields(%id_domain, %agent_id))]
+    async fn get_experiences(&self, id_domain: &str, agent_id: &str) -> StorageResult<Vec<TrustExperience>> {
+        let rows = sqlx::query_as::<_, ExperienceRow>(
+            r#"
+            SELECT id, id_domain, agent_id, pv_roi, invested_volume, timestamp, notes, data
+            FROM experiences
+            WHERE id_domain = ?1 AND agent_id = ?2
+            ORDER BY timestamp DESC
+            "#
+        )
+        .bind(id_domain)
+        .bind(agent_id)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        let experiences = rows.into_iter().map(TrustExperience::from).collect();
+        
+        Ok(experiences)
+    }
+
+    async fn get_all_experiences(&self) -> StorageResult<Vec<TrustExperience>> {
+        let rows = sqlx::query_as::<_, ExperienceRow>(
+            r#"
+            SELECT id, id_domain, agent_id, pv_roi, invested_volume, timestamp, notes, data
+            FROM experiences
+            ORDER BY timestamp DESC
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        
+        let experiences = rows.into_iter().map(TrustExperience::from).collect();
+        
+        Ok(experiences)
+    }
+
+    fn stream_experiences(&self) -> BoxStream<'static, StorageResult<TrustExperience>> {
+        enum Cursor {
+            Start,
+            After { timestamp: String, id: String },
+            Done,
+        }
+
+        let pool = self.pool.clone();
+        // Keyset pagination keeps every query short, so the scan never holds a read
+        // transaction open for longer than one page
+        stream::try_unfold(Cursor::Start, move |cursor| {
+            let pool = pool.clone();
+            async move {
+                let rows = match &cursor {
+                    Cursor::Done => return Ok(None),
+                    Cursor::Start => {
+                        sqlx::query_as::<_, ExperienceRow>(
+                            r#"
+                            SELECT id, id_domain, agent_id, pv_roi, invested_volume, timestamp, notes, data
+                            FROM experiences
+                            ORDER BY timestamp DESC, id DESC
+                            LIMIT ?1
+                            "#
+                        )
+                        .bind(STREAM_PAGE_SIZE)
+                        .fetch_all(&pool)
+                        .await?
+                    }
+                    Cursor::After { timestamp, id } => {
+                        sqlx::query_as::<_, ExperienceRow>(
+                            r#"
+                            SELECT id, id_domain, agent_id, pv_roi, invested_volume, timestamp, notes, data
+                            FROM experiences
+                            WHERE (timestamp, id) < (?1, ?2)
+                            ORDER BY timestamp DESC, id DESC
+                            LIMIT ?3
+                            "#
+                        )
+                        .bind(timestamp)
+                        .bind(id)
+                        .bind(STREAM_PAGE_SIZE)
+                        .fetch_all(&pool)
+                        .await?
+                    }
+                };
+
+                let next = match rows.last() {
+                    Some(last) if rows.len() as i64 == STREAM_PAGE_SIZE => Cursor::After {
+                        timestamp: last.timestamp.clone(),
+                        id: last.id.clone(),
+                    },
+                    _ => Cursor::Done,
+                };
+                let page: Vec<TrustExperience> = rows.into_iter().map(TrustExperience::from).collect();
+                Ok::<_, StorageError>(Some((page, next)))
+            }
+        })
+        .map_ok(|page| stream::iter(page.into_iter().map(Ok)))
+        .try_flatten()
+        .boxed()
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn find_experiences(&self, filter: &ExperienceFilter) -> StorageResult<Vec<TrustExperience>> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT e.id, e.id_domain, e.agent_id, e.pv_roi, e.invested_volume, e.timestamp, e.notes, e.data"
+        );
+        push_experience_filter(&mut query, filter);
+
+        // Only a query with words joins the full-text index, so a blank one sorts by time like no query
+        let searching = filter.q.as_deref().is_some_and(|q| !fts_terms(q).is_empty());
+        // Timestamps are stored as UTC RFC 3339 strings, which sort chronologically
+        query.push(match (filter.sort, searching) {
+            (ExperienceSort::Relevance, true) => " ORDER BY bm25(experiences_fts), e.timestamp DESC",
+            (ExperienceSort::Newest | ExperienceSort::Relevance, _) => " ORDER BY e.timestamp DESC",
+            (ExperienceSort::Oldest, _) => " ORDER BY e.timestamp ASC",
+            (ExperienceSort::Volume, _) => " ORDER BY e.invested_volume DESC, e.timestamp DESC",
+            (ExperienceSort::Roi, _) => " ORDER BY e.pv_roi DESC, e.timestamp DESC",
+        });
+        // SQLite only accepts OFFSET after a LIMIT, -1 means unlimited
+        query.push(" LIMIT ").push_bind(filter.limit.map(i64::from).unwrap_or(-1));
+        if let Some(offset) = filter.offset {
+            query.push(" OFFSET ").push_bind(i64::from(offset));
+        }
+
+        let rows = query
+            .build_query_as::<ExperienceRow>()
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(TrustExperience::from).collect())
+    }
+
+    async fn count_experiences(&self, filter: &ExperienceFilter) -> StorageResult<u64> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT COUNT(*)");
+        push_experience_filter(&mut query, filter);
+        let count: i64 = query.build_query_scalar().fetch_one(&self.pool).await?;
+        Ok(count as u64)
+    }
+
+    async fn add_peer(&self, peer: Peer) -> StorageResult<()> {
+        let peer = peer.normalized();
+        // Check if peer already exists
+        let existing = sqlx::query("SELECT peer_id FROM peers WHERE peer_id = ?1")
+            .bind(&peer.peer_id)
             .fetch_optional(&self.pool)
             .await?;
-            
-        if existing.is_some() {
-            return Err(anyhow::anyhow!("{} is already in your list of peers", peer.name));
+            
+        if existing.is_some() {
+            return Err(StorageError::Duplicate(format!("{} is already in your list of peers", peer.name)));
+        }
+        
+        let mut tx = self.pool.begin().await?;
+        insert_peer(&mut *tx, &peer).await?;
+        record_quality_change(&mut *tx, &peer.peer_id, None, peer.recommender_quality, QUALITY_ADDED, self.clock.now()).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn add_peers(&self, peers: Vec<Peer>) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        let now = self.clock.now();
+        for peer in peers.into_iter().map(Peer::normalized) {
+            insert_peer(&mut *tx, &peer).await?;
+            record_quality_change(&mut *tx, &peer.peer_id, None, peer.recommender_quality, QUALITY_ADDED, now).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_peers(&self) -> StorageResult<Vec<Peer>> {
+        #[derive(sqlx::FromRow)]
+        struct PeerRow {
+            peer_id: String,
+            name: String,
+            recommender_quality: f64,
+            added_at: String,
+            notes: Option<String>,
+            tags: String,
+            contact_email: Option<String>,
+            contact_fediverse: Option<String>,
+            last_interaction_at: Option<String>,
+            addresses: String,
+            handle: Option<String>,
+        }
+        
+        let rows = sqlx::query_as::<_, PeerRow>(
+            r#"
+            SELECT peer_id, name, recommender_quality, added_at, notes, tags, contact_email, contact_fediverse,
+                   last_interaction_at, addresses, handle
+            FROM peers
+            ORDER BY added_at DESC
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        
+        let peers = rows
+            .into_iter()
+            .map(|row| Peer {
+                peer_id: row.peer_id,
+                addresses: serde_json::from_str(&row.addresses).unwrap_or_default(),
+                handle: row.handle,
+                name: row.name,
+                recommender_quality: row.recommender_quality,
+                added_at: DateTime::parse_from_rfc3339(&row.added_at).unwrap().with_timezone(&Utc),
+                notes: row.notes,
+                tags: serde_json::from_str(&row.tags).unwrap_or_default(),
+                contact: PeerContact {
+                    email: row.contact_email,
+                    fediverse: row.contact_fediverse,
+                },
+                last_interaction_at: row.last_interaction_at
+                    .and_then(|at| DateTime::parse_from_rfc3339(&at).ok())
+                    .map(|at| at.with_timezone(&Utc)),
+            })
+            .collect();
+        
+        Ok(peers)
+    }
+
+    async fn update_peer(&self, peer: &Peer) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        let previous_quality = fetch_peer_quality(&mut *tx, &peer.peer_id).await?;
+
+        sqlx::query(
+            r#"
+            UPDATE peers
+            SET name = ?1, recommender_quality = ?2, notes = ?3, tags = ?4,
+                contact_email = ?5, contact_fediverse = ?6, last_interaction_at = ?7, addresses = ?8, handle = ?9,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE peer_id = ?10
+            "#
+        )
+        .bind(&peer.name)
+        .bind(peer.recommender_quality)
+        .bind(&peer.notes)
+        .bind(serde_json::to_string(&peer.tags).unwrap_or_else(|_| "[]".to_string()))
+        .bind(&peer.contact.email)
+        .bind(&peer.contact.fediverse)
+        .bind(peer.last_interaction_at.map(|at| at.to_rfc3339()))
+        .bind(serde_json::to_string(&peer.addresses).unwrap_or_else(|_| "[]".to_string()))
+        .bind(&peer.handle)
+        .bind(&peer.peer_id)
+        .execute(&mut *tx)
+        .await?;
+
+        if previous_quality != peer.recommender_quality {
+            record_quality_change(
+                &mut *tx,
+                &peer.peer_id,
+                Some(previous_quality),
+                peer.recommender_quality,
+                QUALITY_EDITED,
+                self.clock.now(),
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn update_peer_quality(&self, peer_id: &str, quality: f64, reason: &str) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        let previous_quality = fetch_peer_quality(&mut *tx, peer_id).await?;
+
+        sqlx::query("UPDATE peers SET recommender_quality = ?1 WHERE peer_id = ?2")
+            .bind(quality)
+            .bind(peer_id)
+            .execute(&mut *tx)
+            .await?;
+
+        // Re-confirming the same quality is still worth a line in the audit trail
+        record_quality_change(&mut *tx, peer_id, Some(previous_quality), quality, reason, self.clock.now()).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_quality_history(&self, peer_id: &str) -> StorageResult<Vec<QualityRevision>> {
+        #[derive(sqlx::FromRow)]
+        struct QualityRow {
+            revision: i64,
+            peer_id: String,
+            previous_quality: Option<f64>,
+            quality: f64,
+            changed_at: String,
+            reason: String,
+        }
+
+        let rows = sqlx::query_as::<_, QualityRow>(
+            r#"
+            SELECT revision, peer_id, previous_quality, quality, changed_at, reason
+            FROM peer_quality_history
+            WHERE peer_id = ?1
+            ORDER BY revision DESC
+            "#
+        )
+        .bind(peer_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(QualityRevision {
+                    revision: row.revision,
+                    peer_id: row.peer_id,
+                    previous_quality: row.previous_quality,
+                    quality: row.quality,
+                    changed_at: DateTime::parse_from_rfc3339(&row.changed_at)
+                        .map_err(|e| StorageError::Corruption(e.to_string()))?
+                        .with_timezone(&Utc),
+                    reason: row.reason,
+                })
+            })
+            .collect()
+    }
+
+    async fn remove_peer(&self, peer_id: &str) -> StorageResult<()> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM peers WHERE peer_id = ?1
+            "#
+        )
+        .bind(peer_id)
+        .execute(&self.pool)
+        .await?;
+        
+        if result.rows_affected() == 0 {
+            return Err(StorageError::NotFound(format!("Unknown peer {}", peer_id)));
+        }
+        Ok(())
+    }
+
+    async fn rename_peer(&self, old_peer_id: &str, new_peer_id: &str) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        // Fails with Duplicate through the primary key if the new id is already a peer
+        let result = sqlx::query("UPDATE peers SET peer_id = ?1, updated_at = CURRENT_TIMESTAMP WHERE peer_id = ?2")
+            .bind(new_peer_id)
+            .bind(old_peer_id)
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(StorageError::NotFound(format!("Unknown peer {}", old_peer_id)));
+        }
+
+        sqlx::query("UPDATE peer_quality_history SET peer_id = ?1 WHERE peer_id = ?2")
+            .bind(new_peer_id)
+            .bind(old_peer_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE OR REPLACE cached_scores SET from_peer = ?1 WHERE from_peer = ?2")
+            .bind(new_peer_id)
+            .bind(old_peer_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE OR REPLACE compacted_scores SET from_peer = ?1 WHERE from_peer = ?2")
+            .bind(new_peer_id)
+            .bind(old_peer_id)
+            .execute(&mut *tx)
+            .await?;
+        // A recommendation still cached supersedes the folded one it may now share a peer with
+        sqlx::query(
+            r#"
+            DELETE FROM compacted_scores
+            WHERE from_peer = ?1 AND EXISTS (
+                SELECT 1 FROM cached_scores c
+                WHERE c.id_domain = compacted_scores.id_domain AND c.agent_id = compacted_scores.agent_id AND c.from_peer = ?1
+            )
+            "#
+        )
+        .bind(new_peer_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn clear_peers(&self) -> StorageResult<()> {
+        sqlx::query("DELETE FROM peers")
+            .execute(&self.pool)
+            .await?;
+        
+        Ok(())
+    }
+
+    async fn clear_experiences(&self) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM experiences")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM pending_experiences")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        
+        Ok(())
+    }
+
+    async fn update_experience(&self, experience: TrustExperience, changed_by: Option<&str>) -> StorageResult<()> {
+        let id = experience.id.to_string();
+        let mut tx = self.pool.begin().await?;
+
+        let previous = fetch_experience(&mut *tx, &id).await?
+            .ok_or_else(|| StorageError::NotFound(format!("Unknown experience {}", id)))?;
+        record_history(&mut *tx, &previous, ExperienceChange::Update, changed_by, self.clock.now()).await?;
+        write_experience(&mut *tx, &experience).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_experience(&self, experience_id: &str) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        delete_experience(&mut tx, experience_id, self.clock.now()).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn add_pending_experience(&self, pending: PendingExperience) -> StorageResult<()> {
+        // An id can't be pending once it has an outcome
+        if fetch_experience(&self.pool, &pending.id.to_string()).await?.is_some() {
+            return Err(StorageError::Duplicate(format!("Experience {} already exists", pending.id)));
+        }
+        insert_pending_experience(&self.pool, &pending).await
+    }
+
+    async fn get_pending_experiences(&self) -> StorageResult<Vec<PendingExperience>> {
+        #[derive(sqlx::FromRow)]
+        struct PendingRow {
+            id: String,
+            id_domain: String,
+            agent_id: String,
+            invested_volume: f64,
+            timestamp: String,
+            notes: Option<String>,
+            data: Option<String>,
+            expected_return: Option<f64>,
+            template_id: Option<String>,
+        }
+
+        let rows = sqlx::query_as::<_, PendingRow>(
+            r#"
+            SELECT id, id_domain, agent_id, invested_volume, timestamp, notes, data, expected_return, template_id
+            FROM pending_experiences
+            ORDER BY timestamp ASC
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(PendingExperience {
+                    id: Uuid::parse_str(&row.id).map_err(|e| StorageError::Corruption(e.to_string()))?,
+                    id_domain: row.id_domain,
+                    agent_id: row.agent_id,
+                    invested_volume: row.invested_volume,
+                    timestamp: DateTime::parse_from_rfc3339(&row.timestamp)
+                        .map_err(|e| StorageError::Corruption(e.to_string()))?
+                        .with_timezone(&Utc),
+                    notes: row.notes,
+                    data: row.data.and_then(|d| serde_json::from_str(&d).ok()),
+                    expected_return: row.expected_return,
+                    template_id: row.template_id.and_then(|id| Uuid::parse_str(&id).ok()),
+                })
+            })
+            .collect()
+    }
+
+    async fn settle_experience(&self, experience: TrustExperience) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        let removed = sqlx::query("DELETE FROM pending_experiences WHERE id = ?1")
+            .bind(experience.id.to_string())
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if removed == 0 {
+            return Err(StorageError::NotFound(format!("No pending experience {}", experience.id)));
+        }
+        insert_experience(&mut *tx, &experience).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_pending_experience(&self, experience_id: &str) -> StorageResult<()> {
+        let removed = sqlx::query("DELETE FROM pending_experiences WHERE id = ?1")
+            .bind(experience_id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        if removed == 0 {
+            return Err(StorageError::NotFound(format!("Unknown experience {}", experience_id)));
+        }
+        Ok(())
+    }
+
+    async fn add_experience_template(&self, template: &ExperienceTemplate) -> StorageResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO experience_templates
+                (id, id_domain, agent_id, investment, expected_return, interval_days, next_due_at, notes, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            "#
+        )
+        .bind(template.id.to_string())
+        .bind(&template.id_domain)
+        .bind(&template.agent_id)
+        .bind(template.investment)
+        .bind(template.expected_return)
+        .bind(template.interval_days as i64)
+        .bind(template.next_due_at.to_rfc3339())
+        .bind(&template.notes)
+        .bind(template.created_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_experience_templates(&self) -> StorageResult<Vec<ExperienceTemplate>> {
+        #[derive(sqlx::FromRow)]
+        struct TemplateRow {
+            id: String,
+            id_domain: String,
+            agent_id: String,
+            investment: f64,
+            expected_return: f64,
+            interval_days: i64,
+            next_due_at: String,
+            notes: Option<String>,
+            created_at: String,
+        }
+
+        let rows = sqlx::query_as::<_, TemplateRow>(
+            r#"
+            SELECT id, id_domain, agent_id, investment, expected_return, interval_days, next_due_at, notes, created_at
+            FROM experience_templates
+            ORDER BY next_due_at ASC
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let parse_time = |value: &str| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|e| StorageError::Corruption(e.to_string()))
+        };
+        rows.into_iter()
+            .map(|row| {
+                Ok(ExperienceTemplate {
+                    id: Uuid::parse_str(&row.id).map_err(|e| StorageError::Corruption(e.to_string()))?,
+                    id_domain: row.id_domain,
+                    agent_id: row.agent_id,
+                    investment: row.investment,
+                    expected_return: row.expected_return,
+                    interval_days: row.interval_days as u32,
+                    next_due_at: parse_time(&row.next_due_at)?,
+                    notes: row.notes,
+                    created_at: parse_time(&row.created_at)?,
+                })
+            })
+            .collect()
+    }
+
+    async fn remove_experience_template(&self, template_id: &str) -> StorageResult<()> {
+        let removed = sqlx::query("DELETE FROM experience_templates WHERE id = ?1")
+            .bind(template_id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        if removed == 0 {
+            return Err(StorageError::NotFound(format!("Unknown template {}", template_id)));
+        }
+        Ok(())
+    }
+
+    async fn record_template_occurrences(
+        &self,
+        template_id: &str,
+        occurrences: Vec<PendingExperience>,
+        next_due_at: DateTime<Utc>,
+    ) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        let updated = sqlx::query("UPDATE experience_templates SET next_due_at = ?2 WHERE id = ?1")
+            .bind(template_id)
+            .bind(next_due_at.to_rfc3339())
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if updated == 0 {
+            return Err(StorageError::NotFound(format!("Unknown template {}", template_id)));
+        }
+        for pending in &occurrences {
+            insert_pending_experience(&mut *tx, pending).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_experience_history(&self, experience_id: &str) -> StorageResult<Vec<ExperienceRevision>> {
+        let rows = sqlx::query_as::<_, RevisionRow>(
+            r#"
+            SELECT revision, experience_id, change, changed_at, changed_by, previous
+            FROM experience_history
+            WHERE experience_id = ?1
+            ORDER BY revision DESC
+            "#
+        )
+        .bind(experience_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(ExperienceRevision::try_from).collect()
+    }
+
+    async fn get_experience_deletions(&self) -> StorageResult<Vec<ExperienceRevision>> {
+        let rows = sqlx::query_as::<_, RevisionRow>(
+            r#"
+            SELECT revision, experience_id, change, changed_at, changed_by, previous
+            FROM experience_history
+            WHERE change = 'delete'
+            ORDER BY revision
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(ExperienceRevision::try_from).collect()
+    }
+
+    #[instrument(level = "debug", skip_all, fields(id_domain = %cached.id_domain, agent_id = %cached.agent_id))]
+    async fn cache_trust_score(&self, cached: CachedTrustScore) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO cached_scores 
+            (id_domain, agent_id, expected_pv_roi, total_volume, data_points, from_peer, cached_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            "#
+        )
+        .bind(&cached.id_domain)
+        .bind(&cached.agent_id)
+        .bind(cached.score.expected_pv_roi)
+        .bind(cached.score.total_volume)
+        .bind(cached.score.data_points as i64)
+        .bind(&cached.from_peer)
+        .bind(cached.cached_at.to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+        // A peer's score is everything it knows, so the new one replaces what was folded from it before
+        sqlx::query("DELETE FROM compacted_scores WHERE id_domain = ?1 AND agent_id = ?2 AND from_peer = ?3")
+            .bind(&cached.id_domain)
+            .bind(&cached.agent_id)
+            .bind(&cached.from_peer)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all, fields(%id_domain, %agent_id))]
+    async fn get_cached_scores(&self, id_domain: &str, agent_id: &str) -> StorageResult<Vec<CachedTrustScore>> {
+        let rows = sqlx::query_as::<_, CachedScoreRow>(
+            r#"
+            SELECT id_domain, agent_id, expected_pv_roi, total_volume, data_points, from_peer, cached_at
+            FROM cached_scores
+            WHERE id_domain = ?1 AND agent_id = ?2
+            UNION ALL
+            -- The folded recommendations of known peers, weighted by their current qualities
+            SELECT c.id_domain, c.agent_id,
+                   CASE WHEN SUM(c.total_volume * p.recommender_quality) > 0
+                        THEN SUM(c.expected_pv_roi * c.total_volume * p.recommender_quality) / SUM(c.total_volume * p.recommender_quality)
+                        ELSE 0.0 END,
+                   SUM(c.total_volume * p.recommender_quality), SUM(c.data_points), ?3, MAX(c.cached_at)
+            FROM compacted_scores c
+            JOIN peers p ON p.peer_id = c.from_peer
+            WHERE c.id_domain = ?1 AND c.agent_id = ?2
+            GROUP BY c.id_domain, c.agent_id
+            ORDER BY cached_at DESC
+            "#
+        )
+        .bind(id_domain)
+        .bind(agent_id)
+        .bind(COMPACTED_PEER)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(rows.into_iter().map(CachedTrustScore::from).collect())
+    }
+
+    async fn get_all_cached_scores(&self) -> StorageResult<Vec<CachedTrustScore>> {
+        let rows = sqlx::query_as::<_, CachedScoreRow>(
+            r#"
+            SELECT id_domain, agent_id, expected_pv_roi, total_volume, data_points, from_peer, cached_at
+            FROM cached_scores
+            UNION ALL
+            SELECT id_domain, agent_id, expected_pv_roi, total_volume, data_points, from_peer, cached_at
+            FROM compacted_scores
+            ORDER BY id_domain, agent_id
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(CachedTrustScore::from).collect())
+    }
+
+    async fn compact_cached_scores(&self, older_than: DateTime<Utc>) -> StorageResult<u64> {
+        let mut tx = self.pool.begin().await?;
+        // Recommendations of peers we no longer know are ignored at query time, so they're left alone
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO compacted_scores
+            (id_domain, agent_id, expected_pv_roi, total_volume, data_points, from_peer, cached_at)
+            SELECT id_domain, agent_id, expected_pv_roi, total_volume, data_points, from_peer, cached_at
+            FROM cached_scores
+            WHERE cached_at < ?1 AND from_peer IN (SELECT peer_id FROM peers)
+            "#
+        )
+        .bind(older_than.to_rfc3339())
+        .execute(&mut *tx)
+        .await?;
+        let folded = sqlx::query("DELETE FROM cached_scores WHERE cached_at < ?1 AND from_peer IN (SELECT peer_id FROM peers)")
+            .bind(older_than.to_rfc3339())
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        tx.commit().await?;
+        Ok(folded)
+    }
+
+    async fn get_experienced_agents(&self) -> StorageResult<Vec<AgentIdentifier>> {
+        let rows: Vec<(String, String)> =
+            sqlx::query_as("SELECT id_domain, agent_id FROM agent_aggregates WHERE data_points > 0 ORDER BY id_domain, agent_id")
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(rows.into_iter().map(|(id_domain, agent_id)| AgentIdentifier::new(id_domain, agent_id)).collect())
+    }
+
+    async fn get_experiences_page(&self, after: Option<(&str, &str)>, limit: u32) -> StorageResult<Vec<TrustExperience>> {
+        let rows = sqlx::query_as::<_, ExperienceRow>(
+            r#"
+            SELECT e.id, e.id_domain, e.agent_id, e.pv_roi, e.invested_volume, e.timestamp, e.notes, e.data
+            FROM (
+                SELECT id_domain, agent_id
+                FROM agent_aggregates
+                WHERE data_points > 0 AND (?1 IS NULL OR (id_domain, agent_id) > (?1, ?2))
+                ORDER BY id_domain, agent_id
+                LIMIT ?3
+            ) AS page
+            JOIN experiences e ON e.id_domain = page.id_domain AND e.agent_id = page.agent_id
+            ORDER BY e.id_domain, e.agent_id, e.timestamp DESC
+            "#
+        )
+        .bind(after.map(|(id_domain, _)| id_domain))
+        .bind(after.map(|(_, agent_id)| agent_id))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(TrustExperience::from).collect())
+    }
+
+    #[instrument(level = "debug", skip_all, fields(%id_domain, %agent_id))]
+    async fn get_agent_aggregate(&self, id_domain: &str, agent_id: &str) -> StorageResult<Option<TrustScore>> {
+        let row: Option<(f64, f64, i64)> = sqlx::query_as(
+            r#"
+            SELECT weighted_roi_sum, total_volume, data_points
+            FROM agent_aggregates
+            WHERE id_domain = ?1 AND agent_id = ?2
+            "#
+        )
+        .bind(id_domain)
+        .bind(agent_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(weighted_roi_sum, total_volume, data_points)| {
+            let (expected_pv_roi, total_volume) = if total_volume > 0.0 {
+                (weighted_roi_sum / total_volume, total_volume)
+            } else {
+                (1.0, 0.0)
+            };
+            TrustScore::new(expected_pv_roi, total_volume, data_points as usize)
+        }))
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn get_query_result(&self, cache_key: &str) -> StorageResult<Option<QueryResultEntry>> {
+        let row: Option<(String, String, String, f64, f64, i64, String)> = sqlx::query_as(
+            r#"
+            SELECT cache_key, id_domain, agent_id, expected_pv_roi, total_volume, data_points, calculated_at
+            FROM query_results
+            WHERE cache_key = ?1
+            "#
+        )
+        .bind(cache_key)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(cache_key, id_domain, agent_id, expected_pv_roi, total_volume, data_points, calculated_at)| {
+            QueryResultEntry {
+                cache_key,
+                id_domain,
+                agent_id,
+                score: TrustScore {
+                    expected_pv_roi,
+                    total_volume,
+                    data_points: data_points as usize,
+                },
+                calculated_at: DateTime::parse_from_rfc3339(&calculated_at).unwrap().with_timezone(&Utc),
+            }
+        }))
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn put_query_result(&self, entry: &QueryResultEntry) -> StorageResult<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO query_results
+            (cache_key, id_domain, agent_id, expected_pv_roi, total_volume, data_points, calculated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            "#
+        )
+        .bind(&entry.cache_key)
+        .bind(&entry.id_domain)
+        .bind(&entry.agent_id)
+        .bind(entry.score.expected_pv_roi)
+        .bind(entry.score.total_volume)
+        .bind(entry.score.data_points as i64)
+        .bind(entry.calculated_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn invalidate_query_results(&self, id_domain: &str, agent_id: &str) -> StorageResult<()> {
+        sqlx::query("DELETE FROM query_results WHERE id_domain = ?1 AND agent_id = ?2")
+            .bind(id_domain)
+            .bind(agent_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn purge_query_results(&self, older_than: Option<DateTime<Utc>>) -> StorageResult<u64> {
+        let result = match older_than {
+            Some(cutoff) => {
+                sqlx::query("DELETE FROM query_results WHERE calculated_at < ?1")
+                    .bind(cutoff.to_rfc3339())
+                    .execute(&self.pool)
+                    .await?
+            }
+            None => sqlx::query("DELETE FROM query_results").execute(&self.pool).await?,
+        };
+        Ok(result.rows_affected())
+    }
+
+    async fn record_agent_queries(&self, queries: &[AgentQueries]) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        for agent in queries {
+            sqlx::query(
+                r#"
+                INSERT INTO agent_queries (id_domain, agent_id, queries, last_queried_at)
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT(id_domain, agent_id) DO UPDATE SET queries = queries + ?3, last_queried_at = ?4
+                "#
+            )
+            .bind(&agent.id_domain)
+            .bind(&agent.agent_id)
+            .bind(agent.queries as i64)
+            .bind(agent.last_queried_at.to_rfc3339())
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_hot_agents(&self, limit: usize, now: DateTime<Utc>) -> StorageResult<Vec<AgentIdentifier>> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            r#"
+            SELECT id_domain, agent_id
+            FROM agent_queries
+            ORDER BY queries / (1.0 + MAX(julianday(?1) - julianday(last_queried_at), 0)) DESC, last_queried_at DESC
+            LIMIT ?2
+            "#
+        )
+        .bind(now.to_rfc3339())
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|(id_domain, agent_id)| AgentIdentifier::new(id_domain, agent_id)).collect())
+    }
+
+    async fn get_idempotent_response(&self, endpoint: &str, key: &str, now: DateTime<Utc>) -> StorageResult<Option<IdempotentResponse>> {
+        let row: Option<(String, i64, i64, Option<String>, Vec<u8>)> = sqlx::query_as(
+            r#"
+            SELECT request_hash, request_bytes, status, content_type, body
+            FROM idempotent_responses
+            WHERE endpoint = ?1 AND idempotency_key = ?2 AND expires_at > ?3
+            "#
+        )
+        .bind(endpoint)
+        .bind(key)
+        .bind(now.to_rfc3339())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(request_hash, request_bytes, status, content_type, body)| IdempotentResponse {
+            endpoint: endpoint.to_string(),
+            key: key.to_string(),
+            request_hash,
+            request_bytes: request_bytes as u64,
+            status: status as u16,
+            content_type,
+            body,
+        }))
+    }
+
+    async fn put_idempotent_response(&self, response: &IdempotentResponse, expires_at: DateTime<Utc>) -> StorageResult<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO idempotent_responses
+            (endpoint, idempotency_key, request_hash, request_bytes, status, content_type, body, expires_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            "#
+        )
+        .bind(&response.endpoint)
+        .bind(&response.key)
+        .bind(&response.request_hash)
+        .bind(response.request_bytes as i64)
+        .bind(response.status as i64)
+        .bind(&response.content_type)
+        .bind(&response.body)
+        .bind(expires_at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn purge_idempotent_responses(&self, now: DateTime<Utc>) -> StorageResult<u64> {
+        let result = sqlx::query("DELETE FROM idempotent_responses WHERE expires_at <= ?1")
+            .bind(now.to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    async fn get_domain_defaults(&self) -> StorageResult<Vec<DomainDefaults>> {
+        #[derive(sqlx::FromRow)]
+        struct DomainRow {
+            id_domain: String,
+            forget_rate: Option<f64>,
+            decay: Option<String>,
+            max_depth: Option<i64>,
+            aggregator: Option<String>,
+            agent_id_form: Option<String>,
+            discounting: Option<String>,
+            currency: Option<String>,
+        }
+
+        let rows = sqlx::query_as::<_, DomainRow>(
+            "SELECT id_domain, forget_rate, decay, max_depth, aggregator, agent_id_form, discounting, currency FROM domains ORDER BY id_domain"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|DomainRow { id_domain, forget_rate, decay, max_depth, aggregator, agent_id_form, discounting, currency }| {
+                let decay = decay
+                    .map(|d| d.parse::<DecayFunction>())
+                    .transpose()
+                    .map_err(StorageError::Corruption)?;
+                let aggregator = aggregator
+                    .map(|a| a.parse::<Aggregator>())
+                    .transpose()
+                    .map_err(StorageError::Corruption)?;
+                let agent_id_form = agent_id_form
+                    .map(|f| f.parse::<AgentIdForm>())
+                    .transpose()
+                    .map_err(StorageError::Corruption)?;
+                let discounting = discounting
+                    .map(|d| serde_json::from_str(&d))
+                    .transpose()
+                    .map_err(|e| StorageError::Corruption(e.to_string()))?;
+                Ok(DomainDefaults {
+                    id_domain,
+                    forget_rate,
+                    decay,
+                    max_depth: max_depth.map(|depth| depth.clamp(0, u8::MAX as i64) as u8),
+                    aggregator,
+                    agent_id_form,
+                    discounting,
+                    currency,
+                })
+            })
+            .collect()
+    }
+
+    async fn set_domain_defaults(&self, defaults: &DomainDefaults) -> StorageResult<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO domains
+                (id_domain, forget_rate, decay, max_depth, aggregator, agent_id_form, discounting, currency, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, CURRENT_TIMESTAMP)
+            "#
+        )
+        .bind(&defaults.id_domain)
+        .bind(defaults.forget_rate)
+        .bind(defaults.decay.map(DecayFunction::as_str))
+        .bind(defaults.max_depth.map(i64::from))
+        .bind(defaults.aggregator.map(Aggregator::as_str))
+        .bind(defaults.agent_id_form.map(AgentIdForm::as_str))
+        .bind(defaults.discounting.as_ref().map(|d| serde_json::to_string(d).unwrap_or_default()))
+        .bind(&defaults.currency)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_domain_defaults(&self, id_domain: &str) -> StorageResult<()> {
+        let result = sqlx::query("DELETE FROM domains WHERE id_domain = ?1")
+            .bind(id_domain)
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(StorageError::NotFound(format!("No defaults registered for domain {}", id_domain)));
+        }
+        Ok(())
+    }
+
+    async fn get_inflation_indexes(&self) -> StorageResult<Vec<InflationIndex>> {
+        let rows: Vec<(String, String)> = sqlx::query_as("SELECT currency, points FROM inflation_indexes ORDER BY currency")
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.into_iter()
+            .map(|(currency, points)| {
+                let points = serde_json::from_str(&points).map_err(|e| StorageError::Corruption(e.to_string()))?;
+                Ok(InflationIndex { currency, points })
+            })
+            .collect()
+    }
+
+    async fn set_inflation_index(&self, index: &InflationIndex) -> StorageResult<()> {
+        let points = serde_json::to_string(&index.points).map_err(|e| StorageError::Corruption(e.to_string()))?;
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO inflation_indexes (currency, points, updated_at)
+            VALUES (?1, ?2, CURRENT_TIMESTAMP)
+            "#
+        )
+        .bind(&index.currency)
+        .bind(points)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_inflation_index(&self, currency: &str) -> StorageResult<()> {
+        let result = sqlx::query("DELETE FROM inflation_indexes WHERE currency = ?1")
+            .bind(currency)
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(StorageError::NotFound(format!("No inflation index registered for {}", currency)));
         }
-        
+        Ok(())
+    }
+
+    async fn get_sharing_precision(&self) -> StorageResult<Vec<SharingPrecision>> {
+        let rows: Vec<(String, Option<f64>, bool)> =
+            sqlx::query_as("SELECT subject, roi_step, bucket_volumes FROM sharing_precision ORDER BY subject")
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(subject, roi_step, bucket_volumes)| SharingPrecision { subject, roi_step, bucket_volumes })
+            .collect())
+    }
+
+    async fn set_sharing_precision(&self, precision: &SharingPrecision) -> StorageResult<()> {
         sqlx::query(
             r#"
-            INSERT INTO peers (peer_id, name, recommender_quality, added_at)
-            VALUES (?1, ?2, ?3, ?4)
+            INSERT OR REPLACE INTO sharing_precision (subject, roi_step, bucket_volumes, updated_at)
+            VALUES (?1, ?2, ?3, CURRENT_TIMESTAMP)
             "#
         )
-        .bind(&peer.peer_id)
-        .bind(&peer.name)
-        .bind(peer.recommender_quality)
-        .bind(peer.added_at.to_rfc3339())
+        .bind(&precision.subject)
+        .bind(precision.roi_step)
+        .bind(precision.bucket_volumes)
         .execute(&self.pool)
         .await?;
-        
+
+        Ok(())
+    }
+
+    async fn remove_sharing_precision(&self, subject: &str) -> StorageResult<()> {
+        let result = sqlx::query("DELETE FROM sharing_precision WHERE subject = ?1")
+            .bind(subject)
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(StorageError::NotFound(format!("No precision set for {}", subject)));
+        }
+        Ok(())
+    }
+
+    async fn get_agent_claims(&self) -> StorageResult<Vec<AgentClaim>> {
+        let rows: Vec<AgentClaimRow> = sqlx::query_as(
+            "SELECT id_domain, agent_id, challenge, claimed_at, proof_url, verified_at FROM agent_claims ORDER BY id_domain, agent_id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(AgentClaim::try_from).collect()
+    }
+
+    async fn add_agent_claim(&self, claim: &AgentClaim) -> StorageResult<()> {
+        let result = sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO agent_claims (id_domain, agent_id, challenge, claimed_at, proof_url, verified_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            "#
+        )
+        .bind(&claim.id_domain)
+        .bind(&claim.agent_id)
+        .bind(&claim.challenge)
+        .bind(claim.claimed_at.to_rfc3339())
+        .bind(&claim.proof_url)
+        .bind(claim.verified_at.map(|at| at.to_rfc3339()))
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(StorageError::Duplicate(format!("{}/{} is already claimed", claim.id_domain, claim.agent_id)));
+        }
+        Ok(())
+    }
+
+    async fn verify_agent_claim(&self, id_domain: &str, agent_id: &str, proof_url: &str, at: DateTime<Utc>) -> StorageResult<()> {
+        let result = sqlx::query("UPDATE agent_claims SET proof_url = ?3, verified_at = ?4 WHERE id_domain = ?1 AND agent_id = ?2")
+            .bind(id_domain)
+            .bind(agent_id)
+            .bind(proof_url)
+            .bind(at.to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(StorageError::NotFound(format!("No claim on {}/{}", id_domain, agent_id)));
+        }
         Ok(())
     }
 
-    async fn get_peers(&self) -> Result<Vec<Peer>> {
+    async fn remove_agent_claim(&self, id_domain: &str, agent_id: &str) -> StorageResult<()> {
+        let result = sqlx::query("DELETE FROM agent_claims WHERE id_domain = ?1 AND agent_id = ?2")
+            .bind(id_domain)
+            .bind(agent_id)
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(StorageError::NotFound(format!("No claim on {}/{}", id_domain, agent_id)));
+        }
+        Ok(())
+    }
+
+    async fn get_watchlist(&self) -> StorageResult<Vec<WatchedAgent>> {
         #[derive(sqlx::FromRow)]
-        struct PeerRow {
-            peer_id: String,
-            name: String,
-            recommender_quality: f64,
+        struct WatchRow {
+            id_domain: String,
+            agent_id: String,
+            thresholds: String,
+            min_delta: Option<f64>,
+            webhook: Option<String>,
+            last_expected_pv_roi: f64,
+            last_total_volume: f64,
+            last_data_points: i64,
             added_at: String,
         }
-        
-        let rows = sqlx::query_as::<_, PeerRow>(
+
+        let rows = sqlx::query_as::<_, WatchRow>(
             r#"
-            SELECT peer_id, name, recommender_quality, added_at
-            FROM peers
-            ORDER BY added_at DESC
+            SELECT id_domain, agent_id, thresholds, min_delta, webhook,
+                   last_expected_pv_roi, last_total_volume, last_data_points, added_at
+            FROM watchlist
+            ORDER BY id_domain, agent_id
             "#
         )
         .fetch_all(&self.pool)
         .await?;
-        
-        let peers = rows
-            .into_iter()
-            .map(|row| Peer {
-                peer_id: row.peer_id,
-                name: row.name,
-                recommender_quality: row.recommender_quality,
-                added_at: DateTime::parse_from_rfc3339(&row.added_at).unwrap().with_timezone(&Utc),
+
+        rows.into_iter()
+            .map(|row| {
+                let added_at = DateTime::parse_from_rfc3339(&row.added_at)
+                    .map_err(|e| StorageError::Corruption(format!("watchlist added_at {}: {}", row.added_at, e)))?
+                    .with_timezone(&Utc);
+                Ok(WatchedAgent {
+                    id_domain: row.id_domain,
+                    agent_id: row.agent_id,
+                    thresholds: serde_json::from_str(&row.thresholds).unwrap_or_default(),
+                    min_delta: row.min_delta,
+                    webhook: row.webhook,
+                    last_score: TrustScore::new(row.last_expected_pv_roi, row.last_total_volume, row.last_data_points.max(0) as usize),
+                    added_at,
+                })
             })
-            .collect();
-        
-        Ok(peers)
+            .collect()
     }
 
-    async fn update_peer_quality(&self, peer_id: &str, quality: f64) -> Result<()> {
+    async fn watch_agent(&self, watched: &WatchedAgent) -> StorageResult<()> {
         sqlx::query(
             r#"
-            UPDATE peers SET recommender_quality = ?1 WHERE peer_id = ?2
+            INSERT OR REPLACE INTO watchlist (id_domain, agent_id, thresholds, min_delta, webhook,
+                last_expected_pv_roi, last_total_volume, last_data_points, added_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
             "#
         )
-        .bind(quality)
-        .bind(peer_id)
+        .bind(&watched.id_domain)
+        .bind(&watched.agent_id)
+        .bind(serde_json::to_string(&watched.thresholds).unwrap_or_else(|_| "[]".to_string()))
+        .bind(watched.min_delta)
+        .bind(&watched.webhook)
+        .bind(watched.last_score.expected_pv_roi)
+        .bind(watched.last_score.total_volume)
+        .bind(watched.last_score.data_points as i64)
+        .bind(watched.added_at.to_rfc3339())
         .execute(&self.pool)
         .await?;
-        
+
+        Ok(())
+    }
+
+    async fn unwatch_agent(&self, id_domain: &str, agent_id: &str) -> StorageResult<()> {
+        let result = sqlx::query("DELETE FROM watchlist WHERE id_domain = ?1 AND agent_id = ?2")
+            .bind(id_domain)
+            .bind(agent_id)
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(StorageError::NotFound(format!("Agent {}/{} is not watched", id_domain, agent_id)));
+        }
         Ok(())
     }
 
-    async fn remove_peer(&self, peer_id: &str) -> Result<()> {
+    async fn set_watched_score(&self, id_domain: &str, agent_id: &str, score: &TrustScore) -> StorageResult<()> {
         sqlx::query(
             r#"
-            DELETE FROM peers WHERE peer_id = ?1
+            UPDATE watchlist SET last_expected_pv_roi = ?3, last_total_volume = ?4, last_data_points = ?5
+            WHERE id_domain = ?1 AND agent_id = ?2
             "#
         )
-        .bind(peer_id)
+        .bind(id_domain)
+        .bind(agent_id)
+        .bind(score.expected_pv_roi)
+        .bind(score.total_volume)
+        .bind(score.data_points as i64)
         .execute(&self.pool)
         .await?;
-        
+
         Ok(())
     }
 
-    async fn clear_peers(&self) -> Result<()> {
-        sqlx::query("DELETE FROM peers")
-            .execute(&self.pool)
-            .await?;
-        
+    async fn get_peer_requests(&self) -> StorageResult<Vec<PeerRequest>> {
+        let rows: Vec<PeerRequestRow> = sqlx::query_as(
+            "SELECT peer_id, status, first_seen_at, last_seen_at, queries, proposal FROM peer_requests ORDER BY last_seen_at DESC, peer_id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(PeerRequest::try_from).collect()
+    }
+
+    async fn record_peer_request(&self, peer_id: &str, at: DateTime<Utc>) -> StorageResult<PeerRequest> {
+        let row: PeerRequestRow = sqlx::query_as(
+            r#"
+            INSERT INTO peer_requests (peer_id, first_seen_at, last_seen_at, queries)
+            VALUES (?1, ?2, ?2, 1)
+            ON CONFLICT(peer_id) DO UPDATE SET last_seen_at = ?2, queries = queries + 1
+            RETURNING peer_id, status, first_seen_at, last_seen_at, queries, proposal
+            "#
+        )
+        .bind(peer_id)
+        .bind(at.to_rfc3339())
+        .fetch_one(&self.pool)
+        .await?;
+
+        PeerRequest::try_from(row)
+    }
+
+    async fn record_peer_proposal(&self, peer_id: &str, proposal: &PeerProposal, at: DateTime<Utc>) -> StorageResult<PeerRequest> {
+        let proposal = serde_json::to_string(proposal).unwrap_or_else(|_| "{}".to_string());
+        let row: PeerRequestRow = sqlx::query_as(
+            r#"
+            INSERT INTO peer_requests (peer_id, first_seen_at, last_seen_at, proposal)
+            VALUES (?1, ?2, ?2, ?3)
+            ON CONFLICT(peer_id) DO UPDATE SET last_seen_at = ?2, proposal = ?3
+            RETURNING peer_id, status, first_seen_at, last_seen_at, queries, proposal
+            "#
+        )
+        .bind(peer_id)
+        .bind(at.to_rfc3339())
+        .bind(proposal)
+        .fetch_one(&self.pool)
+        .await?;
+
+        PeerRequest::try_from(row)
+    }
+
+    async fn set_peer_request_status(&self, peer_id: &str, status: PeerRequestStatus, at: DateTime<Utc>) -> StorageResult<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO peer_requests (peer_id, status, first_seen_at, last_seen_at)
+            VALUES (?1, ?2, ?3, ?3)
+            ON CONFLICT(peer_id) DO UPDATE SET status = ?2
+            "#
+        )
+        .bind(peer_id)
+        .bind(status.as_str())
+        .bind(at.to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
         Ok(())
     }
 
-    async fn clear_experiences(&self) -> Result<()> {
-        sqlx::query("DELETE FROM experiences")
+    async fn remove_peer_request(&self, peer_id: &str) -> StorageResult<()> {
+        let result = sqlx::query("DELETE FROM peer_requests WHERE peer_id = ?1")
+            .bind(peer_id)
             .execute(&self.pool)
             .await?;
-        
+
+        if result.rows_affected() == 0 {
+            return Err(StorageError::NotFound(format!("No peer request from {}", peer_id)));
+        }
         Ok(())
     }
 
-    async fn remove_experience(&self, experience_id: &str) -> Result<()> {
+    async fn get_proposed_peers(&self) -> StorageResult<Vec<ProposedPeer>> {
+        let rows: Vec<(String, String, String, bool)> =
+            sqlx::query_as("SELECT peer, proposal, proposed_at, delivered FROM proposed_peers ORDER BY proposed_at, peer_id")
+                .fetch_all(&self.pool)
+                .await?;
+
+        rows.into_iter()
+            .map(|(peer, proposal, proposed_at, delivered)| {
+                Ok(ProposedPeer {
+                    peer: serde_json::from_str(&peer).map_err(|e| StorageError::Corruption(format!("proposed peer: {}", e)))?,
+                    proposal: serde_json::from_str(&proposal).map_err(|e| StorageError::Corruption(format!("proposal: {}", e)))?,
+                    proposed_at: DateTime::parse_from_rfc3339(&proposed_at)
+                        .map(|time| time.with_timezone(&Utc))
+                        .map_err(|e| StorageError::Corruption(format!("proposal time {}: {}", proposed_at, e)))?,
+                    delivered,
+                })
+            })
+            .collect()
+    }
+
+    async fn put_proposed_peer(&self, proposed: &ProposedPeer) -> StorageResult<()> {
         sqlx::query(
             r#"
-            DELETE FROM experiences WHERE id = ?1
+            INSERT OR REPLACE INTO proposed_peers (peer_id, peer, proposal, proposed_at, delivered)
+            VALUES (?1, ?2, ?3, ?4, ?5)
             "#
         )
-        .bind(experience_id)
+        .bind(&proposed.peer.peer_id)
+        .bind(serde_json::to_string(&proposed.peer).unwrap_or_else(|_| "{}".to_string()))
+        .bind(serde_json::to_string(&proposed.proposal).unwrap_or_else(|_| "{}".to_string()))
+        .bind(proposed.proposed_at.to_rfc3339())
+        .bind(proposed.delivered)
         .execute(&self.pool)
         .await?;
-        
+
         Ok(())
     }
 
-    async fn cache_trust_score(&self, cached: CachedTrustScore) -> Result<()> {
+    async fn remove_proposed_peer(&self, peer_id: &str) -> StorageResult<()> {
+        sqlx::query("DELETE FROM proposed_peers WHERE peer_id = ?1").bind(peer_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn get_peering_answers(&self) -> StorageResult<Vec<PeeringAnswerOut>> {
+        let rows: Vec<(String, bool, Option<String>, String)> =
+            sqlx::query_as("SELECT peer_id, accepted, reason, answered_at FROM peering_answers ORDER BY answered_at, peer_id")
+                .fetch_all(&self.pool)
+                .await?;
+
+        rows.into_iter()
+            .map(|(peer_id, accepted, reason, answered_at)| {
+                Ok(PeeringAnswerOut {
+                    answered_at: DateTime::parse_from_rfc3339(&answered_at)
+                        .map(|time| time.with_timezone(&Utc))
+                        .map_err(|e| StorageError::Corruption(format!("answer time {}: {}", answered_at, e)))?,
+                    peer_id,
+                    accepted,
+                    reason,
+                })
+            })
+            .collect()
+    }
+
+    async fn put_peering_answer(&self, answer: &PeeringAnswerOut) -> StorageResult<()> {
         sqlx::query(
             r#"
-            INSERT OR REPLACE INTO cached_scores 
-            (id_domain, agent_id, expected_pv_roi, total_volume, data_points, from_peer, cached_at)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            INSERT OR REPLACE INTO peering_answers (peer_id, accepted, reason, answered_at)
+            VALUES (?1, ?2, ?3, ?4)
             "#
         )
-        .bind(&cached.id_domain)
-        .bind(&cached.agent_id)
-        .bind(cached.score.expected_pv_roi)
-        .bind(cached.score.total_volume)
-        .bind(cached.score.data_points as i64)
-        .bind(&cached.from_peer)
-        .bind(cached.cached_at.to_rfc3339())
+        .bind(&answer.peer_id)
+        .bind(answer.accepted)
+        .bind(&answer.reason)
+        .bind(answer.answered_at.to_rfc3339())
         .execute(&self.pool)
         .await?;
-        
+
         Ok(())
     }
 
-    async fn get_cached_scores(&self, id_domain: &str, agent_id: &str) -> Result<Vec<CachedTrustScore>> {
-        #[derive(sqlx::FromRow)]
-        struct CachedScoreRow {
-            id_domain: String,
-            agent_id: String,
-            expected_pv_roi: f64,
-            total_volume: f64,
-            data_points: i64,
-            from_peer: String,
-            cached_at: String,
-        }
-        
-        let rows = sqlx::query_as::<_, CachedScoreRow>(
-            r#"
-            SELECT id_domain, agent_id, expected_pv_roi, total_volume, data_points, from_peer, cached_at
-            FROM cached_scores
-            WHERE id_domain = ?1 AND agent_id = ?2
-            ORDER BY cached_at DESC
-            "#
+    async fn remove_peering_answer(&self, peer_id: &str) -> StorageResult<()> {
+        sqlx::query("DELETE FROM peering_answers WHERE peer_id = ?1").bind(peer_id).execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn storage_stats(&self) -> StorageResult<StorageStats> {
+        let (total_experiences, total_invested_volume, oldest, newest): (i64, f64, Option<String>, Option<String>) =
+            sqlx::query_as(
+                r#"
+                SELECT COUNT(*), COALESCE(SUM(invested_volume), 0.0), MIN(timestamp), MAX(timestamp)
+                FROM experiences
+                "#
+            )
+            .fetch_one(&self.pool)
+            .await?;
+
+        let experiences_per_domain: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT id_domain, COUNT(*) FROM experiences GROUP BY id_domain"
         )
-        .bind(id_domain)
-        .bind(agent_id)
         .fetch_all(&self.pool)
         .await?;
-        
-        Ok(rows
-            .into_iter()
-            .map(|row| CachedTrustScore {
-                id_domain: row.id_domain,
-                agent_id: row.agent_id,
-                score: TrustScore {
-                    expected_pv_roi: row.expected_pv_roi,
-                    total_volume: row.total_volume,
-                    data_points: row.data_points as usize,
-                },
-                from_peer: row.from_peer,
-                cached_at: DateTime::parse_from_rfc3339(&row.cached_at).unwrap().with_timezone(&Utc),
-            })
-            .collect())
+
+        let cached_scores_per_peer: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT from_peer, COUNT(*) FROM cached_scores GROUP BY from_peer"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let database_size_bytes = self.database_size_bytes().await?;
+
+        let parse_timestamp = |ts: Option<String>| {
+            ts.and_then(|ts| DateTime::parse_from_rfc3339(&ts).ok())
+                .map(|ts| ts.with_timezone(&Utc))
+        };
+
+        Ok(StorageStats {
+            total_experiences: total_experiences as u64,
+            experiences_per_domain: experiences_per_domain
+                .into_iter()
+                .map(|(domain, count)| (domain, count as u64))
+                .collect(),
+            total_invested_volume,
+            oldest_experience: parse_timestamp(oldest),
+            newest_experience: parse_timestamp(newest),
+            total_cached_scores: cached_scores_per_peer.iter().map(|(_, count)| *count as u64).sum(),
+            cached_scores_per_peer: cached_scores_per_peer
+                .into_iter()
+                .map(|(peer, count)| (peer, count as u64))
+                .collect(),
+            database_size_bytes,
+        })
+    }
+
+    async fn maintain(&self) -> StorageResult<MaintenanceReport> {
+        let started = std::time::Instant::now();
+        let size_before_bytes = self.database_size_bytes().await?;
+
+        let integrity_messages: Vec<String> = sqlx::query_scalar("PRAGMA integrity_check")
+            .fetch_all(&self.pool)
+            .await?;
+        let integrity_ok = integrity_messages.len() == 1 && integrity_messages[0] == "ok";
+
+        // Rewriting a corrupt database could make things worse, leave it for a restore
+        if integrity_ok {
+            sqlx::query("VACUUM").execute(&self.pool).await?;
+            // VACUUM may renumber the rowids the full-text index refers to
+            rebuild_fts(&self.pool).await?;
+            sqlx::query("ANALYZE").execute(&self.pool).await?;
+        }
+
+        Ok(MaintenanceReport {
+            integrity_ok,
+            integrity_messages,
+            vacuumed: integrity_ok,
+            analyzed: integrity_ok,
+            size_before_bytes,
+            size_after_bytes: self.database_size_bytes().await?,
+            duration_ms: started.elapsed().as_millis() as u64,
+        })
+    }
+
+    async fn backup_to(&self, path: &Path) -> StorageResult<()> {
+        // VACUUM INTO produces a consistent, compacted copy without blocking writers for long
+        sqlx::query("VACUUM INTO ?1")
+            .bind(path.to_string_lossy().to_string())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn restore_snapshot(&self, path: &Path) -> StorageResult<()> {
+        // ATTACH is per connection, so keep one connection for the whole restore
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("ATTACH DATABASE ?1 AS snapshot")
+            .bind(path.to_string_lossy().to_string())
+            .execute(&mut *conn)
+            .await?;
+
+        let result = copy_from_snapshot(&mut conn).await;
+
+        sqlx::query("DETACH DATABASE snapshot")
+            .execute(&mut *conn)
+            .await?;
+        result
+    }
+
+    async fn restore_export(&self, data: TrustDataExport) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM experiences").execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM peers").execute(&mut *tx).await?;
+        for experience in &data.experiences {
+            insert_experience(&mut *tx, experience).await?;
+        }
+        for peer in data.peers.into_iter().map(Peer::normalized) {
+            insert_peer(&mut *tx, &peer).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn import_records(
+        &self,
+        replaced_experiences: &[String],
+        experiences: Vec<TrustExperience>,
+        replaced_peers: &[String],
+        peers: Vec<Peer>,
+    ) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        let now = self.clock.now();
+
+        for experience_id in replaced_experiences {
+            let previous = fetch_experience(&mut *tx, experience_id).await?
+                .ok_or_else(|| StorageError::NotFound(format!("Unknown experience {}", experience_id)))?;
+            record_history(&mut *tx, &previous, ExperienceChange::Delete, None, now).await?;
+            sqlx::query("DELETE FROM experiences WHERE id = ?1").bind(experience_id).execute(&mut *tx).await?;
+        }
+        for experience in &experiences {
+            insert_experience(&mut *tx, experience).await?;
+        }
+
+        for peer_id in replaced_peers {
+            let result = sqlx::query("DELETE FROM peers WHERE peer_id = ?1").bind(peer_id).execute(&mut *tx).await?;
+            if result.rows_affected() == 0 {
+                return Err(StorageError::NotFound(format!("Unknown peer {}", peer_id)));
+            }
+        }
+        for peer in peers.into_iter().map(Peer::normalized) {
+            insert_peer(&mut *tx, &peer).await?;
+            record_quality_change(&mut *tx, &peer.peer_id, None, peer.recommender_quality, QUALITY_ADDED, now).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn merge_records(
+        &self,
+        added: Vec<TrustExperience>,
+        updated: Vec<TrustExperience>,
+        removed: &[String],
+        peers: Vec<Peer>,
+        changed_by: &str,
+    ) -> StorageResult<()> {
+        let mut tx = self.pool.begin().await?;
+        let now = self.clock.now();
+
+        for experience in &added {
+            insert_experience(&mut *tx, experience).await?;
+        }
+        for experience in &updated {
+            let id = experience.id.to_string();
+            let previous = fetch_experience(&mut *tx, &id).await?
+                .ok_or_else(|| StorageError::NotFound(format!("Unknown experience {}", id)))?;
+            record_history(&mut *tx, &previous, ExperienceChange::Update, Some(changed_by), now).await?;
+            write_experience(&mut *tx, experience).await?;
+        }
+        for experience_id in removed {
+            delete_experience(&mut tx, experience_id, now).await?;
+        }
+        for peer in peers.into_iter().map(Peer::normalized) {
+            insert_peer(&mut *tx, &peer).await?;
+            record_quality_change(&mut *tx, &peer.peer_id, None, peer.recommender_quality, QUALITY_ADDED, now).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
     }
 }
 
@@ -0,0 +1,221 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{Span, Subscriber};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+#[cfg(feature = "otlp")]
+pub use otlp::{otlp_layer, OtlpConfig, OtlpGuard};
+
+/// Tracing layer serving task and resource events to tokio-console at `addr`; it needs the runtime's own
+/// spans, so the log filter must only apply to the other layers
+#[cfg(feature = "console")]
+pub fn console_layer<S>(addr: std::net::SocketAddr) -> impl Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    console_subscriber::ConsoleLayer::builder().server_addr(addr).spawn()
+}
+
+/// How log lines are written to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line with the event's fields at the top level, e.g. peer_id, request_id
+    /// and agent, for Loki or Elasticsearch
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format {}", other)),
+        }
+    }
+}
+
+/// Log file that can be reopened after logrotate moved it away
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl LogFile {
+    pub fn open(path: &Path) -> io::Result<Arc<Self>> {
+        let file = Mutex::new(Self::append_to(path)?);
+        Ok(Arc::new(Self { path: path.to_path_buf(), file }))
+    }
+
+    fn append_to(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Continue in a fresh file at the same path
+    pub fn reopen(&self) -> io::Result<()> {
+        let file = Self::append_to(&self.path)?;
+        *self.file.lock().unwrap() = file;
+        Ok(())
+    }
+}
+
+impl Write for &LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.lock().unwrap().flush()
+    }
+}
+
+/// The log output layer for `format`, writing to `log_file` or else stdout
+pub fn fmt_layer<S>(format: LogFormat, log_file: Option<Arc<LogFile>>) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let text = tracing_subscriber::fmt::layer();
+    let json = || {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+    };
+    match (format, log_file) {
+        (LogFormat::Text, None) => Box::new(text),
+        (LogFormat::Text, Some(log_file)) => Box::new(text.with_ansi(false).with_writer(log_file)),
+        (LogFormat::Json, None) => Box::new(json()),
+        (LogFormat::Json, Some(log_file)) => Box::new(json().with_writer(log_file)),
+    }
+}
+
+/// W3C `traceparent` of `span`, to send along with a peer query so the peer's spans join the trace
+pub fn traceparent(span: &Span) -> Option<String> {
+    #[cfg(feature = "otlp")]
+    {
+        otlp::traceparent(span)
+    }
+    #[cfg(not(feature = "otlp"))]
+    {
+        let _ = span;
+        None
+    }
+}
+
+/// Make `span` a child of the remote span a peer query came from
+pub fn set_remote_parent(span: &Span, traceparent: Option<&str>) {
+    #[cfg(feature = "otlp")]
+    if let Some(traceparent) = traceparent {
+        otlp::set_remote_parent(span, traceparent);
+    }
+    #[cfg(not(feature = "otlp"))]
+    let _ = (span, traceparent);
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use anyhow::Result;
+    use opentelemetry::propagation::TextMapPropagator;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::{Config, Sampler, TracerProvider};
+    use opentelemetry_sdk::{runtime, Resource};
+    use std::collections::HashMap;
+    use tracing::{warn, Span, Subscriber};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
+
+    const TRACEPARENT: &str = "traceparent";
+
+    /// Where to send spans and how many traces to keep
+    #[derive(Debug, Clone)]
+    pub struct OtlpConfig {
+        /// gRPC endpoint of an OTLP collector, e.g. http://localhost:4317 for Jaeger or Tempo
+        pub endpoint: String,
+        /// Share of traces started here that are exported; traces started by a peer follow the peer's decision
+        pub sample_ratio: f64,
+        /// Reported as service.name, so nodes can be told apart in a cluster's traces
+        pub service_name: String,
+    }
+
+    /// Flushes spans that are still batched when dropped
+    pub struct OtlpGuard {
+        provider: TracerProvider,
+    }
+
+    impl Drop for OtlpGuard {
+        fn drop(&mut self) {
+            if let Err(e) = self.provider.shutdown() {
+                warn!("Failed to flush traces: {}", e);
+            }
+        }
+    }
+
+    /// Tracing layer exporting spans over OTLP; keep the guard alive until shutdown
+    pub fn otlp_layer<S>(config: &OtlpConfig) -> Result<(impl Layer<S>, OtlpGuard)>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+        let provider = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(config.endpoint.clone()))
+            .with_trace_config(
+                Config::default()
+                    .with_sampler(sampler)
+                    .with_resource(Resource::new(vec![KeyValue::new("service.name", config.service_name.clone())])),
+            )
+            .install_batch(runtime::Tokio)?;
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("trust-node"));
+        Ok((layer, OtlpGuard { provider }))
+    }
+
+    pub(super) fn traceparent(span: &Span) -> Option<String> {
+        let mut carrier = HashMap::new();
+        TraceContextPropagator::new().inject_context(&span.context(), &mut carrier);
+        carrier.remove(TRACEPARENT)
+    }
+
+    pub(super) fn set_remote_parent(span: &Span, traceparent: &str) {
+        let carrier = HashMap::from([(TRACEPARENT.to_string(), traceparent.to_string())]);
+        span.set_parent(TraceContextPropagator::new().extract(&carrier));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_log_file_reopens_after_rotation() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("node.log");
+        let log_file = LogFile::open(&path).unwrap();
+        writeln!(&*log_file, "before").unwrap();
+
+        std::fs::rename(&path, dir.path().join("node.log.1")).unwrap();
+        writeln!(&*log_file, "still old").unwrap();
+        log_file.reopen().unwrap();
+        writeln!(&*log_file, "after").unwrap();
+
+        assert_eq!(std::fs::read_to_string(dir.path().join("node.log.1")).unwrap(), "before\nstill old\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "after\n");
+    }
+}
\ No newline at end of file
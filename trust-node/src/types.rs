@@ -1,5 +1,7 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,6 +16,320 @@ pub struct TrustExperience {
     pub data: Option<serde_json::Value>, // Adapter-specific data (e.g., tx links, purchase info)
 }
 
+/// Partial edit of an experience; unset fields keep their current value
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExperienceUpdate {
+    pub id_domain: Option<String>,
+    pub agent_id: Option<String>,
+    pub pv_roi: Option<f64>,
+    pub invested_volume: Option<f64>,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub notes: Option<String>,
+    pub data: Option<serde_json::Value>,
+    /// Who made the change, recorded in the experience history
+    pub changed_by: Option<String>,
+}
+
+impl ExperienceUpdate {
+    pub fn apply_to(self, experience: &mut TrustExperience) {
+        if let Some(id_domain) = self.id_domain {
+            experience.id_domain = id_domain;
+        }
+        if let Some(agent_id) = self.agent_id {
+            experience.agent_id = agent_id;
+        }
+        if let Some(pv_roi) = self.pv_roi {
+            experience.pv_roi = pv_roi;
+        }
+        if let Some(invested_volume) = self.invested_volume {
+            experience.invested_volume = invested_volume;
+        }
+        if let Some(timestamp) = self.timestamp {
+            experience.timestamp = timestamp;
+        }
+        if let Some(notes) = self.notes {
+            experience.notes = Some(notes);
+        }
+        if let Some(data) = self.data {
+            experience.data = Some(data);
+        }
+    }
+}
+
+/// Discount rate applied when neither the request, the domain nor the node name one
+pub const DEFAULT_DISCOUNT_RATE: f64 = 0.05;
+
+/// How a return that comes back later is brought to its value at the time of the investment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum Discounting {
+    /// Compounded once a year: return / (1 + rate)^years
+    Annual { rate: f64 },
+    /// Compounded continuously: return * e^(-rate * years)
+    Continuous { rate: f64 },
+    /// Returns count at face value however long they took
+    Zero,
+    /// Compounded once a year at a rate that depends on how long the money was out, interpolated
+    /// linearly between the points and flat before the first and after the last
+    Curve { points: Vec<RatePoint> },
+}
+
+/// The annual rate for money out for `years`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RatePoint {
+    pub years: f64,
+    pub rate: f64,
+}
+
+impl Default for Discounting {
+    fn default() -> Self {
+        Discounting::Annual { rate: DEFAULT_DISCOUNT_RATE }
+    }
+}
+
+impl Discounting {
+    /// A model by the name used on the command line; curves only come from domains and requests
+    pub fn named(model: &str, rate: f64) -> Result<Self, String> {
+        match model {
+            "annual" => Ok(Discounting::Annual { rate }),
+            "continuous" => Ok(Discounting::Continuous { rate }),
+            "zero" => Ok(Discounting::Zero),
+            other => Err(format!("unknown discounting model {}, expected annual, continuous or zero", other)),
+        }
+    }
+
+    /// The same model at another rate; models without a single rate become annual compounding at it
+    pub fn with_rate(self, rate: f64) -> Self {
+        match self {
+            Discounting::Continuous { .. } => Discounting::Continuous { rate },
+            _ => Discounting::Annual { rate },
+        }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        let valid_rate = |rate: f64| rate.is_finite() && rate > -1.0;
+        let valid_point = |point: &RatePoint| point.years.is_finite() && point.years >= 0.0 && valid_rate(point.rate);
+        match self {
+            Discounting::Annual { rate } | Discounting::Continuous { rate } if !valid_rate(*rate) => {
+                Err(format!("discount rate must be a number above -1, got {}", rate))
+            }
+            Discounting::Curve { points } if points.is_empty() => Err("a rate curve needs at least one point".to_string()),
+            Discounting::Curve { points } if !points.iter().all(valid_point) => {
+                Err("curve points need years of at least 0 and rates above -1".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// What one unit returned after `years` is worth at the start
+    pub fn factor(&self, years: f64) -> f64 {
+        match self {
+            Discounting::Annual { rate } => (1.0 + rate).powf(-years),
+            Discounting::Continuous { rate } => (-rate * years).exp(),
+            Discounting::Zero => 1.0,
+            Discounting::Curve { points } => (1.0 + curve_rate(points, years)).powf(-years),
+        }
+    }
+
+    /// Present value of `return_value` after `timeframe_days`, per unit invested
+    pub fn pv_roi(&self, investment: f64, return_value: f64, timeframe_days: f64) -> f64 {
+        return_value * self.factor(timeframe_days / 365.0) / investment
+    }
+}
+
+fn curve_rate(points: &[RatePoint], years: f64) -> f64 {
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| a.years.total_cmp(&b.years));
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return DEFAULT_DISCOUNT_RATE;
+    };
+    if years <= first.years {
+        return first.rate;
+    }
+    if years >= last.years {
+        return last.rate;
+    }
+    points
+        .windows(2)
+        .find(|pair| years <= pair[1].years)
+        .map(|pair| {
+            let span = pair[1].years - pair[0].years;
+            let t = if span > 0.0 { (years - pair[0].years) / span } else { 1.0 };
+            pair[0].rate + t * (pair[1].rate - pair[0].rate)
+        })
+        .unwrap_or(last.rate)
+}
+
+/// An investment whose outcome isn't known yet; it counts toward no score until it is settled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingExperience {
+    pub id: Uuid,
+    pub id_domain: String,
+    pub agent_id: String,
+    pub invested_volume: f64,
+    /// When the investment was made
+    pub timestamp: DateTime<Utc>,
+    pub notes: Option<String>,
+    pub data: Option<serde_json::Value>,
+    /// Return assumed when the experience is settled without one, set for experiences from a template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_return: Option<f64>,
+    /// The recurring template that generated this experience
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template_id: Option<Uuid>,
+}
+
+impl PendingExperience {
+    /// The experience once `return_value` came back at `settled_at`, discounted over the time in between.
+    /// It keeps the id and the investment's timestamp, so it ages from when the money was put in.
+    pub fn settle(self, return_value: f64, discounting: &Discounting, settled_at: DateTime<Utc>) -> TrustExperience {
+        let timeframe_days = (settled_at - self.timestamp).num_seconds().max(0) as f64 / 86_400.0;
+        TrustExperience {
+            id: self.id,
+            id_domain: self.id_domain,
+            agent_id: self.agent_id,
+            pv_roi: discounting.pv_roi(self.invested_volume, return_value, timeframe_days),
+            invested_volume: self.invested_volume,
+            timestamp: self.timestamp,
+            notes: self.notes,
+            data: self.data,
+        }
+    }
+}
+
+/// Most occurrences a template catches up on in one run, so a long downtime doesn't flood the pending list
+pub const MAX_TEMPLATE_CATCH_UP: usize = 12;
+
+/// Longest interval between a template's occurrences
+pub const MAX_TEMPLATE_INTERVAL_DAYS: u32 = 3650;
+
+/// A recurring relationship, like a monthly subscription, that adds a pending experience every interval.
+/// Each one waits for confirmation before it counts, settling at `expected_return` unless told otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExperienceTemplate {
+    pub id: Uuid,
+    pub id_domain: String,
+    pub agent_id: String,
+    pub investment: f64,
+    pub expected_return: f64,
+    pub interval_days: u32,
+    pub next_due_at: DateTime<Utc>,
+    pub notes: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// When a template whose next occurrence would overflow the calendar is due, the last second of year 9999,
+/// as storage keeps times as RFC 3339 with four-digit years
+pub fn template_never_due() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(9999, 12, 31, 23, 59, 59).unwrap()
+}
+
+impl ExperienceTemplate {
+    /// Pending experiences for the occurrences due by `now`, and when the one after them is due
+    pub fn due(&self, now: DateTime<Utc>) -> (Vec<PendingExperience>, DateTime<Utc>) {
+        let interval = chrono::Duration::days(self.interval_days.clamp(1, MAX_TEMPLATE_INTERVAL_DAYS) as i64);
+        let mut next_due_at = self.next_due_at;
+        let mut occurrences = Vec::new();
+        while next_due_at <= now && occurrences.len() < MAX_TEMPLATE_CATCH_UP {
+            occurrences.push(PendingExperience {
+                id: Uuid::new_v4(),
+                id_domain: self.id_domain.clone(),
+                agent_id: self.agent_id.clone(),
+                invested_volume: self.investment,
+                timestamp: next_due_at,
+                notes: self.notes.clone(),
+                data: None,
+                expected_return: Some(self.expected_return),
+                template_id: Some(self.id),
+            });
+            // Past the last time RFC 3339 can write down, the template is simply never due again
+            let never = template_never_due();
+            match next_due_at.checked_add_signed(interval) {
+                Some(next) if next < never => next_due_at = next,
+                _ => {
+                    next_due_at = never;
+                    break;
+                }
+            }
+        }
+        (occurrences, next_due_at)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExperienceChange {
+    Update,
+    Delete,
+}
+
+impl ExperienceChange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExperienceChange::Update => "update",
+            ExperienceChange::Delete => "delete",
+        }
+    }
+}
+
+/// A prior version of an experience, kept whenever it is edited or deleted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExperienceRevision {
+    pub revision: i64,
+    pub experience_id: Uuid,
+    pub change: ExperienceChange,
+    pub changed_at: DateTime<Utc>,
+    pub changed_by: Option<String>,
+    /// The experience as it was before the change
+    pub previous: TrustExperience,
+}
+
+/// Criteria for selecting experiences; unset fields don't constrain the result
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExperienceFilter {
+    #[serde(alias = "domain")]
+    pub id_domain: Option<String>,
+    #[serde(alias = "agent")]
+    pub agent_id: Option<String>,
+    #[serde(alias = "from")]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(alias = "to")]
+    pub until: Option<DateTime<Utc>>,
+    pub min_volume: Option<f64>,
+    pub has_notes: Option<bool>,
+    pub has_data: Option<bool>,
+    /// Only experiences whose adapter data lists this tag in its `tags` array
+    pub tag: Option<String>,
+    /// Full-text search over notes and adapter data; every word must match, as a word or word prefix
+    pub q: Option<String>,
+    #[serde(default)]
+    pub sort: ExperienceSort,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+/// Order of experiences returned by a search
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExperienceSort {
+    #[default]
+    Newest,
+    Oldest,
+    /// Largest invested volume first
+    Volume,
+    /// Highest present value ROI first
+    Roi,
+    /// Best full-text match first; newest first without a `q`
+    Relevance,
+}
+
+/// A page of search results and how many experiences match in total
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExperiencePage {
+    pub experiences: Vec<TrustExperience>,
+    pub total: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TrustScore {
     pub expected_pv_roi: f64,
@@ -40,8 +356,13 @@ impl TrustScore {
     /// # Returns
     /// A new TrustScore representing the merged result
     pub fn merge_with(&self, other: &TrustScore, other_weight: f64) -> TrustScore {
+        self.merge_with_clamped(other, other_weight, &ScoreClamping::default())
+    }
+
+    /// Like `merge_with`, but capping the other score's weighted volume and clamping the ROIs as `clamping` says
+    pub fn merge_with_clamped(&self, other: &TrustScore, other_weight: f64, clamping: &ScoreClamping) -> TrustScore {
         let self_adjusted_volume = self.total_volume;
-        let other_adjusted_volume = other.total_volume * other_weight.abs();
+        let other_adjusted_volume = clamping.source_volume(other.total_volume, other_weight);
 
         if self_adjusted_volume == 0.0 && other_adjusted_volume == 0.0 {
             return TrustScore::default();
@@ -57,13 +378,14 @@ impl TrustScore {
         };
 
         let weighted_roi = if total_weight > 0.0 {
-            (self.expected_pv_roi * self_adjusted_volume + other_roi * other_adjusted_volume) / total_weight
+            (clamping.roi(self.expected_pv_roi) * self_adjusted_volume + clamping.roi(other_roi) * other_adjusted_volume)
+                / total_weight
         } else {
             1.0 // Default neutral ROI
         };
 
         TrustScore {
-            expected_pv_roi: weighted_roi,
+            expected_pv_roi: clamping.roi(weighted_roi),
             total_volume: total_weight,
             data_points: self.data_points + other.data_points,
         }
@@ -77,6 +399,11 @@ impl TrustScore {
     /// # Returns
     /// A new TrustScore representing the merged result
     pub fn merge_multiple(scores: Vec<(TrustScore, f64)>) -> TrustScore {
+        Self::merge_multiple_clamped(scores, &ScoreClamping::default())
+    }
+
+    /// Like `merge_multiple`, but clamping every source as `merge_with_clamped` does
+    pub fn merge_multiple_clamped(scores: Vec<(TrustScore, f64)>, clamping: &ScoreClamping) -> TrustScore {
         if scores.is_empty() {
             return TrustScore::default();
         }
@@ -86,130 +413,1901 @@ impl TrustScore {
         let (mut result, first_weight) = scores_iter.next().unwrap();
         
         // Apply weight to the first score
-        if first_weight != 1.0 {
-            result.total_volume *= first_weight.abs();
-            if first_weight < 0.0 {
-                result.expected_pv_roi = 2.0 - result.expected_pv_roi;
-            }
+        if first_weight < 0.0 {
+            result.expected_pv_roi = 2.0 - result.expected_pv_roi;
         }
+        result.total_volume = clamping.source_volume(result.total_volume, first_weight);
+        result.expected_pv_roi = clamping.roi(result.expected_pv_roi);
         
         // Merge remaining scores
         for (score, weight) in scores_iter {
-            result = result.merge_with(&score, weight);
+            result = result.merge_with_clamped(&score, weight, clamping);
         }
         result
     }
 
+    /// Like `merge_multiple_clamped`, but combining the ROIs with the given aggregator
+    pub fn merge_multiple_with(
+        scores: Vec<(TrustScore, f64)>,
+        aggregator: Aggregator,
+        clamping: &ScoreClamping,
+    ) -> TrustScore {
+        if aggregator == Aggregator::WeightedMean {
+            return Self::merge_multiple_clamped(scores, clamping);
+        }
+
+        let mut rois = Vec::with_capacity(scores.len());
+        let mut data_points = 0;
+        for (score, weight) in scores {
+            // Negative recommender quality inverts the ROI, as in merge_with
+            let roi = if weight < 0.0 { 2.0 - score.expected_pv_roi } else { score.expected_pv_roi };
+            rois.push((clamping.roi(roi), clamping.source_volume(score.total_volume, weight)));
+            data_points += score.data_points;
+        }
+
+        match aggregator.aggregate(&rois) {
+            Some((expected_pv_roi, total_volume)) => {
+                TrustScore::new(clamping.roi(expected_pv_roi), total_volume, data_points)
+            }
+            None => TrustScore::default(),
+        }
+    }
+
     /// Check if this trust score has any data
     pub fn has_data(&self) -> bool {
         self.data_points > 0 && self.total_volume > 0.0
     }
 }
 
+/// Path segments under /peers that are routes of their own, so no handle may take them
+const RESERVED_PEER_HANDLES: &[&str] = &["batch", "clear", "connected", "discover", "events", "invites", "redeem", "self"];
+
+/// Whether `handle` can name a peer: 1 to 32 lowercase letters, digits, '-' or '_', and not a route under /peers
+pub fn is_valid_peer_handle(handle: &str) -> bool {
+    (1..=32).contains(&handle.len())
+        && handle.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+        && !RESERVED_PEER_HANDLES.contains(&handle)
+}
+
+/// Whether `id` is usable as a query's correlation id: 1 to 64 letters, digits, '-' or '_', so it's safe to log
+pub fn is_valid_correlation_id(id: &str) -> bool {
+    (1..=64).contains(&id.len()) && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Split a multiaddr ending in /p2p/<id>, which older nodes stored as peer_id, into the bare id and the address
+pub fn split_peer_multiaddr(peer_id: &str) -> Option<(&str, &str)> {
+    peer_id.rsplit_once("/p2p/").map(|(address, id)| (id, address))
+}
+
+/// Most addresses kept per peer; addresses learned from identify beyond this replace ones that never worked
+pub const MAX_PEER_ADDRESSES: usize = 8;
+
+/// A multiaddr a peer can be dialed at, without the trailing /p2p/<id>, e.g. its home node, a VPS or a relay
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "PeerAddressRepr")]
+pub struct PeerAddress {
+    pub address: String,
+    /// When a connection to the peer was last established through this address
+    pub last_success_at: Option<DateTime<Utc>>,
+}
+
+impl PeerAddress {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self { address: address.into(), last_success_at: None }
+    }
+}
+
+/// Addresses may be sent as plain strings, as they were before success times were kept
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PeerAddressRepr {
+    Plain(String),
+    Full {
+        address: String,
+        #[serde(default)]
+        last_success_at: Option<DateTime<Utc>>,
+    },
+}
+
+impl From<PeerAddressRepr> for PeerAddress {
+    fn from(repr: PeerAddressRepr) -> Self {
+        match repr {
+            PeerAddressRepr::Plain(address) => PeerAddress::new(address),
+            PeerAddressRepr::Full { address, last_success_at } => PeerAddress { address, last_success_at },
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Peer {
+    /// The bare libp2p PeerId, as recommendations and cached scores name their sender
     pub peer_id: String,
+    /// Where to dial the peer; empty to find it through the DHT
+    #[serde(default)]
+    pub addresses: Vec<PeerAddress>,
+    /// Short unique name API paths can use instead of the PeerId, e.g. /peers/bob/quality
+    #[serde(default)]
+    pub handle: Option<String>,
     pub name: String,
     pub recommender_quality: f64,
     pub added_at: DateTime<Utc>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub contact: PeerContact,
+    /// When the peer last answered one of our queries or we last set its quality
+    #[serde(default)]
+    pub last_interaction_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TrustQuery {
-    pub agents: Vec<AgentIdentifier>,
-    pub max_depth: u8,
-    pub point_in_time: Option<DateTime<Utc>>,
-    pub forget_rate: Option<f64>,
-}
+impl Peer {
+    /// Move a legacy multiaddr peer_id like /ip4/1.2.3.4/tcp/9001/p2p/<id> into the bare id and an address
+    pub fn normalized(mut self) -> Self {
+        if let Some((id, address)) = split_peer_multiaddr(&self.peer_id) {
+            let (id, address) = (id.to_string(), address.to_string());
+            if !address.is_empty() {
+                self.learn_address(&address);
+            }
+            self.peer_id = id;
+        }
+        self
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct AgentIdentifier {
-    pub id_domain: String,
-    pub agent_id: String,
-}
+    /// Addresses to try when dialing, the most recently successful first; untried ones keep their order after those
+    pub fn dial_order(&self) -> Vec<&PeerAddress> {
+        let mut addresses: Vec<&PeerAddress> = self.addresses.iter().collect();
+        addresses.sort_by_key(|address| std::cmp::Reverse(address.last_success_at));
+        addresses
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TrustResponse {
-    pub scores: Vec<AgentScore>,
-    pub timestamp: DateTime<Utc>,
+    /// Add an address, e.g. one the peer announced, unless it's known or the list is full of ones that worked.
+    /// Returns whether the list changed.
+    pub fn learn_address(&mut self, address: &str) -> bool {
+        if self.addresses.iter().any(|known| known.address == address) {
+            return false;
+        }
+        if self.addresses.len() >= MAX_PEER_ADDRESSES {
+            let Some(untried) = self.addresses.iter().rposition(|known| known.last_success_at.is_none()) else {
+                return false;
+            };
+            self.addresses.remove(untried);
+        }
+        self.addresses.push(PeerAddress::new(address));
+        true
+    }
+
+    /// Note that a connection through `address` was just established, adding the address if it's new
+    pub fn record_address_success(&mut self, address: &str, at: DateTime<Utc>) {
+        if !self.addresses.iter().any(|known| known.address == address) && !self.learn_address(address) {
+            // Full of addresses that worked before: the one that worked longest ago makes room
+            if let Some((oldest, _)) = self.addresses.iter().enumerate().min_by_key(|(_, known)| known.last_success_at) {
+                self.addresses.remove(oldest);
+            }
+            self.addresses.push(PeerAddress::new(address));
+        }
+        if let Some(known) = self.addresses.iter_mut().find(|known| known.address == address) {
+            known.last_success_at = Some(at);
+        }
+    }
+
+    /// Last time we heard from or about the peer, counting its addition
+    pub fn last_interaction(&self) -> DateTime<Utc> {
+        self.last_interaction_at.unwrap_or(self.added_at)
+    }
 }
 
+/// One change of a peer's recommender_quality, kept so users can audit how their weighting evolved
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct AgentScore {
-    pub id_domain: String,
-    pub agent_id: String,
-    pub score: TrustScore,
+pub struct QualityRevision {
+    pub revision: i64,
+    pub peer_id: String,
+    /// None for the quality a peer was added with
+    pub previous_quality: Option<f64>,
+    pub quality: f64,
+    pub changed_at: DateTime<Utc>,
+    pub reason: String,
 }
 
-/// Cached trust score from a peer's recommendation
-/// 
-/// The key distinction between fields:
-/// - `id_domain` + `agent_id`: The entity being evaluated (e.g., domain="ethereum", agent_id="0x123")
-/// - `from_peer`: The peer who provided this trust score (e.g., PeerId of the recommending node)
-/// 
-/// Example: Alice (from_peer) recommends trust score for Bob's Ethereum address (id_domain="ethereum", agent_id="0x123")
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CachedTrustScore {
-    pub id_domain: String,    // The domain of the entity being evaluated
-    pub agent_id: String,     // The agent identifier within that domain
-    pub score: TrustScore,    // The trust score for this agent
-    pub from_peer: String,    // The peer who provided this recommendation
-    pub cached_at: DateTime<Utc>, // When this score was cached
+/// Lets a recommender's quality fade toward a neutral prior while we don't hear from them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityDecay {
+    /// Silence after which a peer's quality is halfway between what we set and the prior
+    pub half_life: std::time::Duration,
+    pub prior: f64,
 }
 
-impl TrustExperience {
-    pub fn aged_volume(&self, point_in_time: DateTime<Utc>, forget_rate: f64) -> f64 {
-        let years_elapsed = (point_in_time - self.timestamp).num_days() as f64 / 365.0;
-        let age_factor = (1.0 - years_elapsed.abs() * forget_rate).max(0.0);
-        self.invested_volume * age_factor
+impl QualityDecay {
+    /// The quality a peer's recommendations are weighted with at `now`
+    pub fn apply(&self, peer: &Peer, now: DateTime<Utc>) -> f64 {
+        let silent_secs = (now - peer.last_interaction()).num_seconds().max(0) as f64;
+        let half_life_secs = self.half_life.as_secs_f64();
+        if half_life_secs <= 0.0 {
+            return peer.recommender_quality;
+        }
+        let kept = 0.5f64.powf(silent_secs / half_life_secs);
+        self.prior + (peer.recommender_quality - self.prior) * kept
     }
 }
 
-impl Default for TrustScore {
+/// What a score from a peer may claim to be cached and merged, so a buggy or hostile peer can't poison
+/// the cache with NaNs or 1e300 volumes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreBounds {
+    pub min_roi: f64,
+    pub max_roi: f64,
+    pub max_volume: f64,
+    pub max_data_points: usize,
+}
+
+impl Default for ScoreBounds {
     fn default() -> Self {
         Self {
-            expected_pv_roi: 1.0,
-            total_volume: 0.0,
-            data_points: 0,
+            min_roi: -1e6,
+            max_roi: 1e6,
+            max_volume: 1e15,
+            max_data_points: 1_000_000_000,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TrustDataExport {
-    pub version: String,
-    pub exported_at: DateTime<Utc>,
-    pub experiences: Vec<TrustExperience>,
-    pub peers: Vec<Peer>,
+impl ScoreBounds {
+    /// Why the score is out of bounds, if it is
+    pub fn check(&self, score: &TrustScore) -> Result<(), String> {
+        let roi = score.expected_pv_roi;
+        let volume = score.total_volume;
+        if !roi.is_finite() || roi < self.min_roi || roi > self.max_roi {
+            return Err(format!("ROI {} is outside {}..={}", roi, self.min_roi, self.max_roi));
+        }
+        if !volume.is_finite() || volume < 0.0 || volume > self.max_volume {
+            return Err(format!("volume {} is outside 0..={}", volume, self.max_volume));
+        }
+        if score.data_points > self.max_data_points {
+            return Err(format!("{} data points are more than {}", score.data_points, self.max_data_points));
+        }
+        if volume > 0.0 && score.data_points == 0 {
+            return Err(format!("volume {} rests on no data points", volume));
+        }
+        Ok(())
+    }
+
+    /// Whether the score is one no node could have computed, rather than just outside what we accept
+    pub fn is_absurd(score: &TrustScore) -> bool {
+        !score.expected_pv_roi.is_finite() || !score.total_volume.is_finite() || score.total_volume < 0.0
+    }
 }
 
-impl TrustDataExport {
-    pub fn new(experiences: Vec<TrustExperience>, peers: Vec<Peer>) -> Self {
-        Self {
-            version: "1.0".to_string(),
-            exported_at: Utc::now(),
-            experiences,
-            peers,
+/// Range merged ROIs are clamped into and most effective volume one source may add to a merge, so a single
+/// extreme score pulls the result to the edge of the range instead of anywhere it likes; None leaves that side open
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScoreClamping {
+    pub min_roi: Option<f64>,
+    pub max_roi: Option<f64>,
+    /// Cap on a source's volume after weighting, own experiences included
+    pub max_source_volume: Option<f64>,
+}
+
+impl ScoreClamping {
+    pub fn roi(&self, roi: f64) -> f64 {
+        let roi = self.min_roi.map_or(roi, |min| roi.max(min));
+        self.max_roi.map_or(roi, |max| roi.min(max))
+    }
+
+    /// A source's volume once weighted by `weight` and capped
+    pub fn source_volume(&self, volume: f64, weight: f64) -> f64 {
+        let volume = volume * weight.abs();
+        self.max_source_volume.map_or(volume, |max| volume.min(max))
+    }
+}
+
+/// Limits on how much of an agent's merged volume one peer, or one circle of peers sharing a tag, may supply,
+/// so a farm of fake friends can't outvote everyone else. Shares are fractions of the volume before capping.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InfluenceCaps {
+    pub max_peer_share: Option<f64>,
+    pub max_circle_share: Option<f64>,
+}
+
+impl InfluenceCaps {
+    pub fn is_enabled(&self) -> bool {
+        self.max_peer_share.is_some() || self.max_circle_share.is_some()
+    }
+
+    /// Whether `share` can be a cap: a fraction of the merged volume above 0 and at most all of it
+    pub fn valid_share(share: f64) -> bool {
+        share > 0.0 && share <= 1.0
+    }
+
+    /// Scale factors for `contributions`, given as (peer, weighted volume) with None for own experiences,
+    /// which are never capped; `circles` maps peers to their tags. Shares are of the volume left after capping,
+    /// so a capped peer or circle ends up with at most its share of the merged score.
+    pub fn apply(
+        &self,
+        contributions: &[(Option<&str>, f64)],
+        circles: &HashMap<String, Vec<String>>,
+    ) -> (Vec<f64>, Vec<CappedInfluence>) {
+        let mut factors = vec![1.0; contributions.len()];
+        let total: f64 = contributions.iter().map(|(_, volume)| volume).sum();
+        if total <= 0.0 {
+            return (factors, Vec::new());
+        }
+        let in_circle = |peer: Option<&str>, circle: &str| {
+            peer.and_then(|peer| circles.get(peer)).is_some_and(|tags| tags.iter().any(|tag| tag == circle))
+        };
+
+        // Capping one group frees share for the others, so caps are applied until they all hold
+        let mut capped_peers = BTreeSet::new();
+        let mut capped_circles = BTreeSet::new();
+        for _ in 0..MAX_CAP_ROUNDS {
+            let mut changed = false;
+            if let Some(share) = self.max_peer_share {
+                for (peer, factor) in cap_peers(contributions, &factors, share) {
+                    for (i, _) in contributions.iter().enumerate().filter(|(_, (p, _))| *p == Some(peer)) {
+                        factors[i] *= factor;
+                    }
+                    capped_peers.insert(peer);
+                    changed = true;
+                }
+            }
+            if let Some(share) = self.max_circle_share {
+                let volumes: Vec<f64> = contributions.iter().zip(&factors).map(|((_, volume), factor)| volume * factor).collect();
+                let total: f64 = volumes.iter().sum();
+                let mut by_circle: BTreeMap<&str, f64> = BTreeMap::new();
+                for ((peer, _), volume) in contributions.iter().zip(&volumes) {
+                    for circle in peer.and_then(|peer| circles.get(peer)).into_iter().flatten() {
+                        *by_circle.entry(circle.as_str()).or_default() += volume;
+                    }
+                }
+                by_circle.retain(|_, volume| *volume > share * total * (1.0 + CAP_TOLERANCE));
+                // With nothing outside the oversized circles, capping them would only shrink everything toward zero
+                let outside: f64 = contributions
+                    .iter()
+                    .zip(&volumes)
+                    .filter(|((peer, _), _)| !by_circle.keys().any(|circle| in_circle(*peer, circle)))
+                    .map(|(_, volume)| volume)
+                    .sum();
+                if outside > 0.0 {
+                    // A peer in several oversized circles is scaled by the strictest of them
+                    let mut circle_factors = vec![1.0f64; contributions.len()];
+                    for (circle, volume) in &by_circle {
+                        let factor = share * total / volume;
+                        for (i, (peer, _)) in contributions.iter().enumerate() {
+                            if in_circle(*peer, circle) {
+                                circle_factors[i] = circle_factors[i].min(factor);
+                            }
+                        }
+                        capped_circles.insert(*circle);
+                        changed = true;
+                    }
+                    for (factor, circle_factor) in factors.iter_mut().zip(circle_factors) {
+                        *factor *= circle_factor;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let volume_of = |member: &dyn Fn(Option<&str>) -> bool| {
+            contributions.iter().zip(&factors).filter(|((peer, _), _)| member(*peer)).fold((0.0, 0.0), |(before, after), ((_, volume), factor)| {
+                (before + volume, after + volume * factor)
+            })
+        };
+        let mut capped = Vec::new();
+        for peer in capped_peers {
+            let (volume, capped_volume) = volume_of(&|p| p == Some(peer));
+            capped.push(CappedInfluence { source: peer.to_string(), volume, capped_volume });
         }
+        for circle in capped_circles {
+            let (volume, capped_volume) = volume_of(&|p| in_circle(p, circle));
+            capped.push(CappedInfluence { source: format!("circle:{}", circle), volume, capped_volume });
+        }
+        (factors, capped)
     }
 }
 
-impl AgentIdentifier {
-    pub fn new(id_domain: impl Into<String>, agent_id: impl Into<String>) -> Self {
-        Self {
-            id_domain: id_domain.into(),
-            agent_id: agent_id.into(),
+/// Rounds of capping peers and circles in turn before the caps are taken to hold
+const MAX_CAP_ROUNDS: usize = 16;
+/// Relative excess over a cap that is put down to rounding rather than capped again
+const CAP_TOLERANCE: f64 = 1e-9;
+
+/// Factors that bring each peer above `share` of the merged volume down to it, given the `factors` so far.
+/// Peers are capped to one common level, the highest that still holds once they are capped; with too few
+/// other sources for any level to hold, the capped peers are evened out at the smallest of them instead.
+fn cap_peers<'a>(contributions: &[(Option<&'a str>, f64)], factors: &[f64], share: f64) -> Vec<(&'a str, f64)> {
+    let mut by_peer: BTreeMap<&str, f64> = BTreeMap::new();
+    let mut total = 0.0;
+    for ((peer, volume), factor) in contributions.iter().zip(factors) {
+        if let Some(peer) = peer {
+            *by_peer.entry(*peer).or_default() += volume * factor;
         }
+        total += volume * factor;
+    }
+    let mut volumes: Vec<(&str, f64)> = by_peer.into_iter().collect();
+    volumes.sort_by(|a, b| b.1.total_cmp(&a.1));
+    if volumes.first().is_none_or(|(_, largest)| *largest <= share * total * (1.0 + CAP_TOLERANCE)) {
+        return Vec::new();
     }
 
+    // With the k largest peers capped at a level, it holds when level = share * (k * level + rest)
+    let mut rest = total;
+    let mut level = 0.0;
+    for k in 1..=volumes.len() {
+        rest -= volumes[k - 1].1;
+        let next = volumes.get(k).map_or(0.0, |(_, volume)| *volume);
+        if rest <= 0.0 || share * k as f64 >= 1.0 {
+            level = volumes[k - 1].1;
+            break;
+        }
+        level = share * rest / (1.0 - share * k as f64);
+        if level >= next {
+            break;
+        }
+    }
+    volumes
+        .into_iter()
+        .filter(|(_, volume)| *volume > level * (1.0 + CAP_TOLERANCE))
+        .map(|(peer, volume)| (peer, level / volume))
+        .collect()
 }
 
-impl AgentScore {
-    pub fn new(id_domain: impl Into<String>, agent_id: impl Into<String>, score: TrustScore) -> Self {
-        Self {
-            id_domain: id_domain.into(),
-            agent_id: agent_id.into(),
-            score,
+/// A peer or circle whose contribution to a score was cut down by the influence caps
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CappedInfluence {
+    /// A peer id, or `circle:<tag>` for the peers sharing a tag
+    pub source: String,
+    /// Weighted volume it would have contributed
+    pub volume: f64,
+    /// What it was allowed to contribute
+    pub capped_volume: f64,
+}
+
+/// A peer as listed by GET /peers, with the quality queries currently weight it with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerStatus {
+    #[serde(flatten)]
+    pub peer: Peer,
+    pub effective_quality: f64,
+}
+
+/// A peer that queried this node without being in its peer list, kept for the user to accept, ignore or block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerRequest {
+    pub peer_id: String,
+    pub status: PeerRequestStatus,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    /// Queries received from the peer while it wasn't in the peer list, counting at most one a minute
+    pub queries: u64,
+    /// Set when the peer asked to become mutual peers rather than just querying
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proposal: Option<PeerProposal>,
+}
+
+/// A node's ask to add each other as peers, with what it tells about itself
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerProposal {
+    /// How the proposer introduces themselves
+    pub name: Option<String>,
+    pub addresses: Vec<String>,
+    #[serde(default)]
+    pub contact: PeerContact,
+    /// Circles the proposer files us under, shown as a suggestion; the accepting user picks the circles
+    /// the proposer is filed under on our side
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+}
+
+/// A peering proposal we sent, kept until the other node answers it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProposedPeer {
+    /// The entry the other node gets in our peer list once it accepts
+    pub peer: Peer,
+    pub proposal: PeerProposal,
+    pub proposed_at: DateTime<Utc>,
+    /// Whether the other node confirmed receiving the proposal; until then it's sent again on every connection
+    pub delivered: bool,
+}
+
+/// Our answer to a peer's peering proposal, kept until the peer confirms receiving it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeeringAnswerOut {
+    pub peer_id: String,
+    pub accepted: bool,
+    pub reason: Option<String>,
+    pub answered_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerRequestStatus {
+    /// Waiting in the inbox; its queries are answered meanwhile
+    #[default]
+    Pending,
+    /// Out of the inbox, still answered
+    Ignored,
+    /// Its queries are answered empty
+    Blocked,
+}
+
+impl PeerRequestStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PeerRequestStatus::Pending => "pending",
+            PeerRequestStatus::Ignored => "ignored",
+            PeerRequestStatus::Blocked => "blocked",
+        }
+    }
+}
+
+impl std::str::FromStr for PeerRequestStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(PeerRequestStatus::Pending),
+            "ignored" => Ok(PeerRequestStatus::Ignored),
+            "blocked" => Ok(PeerRequestStatus::Blocked),
+            other => Err(format!("unknown peer request status {}", other)),
+        }
+    }
+}
+
+/// Optional ways to reach the person behind a peer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerContact {
+    pub email: Option<String>,
+    pub fediverse: Option<String>,
+}
+
+/// Partial edit of a peer, as sent to PATCH /peers/:peer_id; unset fields keep their current value
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeerUpdate {
+    pub name: Option<String>,
+    pub recommender_quality: Option<f64>,
+    pub notes: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub email: Option<String>,
+    pub fediverse: Option<String>,
+    pub addresses: Option<Vec<PeerAddress>>,
+    pub handle: Option<String>,
+}
+
+impl PeerUpdate {
+    pub fn apply_to(self, peer: &mut Peer) {
+        if let Some(name) = self.name {
+            peer.name = name;
+        }
+        if let Some(quality) = self.recommender_quality {
+            peer.recommender_quality = quality;
+        }
+        // An empty string clears the free-form fields
+        if let Some(notes) = self.notes {
+            peer.notes = Some(notes).filter(|n| !n.is_empty());
+        }
+        if let Some(tags) = self.tags {
+            peer.tags = tags;
+        }
+        // Addresses that stay keep when they last worked
+        if let Some(addresses) = self.addresses {
+            peer.addresses = addresses
+                .into_iter()
+                .map(|mut address| {
+                    if let Some(known) = peer.addresses.iter().find(|known| known.address == address.address) {
+                        address.last_success_at = address.last_success_at.or(known.last_success_at);
+                    }
+                    address
+                })
+                .collect();
+        }
+        if let Some(handle) = self.handle {
+            peer.handle = Some(handle).filter(|h| !h.is_empty());
+        }
+        if let Some(email) = self.email {
+            peer.contact.email = Some(email).filter(|e| !e.is_empty());
+        }
+        if let Some(fediverse) = self.fediverse {
+            peer.contact.fediverse = Some(fediverse).filter(|f| !f.is_empty());
+        }
+    }
+}
+
+/// Unset parameters fall back to the defaults registered for each agent's domain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustQuery {
+    pub agents: Vec<AgentIdentifier>,
+    #[serde(default)]
+    pub max_depth: Option<u8>,
+    pub point_in_time: Option<DateTime<Utc>>,
+    pub forget_rate: Option<f64>,
+    #[serde(default)]
+    pub decay: Option<DecayFunction>,
+    #[serde(default)]
+    pub aggregator: Option<Aggregator>,
+    /// Milliseconds the asker is willing to wait; when they run out the node answers with what it has
+    #[serde(default)]
+    pub budget_ms: Option<u64>,
+    /// Token from a truncated response; only agents after it are answered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continuation: Option<String>,
+    /// W3C trace context of the asking node's span, so one query can be followed across nodes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traceparent: Option<String>,
+    /// Shared by every hop of one query and logged on every node, so it can be followed through the friend graph;
+    /// the first node sets one if the asker didn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Answer with a breakdown of where the time went; passed on, so peers include theirs
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub timing: bool,
+    /// Recalculate instead of using cached scores, and ask peers afresh; passed on to them
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub refresh: bool,
+    /// Answer from own experiences and peers' cached answers, without asking any peer
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cache_only: bool,
+    /// Nodes neither answer nor forward the query after this time; passed on unchanged so it bounds the whole chain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+pub const DEFAULT_MAX_DEPTH: u8 = 3;
+
+/// How an experience loses weight with age, given a forget rate per year
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecayFunction {
+    /// Weight drops by forget_rate per year until it reaches zero
+    #[default]
+    Linear,
+    /// Weight shrinks by a factor of e^-forget_rate per year and never reaches zero
+    Exponential,
+}
+
+impl DecayFunction {
+    pub fn age_factor(self, years_elapsed: f64, forget_rate: f64) -> f64 {
+        match self {
+            DecayFunction::Linear => (1.0 - years_elapsed.abs() * forget_rate).max(0.0),
+            DecayFunction::Exponential => (-years_elapsed.abs() * forget_rate).exp(),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DecayFunction::Linear => "linear",
+            DecayFunction::Exponential => "exponential",
+        }
+    }
+}
+
+impl std::str::FromStr for DecayFunction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(DecayFunction::Linear),
+            "exponential" => Ok(DecayFunction::Exponential),
+            other => Err(format!("unknown decay function {}", other)),
+        }
+    }
+}
+
+/// Share of the total weight dropped from each tail by `Aggregator::TrimmedMean`
+pub const TRIM_FRACTION: f64 = 0.1;
+
+/// How ROIs of experiences or recommendations are combined, each weighted by its volume
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregator {
+    #[default]
+    WeightedMean,
+    /// The ROI with half of the weight on either side; a single huge volume can't drag it far
+    WeightedMedian,
+    /// Weighted mean after dropping TRIM_FRACTION of the weight from both ends
+    TrimmedMean,
+}
+
+impl Aggregator {
+    /// Combine (roi, weight) pairs into (roi, total weight); None if no pair has positive weight
+    pub fn aggregate(self, values: &[(f64, f64)]) -> Option<(f64, f64)> {
+        let mut values: Vec<(f64, f64)> = values.iter().copied().filter(|(_, weight)| *weight > 0.0).collect();
+        let total_weight: f64 = values.iter().map(|(_, weight)| weight).sum();
+        if values.is_empty() || total_weight <= 0.0 {
+            return None;
+        }
+
+        let roi = match self {
+            Aggregator::WeightedMean => {
+                values.iter().map(|(roi, weight)| roi * weight).sum::<f64>() / total_weight
+            }
+            Aggregator::WeightedMedian => {
+                values.sort_by(|a, b| a.0.total_cmp(&b.0));
+                let half = total_weight / 2.0;
+                let mut cumulative = 0.0;
+                values
+                    .iter()
+                    .find(|(_, weight)| {
+                        cumulative += weight;
+                        cumulative >= half
+                    })
+                    .map(|(roi, _)| *roi)
+                    .unwrap_or(values[values.len() - 1].0)
+            }
+            Aggregator::TrimmedMean => {
+                values.sort_by(|a, b| a.0.total_cmp(&b.0));
+                let (low, high) = (total_weight * TRIM_FRACTION, total_weight * (1.0 - TRIM_FRACTION));
+                let mut cumulative = 0.0;
+                let (mut kept_sum, mut kept_weight) = (0.0, 0.0);
+                for (roi, weight) in &values {
+                    // Keep the part of this value's weight that falls inside [low, high]
+                    let kept = (cumulative + weight).min(high) - cumulative.max(low);
+                    if kept > 0.0 {
+                        kept_sum += roi * kept;
+                        kept_weight += kept;
+                    }
+                    cumulative += weight;
+                }
+                if kept_weight > 0.0 { kept_sum / kept_weight } else { values[values.len() / 2].0 }
+            }
+        };
+
+        Some((roi, total_weight))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Aggregator::WeightedMean => "weighted_mean",
+            Aggregator::WeightedMedian => "weighted_median",
+            Aggregator::TrimmedMean => "trimmed_mean",
+        }
+    }
+}
+
+impl std::str::FromStr for Aggregator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "weighted_mean" => Ok(Aggregator::WeightedMean),
+            "weighted_median" => Ok(Aggregator::WeightedMedian),
+            "trimmed_mean" => Ok(Aggregator::TrimmedMean),
+            other => Err(format!("unknown aggregator {}", other)),
+        }
+    }
+}
+
+/// The notation a domain's agent ids are brought into before they are stored, looked up or sent to peers,
+/// so one agent doesn't end up split across spellings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentIdForm {
+    /// Only surrounding whitespace is removed
+    #[default]
+    Trimmed,
+    Lowercase,
+    /// 0x addresses lowercased, so EIP-55 checksummed and plain spellings match; other ids, and mixed-case
+    /// addresses whose checksum is wrong, only trimmed
+    Ethereum,
+    /// Host names lowercased, without a scheme, a leading `www.` or a trailing slash or dot
+    Hostname,
+}
+
+impl AgentIdForm {
+    /// The form used when the registry has none for the domain
+    pub fn for_domain(id_domain: &str) -> Self {
+        match id_domain {
+            "ethereum" => AgentIdForm::Ethereum,
+            "domain" => AgentIdForm::Hostname,
+            _ => AgentIdForm::Trimmed,
+        }
+    }
+
+    pub fn canonicalize(self, agent_id: &str) -> String {
+        let trimmed = agent_id.trim();
+        match self {
+            AgentIdForm::Trimmed => trimmed.to_string(),
+            AgentIdForm::Lowercase => trimmed.to_lowercase(),
+            AgentIdForm::Ethereum => match ethereum_address(trimmed) {
+                // A typo in a checksummed address must not file it under somebody else's
+                Some(address) if has_valid_checksum(address) => trimmed.to_ascii_lowercase(),
+                _ => trimmed.to_string(),
+            },
+            AgentIdForm::Hostname => {
+                let host = trimmed.to_lowercase();
+                let host = host.strip_prefix("https://").or_else(|| host.strip_prefix("http://")).unwrap_or(&host);
+                let host = host.strip_prefix("www.").unwrap_or(host);
+                host.trim_end_matches(['/', '.']).to_string()
+            }
+        }
+    }
+
+    /// Why `agent_id` can't be an id of this form, if it can't: an Ethereum address in mixed case has to
+    /// carry a valid EIP-55 checksum
+    pub fn check(self, agent_id: &str) -> Result<(), String> {
+        match self {
+            AgentIdForm::Ethereum => match ethereum_address(agent_id.trim()) {
+                Some(address) if !has_valid_checksum(address) => Err("has an invalid EIP-55 checksum".to_string()),
+                _ => Ok(()),
+            },
+            AgentIdForm::Trimmed | AgentIdForm::Lowercase | AgentIdForm::Hostname => Ok(()),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AgentIdForm::Trimmed => "trimmed",
+            AgentIdForm::Lowercase => "lowercase",
+            AgentIdForm::Ethereum => "ethereum",
+            AgentIdForm::Hostname => "hostname",
+        }
+    }
+}
+
+impl std::str::FromStr for AgentIdForm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trimmed" => Ok(AgentIdForm::Trimmed),
+            "lowercase" => Ok(AgentIdForm::Lowercase),
+            "ethereum" => Ok(AgentIdForm::Ethereum),
+            "hostname" => Ok(AgentIdForm::Hostname),
+            other => Err(format!("unknown agent id form {}", other)),
+        }
+    }
+}
+
+/// The 0x address in an Ethereum agent id, with or without an `ethereum:` prefix
+fn ethereum_address(agent_id: &str) -> Option<&str> {
+    let address = agent_id.strip_prefix("ethereum:").unwrap_or(agent_id);
+    let is_address = address.len() == 42 && address.starts_with("0x") && address[2..].chars().all(|c| c.is_ascii_hexdigit());
+    is_address.then_some(address)
+}
+
+/// Whether an address is all one case, which carries no checksum, or checksummed as EIP-55 has it: a letter is
+/// uppercase where the keccak-256 hash of the lowercase hex has a nibble of 8 or more
+fn has_valid_checksum(address: &str) -> bool {
+    let hex = &address[2..];
+    if hex == hex.to_ascii_lowercase() || hex == hex.to_ascii_uppercase() {
+        return true;
+    }
+    let hash = Keccak256::digest(hex.to_ascii_lowercase().as_bytes());
+    hex.chars().enumerate().all(|(i, c)| {
+        let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+        !c.is_ascii_alphabetic() || c.is_ascii_uppercase() == (nibble >= 8)
+    })
+}
+
+/// Price level of a currency over time, like a consumer price index; only ratios between dates matter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InflationIndex {
+    pub currency: String,
+    pub points: Vec<IndexPoint>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct IndexPoint {
+    pub at: DateTime<Utc>,
+    pub level: f64,
+}
+
+impl InflationIndex {
+    pub fn check(&self) -> Result<(), String> {
+        if self.points.is_empty() {
+            return Err("an inflation index needs at least one point".to_string());
+        }
+        if self.points.iter().any(|point| !point.level.is_finite() || point.level <= 0.0) {
+            return Err("index levels must be positive numbers".to_string());
+        }
+        Ok(())
+    }
+
+    /// The level at `at`, interpolated linearly between points and flat before the first and after the last
+    pub fn level_at(&self, at: DateTime<Utc>) -> f64 {
+        let mut points = self.points.clone();
+        points.sort_by_key(|point| point.at);
+        let (Some(first), Some(last)) = (points.first(), points.last()) else {
+            return 1.0;
+        };
+        if at <= first.at {
+            return first.level;
+        }
+        if at >= last.at {
+            return last.level;
+        }
+        points
+            .windows(2)
+            .find(|pair| at <= pair[1].at)
+            .map(|pair| {
+                let span = (pair[1].at - pair[0].at).num_seconds() as f64;
+                let t = if span > 0.0 { (at - pair[0].at).num_seconds() as f64 / span } else { 1.0 };
+                pair[0].level + t * (pair[1].level - pair[0].level)
+            })
+            .unwrap_or(last.level)
+    }
+
+    /// Factor turning an amount from `from` into money of `to`
+    pub fn adjustment(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+        self.level_at(to) / self.level_at(from)
+    }
+}
+
+/// How a single agent's experiences are turned into a score
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScoringOptions {
+    pub forget_rate: f64,
+    pub decay: DecayFunction,
+    pub aggregator: Aggregator,
+}
+
+impl ScoringOptions {
+    /// Fill in what a query leaves unset from the domain's registered defaults, then the built-in ones
+    pub fn resolve(
+        forget_rate: Option<f64>,
+        decay: Option<DecayFunction>,
+        aggregator: Option<Aggregator>,
+        defaults: Option<&DomainDefaults>,
+    ) -> Self {
+        Self {
+            forget_rate: forget_rate.or_else(|| defaults.and_then(|d| d.forget_rate)).unwrap_or(0.0),
+            decay: decay.or_else(|| defaults.and_then(|d| d.decay)).unwrap_or_default(),
+            aggregator: aggregator.or_else(|| defaults.and_then(|d| d.aggregator)).unwrap_or_default(),
+        }
+    }
+}
+
+/// Hypothetical changes to score an agent against; nothing in it is persisted
+#[derive(Debug, Clone, Default)]
+pub struct TrustSimulation {
+    pub id_domain: String,
+    pub agent_id: String,
+    /// Experiences scored as if they had been added
+    pub experiences: Vec<TrustExperience>,
+    /// Recommender qualities to use instead of the stored ones, by peer_id
+    pub peer_qualities: HashMap<String, f64>,
+    pub point_in_time: Option<DateTime<Utc>>,
+    pub forget_rate: Option<f64>,
+    pub decay: Option<DecayFunction>,
+    pub aggregator: Option<Aggregator>,
+}
+
+/// The agent's score from own experiences and cached recommendations, before and after the hypothetical changes
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulationResult {
+    pub current: AgentScore,
+    pub simulated: AgentScore,
+}
+
+/// Query parameters registered for a domain, used when a query leaves them unset
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DomainDefaults {
+    pub id_domain: String,
+    pub forget_rate: Option<f64>,
+    pub decay: Option<DecayFunction>,
+    pub max_depth: Option<u8>,
+    #[serde(default)]
+    pub aggregator: Option<Aggregator>,
+    /// Overrides AgentIdForm::for_domain
+    #[serde(default)]
+    pub agent_id_form: Option<AgentIdForm>,
+    /// Overrides the node's discounting for experiences added in this domain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discounting: Option<Discounting>,
+    /// Currency volumes in this domain are in; with an inflation index for it, old volumes are scored in today's terms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentIdentifier {
+    pub id_domain: String,
+    pub agent_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustResponse {
+    pub scores: Vec<AgentScore>,
+    pub timestamp: DateTime<Utc>,
+    /// Set when the answer didn't fit in one message; ask again with it as `continuation` for the rest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continuation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<WireError>,
+    /// Only when the query asked for `timing`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<QueryTiming>,
+}
+
+impl TrustResponse {
+    pub fn new(scores: Vec<AgentScore>) -> Self {
+        Self {
+            scores,
+            timestamp: Utc::now(),
+            continuation: None,
+            error: None,
+            timing: None,
+        }
+    }
+}
+
+/// Where one node spent the time answering a query, with the same breakdown nested for every peer it asked
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueryTiming {
+    /// Reading own experiences and cached recommendations
+    pub local_ms: u64,
+    /// Every peer asked, whether or not it answered in time
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub peers: Vec<PeerTiming>,
+    /// Combining the local scores with the peers' answers
+    pub merge_ms: u64,
+    /// From taking the query on to answering it
+    pub total_ms: u64,
+}
+
+/// Peers listed per level of a breakdown a peer sent, at most
+pub const MAX_TIMING_PEERS: usize = 64;
+
+impl QueryTiming {
+    /// This breakdown and at most `levels - 1` nested ones below it, each listing at most MAX_TIMING_PEERS peers,
+    /// so a peer can't make us hold or pass on more than an honest answer would have
+    pub fn bounded(mut self, levels: u8) -> Self {
+        self.peers.truncate(MAX_TIMING_PEERS);
+        for peer in &mut self.peers {
+            peer.timing = match (peer.timing.take(), levels > 1) {
+                (Some(timing), true) => Some(Box::new(timing.bounded(levels - 1))),
+                _ => None,
+            };
+        }
+        self
+    }
+
+    /// Leave out whom we and the peers below us asked, before the breakdown goes to a peer
+    pub fn anonymize(&mut self) {
+        for peer in &mut self.peers {
+            peer.peer_id = None;
+            if let Some(timing) = &mut peer.timing {
+                timing.anonymize();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerTiming {
+    /// None in a breakdown a peer sent, which doesn't say whom it asked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    /// From asking to the last page of the answer; None if the peer failed or missed its cutoff
+    pub latency_ms: Option<u64>,
+    /// The peer's own breakdown, if it sent one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<Box<QueryTiming>>,
+}
+
+/// Why a peer couldn't answer a query, sent instead of scores
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WireError {
+    /// Not even a single score fits in a response message
+    ResponseTooLarge { limit_bytes: usize },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentScore {
+    pub id_domain: String,
+    pub agent_id: String,
+    pub score: TrustScore,
+    /// Set when the score is backed by less evidence than the node's minimum; the ROI is then neutral
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub insufficient_data: bool,
+    /// Hops from the answering node to the closest evidence behind the score; 0 means its own experiences
+    #[serde(default)]
+    pub hops: u8,
+    /// How deep the answering node looked and how far the evidence it used came from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<ScoreDepth>,
+    #[serde(default)]
+    pub freshness: Freshness,
+    /// The EigenTrust-style aggregate over every rater this node knows, for comparison with the
+    /// personal score; only set while the global aggregation job runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global: Option<TrustScore>,
+    /// Peers and circles whose share of the volume was limited by the node's influence caps
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capped: Vec<CappedInfluence>,
+}
+
+/// The reach of a score, to check per-hop attenuation against
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScoreDepth {
+    /// The max_depth the answering node queried with, after domain defaults and limits
+    pub max_depth: u8,
+    /// Hops to the farthest evidence behind the score; `hops` is the closest
+    pub farthest_hops: u8,
+}
+
+/// Where the weighted volume behind a score came from, so clients can flag answers built on stale data
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Freshness {
+    /// From the answering node's own experiences
+    pub local_volume: f64,
+    /// From peers that answered this query
+    pub live_volume: f64,
+    /// From peer scores cached by earlier queries
+    pub cached_volume: f64,
+    /// When the oldest contributing cache entry was stored
+    pub oldest_cached_at: Option<DateTime<Utc>>,
+}
+
+impl Freshness {
+    fn reported_volume(&self) -> f64 {
+        self.local_volume + self.live_volume + self.cached_volume
+    }
+
+    /// Account for a peer's live answer, keeping the cached share the peer itself reported as cached
+    pub fn add_live(&mut self, volume: f64, reported: &Freshness) {
+        let cached_share = if reported.reported_volume() > 0.0 {
+            reported.cached_volume / reported.reported_volume()
+        } else {
+            0.0
+        };
+        self.live_volume += volume * (1.0 - cached_share);
+        self.cached_volume += volume * cached_share;
+        self.add_cache_time(reported.oldest_cached_at);
+    }
+
+    pub fn add_cached(&mut self, volume: f64, cached_at: DateTime<Utc>) {
+        self.cached_volume += volume;
+        self.add_cache_time(Some(cached_at));
+    }
+
+    fn add_cache_time(&mut self, cached_at: Option<DateTime<Utc>>) {
+        self.oldest_cached_at = match (self.oldest_cached_at, cached_at) {
+            (Some(oldest), Some(cached_at)) => Some(oldest.min(cached_at)),
+            (oldest, cached_at) => oldest.or(cached_at),
+        };
+    }
+}
+
+/// Least evidence a score needs before it is reported as a number
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MinEvidence {
+    pub data_points: usize,
+    pub total_volume: f64,
+}
+
+impl MinEvidence {
+    pub fn is_met_by(&self, score: &TrustScore) -> bool {
+        score.data_points >= self.data_points && score.total_volume >= self.total_volume
+    }
+}
+
+/// What peers learn about agents the node has little evidence on, so a friend can't read a single
+/// identifiable transaction out of an answer
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AnswerPrivacy {
+    /// Scores from fewer experiences are held back from peers; 0 answers everything
+    pub min_data_points: usize,
+    /// Send such scores with their volume bucketed instead of holding them back
+    pub bucket_below_min: bool,
+}
+
+impl AnswerPrivacy {
+    /// Hold back or blur the scores of an answer to a peer; returns how many were held back
+    pub fn apply(&self, scores: &mut Vec<AgentScore>) -> usize {
+        let before = scores.len();
+        if self.bucket_below_min {
+            for score in scores.iter_mut().filter(|score| score.score.data_points < self.min_data_points) {
+                bucket_volumes(score);
+            }
+        } else {
+            scores.retain(|score| score.score.data_points >= self.min_data_points);
+        }
+        before - scores.len()
+    }
+}
+
+/// An agent id the user says is theirs, e.g. their own address; peers are only asked about it once verified
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentClaim {
+    pub id_domain: String,
+    pub agent_id: String,
+    /// Published where only the agent's owner can, at the proof URL
+    pub challenge: String,
+    pub claimed_at: DateTime<Utc>,
+    pub proof_url: Option<String>,
+    pub verified_at: Option<DateTime<Utc>>,
+}
+
+impl AgentClaim {
+    pub fn is_verified(&self) -> bool {
+        self.verified_at.is_some()
+    }
+}
+
+/// How precise the scores shared with a peer, or a circle of peers sharing a tag, are
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SharingPrecision {
+    /// A peer id, or `circle:<tag>` for the peers sharing a tag
+    pub subject: String,
+    /// ROIs are rounded to a multiple of this; unset shares them as calculated
+    #[serde(default)]
+    pub roi_step: Option<f64>,
+    /// Round volumes to a step of the 1-2-5 series
+    #[serde(default)]
+    pub bucket_volumes: bool,
+}
+
+impl SharingPrecision {
+    pub fn check(&self) -> Result<(), String> {
+        if self.subject.is_empty() || self.subject == "circle:" {
+            return Err("a precision needs a peer id or circle:<tag>".to_string());
+        }
+        if self.roi_step.is_some_and(|step| !step.is_finite() || step <= 0.0) {
+            return Err("roi_step must be a positive number".to_string());
+        }
+        Ok(())
+    }
+
+    /// The precision a peer gets: its own if it has one, else the coarsest of its circles'; None shares full figures
+    pub fn for_peer(precisions: &[SharingPrecision], peer_id: &str, tags: &[String]) -> Option<SharingPrecision> {
+        if let Some(own) = precisions.iter().find(|precision| precision.subject == peer_id) {
+            return Some(own.clone());
+        }
+        precisions
+            .iter()
+            .filter(|precision| {
+                precision
+                    .subject
+                    .strip_prefix("circle:")
+                    .is_some_and(|circle| tags.iter().any(|tag| tag == circle))
+            })
+            .cloned()
+            .reduce(|coarsest, other| SharingPrecision {
+                subject: coarsest.subject,
+                roi_step: match (coarsest.roi_step, other.roi_step) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                },
+                bucket_volumes: coarsest.bucket_volumes || other.bucket_volumes,
+            })
+    }
+
+    pub fn apply(&self, score: &mut AgentScore) {
+        for figures in std::iter::once(&mut score.score).chain(score.global.as_mut()) {
+            if let Some(step) = self.roi_step {
+                figures.expected_pv_roi = (figures.expected_pv_roi / step).round() * step;
+            }
+        }
+        if self.bucket_volumes {
+            bucket_volumes(score);
+        }
+    }
+}
+
+/// Volumes are rounded to the closest step of this series, times a power of ten
+const VOLUME_STEPS: [f64; 3] = [1.0, 2.0, 5.0];
+
+/// Bucket every volume an answer carries, so none of them gives away the exact figure the others hide
+fn bucket_volumes(score: &mut AgentScore) {
+    for figures in std::iter::once(&mut score.score).chain(score.global.as_mut()) {
+        figures.total_volume = bucket_volume(figures.total_volume);
+    }
+    let freshness = &mut score.freshness;
+    for volume in [&mut freshness.local_volume, &mut freshness.live_volume, &mut freshness.cached_volume] {
+        *volume = bucket_volume(*volume);
+    }
+}
+
+/// The step of the 1-2-5 series closest to `volume` on a log scale, so weights keep their rough proportions
+pub fn bucket_volume(volume: f64) -> f64 {
+    if !volume.is_finite() || volume <= 0.0 {
+        return volume;
+    }
+    let magnitude = 10f64.powf(volume.log10().floor());
+    VOLUME_STEPS
+        .iter()
+        .chain(std::iter::once(&10.0))
+        .map(|step| step * magnitude)
+        .min_by(|a, b| (volume.ln() - a.ln()).abs().total_cmp(&(volume.ln() - b.ln()).abs()))
+        .unwrap_or(volume)
+}
+
+/// Bounds on what a single trust query may ask for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueryLimits {
+    pub max_agents: usize,
+    pub max_depth: u8,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        Self {
+            max_agents: 1000,
+            max_depth: 6,
+        }
+    }
+}
+
+impl QueryLimits {
+    /// Everything wrong with a query, so a client can fix it in one go
+    pub fn check(&self, query: &TrustQuery) -> Result<(), InvalidQuery> {
+        let mut violations = Vec::new();
+        if query.agents.len() > self.max_agents {
+            violations.push(format!("{} agents requested, at most {} are allowed per query", query.agents.len(), self.max_agents));
+        }
+        if let Some(depth) = query.max_depth.filter(|depth| *depth > self.max_depth) {
+            violations.push(format!("max_depth {} exceeds the limit of {}", depth, self.max_depth));
+        }
+        if query.refresh && query.cache_only {
+            violations.push("refresh and cache_only can't both be set".to_string());
+        }
+        for (index, agent) in query.agents.iter().enumerate() {
+            if agent.id_domain.trim().is_empty() {
+                violations.push(format!("agents[{}]: id_domain is empty", index));
+            }
+            if agent.agent_id.trim().is_empty() {
+                violations.push(format!("agents[{}]: agent_id is empty", index));
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidQuery { violations })
+        }
+    }
+}
+
+/// A trust query rejected by QueryLimits::check
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("invalid query: {}", .violations.join("; "))]
+pub struct InvalidQuery {
+    pub violations: Vec<String>,
+}
+
+/// Longest discovery interval; a week is plenty, and much longer ones overflow the timer
+pub const MAX_DISCOVERY_INTERVAL_SECS: u64 = 7 * 24 * 3600;
+
+/// Tunables that can be changed while the node runs, through GET/PATCH /config; changes last until restart
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuntimeConfig {
+    /// Seconds between Kademlia peer discovery rounds
+    pub discovery_interval_secs: u64,
+    /// Most peers asked per query, highest recommender quality first; 0 asks every connected peer
+    pub max_fanout: usize,
+    /// How long a calculated score is served from the cache
+    pub cache_ttl_secs: u64,
+    /// Peer queries whose local scores are gathered concurrently
+    pub inbound_workers: usize,
+    /// Queries one peer may have waiting; further ones are answered empty
+    pub max_queued_per_peer: usize,
+    pub max_query_agents: usize,
+    pub max_query_depth: u8,
+}
+
+/// Partial edit of the runtime config, as sent to PATCH /config; unset fields keep their current value
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RuntimeConfigUpdate {
+    pub discovery_interval_secs: Option<u64>,
+    pub max_fanout: Option<usize>,
+    pub cache_ttl_secs: Option<u64>,
+    pub inbound_workers: Option<usize>,
+    pub max_queued_per_peer: Option<usize>,
+    pub max_query_agents: Option<usize>,
+    pub max_query_depth: Option<u8>,
+}
+
+impl RuntimeConfigUpdate {
+    /// `config` with the update applied, or everything wrong with the update
+    pub fn apply_to(self, config: &RuntimeConfig) -> Result<RuntimeConfig, InvalidConfig> {
+        let updated = RuntimeConfig {
+            discovery_interval_secs: self.discovery_interval_secs.unwrap_or(config.discovery_interval_secs),
+            max_fanout: self.max_fanout.unwrap_or(config.max_fanout),
+            cache_ttl_secs: self.cache_ttl_secs.unwrap_or(config.cache_ttl_secs),
+            inbound_workers: self.inbound_workers.unwrap_or(config.inbound_workers),
+            max_queued_per_peer: self.max_queued_per_peer.unwrap_or(config.max_queued_per_peer),
+            max_query_agents: self.max_query_agents.unwrap_or(config.max_query_agents),
+            max_query_depth: self.max_query_depth.unwrap_or(config.max_query_depth),
+        };
+
+        let mut violations = Vec::new();
+        if updated.discovery_interval_secs == 0 {
+            violations.push("discovery_interval_secs must be at least 1".to_string());
+        }
+        if updated.discovery_interval_secs > MAX_DISCOVERY_INTERVAL_SECS {
+            violations.push(format!("discovery_interval_secs must be at most {}", MAX_DISCOVERY_INTERVAL_SECS));
+        }
+        if updated.inbound_workers == 0 {
+            violations.push("inbound_workers must be at least 1".to_string());
+        }
+        if updated.max_queued_per_peer == 0 {
+            violations.push("max_queued_per_peer must be at least 1".to_string());
+        }
+        if updated.max_query_agents == 0 {
+            violations.push("max_query_agents must be at least 1".to_string());
+        }
+        if updated.cache_ttl_secs > i64::MAX as u64 {
+            violations.push("cache_ttl_secs is too large".to_string());
+        }
+        if violations.is_empty() {
+            Ok(updated)
+        } else {
+            Err(InvalidConfig { violations })
+        }
+    }
+}
+
+/// A runtime config update rejected by RuntimeConfigUpdate::apply_to
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("invalid config: {}", .violations.join("; "))]
+pub struct InvalidConfig {
+    pub violations: Vec<String>,
+}
+
+/// What to watch an agent's merged score for, sent to PUT /watchlist/{id_domain}/{agent_id}
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WatchRequest {
+    /// Expected ROI levels that are announced whenever the score moves across them
+    #[serde(default)]
+    pub thresholds: Vec<f64>,
+    /// Announce any move of the expected ROI by at least this much since the last announcement
+    pub min_delta: Option<f64>,
+    /// URL every change of this agent is POSTed to, besides the score_changed event
+    pub webhook: Option<String>,
+}
+
+impl WatchRequest {
+    pub fn validate(&self) -> Result<(), InvalidWatch> {
+        let mut violations = Vec::new();
+        if self.thresholds.is_empty() && self.min_delta.is_none() {
+            violations.push("set thresholds, min_delta or both".to_string());
+        }
+        if self.thresholds.iter().any(|t| !t.is_finite()) {
+            violations.push("thresholds must be finite".to_string());
+        }
+        if let Some(min_delta) = self.min_delta {
+            if !(min_delta.is_finite() && min_delta > 0.0) {
+                violations.push("min_delta must be greater than 0".to_string());
+            }
+        }
+        if let Some(webhook) = &self.webhook {
+            if !(webhook.starts_with("http://") || webhook.starts_with("https://")) {
+                violations.push("webhook must be an http:// or https:// URL".to_string());
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidWatch { violations })
+        }
+    }
+}
+
+/// A watch request rejected by WatchRequest::validate
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("invalid watch: {}", .violations.join("; "))]
+pub struct InvalidWatch {
+    pub violations: Vec<String>,
+}
+
+/// An agent on the watchlist, with the merged score last announced for it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchedAgent {
+    pub id_domain: String,
+    pub agent_id: String,
+    pub thresholds: Vec<f64>,
+    pub min_delta: Option<f64>,
+    pub webhook: Option<String>,
+    pub last_score: TrustScore,
+    pub added_at: DateTime<Utc>,
+}
+
+impl WatchedAgent {
+    /// The change worth announcing when the merged score is now `current`, if there is one
+    pub fn change_to(&self, current: &TrustScore, at: DateTime<Utc>) -> Option<ScoreChange> {
+        let previous = self.last_score.expected_pv_roi;
+        let now = current.expected_pv_roi;
+        let crossed: Vec<f64> = self
+            .thresholds
+            .iter()
+            .copied()
+            .filter(|&threshold| (previous >= threshold) != (now >= threshold))
+            .collect();
+        let moved = self.min_delta.is_some_and(|min_delta| (now - previous).abs() >= min_delta);
+        if crossed.is_empty() && !moved {
+            return None;
+        }
+        Some(ScoreChange {
+            id_domain: self.id_domain.clone(),
+            agent_id: self.agent_id.clone(),
+            previous: self.last_score.clone(),
+            current: current.clone(),
+            crossed,
+            changed_at: at,
+        })
+    }
+}
+
+/// A watched agent's merged score moved across a threshold or by at least its min_delta
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoreChange {
+    pub id_domain: String,
+    pub agent_id: String,
+    pub previous: TrustScore,
+    pub current: TrustScore,
+    /// Thresholds between the previous and the current expected ROI
+    pub crossed: Vec<f64>,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Cached trust score from a peer's recommendation
+/// 
+/// The key distinction between fields:
+/// - `id_domain` + `agent_id`: The entity being evaluated (e.g., domain="ethereum", agent_id="0x123")
+/// - `from_peer`: The peer who provided this trust score (e.g., PeerId of the recommending node)
+/// 
+/// Example: Alice (from_peer) recommends trust score for Bob's Ethereum address (id_domain="ethereum", agent_id="0x123")
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedTrustScore {
+    pub id_domain: String,    // The domain of the entity being evaluated
+    pub agent_id: String,     // The agent identifier within that domain
+    pub score: TrustScore,    // The trust score for this agent
+    pub from_peer: String,    // The peer who provided this recommendation
+    pub cached_at: DateTime<Utc>, // When this score was cached
+}
+
+/// `from_peer` of the entry an agent's compacted recommendations are read back as; its volume
+/// already carries the recommenders' qualities, so it is weighted as a peer of quality 1
+pub const COMPACTED_PEER: &str = "compacted";
+
+/// A score computed by the QueryEngine from our own experiences, persisted so it survives restarts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryResultEntry {
+    pub cache_key: String,    // Agent plus point_in_time, forget_rate and decay, as used in memory
+    pub id_domain: String,
+    pub agent_id: String,
+    pub score: TrustScore,
+    pub calculated_at: DateTime<Utc>,
+}
+
+/// Queries counted for an agent since they were last written out
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgentQueries {
+    pub id_domain: String,
+    pub agent_id: String,
+    pub queries: u64,
+    pub last_queried_at: DateTime<Utc>,
+}
+
+/// The response to a write request sent with an Idempotency-Key, replayed when the request is retried
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdempotentResponse {
+    /// Method and versioned path, e.g. `POST /v1/experiences`; keys are only unique per endpoint
+    pub endpoint: String,
+    pub key: String,
+    /// SHA-256 of the query string and the body bytes the request was answered on, to tell a retry from key reuse
+    pub request_hash: String,
+    pub request_bytes: u64,
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl TrustExperience {
+    pub fn aged_volume(&self, point_in_time: DateTime<Utc>, forget_rate: f64) -> f64 {
+        self.decayed_volume(point_in_time, forget_rate, DecayFunction::Linear)
+    }
+
+    pub fn decayed_volume(&self, point_in_time: DateTime<Utc>, forget_rate: f64, decay: DecayFunction) -> f64 {
+        let years_elapsed = (point_in_time - self.timestamp).num_days() as f64 / 365.0;
+        self.invested_volume * decay.age_factor(years_elapsed, forget_rate)
+    }
+}
+
+impl Default for TrustScore {
+    fn default() -> Self {
+        Self {
+            expected_pv_roi: 1.0,
+            total_volume: 0.0,
+            data_points: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustDataExport {
+    pub version: String,
+    pub exported_at: DateTime<Utc>,
+    pub experiences: Vec<TrustExperience>,
+    pub peers: Vec<Peer>,
+    /// Set on snapshots, which only hold what was known then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub point_in_time: Option<DateTime<Utc>>,
+    /// Merged scores as of point_in_time; imports ignore them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scores: Vec<AgentScore>,
+}
+
+pub const EXPORT_FORMAT_VERSION: &str = "1.0";
+
+impl TrustDataExport {
+    pub fn new(experiences: Vec<TrustExperience>, peers: Vec<Peer>) -> Self {
+        Self {
+            version: EXPORT_FORMAT_VERSION.to_string(),
+            exported_at: Utc::now(),
+            experiences,
+            peers,
+            point_in_time: None,
+            scores: Vec::new(),
+        }
+    }
+}
+
+/// Aggregate figures about the local database, as returned by GET /stats/storage
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StorageStats {
+    pub total_experiences: u64,
+    pub experiences_per_domain: BTreeMap<String, u64>,
+    pub total_invested_volume: f64,
+    pub oldest_experience: Option<DateTime<Utc>>,
+    pub newest_experience: Option<DateTime<Utc>>,
+    pub total_cached_scores: u64,
+    pub cached_scores_per_peer: BTreeMap<String, u64>,
+    pub database_size_bytes: u64,
+}
+
+/// Query engine cache figures, as returned by GET /stats/cache
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CacheStats {
+    /// Lookups answered from memory or persisted results since the node started
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries in memory, including expired ones not yet cleaned up
+    pub entries: usize,
+    pub valid_entries: usize,
+    pub ttl_seconds: i64,
+}
+
+/// Queries waiting on peers and how they ended early, as returned by GET /stats/queries
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueryStats {
+    pub pending_queries: usize,
+    /// Answered early because too many queries were pending
+    pub evicted_for_capacity: u64,
+    /// Answered early because they had been pending for too long
+    pub evicted_for_age: u64,
+    pub budget_exhausted: u64,
+    /// Peers that didn't answer before their individual cutoff
+    pub peer_cutoffs_missed: u64,
+    /// Inbound queries dropped because their expires_at had passed
+    pub expired: u64,
+    /// Scores held back from peers for resting on too few experiences
+    pub withheld_from_peers: u64,
+    /// Peers not asked because their availability filter showed they know none of the agents
+    pub skipped_by_filter: u64,
+    /// Peers not asked because they recently answered without a score for any of the agents
+    pub skipped_no_data: u64,
+    /// Scores from peers dropped for being out of the node's score bounds
+    pub rejected_scores: u64,
+}
+
+/// How late the node loop got to its probe ticks; late ticks mean a handler held up the swarm and commands
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LoopLag {
+    pub ticks: u64,
+    pub last_ms: u64,
+    pub max_ms: u64,
+    /// Ticks handled 100ms or more late
+    pub stalls: u64,
+}
+
+/// Health of the async runtime and the node loop, as returned by GET /stats/runtime
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuntimeStats {
+    pub workers: usize,
+    pub alive_tasks: usize,
+    /// Tasks waiting for a free worker
+    pub global_queue_depth: usize,
+    /// Workers busy for nearly all of the last probe period, e.g. in a blocking call
+    pub blocked_workers: usize,
+    pub worker_busy_ms: Vec<u64>,
+    pub worker_parks: Vec<u64>,
+    /// Only measured in builds with RUSTFLAGS="--cfg tokio_unstable"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker_mean_poll_us: Option<Vec<u64>>,
+    pub event_loop: LoopLag,
+}
+
+/// Trust queries and answers exchanged with one peer since the node started
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerTraffic {
+    pub queries_received: u64,
+    pub queries_sent: u64,
+    pub answers_sent: u64,
+    pub answers_received: u64,
+    pub scores_sent: u64,
+    pub scores_received: u64,
+    /// Wire size of the queries and answers going each way
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl PeerTraffic {
+    /// Queries the peer sent us per query we sent it; None until we've asked it something
+    pub fn query_ratio(&self) -> Option<f64> {
+        (self.queries_sent > 0).then(|| self.queries_received as f64 / self.queries_sent as f64)
+    }
+}
+
+/// One peer's side of the traffic, as listed by GET /stats/reciprocity
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerReciprocity {
+    pub peer_id: String,
+    /// Unset for peers outside the peer list
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub traffic: PeerTraffic,
+    pub query_ratio: Option<f64>,
+}
+
+impl PeerReciprocity {
+    /// Peers that take the most answers for what they give first
+    pub fn list(traffic: &HashMap<String, PeerTraffic>, peers: &HashMap<String, Peer>) -> Vec<PeerReciprocity> {
+        let mut list: Vec<PeerReciprocity> = traffic
+            .iter()
+            .map(|(peer_id, traffic)| PeerReciprocity {
+                peer_id: peer_id.clone(),
+                name: peers.get(peer_id).map(|peer| peer.name.clone()),
+                traffic: traffic.clone(),
+                query_ratio: traffic.query_ratio(),
+            })
+            .collect();
+        let taken = |entry: &PeerReciprocity| entry.traffic.bytes_sent as i64 - entry.traffic.bytes_received as i64;
+        list.sort_by(|a, b| taken(b).cmp(&taken(a)).then_with(|| a.peer_id.cmp(&b.peer_id)));
+        list
+    }
+}
+
+/// Result of a VACUUM / ANALYZE / integrity_check run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceReport {
+    pub integrity_ok: bool,
+    /// Rows reported by PRAGMA integrity_check, just "ok" for a healthy database
+    pub integrity_messages: Vec<String>,
+    pub vacuumed: bool,
+    pub analyzed: bool,
+    pub size_before_bytes: u64,
+    pub size_after_bytes: u64,
+    pub duration_ms: u64,
+}
+
+/// How an import resolves records that already exist locally
+///
+/// Experiences are matched by their id, peers by their peer_id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImportStrategy {
+    /// Keep the local record, ignore the imported one
+    #[default]
+    SkipDuplicates,
+    /// Replace the local record if the imported one is newer
+    NewerWins,
+    /// Skip duplicate experiences; take names of known peers from the import but keep my recommender quality
+    MergePeersKeepMyQuality,
+    /// Always replace the local record
+    Overwrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportRecordKind {
+    Experience,
+    Peer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportOutcome {
+    Added,
+    Replaced,
+    Merged,
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportRecordResult {
+    pub kind: ImportRecordKind,
+    pub id: String,
+    pub outcome: ImportOutcome,
+}
+
+/// Outcome of an import, or of a dry-run preview of one
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub dry_run: bool,
+    pub strategy: ImportStrategy,
+    pub new_experiences: usize,
+    pub conflicting_experiences: usize,
+    pub new_peers: usize,
+    pub existing_peers: usize,
+    pub records: Vec<ImportRecordResult>,
+}
+
+impl ImportSummary {
+    /// Add up the counts of a batch; per-record results are left out, they'd grow with the import
+    pub fn absorb(&mut self, batch: &ImportSummary) {
+        self.new_experiences += batch.new_experiences;
+        self.conflicting_experiences += batch.conflicting_experiences;
+        self.new_peers += batch.new_peers;
+        self.existing_peers += batch.existing_peers;
+    }
+}
+
+impl AgentIdentifier {
+    pub fn new(id_domain: impl Into<String>, agent_id: impl Into<String>) -> Self {
+        Self {
+            id_domain: id_domain.into(),
+            agent_id: agent_id.into(),
+        }
+    }
+
+}
+
+/// `id_domain:agent_id`, as agents appear in logs
+impl std::fmt::Display for AgentIdentifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.id_domain, self.agent_id)
+    }
+}
+
+impl AgentScore {
+    pub fn new(id_domain: impl Into<String>, agent_id: impl Into<String>, score: TrustScore) -> Self {
+        Self {
+            id_domain: id_domain.into(),
+            agent_id: agent_id.into(),
+            score,
+            insufficient_data: false,
+            hops: 0,
+            depth: None,
+            freshness: Freshness::default(),
+            global: None,
+            capped: Vec::new(),
+        }
+    }
+
+    pub fn with_hops(mut self, hops: u8) -> Self {
+        self.hops = hops;
+        self
+    }
+
+    pub fn with_depth(mut self, depth: Option<ScoreDepth>) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    pub fn with_freshness(mut self, freshness: Freshness) -> Self {
+        self.freshness = freshness;
+        self
+    }
+
+    pub fn with_global(mut self, global: Option<TrustScore>) -> Self {
+        self.global = global;
+        self
+    }
+
+    pub fn with_capped(mut self, capped: Vec<CappedInfluence>) -> Self {
+        self.capped = capped;
+        self
+    }
+
+    /// Flag the score and replace its ROI with the neutral 1.0 if it lacks the minimum evidence
+    pub fn apply_min_evidence(&mut self, min_evidence: &MinEvidence) {
+        if !min_evidence.is_met_by(&self.score) {
+            self.insufficient_data = true;
+            self.score.expected_pv_roi = 1.0;
         }
     }
 }
\ No newline at end of file
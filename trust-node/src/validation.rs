@@ -0,0 +1,300 @@
+use crate::import::{ImportBatch, ImportRecord};
+use crate::types::{is_valid_peer_handle, AgentIdForm, Discounting, DomainDefaults, ExperienceUpdate, InflationIndex, Peer, PeerUpdate, RuntimeConfigUpdate, SharingPrecision, TrustDataExport, TrustExperience, WatchRequest, MAX_DISCOVERY_INTERVAL_SECS};
+use serde::Serialize;
+
+/// One thing wrong with a write request, named by its path in the body, e.g. `[2].investment`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// A write request rejected by validation, with everything wrong with it so a client can fix it in one go
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("invalid input: {}", .errors.iter().map(|e| format!("{}: {}", e.field, e.message)).collect::<Vec<_>>().join("; "))]
+pub struct InvalidInput {
+    pub errors: Vec<FieldError>,
+}
+
+/// Something a write endpoint accepts, checked before it reaches the node
+pub trait Validate {
+    fn validate(&self, fields: &mut Fields);
+}
+
+/// Collects field errors, naming fields relative to the value being validated
+#[derive(Debug, Default)]
+pub struct Fields {
+    prefix: String,
+    errors: Vec<FieldError>,
+}
+
+impl Fields {
+    /// Record `message` against `field` unless `ok`
+    pub fn check(&mut self, ok: bool, field: &str, message: impl Into<String>) {
+        if !ok {
+            self.errors.push(FieldError { field: format!("{}{}", self.prefix, field), message: message.into() });
+        }
+    }
+
+    pub fn non_empty(&mut self, field: &str, value: &str) {
+        self.check(!value.trim().is_empty(), field, "must not be empty");
+    }
+
+    pub fn finite(&mut self, field: &str, value: f64) {
+        self.check(value.is_finite(), field, "must be a finite number");
+    }
+
+    pub fn positive(&mut self, field: &str, value: f64) {
+        self.check(value.is_finite() && value > 0.0, field, "must be a number above 0");
+    }
+
+    /// A non-empty agent id that fits the default form of its domain, e.g. an Ethereum address with a good checksum
+    pub fn agent_id(&mut self, field: &str, id_domain: &str, agent_id: &str) {
+        self.non_empty(field, agent_id);
+        if let Err(e) = AgentIdForm::for_domain(id_domain).check(agent_id) {
+            self.check(false, field, e);
+        }
+    }
+
+    /// An http:// or https:// URL
+    pub fn http_url(&mut self, field: &str, value: &str) {
+        self.check(value.starts_with("http://") || value.starts_with("https://"), field, "must be an http:// or https:// URL");
+    }
+
+    pub fn non_negative(&mut self, field: &str, value: f64) {
+        self.check(value.is_finite() && value >= 0.0, field, "must be a number of at least 0");
+    }
+
+    /// Recommender qualities run from -1, a perfect reverse indicator, to 1, trusted like oneself
+    pub fn quality(&mut self, field: &str, value: f64) {
+        self.check((-1.0..=1.0).contains(&value), field, "must be between -1 and 1");
+    }
+
+    /// An optional handle may be empty, which clears it, or one is_valid_peer_handle accepts
+    pub fn handle(&mut self, field: &str, handle: Option<&str>) {
+        let valid = handle.is_none_or(|handle| handle.is_empty() || is_valid_peer_handle(handle));
+        self.check(valid, field, "must be 1 to 32 lowercase letters, digits, '-' or '_', and not a reserved path");
+    }
+
+    /// A discounting model and a rate to apply it at, either of which may be left out
+    pub fn discounting(&mut self, discounting: Option<&Discounting>, rate: Option<f64>) {
+        if let Some(Err(e)) = discounting.map(Discounting::check) {
+            self.check(false, "discounting", e);
+        }
+        if let Some(rate) = rate {
+            self.check(Discounting::default().with_rate(rate).check().is_ok(), "discount_rate", "must be a number above -1");
+        }
+    }
+
+    /// Validate a nested value, e.g. an element of a list, under `prefix`
+    pub fn nested<T: Validate + ?Sized>(&mut self, prefix: &str, value: &T) {
+        let outer = self.prefix.len();
+        self.prefix.push_str(prefix);
+        value.validate(self);
+        self.prefix.truncate(outer);
+    }
+
+    /// Validate every element of a list, naming their fields `<field>[<index>].`
+    pub fn each<T: Validate>(&mut self, field: &str, values: &[T]) {
+        for (index, value) in values.iter().enumerate() {
+            self.nested(&format!("{}[{}].", field, index), value);
+        }
+    }
+
+    pub fn finish(self) -> Result<(), InvalidInput> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidInput { errors: self.errors })
+        }
+    }
+}
+
+/// Everything wrong with `value`, if anything is
+pub fn validate<T: Validate + ?Sized>(value: &T) -> Result<(), InvalidInput> {
+    let mut fields = Fields::default();
+    value.validate(&mut fields);
+    fields.finish()
+}
+
+/// A batch of requests, whose fields are named `[<index>].<field>`
+impl<T: Validate> Validate for [T] {
+    fn validate(&self, fields: &mut Fields) {
+        fields.each("", self);
+    }
+}
+
+impl Validate for TrustExperience {
+    fn validate(&self, fields: &mut Fields) {
+        fields.non_empty("id_domain", &self.id_domain);
+        fields.agent_id("agent_id", &self.id_domain, &self.agent_id);
+        fields.finite("pv_roi", self.pv_roi);
+        fields.positive("invested_volume", self.invested_volume);
+    }
+}
+
+impl Validate for ExperienceUpdate {
+    fn validate(&self, fields: &mut Fields) {
+        if let Some(id_domain) = &self.id_domain {
+            fields.non_empty("id_domain", id_domain);
+        }
+        if let Some(agent_id) = &self.agent_id {
+            fields.agent_id("agent_id", self.id_domain.as_deref().unwrap_or_default(), agent_id);
+        }
+        if let Some(pv_roi) = self.pv_roi {
+            fields.finite("pv_roi", pv_roi);
+        }
+        if let Some(volume) = self.invested_volume {
+            fields.positive("invested_volume", volume);
+        }
+    }
+}
+
+impl Validate for Peer {
+    fn validate(&self, fields: &mut Fields) {
+        fields.non_empty("peer_id", &self.peer_id);
+        fields.quality("recommender_quality", self.recommender_quality);
+        fields.handle("handle", self.handle.as_deref());
+    }
+}
+
+impl Validate for PeerUpdate {
+    fn validate(&self, fields: &mut Fields) {
+        if let Some(quality) = self.recommender_quality {
+            fields.quality("recommender_quality", quality);
+        }
+        fields.handle("handle", self.handle.as_deref());
+    }
+}
+
+impl Validate for TrustDataExport {
+    fn validate(&self, fields: &mut Fields) {
+        fields.each("experiences", &self.experiences);
+        fields.each("peers", &self.peers);
+    }
+}
+
+impl Validate for ImportBatch {
+    fn validate(&self, fields: &mut Fields) {
+        fields.each("experiences", &self.experiences);
+        fields.each("peers", &self.peers);
+    }
+}
+
+impl Validate for ImportRecord {
+    fn validate(&self, fields: &mut Fields) {
+        match self {
+            ImportRecord::Experience(experience) => experience.validate(fields),
+            ImportRecord::Peer(peer) => peer.validate(fields),
+            ImportRecord::Manifest(_) => {}
+        }
+    }
+}
+
+impl Validate for DomainDefaults {
+    fn validate(&self, fields: &mut Fields) {
+        if let Some(forget_rate) = self.forget_rate {
+            fields.non_negative("forget_rate", forget_rate);
+        }
+        fields.discounting(self.discounting.as_ref(), None);
+    }
+}
+
+impl Validate for InflationIndex {
+    fn validate(&self, fields: &mut Fields) {
+        if let Err(e) = self.check() {
+            fields.check(false, "points", e);
+        }
+    }
+}
+
+impl Validate for SharingPrecision {
+    fn validate(&self, fields: &mut Fields) {
+        fields.check(!self.subject.is_empty() && self.subject != "circle:", "subject", "must be a peer id or circle:<tag>");
+        if let Some(step) = self.roi_step {
+            fields.positive("roi_step", step);
+        }
+    }
+}
+
+impl Validate for RuntimeConfigUpdate {
+    fn validate(&self, fields: &mut Fields) {
+        if let Some(secs) = self.discovery_interval_secs {
+            fields.check((1..=MAX_DISCOVERY_INTERVAL_SECS).contains(&secs), "discovery_interval_secs", format!("must be 1 to {}", MAX_DISCOVERY_INTERVAL_SECS));
+        }
+        if let Some(secs) = self.cache_ttl_secs {
+            fields.check(secs <= i64::MAX as u64, "cache_ttl_secs", "is too large");
+        }
+        for (field, value) in [
+            ("inbound_workers", self.inbound_workers),
+            ("max_queued_per_peer", self.max_queued_per_peer),
+            ("max_query_agents", self.max_query_agents),
+        ] {
+            fields.check(value != Some(0), field, "must be at least 1");
+        }
+    }
+}
+
+impl Validate for WatchRequest {
+    fn validate(&self, fields: &mut Fields) {
+        fields.check(!self.thresholds.is_empty() || self.min_delta.is_some(), "thresholds", "set thresholds, min_delta or both");
+        for (index, threshold) in self.thresholds.iter().enumerate() {
+            fields.finite(&format!("thresholds[{}]", index), *threshold);
+        }
+        if let Some(min_delta) = self.min_delta {
+            fields.positive("min_delta", min_delta);
+        }
+        if let Some(webhook) = &self.webhook {
+            fields.http_url("webhook", webhook);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn experience(agent_id: &str, invested_volume: f64) -> TrustExperience {
+        TrustExperience {
+            id: Uuid::new_v4(),
+            id_domain: "test".to_string(),
+            agent_id: agent_id.to_string(),
+            pv_roi: 1.1,
+            invested_volume,
+            timestamp: Utc::now(),
+            notes: None,
+            data: None,
+        }
+    }
+
+    #[test]
+    fn test_every_bad_field_is_named_by_its_path() {
+        assert!(validate(&experience("alice", 100.0)).is_ok());
+
+        let batch = vec![experience("alice", 100.0), experience(" ", -5.0)];
+        let invalid = validate(batch.as_slice()).unwrap_err();
+        let fields: Vec<&str> = invalid.errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, ["[1].agent_id", "[1].invested_volume"]);
+
+        let export = TrustDataExport::new(batch, Vec::new());
+        let invalid = validate(&export).unwrap_err();
+        assert_eq!(invalid.errors[0].field, "experiences[1].agent_id");
+        assert!(invalid.to_string().contains("experiences[1].invested_volume: must be a number above 0"), "{}", invalid);
+    }
+
+    #[test]
+    fn test_config_and_watch_bodies_are_validated() {
+        let update = RuntimeConfigUpdate { inbound_workers: Some(0), discovery_interval_secs: Some(0), ..Default::default() };
+        let invalid = validate(&update).unwrap_err();
+        let fields: Vec<&str> = invalid.errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, ["discovery_interval_secs", "inbound_workers"]);
+
+        let watch = WatchRequest { thresholds: vec![1.0, f64::NAN], webhook: Some("ftp://example.com".to_string()), ..Default::default() };
+        let invalid = validate(&watch).unwrap_err();
+        let fields: Vec<&str> = invalid.errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, ["thresholds[1]", "webhook"]);
+        assert!(validate(&WatchRequest::default()).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use crate::events::{EventSender, NodeEvent};
+use crate::types::{ScoreChange, TrustScore, WatchedAgent};
+use std::collections::HashMap;
+#[cfg(feature = "http-client")]
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// How long a webhook gets to accept a score change before it's given up on
+#[cfg(feature = "http-client")]
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Agents whose merged score the node keeps an eye on, mirrored from storage
+pub struct Watchlist {
+    agents: HashMap<(String, String), WatchedAgent>,
+    #[cfg(feature = "http-client")]
+    client: reqwest::Client,
+}
+
+impl Watchlist {
+    pub fn new(watched: Vec<WatchedAgent>) -> Self {
+        let agents = watched
+            .into_iter()
+            .map(|agent| ((agent.id_domain.clone(), agent.agent_id.clone()), agent))
+            .collect();
+        Self {
+            agents,
+            #[cfg(feature = "http-client")]
+            client: reqwest::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// All entries, ordered by domain and agent
+    pub fn list(&self) -> Vec<WatchedAgent> {
+        let mut watched: Vec<WatchedAgent> = self.agents.values().cloned().collect();
+        watched.sort_by(|a, b| (&a.id_domain, &a.agent_id).cmp(&(&b.id_domain, &b.agent_id)));
+        watched
+    }
+
+    /// The entries for those of `agents` that are watched
+    pub fn select<'a>(&self, agents: impl IntoIterator<Item = (&'a str, &'a str)>) -> Vec<WatchedAgent> {
+        agents
+            .into_iter()
+            .filter_map(|(id_domain, agent_id)| self.agents.get(&(id_domain.to_string(), agent_id.to_string())))
+            .cloned()
+            .collect()
+    }
+
+    pub fn insert(&mut self, watched: WatchedAgent) {
+        self.agents.insert((watched.id_domain.clone(), watched.agent_id.clone()), watched);
+    }
+
+    pub fn remove(&mut self, id_domain: &str, agent_id: &str) {
+        self.agents.remove(&(id_domain.to_string(), agent_id.to_string()));
+    }
+
+    /// Take `score` as the baseline for the agent's next change
+    pub fn record(&mut self, id_domain: &str, agent_id: &str, score: &TrustScore) {
+        if let Some(watched) = self.agents.get_mut(&(id_domain.to_string(), agent_id.to_string())) {
+            watched.last_score = score.clone();
+        }
+    }
+
+    /// Publish a change as a score_changed event and POST it to the agent's webhook, if it has one
+    pub fn announce(&self, change: ScoreChange, webhook: Option<&str>, events: &EventSender) {
+        debug!(
+            agent = %change.agent_id,
+            "Watched score moved from {:.4} to {:.4}",
+            change.previous.expected_pv_roi,
+            change.current.expected_pv_roi
+        );
+        #[cfg(feature = "http-client")]
+        if let Some(webhook) = webhook {
+            let request = self.client.post(webhook).json(&change);
+            let webhook = webhook.to_string();
+            tokio::spawn(async move {
+                let result = request.send().await.and_then(reqwest::Response::error_for_status);
+                if let Err(e) = result {
+                    warn!("Failed to deliver score change to {}: {}", webhook, e);
+                }
+            });
+        }
+        #[cfg(not(feature = "http-client"))]
+        if let Some(webhook) = webhook {
+            warn!("Built without http-client, not delivering score change to {}", webhook);
+        }
+        // Nobody listening is fine
+        let _ = events.send(NodeEvent::ScoreChanged(change));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,89 @@
+use trust_node::{
+    backup::{restore_from_file, run_backup, BackupConfig, RestoreFormat},
+    storage::{Storage, SqliteStorage},
+    types::{Peer, TrustDataExport, TrustExperience},
+};
+use chrono::Utc;
+use std::time::Duration;
+use tempfile::tempdir;
+use uuid::Uuid;
+
+fn experience(agent_id: &str) -> TrustExperience {
+    TrustExperience {
+        id: Uuid::new_v4(),
+        id_domain: "test".to_string(),
+        agent_id: agent_id.to_string(),
+        pv_roi: 1.1,
+        invested_volume: 100.0,
+        timestamp: Utc::now(),
+        notes: None,
+        data: None,
+    }
+}
+
+#[tokio::test]
+async fn test_snapshot_roundtrip() {
+    let dir = tempdir().unwrap();
+    let storage = SqliteStorage::new(&dir.path().join("node.db")).await.unwrap();
+    storage.add_experience(experience("agent")).await.unwrap();
+
+    let config = BackupConfig {
+        directory: dir.path().join("backups"),
+        interval: Duration::from_secs(3600),
+        keep: 3,
+    };
+    let snapshot = run_backup(&storage, &config).await.unwrap();
+
+    storage.clear_experiences().await.unwrap();
+    storage.add_experience(experience("other_agent")).await.unwrap();
+
+    let summary = restore_from_file(&storage, &snapshot).await.unwrap();
+    assert_eq!(summary.format, RestoreFormat::Snapshot);
+
+    let experiences = storage.get_all_experiences().await.unwrap();
+    assert_eq!(experiences.len(), 1);
+    assert_eq!(experiences[0].agent_id, "agent");
+}
+
+#[tokio::test]
+async fn test_restore_export_replaces_peers() {
+    let dir = tempdir().unwrap();
+    let storage = SqliteStorage::new(&dir.path().join("node.db")).await.unwrap();
+    storage.add_peer(Peer {
+        peer_id: "old_peer".to_string(),
+        addresses: vec![],
+        handle: None,
+        name: "Old".to_string(),
+        recommender_quality: 0.5,
+        added_at: Utc::now(),
+        notes: None,
+        tags: vec![],
+        contact: Default::default(),
+        last_interaction_at: None,
+    }).await.unwrap();
+
+    let export = TrustDataExport::new(
+        vec![experience("agent")],
+        vec![Peer {
+            peer_id: "new_peer".to_string(),
+            addresses: vec![],
+            handle: None,
+            name: "New".to_string(),
+            recommender_quality: 0.9,
+            added_at: Utc::now(),
+            notes: None,
+            tags: vec![],
+            contact: Default::default(),
+            last_interaction_at: None,
+        }],
+    );
+    let export_path = dir.path().join("export.json");
+    std::fs::write(&export_path, serde_json::to_vec(&export).unwrap()).unwrap();
+
+    let summary = restore_from_file(&storage, &export_path).await.unwrap();
+    assert_eq!(summary.format, RestoreFormat::Export);
+
+    let peers = storage.get_peers().await.unwrap();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].peer_id, "new_peer");
+}
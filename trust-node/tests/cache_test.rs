@@ -1,9 +1,17 @@
 use trust_node::{
+    clock::{Clock, ManualClock},
+    query_engine::QueryEngine,
     storage::{Storage, SqliteStorage},
-    types::{CachedTrustScore, TrustScore},
+    types::{AgentIdentifier, AgentQueries, CachedTrustScore, IdempotentResponse, Peer, TrustScore, COMPACTED_PEER},
 };
-use chrono::Utc;
+use chrono::{Duration, Utc};
 use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A database that lives only as long as the test
+async fn memory_storage() -> SqliteStorage {
+    SqliteStorage::new(&PathBuf::from(":memory:")).await.unwrap()
+}
 
 #[tokio::test]
 async fn test_trust_score_caching() {
@@ -124,4 +132,138 @@ async fn test_cache_update() {
     assert_eq!(retrieved[0].score.expected_pv_roi, 1.5);
     assert_eq!(retrieved[0].score.total_volume, 200.0);
     assert_eq!(retrieved[0].score.data_points, 2);
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_score_cache_expires_with_the_clock() {
+    let storage = Arc::new(memory_storage().await);
+    let clock = Arc::new(ManualClock::new(Utc::now()));
+    let query_engine = QueryEngine::new_with_cache_ttl(storage, 60).with_clock(clock.clone());
+    let point_in_time = clock.now();
+
+    query_engine.calculate_trust_score("test", "agent", point_in_time, 0.1).await.unwrap();
+    clock.advance(Duration::seconds(59));
+    query_engine.calculate_trust_score("test", "agent", point_in_time, 0.1).await.unwrap();
+    assert_eq!((query_engine.cache_stats().hits, query_engine.cache_stats().misses), (1, 1));
+
+    // Past the TTL the same lookup is recalculated, no sleeping required
+    clock.advance(Duration::seconds(2));
+    query_engine.calculate_trust_score("test", "agent", point_in_time, 0.1).await.unwrap();
+    assert_eq!(query_engine.cache_stats().misses, 2);
+}
+
+#[tokio::test]
+async fn test_hot_agents_favor_recent_queries() {
+    let storage = memory_storage().await;
+    let now = Utc::now();
+    let old = AgentIdentifier::new("test", "old");
+    let recent = AgentIdentifier::new("test", "recent");
+    let rare = AgentIdentifier::new("test", "rare");
+
+    // Queried often, but a month ago, and counted over two flushes
+    let counted = |agent: &AgentIdentifier, queries, last_queried_at| AgentQueries {
+        id_domain: agent.id_domain.clone(),
+        agent_id: agent.agent_id.clone(),
+        queries,
+        last_queried_at,
+    };
+    storage.record_agent_queries(&[counted(&old, 6, now - Duration::days(31))]).await.unwrap();
+    storage.record_agent_queries(&[counted(&old, 4, now - Duration::days(30)), counted(&recent, 3, now)]).await.unwrap();
+    storage.record_agent_queries(&[counted(&rare, 1, now - Duration::days(1))]).await.unwrap();
+
+    let hot: Vec<String> = storage.get_hot_agents(2, now).await.unwrap()
+        .into_iter()
+        .map(|agent| agent.agent_id)
+        .collect();
+    assert_eq!(hot, vec!["recent", "rare"]);
+}
+
+#[tokio::test]
+async fn test_idempotent_responses_are_kept_until_they_expire() {
+    let storage = memory_storage().await;
+    let now = Utc::now();
+    let response = IdempotentResponse {
+        endpoint: "POST /v1/experiences".to_string(),
+        key: "retry-1".to_string(),
+        request_hash: "abc".to_string(),
+        request_bytes: 42,
+        status: 201,
+        content_type: Some("application/json".to_string()),
+        body: br#"{"id":"1"}"#.to_vec(),
+    };
+    storage.put_idempotent_response(&response, now + Duration::hours(24)).await.unwrap();
+
+    let kept = storage.get_idempotent_response("POST /v1/experiences", "retry-1", now).await.unwrap();
+    assert_eq!(kept.as_ref(), Some(&response));
+    // Keys are only unique per endpoint
+    assert!(storage.get_idempotent_response("POST /v1/peers", "retry-1", now).await.unwrap().is_none());
+
+    let later = now + Duration::hours(25);
+    assert!(storage.get_idempotent_response("POST /v1/experiences", "retry-1", later).await.unwrap().is_none());
+    assert_eq!(storage.purge_idempotent_responses(now).await.unwrap(), 0);
+    assert_eq!(storage.purge_idempotent_responses(later).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn test_compaction_folds_old_recommendations_by_quality() {
+    let storage = memory_storage().await;
+    let now = Utc::now();
+    for (peer_id, quality) in [("good", 1.0), ("poor", 0.25), ("fresh", 0.5)] {
+        storage.add_peer(Peer {
+            peer_id: peer_id.to_string(),
+            addresses: vec![],
+            handle: None,
+            name: peer_id.to_string(),
+            recommender_quality: quality,
+            added_at: now,
+            notes: None,
+            tags: vec![],
+            contact: Default::default(),
+            last_interaction_at: None,
+        }).await.unwrap();
+    }
+    let cached = |from_peer: &str, expected_pv_roi: f64, cached_at| CachedTrustScore {
+        id_domain: "test".to_string(),
+        agent_id: "agent".to_string(),
+        score: TrustScore { expected_pv_roi, total_volume: 100.0, data_points: 2 },
+        from_peer: from_peer.to_string(),
+        cached_at,
+    };
+    storage.cache_trust_score(cached("good", 1.2, now - Duration::days(100))).await.unwrap();
+    storage.cache_trust_score(cached("poor", 0.2, now - Duration::days(120))).await.unwrap();
+    // Recent, and from a peer we don't know: both stay as they are
+    storage.cache_trust_score(cached("fresh", 0.9, now)).await.unwrap();
+    storage.cache_trust_score(cached("stranger", 0.5, now - Duration::days(100))).await.unwrap();
+
+    let folded = storage.compact_cached_scores(now - Duration::days(90)).await.unwrap();
+    assert_eq!(folded, 2);
+
+    let remaining = storage.get_cached_scores("test", "agent").await.unwrap();
+    let mut peers: Vec<&str> = remaining.iter().map(|cached| cached.from_peer.as_str()).collect();
+    peers.sort();
+    assert_eq!(peers, vec![COMPACTED_PEER, "fresh", "stranger"]);
+    let compacted = remaining.iter().find(|cached| cached.from_peer == COMPACTED_PEER).unwrap();
+    assert_eq!(compacted.score.total_volume, 125.0);
+    assert!((compacted.score.expected_pv_roi - 1.0).abs() < 1e-9);
+    assert_eq!(compacted.score.data_points, 4);
+    assert_eq!(compacted.cached_at.timestamp(), (now - Duration::days(100)).timestamp());
+
+    // Once it ages too, the fresh recommendation joins the compacted entry
+    assert_eq!(storage.compact_cached_scores(now + Duration::days(1)).await.unwrap(), 1);
+    assert_eq!(storage.get_cached_scores("test", "agent").await.unwrap().len(), 2);
+
+    // A newer score from a peer replaces the one folded from it, each time, instead of adding to it
+    let compacted_volume = || async {
+        let remaining = storage.get_cached_scores("test", "agent").await.unwrap();
+        remaining.iter().find(|cached| cached.from_peer == COMPACTED_PEER).unwrap().score.total_volume
+    };
+    storage.cache_trust_score(cached("good", 2.0, now)).await.unwrap();
+    assert_eq!(compacted_volume().await, 75.0);
+    assert_eq!(storage.compact_cached_scores(now + Duration::days(1)).await.unwrap(), 1);
+    assert_eq!(compacted_volume().await, 175.0);
+    assert_eq!(storage.get_all_cached_scores().await.unwrap().len(), 4);
+
+    // A removed peer's recommendations drop out of it
+    storage.remove_peer("poor").await.unwrap();
+    assert_eq!(compacted_volume().await, 150.0);
+}
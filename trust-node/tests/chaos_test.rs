@@ -0,0 +1,164 @@
+use std::sync::Arc;
+use std::time::Duration;
+use trust_node::chaos::{Chaos, ChaosConfig};
+use tokio::sync::oneshot;
+use trust_node::config::NodeConfig;
+use trust_node::node::NodeCommand;
+use trust_node::quarantine::{QuarantinePolicy, Violation};
+use trust_node::simulation::Network;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// alice asks bob, whose responses suffer from `faults`; both have dealt with the vendor
+async fn alice_and_faulty_bob(faults: ChaosConfig) -> Network {
+    alice_with_faulty_bob(NodeConfig::default(), faults).await
+}
+
+async fn alice_with_faulty_bob(alice: NodeConfig, faults: ChaosConfig) -> Network {
+    let faulty = NodeConfig { chaos: Arc::new(Chaos::new(faults)), ..NodeConfig::default() };
+    let mut network = Network::spawn_each(vec![alice, faulty]).await.unwrap();
+    network.befriend(0, 1, 1.0).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    network.add_experience(0, "test", "vendor", 1.0, 100.0).await.unwrap();
+    network.add_experience(1, "test", "vendor", 2.0, 100.0).await.unwrap();
+    network
+}
+
+#[tokio::test]
+async fn test_dropped_responses_fall_back_to_own_scores() {
+    let network = alice_and_faulty_bob(ChaosConfig { drop_responses: 1.0, ..ChaosConfig::default() }).await;
+
+    let score = network.query(0, "test", "vendor", 1).await.unwrap();
+    assert_eq!(score.score.total_volume, 100.0);
+    assert_eq!(score.score.expected_pv_roi, 1.0);
+}
+
+#[tokio::test]
+async fn test_corrupt_frames_fall_back_to_own_scores() {
+    let network = alice_and_faulty_bob(ChaosConfig { corrupt_frames: 1.0, ..ChaosConfig::default() }).await;
+
+    let score = network.query(0, "test", "vendor", 1).await.unwrap();
+    assert_eq!(score.score.total_volume, 100.0);
+}
+
+#[tokio::test]
+async fn test_delayed_responses_are_still_merged() {
+    let network = alice_and_faulty_bob(ChaosConfig {
+        delay_responses: 1.0,
+        response_delay: Duration::from_millis(200),
+        ..ChaosConfig::default()
+    })
+    .await;
+
+    let score = network.query(0, "test", "vendor", 1).await.unwrap();
+    assert_eq!(score.score.total_volume, 200.0);
+    assert!((score.score.expected_pv_roi - 1.5).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn test_responses_slower_than_the_request_timeout_are_left_out() {
+    let faults = ChaosConfig {
+        delay_responses: 1.0,
+        response_delay: Duration::from_millis(1500),
+        ..ChaosConfig::default()
+    };
+    let impatient = NodeConfig { request_timeout: Duration::from_millis(500), ..NodeConfig::default() };
+    let network = alice_with_faulty_bob(impatient, faults).await;
+
+    let score = network.query(0, "test", "vendor", 1).await.unwrap();
+    assert_eq!(score.score.total_volume, 100.0);
+}
+
+#[tokio::test]
+async fn test_peers_sending_garbage_are_quarantined_until_released() {
+    let strict = NodeConfig {
+        quarantine: Some(QuarantinePolicy { max_violations: 2, ..QuarantinePolicy::default() }),
+        ..NodeConfig::default()
+    };
+    let network = alice_with_faulty_bob(strict, ChaosConfig { corrupt_frames: 1.0, ..ChaosConfig::default() }).await;
+    let alice = network.node(0).commands.clone();
+    let bob = network.node(1).peer_id.clone();
+
+    for _ in 0..2 {
+        network.query(0, "test", "vendor", 1).await.unwrap();
+    }
+    let (response, quarantined) = oneshot::channel();
+    alice.send(NodeCommand::GetQuarantinedPeers { response }).await.unwrap();
+    let quarantined = quarantined.await.unwrap().unwrap();
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].peer_id, bob);
+    assert_eq!(quarantined[0].violations, [Violation::UndecodableFrame, Violation::UndecodableFrame]);
+
+    // Its connection is closed and stays so
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    loop {
+        let (response, connected) = oneshot::channel();
+        alice.send(NodeCommand::GetConnectedPeers { response }).await.unwrap();
+        if !connected.await.unwrap().unwrap().contains(&bob) {
+            break;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "bob stayed connected");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let (response, released) = oneshot::channel();
+    alice.send(NodeCommand::ReleaseQuarantinedPeer { peer_id: bob.clone(), response }).await.unwrap();
+    released.await.unwrap().unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+
+    // Releasing twice finds nobody to release
+    let (response, released) = oneshot::channel();
+    alice.send(NodeCommand::ReleaseQuarantinedPeer { peer_id: bob, response }).await.unwrap();
+    assert!(released.await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn test_failed_score_refresh_keeps_the_node_running() {
+    let chaos = Arc::new(Chaos::new(ChaosConfig::default()));
+    let config = NodeConfig {
+        refresh_interval: Some(Duration::from_millis(100)),
+        chaos: chaos.clone(),
+        ..NodeConfig::default()
+    };
+    let network = Network::spawn_each(vec![config]).await.unwrap();
+    network.add_experience(0, "test", "vendor", 1.0, 100.0).await.unwrap();
+    // Makes the vendor popular enough to be refreshed
+    network.query(0, "test", "vendor", 0).await.unwrap();
+
+    // Refreshes only run once the node has been idle for a while
+    chaos.set_fail_storage(1.0);
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    chaos.set_fail_storage(0.0);
+
+    let score = network.query(0, "test", "vendor", 0).await.unwrap();
+    assert_eq!(score.score.total_volume, 100.0);
+}
+
+#[tokio::test]
+async fn test_answers_for_requesters_that_left_keep_the_node_running() {
+    // carol answers bob slowly, so bob's answer to alice is only ready after alice is gone
+    let slow = ChaosConfig {
+        delay_responses: 1.0,
+        response_delay: Duration::from_millis(1000),
+        ..ChaosConfig::default()
+    };
+    let carol = NodeConfig { chaos: Arc::new(Chaos::new(slow)), ..NodeConfig::default() };
+    let mut network = Network::spawn_each(vec![NodeConfig::default(), NodeConfig::default(), carol]).await.unwrap();
+    network.befriend(0, 1, 1.0).await.unwrap();
+    network.befriend(1, 2, 1.0).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    network.add_experience(2, "test", "vendor", 2.0, 100.0).await.unwrap();
+
+    let alice = network.node(0).commands.clone();
+    let query = network.trust_query("test", "vendor", 2);
+    let (response, _answer) = oneshot::channel();
+    alice.send(NodeCommand::QueryTrust { query, response }).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    network.stop(0);
+
+    // Past carol's delay bob has tried to answer alice
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(network.is_running(1));
+    let score = network.query(1, "test", "vendor", 1).await.unwrap();
+    assert_eq!(score.score.total_volume, 100.0);
+}
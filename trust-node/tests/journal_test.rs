@@ -0,0 +1,114 @@
+use trust_node::{
+    clock::{Clock, ManualClock},
+    journal::{rebuild, replay, JournaledStorage},
+    storage::{SqliteStorage, Storage},
+    types::{Peer, PeerAddress, PeerContact, PeerProposal, TrustExperience},
+};
+use chrono::{Duration, Utc};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tempfile::tempdir;
+use uuid::Uuid;
+
+fn experience(agent_id: &str) -> TrustExperience {
+    TrustExperience {
+        id: Uuid::new_v4(),
+        id_domain: "test".to_string(),
+        agent_id: agent_id.to_string(),
+        pv_roi: 1.1,
+        invested_volume: 100.0,
+        timestamp: Utc::now(),
+        notes: None,
+        data: None,
+    }
+}
+
+fn peer(name: &str) -> Peer {
+    Peer {
+        peer_id: name.to_string(),
+        addresses: vec![PeerAddress::new("/ip4/127.0.0.1/tcp/9000")],
+        handle: None,
+        name: name.to_string(),
+        recommender_quality: 0.5,
+        added_at: Utc::now(),
+        notes: None,
+        tags: Vec::new(),
+        contact: PeerContact::default(),
+        last_interaction_at: None,
+    }
+}
+
+async fn memory_storage() -> SqliteStorage {
+    SqliteStorage::new(&PathBuf::from(":memory:")).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_replay_reproduces_state() {
+    let dir = tempdir().unwrap();
+    let journal = dir.path().join("alice.journal");
+    let clock = Arc::new(ManualClock::new(Utc::now()));
+    let storage = JournaledStorage::open(memory_storage().await, Some(&journal), clock.clone()).await.unwrap();
+
+    let kept = experience("kept");
+    let removed = experience("removed");
+    storage.add_experiences(vec![kept.clone(), removed.clone()]).await.unwrap();
+    storage.remove_experience(&removed.id.to_string()).await.unwrap();
+    storage.add_peer(peer("bob")).await.unwrap();
+    storage.update_peer_quality(&peer("bob").peer_id, 0.9, "verified").await.unwrap();
+    storage.record_peer_request("carol", clock.now()).await.unwrap();
+    let proposal = PeerProposal { name: Some("Dave".to_string()), addresses: Vec::new(), contact: PeerContact::default(), tags: Vec::new(), note: None };
+    storage.record_peer_proposal("dave", &proposal, clock.now()).await.unwrap();
+    // A rejected change must not end up in the journal
+    assert!(storage.add_experience(kept.clone()).await.is_err());
+
+    let rebuilt = memory_storage().await;
+    let summary = replay(&rebuilt, &journal, None).await.unwrap();
+    assert_eq!((summary.applied, summary.skipped, summary.last_seq), (7, 0, Some(7)));
+
+    let experiences = rebuilt.get_all_experiences().await.unwrap();
+    assert_eq!(experiences.len(), 1);
+    assert_eq!(experiences[0].id, kept.id);
+    let peers = rebuilt.get_peers().await.unwrap();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].recommender_quality, 0.9);
+    let requests = rebuilt.get_peer_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    assert!(requests.iter().any(|request| request.peer_id == "dave" && request.proposal.is_some()));
+
+    // Reopening continues the numbering
+    drop(storage);
+    let storage = JournaledStorage::open(memory_storage().await, Some(&journal), clock).await.unwrap();
+    storage.add_experience(experience("later")).await.unwrap();
+    let summary = replay(&memory_storage().await, &journal, None).await.unwrap();
+    assert_eq!(summary.last_seq, Some(8));
+}
+
+#[tokio::test]
+async fn test_rebuild_to_point_in_time() {
+    let dir = tempdir().unwrap();
+    let journal = dir.path().join("alice.journal");
+    let clock = Arc::new(ManualClock::new(Utc::now()));
+    let database = SqliteStorage::new(&dir.path().join("alice.db")).await.unwrap();
+    let storage = JournaledStorage::open(database, Some(&journal), clock.clone()).await.unwrap();
+
+    storage.add_experience(experience("before")).await.unwrap();
+    let checkpoint = clock.now();
+    clock.advance(Duration::hours(1));
+    storage.add_experience(experience("mistake")).await.unwrap();
+    storage.clear_peers().await.unwrap();
+    drop(storage);
+
+    let database = SqliteStorage::new(&dir.path().join("alice.db")).await.unwrap();
+    let summary = rebuild(&database, &journal, &dir.path().join("alice.replay.db"), Some(checkpoint)).await.unwrap();
+    assert_eq!((summary.applied, summary.skipped), (1, 2));
+
+    let experiences = database.get_all_experiences().await.unwrap();
+    assert_eq!(experiences.len(), 1);
+    assert_eq!(experiences[0].agent_id, "before");
+
+    // The journal now ends at the checkpoint, the full one is kept aside
+    let summary = replay(&memory_storage().await, &journal, None).await.unwrap();
+    assert_eq!((summary.applied, summary.last_seq), (1, Some(1)));
+    assert!(dir.path().join("alice.journal.before-replay").is_file());
+    assert!(!dir.path().join("alice.replay.db").exists());
+}
\ No newline at end of file
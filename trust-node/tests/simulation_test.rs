@@ -0,0 +1,784 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use trust_node::config::NodeConfig;
+use trust_node::events::NodeEvent;
+use trust_node::node::NodeCommand;
+use trust_node::quarantine::QuarantinePolicy;
+use trust_node::reputation::ReputationPolicy;
+use trust_node::simulation::Network;
+use trust_node::storage::StorageError;
+use trust_node::types::{ExperienceUpdate, ImportOutcome, ImportStrategy, Peer, PeerAddress, PeerContact, PeerRequestStatus, PeerUpdate, ScoreDepth, TrustExperience, TrustQuery, WatchRequest};
+use uuid::Uuid;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::test]
+async fn test_scores_travel_along_a_friendship_chain() {
+    // alice -> bob -> carol, and only carol has dealt with the vendor
+    let mut network = Network::spawn(3, NodeConfig::default()).await.unwrap();
+    network.befriend_all(&[(0, 1, 1.0), (1, 2, 1.0)]).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    network.add_experience(2, "test", "vendor", 1.5, 200.0).await.unwrap();
+
+    let score = network
+        .query_until(0, "test", "vendor", 2, TIMEOUT, |s| s.score.total_volume > 0.0)
+        .await
+        .unwrap();
+    assert!((score.score.expected_pv_roi - 1.5).abs() < 1e-9);
+    assert!((score.score.total_volume - 200.0).abs() < 1e-9);
+    assert_eq!(score.hops, 2);
+    assert_eq!(score.depth, Some(ScoreDepth { max_depth: 2, farthest_hops: 2 }));
+
+    // Asked again, bob's answer replaces the one alice cached from him instead of adding to it
+    let again = network.query(0, "test", "vendor", 2).await.unwrap();
+    assert!((again.score.total_volume - 200.0).abs() < 1e-9);
+
+    // One hop is not enough to reach carol once what she said earlier, cached along the way, is left out
+    let shallow = TrustQuery { refresh: true, ..network.trust_query("test", "vendor", 1) };
+    assert!(network.query_with(0, shallow).await.is_err());
+}
+
+#[tokio::test]
+async fn test_timing_breaks_down_every_hop() {
+    // alice -> bob -> carol again, this time asking where the time went
+    let mut network = Network::spawn(3, NodeConfig::default()).await.unwrap();
+    network.befriend_all(&[(0, 1, 1.0), (1, 2, 1.0)]).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    network.add_experience(2, "test", "vendor", 1.5, 200.0).await.unwrap();
+    network.query_until(0, "test", "vendor", 2, TIMEOUT, |s| s.score.total_volume > 0.0).await.unwrap();
+
+    let untimed = network.query_response(0, network.trust_query("test", "vendor", 2)).await.unwrap();
+    assert!(untimed.timing.is_none());
+
+    let timed = TrustQuery { timing: true, ..network.trust_query("test", "vendor", 2) };
+    let timing = network.query_response(0, timed).await.unwrap().timing.unwrap();
+    assert_eq!(timing.peers.len(), 1);
+    let bob = &timing.peers[0];
+    assert_eq!(bob.peer_id.as_ref(), Some(&network.node(1).peer_id));
+    let bob_latency = bob.latency_ms.unwrap();
+    assert!(bob_latency <= timing.total_ms);
+
+    // bob asked carol in turn, and says so, but not that it was her
+    let bob_timing = bob.timing.as_ref().unwrap();
+    assert_eq!(bob_timing.peers.len(), 1);
+    assert!(bob_timing.peers[0].peer_id.is_none());
+    assert!(bob_timing.total_ms <= bob_latency);
+    let carol_timing = bob_timing.peers[0].timing.as_ref().unwrap();
+    assert!(carol_timing.peers.is_empty());
+}
+
+#[tokio::test]
+async fn test_answers_of_several_friends_are_combined() {
+    // alice asks bob and carol, who had opposite experiences with the vendor
+    let mut network = Network::spawn(3, NodeConfig::default()).await.unwrap();
+    network.befriend_all(&[(0, 1, 1.0), (0, 2, 1.0)]).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    network.add_experience(1, "test", "vendor", 2.0, 300.0).await.unwrap();
+    network.add_experience(2, "test", "vendor", 0.5, 100.0).await.unwrap();
+
+    let score = network
+        .query_until(0, "test", "vendor", 1, TIMEOUT, |s| s.score.total_volume >= 400.0)
+        .await
+        .unwrap();
+    assert!((score.score.total_volume - 400.0).abs() < 1e-9);
+    assert!((score.score.expected_pv_roi - 1.625).abs() < 1e-9);
+    assert_eq!(score.hops, 1);
+}
+
+#[tokio::test]
+async fn test_friend_lists_are_added_in_one_batch() {
+    let network = Network::spawn(3, NodeConfig::default()).await.unwrap();
+    let friend = |index: usize| Peer {
+        peer_id: network.node(index).peer_id.clone(),
+        addresses: vec![PeerAddress::new(network.node(index).address.clone())],
+        handle: None,
+        name: network.node(index).name.clone(),
+        recommender_quality: 0.8,
+        added_at: Utc::now(),
+        notes: None,
+        tags: Vec::new(),
+        contact: PeerContact::default(),
+        last_interaction_at: None,
+    };
+    let add_peers = |peers: Vec<Peer>| {
+        let commands = network.node(0).commands.clone();
+        async move {
+            let (response, summary) = oneshot::channel();
+            commands
+                .send(NodeCommand::AddPeers { peers, strategy: ImportStrategy::SkipDuplicates, dry_run: false, response })
+                .await
+                .unwrap();
+            summary.await.unwrap().unwrap()
+        }
+    };
+
+    // bob is listed twice, as exported friend lists sometimes do
+    let summary = add_peers(vec![friend(1), friend(2), friend(1)]).await;
+    let outcomes: Vec<ImportOutcome> = summary.records.iter().map(|record| record.outcome).collect();
+    assert_eq!(outcomes, [ImportOutcome::Added, ImportOutcome::Added, ImportOutcome::Skipped]);
+
+    // Known peers are skipped rather than failing the batch
+    let again = add_peers(vec![friend(2)]).await;
+    assert_eq!(again.existing_peers, 1);
+    assert_eq!(again.records[0].outcome, ImportOutcome::Skipped);
+
+    // New friends are dialed like ones added one by one
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    loop {
+        let (response, connected) = oneshot::channel();
+        network.node(0).commands.send(NodeCommand::GetConnectedPeers { response }).await.unwrap();
+        let connected = connected.await.unwrap().unwrap();
+        if connected.contains(&network.node(1).peer_id) && connected.contains(&network.node(2).peer_id) {
+            break;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "batch-added friends never connected");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_peers_are_addressed_by_handle_or_name() {
+    let mut network = Network::spawn(4, NodeConfig::default()).await.unwrap();
+    network.befriend_all(&[(0, 1, 0.5), (0, 2, 0.5)]).await.unwrap();
+    let commands = network.node(0).commands.clone();
+    let update = |peer_id: &str, update: PeerUpdate| {
+        let (commands, peer_id) = (commands.clone(), peer_id.to_string());
+        async move {
+            let (response, updated) = oneshot::channel();
+            commands.send(NodeCommand::UpdatePeer { peer_id, update, response }).await.unwrap();
+            updated.await.unwrap()
+        }
+    };
+
+    // A unique name works in place of the id, and a handle can be set through it
+    let bob = update("node1", PeerUpdate { handle: Some("bob".to_string()), ..Default::default() }).await.unwrap();
+    assert_eq!(bob.peer_id, network.node(1).peer_id);
+    let bob = update("bob", PeerUpdate { recommender_quality: Some(0.9), ..Default::default() }).await.unwrap();
+    assert_eq!(bob.recommender_quality, 0.9);
+
+    // Handles are unique
+    let taken = update("node2", PeerUpdate { handle: Some("bob".to_string()), ..Default::default() }).await;
+    assert!(matches!(taken.unwrap_err().downcast_ref::<StorageError>(), Some(StorageError::Duplicate(_))));
+
+    // Two peers with the same name need a handle or id
+    network.befriend(0, 3, 0.5).await.unwrap();
+    update(&network.node(3).peer_id.clone(), PeerUpdate { name: Some("node2".to_string()), ..Default::default() })
+        .await
+        .unwrap();
+    let ambiguous = update("node2", PeerUpdate { recommender_quality: Some(0.1), ..Default::default() }).await;
+    assert!(matches!(ambiguous.unwrap_err().downcast_ref::<StorageError>(), Some(StorageError::Duplicate(_))));
+}
+
+#[tokio::test]
+async fn test_redeemed_invites_befriend_both_sides_once() {
+    let network = Network::spawn(3, NodeConfig::default()).await.unwrap();
+    let redeem = |index: usize, token: String, introduce_as: &str| {
+        let commands = network.node(index).commands.clone();
+        let introduce_as = Some(introduce_as.to_string());
+        async move {
+            let (response, peer) = oneshot::channel();
+            let command = NodeCommand::RedeemInvite {
+                token,
+                name: None,
+                handle: None,
+                recommender_quality: 0.8,
+                introduce_as,
+                response,
+            };
+            commands.send(command).await.unwrap();
+            peer.await.unwrap()
+        }
+    };
+    let peer_ids = |index: usize| {
+        let commands = network.node(index).commands.clone();
+        async move {
+            let (response, peers) = oneshot::channel();
+            commands.send(NodeCommand::GetPeers { response }).await.unwrap();
+            peers.await.unwrap().unwrap().into_iter().map(|status| status.peer.peer_id).collect::<Vec<_>>()
+        }
+    };
+
+    // The invite carries the addresses alice's node listens on, so wait until it does
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    loop {
+        let (response, addresses) = oneshot::channel();
+        network.node(0).commands.send(NodeCommand::GetListenAddresses { response }).await.unwrap();
+        if !addresses.await.unwrap().unwrap().is_empty() {
+            break;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "inviter never started listening");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let (response, invite) = oneshot::channel();
+    network
+        .node(0)
+        .commands
+        .send(NodeCommand::CreateInvite { name: Some("alice".to_string()), addresses: None, ttl: None, response })
+        .await
+        .unwrap();
+    let token = invite.await.unwrap().unwrap().token;
+
+    let alice = redeem(1, token.clone(), "bob").await.unwrap();
+    assert_eq!(alice.peer_id, network.node(0).peer_id);
+    assert_eq!(alice.name, "alice");
+
+    // alice's node adds bob back once his friend request arrives
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    while !peer_ids(0).await.contains(&network.node(1).peer_id) {
+        assert!(tokio::time::Instant::now() < deadline, "inviter never added the redeemer");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    // The token is used up: carol can add alice, but alice won't add carol
+    redeem(2, token, "carol").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(!peer_ids(0).await.contains(&network.node(2).peer_id));
+}
+
+#[tokio::test]
+async fn test_rotated_keys_keep_their_place_with_peers() {
+    let mut network = Network::spawn(2, NodeConfig::default()).await.unwrap();
+    network.befriend(1, 0, 0.9).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    let bob = network.node(1).commands.clone();
+    let peers = || {
+        let bob = bob.clone();
+        async move {
+            let (response, peers) = oneshot::channel();
+            bob.send(NodeCommand::GetPeers { response }).await.unwrap();
+            peers.await.unwrap().unwrap().into_iter().map(|status| status.peer).collect::<Vec<_>>()
+        }
+    };
+
+    let (response, rotation) = oneshot::channel();
+    network.node(0).commands.send(NodeCommand::RotateIdentity { response }).await.unwrap();
+    let rotation = rotation.await.unwrap().unwrap();
+    assert_eq!(rotation.old_peer_id, network.node(0).peer_id);
+    assert_ne!(rotation.new_peer_id, rotation.old_peer_id);
+    assert_eq!(rotation.notified, vec![network.node(1).peer_id.clone()]);
+
+    // bob moves alice over to her new id, as trusted as before
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    let alice = loop {
+        if let Some(alice) = peers().await.into_iter().find(|peer| peer.peer_id == rotation.new_peer_id) {
+            break alice;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "peer never learned the new key");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    assert_eq!(alice.recommender_quality, 0.9);
+    assert_eq!(peers().await.len(), 1);
+
+    let (response, history) = oneshot::channel();
+    bob.send(NodeCommand::GetQualityHistory { peer_id: rotation.new_peer_id.clone(), response }).await.unwrap();
+    assert!(!history.await.unwrap().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_cache_only_and_refresh_trade_freshness_for_latency() {
+    let mut network = Network::spawn(2, NodeConfig::default()).await.unwrap();
+    network.befriend(0, 1, 1.0).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    network.add_experience(1, "test", "vendor", 1.5, 100.0).await.unwrap();
+    network.query_until(0, "test", "vendor", 1, TIMEOUT, |s| s.score.total_volume > 0.0).await.unwrap();
+
+    // bob changes his mind, so what alice cached from him is out of date
+    network.add_experience(1, "test", "vendor", 0.5, 100.0).await.unwrap();
+
+    let cache_only = TrustQuery { cache_only: true, ..network.trust_query("test", "vendor", 1) };
+    let cached = network.query_with(0, cache_only).await.unwrap();
+    assert!((cached.score.expected_pv_roi - 1.5).abs() < 1e-9);
+
+    let refresh = TrustQuery { refresh: true, ..network.trust_query("test", "vendor", 1) };
+    let fresh = network.query_with(0, refresh).await.unwrap();
+    assert!((fresh.score.expected_pv_roi - 1.0).abs() < 1e-9);
+    assert!((fresh.score.total_volume - 200.0).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn test_unknown_peers_wait_for_review() {
+    // alice adds bob, but bob never added alice
+    let mut network = Network::spawn(2, NodeConfig::default()).await.unwrap();
+    network.befriend(0, 1, 1.0).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    network.add_experience(1, "test", "vendor", 1.5, 100.0).await.unwrap();
+    network.query_until(0, "test", "vendor", 1, TIMEOUT, |s| s.score.total_volume > 0.0).await.unwrap();
+
+    let bob = network.node(1).commands.clone();
+    let (response, requests) = oneshot::channel();
+    bob.send(NodeCommand::GetPeerRequests { response }).await.unwrap();
+    let requests = requests.await.unwrap().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].peer_id, network.node(0).peer_id);
+    assert_eq!(requests[0].status, PeerRequestStatus::Pending);
+
+    // Once blocked, alice's queries are answered empty
+    let (response, blocked) = oneshot::channel();
+    let peer_id = network.node(0).peer_id.clone();
+    bob.send(NodeCommand::SetPeerRequestStatus { peer_id, status: PeerRequestStatus::Blocked, response }).await.unwrap();
+    blocked.await.unwrap().unwrap();
+    let refresh = TrustQuery { refresh: true, ..network.trust_query("test", "vendor", 1) };
+    assert!(network.query_response(0, refresh.clone()).await.unwrap().scores.is_empty());
+
+    // Accepting her moves her into the peer list, and she's answered again
+    let (response, accepted) = oneshot::channel();
+    let peer_id = network.node(0).peer_id.clone();
+    bob.send(NodeCommand::AcceptPeerRequest { peer_id, name: Some("alice".to_string()), quality: Some(0.6), tags: Vec::new(), response })
+        .await
+        .unwrap();
+    let alice = accepted.await.unwrap().unwrap();
+    assert_eq!((alice.name.as_str(), alice.recommender_quality), ("alice", 0.6));
+    assert_eq!(network.query_with(0, refresh).await.unwrap().score.total_volume, 100.0);
+
+    let (response, requests) = oneshot::channel();
+    bob.send(NodeCommand::GetPeerRequests { response }).await.unwrap();
+    assert!(requests.await.unwrap().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_peering_proposals_add_both_sides_once_accepted() {
+    let network = Network::spawn(3, NodeConfig::default()).await.unwrap();
+    let propose = |from: usize, introduce_as: &str| {
+        let commands = network.node(from).commands.clone();
+        let peer_id = network.node(1).peer_id.clone();
+        let address = network.node(1).address.clone();
+        let introduce_as = introduce_as.to_string();
+        let command = move |response| NodeCommand::ProposePeering {
+            peer_id,
+            addresses: vec![address],
+            name: Some("bob".to_string()),
+            recommender_quality: 0.7,
+            tags: vec!["work".to_string()],
+            introduce_as: Some(introduce_as),
+            note: Some("met at the meetup".to_string()),
+            response,
+        };
+        async move {
+            let (response, sent) = oneshot::channel();
+            commands.send(command(response)).await.unwrap();
+            sent.await.unwrap()
+        }
+    };
+    let peers = |index: usize| {
+        let commands = network.node(index).commands.clone();
+        async move {
+            let (response, peers) = oneshot::channel();
+            commands.send(NodeCommand::GetPeers { response }).await.unwrap();
+            peers.await.unwrap().unwrap().into_iter().map(|status| status.peer).collect::<Vec<_>>()
+        }
+    };
+    let bob = network.node(1).commands.clone();
+    let proposals = || {
+        let bob = bob.clone();
+        async move {
+            let (response, requests) = oneshot::channel();
+            bob.send(NodeCommand::GetPeerRequests { response }).await.unwrap();
+            requests.await.unwrap().unwrap().into_iter().filter(|r| r.proposal.is_some()).collect::<Vec<_>>()
+        }
+    };
+
+    propose(0, "alice").await.unwrap();
+    propose(2, "carol").await.unwrap();
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    while proposals().await.len() < 2 {
+        assert!(tokio::time::Instant::now() < deadline, "proposals never reached bob");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    // Nobody is added before bob decides
+    assert!(peers(0).await.is_empty());
+    assert!(peers(1).await.is_empty());
+
+    // Accepting alice lists her the way she introduced herself, in the circle bob picks rather than hers
+    let (response, accepted) = oneshot::channel();
+    let peer_id = network.node(0).peer_id.clone();
+    let tags = vec!["meetup".to_string()];
+    bob.send(NodeCommand::AcceptPeerRequest { peer_id, name: None, quality: None, tags, response }).await.unwrap();
+    let alice = accepted.await.unwrap().unwrap();
+    assert_eq!((alice.name.as_str(), alice.tags.clone()), ("alice", vec!["meetup".to_string()]));
+    assert_eq!(alice.notes.as_deref(), Some("met at the meetup"));
+
+    // alice's node adds bob with what she proposed once the answer arrives
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    let bob_at_alice = loop {
+        if let Some(peer) = peers(0).await.into_iter().find(|p| p.peer_id == network.node(1).peer_id) {
+            break peer;
+        }
+        assert!(tokio::time::Instant::now() < deadline, "proposer never added the accepting peer");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    assert_eq!((bob_at_alice.name.as_str(), bob_at_alice.recommender_quality), ("bob", 0.7));
+
+    // Ignoring carol declines her proposal, so neither side lists the other
+    let (response, ignored) = oneshot::channel();
+    let peer_id = network.node(2).peer_id.clone();
+    bob.send(NodeCommand::SetPeerRequestStatus { peer_id, status: PeerRequestStatus::Ignored, response }).await.unwrap();
+    ignored.await.unwrap().unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(peers(2).await.is_empty());
+    assert_eq!(peers(1).await.len(), 1);
+}
+
+#[tokio::test]
+async fn test_reciprocity_counts_both_directions() {
+    let mut network = Network::spawn(2, NodeConfig::default()).await.unwrap();
+    network.befriend(0, 1, 1.0).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    network.add_experience(1, "test", "vendor", 1.5, 100.0).await.unwrap();
+    network.query_until(0, "test", "vendor", 1, TIMEOUT, |s| s.score.total_volume > 0.0).await.unwrap();
+
+    let reciprocity = |index: usize| {
+        let commands = network.node(index).commands.clone();
+        async move {
+            let (response, reciprocity) = oneshot::channel();
+            commands.send(NodeCommand::GetReciprocity { response }).await.unwrap();
+            reciprocity.await.unwrap().unwrap()
+        }
+    };
+    let alice = reciprocity(0).await;
+    assert_eq!(alice.len(), 1);
+    assert_eq!(alice[0].peer_id, network.node(1).peer_id);
+    assert!(alice[0].traffic.queries_sent >= 1 && alice[0].traffic.scores_received >= 1);
+    assert_eq!(alice[0].traffic.queries_received, 0);
+
+    // bob answered each query alice sent
+    let bob = reciprocity(1).await;
+    assert_eq!(bob[0].peer_id, network.node(0).peer_id);
+    assert_eq!(bob[0].traffic.queries_received, alice[0].traffic.queries_sent);
+    assert_eq!(bob[0].traffic.answers_sent, alice[0].traffic.answers_received);
+    assert!(bob[0].traffic.bytes_sent > 0);
+    assert_eq!(bob[0].query_ratio, None);
+}
+
+#[tokio::test]
+async fn test_expired_queries_are_not_forwarded() {
+    let mut network = Network::spawn(2, NodeConfig::default()).await.unwrap();
+    network.befriend(0, 1, 1.0).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    network.add_experience(0, "test", "vendor", 1.0, 100.0).await.unwrap();
+    network.add_experience(1, "test", "vendor", 1.5, 100.0).await.unwrap();
+
+    // Too late to ask bob, alice answers from her own experience
+    let expired = TrustQuery {
+        expires_at: Some(Utc::now() - ChronoDuration::seconds(1)),
+        ..network.trust_query("test", "vendor", 1)
+    };
+    let score = network.query_with(0, expired).await.unwrap();
+    assert_eq!(score.score.total_volume, 100.0);
+
+    let in_time = TrustQuery {
+        expires_at: Some(Utc::now() + ChronoDuration::seconds(30)),
+        ..network.trust_query("test", "vendor", 1)
+    };
+    let score = network.query_with(0, in_time).await.unwrap();
+    assert!((score.score.total_volume - 200.0).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn test_snapshot_exports_hold_what_was_known_then() {
+    use futures::StreamExt;
+
+    let mut network = Network::spawn(2, NodeConfig::default()).await.unwrap();
+    network.befriend(0, 1, 1.0).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    let before = Utc::now() - ChronoDuration::seconds(1);
+    network.add_experience(0, "test", "vendor", 1.0, 100.0).await.unwrap();
+    network.add_experience(1, "test", "vendor", 1.5, 100.0).await.unwrap();
+    network.query_until(0, "test", "vendor", 1, TIMEOUT, |s| s.score.total_volume >= 200.0).await.unwrap();
+
+    let network = &network;
+    let snapshot = |point_in_time| async move {
+        let (response, rx) = oneshot::channel();
+        let command = NodeCommand::ExportTrustData { point_in_time: Some(point_in_time), response };
+        network.node(0).commands.send(command).await.unwrap();
+        rx.await.unwrap().unwrap()
+    };
+
+    // alice's own experience and what bob told her both count
+    let now = snapshot(Utc::now()).await;
+    assert_eq!(now.experiences.count().await, 1);
+    assert_eq!(now.scores.len(), 1);
+    assert!((now.scores[0].score.total_volume - 200.0).abs() < 1e-9);
+    assert!((now.scores[0].score.expected_pv_roi - 1.25).abs() < 1e-9);
+
+    let earlier = snapshot(before).await;
+    assert_eq!(earlier.experiences.count().await, 0);
+    assert!(earlier.scores.is_empty());
+}
+
+#[tokio::test]
+async fn test_peers_tell_us_what_they_think_of_our_verified_agents() {
+    // bob and carol are alice's peers, but only bob lists alice back
+    let mut network = Network::spawn(3, NodeConfig::default()).await.unwrap();
+    network.befriend_all(&[(0, 1, 1.0), (0, 2, 1.0), (1, 0, 1.0)]).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    network.add_experience(1, "shop", "alices-shop", 1.4, 100.0).await.unwrap();
+    network.add_experience(2, "shop", "alices-shop", 0.8, 100.0).await.unwrap();
+    let alice = network.node(0).commands.clone();
+
+    let (response, claim) = oneshot::channel();
+    let command = NodeCommand::ClaimAgent { id_domain: "shop".to_string(), agent_id: "alices-shop".to_string(), response };
+    alice.send(command).await.unwrap();
+    let claim = claim.await.unwrap().unwrap();
+    assert!(!claim.is_verified());
+
+    // Unverified claims aren't asked about
+    let (response, opinions) = oneshot::channel();
+    alice.send(NodeCommand::QueryOwnReputation { response }).await.unwrap();
+    assert!(opinions.await.unwrap().is_err());
+
+    // The challenge goes up on a page only alice controls
+    let challenge = claim.challenge.clone();
+    let app = axum::Router::new().route("/about", axum::routing::get(move || async move { challenge }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proof_url = format!("http://{}/about", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let verify = |proof_url: String| {
+        let alice = alice.clone();
+        async move {
+            let (response, verified) = oneshot::channel();
+            let id_domain = "shop".to_string();
+            let agent_id = "alices-shop".to_string();
+            alice.send(NodeCommand::VerifyAgentClaim { id_domain, agent_id, proof_url, response }).await.unwrap();
+            verified.await.unwrap()
+        }
+    };
+    assert!(verify(proof_url.replace("/about", "/elsewhere")).await.is_err());
+    assert!(verify(proof_url).await.unwrap().is_verified());
+
+    // carol's policy only answers her own peers
+    let (response, opinions) = oneshot::channel();
+    alice.send(NodeCommand::QueryOwnReputation { response }).await.unwrap();
+    let opinions = opinions.await.unwrap().unwrap();
+    assert_eq!(opinions.len(), 2);
+    let bob = opinions.iter().find(|o| o.peer_id == network.node(1).peer_id).unwrap();
+    assert_eq!(bob.scores.len(), 1);
+    assert!((bob.scores[0].score.expected_pv_roi - 1.4).abs() < 1e-9);
+    let carol = opinions.iter().find(|o| o.peer_id == network.node(2).peer_id).unwrap();
+    assert!(carol.scores.is_empty());
+    assert_eq!(carol.error.as_deref(), Some("refused"));
+}
+
+#[tokio::test]
+async fn test_blocked_peers_are_refused_even_when_anyone_is_answered() {
+    let config = NodeConfig { reputation_policy: ReputationPolicy::Anyone, ..NodeConfig::default() };
+    let mut network = Network::spawn(2, config).await.unwrap();
+    network.befriend(0, 1, 1.0).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    network.add_experience(1, "shop", "alices-shop", 1.4, 100.0).await.unwrap();
+    let alice = network.node(0).commands.clone();
+
+    let (response, claim) = oneshot::channel();
+    let command = NodeCommand::ClaimAgent { id_domain: "shop".to_string(), agent_id: "alices-shop".to_string(), response };
+    alice.send(command).await.unwrap();
+    let challenge = claim.await.unwrap().unwrap().challenge;
+    let app = axum::Router::new().route("/about", axum::routing::get(move || async move { challenge }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proof_url = format!("http://{}/about", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    let (response, verified) = oneshot::channel();
+    let (id_domain, agent_id) = ("shop".to_string(), "alices-shop".to_string());
+    alice.send(NodeCommand::VerifyAgentClaim { id_domain, agent_id, proof_url, response }).await.unwrap();
+    verified.await.unwrap().unwrap();
+
+    let opinions = || {
+        let alice = alice.clone();
+        async move {
+            let (response, opinions) = oneshot::channel();
+            alice.send(NodeCommand::QueryOwnReputation { response }).await.unwrap();
+            opinions.await.unwrap().unwrap().remove(0)
+        }
+    };
+    // bob doesn't list alice, but checks her proof and answers anyone
+    assert_eq!(opinions().await.scores.len(), 1);
+
+    let (response, blocked) = oneshot::channel();
+    let peer_id = network.node(0).peer_id.clone();
+    network.node(1).commands.send(NodeCommand::SetPeerRequestStatus { peer_id, status: PeerRequestStatus::Blocked, response }).await.unwrap();
+    blocked.await.unwrap().unwrap();
+    assert_eq!(opinions().await.error.as_deref(), Some("refused"));
+}
+
+#[tokio::test]
+async fn test_peers_without_data_are_not_asked_again_for_a_while() {
+    let mut network = Network::spawn(2, NodeConfig::default()).await.unwrap();
+    network.befriend(0, 1, 1.0).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+
+    // bob knows nothing of the vendor, so the second query doesn't go to him
+    for _ in 0..2 {
+        assert!(network.query(0, "test", "vendor", 1).await.is_err());
+    }
+    let alice = network.node(0).commands.clone();
+    let stats = || {
+        let alice = alice.clone();
+        async move {
+            let (response, stats) = oneshot::channel();
+            alice.send(NodeCommand::GetQueryStats { response }).await.unwrap();
+            stats.await.unwrap().unwrap()
+        }
+    };
+    assert_eq!(stats().await.skipped_no_data, 1);
+
+    // A refresh asks him anyway and finds what he's learned since
+    network.add_experience(1, "test", "vendor", 1.5, 100.0).await.unwrap();
+    let refresh = TrustQuery { refresh: true, ..network.trust_query("test", "vendor", 1) };
+    assert_eq!(network.query_with(0, refresh).await.unwrap().score.total_volume, 100.0);
+    assert_eq!(stats().await.skipped_no_data, 1);
+}
+
+#[tokio::test]
+async fn test_connections_to_good_peers_outlive_the_idle_timeout() {
+    let config = NodeConfig {
+        idle_connection_timeout: Duration::from_millis(300),
+        keep_alive_quality: Some(0.8),
+        ..NodeConfig::default()
+    };
+    let mut network = Network::spawn(3, config).await.unwrap();
+    // Either side closes a connection it lets idle out, so alice and bob both keep theirs
+    network.befriend_all(&[(0, 1, 0.9), (1, 0, 0.9), (0, 2, 0.5)]).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+
+    // carol's connection idles out, bob's is kept however quiet it gets
+    let alice = network.node(0).commands.clone();
+    let connected = || {
+        let alice = alice.clone();
+        async move {
+            let (response, peers) = oneshot::channel();
+            alice.send(NodeCommand::GetConnectedPeers { response }).await.unwrap();
+            peers.await.unwrap().unwrap()
+        }
+    };
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(4);
+    while connected().await.contains(&network.node(2).peer_id) {
+        assert!(tokio::time::Instant::now() < deadline, "carol's connection never idled out");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(connected().await.contains(&network.node(1).peer_id));
+}
+
+#[tokio::test]
+async fn test_out_of_bounds_scores_are_not_cached() {
+    let config = NodeConfig { quarantine: Some(QuarantinePolicy { max_violations: 1, ..QuarantinePolicy::default() }), ..NodeConfig::default() };
+    let mut network = Network::spawn(2, config).await.unwrap();
+    network.befriend(0, 1, 1.0).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    // bob claims more volume than the default bounds allow
+    network.add_experience(0, "test", "vendor", 1.0, 100.0).await.unwrap();
+    network.add_experience(1, "test", "vendor", 5.0, 1e20).await.unwrap();
+
+    let score = network.query(0, "test", "vendor", 1).await.unwrap();
+    assert_eq!(score.score.total_volume, 100.0);
+    assert_eq!(score.score.expected_pv_roi, 1.0);
+
+    let (response, stats) = oneshot::channel();
+    network.node(0).commands.send(NodeCommand::GetQueryStats { response }).await.unwrap();
+    assert_eq!(stats.await.unwrap().unwrap().rejected_scores, 1);
+    let cached = network.query_with(0, TrustQuery { cache_only: true, ..network.trust_query("test", "vendor", 1) }).await.unwrap();
+    assert_eq!(cached.score.total_volume, 100.0);
+
+    // Too much volume for alice's bounds isn't a protocol violation, so bob isn't quarantined for it
+    let (response, quarantined) = oneshot::channel();
+    network.node(0).commands.send(NodeCommand::GetQuarantinedPeers { response }).await.unwrap();
+    assert!(quarantined.await.unwrap().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_idle_prefetch_warms_caches_from_the_best_peers() {
+    let config = NodeConfig {
+        refresh_interval: Some(Duration::from_millis(200)),
+        prefetch_peers: 1,
+        ..NodeConfig::default()
+    };
+    let mut network = Network::spawn(3, config).await.unwrap();
+    // alice trusts bob more than carol, so a prefetch only asks bob
+    network.befriend_all(&[(0, 1, 0.9), (0, 2, 0.7)]).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    for (node, pv_roi) in [(1, 1.5), (2, 0.5)] {
+        network.add_experience(node, "test", "vendor", pv_roi, 100.0).await.unwrap();
+        network.add_experience(node, "test", "gadget", pv_roi, 100.0).await.unwrap();
+    }
+
+    // alice watches the vendor and has asked about the gadget, both without reaching her peers
+    let (response, watched) = oneshot::channel();
+    let request = WatchRequest { thresholds: vec![1.0], min_delta: None, webhook: None };
+    network.node(0).commands
+        .send(NodeCommand::WatchAgent { id_domain: "test".to_string(), agent_id: "vendor".to_string(), request, response })
+        .await
+        .unwrap();
+    watched.await.unwrap().unwrap();
+    assert!(network.query(0, "test", "gadget", 0).await.is_err());
+
+    // Once she has been idle for a while, both are answered at depth 0 from what bob told her, weighted by his quality
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    for agent in ["vendor", "gadget"] {
+        let score = network.query(0, "test", agent, 0).await.unwrap();
+        assert!((score.score.total_volume - 90.0).abs() < 1e-9, "{}", agent);
+        assert!((score.score.expected_pv_roi - 1.5).abs() < 1e-9, "{}", agent);
+    }
+}
+
+#[tokio::test]
+async fn test_new_agents_reach_availability_filters_before_the_next_exchange() {
+    // Filters are swapped once a minute, far longer than the test waits
+    let config = NodeConfig { availability_interval: Some(Duration::from_secs(60)), ..NodeConfig::default() };
+    let mut network = Network::spawn(2, config).await.unwrap();
+    network.befriend(0, 1, 1.0).await.unwrap();
+    network.wait_connected(TIMEOUT).await.unwrap();
+    network.add_experience(1, "test", "vendor", 1.5, 100.0).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // alice holds bob's filter from when they connected, and it already rules the gadget out
+    network.add_experience(1, "test", "gadget", 0.5, 100.0).await.unwrap();
+    let score = network
+        .query_until(0, "test", "gadget", 1, TIMEOUT, |s| s.score.total_volume > 0.0)
+        .await
+        .unwrap();
+    assert!((score.score.expected_pv_roi - 0.5).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn test_moving_an_experience_rescores_both_watched_agents() {
+    let network = Network::spawn(1, NodeConfig::default()).await.unwrap();
+    let alice = network.node(0).commands.clone();
+    let experience = TrustExperience {
+        id: Uuid::new_v4(),
+        id_domain: "test".to_string(),
+        agent_id: "vendor".to_string(),
+        pv_roi: 1.5,
+        invested_volume: 100.0,
+        timestamp: Utc::now(),
+        notes: None,
+        data: None,
+    };
+    let (response, added) = oneshot::channel();
+    alice.send(NodeCommand::AddExperience { experience: experience.clone(), response }).await.unwrap();
+    added.await.unwrap().unwrap();
+    for agent_id in ["vendor", "gadget"] {
+        let (response, watched) = oneshot::channel();
+        let request = WatchRequest { thresholds: Vec::new(), min_delta: Some(0.1), webhook: None };
+        alice.send(NodeCommand::WatchAgent { id_domain: "test".to_string(), agent_id: agent_id.to_string(), request, response }).await.unwrap();
+        watched.await.unwrap().unwrap();
+    }
+    let (response, events) = oneshot::channel();
+    alice.send(NodeCommand::SubscribeEvents { response }).await.unwrap();
+    let mut events = events.await.unwrap().unwrap();
+
+    // The experience turns out to be about the gadget, which moves both scores
+    let update = ExperienceUpdate { agent_id: Some("gadget".to_string()), ..Default::default() };
+    let (response, updated) = oneshot::channel();
+    alice.send(NodeCommand::UpdateExperience { experience_id: experience.id.to_string(), update, response }).await.unwrap();
+    updated.await.unwrap().unwrap();
+
+    let mut changed = Vec::new();
+    while changed.len() < 2 {
+        match tokio::time::timeout(TIMEOUT, events.recv()).await.unwrap().unwrap() {
+            NodeEvent::ScoreChanged(change) => changed.push(change.agent_id),
+            _ => continue,
+        }
+    }
+    changed.sort();
+    assert_eq!(changed, ["gadget", "vendor"]);
+}
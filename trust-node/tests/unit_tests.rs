@@ -1,12 +1,38 @@
 use trust_node::{
+    config::DialPriority,
+    eigentrust,
+    graph,
+    events::{PeerEvent, PeerEventKind, PeerEventLog},
+    merge,
     query_engine::QueryEngine,
+    protocols::{agents_after_continuation, encode_response_page, merge_responses, TrustResponseInternal},
     storage::{Storage, SqliteStorage},
-    types::{TrustExperience, Peer},
+    types::{AgentClaim, AgentIdForm, AgentIdentifier, AgentScore, AnswerPrivacy, Aggregator, CachedTrustScore, DecayFunction, Discounting, DomainDefaults, ExperienceFilter, ExperienceSort, ExperienceTemplate, Freshness, IndexPoint, InflationIndex, InfluenceCaps, MinEvidence, PeerAddress, PeerProposal, PeeringAnswerOut, PeerReciprocity, PeerRequestStatus, PeerTiming, PeerTraffic, PendingExperience, ProposedPeer, QualityDecay, ScoringOptions, TrustExperience, TrustQuery, Peer, PeerUpdate, QueryLimits, QueryTiming, RatePoint, RuntimeConfig, RuntimeConfigUpdate, ScoreBounds, ScoreClamping, SharingPrecision, TrustResponse, TrustScore, WatchRequest, WatchedAgent, WireError, is_valid_correlation_id, is_valid_peer_handle, template_never_due, MAX_PEER_ADDRESSES, MAX_TIMING_PEERS},
 };
 use uuid::Uuid;
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
 use std::sync::Arc;
 
+/// A database that lives only as long as the test
+async fn memory_storage() -> SqliteStorage {
+    SqliteStorage::new(&std::path::PathBuf::from(":memory:")).await.unwrap()
+}
+
+/// An experience with `agent_id` in the "test" domain, made just now
+fn experience(agent_id: &str, pv_roi: f64, invested_volume: f64) -> TrustExperience {
+    TrustExperience {
+        id: Uuid::new_v4(),
+        id_domain: "test".to_string(),
+        agent_id: agent_id.to_string(),
+        pv_roi,
+        invested_volume,
+        timestamp: Utc::now(),
+        notes: None,
+        data: None,
+    }
+}
+
 #[tokio::test]  
 async fn test_storage_operations() {
     // Use an in-memory database for testing
@@ -105,9 +131,15 @@ async fn test_peer_management() {
     // Test adding and retrieving peers
     let peer = Peer {
         peer_id: "test_peer".to_string(),
+        addresses: vec![],
+        handle: None,
         name: "Test Peer".to_string(),
         recommender_quality: 0.8,
         added_at: Utc::now(),
+        notes: None,
+        tags: vec![],
+        contact: Default::default(),
+        last_interaction_at: None,
     };
 
     storage.add_peer(peer.clone()).await.unwrap();
@@ -116,4 +148,1442 @@ async fn test_peer_management() {
     assert_eq!(peers.len(), 1);
     assert_eq!(peers[0].peer_id, peer.peer_id);
     assert_eq!(peers[0].recommender_quality, peer.recommender_quality);
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_quality_changes_are_kept_in_history() {
+    let storage = memory_storage().await;
+
+    let mut peer = Peer {
+        peer_id: "friend".to_string(),
+        addresses: vec![],
+        handle: None,
+        name: "Friend".to_string(),
+        recommender_quality: 0.5,
+        added_at: Utc::now(),
+        notes: None,
+        tags: vec![],
+        contact: Default::default(),
+        last_interaction_at: None,
+    };
+    storage.add_peer(peer.clone()).await.unwrap();
+    storage.update_peer_quality("friend", 0.8, "good tips on NFTs").await.unwrap();
+
+    // Edits that leave the quality alone don't show up
+    peer.recommender_quality = 0.8;
+    peer.notes = Some("met at a meetup".to_string());
+    storage.update_peer(&peer).await.unwrap();
+    peer.recommender_quality = 0.3;
+    storage.update_peer(&peer).await.unwrap();
+
+    let history = storage.get_quality_history("friend").await.unwrap();
+    let changes: Vec<(Option<f64>, f64, &str)> = history
+        .iter()
+        .map(|revision| (revision.previous_quality, revision.quality, revision.reason.as_str()))
+        .collect();
+    assert_eq!(changes, [(Some(0.8), 0.3, "edited"), (Some(0.5), 0.8, "good tips on NFTs"), (None, 0.5, "added")]);
+    assert!(storage.update_peer_quality("stranger", 0.1, "set").await.is_err());
+}
+
+#[tokio::test]
+async fn test_global_trust_follows_agreement_with_own_experiences() {
+    let storage = Arc::new(memory_storage().await);
+    let query_engine = QueryEngine::new(storage.clone());
+    let now = Utc::now();
+
+    for (agent_id, pv_roi) in [("good", 1.4), ("bad", 0.6)] {
+        storage.add_experience(TrustExperience { timestamp: now, ..experience(agent_id, pv_roi, 100.0) }).await.unwrap();
+    }
+    // The honest peer agrees with us; the sybils agree only with each other and vouch for an unknown agent
+    let recommendations = [
+        ("honest", "good", 1.3), ("honest", "bad", 0.7), ("honest", "new", 1.2),
+        ("sybil1", "good", 0.5), ("sybil1", "bad", 1.8), ("sybil1", "new", 0.4),
+        ("sybil2", "good", 0.5), ("sybil2", "bad", 1.8), ("sybil2", "new", 0.4),
+    ];
+    for (from_peer, agent_id, roi) in recommendations {
+        storage.cache_trust_score(CachedTrustScore {
+            id_domain: "test".to_string(),
+            agent_id: agent_id.to_string(),
+            score: TrustScore::new(roi, 100.0, 1),
+            from_peer: from_peer.to_string(),
+            cached_at: now,
+        }).await.unwrap();
+    }
+
+    let global = eigentrust::compute(storage.as_ref(), &query_engine, now).await.unwrap();
+
+    assert!(global.converged);
+    assert!(global.rater_trust["honest"] > 0.3);
+    assert!(global.rater_trust["sybil1"] < 1e-6);
+    // Two sybils against one honest peer would pull a plain average below 1.0
+    let new = global.score("test", "new").unwrap();
+    assert!((new.expected_pv_roi - 1.2).abs() < 1e-3);
+    assert!(global.score("test", "missing").is_none());
+}
+
+#[test]
+fn test_influence_caps_limit_peers_and_circles() {
+    // Own experiences 400, a loud friend 400, and three fake friends sharing a tag 100 each
+    let contributions = [
+        (None, 400.0),
+        (Some("loud"), 300.0),
+        (Some("loud"), 100.0),
+        (Some("fake1"), 100.0),
+        (Some("fake2"), 100.0),
+        (Some("fake3"), 100.0),
+    ];
+    let circles: std::collections::HashMap<String, Vec<String>> = ["fake1", "fake2", "fake3"]
+        .into_iter()
+        .map(|peer| (peer.to_string(), vec!["farm".to_string()]))
+        .collect();
+
+    let caps = InfluenceCaps { max_peer_share: Some(0.25), max_circle_share: Some(0.1) };
+    let (factors, capped) = caps.apply(&contributions, &circles);
+
+    // Own experiences stay and make up the remaining 65%, so the loud friend ends up with 25% of what's
+    // merged and the whole farm with 10%
+    let capped_volumes: Vec<f64> = contributions.iter().zip(&factors).map(|((_, volume), factor)| volume * factor).collect();
+    let total: f64 = capped_volumes.iter().sum();
+    assert_eq!(capped_volumes[0], 400.0);
+    assert!((total - 400.0 / 0.65).abs() < 1e-6);
+    assert!((capped_volumes[1] + capped_volumes[2] - 0.25 * total).abs() < 1e-6);
+    assert!((capped_volumes[3..].iter().sum::<f64>() - 0.1 * total).abs() < 1e-6);
+    let sources: Vec<&str> = capped.iter().map(|c| c.source.as_str()).collect();
+    assert_eq!(sources, ["loud", "circle:farm"]);
+    assert_eq!(capped[0].volume, 400.0);
+
+    // Three equal friends can't each stay below 25% on their own, so none is capped at all;
+    // two friends that could be are evened out rather than cut to nothing
+    let friends = [(Some("a"), 100.0), (Some("b"), 100.0), (Some("c"), 100.0)];
+    let caps = InfluenceCaps { max_peer_share: Some(0.25), max_circle_share: None };
+    let (factors, _) = caps.apply(&friends, &Default::default());
+    assert!(factors.iter().all(|f| *f == 1.0));
+    let (factors, capped) = caps.apply(&[(Some("a"), 300.0), (Some("b"), 100.0)], &Default::default());
+    assert_eq!(factors, [1.0 / 3.0, 1.0]);
+    assert_eq!(capped.len(), 1);
+    assert!(!InfluenceCaps::valid_share(0.0) && !InfluenceCaps::valid_share(1.5) && InfluenceCaps::valid_share(1.0));
+
+    let (factors, capped) = InfluenceCaps::default().apply(&contributions, &circles);
+    assert!(factors.iter().all(|f| *f == 1.0));
+    assert!(capped.is_empty());
+}
+
+#[tokio::test]
+async fn test_bulk_insert_is_atomic() {
+    let storage = memory_storage().await;
+
+    let bulk = experience("bulk_agent", 1.0, 10.0);
+
+    // A duplicate id inside the batch makes the whole batch fail
+    let result = storage.add_experiences(vec![bulk.clone(), bulk.clone()]).await;
+    assert!(result.is_err());
+    assert!(storage.get_experiences("test", "bulk_agent").await.unwrap().is_empty());
+
+    storage.add_experiences(vec![bulk]).await.unwrap();
+    assert_eq!(storage.get_experiences("test", "bulk_agent").await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_import_replaces_records_atomically() {
+    let storage = memory_storage().await;
+
+    let original = experience("imported_agent", 1.0, 10.0);
+    storage.add_experience(original.clone()).await.unwrap();
+    let replacement = TrustExperience { pv_roi: 1.5, ..original.clone() };
+    let replaced = vec![original.id.to_string()];
+
+    // Replacing a peer that isn't there fails the import without losing the experience it would replace
+    let result = storage.import_records(&replaced, vec![replacement.clone()], &["ghost".to_string()], vec![]).await;
+    assert!(result.is_err());
+    let kept = storage.get_experiences("test", "imported_agent").await.unwrap();
+    assert_eq!(kept.len(), 1);
+    assert_eq!(kept[0].pv_roi, 1.0);
+    assert!(storage.get_experience_history(&replaced[0]).await.unwrap().is_empty());
+
+    storage.import_records(&replaced, vec![replacement], &[], vec![]).await.unwrap();
+    let imported = storage.get_experiences("test", "imported_agent").await.unwrap();
+    assert_eq!(imported.len(), 1);
+    assert_eq!(imported[0].pv_roi, 1.5);
+    assert_eq!(storage.get_experience_history(&replaced[0]).await.unwrap().len(), 1);
+    let aggregate = storage.get_agent_aggregate("test", "imported_agent").await.unwrap().unwrap();
+    assert_eq!(aggregate.data_points, 1);
+}
+
+#[tokio::test]
+async fn test_pending_experiences_count_once_settled() {
+    let storage = memory_storage().await;
+
+    let invested_at = Utc::now() - Duration::days(365);
+    let pending = PendingExperience {
+        id: Uuid::new_v4(),
+        id_domain: "test".to_string(),
+        agent_id: "slow_vendor".to_string(),
+        invested_volume: 100.0,
+        timestamp: invested_at,
+        notes: None,
+        data: None,
+        expected_return: None,
+        template_id: None,
+    };
+    storage.add_pending_experience(pending.clone()).await.unwrap();
+    assert_eq!(storage.get_pending_experiences().await.unwrap().len(), 1);
+    assert!(storage.get_agent_aggregate("test", "slow_vendor").await.unwrap().is_none());
+    assert!(storage.get_experiences("test", "slow_vendor").await.unwrap().is_empty());
+
+    // 110 back after a year at 10% is worth exactly the investment today
+    let settled = pending.clone().settle(110.0, &Discounting::Annual { rate: 0.10 }, invested_at + Duration::days(365));
+    assert!((settled.pv_roi - 1.0).abs() < 1e-9);
+    assert_eq!(settled.timestamp, invested_at);
+    storage.settle_experience(settled.clone()).await.unwrap();
+
+    assert!(storage.get_pending_experiences().await.unwrap().is_empty());
+    let aggregate = storage.get_agent_aggregate("test", "slow_vendor").await.unwrap().unwrap();
+    assert_eq!(aggregate.total_volume, 100.0);
+    assert!(storage.settle_experience(settled).await.is_err());
+}
+
+#[tokio::test]
+async fn test_templates_add_each_missed_occurrence_once() {
+    let storage = memory_storage().await;
+
+    let now = Utc::now();
+    let template = ExperienceTemplate {
+        id: Uuid::new_v4(),
+        id_domain: "test".to_string(),
+        agent_id: "saas_vendor".to_string(),
+        investment: 20.0,
+        expected_return: 25.0,
+        interval_days: 30,
+        next_due_at: now - Duration::days(65),
+        notes: Some("monthly plan".to_string()),
+        created_at: now - Duration::days(65),
+    };
+    storage.add_experience_template(&template).await.unwrap();
+
+    // Two months and five days on, three payments are due and the next one is 25 days away
+    let (occurrences, next_due_at) = template.due(now);
+    assert_eq!(occurrences.len(), 3);
+    assert_eq!(next_due_at, template.next_due_at + Duration::days(90));
+    assert!(occurrences.iter().all(|o| o.template_id == Some(template.id) && o.expected_return == Some(25.0)));
+    storage.record_template_occurrences(&template.id.to_string(), occurrences, next_due_at).await.unwrap();
+
+    let stored = storage.get_experience_templates().await.unwrap();
+    assert!(stored[0].due(now).0.is_empty());
+    let pending = storage.get_pending_experiences().await.unwrap();
+    assert_eq!(pending.len(), 3);
+    assert_eq!(pending[0].timestamp, template.next_due_at);
+    assert!(storage.get_agent_aggregate("test", "saas_vendor").await.unwrap().is_none());
+
+    // A template that can't be found takes nothing with it
+    let orphan = template.due(now).0;
+    assert!(storage.record_template_occurrences(&Uuid::new_v4().to_string(), orphan, now).await.is_err());
+    assert_eq!(storage.get_pending_experiences().await.unwrap().len(), 3);
+
+    // One occurrence short of the end of time, the next one is never due
+    let last = ExperienceTemplate {
+        interval_days: u32::MAX,
+        next_due_at: DateTime::<Utc>::MAX_UTC - Duration::days(1),
+        ..template
+    };
+    let (occurrences, next_due_at) = last.due(DateTime::<Utc>::MAX_UTC);
+    assert_eq!(occurrences.len(), 1);
+    assert_eq!(next_due_at, template_never_due());
+    storage.record_template_occurrences(&template.id.to_string(), Vec::new(), next_due_at).await.unwrap();
+    assert_eq!(storage.get_experience_templates().await.unwrap()[0].next_due_at, template_never_due());
+}
+
+#[tokio::test]
+async fn test_find_experiences_filters_in_sql() {
+    let storage = memory_storage().await;
+
+    let now = Utc::now();
+    let experiences = vec![
+        ("ethereum", 1000.0, now, Some("big trade")),
+        ("ethereum", 10.0, now - Duration::days(30), None),
+        ("aliexpress", 500.0, now - Duration::days(2), None),
+    ];
+    for (id_domain, volume, timestamp, notes) in experiences {
+        storage.add_experience(TrustExperience {
+            id_domain: id_domain.to_string(),
+            timestamp,
+            notes: notes.map(|n| n.to_string()),
+            ..experience("agent", 1.0, volume)
+        }).await.unwrap();
+    }
+
+    let ethereum = storage.find_experiences(&ExperienceFilter {
+        id_domain: Some("ethereum".to_string()),
+        ..Default::default()
+    }).await.unwrap();
+    assert_eq!(ethereum.len(), 2);
+
+    let recent_large = storage.find_experiences(&ExperienceFilter {
+        since: Some(now - Duration::days(7)),
+        min_volume: Some(100.0),
+        ..Default::default()
+    }).await.unwrap();
+    assert_eq!(recent_large.len(), 2);
+
+    let with_notes = storage.find_experiences(&ExperienceFilter {
+        has_notes: Some(true),
+        ..Default::default()
+    }).await.unwrap();
+    assert_eq!(with_notes.len(), 1);
+    assert_eq!(with_notes[0].invested_volume, 1000.0);
+}
+
+#[tokio::test]
+async fn test_experience_search_combines_text_tags_and_sorting() {
+    let storage = memory_storage().await;
+
+    let now = Utc::now();
+    let experiences = vec![
+        (100.0, Some("Fast shipping, great seller"), serde_json::json!({ "tags": ["electronics"] })),
+        (900.0, Some("Shipped a broken phone"), serde_json::json!({ "tags": ["electronics", "refund"] })),
+        (50.0, None, serde_json::json!({ "title": "Shipping container" })),
+    ];
+    for (i, (volume, notes, data)) in experiences.into_iter().enumerate() {
+        storage.add_experience(TrustExperience {
+            id_domain: "aliexpress".to_string(),
+            timestamp: now - Duration::days(i as i64),
+            notes: notes.map(|n| n.to_string()),
+            data: Some(data),
+            ..experience(&format!("seller-{}", i), 1.0, volume)
+        }).await.unwrap();
+    }
+
+    let shipping = ExperienceFilter { q: Some("ship".to_string()), sort: ExperienceSort::Volume, ..Default::default() };
+    let found = storage.find_experiences(&shipping).await.unwrap();
+    assert_eq!(found.iter().map(|e| e.invested_volume).collect::<Vec<_>>(), [900.0, 100.0, 50.0]);
+
+    let tagged = ExperienceFilter { tag: Some("electronics".to_string()), limit: Some(1), ..Default::default() };
+    let page = storage.find_experiences(&tagged).await.unwrap();
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].agent_id, "seller-0");
+    assert_eq!(storage.count_experiences(&tagged).await.unwrap(), 2);
+
+    // FTS syntax in a search is taken literally rather than failing the query
+    let odd = ExperienceFilter { q: Some("\"broken phone".to_string()), ..Default::default() };
+    assert_eq!(storage.count_experiences(&odd).await.unwrap(), 1);
+
+    // A blank search ranks nothing, so relevance falls back to the newest first
+    let blank = ExperienceFilter { q: Some("  ".to_string()), sort: ExperienceSort::Relevance, ..Default::default() };
+    let found = storage.find_experiences(&blank).await.unwrap();
+    assert_eq!(found.iter().map(|e| e.agent_id.as_str()).collect::<Vec<_>>(), ["seller-0", "seller-1", "seller-2"]);
+}
+
+#[tokio::test]
+async fn test_stream_experiences_pages_through_everything() {
+    use futures::TryStreamExt;
+
+    let storage = memory_storage().await;
+
+    // More than one page, with identical timestamps to exercise the id tie-breaker
+    let timestamp = Utc::now();
+    let experiences: Vec<TrustExperience> = (0..1203)
+        .map(|i| TrustExperience { timestamp, ..experience(&format!("agent{}", i % 7), 1.0, 1.0) })
+        .collect();
+    storage.add_experiences(experiences).await.unwrap();
+
+    let streamed: Vec<TrustExperience> = storage.stream_experiences().try_collect().await.unwrap();
+    assert_eq!(streamed.len(), 1203);
+
+    let unique: std::collections::HashSet<Uuid> = streamed.iter().map(|e| e.id).collect();
+    assert_eq!(unique.len(), 1203);
+}
+
+#[tokio::test]
+async fn test_experience_history_keeps_prior_versions() {
+    let storage = memory_storage().await;
+
+    let mut edited = experience("edited_agent", 1.0, 100.0);
+    let id = edited.id.to_string();
+    storage.add_experience(edited.clone()).await.unwrap();
+
+    edited.pv_roi = 0.5;
+    storage.update_experience(edited.clone(), Some("tester")).await.unwrap();
+    storage.remove_experience(&id).await.unwrap();
+
+    let history = storage.get_experience_history(&id).await.unwrap();
+    assert_eq!(history.len(), 2);
+    // Newest first: the delete captured the edited version, the update the original
+    assert_eq!(history[0].previous.pv_roi, 0.5);
+    assert_eq!(history[1].previous.pv_roi, 1.0);
+    assert_eq!(history[1].changed_by.as_deref(), Some("tester"));
+}
+
+#[tokio::test]
+async fn test_peer_notes_tags_and_contact_roundtrip() {
+    let storage = memory_storage().await;
+
+    let mut peer = Peer {
+        peer_id: "12D3KooWtest".to_string(),
+        addresses: vec![],
+        handle: None,
+        name: "Alice".to_string(),
+        recommender_quality: 0.5,
+        added_at: Utc::now(),
+        notes: None,
+        tags: vec![],
+        contact: Default::default(),
+        last_interaction_at: None,
+    };
+    storage.add_peer(peer.clone()).await.unwrap();
+
+    PeerUpdate {
+        notes: Some("Met at the local meetup".to_string()),
+        tags: Some(vec!["friend".to_string(), "dev".to_string()]),
+        email: Some("alice@example.org".to_string()),
+        ..Default::default()
+    }
+    .apply_to(&mut peer);
+    storage.update_peer(&peer).await.unwrap();
+
+    let stored = storage.get_peers().await.unwrap().remove(0);
+    assert_eq!(stored.notes.as_deref(), Some("Met at the local meetup"));
+    assert_eq!(stored.tags, vec!["friend", "dev"]);
+    assert_eq!(stored.contact.email.as_deref(), Some("alice@example.org"));
+    assert_eq!(stored.contact.fediverse, None);
+}
+
+#[tokio::test]
+async fn test_legacy_multiaddr_peer_ids_are_split() {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("node.db");
+    let storage = SqliteStorage::new(&db_path).await.unwrap();
+
+    // Added through the API the old way, with the address folded into the id
+    storage.add_peer(Peer {
+        peer_id: "/ip4/10.0.0.1/tcp/9001/p2p/12D3KooWbob".to_string(),
+        addresses: vec![],
+        handle: None,
+        name: "Bob".to_string(),
+        recommender_quality: 0.7,
+        added_at: Utc::now(),
+        notes: None,
+        tags: vec![],
+        contact: Default::default(),
+        last_interaction_at: None,
+    }).await.unwrap();
+    let bob = storage.get_peers().await.unwrap().remove(0);
+    assert_eq!(bob.peer_id, "12D3KooWbob");
+    assert_eq!(bob.addresses, vec![PeerAddress::new("/ip4/10.0.0.1/tcp/9001")]);
+    drop(storage);
+
+    // Rows written by older nodes are moved over when the database is opened
+    let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_path.display())).await.unwrap();
+    sqlx::query("INSERT INTO peers (peer_id, name, recommender_quality, added_at) VALUES (?1, 'Carol', 0.6, ?2)")
+        .bind("/dns4/carol.example/tcp/9001/p2p/12D3KooWcarol")
+        .bind(Utc::now().to_rfc3339())
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO peers (peer_id, name, recommender_quality, added_at) VALUES (?1, 'Bob', 0.7, ?2)")
+        .bind("/ip4/10.0.0.2/tcp/9001/p2p/12D3KooWbob")
+        .bind(Utc::now().to_rfc3339())
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+
+    let storage = SqliteStorage::new(&db_path).await.unwrap();
+    let mut peers = storage.get_peers().await.unwrap();
+    peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+    assert_eq!(peers.len(), 2);
+    assert_eq!(peers[0].peer_id, "12D3KooWbob");
+    let addresses: Vec<&str> = peers[0].addresses.iter().map(|a| a.address.as_str()).collect();
+    assert_eq!(addresses, vec!["/ip4/10.0.0.1/tcp/9001", "/ip4/10.0.0.2/tcp/9001"]);
+    assert_eq!(peers[1].peer_id, "12D3KooWcarol");
+    assert_eq!(peers[1].addresses, vec![PeerAddress::new("/dns4/carol.example/tcp/9001")]);
+    assert_eq!(storage.get_quality_history("12D3KooWbob").await.unwrap().len(), 1);
+}
+
+#[test]
+fn test_peer_handles_are_short_lowercase_and_not_routes() {
+    assert!(is_valid_peer_handle("bob"));
+    assert!(is_valid_peer_handle("home-vps_2"));
+    assert!(!is_valid_peer_handle(""));
+    assert!(!is_valid_peer_handle("Bob"));
+    assert!(!is_valid_peer_handle("12D3KooWbob"));
+    assert!(!is_valid_peer_handle("bob/quality"));
+    assert!(!is_valid_peer_handle("events"));
+    assert!(!is_valid_peer_handle(&"b".repeat(33)));
+}
+
+#[test]
+fn test_peer_addresses_are_dialed_by_recent_success() {
+    let now = Utc::now();
+    let mut peer = Peer {
+        peer_id: "12D3KooWbob".to_string(),
+        addresses: vec![PeerAddress::new("/ip4/10.0.0.1/tcp/9001"), PeerAddress::new("/dns4/vps.example/tcp/9001")],
+        handle: None,
+        name: "Bob".to_string(),
+        recommender_quality: 0.7,
+        added_at: now,
+        notes: None,
+        tags: vec![],
+        contact: Default::default(),
+        last_interaction_at: None,
+    };
+    peer.record_address_success("/dns4/vps.example/tcp/9001", now - Duration::days(2));
+    peer.record_address_success("/ip4/203.0.113.5/tcp/4001/p2p-circuit", now);
+    let order: Vec<&str> = peer.dial_order().iter().map(|a| a.address.as_str()).collect();
+    assert_eq!(order, ["/ip4/203.0.113.5/tcp/4001/p2p-circuit", "/dns4/vps.example/tcp/9001", "/ip4/10.0.0.1/tcp/9001"]);
+
+    // Announced addresses fill the list, then only replace ones that never worked
+    assert!(!peer.learn_address("/ip4/10.0.0.1/tcp/9001"));
+    for port in 0..10 {
+        peer.learn_address(&format!("/ip4/10.0.0.9/tcp/{}", port));
+    }
+    assert_eq!(peer.addresses.len(), MAX_PEER_ADDRESSES);
+    assert_eq!(peer.addresses.iter().filter(|a| a.last_success_at.is_some()).count(), 2);
+    assert!(peer.addresses.iter().any(|a| a.address == "/ip4/10.0.0.9/tcp/9"));
+
+    // Replacing the list keeps when the remaining addresses last worked; plain strings still parse
+    let update: PeerUpdate = serde_json::from_str(r#"{"addresses": ["/dns4/vps.example/tcp/9001", "/ip4/10.0.0.2/tcp/9001"]}"#).unwrap();
+    update.apply_to(&mut peer);
+    assert_eq!(peer.addresses[0].last_success_at, Some(now - Duration::days(2)));
+    assert_eq!(peer.addresses[1], PeerAddress::new("/ip4/10.0.0.2/tcp/9001"));
+}
+
+#[test]
+fn test_known_peers_are_dialed_by_priority() {
+    let now = Utc::now();
+    let peer = |name: &str, quality: f64, last_interaction_at: Option<chrono::DateTime<Utc>>| Peer {
+        peer_id: format!("12D3KooW{}", name),
+        addresses: vec![],
+        handle: None,
+        name: name.to_string(),
+        recommender_quality: quality,
+        added_at: now - Duration::days(30),
+        notes: None,
+        tags: vec![],
+        contact: Default::default(),
+        last_interaction_at,
+    };
+    let bob = peer("bob", 0.9, None);
+    let carol = peer("carol", 0.5, Some(now));
+    let dave = peer("dave", 0.9, Some(now - Duration::days(1)));
+    let order = |priority: DialPriority| {
+        let mut peers = vec![(&bob, bob.recommender_quality), (&carol, carol.recommender_quality), (&dave, dave.recommender_quality)];
+        priority.sort(&mut peers);
+        peers.into_iter().map(|(peer, _)| peer.name.as_str()).collect::<Vec<_>>()
+    };
+    assert_eq!(order(DialPriority::Quality), ["dave", "bob", "carol"]);
+    assert_eq!(order(DialPriority::Recency), ["carol", "dave", "bob"]);
+    assert_eq!(DialPriority::named("recency"), Ok(DialPriority::Recency));
+    assert!(DialPriority::named("random").is_err());
+}
+
+#[test]
+fn test_peer_scores_must_stay_within_bounds() {
+    let bounds = ScoreBounds { min_roi: 0.0, max_roi: 10.0, max_volume: 1e6, max_data_points: 1000 };
+    assert!(bounds.check(&TrustScore::new(1.2, 5000.0, 3)).is_ok());
+    assert!(bounds.check(&TrustScore::new(1.0, 0.0, 0)).is_ok());
+
+    assert!(bounds.check(&TrustScore::new(f64::NAN, 5000.0, 3)).is_err());
+    assert!(bounds.check(&TrustScore::new(11.0, 5000.0, 3)).is_err());
+    assert!(bounds.check(&TrustScore::new(-0.5, 5000.0, 3)).is_err());
+    assert!(bounds.check(&TrustScore::new(1.0, -5.0, 3)).is_err());
+    assert!(bounds.check(&TrustScore::new(1.0, 1e300, 3)).is_err());
+    assert!(bounds.check(&TrustScore::new(1.0, 5000.0, 1001)).is_err());
+    let reason = bounds.check(&TrustScore::new(1.0, 5000.0, 0)).unwrap_err();
+    assert!(reason.contains("no data points"), "{}", reason);
+
+    // Only what no node could have computed is absurd; the rest is just beyond our own bounds
+    assert!(ScoreBounds::is_absurd(&TrustScore::new(f64::NAN, 5000.0, 3)));
+    assert!(ScoreBounds::is_absurd(&TrustScore::new(1.0, -5.0, 3)));
+    assert!(ScoreBounds::is_absurd(&TrustScore::new(1.0, f64::INFINITY, 3)));
+    assert!(!ScoreBounds::is_absurd(&TrustScore::new(11.0, 5000.0, 3)));
+    assert!(!ScoreBounds::is_absurd(&TrustScore::new(-0.5, 1e300, 3)));
+}
+
+#[test]
+fn test_agent_ids_are_canonicalized_per_domain() {
+    let checksummed = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+    let ethereum = AgentIdForm::for_domain("ethereum");
+    assert_eq!(ethereum.canonicalize(checksummed), "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed");
+    assert_eq!(ethereum.canonicalize(&format!(" ethereum:{} ", checksummed)), "ethereum:0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed");
+    // Not an address, so left as it is
+    assert_eq!(ethereum.canonicalize("vitalik.ETH"), "vitalik.ETH");
+    // A mistyped checksum is rejected, and kept apart from the address it was meant to be
+    let mistyped = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD";
+    for valid in [checksummed, "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359", "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb"] {
+        assert!(ethereum.check(valid).is_ok(), "{}", valid);
+    }
+    assert!(ethereum.check(&checksummed.to_ascii_uppercase().replacen("0X", "0x", 1)).is_ok());
+    assert!(ethereum.check(mistyped).is_err());
+    assert_eq!(ethereum.canonicalize(mistyped), mistyped);
+    assert!(AgentIdForm::Trimmed.check(mistyped).is_ok());
+
+    let hostname = AgentIdForm::for_domain("domain");
+    for spelling in ["example.com", "www.Example.com", "https://www.example.com/", " EXAMPLE.COM. "] {
+        assert_eq!(hostname.canonicalize(spelling), "example.com");
+    }
+
+    assert_eq!(AgentIdForm::for_domain("aliexpress").canonicalize("  1005006 \n"), "1005006");
+    assert_eq!(AgentIdForm::Lowercase.canonicalize(" Seller-A "), "seller-a");
+    assert_eq!("hostname".parse::<AgentIdForm>(), Ok(AgentIdForm::Hostname));
+}
+
+#[test]
+fn test_discounting_models() {
+    // 110 back after a year
+    let pv = |discounting: Discounting| discounting.pv_roi(100.0, 110.0, 365.0);
+    assert!((pv(Discounting::Annual { rate: 0.10 }) - 1.0).abs() < 1e-9);
+    assert!((pv(Discounting::Continuous { rate: 0.10 }) - 1.1 * (-0.1f64).exp()).abs() < 1e-9);
+    assert!((pv(Discounting::Zero) - 1.1).abs() < 1e-9);
+    assert_eq!(Discounting::default(), Discounting::Annual { rate: 0.05 });
+
+    // Five years sits halfway along the curve, at 4%; beyond its ends the rate stays flat
+    let curve = Discounting::Curve {
+        points: vec![RatePoint { years: 10.0, rate: 0.06 }, RatePoint { years: 0.0, rate: 0.02 }],
+    };
+    assert!((curve.factor(5.0) - 1.04f64.powf(-5.0)).abs() < 1e-12);
+    assert!((curve.factor(20.0) - 1.06f64.powf(-20.0)).abs() < 1e-12);
+    assert_eq!(curve.clone().with_rate(0.03), Discounting::Annual { rate: 0.03 });
+
+    assert!(Discounting::Curve { points: vec![] }.check().is_err());
+    assert!(Discounting::Annual { rate: -1.5 }.check().is_err());
+    assert!(Discounting::named("continuous", 0.1).unwrap().check().is_ok());
+    assert!(Discounting::named("monthly", 0.1).is_err());
+}
+
+#[tokio::test]
+async fn test_domain_defaults_registry() {
+    let storage = memory_storage().await;
+
+    let restaurants = DomainDefaults {
+        id_domain: "restaurant".to_string(),
+        forget_rate: Some(0.5),
+        decay: Some(DecayFunction::Exponential),
+        max_depth: Some(1),
+        aggregator: Some(Aggregator::WeightedMedian),
+        agent_id_form: Some(AgentIdForm::Lowercase),
+        discounting: Some(Discounting::Curve {
+            points: vec![RatePoint { years: 0.0, rate: 0.02 }, RatePoint { years: 10.0, rate: 0.06 }],
+        }),
+        currency: Some("EUR".to_string()),
+    };
+    storage.set_domain_defaults(&restaurants).await.unwrap();
+    assert_eq!(storage.get_domain_defaults().await.unwrap(), vec![restaurants.clone()]);
+
+    // Setting again replaces the entry
+    let relaxed = DomainDefaults { forget_rate: Some(0.1), ..restaurants };
+    storage.set_domain_defaults(&relaxed).await.unwrap();
+    assert_eq!(storage.get_domain_defaults().await.unwrap(), vec![relaxed]);
+
+    storage.remove_domain_defaults("restaurant").await.unwrap();
+    assert!(storage.get_domain_defaults().await.unwrap().is_empty());
+    assert!(storage.remove_domain_defaults("restaurant").await.is_err());
+}
+
+#[tokio::test]
+async fn test_inflation_indexes() {
+    let storage = memory_storage().await;
+
+    let end = Utc::now();
+    let start = end - Duration::days(3650);
+    let index = InflationIndex {
+        currency: "EUR".to_string(),
+        points: vec![IndexPoint { at: end, level: 130.0 }, IndexPoint { at: start, level: 100.0 }],
+    };
+    assert!(index.check().is_ok());
+    assert_eq!(index.level_at(start - Duration::days(365)), 100.0);
+    assert_eq!(index.level_at(end + Duration::days(365)), 130.0);
+    let midway = start + (end - start) / 2;
+    assert!((index.level_at(midway) - 115.0).abs() < 1e-9);
+    assert!((index.adjustment(start, end) - 1.3).abs() < 1e-9);
+
+    assert!(InflationIndex { currency: "EUR".to_string(), points: vec![] }.check().is_err());
+    let negative = InflationIndex { currency: "EUR".to_string(), points: vec![IndexPoint { at: start, level: -1.0 }] };
+    assert!(negative.check().is_err());
+
+    storage.set_inflation_index(&index).await.unwrap();
+    assert_eq!(storage.get_inflation_indexes().await.unwrap(), vec![index]);
+    storage.remove_inflation_index("EUR").await.unwrap();
+    assert!(storage.get_inflation_indexes().await.unwrap().is_empty());
+    assert!(storage.remove_inflation_index("EUR").await.is_err());
+}
+
+#[tokio::test]
+async fn test_sharing_precision() {
+    let storage = memory_storage().await;
+
+    let acquaintances = SharingPrecision { subject: "circle:acquaintances".to_string(), roi_step: Some(0.1), bucket_volumes: true };
+    let work = SharingPrecision { subject: "circle:work".to_string(), roi_step: Some(0.25), bucket_volumes: false };
+    let alice = SharingPrecision { subject: "alice".to_string(), roi_step: None, bucket_volumes: false };
+    assert!(acquaintances.check().is_ok());
+    assert!(SharingPrecision { roi_step: Some(0.0), ..alice.clone() }.check().is_err());
+    assert!(SharingPrecision { subject: "circle:".to_string(), ..alice.clone() }.check().is_err());
+
+    storage.set_sharing_precision(&acquaintances).await.unwrap();
+    storage.set_sharing_precision(&work).await.unwrap();
+    storage.set_sharing_precision(&alice).await.unwrap();
+    let precisions = storage.get_sharing_precision().await.unwrap();
+    assert_eq!(precisions, vec![alice.clone(), acquaintances.clone(), work.clone()]);
+
+    // A peer's own precision beats its circles', several circles give the coarsest of them
+    let tags = vec!["acquaintances".to_string(), "work".to_string()];
+    assert_eq!(SharingPrecision::for_peer(&precisions, "alice", &tags), Some(alice));
+    let bob = SharingPrecision::for_peer(&precisions, "bob", &tags).unwrap();
+    assert_eq!((bob.roi_step, bob.bucket_volumes), (Some(0.25), true));
+    assert_eq!(SharingPrecision::for_peer(&precisions, "carol", &["family".to_string()]), None);
+
+    let mut score = AgentScore::new("test", "vendor", TrustScore::new(1.137, 372.0, 4));
+    acquaintances.apply(&mut score);
+    assert!((score.score.expected_pv_roi - 1.1).abs() < 1e-9);
+    assert_eq!((score.score.total_volume, score.score.data_points), (500.0, 4));
+
+    storage.remove_sharing_precision("circle:work").await.unwrap();
+    assert_eq!(storage.get_sharing_precision().await.unwrap().len(), 2);
+    assert!(storage.remove_sharing_precision("circle:work").await.is_err());
+}
+
+#[tokio::test]
+async fn test_peer_requests() {
+    let storage = memory_storage().await;
+    let first = Utc::now() - Duration::hours(2);
+    let later = Utc::now();
+
+    let request = storage.record_peer_request("stranger", first).await.unwrap();
+    assert_eq!((request.status, request.queries), (PeerRequestStatus::Pending, 1));
+    let request = storage.record_peer_request("stranger", later).await.unwrap();
+    assert_eq!(request.queries, 2);
+    assert_eq!(request.first_seen_at.timestamp(), first.timestamp());
+    assert_eq!(request.last_seen_at.timestamp(), later.timestamp());
+
+    // Peers can be blocked before they ever query
+    storage.set_peer_request_status("spammer", PeerRequestStatus::Blocked, first).await.unwrap();
+    let requests = storage.get_peer_requests().await.unwrap();
+    assert_eq!(requests.iter().map(|r| r.peer_id.as_str()).collect::<Vec<_>>(), vec!["stranger", "spammer"]);
+    assert_eq!(requests[1].status, PeerRequestStatus::Blocked);
+    assert_eq!(storage.record_peer_request("spammer", later).await.unwrap().status, PeerRequestStatus::Blocked);
+
+    storage.remove_peer_request("stranger").await.unwrap();
+    assert!(storage.remove_peer_request("stranger").await.is_err());
+    assert_eq!(storage.get_peer_requests().await.unwrap().len(), 1);
+
+    // A proposal rides on the request, and doesn't lift a block
+    let proposal = PeerProposal { name: Some("Carol".to_string()), tags: vec!["work".to_string()], ..Default::default() };
+    let request = storage.record_peer_proposal("carol", &proposal, later).await.unwrap();
+    assert_eq!((request.status, request.queries), (PeerRequestStatus::Pending, 0));
+    assert_eq!(storage.record_peer_request("carol", later).await.unwrap().proposal, Some(proposal.clone()));
+    let request = storage.record_peer_proposal("spammer", &proposal, later).await.unwrap();
+    assert_eq!(request.status, PeerRequestStatus::Blocked);
+}
+
+#[tokio::test]
+async fn test_peering_messages_are_kept_until_done() {
+    let storage = memory_storage().await;
+    let now = Utc::now();
+    let peer = Peer {
+        peer_id: "bob".to_string(),
+        addresses: vec![],
+        handle: None,
+        name: "Bob".to_string(),
+        recommender_quality: 0.7,
+        added_at: now,
+        notes: None,
+        tags: vec!["work".to_string()],
+        contact: Default::default(),
+        last_interaction_at: None,
+    };
+    let mut proposed = ProposedPeer { peer, proposal: PeerProposal::default(), proposed_at: now, delivered: false };
+    storage.put_proposed_peer(&proposed).await.unwrap();
+    proposed.delivered = true;
+    storage.put_proposed_peer(&proposed).await.unwrap();
+    let stored = storage.get_proposed_peers().await.unwrap();
+    assert_eq!(stored.len(), 1);
+    assert!(stored[0].delivered);
+    assert_eq!((stored[0].peer.name.as_str(), stored[0].peer.tags.clone()), ("Bob", vec!["work".to_string()]));
+    storage.remove_proposed_peer("bob").await.unwrap();
+    assert!(storage.get_proposed_peers().await.unwrap().is_empty());
+
+    let answer = PeeringAnswerOut { peer_id: "carol".to_string(), accepted: false, reason: Some("declined".to_string()), answered_at: now };
+    storage.put_peering_answer(&answer).await.unwrap();
+    assert_eq!(storage.get_peering_answers().await.unwrap(), vec![answer]);
+    storage.remove_peering_answer("carol").await.unwrap();
+    assert!(storage.get_peering_answers().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_agent_claims() {
+    let storage = memory_storage().await;
+    let claim = AgentClaim {
+        id_domain: "ethereum".to_string(),
+        agent_id: "0xabc".to_string(),
+        challenge: "repeer-claim-1".to_string(),
+        claimed_at: Utc::now(),
+        proof_url: None,
+        verified_at: None,
+    };
+
+    storage.add_agent_claim(&claim).await.unwrap();
+    assert!(storage.add_agent_claim(&claim).await.is_err());
+    assert!(!storage.get_agent_claims().await.unwrap()[0].is_verified());
+
+    storage.verify_agent_claim("ethereum", "0xabc", "https://example.com/me", Utc::now()).await.unwrap();
+    let claims = storage.get_agent_claims().await.unwrap();
+    assert!(claims[0].is_verified());
+    assert_eq!(claims[0].proof_url.as_deref(), Some("https://example.com/me"));
+    assert!(storage.verify_agent_claim("ethereum", "0xdef", "https://example.com/me", Utc::now()).await.is_err());
+
+    storage.remove_agent_claim("ethereum", "0xabc").await.unwrap();
+    assert!(storage.remove_agent_claim("ethereum", "0xabc").await.is_err());
+}
+
+#[test]
+fn test_reciprocity_lists_biggest_takers_first() {
+    let traffic = |queries_received, queries_sent, bytes_received, bytes_sent| PeerTraffic {
+        queries_received,
+        queries_sent,
+        bytes_received,
+        bytes_sent,
+        ..Default::default()
+    };
+    let stats = HashMap::from([
+        ("giver".to_string(), traffic(1, 4, 9_000, 400)),
+        ("taker".to_string(), traffic(20, 0, 2_000, 50_000)),
+    ]);
+    let gina = Peer {
+        peer_id: "giver".to_string(),
+        addresses: vec![],
+        handle: None,
+        name: "Gina".to_string(),
+        recommender_quality: 0.5,
+        added_at: Utc::now(),
+        notes: None,
+        tags: vec![],
+        contact: Default::default(),
+        last_interaction_at: None,
+    };
+    let peers = HashMap::from([("giver".to_string(), gina)]);
+
+    let list = PeerReciprocity::list(&stats, &peers);
+    assert_eq!(list.iter().map(|r| r.peer_id.as_str()).collect::<Vec<_>>(), vec!["taker", "giver"]);
+    assert_eq!((list[0].name.as_deref(), list[0].query_ratio), (None, None));
+    assert_eq!((list[1].name.as_deref(), list[1].query_ratio), (Some("Gina"), Some(0.25)));
+}
+
+#[test]
+fn test_decay_functions() {
+    assert_eq!(DecayFunction::Linear.age_factor(2.0, 0.5), 0.0);
+    assert!((DecayFunction::Exponential.age_factor(2.0, 0.5) - (-1.0f64).exp()).abs() < 1e-9);
+    assert_eq!(DecayFunction::Exponential.age_factor(0.0, 0.5), 1.0);
+}
+
+#[tokio::test]
+async fn test_invalidate_agent_drops_cached_score() {
+    let storage = Arc::new(memory_storage().await);
+    let query_engine = QueryEngine::new(storage.clone());
+    let timestamp = Utc::now();
+
+    let cached = |pv_roi: f64| TrustExperience { timestamp, ..experience("cached", pv_roi, 100.0) };
+
+    storage.add_experience(cached(1.5)).await.unwrap();
+    let first = query_engine.calculate_trust_score("test", "cached", timestamp, 0.1).await.unwrap();
+    assert_eq!(first.data_points, 1);
+
+    // Without invalidation the stale score is served from the cache
+    storage.add_experience(cached(0.5)).await.unwrap();
+    let stale = query_engine.calculate_trust_score("test", "cached", timestamp, 0.1).await.unwrap();
+    assert_eq!(stale.data_points, 1);
+
+    query_engine.invalidate_agent("test", "cached").await;
+    let fresh = query_engine.calculate_trust_score("test", "cached", timestamp, 0.1).await.unwrap();
+    assert_eq!(fresh.data_points, 2);
+    assert!((fresh.expected_pv_roi - 1.0).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn test_query_results_survive_engine_restart() {
+    let storage = Arc::new(memory_storage().await);
+    let timestamp = Utc::now();
+
+    storage.add_experience(TrustExperience { timestamp, ..experience("persisted", 1.5, 100.0) }).await.unwrap();
+
+    let first_engine = QueryEngine::new(storage.clone());
+    let score = first_engine.calculate_trust_score("test", "persisted", timestamp, 0.1).await.unwrap();
+    assert_eq!(score.data_points, 1);
+    assert!(storage.get_query_result(&format!("test:persisted:{}:0.100:linear:weighted_mean", timestamp.timestamp())).await.unwrap().is_some());
+
+    // A fresh engine starts with an empty memory cache but picks up the persisted result
+    let second_engine = QueryEngine::new(storage.clone());
+    let reloaded = second_engine.calculate_trust_score("test", "persisted", timestamp, 0.1).await.unwrap();
+    assert_eq!(reloaded.expected_pv_roi, score.expected_pv_roi);
+    assert_eq!(second_engine.get_cache_stats(), (1, 1));
+
+    second_engine.clear_cache().await;
+    assert_eq!(storage.purge_query_results(None).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_agent_aggregates_follow_writes() {
+    let storage = memory_storage().await;
+
+    let mut first = experience("aggregated", 1.5, 100.0);
+    let second = experience("aggregated", 0.5, 300.0);
+    storage.add_experiences(vec![first.clone(), second.clone()]).await.unwrap();
+
+    let score = storage.get_agent_aggregate("test", "aggregated").await.unwrap().unwrap();
+    assert_eq!(score.data_points, 2);
+    assert_eq!(score.total_volume, 400.0);
+    assert!((score.expected_pv_roi - 0.75).abs() < 1e-9);
+
+    first.invested_volume = 300.0;
+    storage.update_experience(first.clone(), None).await.unwrap();
+    let score = storage.get_agent_aggregate("test", "aggregated").await.unwrap().unwrap();
+    assert!((score.expected_pv_roi - 1.0).abs() < 1e-9);
+
+    storage.remove_experience(&first.id.to_string()).await.unwrap();
+    storage.remove_experience(&second.id.to_string()).await.unwrap();
+    assert!(storage.get_agent_aggregate("test", "aggregated").await.unwrap().is_none());
+}
+
+#[test]
+fn test_robust_aggregators_resist_a_single_huge_volume() {
+    // Three modest experiences around 1.0 and one enormous outlier
+    let values = [(0.9, 10.0), (1.0, 10.0), (1.1, 10.0), (5.0, 1000.0)];
+
+    let (mean, total) = Aggregator::WeightedMean.aggregate(&values).unwrap();
+    assert_eq!(total, 1030.0);
+    assert!(mean > 4.5);
+
+    let (median, _) = Aggregator::WeightedMedian.aggregate(&values).unwrap();
+    assert_eq!(median, 5.0); // The outlier holds most of the weight, so it is the median
+
+    let balanced = [(0.9, 10.0), (1.0, 10.0), (1.1, 10.0), (5.0, 10.0), (0.1, 10.0)];
+    let (median, _) = Aggregator::WeightedMedian.aggregate(&balanced).unwrap();
+    assert_eq!(median, 1.0);
+    let (trimmed, _) = Aggregator::TrimmedMean.aggregate(&balanced).unwrap();
+    // 10% of 50 is cut from each end: half of the 0.1 and half of the 5.0 experience
+    let expected = (0.1 * 5.0 + 0.9 * 10.0 + 1.0 * 10.0 + 1.1 * 10.0 + 5.0 * 5.0) / 40.0;
+    assert!((trimmed - expected).abs() < 1e-9);
+
+    assert!(Aggregator::TrimmedMean.aggregate(&[(1.0, 0.0)]).is_none());
+}
+
+#[test]
+fn test_merge_multiple_with_median_ignores_outlier_peer() {
+    let scores = vec![
+        (TrustScore::new(1.0, 100.0, 1), 1.0),
+        (TrustScore::new(1.1, 100.0, 1), 1.0),
+        (TrustScore::new(0.9, 100.0, 1), 1.0),
+        (TrustScore::new(3.0, 150.0, 1), 1.0),
+    ];
+
+    let mean = TrustScore::merge_multiple_with(scores.clone(), Aggregator::WeightedMean, &ScoreClamping::default());
+    let median = TrustScore::merge_multiple_with(scores, Aggregator::WeightedMedian, &ScoreClamping::default());
+    assert!(mean.expected_pv_roi > 1.5);
+    assert_eq!(median.expected_pv_roi, 1.1);
+    assert_eq!(median.total_volume, 450.0);
+    assert_eq!(median.data_points, 4);
+}
+
+#[test]
+fn test_clamping_keeps_extreme_scores_from_dominating_merges() {
+    let clamping = ScoreClamping { min_roi: Some(0.0), max_roi: Some(3.0), max_source_volume: Some(1000.0) };
+    let honest = TrustScore::new(1.1, 1000.0, 10);
+    let extreme = TrustScore::new(500.0, 1e9, 1);
+
+    let unclamped = honest.merge_with(&extreme, 1.0);
+    assert!(unclamped.expected_pv_roi > 400.0);
+    let clamped = honest.merge_with_clamped(&extreme, 1.0, &clamping);
+    assert!((clamped.expected_pv_roi - (1.1 + 3.0) / 2.0).abs() < 1e-9);
+    assert_eq!(clamped.total_volume, 2000.0);
+
+    // The cap applies after weighting, and to the first source as well
+    let scores = vec![(extreme.clone(), 0.5), (honest.clone(), 1.0)];
+    let merged = TrustScore::merge_multiple_clamped(scores.clone(), &clamping);
+    assert_eq!((merged.expected_pv_roi, merged.total_volume), (clamped.expected_pv_roi, clamped.total_volume));
+    // Capped to the honest score's volume, the outlier no longer holds most of the weight
+    assert_eq!(TrustScore::merge_multiple_with(scores, Aggregator::WeightedMedian, &clamping).expected_pv_roi, 1.1);
+
+    // Inverted by a negative weight, a huge ROI is clamped at the bottom of the range
+    let inverted = TrustScore::merge_multiple_clamped(vec![(extreme, -1.0)], &clamping);
+    assert_eq!(inverted.expected_pv_roi, 0.0);
+    assert_eq!(inverted.total_volume, 1000.0);
+}
+
+#[test]
+fn test_min_evidence_marks_thin_scores() {
+    let min_evidence = MinEvidence { data_points: 3, total_volume: 500.0 };
+
+    let mut thin = AgentScore::new("test", "thin", TrustScore::new(2.5, 1000.0, 1));
+    thin.apply_min_evidence(&min_evidence);
+    assert!(thin.insufficient_data);
+    assert_eq!(thin.score.expected_pv_roi, 1.0);
+    assert_eq!(thin.score.data_points, 1);
+
+    let mut solid = AgentScore::new("test", "solid", TrustScore::new(1.3, 800.0, 4));
+    solid.apply_min_evidence(&min_evidence);
+    assert!(!solid.insufficient_data);
+    assert_eq!(solid.score.expected_pv_roi, 1.3);
+
+    // The default minimum accepts everything
+    let mut empty = AgentScore::new("test", "empty", TrustScore::default());
+    empty.apply_min_evidence(&MinEvidence::default());
+    assert!(!empty.insufficient_data);
+}
+
+#[test]
+fn test_merge_responses_damps_relayed_scores() {
+    let response = |peer: &str, score: AgentScore| TrustResponseInternal {
+        response: TrustResponse::new(vec![score]),
+        peer_id: peer.to_string(),
+    };
+    let responses = vec![
+        response("friend", AgentScore::new("test", "agent", TrustScore::new(1.2, 100.0, 1))),
+        response("relay", AgentScore::new("test", "agent", TrustScore::new(1.2, 300.0, 2)).with_hops(1)),
+    ];
+
+    let merged = merge_responses(responses, 0.5);
+    assert_eq!(merged.scores.len(), 1);
+    assert_eq!(merged.scores[0].hops, 1);
+    assert!((merged.scores[0].score.total_volume - 200.0).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn test_simulated_experiences_are_not_persisted() {
+    let storage = Arc::new(memory_storage().await);
+    let query_engine = QueryEngine::new(storage.clone());
+    let timestamp = Utc::now();
+
+    let vendor = |pv_roi: f64, invested_volume: f64| TrustExperience { timestamp, ..experience("vendor", pv_roi, invested_volume) };
+    storage.add_experience(vendor(1.2, 300.0)).await.unwrap();
+
+    let bad_order = [vendor(0.0, 100.0)];
+    let simulated = query_engine
+        .simulate_trust_score("test", "vendor", timestamp, ScoringOptions::default(), &bad_order)
+        .await
+        .unwrap();
+    assert_eq!(simulated.data_points, 2);
+    assert!((simulated.expected_pv_roi - 0.9).abs() < 1e-9);
+
+    let actual = query_engine.calculate_trust_score("test", "vendor", timestamp, 0.0).await.unwrap();
+    assert_eq!(actual.data_points, 1);
+    assert_eq!(storage.get_experiences("test", "vendor").await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_cache_stats_count_hits_and_misses() {
+    let storage = Arc::new(memory_storage().await);
+    let query_engine = QueryEngine::new_with_cache_ttl(storage, 60);
+    let timestamp = Utc::now();
+
+    query_engine.calculate_trust_score("test", "agent", timestamp, 0.1).await.unwrap();
+    query_engine.calculate_trust_score("test", "agent", timestamp, 0.1).await.unwrap();
+
+    let stats = query_engine.cache_stats();
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats.misses, 1);
+    assert_eq!(stats.entries, 1);
+    assert_eq!(stats.ttl_seconds, 60);
+}
+
+#[tokio::test]
+async fn test_score_pages_match_per_agent_scores() {
+    let storage = Arc::new(memory_storage().await);
+    let query_engine = QueryEngine::new(storage.clone());
+    let now = Utc::now();
+
+    for (agent_id, pv_roi, days_ago) in [("a", 1.5, 0), ("a", 0.5, 400), ("b", 1.2, 30), ("c", 0.9, 200)] {
+        let timestamp = now - Duration::days(days_ago);
+        storage.add_experience(TrustExperience { timestamp, ..experience(agent_id, pv_roi, 100.0) }).await.unwrap();
+    }
+    // Pages have to apply the domain's decay function and inflation just like single agent scores do
+    let defaults = DomainDefaults {
+        id_domain: "test".to_string(),
+        forget_rate: Some(0.5),
+        decay: Some(DecayFunction::Exponential),
+        currency: Some("EUR".to_string()),
+        ..DomainDefaults::default()
+    };
+    storage.set_domain_defaults(&defaults).await.unwrap();
+    let index = InflationIndex {
+        currency: "EUR".to_string(),
+        points: vec![IndexPoint { at: now - Duration::days(400), level: 100.0 }, IndexPoint { at: now, level: 150.0 }],
+    };
+    query_engine.set_inflation(HashMap::from([("test".to_string(), index)])).await;
+    let options = ScoringOptions::resolve(None, None, None, Some(&defaults));
+
+    let first = query_engine.calculate_trust_scores_page(now, None, 2).await.unwrap();
+    assert_eq!(first.iter().map(|s| s.agent_id.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+    let last = first.last().unwrap();
+    let second = query_engine
+        .calculate_trust_scores_page(now, Some((&last.id_domain, &last.agent_id)), 2)
+        .await
+        .unwrap();
+    assert_eq!(second.len(), 1);
+    assert_eq!(second[0].agent_id, "c");
+
+    // The graph and global trust page through the same scores
+    let graph = graph::build(storage.as_ref(), &query_engine, "me", now).await.unwrap();
+    let global = eigentrust::compute(storage.as_ref(), &query_engine, now).await.unwrap();
+
+    for page_score in first.iter().chain(&second) {
+        let expected = query_engine
+            .calculate_trust_score_with("test", &page_score.agent_id, now, options)
+            .await
+            .unwrap();
+        assert!((page_score.score.expected_pv_roi - expected.expected_pv_roi).abs() < 1e-9);
+        assert!((page_score.score.total_volume - expected.total_volume).abs() < 1e-9);
+        assert_eq!(page_score.score.data_points, expected.data_points);
+
+        let edge = graph.edges.iter().find(|edge| edge.to == format!("agent:test/{}", page_score.agent_id)).unwrap();
+        assert!((edge.weight - expected.expected_pv_roi).abs() < 1e-9);
+        assert!((edge.volume.unwrap() - expected.total_volume).abs() < 1e-9);
+        let global_score = global.score("test", &page_score.agent_id).unwrap();
+        assert!((global_score.expected_pv_roi - expected.expected_pv_roi).abs() < 1e-9);
+        assert!((global_score.total_volume - expected.total_volume).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn test_freshness_keeps_cached_share_of_live_answers() {
+    let now = Utc::now();
+    let week_ago = now - Duration::days(7);
+
+    // A peer answered with 100 volume of its own and 100 from its cache
+    let reported = Freshness {
+        local_volume: 100.0,
+        live_volume: 0.0,
+        cached_volume: 100.0,
+        oldest_cached_at: Some(week_ago),
+    };
+    let mut freshness = Freshness::default();
+    freshness.add_live(200.0, &reported);
+    freshness.add_cached(50.0, now);
+
+    assert_eq!(freshness.live_volume, 100.0);
+    assert_eq!(freshness.cached_volume, 150.0);
+    assert_eq!(freshness.oldest_cached_at, Some(week_ago));
+
+    // Answers from nodes that don't report freshness count as entirely live
+    let mut legacy = Freshness::default();
+    legacy.add_live(80.0, &Freshness::default());
+    assert_eq!(legacy.live_volume, 80.0);
+    assert_eq!(legacy.oldest_cached_at, None);
+}
+
+#[test]
+fn test_oversized_responses_are_paged() {
+    let scores: Vec<AgentScore> = (0..100)
+        .map(|i| AgentScore::new("test", format!("agent{:03}", i), TrustScore::new(1.0, 100.0, 1)))
+        .collect();
+    let mut query = TrustQuery {
+        agents: scores.iter().map(|s| AgentIdentifier::new(s.id_domain.clone(), s.agent_id.clone())).collect(),
+        max_depth: Some(0),
+        point_in_time: None,
+        forget_rate: None,
+        decay: None,
+        aggregator: None,
+        budget_ms: None,
+        continuation: None,
+        traceparent: None,
+        correlation_id: None,
+        timing: false,
+        refresh: false,
+        cache_only: false,
+        expires_at: None,
+    };
+
+    // Collect pages the way a requester would, until no continuation is left
+    let mut received = Vec::new();
+    loop {
+        let remaining: Vec<AgentScore> = agents_after_continuation(&query)
+            .iter()
+            .map(|a| AgentScore::new(&a.id_domain, &a.agent_id, TrustScore::new(1.0, 100.0, 1)))
+            .collect();
+        let data = encode_response_page(TrustResponse::new(remaining), 4_000).unwrap();
+        assert!(data.len() <= 4_000);
+        let page: TrustResponse = serde_json::from_slice(&data).unwrap();
+        assert!(!page.scores.is_empty());
+        received.extend(page.scores);
+        match page.continuation {
+            Some(token) => query.continuation = Some(token),
+            None => break,
+        }
+    }
+    assert_eq!(received.len(), 100);
+
+    // Not even one score fits: the peer learns why instead of getting nothing
+    let data = encode_response_page(TrustResponse::new(scores), 50).unwrap();
+    let error: TrustResponse = serde_json::from_slice(&data).unwrap();
+    assert!(error.scores.is_empty());
+    assert_eq!(error.error, Some(WireError::ResponseTooLarge { limit_bytes: 50 }));
+}
+
+#[test]
+fn test_query_limits_list_every_violation() {
+    let limits = QueryLimits { max_agents: 2, max_depth: 4 };
+    let mut query = TrustQuery {
+        agents: vec![AgentIdentifier::new("test", "a"), AgentIdentifier::new("test", "b")],
+        max_depth: Some(4),
+        point_in_time: None,
+        forget_rate: None,
+        decay: None,
+        aggregator: None,
+        budget_ms: None,
+        continuation: None,
+        traceparent: None,
+        correlation_id: None,
+        timing: false,
+        refresh: false,
+        cache_only: false,
+        expires_at: None,
+    };
+    assert!(limits.check(&query).is_ok());
+
+    query.agents.push(AgentIdentifier::new("test", " "));
+    query.max_depth = Some(200);
+    query.refresh = true;
+    query.cache_only = true;
+    let invalid = limits.check(&query).unwrap_err();
+    assert_eq!(invalid.violations.len(), 4);
+    assert!(invalid.violations.iter().any(|v| v.contains("agents[2]: agent_id")));
+    assert!(invalid.violations.iter().any(|v| v.contains("cache_only")));
+}
+
+#[tokio::test]
+async fn test_seed_populates_requested_amounts() {
+    let storage = memory_storage().await;
+    let options = trust_node::seed::SeedOptions { agents: 20, experiences: 750, peers: 3, years: 2, seed: 42 };
+    let now = Utc::now();
+    let summary = trust_node::seed::populate(&storage, &options, now).await.unwrap();
+
+    let experiences = storage.get_all_experiences().await.unwrap();
+    assert_eq!(experiences.len(), 750);
+    assert!(experiences.iter().all(|e| e.pv_roi >= 0.0 && e.timestamp <= now && e.timestamp >= now - Duration::days(2 * 365)));
+    assert_eq!(storage.get_peers().await.unwrap().len(), 3);
+    assert_eq!(storage.get_all_cached_scores().await.unwrap().len(), summary.cached_scores);
+    assert_eq!(summary.agents, 20);
+
+    // The same seed and end time give the same records, ids and peer keys included
+    let again = memory_storage().await;
+    trust_node::seed::populate(&again, &options, now).await.unwrap();
+    let ids = |experiences: Vec<TrustExperience>| {
+        let mut ids: Vec<(Uuid, chrono::DateTime<Utc>)> = experiences.into_iter().map(|e| (e.id, e.timestamp)).collect();
+        ids.sort();
+        ids
+    };
+    assert_eq!(ids(again.get_all_experiences().await.unwrap()), ids(experiences));
+    let peer_ids = |peers: Vec<Peer>| peers.into_iter().map(|peer| peer.peer_id).collect::<std::collections::BTreeSet<_>>();
+    assert_eq!(peer_ids(again.get_peers().await.unwrap()), peer_ids(storage.get_peers().await.unwrap()));
+}
+
+#[test]
+fn test_trace_context_is_optional_on_the_wire() {
+    // Queries from nodes without tracing have no traceparent and must still decode
+    let query: TrustQuery = serde_json::from_str(r#"{"agents": [], "point_in_time": null, "forget_rate": null}"#).unwrap();
+    assert_eq!(query.traceparent, None);
+    assert!(!serde_json::to_string(&query).unwrap().contains("traceparent"));
+
+    let traced = TrustQuery {
+        traceparent: Some("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string()),
+        ..query
+    };
+    let decoded: TrustQuery = serde_json::from_str(&serde_json::to_string(&traced).unwrap()).unwrap();
+    assert_eq!(decoded.traceparent, traced.traceparent);
+}
+
+#[test]
+fn test_correlation_ids_are_safe_to_log() {
+    assert!(is_valid_correlation_id(&Uuid::new_v4().to_string()));
+    assert!(is_valid_correlation_id("checkout_42"));
+    assert!(!is_valid_correlation_id(""));
+    assert!(!is_valid_correlation_id("two words"));
+    assert!(!is_valid_correlation_id("line\nbreak"));
+    assert!(!is_valid_correlation_id(&"x".repeat(65)));
+
+    // Older nodes neither send nor expect one
+    let query: TrustQuery = serde_json::from_str(r#"{"agents": [], "point_in_time": null, "forget_rate": null}"#).unwrap();
+    assert_eq!(query.correlation_id, None);
+    assert!(!serde_json::to_string(&query).unwrap().contains("correlation_id"));
+}
+
+#[test]
+fn test_runtime_config_update_keeps_unset_fields() {
+    let config = RuntimeConfig {
+        discovery_interval_secs: 30,
+        max_fanout: 0,
+        cache_ttl_secs: 300,
+        inbound_workers: 4,
+        max_queued_per_peer: 32,
+        max_query_agents: 1000,
+        max_query_depth: 6,
+    };
+    let update = RuntimeConfigUpdate { max_fanout: Some(3), cache_ttl_secs: Some(60), ..Default::default() };
+    let updated = update.apply_to(&config).unwrap();
+    assert_eq!(updated, RuntimeConfig { max_fanout: 3, cache_ttl_secs: 60, ..config.clone() });
+
+    let invalid = RuntimeConfigUpdate { discovery_interval_secs: Some(0), inbound_workers: Some(0), ..Default::default() };
+    assert_eq!(invalid.apply_to(&config).unwrap_err().violations.len(), 2);
+    let too_rare = RuntimeConfigUpdate { discovery_interval_secs: Some(u64::MAX), ..Default::default() };
+    assert!(too_rare.apply_to(&config).is_err());
+}
+
+#[test]
+fn test_watched_agent_announces_threshold_crossings_and_large_moves() {
+    let watched = WatchedAgent {
+        id_domain: "ethereum".to_string(),
+        agent_id: "0x123".to_string(),
+        thresholds: vec![1.0],
+        min_delta: Some(0.2),
+        webhook: None,
+        last_score: TrustScore::new(1.05, 100.0, 2),
+        added_at: Utc::now(),
+    };
+
+    // A small move on the same side of every threshold stays quiet
+    assert!(watched.change_to(&TrustScore::new(1.1, 150.0, 3), Utc::now()).is_none());
+    let crossed = watched.change_to(&TrustScore::new(0.95, 150.0, 3), Utc::now()).unwrap();
+    assert_eq!(crossed.crossed, vec![1.0]);
+    let moved = watched.change_to(&TrustScore::new(1.3, 150.0, 3), Utc::now()).unwrap();
+    assert!(moved.crossed.is_empty());
+    assert_eq!(moved.previous.expected_pv_roi, 1.05);
+
+    assert!(WatchRequest::default().validate().is_err());
+    let invalid = WatchRequest { thresholds: vec![f64::NAN], min_delta: Some(0.0), webhook: Some("ftp://x".to_string()) };
+    assert_eq!(invalid.validate().unwrap_err().violations.len(), 3);
+}
+
+#[test]
+fn test_peer_event_log_keeps_the_most_recent_events() {
+    let mut log = PeerEventLog::new(3);
+    for (kind, peer) in [
+        (PeerEventKind::Dialed, "alice"),
+        (PeerEventKind::Failed, "alice"),
+        (PeerEventKind::Dialed, "bob"),
+        (PeerEventKind::Connected, "bob"),
+    ] {
+        log.push(PeerEvent { kind, peer_id: Some(peer.to_string()), address: None, reason: None, at: Utc::now() });
+    }
+
+    let kinds: Vec<PeerEventKind> = log.recent(None).iter().map(|event| event.kind).collect();
+    assert_eq!(kinds, [PeerEventKind::Failed, PeerEventKind::Dialed, PeerEventKind::Connected]);
+    assert_eq!(log.recent(Some("alice")).len(), 1);
+}
+
+#[test]
+fn test_silent_peer_quality_decays_toward_prior() {
+    let decay = QualityDecay { half_life: std::time::Duration::from_secs(365 * 86400), prior: 0.5 };
+    let now = Utc::now();
+    let mut peer = Peer {
+        peer_id: "12D3KooWExample".to_string(),
+        addresses: Vec::new(),
+        handle: None,
+        name: "alice".to_string(),
+        recommender_quality: 0.9,
+        added_at: now - Duration::days(3 * 365),
+        notes: None,
+        tags: Vec::new(),
+        contact: Default::default(),
+        last_interaction_at: Some(now - Duration::days(365)),
+    };
+    assert!((decay.apply(&peer, now) - 0.7).abs() < 1e-6);
+
+    // Without any interaction the peer has been silent since it was added
+    peer.last_interaction_at = None;
+    assert!((decay.apply(&peer, now) - 0.55).abs() < 1e-6);
+    peer.last_interaction_at = Some(now);
+    assert!((decay.apply(&peer, now) - 0.9).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn test_merge_honors_deletions_and_newer_edits() {
+    let laptop = memory_storage().await;
+    let phone = memory_storage().await;
+
+    let (deleted_here, deleted_there, edited_there) = (
+        experience("deleted_here", 1.0, 100.0),
+        experience("deleted_there", 1.0, 100.0),
+        experience("edited_there", 1.0, 100.0),
+    );
+    for shared in [&deleted_here, &deleted_there, &edited_there] {
+        laptop.add_experience(shared.clone()).await.unwrap();
+        phone.add_experience(shared.clone()).await.unwrap();
+    }
+    laptop.remove_experience(&deleted_here.id.to_string()).await.unwrap();
+    phone.remove_experience(&deleted_there.id.to_string()).await.unwrap();
+    let edited = TrustExperience { pv_roi: 0.5, ..edited_there.clone() };
+    phone.update_experience(edited, None).await.unwrap();
+    let new_there = experience("new_there", 1.0, 100.0);
+    phone.add_experience(new_there.clone()).await.unwrap();
+    phone.add_peer(Peer {
+        peer_id: "bob".to_string(),
+        addresses: vec![],
+        handle: None,
+        name: "Bob".to_string(),
+        recommender_quality: 0.8,
+        added_at: Utc::now(),
+        notes: None,
+        tags: vec![],
+        contact: Default::default(),
+        last_interaction_at: None,
+    }).await.unwrap();
+
+    let summary = merge::merge_from(&laptop, &phone).await.unwrap();
+    assert_eq!(summary.experiences_added, 1);
+    assert_eq!(summary.experiences_updated, 1);
+    assert_eq!(summary.experiences_removed, 1);
+    assert_eq!(summary.deletions_kept, 1);
+    assert_eq!(summary.peers_added, 1);
+
+    let mut agents: Vec<String> = laptop.get_all_experiences().await.unwrap().into_iter().map(|e| e.agent_id).collect();
+    agents.sort();
+    assert_eq!(agents, vec!["edited_there", "new_there"]);
+    let merged = laptop.get_experience(&edited_there.id.to_string()).await.unwrap().unwrap();
+    assert_eq!(merged.pv_roi, 0.5);
+
+    // Merging again changes nothing
+    let again = merge::merge_from(&laptop, &phone).await.unwrap();
+    assert_eq!((again.experiences_added, again.experiences_updated, again.experiences_removed), (0, 0, 0));
+}
+
+#[test]
+fn test_answer_privacy_holds_back_thin_scores() {
+    let score = |agent_id: &str, total_volume: f64, data_points: usize| {
+        let mut score = AgentScore::new("test", agent_id, TrustScore::new(1.1, total_volume, data_points));
+        score.freshness.local_volume = total_volume;
+        score
+    };
+    let answer = vec![score("single", 37.0, 1), score("known", 420.0, 3)];
+
+    let mut scores = answer.clone();
+    assert_eq!(AnswerPrivacy::default().apply(&mut scores), 0);
+    assert_eq!(scores.len(), 2);
+
+    let mut scores = answer.clone();
+    let withheld = AnswerPrivacy { min_data_points: 2, bucket_below_min: false }.apply(&mut scores);
+    assert_eq!(withheld, 1);
+    assert_eq!(scores.iter().map(|s| s.agent_id.as_str()).collect::<Vec<_>>(), vec!["known"]);
+
+    let mut scores = answer;
+    assert_eq!(AnswerPrivacy { min_data_points: 2, bucket_below_min: true }.apply(&mut scores), 0);
+    assert_eq!(scores[0].score.total_volume, 50.0);
+    // The volume split doesn't give the exact figure away either
+    assert_eq!(scores[0].freshness.local_volume, 50.0);
+    assert_eq!(scores[1].score.total_volume, 420.0);
+}
+
+#[test]
+fn test_peer_timing_is_bounded_and_anonymized() {
+    // Breakdowns nested far deeper than any query goes, listing far more peers than anyone has
+    let peer = |timing| PeerTiming { peer_id: Some("peer".to_string()), latency_ms: Some(1), timing: Some(Box::new(timing)) };
+    let mut timing = QueryTiming::default();
+    for _ in 0..10 {
+        timing = QueryTiming { peers: vec![peer(timing)], ..QueryTiming::default() };
+    }
+    let timing = QueryTiming { peers: vec![peer(timing); MAX_TIMING_PEERS * 2], ..QueryTiming::default() };
+
+    let mut bounded = timing.bounded(2);
+    assert_eq!(bounded.peers.len(), MAX_TIMING_PEERS);
+    let nested = bounded.peers[0].timing.as_ref().unwrap();
+    assert!(nested.peers[0].timing.is_none());
+
+    bounded.anonymize();
+    assert!(bounded.peers.iter().all(|peer| peer.peer_id.is_none()));
+    assert!(bounded.peers[0].timing.as_ref().unwrap().peers.iter().all(|peer| peer.peer_id.is_none()));
+}